@@ -0,0 +1,57 @@
+use std::{env, fs, path::Path, process::Command};
+
+// Captures what `/api/version` reports about the build: the git commit and the locked versions
+// of the dependencies whose wire formats other parties of a signing session depend on.
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+
+    let git_commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&manifest_dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={}", git_commit);
+
+    let lock = fs::read_to_string(Path::new(&manifest_dir).join("Cargo.lock")).unwrap_or_default();
+    println!(
+        "cargo:rustc-env=SOLANA_SDK_VERSION={}",
+        locked_version(&lock, "solana-sdk")
+    );
+    println!(
+        "cargo:rustc-env=MULTI_PARTY_EDDSA_VERSION={}",
+        locked_version(&lock, "multi-party-eddsa")
+    );
+
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
+
+/// Locked `version` of `name`, git dependencies get `+<rev>` appended.
+fn locked_version(lock: &str, name: &str) -> String {
+    for package in lock.split("[[package]]") {
+        let field = |key: &str| {
+            package.lines().find_map(|line| {
+                line.strip_prefix(key)?
+                    .strip_prefix(" = \"")?
+                    .strip_suffix('"')
+            })
+        };
+        if field("name") != Some(name) {
+            continue;
+        }
+        let version = field("version").unwrap_or("unknown");
+        return match field("source")
+            .and_then(|source| source.strip_prefix("git+"))
+            .and_then(|source| source.rsplit_once('#'))
+        {
+            Some((_, rev)) => format!("{}+{}", version, rev),
+            None => version.to_string(),
+        };
+    }
+    "unknown".to_string()
+}
@@ -0,0 +1,189 @@
+use std::fmt::{Display, Formatter};
+
+use clap::ValueEnum;
+use poem::http::HeaderMap;
+use rand07::Rng;
+use serde_json::Value;
+use tracing::Subscriber;
+use tracing_subscriber::{
+    EnvFilter, Layer,
+    fmt::{self, MakeWriter},
+    registry::LookupSpan,
+};
+
+// Every request runs in a `request` span naming its id, method and path, and ends with one
+// event carrying the status and latency. Everything logged while it is handled, RPC calls, TSS
+// steps and errors, is logged within that span. Bodies are only logged at debug level and only
+// after `redacted`: they hold keypairs, secret states and partial signatures, and a log is no
+// place for any of them. The id goes back to the client in `X-Request-Id`.
+
+/// Response header with the id of the request, taken from the request when it sent a usable one.
+pub const HEADER: &str = "X-Request-Id";
+
+/// Longest id a client may choose.
+const MAX_ID_LEN: usize = 64;
+
+/// Fields whose values are replaced by `redacted`, compared without case and underscores so
+/// camelCase aliases are covered too.
+const SECRET_FIELDS: &[&str] = &[
+    "keypair",
+    "feepayerkeypair",
+    "rentsponsorkeypair",
+    "secretshare",
+    "keyshare",
+    "mnemonic",
+    "passphrase",
+    "secretstate",
+    // Takes the secret state kept on the server
+    "secretstateid",
+    "partialsignature",
+    // The partial signatures of an aggregation
+    "signatures",
+];
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One human readable line per event
+    #[default]
+    Text,
+    /// One JSON object per event
+    Json,
+}
+
+/// The id of a request: the client's `X-Request-Id` if it is a short token, a fresh one
+/// otherwise.
+pub fn request_id(headers: &HeaderMap) -> String {
+    let sent = headers
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            (1..=MAX_ID_LEN).contains(&id.len())
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+        });
+    match sent {
+        Some(id) => id.to_string(),
+        None => {
+            let bytes: [u8; 16] = rand07::thread_rng().r#gen();
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+    }
+}
+
+fn is_secret(field: &str) -> bool {
+    let normalized: String = field
+        .chars()
+        .filter(|c| *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    SECRET_FIELDS.contains(&normalized.as_str())
+}
+
+/// `body` with the value of every secret field replaced, however deep it is nested.
+pub fn redacted(body: &Value) -> Value {
+    match body {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| match is_secret(key) {
+                    true => (key.clone(), Value::String(REDACTED.to_string())),
+                    false => (key.clone(), redacted(value)),
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(redacted).collect()),
+        value => value.clone(),
+    }
+}
+
+/// A body that displays as `redacted` makes it, only once it is actually logged.
+pub struct Redacted<'a>(pub &'a Value);
+
+impl Display for Redacted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", redacted(self.0))
+    }
+}
+
+/// The layer writing logs to `writer`, with the levels `filter` enables in `RUST_LOG` syntax.
+pub fn layer<S, W>(
+    format: LogFormat,
+    filter: &str,
+    writer: W,
+) -> Result<Box<dyn Layer<S> + Send + Sync>, String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_new(filter)
+        .map_err(|e| format!("invalid log filter {:?}: {}", filter, e))?;
+    Ok(match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).with_filter(filter).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_writer(writer)
+            .with_filter(filter)
+            .boxed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::HeaderValue;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_redacted() {
+        let body = json!({
+            "keypair": "4wBqpZM9xaSheZzJSMawUKKwhdpChKbZ5eu5ky4Vigw",
+            "feePayerKeypair": [1, 2, 3],
+            "secret_state": "abc",
+            "first_messages": ["m1"],
+            "signatures": ["s1", "s2"],
+            "recipients": [{ "to": "addr", "rent_sponsor_keypair": "k" }],
+            "amount": 1,
+        });
+        assert_eq!(
+            redacted(&body),
+            json!({
+                "keypair": REDACTED,
+                "feePayerKeypair": REDACTED,
+                "secret_state": REDACTED,
+                "first_messages": ["m1"],
+                "signatures": REDACTED,
+                "recipients": [{ "to": "addr", "rent_sponsor_keypair": REDACTED }],
+                "amount": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_id() {
+        let mut headers = HeaderMap::new();
+        let fresh = request_id(&headers);
+        assert_eq!(fresh.len(), 32);
+        assert_ne!(request_id(&headers), fresh);
+
+        headers.insert(HEADER, HeaderValue::from_static("client-id_1.2"));
+        assert_eq!(request_id(&headers), "client-id_1.2");
+        for unusable in ["", "has space", "quote\"", &"a".repeat(MAX_ID_LEN + 1)] {
+            headers.insert(HEADER, HeaderValue::from_str(unusable).unwrap());
+            assert_ne!(request_id(&headers), unusable);
+        }
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        let layer = layer::<tracing_subscriber::Registry, _>(
+            LogFormat::Text,
+            "solana_tss_api_backend=loud",
+            std::io::sink,
+        );
+        assert!(layer.is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient,
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use tokio::sync::oneshot;
+
+use crate::{
+    models::{AccountFetchStats, Network},
+    rpc_methods::{METHOD_NOT_FOUND, unsupported_method},
+    rpc_override,
+};
+
+// Concurrent requests each reading an account or two add up to a call per read, which is what
+// trips the providers' rate limits. Reads of the same network are held for a short window and
+// sent as one `getMultipleAccounts`, every waiting request gets its account back from it. A
+// read still alone when the window ends goes out as the plain `getAccountInfo` it would have
+// been.
+//
+// Dashboards polling the balance of wallets that hold nothing yet would still ask for the same
+// missing accounts over and over. A lookup that found no account answers the lookups of the
+// next `missing_ttl_ms` itself. Only reads that report go through it: a transfer acting on a
+// stale "missing" could pick the wrong instructions, so the send paths read with `get_account`.
+//
+// A request with its own `rpc_url` may be talking to another cluster than the rest. Its reads
+// go out on their own, and neither use nor change the missing addresses.
+
+/// Most addresses `getMultipleAccounts` takes per call.
+pub const MAX_BATCH: usize = 100;
+
+/// Missing addresses remembered at once, more aren't until some expire.
+const MAX_MISSING: usize = 10_000;
+
+/// The `account_batching` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountBatchConfig {
+    /// How long a read waits for others to share its call, 0 sends every read on its own
+    pub window_ms: u64,
+    /// Addresses per `getMultipleAccounts`, larger batches are split
+    pub max_batch: usize,
+    /// How long `lookup` keeps reporting an account it didn't find as missing, 0 asks every time
+    pub missing_ttl_ms: u64,
+}
+
+impl Default for AccountBatchConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: 10,
+            max_batch: MAX_BATCH,
+            missing_ttl_ms: 2_000,
+        }
+    }
+}
+
+type Reply = oneshot::Sender<Result<Option<Account>, ClientError>>;
+type Pending = HashMap<Network, Vec<(Pubkey, Reply)>>;
+
+#[derive(Default)]
+struct Counters {
+    direct: AtomicU64,
+    coalesced: AtomicU64,
+    batches: AtomicU64,
+    missing_hits: AtomicU64,
+}
+
+/// Coalesces account reads per network, shared by every handler through `AppState`.
+pub struct AccountBatcher {
+    window: Duration,
+    max_batch: usize,
+    pending: Arc<Mutex<Pending>>,
+    counters: Arc<Counters>,
+    missing_ttl: Duration,
+    /// Addresses `lookup` found without an account, until when they're reported missing
+    missing: Mutex<HashMap<(Network, Pubkey), Instant>>,
+}
+
+impl AccountBatcher {
+    pub fn new(config: &AccountBatchConfig) -> Self {
+        Self {
+            window: Duration::from_millis(config.window_ms),
+            max_batch: config.max_batch.clamp(1, MAX_BATCH),
+            pending: Arc::default(),
+            counters: Arc::default(),
+            missing_ttl: Duration::from_millis(config.missing_ttl_ms),
+            missing: Mutex::default(),
+        }
+    }
+
+    /// The account at `address` on `net`, `None` when there is none. `rpc_client` has to be
+    /// for `net`, whichever read opens a window sends the batch through its client.
+    pub async fn get_account(
+        &self,
+        rpc_client: Arc<RpcClient>,
+        net: Network,
+        address: Pubkey,
+    ) -> Result<Option<Account>, ClientError> {
+        if rpc_override::current().is_some() {
+            return self.read_alone(&rpc_client, &address).await;
+        }
+        let account = self.read(rpc_client, net, address).await;
+        if let Ok(Some(_)) = account
+            && !self.missing_ttl.is_zero()
+        {
+            self.missing.lock().unwrap().remove(&(net, address));
+        }
+        account
+    }
+
+    /// `get_account` for reads that only report what they find. An address found without an
+    /// account is reported missing for `missing_ttl_ms` without asking the node, unless
+    /// `fresh` is set.
+    pub async fn lookup(
+        &self,
+        rpc_client: Arc<RpcClient>,
+        net: Network,
+        address: Pubkey,
+        fresh: bool,
+    ) -> Result<Option<Account>, ClientError> {
+        if rpc_override::current().is_some() {
+            return self.read_alone(&rpc_client, &address).await;
+        }
+        if !fresh && self.known_missing(net, address) {
+            self.counters.missing_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+        let account = self.read(rpc_client, net, address).await?;
+        match account {
+            Some(_) => {
+                self.missing.lock().unwrap().remove(&(net, address));
+            }
+            None => self.remember_missing(net, address),
+        }
+        Ok(account)
+    }
+
+    /// Forget every address found missing, the next lookup asks the node again.
+    pub fn flush_missing(&self) {
+        self.missing.lock().unwrap().clear();
+    }
+
+    fn known_missing(&self, net: Network, address: Pubkey) -> bool {
+        let mut missing = self.missing.lock().unwrap();
+        match missing.get(&(net, address)) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                missing.remove(&(net, address));
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn remember_missing(&self, net: Network, address: Pubkey) {
+        if self.missing_ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut missing = self.missing.lock().unwrap();
+        if missing.len() >= MAX_MISSING {
+            missing.retain(|_, until| *until > now);
+        }
+        if missing.len() < MAX_MISSING {
+            missing.insert((net, address), now + self.missing_ttl);
+        }
+    }
+
+    async fn read(
+        &self,
+        rpc_client: Arc<RpcClient>,
+        net: Network,
+        address: Pubkey,
+    ) -> Result<Option<Account>, ClientError> {
+        if self.window.is_zero() {
+            return self.read_alone(&rpc_client, &address).await;
+        }
+
+        let (reply, answer) = oneshot::channel();
+        let opens_window = {
+            let mut pending = self.pending.lock().unwrap();
+            let reads = pending.entry(net).or_default();
+            reads.push((address, reply));
+            reads.len() == 1
+        };
+        // Sent from a task of its own, so reads waiting on the batch don't depend on the
+        // request that opened the window staying connected
+        if opens_window {
+            let (window, max_batch) = (self.window, self.max_batch);
+            let (pending, counters) = (self.pending.clone(), self.counters.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(window).await;
+                let reads = pending.lock().unwrap().remove(&net).unwrap_or_default();
+                dispatch(&rpc_client, &counters, reads, max_batch).await;
+            });
+        }
+        answer.await.unwrap_or_else(|_| {
+            Err(ClientError::from(ClientErrorKind::Custom(
+                "account batch was dropped".to_string(),
+            )))
+        })
+    }
+
+    async fn read_alone(
+        &self,
+        rpc_client: &RpcClient,
+        address: &Pubkey,
+    ) -> Result<Option<Account>, ClientError> {
+        self.counters.direct.fetch_add(1, Ordering::Relaxed);
+        fetch_one(rpc_client, address).await
+    }
+
+    /// Reads sent on their own, through a batch and answered as missing since the start.
+    pub fn stats(&self) -> AccountFetchStats {
+        AccountFetchStats {
+            direct: self.counters.direct.load(Ordering::Relaxed),
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+            batches: self.counters.batches.load(Ordering::Relaxed),
+            missing_hits: self.counters.missing_hits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn fetch_one(
+    rpc_client: &RpcClient,
+    address: &Pubkey,
+) -> Result<Option<Account>, ClientError> {
+    Ok(rpc_client
+        .get_account_with_commitment(address, rpc_client.commitment())
+        .await?
+        .value)
+}
+
+async fn dispatch(
+    rpc_client: &RpcClient,
+    counters: &Counters,
+    mut reads: Vec<(Pubkey, Reply)>,
+    max_batch: usize,
+) {
+    if reads.len() == 1 {
+        let (address, reply) = reads.remove(0);
+        counters.direct.fetch_add(1, Ordering::Relaxed);
+        let _ = reply.send(fetch_one(rpc_client, &address).await);
+        return;
+    }
+    while !reads.is_empty() {
+        let rest = reads.split_off(reads.len().min(max_batch));
+        let (addresses, replies): (Vec<_>, Vec<_>) =
+            std::mem::replace(&mut reads, rest).into_iter().unzip();
+        counters.batches.fetch_add(1, Ordering::Relaxed);
+        counters
+            .coalesced
+            .fetch_add(addresses.len() as u64, Ordering::Relaxed);
+        match rpc_client
+            .get_multiple_accounts_with_commitment(&addresses, rpc_client.commitment())
+            .await
+        {
+            Ok(response) => {
+                for (reply, account) in replies.into_iter().zip(response.value) {
+                    let _ = reply.send(Ok(account));
+                }
+            }
+            Err(e) => {
+                for reply in replies {
+                    let _ = reply.send(Err(shared_error(&e)));
+                }
+            }
+        }
+    }
+}
+
+/// `error` for every read of a failed batch, `ClientError` can't be cloned. A method the node
+/// doesn't serve stays recognizable as one.
+fn shared_error(error: &ClientError) -> ClientError {
+    let kind = match unsupported_method(error) {
+        Some(_) => ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code: METHOD_NOT_FOUND,
+            message: error.to_string(),
+            data: RpcResponseErrorData::Empty,
+        }),
+        None => ClientErrorKind::Custom(error.to_string()),
+    };
+    ClientError::new_with_request(kind, RpcRequest::GetMultipleAccounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::{
+        account_batch::{AccountBatchConfig, AccountBatcher},
+        fixtures::{Accounts, counting, mint},
+        models::Network,
+        rpc_override,
+    };
+
+    #[tokio::test]
+    async fn test_concurrent_reads_share_calls() {
+        let addresses: Vec<_> = (0..100).map(|_| Pubkey::new_unique()).collect();
+        // Every other address has an account
+        let accounts = addresses
+            .iter()
+            .step_by(2)
+            .fold(Accounts::default(), |accounts, address| {
+                accounts.with(*address, mint(6))
+            });
+        let upstream = counting(accounts);
+        let rpc_client = Arc::new(upstream.async_rpc_client());
+        let batcher = Arc::new(AccountBatcher::new(&AccountBatchConfig {
+            window_ms: 50,
+            max_batch: 40,
+            ..AccountBatchConfig::default()
+        }));
+
+        let reads: Vec<_> = addresses
+            .iter()
+            .map(|address| {
+                let (batcher, rpc_client, address) =
+                    (batcher.clone(), rpc_client.clone(), *address);
+                tokio::spawn(async move {
+                    batcher
+                        .get_account(rpc_client, Network::Devnet, address)
+                        .await
+                })
+            })
+            .collect();
+        for (i, read) in reads.into_iter().enumerate() {
+            let account = read.await.unwrap().unwrap();
+            assert_eq!(account.is_some(), i % 2 == 0);
+        }
+
+        // 100 reads, split in batches of at most 40
+        assert_eq!(upstream.calls(RpcRequest::GetMultipleAccounts), 3);
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 0);
+        let stats = batcher.stats();
+        assert_eq!((stats.direct, stats.coalesced, stats.batches), (0, 100, 3));
+
+        // A read alone in its window goes out as it is
+        let address = addresses[0];
+        let account = batcher
+            .get_account(rpc_client.clone(), Network::Devnet, address)
+            .await
+            .unwrap();
+        assert!(account.is_some());
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 1);
+        assert_eq!(batcher.stats().direct, 1);
+    }
+
+    #[tokio::test]
+    async fn test_lookups_remember_missing_accounts() {
+        let (missing, present) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let upstream = counting(Accounts::default().with(present, mint(6)));
+        let rpc_client = Arc::new(upstream.async_rpc_client());
+        let batcher = AccountBatcher::new(&AccountBatchConfig {
+            window_ms: 0,
+            ..AccountBatchConfig::default()
+        });
+        let lookup =
+            |address, fresh| batcher.lookup(rpc_client.clone(), Network::Devnet, address, fresh);
+
+        assert!(lookup(missing, false).await.unwrap().is_none());
+        assert!(lookup(missing, false).await.unwrap().is_none());
+        assert!(lookup(present, false).await.unwrap().is_some());
+        assert!(lookup(present, false).await.unwrap().is_some());
+        // Only the missing account was answered without asking
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 3);
+        assert_eq!(batcher.stats().missing_hits, 1);
+
+        // Fresh lookups and the reads of the send paths always ask
+        assert!(lookup(missing, true).await.unwrap().is_none());
+        for _ in 0..2 {
+            let account = batcher
+                .get_account(rpc_client.clone(), Network::Devnet, missing)
+                .await
+                .unwrap();
+            assert!(account.is_none());
+        }
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 6);
+        assert_eq!(batcher.stats().missing_hits, 1);
+        // Remembered per network
+        let account = batcher
+            .lookup(rpc_client.clone(), Network::Testnet, missing, false)
+            .await
+            .unwrap();
+        assert!(account.is_none());
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 7);
+
+        batcher.flush_missing();
+        assert!(lookup(missing, false).await.unwrap().is_none());
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 8);
+
+        // Until the TTL runs out
+        let batcher = AccountBatcher::new(&AccountBatchConfig {
+            window_ms: 0,
+            missing_ttl_ms: 20,
+            ..AccountBatchConfig::default()
+        });
+        let lookup = |fresh| batcher.lookup(rpc_client.clone(), Network::Devnet, missing, fresh);
+        lookup(false).await.unwrap();
+        lookup(false).await.unwrap();
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 9);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        lookup(false).await.unwrap();
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 10);
+    }
+    #[tokio::test]
+    async fn test_reads_of_a_requested_rpc_url_go_alone() {
+        let (missing, present) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let upstream = counting(Accounts::default().with(present, mint(6)));
+        let rpc_client = Arc::new(upstream.async_rpc_client());
+        let batcher = AccountBatcher::new(&AccountBatchConfig {
+            window_ms: 50,
+            ..AccountBatchConfig::default()
+        });
+
+        let reads = async {
+            for address in [missing, missing, present] {
+                batcher
+                    .lookup(rpc_client.clone(), Network::Devnet, address, false)
+                    .await
+                    .unwrap();
+            }
+        };
+        rpc_override::scope("http://127.0.0.1:8899".to_string(), reads).await;
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 3);
+        assert_eq!(upstream.calls(RpcRequest::GetMultipleAccounts), 0);
+        let stats = batcher.stats();
+        assert_eq!((stats.direct, stats.batches, stats.missing_hits), (3, 0, 0));
+
+        // Nor did they leave the missing account for the others
+        let account = batcher
+            .lookup(rpc_client.clone(), Network::Devnet, missing, false)
+            .await
+            .unwrap();
+        assert!(account.is_none());
+        assert_eq!(upstream.calls(RpcRequest::GetMultipleAccounts), 0);
+        assert_eq!(upstream.calls(RpcRequest::GetAccountInfo), 4);
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::signature::Signature;
+
+use crate::{
+    bounded_store::{BoundedStore, StateLimits, StoreConfig, StoreStats},
+    ceremony::Ceremonies,
+    message_review::CompiledMessage,
+    tenant::Caller,
+};
+
+/// Oldest entries are dropped once the log holds this many, unless `state_limits.audit_log`
+/// says otherwise.
+pub const AUDIT_LOG_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// A party's partial signature was made in step two, nothing was sent
+    Signed,
+    /// The client went away before the transaction was handed to the RPC node, or a scheduled
+    /// transaction was cancelled before its `broadcast_at`
+    Cancelled,
+    /// Held by the server until its `broadcast_at`
+    Scheduled,
+    /// Aggregated and handed back by a `dry_run`, the caller sends it
+    DryRun,
+    /// Accepted by the RPC node, confirmation pending
+    Broadcast,
+    Confirmed,
+    Failed,
+    /// Its blockhash expired without it landing
+    Expired,
+    /// A registry token was deleted, it can be restored until it is purged
+    Deleted,
+    Restored,
+    /// A deleted registry token was removed for good
+    Purged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEntry {
+    pub id: u64,
+    /// Unix timestamp in milliseconds
+    pub at: u64,
+    pub endpoint: String,
+    pub signature: Option<String>,
+    pub request_digest: Option<String>,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+    /// Tenant of the API key that made the request, unset for the default tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Scheme, host and port of the RPC node the transaction was sent to or checked on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_endpoint: Option<String>,
+    /// Hex encoded SHA-256 of the compiled message that was signed or sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_hash: Option<String>,
+    /// The compiled message, base64 encoded. Only kept with `audit_full_messages` and only
+    /// served by `/api/audit/{id}/message`
+    #[serde(skip)]
+    pub message: Option<String>,
+    /// On the entry that ended a TSS ceremony, how long it took since its first step two
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ceremony_ms: Option<u64>,
+    /// Symbol of the registry token a deletion, restore or purge was about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Audited outcomes of the transactions sent through one RPC endpoint, since the start.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EndpointOutcomes {
+    pub rpc_endpoint: String,
+    pub broadcast: u64,
+    pub confirmed: u64,
+    /// Sends the node refused and transactions that landed with an error
+    pub failed: u64,
+    pub expired: u64,
+}
+
+/// In-memory record of what happened to every transaction the service tried to broadcast.
+pub struct AuditLog {
+    next_id: Mutex<u64>,
+    /// Counted as entries are recorded, unaffected by eviction
+    by_endpoint: Mutex<BTreeMap<String, EndpointOutcomes>>,
+    /// By id, entries are never looked up so the least recently used is the oldest
+    entries: BoundedStore<u64, AuditEntry>,
+    /// Keep the compiled messages themselves, not only their hashes
+    full_messages: bool,
+    ceremonies: Ceremonies,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new(StoreConfig {
+            capacity: AUDIT_LOG_CAPACITY,
+            ttl_secs: None,
+        })
+    }
+}
+
+impl AuditLog {
+    pub fn new(config: StoreConfig) -> Self {
+        Self {
+            next_id: Mutex::new(0),
+            by_endpoint: Mutex::new(BTreeMap::new()),
+            entries: BoundedStore::new("audit_log", config),
+            full_messages: false,
+            ceremonies: Ceremonies::new(StateLimits::default().ceremonies),
+        }
+    }
+
+    /// Capacity and TTL of the ceremonies in progress.
+    pub fn ceremony_limits(mut self, config: StoreConfig) -> Self {
+        self.ceremonies = Ceremonies::new(config);
+        self
+    }
+
+    /// Clocks of the TSS ceremonies, stopped as their final outcomes are recorded.
+    pub fn ceremonies(&self) -> &Ceremonies {
+        &self.ceremonies
+    }
+
+    /// Keep every audited message in full next to its hash.
+    pub fn full_messages(mut self, full_messages: bool) -> Self {
+        self.full_messages = full_messages;
+        self
+    }
+
+    /// Write an entry, returns its id.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        tenant: Option<&str>,
+        endpoint: &str,
+        signature: Option<&Signature>,
+        request_digest: Option<&str>,
+        rpc_endpoint: Option<&str>,
+        outcome: Outcome,
+        error: Option<String>,
+        message: Option<&CompiledMessage>,
+    ) -> u64 {
+        if let Some(rpc_endpoint) = rpc_endpoint {
+            let mut by_endpoint = self.by_endpoint.lock().unwrap();
+            let counts = by_endpoint
+                .entry(rpc_endpoint.to_string())
+                .or_insert_with(|| EndpointOutcomes {
+                    rpc_endpoint: rpc_endpoint.to_string(),
+                    ..EndpointOutcomes::default()
+                });
+            match outcome {
+                Outcome::Broadcast => counts.broadcast += 1,
+                Outcome::Confirmed => counts.confirmed += 1,
+                Outcome::Failed => counts.failed += 1,
+                Outcome::Expired => counts.expired += 1,
+                Outcome::Signed
+                | Outcome::Cancelled
+                | Outcome::Scheduled
+                | Outcome::DryRun
+                | Outcome::Deleted
+                | Outcome::Restored
+                | Outcome::Purged => {}
+            }
+        }
+        let ceremony_ms = request_digest
+            .and_then(|digest| self.ceremonies.finished(digest, outcome, Instant::now()))
+            .map(|duration| duration.as_millis() as u64);
+        self.insert(AuditEntry {
+            id: 0,
+            at: 0,
+            endpoint: endpoint.to_string(),
+            signature: signature.map(Signature::to_string),
+            request_digest: request_digest.map(str::to_string),
+            outcome,
+            error,
+            tenant: tenant.map(str::to_string),
+            rpc_endpoint: rpc_endpoint.map(str::to_string),
+            message_hash: message.map(|message| message.hash.clone()),
+            message: message
+                .filter(|_| self.full_messages)
+                .map(CompiledMessage::encode),
+            ceremony_ms,
+            token: None,
+        })
+    }
+
+    /// Write an entry for a change of the token registry, returns its id.
+    pub fn record_token(
+        &self,
+        tenant: Option<&str>,
+        endpoint: &str,
+        outcome: Outcome,
+        symbol: &str,
+    ) -> u64 {
+        self.insert(AuditEntry {
+            id: 0,
+            at: 0,
+            endpoint: endpoint.to_string(),
+            signature: None,
+            request_digest: None,
+            outcome,
+            error: None,
+            tenant: tenant.map(str::to_string),
+            rpc_endpoint: None,
+            message_hash: None,
+            message: None,
+            ceremony_ms: None,
+            token: Some(symbol.to_string()),
+        })
+    }
+
+    /// Store `entry` under the next id, stamped with the current time.
+    fn insert(&self, mut entry: AuditEntry) -> u64 {
+        entry.at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        // Held while inserting, so entries are stored in id order
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        entry.id = *next_id;
+        let id = entry.id;
+        self.entries.insert(id, entry, Instant::now());
+        id
+    }
+
+    /// Every entry for `signature`, oldest first.
+    pub fn for_signature(&self, signature: &Signature) -> Vec<AuditEntry> {
+        let signature = signature.to_string();
+        let mut entries = self.entries.filter(Instant::now(), |e| {
+            e.signature.as_deref() == Some(signature.as_str())
+        });
+        // The store keeps them by last use, not by when they were written
+        entries.sort_by_key(|e| (e.at, e.id));
+        entries
+    }
+
+    /// The entry with `id`, unless it was evicted.
+    pub fn entry(&self, id: u64) -> Option<AuditEntry> {
+        // Not `get`, a lookup would count as a use and change which entry is evicted next
+        self.entries
+            .filter(Instant::now(), |e| e.id == id)
+            .into_iter()
+            .next()
+    }
+
+    /// Up to `limit` entries written in `from..to` (Unix milliseconds) with an id above
+    /// `after`, in id order.
+    pub fn page(&self, from: u64, to: u64, after: u64, limit: usize) -> Vec<AuditEntry> {
+        let mut entries = self.entries.filter(Instant::now(), |e| {
+            (from..to).contains(&e.at) && e.id > after
+        });
+        entries.sort_by_key(|e| e.id);
+        entries.truncate(limit);
+        entries
+    }
+
+    /// The entries for `signature` that `caller` may see.
+    pub fn for_signature_as(&self, signature: &Signature, caller: &Caller) -> Vec<AuditEntry> {
+        self.for_signature(signature)
+            .into_iter()
+            .filter(|e| caller.can_access(e.tenant.as_deref()))
+            .collect()
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        self.entries.stats()
+    }
+
+    /// Outcomes by RPC endpoint, ordered by endpoint.
+    pub fn endpoint_outcomes(&self) -> Vec<EndpointOutcomes> {
+        self.by_endpoint.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signature::Signature;
+
+    use crate::{
+        audit::{AuditLog, Outcome},
+        bounded_store::StoreConfig,
+        tenant::Caller,
+    };
+
+    #[test]
+    fn test_capacity_and_lookup() {
+        let log = AuditLog::new(StoreConfig {
+            capacity: 2,
+            ttl_secs: None,
+        });
+        let (first, second) = (Signature::from([1; 64]), Signature::from([2; 64]));
+        let second_tenant = Some("team-b");
+        log.record(
+            None,
+            "send_single",
+            Some(&first),
+            None,
+            None,
+            Outcome::Broadcast,
+            None,
+            None,
+        );
+        log.record(
+            second_tenant,
+            "send_single",
+            Some(&second),
+            None,
+            None,
+            Outcome::Broadcast,
+            None,
+            None,
+        );
+        log.record(
+            second_tenant,
+            "send_single",
+            Some(&second),
+            None,
+            None,
+            Outcome::Confirmed,
+            None,
+            None,
+        );
+
+        assert!(log.for_signature(&first).is_empty());
+        let entries = log.for_signature(&second);
+        assert_eq!(
+            entries.iter().map(|e| e.outcome).collect::<Vec<_>>(),
+            [Outcome::Broadcast, Outcome::Confirmed]
+        );
+        assert!(entries[0].id < entries[1].id);
+
+        // Other tenants don't see them, admins do
+        assert!(log.for_signature_as(&second, &Caller::default()).is_empty());
+        assert!(
+            log.for_signature_as(&second, &Caller::tenant("team-a"))
+                .is_empty()
+        );
+        assert_eq!(
+            log.for_signature_as(&second, &Caller::tenant("team-b"))
+                .len(),
+            2
+        );
+        let admin = Caller {
+            tenant: Some("ops".to_string()),
+            admin: true,
+        };
+        assert_eq!(log.for_signature_as(&second, &admin).len(), 2);
+    }
+}
@@ -0,0 +1,406 @@
+use std::io::Write;
+
+use flate2::{Compression, write::GzEncoder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    Error,
+    audit::{AuditEntry, AuditLog},
+    version::GIT_COMMIT,
+};
+
+// `/api/audit/export` streams the audit log as NDJSON: a header record, the entries of the
+// requested time range in id order and a trailer record. The trailer carries the SHA-256 of
+// every byte before it, so a recipient can tell a complete export from a cut off one. The
+// entries are read a page at a time and written to the response as they are serialized, an
+// export never holds more than a page. An export stops after `limit` entries, its trailer then
+// names the `next_cursor` to continue from; a client whose stream broke off passes the id of the
+// last entry it got instead.
+
+/// Widest `from..to` a single export may span, one week.
+pub const MAX_EXPORT_RANGE_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+/// Most entries of one export, and its default `limit`.
+pub const MAX_EXPORT_ENTRIES: usize = 100_000;
+/// Entries read from the log and written out at once.
+const PAGE_SIZE: usize = 1_000;
+
+/// The entries an export covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportRange {
+    /// Unix milliseconds, inclusive
+    pub from: u64,
+    /// Unix milliseconds, exclusive
+    pub to: u64,
+    /// Only entries with a higher id, from the continuation token
+    pub after: u64,
+    pub limit: usize,
+}
+
+impl ExportRange {
+    pub fn new(
+        from: u64,
+        to: u64,
+        cursor: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Self, Error> {
+        if to <= from {
+            return Err(Error::InvalidExportRange(format!(
+                "to ({}) must be after from ({})",
+                to, from
+            )));
+        }
+        if to - from > MAX_EXPORT_RANGE_MS {
+            return Err(Error::InvalidExportRange(format!(
+                "{} ms requested, an export spans at most {} ms",
+                to - from,
+                MAX_EXPORT_RANGE_MS
+            )));
+        }
+        let after = match cursor {
+            Some(cursor) => cursor.parse().map_err(|_| {
+                Error::InvalidExportRange(format!("cursor {:?} isn't a continuation token", cursor))
+            })?,
+            None => 0,
+        };
+        let limit = limit.unwrap_or(MAX_EXPORT_ENTRIES);
+        if limit == 0 || limit > MAX_EXPORT_ENTRIES {
+            return Err(Error::InvalidExportRange(format!(
+                "limit must be between 1 and {}",
+                MAX_EXPORT_ENTRIES
+            )));
+        }
+        Ok(Self {
+            from,
+            to,
+            after,
+            limit,
+        })
+    }
+}
+
+/// One line of an export.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExportRecord {
+    Header {
+        version: String,
+        git_commit: String,
+        from: u64,
+        to: u64,
+        /// The continuation token the export was requested with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
+    },
+    Entry(AuditEntry),
+    Trailer {
+        entries: u64,
+        /// Hex encoded SHA-256 of every line before the trailer, uncompressed
+        sha256: String,
+        /// Set when the export stopped at its `limit`, request the rest with it as `cursor`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+    },
+}
+
+/// Serializes records into the bytes of an export, gzip compressed or not, and hashes them.
+pub struct ExportWriter {
+    hasher: Sha256,
+    gzip: Option<GzEncoder<Vec<u8>>>,
+    entries: u64,
+}
+
+impl ExportWriter {
+    pub fn new(gzip: bool) -> Self {
+        Self {
+            hasher: Sha256::new(),
+            gzip: gzip.then(|| GzEncoder::new(Vec::new(), Compression::default())),
+            entries: 0,
+        }
+    }
+
+    /// The bytes of `records`, ready to send. Compressed data is flushed so every chunk can be
+    /// decompressed as it arrives.
+    pub fn write(&mut self, records: &[ExportRecord]) -> Vec<u8> {
+        let mut lines = Vec::new();
+        for record in records {
+            if let ExportRecord::Entry(_) = record {
+                self.entries += 1;
+            }
+            // Records are plain data, serializing them can't fail
+            let _ = serde_json::to_writer(&mut lines, record);
+            lines.push(b'\n');
+        }
+        self.hasher.update(&lines);
+        self.encode(&lines, false)
+    }
+
+    /// The trailer and, compressed, the end of the gzip stream.
+    pub fn finish(mut self, next_cursor: Option<String>) -> Vec<u8> {
+        let trailer = ExportRecord::Trailer {
+            entries: self.entries,
+            sha256: self
+                .hasher
+                .clone()
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            next_cursor,
+        };
+        let mut line = serde_json::to_vec(&trailer).unwrap_or_default();
+        line.push(b'\n');
+        self.encode(&line, true)
+    }
+
+    fn encode(&mut self, bytes: &[u8], last: bool) -> Vec<u8> {
+        let Some(gzip) = self.gzip.as_mut() else {
+            return bytes.to_vec();
+        };
+        // Writing into a `Vec` can't fail
+        let _ = gzip.write_all(bytes);
+        match last {
+            true => self
+                .gzip
+                .take()
+                .and_then(|gzip| gzip.finish().ok())
+                .unwrap_or_default(),
+            false => {
+                let _ = gzip.flush();
+                std::mem::take(gzip.get_mut())
+            }
+        }
+    }
+}
+
+/// Write the export of `range` to `out` a page at a time, until it is complete or `out` fails
+/// because the client went away.
+pub async fn export(
+    log: &AuditLog,
+    range: ExportRange,
+    gzip: bool,
+    mut out: impl AsyncWrite + Unpin,
+) -> std::io::Result<()> {
+    let mut writer = ExportWriter::new(gzip);
+    let header = ExportRecord::Header {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: GIT_COMMIT.to_string(),
+        from: range.from,
+        to: range.to,
+        cursor: (range.after > 0).then(|| range.after.to_string()),
+    };
+    out.write_all(&writer.write(&[header])).await?;
+
+    let (mut after, mut left) = (range.after, range.limit);
+    let next_cursor = loop {
+        let want = PAGE_SIZE.min(left);
+        let page = log.page(range.from, range.to, after, want);
+        let Some(last) = page.last() else {
+            break None;
+        };
+        let exhausted = page.len() < want;
+        after = last.id;
+        left -= page.len();
+        let records: Vec<_> = page.into_iter().map(ExportRecord::Entry).collect();
+        out.write_all(&writer.write(&records)).await?;
+        if exhausted {
+            break None;
+        }
+        if left == 0 {
+            let more = !log.page(range.from, range.to, after, 1).is_empty();
+            break more.then(|| after.to_string());
+        }
+    };
+    out.write_all(&writer.finish(next_cursor)).await?;
+    out.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use flate2::read::GzDecoder;
+    use sha2::{Digest, Sha256};
+    use solana_sdk::signature::Signature;
+
+    use crate::{
+        audit::{AuditLog, Outcome},
+        audit_export::{ExportRange, ExportRecord, MAX_EXPORT_RANGE_MS, export},
+        bounded_store::StoreConfig,
+    };
+
+    fn now_ms() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    fn record(log: &AuditLog, n: usize) {
+        for i in 0..n {
+            log.record(
+                (i % 2 == 0).then_some("team-a"),
+                "send_single",
+                Some(&Signature::from([i as u8; 64])),
+                None,
+                Some("https://api.devnet.solana.com"),
+                Outcome::Broadcast,
+                None,
+                None,
+            );
+        }
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Whether the trailer, the last line of `text`, holds the hash of the lines before it.
+    fn intact(text: &str) -> bool {
+        let trailer_start = text[..text.len() - 1].rfind('\n').unwrap() + 1;
+        match serde_json::from_str(&text[trailer_start..]).unwrap() {
+            ExportRecord::Trailer { sha256: hash, .. } => {
+                hash == sha256(&text.as_bytes()[..trailer_start])
+            }
+            other => panic!("no trailer but {:?}", other),
+        }
+    }
+
+    /// The lines of an export and whether its trailer's hash is that of the lines before it.
+    async fn run(log: &AuditLog, range: ExportRange, gzip: bool) -> (Vec<ExportRecord>, bool) {
+        let mut out = Vec::new();
+        export(log, range, gzip, &mut out).await.unwrap();
+        let mut bytes = Vec::new();
+        match gzip {
+            true => {
+                GzDecoder::new(&out[..]).read_to_end(&mut bytes).unwrap();
+            }
+            false => bytes = out,
+        }
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.ends_with('\n'));
+        let records = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (records, intact(&text))
+    }
+
+    fn ids(records: &[ExportRecord]) -> Vec<u64> {
+        records
+            .iter()
+            .filter_map(|record| match record {
+                ExportRecord::Entry(entry) => Some(entry.id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_every_entry_with_its_hash() {
+        let log = AuditLog::new(StoreConfig {
+            capacity: 10_000,
+            ttl_secs: None,
+        });
+        record(&log, 2_500);
+        let start = now_ms() + 1;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        record(&log, 10);
+        let end = now_ms() + 1;
+
+        // More than a page, compressed and not, the same lines
+        let range = ExportRange::new(0, end, None, None).unwrap();
+        let (plain, intact) = run(&log, range, false).await;
+        assert!(intact);
+        let (gzipped, intact) = run(&log, range, true).await;
+        assert!(intact);
+        assert_eq!(
+            serde_json::to_string(&plain).unwrap(),
+            serde_json::to_string(&gzipped).unwrap()
+        );
+        assert!(matches!(
+            &plain[0],
+            ExportRecord::Header { version, .. } if version == env!("CARGO_PKG_VERSION")
+        ));
+        assert_eq!(ids(&plain), (1..=2_510).collect::<Vec<_>>());
+        assert!(matches!(
+            plain.last(),
+            Some(ExportRecord::Trailer {
+                entries: 2_510,
+                next_cursor: None,
+                ..
+            })
+        ));
+
+        // Only the time range
+        let range = ExportRange::new(start, end, None, None).unwrap();
+        let (records, _) = run(&log, range, true).await;
+        assert_eq!(ids(&records), (2_501..=2_510).collect::<Vec<_>>());
+
+        // Stopped at the limit, the cursor picks up where it ended
+        let range = ExportRange::new(0, end, None, Some(1_500)).unwrap();
+        let (first, intact) = run(&log, range, true).await;
+        assert!(intact);
+        let Some(ExportRecord::Trailer {
+            entries: 1_500,
+            next_cursor: Some(cursor),
+            ..
+        }) = first.last()
+        else {
+            panic!("no cursor in {:?}", first.last());
+        };
+        let range = ExportRange::new(0, end, Some(cursor), Some(1_500)).unwrap();
+        let (rest, _) = run(&log, range, true).await;
+        let mut all = ids(&first);
+        all.extend(ids(&rest));
+        assert_eq!(all, (1..=2_510).collect::<Vec<_>>());
+        assert!(matches!(
+            rest.last(),
+            Some(ExportRecord::Trailer {
+                next_cursor: None,
+                ..
+            })
+        ));
+
+        // Exactly at the limit with nothing left, no cursor
+        let range = ExportRange::new(start, end, None, Some(10)).unwrap();
+        let (records, _) = run(&log, range, false).await;
+        assert!(matches!(
+            records.last(),
+            Some(ExportRecord::Trailer {
+                entries: 10,
+                next_cursor: None,
+                ..
+            })
+        ));
+
+        // A tampered line no longer matches the trailer
+        let mut out = Vec::new();
+        export(&log, range, false, &mut out).await.unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(intact(&text));
+        assert!(!intact(&text.replacen("team-a", "team-b", 1)));
+    }
+
+    #[test]
+    fn test_export_range_limits() {
+        assert!(ExportRange::new(0, MAX_EXPORT_RANGE_MS, None, None).is_ok());
+        for (from, to, cursor, limit) in [
+            (0, MAX_EXPORT_RANGE_MS + 1, None, None),
+            (10, 10, None, None),
+            (0, 10, Some("next"), None),
+            (0, 10, None, Some(0)),
+        ] {
+            let error = ExportRange::new(from, to, cursor, limit).unwrap_err();
+            assert_eq!(error.error_code(), "INVALID_EXPORT_RANGE");
+        }
+        let range = ExportRange::new(0, 10, Some("41"), Some(5)).unwrap();
+        assert_eq!((range.after, range.limit), (41, 5));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AUDIT_LOG_CAPACITY;
+
+// Everything the service keeps in memory about past requests goes through a `BoundedStore`:
+// at most `capacity` entries, each dropped once it is older than the store's TTL, and the
+// least recently used one evicted when a new entry needs room. Some stores only work while
+// their entries are kept for long enough, evicting one of those younger than the store's
+// `min_retention` is logged as a warning and counted apart.
+
+/// Capacity and TTL of one store, an entry of the `state_limits` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreConfig {
+    pub capacity: usize,
+    /// Entries older than this are dropped, kept until evicted when unset
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+/// Idempotency keys of scheduled broadcasts evicted sooner than this are warned about, the
+/// status of a transaction still waiting or confirming could no longer be found by its key.
+pub const IDEMPOTENCY_KEY_MIN_RETENTION: Duration = Duration::from_secs(600);
+
+/// The `state_limits` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateLimits {
+    /// Idempotency keys of scheduled broadcasts, for `/api/scheduled_broadcast_status`
+    pub idempotency_keys: StoreConfig,
+    pub audit_log: StoreConfig,
+    /// TSS ceremonies whose transaction hasn't reached its final outcome yet
+    pub ceremonies: StoreConfig,
+    /// Clients of the URLs requests sent in `rpc_url`
+    pub rpc_clients: StoreConfig,
+    /// Step one secret states kept for their step two, see `server_side_secret_state`
+    pub secret_states: StoreConfig,
+    /// Token buckets of the clients seen recently, see `rate_limits`
+    pub rate_limit_buckets: StoreConfig,
+}
+
+impl Default for StateLimits {
+    fn default() -> Self {
+        Self {
+            idempotency_keys: StoreConfig {
+                capacity: 100_000,
+                ttl_secs: Some(7 * 24 * 60 * 60),
+            },
+            audit_log: StoreConfig {
+                capacity: AUDIT_LOG_CAPACITY,
+                ttl_secs: None,
+            },
+            ceremonies: StoreConfig {
+                capacity: 10_000,
+                ttl_secs: Some(24 * 60 * 60),
+            },
+            rpc_clients: StoreConfig {
+                capacity: 64,
+                ttl_secs: Some(60 * 60),
+            },
+            secret_states: StoreConfig {
+                capacity: 10_000,
+                ttl_secs: Some(10 * 60),
+            },
+            rate_limit_buckets: StoreConfig {
+                capacity: 100_000,
+                ttl_secs: None,
+            },
+        }
+    }
+}
+
+/// Size and eviction counters of a store, as reported by `/api/state_stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct StoreStats {
+    pub name: String,
+    pub len: usize,
+    pub capacity: usize,
+    pub ttl_secs: Option<u64>,
+    /// Entries dropped to make room for new ones
+    pub evictions: u64,
+    /// Evictions of entries younger than the store's minimum retention
+    pub early_evictions: u64,
+    /// Entries dropped for being older than the TTL
+    pub expirations: u64,
+}
+
+struct Slot<V> {
+    value: V,
+    inserted: Instant,
+    used: u64,
+}
+
+struct Inner<K, V> {
+    entries: HashMap<K, Slot<V>>,
+    /// Keys by last use, least recently used first
+    order: BTreeMap<u64, K>,
+    next_use: u64,
+    evictions: u64,
+    early_evictions: u64,
+    expirations: u64,
+}
+
+pub struct BoundedStore<K, V> {
+    name: &'static str,
+    config: StoreConfig,
+    min_retention: Duration,
+    inner: Mutex<Inner<K, V>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> BoundedStore<K, V> {
+    pub fn new(name: &'static str, config: StoreConfig) -> Self {
+        Self {
+            name,
+            config: StoreConfig {
+                capacity: config.capacity.max(1),
+                ..config
+            },
+            min_retention: Duration::ZERO,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                next_use: 0,
+                evictions: 0,
+                early_evictions: 0,
+                expirations: 0,
+            }),
+        }
+    }
+
+    /// Warn about evictions of entries younger than `min_retention`.
+    pub fn min_retention(mut self, min_retention: Duration) -> Self {
+        self.min_retention = min_retention;
+        self
+    }
+
+    fn expired(&self, slot: &Slot<V>, now: Instant) -> bool {
+        self.config
+            .ttl_secs
+            .is_some_and(|ttl| now.saturating_duration_since(slot.inserted).as_secs() >= ttl)
+    }
+
+    pub fn insert(&self, key: K, value: V, now: Instant) {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        inner.next_use += 1;
+        let used = inner.next_use;
+        let slot = Slot {
+            value,
+            inserted: now,
+            used,
+        };
+        if let Some(old) = inner.entries.insert(key.clone(), slot) {
+            inner.order.remove(&old.used);
+        }
+        inner.order.insert(used, key);
+
+        while inner.entries.len() > self.config.capacity {
+            let Some((_, oldest)) = inner.order.pop_first() else {
+                break;
+            };
+            let Some(slot) = inner.entries.remove(&oldest) else {
+                continue;
+            };
+            if self.expired(&slot, now) {
+                inner.expirations += 1;
+                continue;
+            }
+            inner.evictions += 1;
+            let age = now.saturating_duration_since(slot.inserted);
+            if age < self.min_retention {
+                inner.early_evictions += 1;
+                tracing::warn!(
+                    store = self.name,
+                    capacity = self.config.capacity,
+                    age_secs = age.as_secs(),
+                    min_retention_secs = self.min_retention.as_secs(),
+                    "evicted an entry before its minimum retention, raise the store's capacity"
+                );
+            }
+        }
+    }
+
+    /// The value of `key`, which counts as a use of it.
+    pub fn get(&self, key: &K, now: Instant) -> Option<V> {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        let slot = inner.entries.get(key)?;
+        if self.expired(slot, now) {
+            let used = slot.used;
+            inner.entries.remove(key);
+            inner.order.remove(&used);
+            inner.expirations += 1;
+            return None;
+        }
+        inner.next_use += 1;
+        let used = inner.next_use;
+        let slot = inner.entries.get_mut(key)?;
+        inner.order.remove(&slot.used);
+        slot.used = used;
+        inner.order.insert(used, key.clone());
+        Some(slot.value.clone())
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        let slot = inner.entries.remove(key)?;
+        inner.order.remove(&slot.used);
+        Some(slot.value)
+    }
+
+    /// Remove the value of `key`, `None` when it already expired.
+    pub fn take(&self, key: &K, now: Instant) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        let slot = inner.entries.remove(key)?;
+        inner.order.remove(&slot.used);
+        if self.expired(&slot, now) {
+            inner.expirations += 1;
+            return None;
+        }
+        Some(slot.value)
+    }
+
+    /// Unexpired values matching `filter`, least recently used first. Doesn't count as a use.
+    pub fn filter(&self, now: Instant, filter: impl Fn(&V) -> bool) -> Vec<V> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .order
+            .values()
+            .filter_map(|key| inner.entries.get(key))
+            .filter(|slot| !self.expired(slot, now) && filter(&slot.value))
+            .map(|slot| slot.value.clone())
+            .collect()
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        let inner = self.inner.lock().unwrap();
+        StoreStats {
+            name: self.name.to_string(),
+            len: inner.entries.len(),
+            capacity: self.config.capacity,
+            ttl_secs: self.config.ttl_secs,
+            evictions: inner.evictions,
+            early_evictions: inner.early_evictions,
+            expirations: inner.expirations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::bounded_store::{BoundedStore, StoreConfig};
+
+    #[test]
+    fn test_lru_and_ttl() {
+        let now = Instant::now();
+        let store = BoundedStore::new(
+            "test",
+            StoreConfig {
+                capacity: 2,
+                ttl_secs: Some(60),
+            },
+        )
+        .min_retention(Duration::from_secs(10));
+        store.insert("a", 1, now);
+        store.insert("b", 2, now);
+        // Using `a` makes `b` the least recently used, evicted for `c` before it is 10s old
+        assert_eq!(store.get(&"a", now), Some(1));
+        store.insert("c", 3, now + Duration::from_secs(5));
+        assert_eq!(store.get(&"b", now), None);
+        assert_eq!(store.filter(now, |_| true), [1, 3]);
+        let stats = store.stats();
+        assert_eq!(
+            (stats.len, stats.evictions, stats.early_evictions),
+            (2, 1, 1)
+        );
+
+        // Old enough, not an early eviction
+        store.insert("d", 4, now + Duration::from_secs(20));
+        assert_eq!(store.stats().early_evictions, 1);
+        assert_eq!(store.stats().evictions, 2);
+
+        // Past the TTL entries are gone, dropping them for room doesn't count as eviction
+        let later = now + Duration::from_secs(70);
+        assert_eq!(store.get(&"c", later), None);
+        assert_eq!(store.filter(later, |_| true), [4]);
+        store.insert("e", 5, later);
+        store.insert("f", 6, later + Duration::from_secs(30));
+        let stats = store.stats();
+        assert_eq!((stats.len, stats.evictions, stats.expirations), (2, 2, 2));
+        assert_eq!(store.remove(&"e"), Some(5));
+        assert_eq!(store.stats().len, 1);
+        // Taking an expired entry drops it all the same
+        assert_eq!(store.take(&"f", later + Duration::from_secs(90)), None);
+        assert_eq!((store.stats().len, store.stats().expirations), (0, 3));
+    }
+}
@@ -0,0 +1,215 @@
+use poem::{
+    Body, Response,
+    http::{HeaderMap, HeaderValue, header},
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::etag;
+
+// Clients written against an older fork of this API use camelCase field names. Requests may
+// use either casing, every multi-word request field has a camelCase alias. Responses are
+// snake_case unless `response_case` or the `X-Response-Case` header of the request asks for
+// camelCase, then their field names are renamed on the way out. Keys that aren't snake_case,
+// token symbols or RPC method names, are kept as they are, and so are the keys of the objects
+// in `KEYED_BY_DATA`, which are names of things rather than fields.
+
+pub const RESPONSE_CASE_HEADER: &str = "X-Response-Case";
+
+/// Casing of the field names in responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldCase {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl FieldCase {
+    /// The casing `headers` ask for, `default` without a recognized `X-Response-Case`.
+    pub fn requested(headers: &HeaderMap, default: FieldCase) -> FieldCase {
+        let value = headers
+            .get(RESPONSE_CASE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase());
+        match value.as_deref() {
+            Some("camel") => FieldCase::Camel,
+            Some("snake") => FieldCase::Snake,
+            _ => default,
+        }
+    }
+}
+
+/// `key` in camelCase if it is a snake_case name, as it is otherwise.
+pub fn camel_case(key: &str) -> String {
+    let snake = key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !snake {
+        return key.to_string();
+    }
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                camel.push(c.to_ascii_uppercase());
+                upper = false;
+            }
+            c => camel.push(c),
+        }
+    }
+    camel
+}
+
+/// Fields holding an object keyed by data, such as the field names under an OpenAPI schema's
+/// `properties`. The objects they map to are renamed, their keys are not.
+const KEYED_BY_DATA: &[&str] = &["properties"];
+
+/// `value` with the field names of every object in it in camelCase.
+pub fn camel_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::Object(map) if KEYED_BY_DATA.contains(&key.as_str()) => {
+                            Value::Object(
+                                map.into_iter().map(|(k, v)| (k, camel_keys(v))).collect(),
+                            )
+                        }
+                        value => camel_keys(value),
+                    };
+                    (camel_case(&key), value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(camel_keys).collect()),
+        value => value,
+    }
+}
+
+/// `resp` with its field names in `case`. A response with an ETag gets that of the renamed
+/// body, and a 304 when `If-None-Match` in `headers` names it.
+pub async fn render(headers: &HeaderMap, resp: Response, case: FieldCase) -> Response {
+    let json = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if case == FieldCase::Snake || !json {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    let bytes = match body.into_bytes().await {
+        Ok(bytes) => bytes,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let body = match serde_json::from_slice(&bytes) {
+        Ok(value) => serde_json::to_vec(&camel_keys(value)).unwrap_or_default(),
+        Err(_) => bytes.to_vec(),
+    };
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static(RESPONSE_CASE_HEADER));
+    let tagged = parts.headers.remove(header::ETAG).is_some();
+    let resp = Response::from_parts(parts, Body::from(body));
+    match tagged {
+        true => etag::conditional(headers, resp).await,
+        false => resp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Serialize, de::DeserializeOwned};
+    use serde_json::{Value, json};
+
+    use crate::{
+        case::{camel_case, camel_keys},
+        models::{AggSendStepTwoRequest, SplAggSendStepTwoRequest},
+    };
+
+    /// `snake` read from its camelCase form writes `snake` again.
+    fn round_trip<T: Serialize + DeserializeOwned>(snake: Value) {
+        let camel = camel_keys(snake.clone());
+        assert_ne!(camel, snake);
+        for request in [snake.clone(), camel] {
+            let request: T = serde_json::from_value(request).unwrap();
+            assert_eq!(serde_json::to_value(&request).unwrap(), snake);
+        }
+    }
+
+    #[test]
+    fn test_both_casings_round_trip() {
+        assert_eq!(camel_case("recent_block_hash"), "recentBlockHash");
+        assert_eq!(camel_case("getLatestBlockhash"), "getLatestBlockhash");
+        assert_eq!(camel_case("USDC"), "USDC");
+        assert_eq!(camel_case("rpc.get_slot"), "rpc.get_slot");
+
+        let common = json!({
+            "keypair": "keypair",
+            "to": "11111111111111111111111111111111",
+            "memo": "invoice 9",
+            "signed_memo": true,
+            "memo_program": "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+            "references": ["11111111111111111111111111111111"],
+            "recent_block_hash": "hash",
+            "keys": ["11111111111111111111111111111111"],
+            "first_messages": ["first"],
+            "secret_state": "secret",
+            "compute_unit_limit": "auto",
+            "priority_fee_micro_lamports": 5,
+            "net": "devnet",
+            "allow_delegate": true,
+            "expected_message_hash": "00",
+            "include_account_keys": true,
+            "session_id": "session",
+        });
+        let mut sol = common.clone();
+        sol["amount"] = Value::Null;
+        sol["lamports"] = 1_000.into();
+        sol["nonce_account"] = "11111111111111111111111111111111".into();
+        sol["nonce_authority"] = "11111111111111111111111111111111".into();
+        sol["allow_non_system_recipient"] = true.into();
+        sol["threshold"] = Value::Null;
+        sol["key_share"] = "share".into();
+        round_trip::<AggSendStepTwoRequest>(sol);
+
+        let mut spl = common;
+        spl["amount"] = 1.5.into();
+        spl["amount_base_units"] = Value::Null;
+        spl["token"] = "USDC".into();
+        spl["token_mint"] = "11111111111111111111111111111111".into();
+        spl["decimals"] = 6.into();
+        spl["token_program"] = "token_2022".into();
+        spl["rent_sponsor"] = "11111111111111111111111111111111".into();
+        round_trip::<SplAggSendStepTwoRequest>(spl);
+    }
+
+    #[test]
+    fn test_data_keys_are_kept() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "team_a": { "max_length": 8 },
+            },
+            "required": ["team_a"],
+            "additional_items": { "properties": { "token_mint": {} } },
+        });
+        assert_eq!(
+            camel_keys(schema),
+            json!({
+                "type": "object",
+                "properties": {
+                    "team_a": { "maxLength": 8 },
+                },
+                "required": ["team_a"],
+                "additionalItems": { "properties": { "token_mint": {} } },
+            })
+        );
+    }
+}
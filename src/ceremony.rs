@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audit::Outcome,
+    bounded_store::{BoundedStore, StoreConfig, StoreStats},
+};
+
+// The server keeps no session for a TSS ceremony, its parties only share the request digest
+// that every step two and the aggregation of the same transfer compute. The first step two
+// with a digest starts the ceremony's clock, the audited final outcome of the transaction with
+// that digest stops it. Step one isn't tied to any transfer yet, so it isn't part of the time.
+// Durations are exported as a Prometheus histogram by party count and outcome.
+
+/// Upper bounds of the histogram buckets in seconds. A blockhash lasts about a minute and a
+/// half, ceremonies close to that are at risk of expiring.
+const BUCKETS: [f64; 11] = [
+    1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 45.0, 60.0, 75.0, 90.0, 120.0,
+];
+
+const METRIC: &str = "tss_ceremony_duration_seconds";
+
+#[derive(Debug, Clone, Copy)]
+struct Open {
+    started: Instant,
+    parties: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Per bucket, not cumulative
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.counts[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// The outcome label of an outcome that ends a ceremony.
+fn final_outcome(outcome: Outcome) -> Option<&'static str> {
+    match outcome {
+        Outcome::Confirmed => Some("confirmed"),
+        Outcome::Expired => Some("expired"),
+        Outcome::Cancelled => Some("cancelled"),
+        Outcome::Failed => Some("failed"),
+        Outcome::Signed
+        | Outcome::Scheduled
+        | Outcome::DryRun
+        | Outcome::Broadcast
+        | Outcome::Deleted
+        | Outcome::Restored
+        | Outcome::Purged => None,
+    }
+}
+
+/// Ceremonies in progress by request digest, and the durations of the finished ones.
+pub struct Ceremonies {
+    open: BoundedStore<String, Open>,
+    durations: Mutex<BTreeMap<(usize, &'static str), Histogram>>,
+}
+
+impl Ceremonies {
+    pub fn new(config: StoreConfig) -> Self {
+        Self {
+            open: BoundedStore::new("ceremonies", config),
+            durations: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Start the clock of the ceremony of `parties` behind `digest`, unless another party
+    /// already did.
+    pub fn started(&self, digest: &str, parties: usize, now: Instant) {
+        let digest = digest.to_string();
+        if self.open.get(&digest, now).is_none() {
+            self.open.insert(
+                digest,
+                Open {
+                    started: now,
+                    parties,
+                },
+                now,
+            );
+        }
+    }
+
+    /// Stop the clock of the ceremony behind `digest` when `outcome` ends it, returns how long
+    /// it took.
+    pub fn finished(&self, digest: &str, outcome: Outcome, now: Instant) -> Option<Duration> {
+        let label = final_outcome(outcome)?;
+        let open = self.open.remove(&digest.to_string())?;
+        let duration = now.duration_since(open.started);
+        self.durations
+            .lock()
+            .unwrap()
+            .entry((open.parties, label))
+            .or_default()
+            .observe(duration.as_secs_f64());
+        Some(duration)
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        self.open.stats()
+    }
+
+    /// The histogram in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "# HELP {METRIC} Time from the first step two of a TSS ceremony to the final \
+             outcome of its transaction\n# TYPE {METRIC} histogram\n"
+        );
+        for ((parties, outcome), histogram) in self.durations.lock().unwrap().iter() {
+            let labels = format!("parties=\"{}\",outcome=\"{}\"", parties, outcome);
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "{METRIC}_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let count = histogram.count;
+            let _ = writeln!(text, "{METRIC}_bucket{{{labels},le=\"+Inf\"}} {count}");
+            let _ = writeln!(text, "{METRIC}_sum{{{labels}}} {}", histogram.sum);
+            let _ = writeln!(text, "{METRIC}_count{{{labels}}} {count}");
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{audit::Outcome, bounded_store::StoreConfig, ceremony::Ceremonies};
+
+    #[test]
+    fn test_ceremony_durations() {
+        let ceremonies = Ceremonies::new(StoreConfig {
+            capacity: 10,
+            ttl_secs: None,
+        });
+        let start = Instant::now();
+        ceremonies.started("a", 2, start);
+        // The second party doesn't restart the clock
+        ceremonies.started("a", 2, start + Duration::from_secs(2));
+        let done = start + Duration::from_secs(3);
+        assert_eq!(ceremonies.finished("a", Outcome::Broadcast, done), None);
+        assert_eq!(
+            ceremonies.finished("a", Outcome::Confirmed, done),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(ceremonies.finished("a", Outcome::Confirmed, done), None);
+        assert_eq!(ceremonies.finished("unknown", Outcome::Expired, done), None);
+
+        let text = ceremonies.render();
+        assert!(text.contains("# TYPE tss_ceremony_duration_seconds histogram"));
+        let labels = "parties=\"2\",outcome=\"confirmed\"";
+        for (le, count) in [("2", 0), ("5", 1), ("+Inf", 1)] {
+            let line = format!(
+                "tss_ceremony_duration_seconds_bucket{{{},le=\"{}\"}} {}\n",
+                labels, le, count
+            );
+            assert!(text.contains(&line), "{}", text);
+        }
+        assert!(text.contains(&format!(
+            "tss_ceremony_duration_seconds_sum{{{}}} 3\n",
+            labels
+        )));
+    }
+}
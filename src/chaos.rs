@@ -0,0 +1,400 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand07::{Rng, SeedableRng, rngs::StdRng};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient as AsyncRpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    rpc_response::RpcSimulateTransactionResult,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_sdk::{
+    hash::Hash, native_token::LAMPORTS_PER_SOL, rent::Rent, signature::Signature,
+    transaction::TransactionError,
+};
+
+use crate::{
+    Error, confirmation::Commitment, models::Network, rpc_clients::traced,
+    rpc_methods::METHOD_NOT_FOUND,
+};
+
+// Staging builds only (`--features chaos`). Every RPC client the handlers use sends through a
+// `ChaosSender`, which delays or fails calls as the rules set through `POST /api/chaos` say, so
+// retries, rebroadcasts and error reporting can be driven through the public handlers, against
+// the configured nodes or the mock node below. Mainnet is out of reach: a chaos build refuses a
+// mainnet `rpc_url` at startup and fails every mainnet call instead of sending it.
+
+/// Lamports of every account on the mock node.
+pub const MOCK_LAMPORTS: u64 = 1_000 * LAMPORTS_PER_SOL;
+
+/// JSON-RPC error code of a transaction refused by preflight.
+const PREFLIGHT_FAILURE: i64 = -32002;
+
+/// The body of `POST /api/chaos`, replacing the rules in effect.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Seeds the draws against the rules' probabilities, so a storm plays out the same way again
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Answer from the mock node instead of the configured ones
+    #[serde(default)]
+    pub mock: bool,
+    /// Tried in order, the first matching rule whose draw succeeds applies
+    #[serde(default)]
+    pub faults: Vec<FaultRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FaultRule {
+    /// JSON-RPC method such as `sendTransaction`, `*` for every method
+    pub method: String,
+    /// Chance a matching call gets the fault, from 0 to 1
+    #[serde(default = "always")]
+    pub probability: f64,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+fn always() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "fault", rename_all = "snake_case")]
+pub enum Fault {
+    /// Hold the call for `ms`, then send it
+    Latency { ms: u64 },
+    /// The 429 of a provider rate limiting us
+    RateLimited,
+    /// The connection drops before an answer
+    TransportError,
+    /// Preflight refuses the transaction for a blockhash the node doesn't know
+    BlockhashNotFound,
+    /// `getSignatureStatuses` answers, one per call with the last one repeating, `null` for
+    /// a signature the node hasn't seen
+    Statuses { sequence: Vec<Option<Commitment>> },
+}
+
+/// Calls of one method since the rules were last set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MethodCalls {
+    pub method: String,
+    pub calls: u64,
+    /// Calls a fault was injected into
+    pub faults: u64,
+}
+
+/// `GET /api/chaos`, the rules in effect and what they did.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ChaosReport {
+    pub config: ChaosConfig,
+    /// Ordered by method
+    pub methods: Vec<MethodCalls>,
+}
+
+struct Inner {
+    config: ChaosConfig,
+    rng: StdRng,
+    /// Next answer of each `statuses` rule, by rule
+    positions: Vec<usize>,
+    calls: BTreeMap<String, MethodCalls>,
+}
+
+/// What a call gets, decided before it is sent.
+enum Injected {
+    Delay(Duration),
+    Answer(Box<ClientResult<Value>>),
+}
+
+/// The rules shared by every chaos client, through `AppState`.
+pub struct Chaos {
+    inner: Mutex<Inner>,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                config: ChaosConfig::default(),
+                rng: StdRng::from_entropy(),
+                positions: Vec::new(),
+                calls: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl Chaos {
+    /// Replace the rules in effect, which also resets the call counts.
+    pub fn set(&self, config: ChaosConfig) -> Result<(), Error> {
+        for rule in &config.faults {
+            if !(0.0..=1.0).contains(&rule.probability) {
+                return Err(Error::InvalidChaosConfig(format!(
+                    "probability of {} faults is {}, it has to be from 0 to 1",
+                    rule.method, rule.probability
+                )));
+            }
+            if let Fault::Statuses { sequence } = &rule.fault {
+                if rule.method != "getSignatureStatuses" {
+                    return Err(Error::InvalidChaosConfig(format!(
+                        "statuses only answer getSignatureStatuses, not {}",
+                        rule.method
+                    )));
+                }
+                if sequence.is_empty() {
+                    return Err(Error::InvalidChaosConfig(
+                        "statuses need at least one answer".to_string(),
+                    ));
+                }
+            }
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        inner.positions = vec![0; config.faults.len()];
+        inner.calls.clear();
+        inner.config = config;
+        Ok(())
+    }
+
+    pub fn report(&self) -> ChaosReport {
+        let inner = self.inner.lock().unwrap();
+        ChaosReport {
+            config: inner.config.clone(),
+            methods: inner.calls.values().cloned().collect(),
+        }
+    }
+
+    /// A client for the node at `url` on `net` whose calls go through the rules.
+    pub fn async_rpc_client(self: &Arc<Self>, net: Network, url: &str) -> AsyncRpcClient {
+        AsyncRpcClient::new_sender(self.sender(net, url), RpcClientConfig::default())
+    }
+
+    fn sender(self: &Arc<Self>, net: Network, url: &str) -> ChaosSender {
+        ChaosSender {
+            chaos: self.clone(),
+            net,
+            url: url.to_string(),
+            upstream: traced(url.to_string()),
+        }
+    }
+
+    /// Count the call and draw its fault, `mock` tells where it goes if it is sent.
+    fn draw(&self, request: RpcRequest, params: &Value) -> (Option<Injected>, bool) {
+        let method = request.to_string();
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        let calls = inner
+            .calls
+            .entry(method.clone())
+            .or_insert_with(|| MethodCalls {
+                method: method.clone(),
+                ..MethodCalls::default()
+            });
+        calls.calls += 1;
+
+        let mock = inner.config.mock;
+        for (i, rule) in inner.config.faults.iter().enumerate() {
+            if rule.method != "*" && rule.method != method {
+                continue;
+            }
+            if inner.rng.r#gen::<f64>() >= rule.probability {
+                continue;
+            }
+            calls.faults += 1;
+            let answer = match &rule.fault {
+                Fault::Latency { ms } => {
+                    return (Some(Injected::Delay(Duration::from_millis(*ms))), mock);
+                }
+                Fault::RateLimited => Err(response_error(
+                    429,
+                    "Too Many Requests",
+                    RpcResponseErrorData::Empty,
+                )),
+                Fault::TransportError => {
+                    Err(ClientError::from(ClientErrorKind::Io(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        "connection reset (injected)",
+                    ))))
+                }
+                Fault::BlockhashNotFound => Err(response_error(
+                    PREFLIGHT_FAILURE,
+                    "Transaction simulation failed: Blockhash not found",
+                    RpcResponseErrorData::SendTransactionPreflightFailure(
+                        RpcSimulateTransactionResult {
+                            err: Some(TransactionError::BlockhashNotFound),
+                            logs: None,
+                            accounts: None,
+                            units_consumed: None,
+                            return_data: None,
+                            inner_instructions: None,
+                        },
+                    ),
+                )),
+                Fault::Statuses { sequence } => {
+                    let position = inner.positions[i].min(sequence.len() - 1);
+                    inner.positions[i] += 1;
+                    Ok(statuses(params, sequence[position]))
+                }
+            };
+            return (Some(Injected::Answer(Box::new(answer))), mock);
+        }
+        (None, mock)
+    }
+}
+
+fn response_error(code: i64, message: &str, data: RpcResponseErrorData) -> ClientError {
+    ClientError::from(ClientErrorKind::RpcError(RpcError::RpcResponseError {
+        code,
+        message: message.to_string(),
+        data,
+    }))
+}
+
+fn with_context(value: Value) -> Value {
+    json!({ "context": { "slot": 1 }, "value": value })
+}
+
+/// The same status for every signature asked about, `None` for not found.
+fn statuses(params: &Value, commitment: Option<Commitment>) -> Value {
+    let status = commitment.map(|commitment| {
+        json!({
+            "slot": 1,
+            "confirmations": if commitment == Commitment::Finalized { None } else { Some(0) },
+            "err": null,
+            "status": { "Ok": null },
+            "confirmationStatus": commitment,
+        })
+    });
+    let count = params[0].as_array().map_or(1, Vec::len);
+    with_context(json!(vec![status; count]))
+}
+
+fn mock_account() -> Value {
+    json!({
+        "lamports": MOCK_LAMPORTS,
+        "owner": "11111111111111111111111111111111",
+        "data": ["", "base64"],
+        "executable": false,
+        "rentEpoch": 0,
+        "space": 0,
+    })
+}
+
+/// A node where every address holds `MOCK_LAMPORTS` and every transaction lands, serving what
+/// the handlers call. The rest is "method not found", like a node with the method disabled.
+fn mock_node(request: RpcRequest, params: &Value) -> ClientResult<Value> {
+    Ok(match request {
+        RpcRequest::GetVersion => json!({ "solana-core": "1.18.26" }),
+        RpcRequest::GetSlot => json!(1),
+        RpcRequest::GetLatestBlockhash => with_context(json!({
+            "blockhash": Hash::default().to_string(),
+            "lastValidBlockHeight": 1_000,
+        })),
+        RpcRequest::IsBlockhashValid => with_context(json!(true)),
+        RpcRequest::GetSignatureStatuses => statuses(params, Some(Commitment::Finalized)),
+        RpcRequest::SendTransaction => json!(first_signature(&params[0]).to_string()),
+        RpcRequest::SimulateTransaction => with_context(json!({
+            "err": null,
+            "logs": [],
+            "accounts": null,
+            "unitsConsumed": 1_000,
+            "returnData": null,
+        })),
+        RpcRequest::GetAccountInfo => with_context(mock_account()),
+        RpcRequest::GetMultipleAccounts => with_context(
+            params[0]
+                .as_array()
+                .map(|addresses| addresses.iter().map(|_| mock_account()).collect())
+                .unwrap_or_default(),
+        ),
+        RpcRequest::GetBalance => with_context(json!(MOCK_LAMPORTS)),
+        RpcRequest::GetMinimumBalanceForRentExemption => {
+            json!(Rent::default().minimum_balance(params[0].as_u64().unwrap_or_default() as usize))
+        }
+        RpcRequest::GetFeeForMessage => with_context(json!(5_000)),
+        RpcRequest::RequestAirdrop => json!(Signature::new_unique().to_string()),
+        _ => {
+            return Err(ClientError::new_with_request(
+                ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                    code: METHOD_NOT_FOUND,
+                    message: "Method not found".to_string(),
+                    data: RpcResponseErrorData::Empty,
+                }),
+                request,
+            ));
+        }
+    })
+}
+
+/// The fee payer's signature of a base64 wire transaction, after its one byte signature count.
+fn first_signature(transaction: &Value) -> Signature {
+    transaction
+        .as_str()
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .and_then(|bytes| Signature::try_from(bytes.get(1..65)?).ok())
+        .unwrap_or_default()
+}
+
+struct ChaosSender {
+    chaos: Arc<Chaos>,
+    net: Network,
+    url: String,
+    upstream: AsyncRpcClient,
+}
+
+#[async_trait]
+impl RpcSender for ChaosSender {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        if self.net == Network::Mainnet {
+            return Err(ClientError::new_with_request(
+                ClientErrorKind::Custom("chaos builds never call mainnet".to_string()),
+                request,
+            ));
+        }
+        let (injected, mock) = self.chaos.draw(request, &params);
+        match injected {
+            Some(Injected::Answer(answer)) => return *answer,
+            Some(Injected::Delay(delay)) => tokio::time::sleep(delay).await,
+            None => {}
+        }
+        if mock {
+            mock_node(request, &params)
+        } else {
+            self.upstream.send(request, params).await
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.upstream.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// A chaos build can't be pointed at mainnet.
+pub fn check_config(rpc_urls: &HashMap<Network, String>) -> Result<(), String> {
+    if rpc_urls.contains_key(&Network::Mainnet) {
+        return Err(
+            "this build injects RPC faults (--features chaos) and refuses a mainnet rpc_url"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,699 @@
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+use reqwest::{Method, StatusCode, header};
+use serde::{Serialize, de::DeserializeOwned};
+
+#[cfg(feature = "chaos")]
+use crate::chaos::{ChaosConfig, ChaosReport};
+use crate::models::*;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    /// The API rejected the request with an `ErrorResponse`
+    Api {
+        status: u16,
+        error: String,
+        error_code: Option<String>,
+        /// The `retryable` flag of the response, or for a body that isn't an
+        /// `ErrorResponse` whether the status is a server error
+        retryable: bool,
+    },
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "request failed: {}", e),
+            Self::Api {
+                status,
+                error,
+                error_code: Some(code),
+            } => write!(f, "{} ({}): {}", status, code, error),
+            Self::Api { status, error, .. } => write!(f, "{}: {}", status, error),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+/// Typed async client for this API, one method per endpoint.
+///
+/// Every POST carries an `Idempotency-Key`, generated per call and reused when the call is
+/// retried after a 429, unless one was pinned with `idempotency_key`.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    idempotency_key: Option<String>,
+    max_retries: u32,
+    retry_delay: Duration,
+}
+
+impl Client {
+    /// `base_url` is the server root, e.g. `http://127.0.0.1:8000`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            idempotency_key: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+
+    /// Sent as `Authorization: Bearer <key>`.
+    pub fn api_key(mut self, key: Option<String>) -> Self {
+        self.api_key = key;
+        self
+    }
+
+    /// Send `key` instead of a fresh one, for callers that retry a request on their own.
+    pub fn idempotency_key(mut self, key: Option<String>) -> Self {
+        self.idempotency_key = key;
+        self
+    }
+
+    /// How often a rate limited (429) request is retried.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// First backoff when the server doesn't send `Retry-After`, doubled on every retry.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    async fn request<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T, ClientError> {
+        let url = format!("{}{}", self.base_url, path);
+        let idempotency_key = match (&self.idempotency_key, body) {
+            (Some(key), _) => Some(key.clone()),
+            (None, Some(_)) => Some(new_idempotency_key()),
+            (None, None) => None,
+        };
+
+        let mut attempt = 0;
+        loop {
+            let mut request = self.http.request(method.clone(), &url);
+            if let Some(key) = &self.api_key {
+                request = request.bearer_auth(key);
+            }
+            if let Some(key) = &idempotency_key {
+                request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+            }
+            #[cfg(feature = "otel")]
+            for (name, value) in trace_context_headers() {
+                request = request.header(name, value);
+            }
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let response = request.send().await?;
+
+            let status = response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS && attempt < self.max_retries {
+                let delay = retry_after(&response).unwrap_or(self.retry_delay * 2u32.pow(attempt));
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+            if status.is_success() {
+                return Ok(response.json().await?);
+            }
+
+            let body = response.text().await?;
+            return Err(match serde_json::from_str::<ErrorResponse>(&body) {
+                Ok(e) => ClientError::Api {
+                    status: status.as_u16(),
+                    error: e.error,
+                    error_code: e.error_code,
+                    retryable: e.retryable,
+                },
+                Err(_) => ClientError::Api {
+                    status: status.as_u16(),
+                    error: body,
+                    error_code: None,
+                    retryable: status.is_server_error(),
+                },
+            });
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.request::<(), T>(Method::GET, path, None).await
+    }
+
+    async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, ClientError> {
+        self.request(Method::POST, path, Some(body)).await
+    }
+
+    pub async fn generate(&self) -> Result<GenerateKeypairResponse, ClientError> {
+        self.get("/api/generate").await
+    }
+
+    /// A keypair derived from a fresh mnemonic, returned along with it.
+    pub async fn generate_with_mnemonic(&self) -> Result<GenerateKeypairResponse, ClientError> {
+        self.get("/api/generate?mnemonic=true").await
+    }
+
+    pub async fn convert_keypair(
+        &self,
+        req: &ConvertKeypairRequest,
+    ) -> Result<ConvertKeypairResponse, ClientError> {
+        self.post("/api/convert_keypair", req).await
+    }
+
+    pub async fn keypair_from_mnemonic(
+        &self,
+        req: &KeypairFromMnemonicRequest,
+    ) -> Result<GenerateKeypairResponse, ClientError> {
+        self.post("/api/keypair_from_mnemonic", req).await
+    }
+
+    pub async fn capabilities(&self) -> Result<CapabilitiesResponse, ClientError> {
+        self.get("/api/capabilities").await
+    }
+
+    pub async fn version(&self) -> Result<VersionResponse, ClientError> {
+        self.get("/api/version").await
+    }
+
+    pub async fn spend_limits(&self) -> Result<SpendLimitsResponse, ClientError> {
+        self.get("/api/spend_limits").await
+    }
+
+    pub async fn health(&self) -> Result<HealthResponse, ClientError> {
+        self.get("/api/health").await
+    }
+
+    pub async fn rpc_stats(&self) -> Result<RpcStatsResponse, ClientError> {
+        self.get("/api/rpc_stats").await
+    }
+
+    /// Needs an admin API key.
+    pub async fn state_stats(&self) -> Result<StateStatsResponse, ClientError> {
+        self.get("/api/state_stats").await
+    }
+
+    /// Needs an admin API key.
+    pub async fn usage_stats(&self) -> Result<UsageStatsResponse, ClientError> {
+        self.get("/api/admin/usage_stats").await
+    }
+
+    /// Read the config file again and switch to it, needs an admin API key.
+    pub async fn reload_config(&self) -> Result<ReloadConfigResponse, ClientError> {
+        self.request::<(), _>(Method::POST, "/api/admin/reload_config", None)
+            .await
+    }
+
+    /// Needs an admin API key.
+    pub async fn flush_caches(&self) -> Result<FlushCachesResponse, ClientError> {
+        self.request::<(), _>(Method::POST, "/api/admin/flush_caches", None)
+            .await
+    }
+
+    /// The compiled message of an audit entry, needs an admin API key.
+    pub async fn audit_message(&self, id: u64) -> Result<AuditMessageResponse, ClientError> {
+        self.get(&format!("/api/audit/{}/message", id)).await
+    }
+
+    /// Needs an admin API key and a server built with `--features chaos`.
+    #[cfg(feature = "chaos")]
+    pub async fn chaos(&self) -> Result<ChaosReport, ClientError> {
+        self.get("/api/chaos").await
+    }
+
+    /// Replace the injected RPC faults, needs an admin API key.
+    #[cfg(feature = "chaos")]
+    pub async fn set_chaos(&self, config: &ChaosConfig) -> Result<ChaosReport, ClientError> {
+        self.post("/api/chaos", config).await
+    }
+
+    pub async fn balance(&self, req: &BalanceRequest) -> Result<BalanceResponse, ClientError> {
+        self.post("/api/balance", req).await
+    }
+
+    pub async fn airdrop(&self, req: &AirdropRequest) -> Result<AirdropResponse, ClientError> {
+        self.post("/api/airdrop", req).await
+    }
+
+    pub async fn airdrop_batch(
+        &self,
+        req: &AirdropBatchRequest,
+    ) -> Result<AirdropBatchResponse, ClientError> {
+        self.post("/api/airdrop_batch", req).await
+    }
+
+    pub async fn send_single(
+        &self,
+        req: &SendSingleRequest,
+    ) -> Result<SendSingleResponse, ClientError> {
+        self.post("/api/send_single", req).await
+    }
+
+    pub async fn recent_block_hash(
+        &self,
+        req: &RecentBlockHashRequest,
+    ) -> Result<RecentBlockHashResponse, ClientError> {
+        self.post("/api/recent_block_hash", req).await
+    }
+
+    pub async fn aggregate_keys(
+        &self,
+        req: &AggregateKeysRequest,
+    ) -> Result<AggregateKeysResponse, ClientError> {
+        self.post("/api/aggregate_keys", req).await
+    }
+
+    pub async fn agg_send_step_one(
+        &self,
+        req: &AggSendStepOneRequest,
+    ) -> Result<AggSendStepOneResponse, ClientError> {
+        self.post("/api/agg_send_step_one", req).await
+    }
+
+    pub async fn build_message(
+        &self,
+        req: &BuildMessageRequest,
+    ) -> Result<BuildMessageResponse, ClientError> {
+        self.post("/api/build_message", req).await
+    }
+
+    pub async fn simulate(
+        &self,
+        req: &BuildMessageRequest,
+    ) -> Result<SimulationResponse, ClientError> {
+        self.post("/api/simulate", req).await
+    }
+
+    pub async fn agg_send_step_two(
+        &self,
+        req: &AggSendStepTwoRequest,
+    ) -> Result<AggSendStepTwoResponse, ClientError> {
+        self.post("/api/agg_send_step_two", req).await
+    }
+
+    pub async fn aggregate_signatures(
+        &self,
+        req: &AggregateSignaturesRequest,
+    ) -> Result<AggregateSignaturesResponse, ClientError> {
+        self.post("/api/aggregate_signatures", req).await
+    }
+
+    pub async fn broadcast(
+        &self,
+        req: &BroadcastRequest,
+    ) -> Result<BroadcastResponse, ClientError> {
+        self.post("/api/broadcast", req).await
+    }
+
+    pub async fn broadcast_status(
+        &self,
+        req: &BroadcastStatusRequest,
+    ) -> Result<BroadcastStatusResponse, ClientError> {
+        self.post("/api/broadcast_status", req).await
+    }
+
+    pub async fn transaction_statuses(
+        &self,
+        req: &TransactionStatusesRequest,
+    ) -> Result<TransactionStatusesResponse, ClientError> {
+        self.post("/api/transaction_statuses", req).await
+    }
+
+    pub async fn scheduled_broadcast_status(
+        &self,
+        req: &ScheduledBroadcastStatusRequest,
+    ) -> Result<ScheduledBroadcastStatusResponse, ClientError> {
+        self.post("/api/scheduled_broadcast_status", req).await
+    }
+
+    pub async fn cancel_scheduled_broadcast(
+        &self,
+        req: &CancelScheduledBroadcastRequest,
+    ) -> Result<CancelScheduledBroadcastResponse, ClientError> {
+        self.post("/api/cancel_scheduled_broadcast", req).await
+    }
+
+    pub async fn tokens(&self) -> Result<TokensResponse, ClientError> {
+        self.get("/api/tokens").await
+    }
+
+    pub async fn upsert_token(&self, req: &TokenEntry) -> Result<TokenEntry, ClientError> {
+        self.post("/api/tokens", req).await
+    }
+
+    pub async fn delete_token(
+        &self,
+        req: &DeleteTokenRequest,
+    ) -> Result<DeletedTokenEntry, ClientError> {
+        self.post("/api/delete_token", req).await
+    }
+
+    pub async fn restore_token(
+        &self,
+        req: &RestoreTokenRequest,
+    ) -> Result<TokenEntry, ClientError> {
+        self.post("/api/restore_token", req).await
+    }
+
+    pub async fn spl_token_balance(
+        &self,
+        req: &SplTokenBalanceRequest,
+    ) -> Result<SplTokenBalanceResponse, ClientError> {
+        self.post("/api/spl_token_balance", req).await
+    }
+
+    pub async fn spl_send_single(
+        &self,
+        req: &SplSendSingleRequest,
+    ) -> Result<SplSendSingleResponse, ClientError> {
+        self.post("/api/spl_send_single", req).await
+    }
+
+    pub async fn spl_rent_summary(
+        &self,
+        req: &SplRentSummaryRequest,
+    ) -> Result<SplRentSummaryResponse, ClientError> {
+        self.post("/api/spl_rent_summary", req).await
+    }
+
+    pub async fn spl_close_empty_accounts(
+        &self,
+        req: &SplCloseEmptyAccountsRequest,
+    ) -> Result<SplCloseEmptyAccountsResponse, ClientError> {
+        self.post("/api/spl_close_empty_accounts", req).await
+    }
+
+    pub async fn spl_build_message(
+        &self,
+        req: &SplBuildMessageRequest,
+    ) -> Result<BuildMessageResponse, ClientError> {
+        self.post("/api/spl_build_message", req).await
+    }
+
+    pub async fn spl_simulate(
+        &self,
+        req: &SplBuildMessageRequest,
+    ) -> Result<SimulationResponse, ClientError> {
+        self.post("/api/spl_simulate", req).await
+    }
+
+    pub async fn spl_cost_preview(
+        &self,
+        req: &SplCostPreviewRequest,
+    ) -> Result<SplCostPreviewResponse, ClientError> {
+        self.post("/api/spl_cost_preview", req).await
+    }
+
+    pub async fn estimate_fee(
+        &self,
+        req: &EstimateFeeRequest,
+    ) -> Result<EstimateFeeResponse, ClientError> {
+        self.post("/api/estimate_fee", req).await
+    }
+
+    pub async fn spl_agg_send_step_two(
+        &self,
+        req: &SplAggSendStepTwoRequest,
+    ) -> Result<SplAggSendStepTwoResponse, ClientError> {
+        self.post("/api/spl_agg_send_step_two", req).await
+    }
+
+    pub async fn spl_aggregate_signatures(
+        &self,
+        req: &SplAggregateSignaturesRequest,
+    ) -> Result<SplAggregateSignaturesResponse, ClientError> {
+        self.post("/api/spl_aggregate_signatures", req).await
+    }
+
+    pub async fn stake(
+        &self,
+        req: &StakeAccountRequest,
+    ) -> Result<StakeAccountResponse, ClientError> {
+        self.post("/api/stake", req).await
+    }
+
+    pub async fn deactivate_stake(
+        &self,
+        req: &DeactivateStakeRequest,
+    ) -> Result<DeactivateStakeResponse, ClientError> {
+        self.post("/api/deactivate_stake", req).await
+    }
+
+    pub async fn withdraw_stake(
+        &self,
+        req: &WithdrawStakeRequest,
+    ) -> Result<WithdrawStakeResponse, ClientError> {
+        self.post("/api/withdraw_stake", req).await
+    }
+
+    pub async fn threshold_keygen_step_one(
+        &self,
+        req: &ThresholdKeygenStepOneRequest,
+    ) -> Result<ThresholdKeygenStepOneResponse, ClientError> {
+        self.post("/api/threshold/keygen_step_one", req).await
+    }
+
+    pub async fn threshold_keygen_step_two(
+        &self,
+        req: &ThresholdKeygenStepTwoRequest,
+    ) -> Result<ThresholdKeygenStepTwoResponse, ClientError> {
+        self.post("/api/threshold/keygen_step_two", req).await
+    }
+
+    pub async fn threshold_keygen_step_three(
+        &self,
+        req: &ThresholdKeygenStepThreeRequest,
+    ) -> Result<ThresholdKeygenStepThreeResponse, ClientError> {
+        self.post("/api/threshold/keygen_step_three", req).await
+    }
+
+    pub async fn nonce_create(
+        &self,
+        req: &NonceCreateRequest,
+    ) -> Result<NonceCreateResponse, ClientError> {
+        self.post("/api/nonce/create", req).await
+    }
+
+    pub async fn nonce_info(
+        &self,
+        req: &NonceInfoRequest,
+    ) -> Result<NonceInfoResponse, ClientError> {
+        self.post("/api/nonce/info", req).await
+    }
+
+    pub async fn nonce_withdraw(
+        &self,
+        req: &NonceWithdrawRequest,
+    ) -> Result<NonceWithdrawResponse, ClientError> {
+        self.post("/api/nonce/withdraw", req).await
+    }
+
+    pub async fn agg_stake_step_two(
+        &self,
+        req: &AggStakeStepTwoRequest,
+    ) -> Result<AggStakeStepTwoResponse, ClientError> {
+        self.post("/api/agg_stake_step_two", req).await
+    }
+
+    pub async fn agg_deactivate_stake_step_two(
+        &self,
+        req: &AggDeactivateStakeStepTwoRequest,
+    ) -> Result<AggDeactivateStakeStepTwoResponse, ClientError> {
+        self.post("/api/agg_deactivate_stake_step_two", req).await
+    }
+
+    pub async fn agg_withdraw_stake_step_two(
+        &self,
+        req: &AggWithdrawStakeStepTwoRequest,
+    ) -> Result<AggWithdrawStakeStepTwoResponse, ClientError> {
+        self.post("/api/agg_withdraw_stake_step_two", req).await
+    }
+
+    pub async fn aggregate_stake_signatures(
+        &self,
+        req: &AggregateStakeSignaturesRequest,
+    ) -> Result<AggregateStakeSignaturesResponse, ClientError> {
+        self.post("/api/aggregate_stake_signatures", req).await
+    }
+
+    pub async fn aggregate_deactivate_stake_signatures(
+        &self,
+        req: &AggregateDeactivateStakeSignaturesRequest,
+    ) -> Result<AggregateDeactivateStakeSignaturesResponse, ClientError> {
+        self.post("/api/aggregate_deactivate_stake_signatures", req)
+            .await
+    }
+
+    pub async fn aggregate_withdraw_stake_signatures(
+        &self,
+        req: &AggregateWithdrawStakeSignaturesRequest,
+    ) -> Result<AggregateWithdrawStakeSignaturesResponse, ClientError> {
+        self.post("/api/aggregate_withdraw_stake_signatures", req)
+            .await
+    }
+}
+
+/// W3C trace context of the current span, so the server's spans join the caller's trace.
+#[cfg(feature = "otel")]
+fn trace_context_headers() -> std::collections::HashMap<String, String> {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let span = tracing::Span::current();
+    let cx = match span.is_none() {
+        true => opentelemetry::Context::current(),
+        false => span.context(),
+    };
+    let mut headers = std::collections::HashMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut headers)
+    });
+    headers
+}
+
+fn new_idempotency_key() -> String {
+    rand07::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `Retry-After` in seconds; the HTTP-date form isn't used by anything we talk to.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use poem::{
+        EndpointExt, Request, Response, Route, Server, handler,
+        http::StatusCode,
+        listener::{Acceptor, Listener, TcpListener},
+        post,
+        web::Data,
+    };
+
+    use crate::client::{Client, ClientError, IDEMPOTENCY_KEY_HEADER};
+    use crate::models::{BalanceRequest, Network};
+
+    #[derive(Default)]
+    struct Seen {
+        calls: AtomicUsize,
+        headers: Mutex<Vec<(Option<String>, Option<String>)>>,
+    }
+
+    // Rate limits the first two calls, then fails with an error code
+    #[handler]
+    fn flaky(req: &Request, seen: Data<&Arc<Seen>>) -> Response {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        seen.headers
+            .lock()
+            .unwrap()
+            .push((header("authorization"), header(IDEMPOTENCY_KEY_HEADER)));
+        if seen.calls.fetch_add(1, Ordering::SeqCst) < 2 {
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .finish();
+        }
+        Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .content_type("application/json")
+            .body(r#"{"error":"no such account","error_code":"ACCOUNT_NOT_FOUND"}"#)
+    }
+
+    #[tokio::test]
+    async fn test_retries_and_typed_errors() {
+        let seen = Arc::new(Seen::default());
+        let acceptor = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = acceptor.local_addr()[0].as_socket_addr().cloned().unwrap();
+        let app = Route::new()
+            .at("/api/balance", post(flaky))
+            .data(seen.clone());
+        tokio::spawn(Server::new_with_acceptor(acceptor).run(app));
+
+        let client = Client::new(format!("http://{}/", addr))
+            .api_key(Some("secret".to_string()))
+            .retry_delay(Duration::from_millis(1));
+        let req = BalanceRequest {
+            address: "11111111111111111111111111111111".to_string(),
+            net: Some(Network::Devnet),
+            fresh: false,
+        };
+        match client.balance(&req).await {
+            Err(ClientError::Api {
+                status: 400,
+                error,
+                error_code: Some(code),
+                retryable: false,
+            }) => {
+                assert_eq!(error, "no such account");
+                assert_eq!(code, "ACCOUNT_NOT_FOUND");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let headers = seen.headers.lock().unwrap().clone();
+        assert_eq!(headers.len(), 3);
+        assert!(
+            headers
+                .iter()
+                .all(|(auth, key)| auth.as_deref() == Some("Bearer secret") && key.is_some())
+        );
+        // Retries of one call reuse its key, the next call gets a new one
+        assert!(headers.iter().all(|(_, key)| key == &headers[0].1));
+        drop(client.balance(&req).await);
+        assert_ne!(seen.headers.lock().unwrap()[3].1, headers[0].1);
+    }
+}
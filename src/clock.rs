@@ -0,0 +1,135 @@
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
+use solana_sdk::{clock::UnixTimestamp, commitment_config::CommitmentConfig};
+
+use crate::models::Network;
+
+// Scheduled broadcasts, audit entries and ceremony durations all trust the system clock. The
+// cluster keeps its own: every block carries the stake-weighted timestamp of its validators.
+// The block time of the latest finalized slot, moved forward by the slots produced since, is
+// what the cluster thinks the time is now. Block times have whole second precision and slots
+// don't all take exactly 400ms, so a skew of a second or two is noise.
+
+/// Target duration of a slot.
+pub const SLOT_MS: i64 = 400;
+
+/// The `clock_check` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockCheckConfig {
+    /// Cluster whose clock the system clock is compared with
+    pub net: Network,
+    /// Skew above which a warning is logged and `/api/health` reports degraded
+    pub threshold_ms: u64,
+    pub interval_secs: u64,
+}
+
+impl Default for ClockCheckConfig {
+    fn default() -> Self {
+        Self {
+            net: Network::Mainnet,
+            threshold_ms: 5_000,
+            interval_secs: 300,
+        }
+    }
+}
+
+/// Unix time of the system clock in milliseconds.
+pub fn system_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// How far the system clock at `system_ms` is ahead of the cluster's, negative when it is
+/// behind. `block_time` is the timestamp of `block_slot`, `current_slot` the slot the cluster
+/// is at now.
+pub fn estimate_skew_ms(
+    system_ms: i64,
+    block_time: UnixTimestamp,
+    block_slot: u64,
+    current_slot: u64,
+) -> i64 {
+    let elapsed = current_slot.saturating_sub(block_slot) as i64 * SLOT_MS;
+    system_ms - (block_time * 1_000 + elapsed)
+}
+
+/// Measure the skew of the system clock from the cluster `rpc_client` talks to.
+pub async fn measure_skew(rpc_client: &RpcClient) -> Result<i64, ClientError> {
+    let block_slot = rpc_client
+        .get_slot_with_commitment(CommitmentConfig::finalized())
+        .await?;
+    let block_time = rpc_client.get_block_time(block_slot).await?;
+    let current_slot = rpc_client
+        .get_slot_with_commitment(CommitmentConfig::processed())
+        .await?;
+    Ok(estimate_skew_ms(
+        system_ms(),
+        block_time,
+        block_slot,
+        current_slot,
+    ))
+}
+
+/// A measured skew and when it was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkewReading {
+    pub skew_ms: i64,
+    /// Unix timestamp in milliseconds
+    pub at: i64,
+}
+
+/// The latest skew measured by the periodic check.
+#[derive(Debug, Default)]
+pub struct ClockSkew {
+    latest: Mutex<Option<SkewReading>>,
+}
+
+impl ClockSkew {
+    pub fn record(&self, reading: SkewReading) {
+        *self.latest.lock().unwrap() = Some(reading);
+    }
+
+    pub fn latest(&self) -> Option<SkewReading> {
+        *self.latest.lock().unwrap()
+    }
+}
+
+/// Whether `skew_ms` is above the threshold of `config`.
+pub fn exceeds(config: &ClockCheckConfig, skew_ms: i64) -> bool {
+    skew_ms.unsigned_abs() > config.threshold_ms
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{ClockCheckConfig, SLOT_MS, estimate_skew_ms, exceeds};
+
+    #[test]
+    fn test_skew_estimate() {
+        let block_time = 1_700_000_000;
+        let block_ms = block_time * 1_000;
+
+        // 32 slots since the finalized block, the cluster is 12.8s past its timestamp
+        let now = block_ms + 32 * SLOT_MS;
+        assert_eq!(estimate_skew_ms(now, block_time, 1_000, 1_032), 0);
+        assert_eq!(estimate_skew_ms(now + 750, block_time, 1_000, 1_032), 750);
+        assert_eq!(
+            estimate_skew_ms(now - 9_000, block_time, 1_000, 1_032),
+            -9_000
+        );
+
+        // A node that reports an older processed slot than the finalized one adds no time
+        assert_eq!(estimate_skew_ms(block_ms, block_time, 1_000, 990), 0);
+
+        let config = ClockCheckConfig::default();
+        assert!(!exceeds(&config, 5_000));
+        assert!(exceeds(&config, 5_001));
+        assert!(exceeds(&config, -60_000));
+    }
+}
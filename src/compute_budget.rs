@@ -0,0 +1,207 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
+use solana_sdk::{compute_budget, message::Message, transaction::Transaction};
+
+use crate::{Error, rpc_methods::unsupported_method};
+
+/// Highest limit the runtime accepts for a single transaction.
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Auto {
+    Auto,
+}
+
+/// `compute_unit_limit` as sent by clients: either a number or `"auto"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ComputeUnitLimit {
+    Units(u32),
+    Auto(Auto),
+}
+
+/// How the limit of a transaction was chosen, `simulated` is only set for `"auto"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ComputeUnitReport {
+    pub simulated: Option<u64>,
+    pub limit: u32,
+}
+
+/// Units the runtime allows each instruction when a transaction sets no limit.
+pub const DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+
+const MICRO_LAMPORTS_PER_LAMPORT: u128 = 1_000_000;
+
+/// Lamports `message` pays on top of its signature fees for the compute unit price it sets:
+/// the price times the limit, rounded up. Without a limit instruction the limit is the
+/// runtime's default for the other instructions.
+pub fn priority_fee(message: &Message) -> u64 {
+    let mut limit = None;
+    let mut price = 0;
+    let mut instructions = 0u32;
+    for ix in &message.instructions {
+        if message.account_keys[ix.program_id_index as usize] != compute_budget::id() {
+            instructions += 1;
+            continue;
+        }
+        match ix.data.split_first() {
+            Some((&2, units)) => limit = units.try_into().ok().map(u32::from_le_bytes),
+            Some((&3, micro_lamports)) => {
+                price = micro_lamports.try_into().map_or(0, u64::from_le_bytes)
+            }
+            _ => {}
+        }
+    }
+    let limit = limit
+        .unwrap_or(instructions.saturating_mul(DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT))
+        .min(MAX_COMPUTE_UNIT_LIMIT);
+    let micro_lamports = u128::from(price) * u128::from(limit);
+    micro_lamports.div_ceil(MICRO_LAMPORTS_PER_LAMPORT) as u64
+}
+
+/// Add `margin_percent` on top of the simulated units, rounding up.
+pub fn with_margin(units: u64, margin_percent: u32) -> u32 {
+    let units = units.saturating_add((units * margin_percent as u64).div_ceil(100));
+    units.min(MAX_COMPUTE_UNIT_LIMIT as u64) as u32
+}
+
+/// Simulate the unsigned `message` and return the compute units it consumed.
+#[tracing::instrument(name = "rpc.simulate_transaction", skip_all)]
+pub async fn simulate_units(rpc_client: &RpcClient, message: Message) -> Result<u64, Error> {
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let result = rpc_client
+        .simulate_transaction_with_config(&Transaction::new_unsigned(message), config)
+        .await
+        .map_err(|e| match unsupported_method(&e) {
+            Some(method) => Error::RpcMethodUnsupported(method),
+            None => Error::SimulationFailed(e.to_string()),
+        })?
+        .value;
+    if let Some(err) = result.err {
+        let logs = result.logs.unwrap_or_default().join("\n");
+        return Err(Error::SimulationFailed(format!("{}\n{}", err, logs)));
+    }
+    result
+        .units_consumed
+        .ok_or_else(|| Error::SimulationFailed("the RPC node didn't report units consumed".into()))
+}
+
+/// Turn the requested limit into the number that goes into the message. `build` constructs the
+/// message for a given limit, for `"auto"` it is simulated once with the maximum limit.
+pub async fn resolve_compute_unit_limit(
+    requested: Option<ComputeUnitLimit>,
+    rpc_client: Option<&RpcClient>,
+    margin_percent: u32,
+    build: impl FnOnce(Option<u32>) -> Result<Message, Error>,
+) -> Result<Option<ComputeUnitReport>, Error> {
+    match requested {
+        None => Ok(None),
+        Some(ComputeUnitLimit::Units(limit)) if limit > MAX_COMPUTE_UNIT_LIMIT => Err(
+            Error::InvalidComputeUnitLimit(format!("at most {} units", MAX_COMPUTE_UNIT_LIMIT)),
+        ),
+        Some(ComputeUnitLimit::Units(limit)) => Ok(Some(ComputeUnitReport {
+            simulated: None,
+            limit,
+        })),
+        Some(ComputeUnitLimit::Auto(_)) => {
+            let rpc_client = rpc_client.ok_or_else(|| {
+                Error::InvalidComputeUnitLimit("\"auto\" requires `net` to be set".into())
+            })?;
+            let units = simulate_units(rpc_client, build(Some(MAX_COMPUTE_UNIT_LIMIT))?).await?;
+            Ok(Some(ComputeUnitReport {
+                simulated: Some(units),
+                limit: with_margin(units, margin_percent),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::{
+        compute_budget::{
+            ComputeUnitLimit, ComputeUnitReport, MAX_COMPUTE_UNIT_LIMIT, priority_fee,
+            resolve_compute_unit_limit, with_margin,
+        },
+        transaction_builder::TransferBuilder,
+        units::Lamports,
+    };
+
+    #[test]
+    fn test_parse_limit() {
+        let auto: ComputeUnitLimit = serde_json::from_str("\"auto\"").unwrap();
+        assert!(matches!(auto, ComputeUnitLimit::Auto(_)));
+        let units: ComputeUnitLimit = serde_json::from_str("200000").unwrap();
+        assert_eq!(units, ComputeUnitLimit::Units(200_000));
+        assert!(serde_json::from_str::<ComputeUnitLimit>("\"max\"").is_err());
+        assert!(serde_json::from_str::<ComputeUnitLimit>("-1").is_err());
+    }
+
+    #[test]
+    fn test_priority_fee() {
+        let from = Pubkey::new_unique();
+        let transfer = TransferBuilder::new(from, Pubkey::new_unique(), Lamports::new(1));
+        assert_eq!(priority_fee(&transfer.build()), 0);
+        // 1.5 lamports for 100k units at 15 micro-lamports, rounded up
+        let priced = transfer.clone().compute_unit_limit(Some(100_000));
+        assert_eq!(
+            priority_fee(&priced.compute_unit_price(Some(15)).build()),
+            2
+        );
+        // One transfer instruction runs on the default limit
+        let unlimited = transfer.compute_unit_price(Some(1_000_000));
+        assert_eq!(priority_fee(&unlimited.build()), 200_000);
+    }
+
+    #[test]
+    fn test_margin() {
+        assert_eq!(with_margin(1000, 0), 1000);
+        assert_eq!(with_margin(1000, 10), 1100);
+        assert_eq!(with_margin(1001, 10), 1102);
+        assert_eq!(with_margin(1_300_000, 50), MAX_COMPUTE_UNIT_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_without_simulation() {
+        let no_build = |_| unreachable!("fixed limits are never simulated");
+        assert_eq!(
+            resolve_compute_unit_limit(None, None, 10, no_build)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            resolve_compute_unit_limit(Some(ComputeUnitLimit::Units(5000)), None, 10, no_build)
+                .await
+                .unwrap(),
+            Some(ComputeUnitReport {
+                simulated: None,
+                limit: 5000
+            })
+        );
+        assert!(
+            resolve_compute_unit_limit(
+                Some(ComputeUnitLimit::Units(MAX_COMPUTE_UNIT_LIMIT + 1)),
+                None,
+                10,
+                no_build
+            )
+            .await
+            .is_err()
+        );
+        let auto: ComputeUnitLimit = serde_json::from_str("\"auto\"").unwrap();
+        assert!(
+            resolve_compute_unit_limit(Some(auto), None, 10, no_build)
+                .await
+                .is_err()
+        );
+    }
+}
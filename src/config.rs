@@ -0,0 +1,411 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use clap::Parser;
+use poem::http::Uri;
+use serde::Deserialize;
+
+use crate::{
+    access_log::LogFormat,
+    account_batch::AccountBatchConfig,
+    bounded_store::StateLimits,
+    case::FieldCase,
+    clock::ClockCheckConfig,
+    confirmation::{ConfirmationOverride, ConfirmationPolicy},
+    confirmation_stream::ConfirmationStreamConfig,
+    faucet::FaucetConfig,
+    models::{CapabilityLimits, Network},
+    policy::WritablePolicy,
+    rate_limit::RateLimitConfig,
+    spend_limit::SpendLimitConfig,
+    tenant::{ApiKeyConfig, key_hash, read_key_file},
+    token_registry::{DELETED_TOKEN_RETENTION_SECS, TokenConfig},
+};
+
+/// Command line flags, everything else lives in the optional JSON config file.
+#[derive(Debug, Parser)]
+#[command(version, about = "Solana TSS wallet API")]
+pub struct Args {
+    /// Path to a JSON config file
+    #[arg(long, env = "SOLANA_TSS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Start the listener without validating the configuration first
+    #[arg(long)]
+    pub skip_startup_checks: bool,
+
+    /// Accept any pubkey as a request's `memo_program`, not just the known memo programs
+    #[arg(long)]
+    pub allow_custom_memo_program: bool,
+
+    /// Add a `timings` breakdown to every response, for diagnosing slow requests
+    #[arg(long)]
+    pub debug_timing: bool,
+
+    /// Don't serve the ceremony UI at `/ui`
+    #[arg(long)]
+    pub no_ui: bool,
+
+    /// Don't rate limit clients, for local development
+    #[arg(long)]
+    pub no_rate_limit: bool,
+
+    /// Keep every audited message in full, not only its hash
+    #[arg(long)]
+    pub audit_full_messages: bool,
+
+    /// RPC URL of the local test validator, `http://127.0.0.1:8899` by default. Takes
+    /// precedence over `rpc_urls.localnet`
+    #[arg(long, env = "SOLANA_TSS_LOCALNET_URL")]
+    pub localnet_url: Option<String>,
+
+    /// API keys `/api/*` requests must send, as `Authorization: Bearer` or `X-Api-Key`. Any
+    /// key given here or in `--api-key-file` turns authentication on
+    #[arg(long = "api-key", env = "SOLANA_TSS_API_KEYS", value_delimiter = ',')]
+    pub api_keys: Vec<String>,
+
+    /// File with more API keys, one per line, read again by a config reload
+    #[arg(long, env = "SOLANA_TSS_API_KEY_FILE")]
+    pub api_key_file: Option<PathBuf>,
+
+    /// PEM certificate chain to serve HTTPS with, together with `--tls-key`. The plain HTTP
+    /// listener on `bind` is off then
+    #[arg(long, env = "SOLANA_TSS_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of `--tls-cert`
+    #[arg(long, env = "SOLANA_TSS_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Address of the HTTPS listener
+    #[arg(long, env = "SOLANA_TSS_TLS_BIND", default_value = "0.0.0.0:8443")]
+    pub tls_bind: String,
+
+    /// Keep serving plain HTTP on `bind` next to HTTPS, `bind` must be a loopback address
+    #[arg(long)]
+    pub plain_on_localhost: bool,
+
+    /// How logs are written to stderr, their levels come from `RUST_LOG` (`info` by default)
+    #[arg(long, env = "SOLANA_TSS_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    /// Per-network RPC URL overrides, networks not listed use the public endpoints
+    pub rpc_urls: HashMap<Network, String>,
+    /// Ping every RPC URL during the startup checks
+    pub startup_ping: bool,
+    pub startup_ping_timeout_ms: u64,
+    /// Requests with a larger body are rejected with 413
+    pub max_body_size: usize,
+    /// Safety margin added to simulated units for `compute_unit_limit: "auto"`
+    pub compute_unit_margin_percent: u32,
+    /// Devnet fallback for `/api/airdrop` when the public faucet is rate limited
+    pub faucet: Option<FaucetConfig>,
+    /// Most parties a TSS request may name, bounds `keys`, `first_messages` and `signatures`
+    pub max_parties: usize,
+    /// File journalling unconfirmed broadcasts, settled in the background after a restart
+    pub broadcast_journal: Option<PathBuf>,
+    /// Accept any pubkey as `memo_program`, also set by `--allow-custom-memo-program`
+    pub allow_custom_memo_program: bool,
+    /// Rolling 24 hour caps on SOL and token transfers, globally and per API key
+    pub spend_limits: Option<SpendLimitConfig>,
+    /// Tokens SPL requests can name by symbol, keyed by the symbol
+    pub tokens: HashMap<String, TokenConfig>,
+    /// How long a token deleted through `/api/delete_token` can be restored
+    pub deleted_token_retention_secs: u64,
+    /// API keys by tenant, requests with other keys act for the default tenant
+    pub api_keys: Vec<ApiKeyConfig>,
+    /// Refuse `/api/*` and `/ws/*` requests other than `/api/health` without a known key, one of
+    /// `api_keys` or of the `access_keys`. Turned on by any `--api-key` or `--api-key-file`
+    pub require_api_key: bool,
+    /// Hashes of the keys of `--api-key`, `SOLANA_TSS_API_KEYS` and `--api-key-file`, acting
+    /// for the default tenant. Never read from the file
+    #[serde(skip)]
+    pub access_keys: Vec<[u8; 32]>,
+    /// Add `timings` to every response, also set by `--debug-timing`. Without it admin keys
+    /// still get them by sending `X-Debug-Timing: 1`
+    pub debug_timing: bool,
+    /// Serve the ceremony UI at `/ui`, turned off by `--no-ui`
+    pub ui: bool,
+    /// How broadcasting handlers wait for their transactions, requests can override any field
+    pub confirmation: ConfirmationPolicy,
+    /// How `/ws/confirmations` polls the signatures it follows and when it gives up on them
+    pub confirmation_stream: ConfirmationStreamConfig,
+    /// Window and size of the batches concurrent account reads are coalesced into, and how long
+    /// balance reads remember missing accounts
+    pub account_batching: AccountBatchConfig,
+    /// Capacity and TTL of the in-memory stores, see `/api/state_stats`
+    pub state_limits: StateLimits,
+    /// Keep the compiled messages in the audit log for `/api/audit/{id}/message`, also set by
+    /// `--audit-full-messages`. Only their hashes are kept otherwise
+    pub audit_full_messages: bool,
+    /// Casing of response field names, `snake` by default. Requests can pick their own with
+    /// `X-Response-Case`
+    pub response_case: FieldCase,
+    /// Compare the system clock with a cluster's at startup and periodically. Off unless set,
+    /// it reaches out to `clock_check.net`
+    pub clock_check: Option<ClockCheckConfig>,
+    /// Label such as `staging` or `prod`, set as `environment` in every JSON response and
+    /// embedded into the signing blobs. Blobs of another environment are refused
+    pub environment: Option<String>,
+    /// Refuse requests sending an `amount` as a JSON float, amounts must be decimal strings
+    /// or integers
+    pub strict_amounts: bool,
+    /// Count which optional fields requests send and in what JSON type, for
+    /// `/api/admin/usage_stats` and `/metrics`. Off by default
+    pub usage_stats: bool,
+    /// Network of requests that leave out `net`. Without it `net` is required
+    pub default_network: Option<Network>,
+    /// Accounts the messages signed and sent here may or may not write to, checked by step two
+    /// and the aggregations
+    pub writable_policy: WritablePolicy,
+    /// Let requests name the RPC node they are served through in `rpc_url`
+    pub allow_request_rpc_url: bool,
+    /// Hosts a request's `rpc_url` may name, with `allow_request_rpc_url`. Empty allows none
+    pub request_rpc_url_hosts: Vec<String>,
+    /// Keep the secret state of step one on the server, step one then answers with a
+    /// `secret_state_id` for step two instead of the `secret_state` itself
+    pub server_side_secret_state: bool,
+    /// Requests each client IP may send, with lower limits for endpoints calling the RPC node.
+    /// `null` or `--no-rate-limit` turns it off
+    pub rate_limits: Option<RateLimitConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8000".to_string(),
+            rpc_urls: HashMap::new(),
+            startup_ping: true,
+            startup_ping_timeout_ms: 3000,
+            max_body_size: 1024 * 1024,
+            compute_unit_margin_percent: 10,
+            faucet: None,
+            max_parties: 16,
+            broadcast_journal: None,
+            allow_custom_memo_program: false,
+            spend_limits: None,
+            tokens: HashMap::new(),
+            deleted_token_retention_secs: DELETED_TOKEN_RETENTION_SECS,
+            api_keys: Vec::new(),
+            require_api_key: false,
+            access_keys: Vec::new(),
+            debug_timing: false,
+            ui: true,
+            confirmation: ConfirmationPolicy::default(),
+            confirmation_stream: ConfirmationStreamConfig::default(),
+            account_batching: AccountBatchConfig::default(),
+            state_limits: StateLimits::default(),
+            audit_full_messages: false,
+            response_case: FieldCase::default(),
+            clock_check: None,
+            environment: None,
+            strict_amounts: false,
+            usage_stats: false,
+            default_network: None,
+            writable_policy: WritablePolicy::default(),
+            allow_request_rpc_url: false,
+            request_rpc_url_hosts: Vec::new(),
+            server_side_secret_state: false,
+            rate_limits: Some(RateLimitConfig::default()),
+        }
+    }
+}
+
+/// Where the running config came from, so a reload reads it the same way: the file, then the
+/// command line flags on top.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    pub allow_custom_memo_program: bool,
+    pub debug_timing: bool,
+    pub no_ui: bool,
+    pub no_rate_limit: bool,
+    pub audit_full_messages: bool,
+    pub localnet_url: Option<String>,
+    /// Hashes of the keys given on the command line or in the environment
+    pub access_keys: Vec<[u8; 32]>,
+    pub api_key_file: Option<PathBuf>,
+}
+
+impl ConfigSource {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            path: args.config.clone(),
+            allow_custom_memo_program: args.allow_custom_memo_program,
+            debug_timing: args.debug_timing,
+            no_ui: args.no_ui,
+            no_rate_limit: args.no_rate_limit,
+            audit_full_messages: args.audit_full_messages,
+            localnet_url: args.localnet_url.clone(),
+            access_keys: args
+                .api_keys
+                .iter()
+                .map(|key| key_hash(key.trim()))
+                .collect(),
+            api_key_file: args.api_key_file.clone(),
+        }
+    }
+
+    pub fn load(&self) -> Result<Config, String> {
+        let mut config = Config::load(self.path.as_ref())?;
+        config.allow_custom_memo_program |= self.allow_custom_memo_program;
+        config.debug_timing |= self.debug_timing;
+        config.ui &= !self.no_ui;
+        if self.no_rate_limit {
+            config.rate_limits = None;
+        }
+        config.audit_full_messages |= self.audit_full_messages;
+        if let Some(url) = &self.localnet_url {
+            config.rpc_urls.insert(Network::Localnet, url.clone());
+        }
+        config.access_keys = self.access_keys.clone();
+        if let Some(path) = &self.api_key_file {
+            config.access_keys.extend(read_key_file(path)?);
+        }
+        // An emptied key file fails the checks rather than turning authentication off
+        config.require_api_key |= !self.access_keys.is_empty() || self.api_key_file.is_some();
+        Ok(config)
+    }
+}
+
+impl Config {
+    pub fn load(path: Option<&PathBuf>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed reading {}: {}", path.display(), e))?;
+        serde_json::from_str(&raw).map_err(|e| format!("failed parsing {}: {}", path.display(), e))
+    }
+
+    /// Optional features enabled by this config, as advertised by `/api/capabilities`.
+    pub fn enabled_features(&self) -> Vec<String> {
+        let mut features = Vec::new();
+        if self.faucet.is_some() {
+            features.push("internal_faucet".to_string());
+        }
+        if self.spend_limits.is_some() {
+            features.push("spend_limits".to_string());
+        }
+        if self.ui {
+            features.push("ui".to_string());
+        }
+        if self.strict_amounts {
+            features.push("strict_amounts".to_string());
+        }
+        if self.allow_request_rpc_url {
+            features.push("request_rpc_url".to_string());
+        }
+        if self.server_side_secret_state {
+            features.push("server_side_secret_state".to_string());
+        }
+        if self.require_api_key {
+            features.push("api_key_auth".to_string());
+        }
+        if self.rate_limits.is_some() {
+            features.push("rate_limits".to_string());
+        }
+        if cfg!(feature = "chaos") {
+            features.push("chaos".to_string());
+        }
+        features
+    }
+
+    /// Effective request limits, as advertised by `/api/capabilities` and `/api/version`.
+    pub fn limits(&self) -> CapabilityLimits {
+        let mut amount_formats = vec!["decimal_string".to_string(), "integer".to_string()];
+        if !self.strict_amounts {
+            amount_formats.push("float".to_string());
+        }
+        CapabilityLimits {
+            max_body_size: self.max_body_size,
+            max_parties: self.max_parties,
+            amount_formats,
+        }
+    }
+
+    /// Sections of `new` that differ from this config but only take effect after a restart,
+    /// they are set up once when the server starts.
+    pub fn restart_required(&self, new: &Config) -> Vec<&'static str> {
+        [
+            ("bind", self.bind != new.bind),
+            ("max_body_size", self.max_body_size != new.max_body_size),
+            ("ui", self.ui != new.ui),
+            ("faucet", self.faucet != new.faucet),
+            (
+                "broadcast_journal",
+                self.broadcast_journal != new.broadcast_journal,
+            ),
+            (
+                "spend_limits",
+                self.spend_limits.is_some() != new.spend_limits.is_some(),
+            ),
+            (
+                "account_batching",
+                self.account_batching != new.account_batching,
+            ),
+            ("state_limits", self.state_limits != new.state_limits),
+            (
+                "audit_full_messages",
+                self.audit_full_messages != new.audit_full_messages,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(section, changed)| changed.then_some(section))
+        .collect()
+    }
+
+    /// `new` with the sections that need a restart kept as they are here.
+    pub fn reloaded(&self, new: Config) -> Config {
+        Config {
+            bind: self.bind.clone(),
+            max_body_size: self.max_body_size,
+            ui: self.ui,
+            faucet: self.faucet.clone(),
+            broadcast_journal: self.broadcast_journal.clone(),
+            account_batching: self.account_batching,
+            state_limits: self.state_limits,
+            audit_full_messages: self.audit_full_messages,
+            spend_limits: match (&self.spend_limits, new.spend_limits) {
+                (Some(_), Some(limits)) => Some(limits),
+                (current, _) => current.clone(),
+            },
+            ..new
+        }
+    }
+
+    pub fn cluster_url(&self, net: Network) -> &str {
+        self.rpc_urls
+            .get(&net)
+            .map(String::as_str)
+            .unwrap_or_else(|| net.get_cluster_url())
+    }
+
+    /// The confirmation policy of a transaction on `net`, with what its request overrides.
+    pub fn confirmation_on(
+        &self,
+        net: Network,
+        request: Option<&ConfirmationOverride>,
+    ) -> ConfirmationPolicy {
+        self.confirmation.on(net).with(request)
+    }
+}
+
+/// Check that `url` is an absolute http(s) URL the RPC client can talk to.
+pub fn validate_rpc_url(url: &str) -> Result<(), String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| format!("invalid RPC URL {:?}: {}", url, e))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") => {}
+        _ => return Err(format!("RPC URL {:?} must use http or https", url)),
+    }
+    if uri.host().is_none() {
+        return Err(format!("RPC URL {:?} has no host", url));
+    }
+    Ok(())
+}
@@ -0,0 +1,621 @@
+use std::{num::NonZeroU64, time::Duration};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_client::{
+    client_error::ClientError, nonblocking::rpc_client::RpcClient,
+    rpc_config::RpcSendTransactionConfig,
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, hash::Hash, message::Message, pubkey::Pubkey,
+    signature::Signature, transaction::Transaction,
+};
+use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
+use tokio::time::Instant;
+
+use crate::{
+    audit::Outcome,
+    durable_nonce::{advanced_nonce_account, nonce_holds},
+    models::Network,
+    rpc_methods::unsupported_method,
+    signature_status::fetch_statuses,
+};
+
+// Every broadcasting handler waits for its transaction the same way: poll its status until it
+// reaches the policy's commitment, stop once its blockhash expired without the cluster having
+// it, and give up after the policy's timeout. A status below the commitment ends nothing, the
+// fork it was seen on can still be dropped and the signature turn up as not found again.
+// Expiry is judged by the newest bank, the one a transaction gets into. A transaction found
+// right as its blockhash expires landed in time and is waited for like any other. A transaction
+// signed against a durable nonce doesn't expire with a blockhash, only once its nonce account
+// holds another nonce.
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl From<TransactionConfirmationStatus> for Commitment {
+    fn from(status: TransactionConfirmationStatus) -> Self {
+        match status {
+            TransactionConfirmationStatus::Processed => Self::Processed,
+            TransactionConfirmationStatus::Confirmed => Self::Confirmed,
+            TransactionConfirmationStatus::Finalized => Self::Finalized,
+        }
+    }
+}
+
+impl Commitment {
+    pub fn config(self) -> CommitmentConfig {
+        match self {
+            Self::Processed => CommitmentConfig::processed(),
+            Self::Confirmed => CommitmentConfig::confirmed(),
+            Self::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+/// How a sent transaction is waited for, the `confirmation` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfirmationPolicy {
+    pub commitment: Commitment,
+    /// Stop waiting after this long, the transaction may still land afterwards
+    pub timeout_ms: u64,
+    pub poll_interval_ms: u64,
+    /// Send the transaction again on every poll that doesn't find it
+    pub rebroadcast: bool,
+    /// `commitment` on localnet, where waiting for more than `processed` gains nothing
+    pub localnet_commitment: Commitment,
+    /// The longest `timeout_ms` a request's `confirmation` gets, longer ones are cut to it
+    pub max_timeout_ms: u64,
+    /// The longest `poll_interval_ms` a request's `confirmation` gets
+    pub max_poll_interval_ms: u64,
+}
+
+impl Default for ConfirmationPolicy {
+    fn default() -> Self {
+        Self {
+            commitment: Commitment::Finalized,
+            timeout_ms: 120_000,
+            poll_interval_ms: 500,
+            rebroadcast: false,
+            localnet_commitment: Commitment::Processed,
+            max_timeout_ms: 600_000,
+            max_poll_interval_ms: 10_000,
+        }
+    }
+}
+
+/// A request's `confirmation`, fields it leaves out keep the configured policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfirmationOverride {
+    pub commitment: Option<Commitment>,
+    #[serde(alias = "timeoutMs")]
+    pub timeout_ms: Option<u64>,
+    #[serde(alias = "pollIntervalMs")]
+    pub poll_interval_ms: Option<NonZeroU64>,
+    pub rebroadcast: Option<bool>,
+}
+
+impl ConfirmationPolicy {
+    /// The policy for transactions on `net`.
+    pub fn on(self, net: Network) -> Self {
+        match net.is_local() {
+            true => Self {
+                commitment: self.localnet_commitment,
+                ..self
+            },
+            false => self,
+        }
+    }
+
+    /// The policy with what `request` overrides, its timeout and poll interval capped at the
+    /// configured maxima.
+    pub fn with(self, request: Option<&ConfirmationOverride>) -> Self {
+        let Some(request) = request else {
+            return self;
+        };
+        Self {
+            commitment: request.commitment.unwrap_or(self.commitment),
+            timeout_ms: request
+                .timeout_ms
+                .map_or(self.timeout_ms, |ms| ms.min(self.max_timeout_ms)),
+            poll_interval_ms: request
+                .poll_interval_ms
+                .map_or(self.poll_interval_ms, |ms| {
+                    ms.get().min(self.max_poll_interval_ms)
+                }),
+            rebroadcast: request.rebroadcast.unwrap_or(self.rebroadcast),
+            ..self
+        }
+    }
+
+    /// When to stop waiting, `None` for a timeout too far out to be one.
+    fn deadline(&self) -> Option<Instant> {
+        Instant::now().checked_add(Duration::from_millis(self.timeout_ms))
+    }
+}
+
+/// How waiting for a transaction ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ConfirmationOutcome {
+    /// Reached the policy's commitment without an error, `commitment` is the one its status
+    /// had by then
+    Confirmed { slot: u64, commitment: Commitment },
+    /// Reached the policy's commitment with an on-chain error
+    Failed { slot: u64, error: String },
+    /// Its blockhash expired, or its durable nonce was advanced, before it landed, it never will
+    Expired,
+    /// Still pending when the policy's timeout ran out
+    TimedOut,
+}
+
+impl ConfirmationOutcome {
+    /// What the audit log records, `None` while the transaction can still land.
+    pub fn audit_outcome(&self) -> Option<(Outcome, Option<String>)> {
+        match self {
+            Self::Confirmed { .. } => Some((Outcome::Confirmed, None)),
+            Self::Failed { error, .. } => Some((Outcome::Failed, Some(error.clone()))),
+            Self::Expired => Some((
+                Outcome::Expired,
+                Some("blockhash expired before the transaction landed".to_string()),
+            )),
+            Self::TimedOut => None,
+        }
+    }
+}
+
+async fn status(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+) -> Result<Option<TransactionStatus>, ClientError> {
+    Ok(rpc_client
+        .get_signature_statuses(&[*signature])
+        .await?
+        .value
+        .pop()
+        .flatten())
+}
+
+fn settled(
+    status: &TransactionStatus,
+    commitment: CommitmentConfig,
+) -> Option<ConfirmationOutcome> {
+    if !status.satisfies_commitment(commitment) {
+        return None;
+    }
+    Some(match &status.status {
+        Ok(()) => ConfirmationOutcome::Confirmed {
+            slot: status.slot,
+            commitment: status.confirmation_status().into(),
+        },
+        Err(e) => ConfirmationOutcome::Failed {
+            slot: status.slot,
+            error: e.to_string(),
+        },
+    })
+}
+
+/// What decides how long a transaction can still land.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifetime {
+    /// Until its recent blockhash expires
+    Blockhash(Hash),
+    /// Until `account` holds another nonce than the one it was signed with
+    DurableNonce { account: Pubkey, nonce: Hash },
+}
+
+impl Lifetime {
+    /// The lifetime of `message`, a durable nonce when it advances one first.
+    pub fn of(message: &Message) -> Self {
+        match advanced_nonce_account(message) {
+            Some(account) => Self::DurableNonce {
+                account,
+                nonce: message.recent_blockhash,
+            },
+            None => Self::Blockhash(message.recent_blockhash),
+        }
+    }
+
+    /// The blockhash or nonce the transaction was signed with.
+    pub fn recent_blockhash(&self) -> &Hash {
+        match self {
+            Self::Blockhash(hash) | Self::DurableNonce { nonce: hash, .. } => hash,
+        }
+    }
+
+    /// Whether a transaction of this lifetime can still land, as the bank at `commitment` sees
+    /// it.
+    pub async fn valid_at(
+        &self,
+        rpc_client: &RpcClient,
+        commitment: CommitmentConfig,
+    ) -> Result<bool, ClientError> {
+        match self {
+            Self::Blockhash(hash) => rpc_client.is_blockhash_valid(hash, commitment).await,
+            Self::DurableNonce { account, nonce } => {
+                nonce_holds(rpc_client, account, nonce, commitment).await
+            }
+        }
+    }
+}
+
+/// Whether a transaction of `lifetime` can still land. Asked of the processed bank: at a deeper
+/// commitment a blockhash fetched at `processed` or `confirmed` isn't known yet.
+async fn lifetime_valid(rpc_client: &RpcClient, lifetime: &Lifetime) -> Result<bool, ClientError> {
+    match lifetime
+        .valid_at(rpc_client, CommitmentConfig::processed())
+        .await
+    {
+        Ok(valid) => Ok(valid),
+        // Then only the timeout ends the wait
+        Err(e) if unsupported_method(&e).is_some() => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// Wait for `signature` as `policy` says. `transaction` is what gets sent again when the
+/// policy rebroadcasts, without it nothing is.
+pub async fn confirm(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    lifetime: &Lifetime,
+    transaction: Option<&Transaction>,
+    policy: &ConfirmationPolicy,
+) -> Result<ConfirmationOutcome, ClientError> {
+    let commitment = policy.commitment.config();
+    let deadline = policy.deadline();
+    loop {
+        match status(rpc_client, signature).await? {
+            Some(status) => {
+                if let Some(outcome) = settled(&status, commitment) {
+                    return Ok(outcome);
+                }
+            }
+            None if !lifetime_valid(rpc_client, lifetime).await? => {
+                // Look once more in case it landed right before the blockhash expired
+                match status(rpc_client, signature).await? {
+                    Some(status) => {
+                        if let Some(outcome) = settled(&status, commitment) {
+                            return Ok(outcome);
+                        }
+                    }
+                    None => return Ok(ConfirmationOutcome::Expired),
+                }
+            }
+            None => {
+                if let (true, Some(transaction)) = (policy.rebroadcast, transaction) {
+                    let config = RpcSendTransactionConfig {
+                        skip_preflight: true,
+                        ..RpcSendTransactionConfig::default()
+                    };
+                    // Best effort, the next poll tells whether it got there
+                    let _ = rpc_client
+                        .send_transaction_with_config(transaction, config)
+                        .await;
+                }
+            }
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(ConfirmationOutcome::TimedOut);
+        }
+        tokio::time::sleep(Duration::from_millis(policy.poll_interval_ms)).await;
+    }
+}
+
+/// Wait for all of `signatures` in one polling loop, with one `getSignatureStatuses` call per
+/// poll. Outcomes come back in the same order. Nothing is sent again, the transactions are
+/// someone else's, and `recent_blockhash` only needs to be at least as old as theirs.
+pub async fn confirm_all(
+    rpc_client: &RpcClient,
+    signatures: &[Signature],
+    recent_blockhash: &Hash,
+    policy: &ConfirmationPolicy,
+) -> Result<Vec<ConfirmationOutcome>, ClientError> {
+    let commitment = policy.commitment.config();
+    let deadline = policy.deadline();
+    let mut outcomes: Vec<Option<ConfirmationOutcome>> = vec![None; signatures.len()];
+    loop {
+        let pending: Vec<usize> = (0..signatures.len())
+            .filter(|i| outcomes[*i].is_none())
+            .collect();
+        if pending.is_empty() {
+            break;
+        }
+        let polled: Vec<Signature> = pending.iter().map(|i| signatures[*i]).collect();
+        let statuses = fetch_statuses(rpc_client, &polled).await?;
+        let mut unseen = Vec::new();
+        for (i, status) in pending.iter().zip(statuses) {
+            match status {
+                Some(status) => outcomes[*i] = settled(&status, commitment),
+                None => unseen.push(*i),
+            }
+        }
+        if !unseen.is_empty()
+            && !lifetime_valid(rpc_client, &Lifetime::Blockhash(*recent_blockhash)).await?
+        {
+            // Those that landed right before the blockhash expired are waited for
+            let polled: Vec<Signature> = unseen.iter().map(|i| signatures[*i]).collect();
+            let statuses = fetch_statuses(rpc_client, &polled).await?;
+            for (i, status) in unseen.into_iter().zip(statuses) {
+                outcomes[i] = match status {
+                    Some(status) => settled(&status, commitment),
+                    None => Some(ConfirmationOutcome::Expired),
+                };
+            }
+        }
+        if outcomes.iter().all(Option::is_some) {
+            break;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(outcomes
+                .into_iter()
+                .map(|outcome| outcome.unwrap_or(ConfirmationOutcome::TimedOut))
+                .collect());
+        }
+        tokio::time::sleep(Duration::from_millis(policy.poll_interval_ms)).await;
+    }
+    Ok(outcomes.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        hash::Hash,
+        instruction::InstructionError,
+        signature::{Keypair, Signature, Signer},
+        system_transaction,
+        transaction::TransactionError,
+    };
+    use solana_transaction_status::TransactionConfirmationStatus::{
+        Confirmed, Finalized, Processed,
+    };
+
+    use crate::{
+        confirmation::{
+            Commitment, ConfirmationOutcome, ConfirmationOverride, ConfirmationPolicy, Lifetime,
+            confirm, confirm_all,
+        },
+        fixtures::{Statuses, status},
+        models::Network,
+    };
+
+    fn policy(commitment: Commitment) -> ConfirmationPolicy {
+        ConfirmationPolicy {
+            commitment,
+            timeout_ms: 1_000,
+            poll_interval_ms: 1,
+            ..ConfirmationPolicy::default()
+        }
+    }
+
+    async fn run(script: Statuses, policy: ConfirmationPolicy) -> ConfirmationOutcome {
+        let payer = Keypair::new();
+        let tx = system_transaction::transfer(&payer, &payer.pubkey(), 1, Hash::new_unique());
+        let rpc_client = script.async_rpc_client();
+        confirm(
+            &rpc_client,
+            &tx.signatures[0],
+            &Lifetime::of(&tx.message),
+            Some(&tx),
+            &policy,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_scripted_statuses() {
+        let confirmed = Statuses::default()
+            .then(None)
+            .then(Some(status(3, Processed, Ok(()))))
+            .then(Some(status(3, Confirmed, Ok(()))));
+        assert_eq!(
+            run(confirmed.clone(), policy(Commitment::Confirmed)).await,
+            ConfirmationOutcome::Confirmed {
+                slot: 3,
+                commitment: Commitment::Confirmed
+            }
+        );
+        // Confirmed isn't enough for finalized, the last status repeats until the timeout
+        let outcome = run(
+            confirmed,
+            ConfirmationPolicy {
+                timeout_ms: 20,
+                ..policy(Commitment::Finalized)
+            },
+        )
+        .await;
+        assert_eq!(outcome, ConfirmationOutcome::TimedOut);
+
+        let error = TransactionError::InstructionError(0, InstructionError::InsufficientFunds);
+        let failed = Statuses::default().then(Some(status(5, Finalized, Err(error.clone()))));
+        assert_eq!(
+            run(failed, policy(Commitment::Finalized)).await,
+            ConfirmationOutcome::Failed {
+                slot: 5,
+                error: error.to_string()
+            }
+        );
+
+        // Processed, then gone with its fork: not confirmed, and expired once the blockhash is
+        let reorg = Statuses::default()
+            .then(Some(status(7, Processed, Ok(()))))
+            .then(None)
+            .blockhash_valid([true, true, false]);
+        assert_eq!(
+            run(reorg.clone(), policy(Commitment::Confirmed)).await,
+            ConfirmationOutcome::Expired
+        );
+        assert_eq!(reorg.sent(), 0);
+
+        // Rebroadcast on every poll that doesn't find it
+        let rebroadcast =
+            Statuses::default()
+                .then(None)
+                .then(None)
+                .then(Some(status(9, Confirmed, Ok(()))));
+        let request = ConfirmationOverride {
+            rebroadcast: Some(true),
+            ..ConfirmationOverride::default()
+        };
+        let outcome = run(
+            rebroadcast.clone(),
+            policy(Commitment::Confirmed).with(Some(&request)),
+        )
+        .await;
+        assert_eq!(
+            outcome,
+            ConfirmationOutcome::Confirmed {
+                slot: 9,
+                commitment: Commitment::Confirmed
+            }
+        );
+        assert_eq!(rebroadcast.sent(), 2);
+
+        // The commitment reached is reported, it may be more than was asked for
+        let finalized = Statuses::default().then(Some(status(2, Finalized, Ok(()))));
+        assert_eq!(
+            run(finalized, policy(Commitment::Processed)).await,
+            ConfirmationOutcome::Confirmed {
+                slot: 2,
+                commitment: Commitment::Finalized
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_landed_as_the_blockhash_expired() {
+        // Not found yet when the blockhash of the request stops being valid, then found: it
+        // landed in time and isn't reported as expired
+        let landed = Statuses::default()
+            .then(None)
+            .then(Some(status(6, Processed, Ok(()))))
+            .then(Some(status(6, Confirmed, Ok(()))))
+            .then(Some(status(6, Finalized, Ok(()))))
+            .blockhash_valid([false]);
+        assert_eq!(
+            run(landed, policy(Commitment::Finalized)).await,
+            ConfirmationOutcome::Confirmed {
+                slot: 6,
+                commitment: Commitment::Finalized
+            }
+        );
+
+        let signatures = [Signature::new_unique()];
+        let landed = Statuses::default()
+            .then(None)
+            .then(Some(status(6, Processed, Ok(()))))
+            .then(Some(status(6, Confirmed, Ok(()))))
+            .blockhash_valid([false]);
+        let outcomes = confirm_all(
+            &landed.async_rpc_client(),
+            &signatures,
+            &Hash::new_unique(),
+            &policy(Commitment::Confirmed),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            outcomes,
+            [ConfirmationOutcome::Confirmed {
+                slot: 6,
+                commitment: Commitment::Confirmed
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_confirm_all() {
+        let signatures = [Signature::new_unique(), Signature::new_unique()];
+        let run_all = |script: Statuses, policy: ConfirmationPolicy| async move {
+            confirm_all(
+                &script.async_rpc_client(),
+                &signatures,
+                &Hash::new_unique(),
+                &policy,
+            )
+            .await
+            .unwrap()
+        };
+        let confirmed = Statuses::default()
+            .then(None)
+            .then(Some(status(4, Processed, Ok(()))))
+            .then(Some(status(4, Finalized, Ok(()))));
+        assert_eq!(
+            run_all(confirmed, policy(Commitment::Finalized)).await,
+            vec![
+                ConfirmationOutcome::Confirmed {
+                    slot: 4,
+                    commitment: Commitment::Finalized
+                };
+                2
+            ]
+        );
+
+        // Unseen ones expire with the blockhash, seen ones are waited for until the timeout
+        let unseen = Statuses::default().then(None).blockhash_valid([false]);
+        assert_eq!(
+            run_all(unseen, policy(Commitment::Confirmed)).await,
+            [ConfirmationOutcome::Expired, ConfirmationOutcome::Expired]
+        );
+        let seen = Statuses::default()
+            .then(Some(status(4, Processed, Ok(()))))
+            .blockhash_valid([false]);
+        let outcomes = run_all(
+            seen,
+            ConfirmationPolicy {
+                timeout_ms: 20,
+                ..policy(Commitment::Confirmed)
+            },
+        )
+        .await;
+        assert_eq!(outcomes, vec![ConfirmationOutcome::TimedOut; 2]);
+    }
+
+    #[test]
+    fn test_override() {
+        let config = ConfirmationPolicy::default();
+        assert_eq!(config.with(None), config);
+        let request: ConfirmationOverride =
+            serde_json::from_str(r#"{"commitment": "processed", "timeout_ms": 5}"#).unwrap();
+        let policy = config.with(Some(&request));
+        assert_eq!(policy.commitment, Commitment::Processed);
+        assert_eq!(policy.timeout_ms, 5);
+        assert_eq!(policy.poll_interval_ms, config.poll_interval_ms);
+        assert!(serde_json::from_str::<ConfirmationOverride>(r#"{"timeout": 5}"#).is_err());
+        assert!(
+            serde_json::from_str::<ConfirmationOverride>(r#"{"poll_interval_ms": 0}"#).is_err()
+        );
+        // A request can't wait or poll past the configured maxima
+        let request: ConfirmationOverride = serde_json::from_str(
+            r#"{"timeout_ms": 18446744073709551615, "poll_interval_ms": 3600000}"#,
+        )
+        .unwrap();
+        let policy = config.with(Some(&request));
+        assert_eq!(policy.timeout_ms, config.max_timeout_ms);
+        assert_eq!(policy.poll_interval_ms, config.max_poll_interval_ms);
+        // Nor does a configured timeout that long overflow the deadline
+        let forever = ConfirmationPolicy {
+            timeout_ms: u64::MAX,
+            ..config
+        };
+        assert!(forever.deadline().is_none());
+
+        // Localnet is processed unless the request asks for more
+        assert_eq!(config.on(Network::Devnet), config);
+        let local = config.on(Network::Localnet);
+        assert_eq!(local.commitment, Commitment::Processed);
+        let request: ConfirmationOverride =
+            serde_json::from_str(r#"{"commitment": "finalized"}"#).unwrap();
+        assert_eq!(local.with(Some(&request)).commitment, Commitment::Finalized);
+    }
+}
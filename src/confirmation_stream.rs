@@ -0,0 +1,393 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use poem::web::websocket::Message;
+use serde::Deserialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::TransactionStatus;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::{
+    Error,
+    confirmation::Commitment,
+    input::normalize,
+    models::{ConfirmationStage, ConfirmationSubscription, ConfirmationUpdate, Network},
+    signature_status::fetch_statuses,
+};
+
+// `/ws/confirmations` saves clients from polling `/api/transaction_statuses` themselves. Each
+// text message names a signature, the server polls the statuses of every signature it follows
+// on one interval, a `getSignatureStatuses` call per network, and sends an update whenever one
+// reaches a higher commitment. A signature is done once it is finalized, failed or timed out;
+// the socket closes once every signature sent on it is done, or after the timeout when none
+// was sent at all. A failing RPC call ends nothing, the next poll tries again.
+
+/// The `confirmation_stream` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfirmationStreamConfig {
+    /// Give up on a signature neither finalized nor failed after this long
+    pub timeout_ms: u64,
+    pub poll_interval_ms: u64,
+    /// Most signatures one connection follows at once
+    pub max_signatures: usize,
+}
+
+impl Default for ConfirmationStreamConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 120_000,
+            poll_interval_ms: 1000,
+            max_signatures: 64,
+        }
+    }
+}
+
+/// A signature followed by a connection.
+#[derive(Debug)]
+struct Followed {
+    signature: Signature,
+    net: Network,
+    reported: Option<Commitment>,
+    deadline: Instant,
+}
+
+impl Followed {
+    /// The update `status` is worth, if any, and whether it is the last one.
+    fn advance(
+        &mut self,
+        status: Option<&TransactionStatus>,
+    ) -> (Option<ConfirmationUpdate>, bool) {
+        let update = |status, slot, err| ConfirmationUpdate {
+            signature: self.signature.to_string(),
+            status,
+            slot,
+            err,
+            error: None,
+            error_code: None,
+        };
+        let Some(status) = status else {
+            return (None, false);
+        };
+        if let Err(e) = &status.status {
+            let failed = update(
+                ConfirmationStage::Failed,
+                Some(status.slot),
+                Some(e.to_string()),
+            );
+            return (Some(failed), true);
+        }
+        let commitment = Commitment::from(status.confirmation_status());
+        if self.reported >= Some(commitment) {
+            return (None, false);
+        }
+        self.reported = Some(commitment);
+        let stage = match commitment {
+            Commitment::Processed => ConfirmationStage::Processed,
+            Commitment::Confirmed => ConfirmationStage::Confirmed,
+            Commitment::Finalized => ConfirmationStage::Finalized,
+        };
+        let update = update(stage, Some(status.slot), None);
+        (Some(update), commitment == Commitment::Finalized)
+    }
+
+    fn timed_out(&self) -> ConfirmationUpdate {
+        ConfirmationUpdate {
+            signature: self.signature.to_string(),
+            status: ConfirmationStage::TimedOut,
+            slot: None,
+            err: None,
+            error: None,
+            error_code: None,
+        }
+    }
+}
+
+/// The `rejected` update of a message that can't be followed.
+fn rejected(signature: String, error: Error) -> ConfirmationUpdate {
+    ConfirmationUpdate {
+        signature,
+        status: ConfirmationStage::Rejected,
+        slot: None,
+        err: None,
+        error_code: Some(error.error_code().to_string()),
+        error: Some(error.to_string()),
+    }
+}
+
+/// The signature `text` asks to follow, on `net` unless it names its own network.
+fn subscription(
+    text: &str,
+    default_network: Option<Network>,
+) -> Result<(Signature, Network), ConfirmationUpdate> {
+    let request: ConfirmationSubscription = serde_json::from_str(text)
+        .map_err(|e| rejected(String::new(), Error::InvalidRequest(e.to_string())))?;
+    let signature = normalize(&request.signature)
+        .map_err(Error::from)
+        .and_then(|s| {
+            s.parse::<Signature>()
+                .map_err(|e| Error::InvalidTransactionId(e.to_string()))
+        })
+        .map_err(|e| rejected(request.signature.clone(), e))?;
+    let net = request
+        .net
+        .or(default_network)
+        .ok_or_else(|| rejected(request.signature.clone(), Error::MissingNetwork))?;
+    Ok((signature, net))
+}
+
+/// Statuses of the `followed` signatures in the same order, `None` for those whose network
+/// couldn't be asked.
+async fn poll<F>(followed: &[Followed], rpc_client: &F) -> Vec<Option<TransactionStatus>>
+where
+    F: Fn(Network) -> Arc<RpcClient>,
+{
+    let mut by_net: HashMap<Network, Vec<usize>> = HashMap::new();
+    for (i, f) in followed.iter().enumerate() {
+        by_net.entry(f.net).or_default().push(i);
+    }
+    let mut statuses = vec![None; followed.len()];
+    for (net, indexes) in by_net {
+        let signatures: Vec<Signature> = indexes.iter().map(|&i| followed[i].signature).collect();
+        match fetch_statuses(&rpc_client(net), &signatures).await {
+            Ok(fetched) => {
+                for (i, status) in indexes.into_iter().zip(fetched) {
+                    statuses[i] = status;
+                }
+            }
+            Err(e) => tracing::warn!(%net, error = %e, "confirmation stream poll failed"),
+        }
+    }
+    statuses
+}
+
+async fn send<S: Sink<Message> + Unpin>(outgoing: &mut S, update: &ConfirmationUpdate) -> bool {
+    let text = serde_json::to_string(update).unwrap_or_default();
+    outgoing.send(Message::Text(text)).await.is_ok()
+}
+
+/// Serve one connection: follow the signatures read from `incoming` and write their updates to
+/// `outgoing` until every one is done or the client goes away.
+pub async fn serve<I, E, S, F>(
+    incoming: I,
+    mut outgoing: S,
+    config: ConfirmationStreamConfig,
+    default_network: Option<Network>,
+    rpc_client: F,
+) where
+    I: Stream<Item = Result<Message, E>>,
+    S: Sink<Message> + Unpin,
+    F: Fn(Network) -> Arc<RpcClient>,
+{
+    let mut incoming = std::pin::pin!(incoming);
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut ticks = tokio::time::interval(Duration::from_millis(config.poll_interval_ms.max(1)));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let idle_deadline = Instant::now() + timeout;
+    let (mut followed, mut finished): (Vec<Followed>, usize) = (Vec::new(), 0);
+    loop {
+        tokio::select! {
+            message = incoming.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    // Pings are answered by the socket itself
+                    Some(Ok(_)) => continue,
+                };
+                let update = match subscription(&text, default_network) {
+                    Ok((signature, _)) if followed.iter().any(|f| f.signature == signature) => {
+                        continue;
+                    }
+                    Ok((signature, _)) if followed.len() >= config.max_signatures => rejected(
+                        signature.to_string(),
+                        Error::TooManySignatures {
+                            len: followed.len() + 1,
+                            max: config.max_signatures,
+                        },
+                    ),
+                    Ok((signature, net)) => {
+                        followed.push(Followed {
+                            signature,
+                            net,
+                            reported: None,
+                            deadline: Instant::now() + timeout,
+                        });
+                        continue;
+                    }
+                    Err(update) => update,
+                };
+                if !send(&mut outgoing, &update).await {
+                    return;
+                }
+            }
+            _ = ticks.tick() => {
+                if !followed.is_empty() {
+                    let statuses = poll(&followed, &rpc_client).await;
+                    let now = Instant::now();
+                    let mut updates = Vec::new();
+                    let mut index = 0;
+                    followed.retain_mut(|f| {
+                        let (update, done) = f.advance(statuses[index].as_ref());
+                        index += 1;
+                        updates.extend(update);
+                        if !done && now >= f.deadline {
+                            updates.push(f.timed_out());
+                            return false;
+                        }
+                        !done
+                    });
+                    finished += statuses.len() - followed.len();
+                    for update in &updates {
+                        if !send(&mut outgoing, update).await {
+                            return;
+                        }
+                    }
+                }
+                let idle = finished == 0 && Instant::now() >= idle_deadline;
+                if followed.is_empty() && (finished > 0 || idle) {
+                    let _ = outgoing.send(Message::Close(None)).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_util::{StreamExt, stream};
+    use poem::web::websocket::Message;
+    use solana_client::nonblocking::rpc_client::RpcClient;
+    use solana_sdk::signature::Signature;
+    use solana_transaction_status::TransactionConfirmationStatus::{
+        Confirmed, Finalized, Processed,
+    };
+
+    use crate::{
+        confirmation_stream::{ConfirmationStreamConfig, serve},
+        fixtures::{Statuses, status},
+        models::{ConfirmationStage, ConfirmationUpdate, Network},
+    };
+
+    const CONFIG: ConfirmationStreamConfig = ConfirmationStreamConfig {
+        timeout_ms: 200,
+        poll_interval_ms: 10,
+        max_signatures: 2,
+    };
+
+    /// Every update sent for `messages`, once the connection closed itself.
+    async fn updates(
+        messages: &[String],
+        config: ConfirmationStreamConfig,
+        rpc_client: impl Fn(Network) -> Arc<RpcClient>,
+    ) -> Vec<ConfirmationUpdate> {
+        let texts = messages
+            .iter()
+            .map(|m| Ok::<_, ()>(Message::Text(m.clone())));
+        // The client stays connected
+        let incoming = stream::iter(texts).chain(stream::pending());
+        let mut outgoing = Vec::new();
+        serve(incoming, &mut outgoing, config, None, rpc_client).await;
+        assert!(matches!(outgoing.pop(), Some(Message::Close(None))));
+        outgoing
+            .into_iter()
+            .map(|message| match message {
+                Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                message => panic!("unexpected {:?}", message),
+            })
+            .collect()
+    }
+
+    fn subscribe(signature: &Signature) -> String {
+        serde_json::json!({ "signature": signature.to_string(), "net": "devnet" }).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_updates_until_finalized() {
+        let statuses = Statuses::default()
+            .then(None)
+            .then(Some(status(1, Processed, Ok(()))))
+            .then(Some(status(1, Processed, Ok(()))))
+            .then(Some(status(1, Confirmed, Ok(()))))
+            .then(Some(status(1, Finalized, Ok(()))));
+        let client = Arc::new(statuses.async_rpc_client());
+        let (first, second) = (Signature::from([1; 64]), Signature::from([2; 64]));
+        let messages = [subscribe(&first), subscribe(&second), subscribe(&first)];
+        let updates = updates(&messages, CONFIG, |_| client.clone()).await;
+
+        // Both signatures get each stage once, the repeated subscription changes nothing
+        let stages: Vec<_> = updates
+            .iter()
+            .map(|u| (u.signature.clone(), u.status))
+            .collect();
+        let expected: Vec<_> = [
+            ConfirmationStage::Processed,
+            ConfirmationStage::Confirmed,
+            ConfirmationStage::Finalized,
+        ]
+        .into_iter()
+        .flat_map(|stage| [(first.to_string(), stage), (second.to_string(), stage)])
+        .collect();
+        assert_eq!(stages, expected);
+        assert!(updates.iter().all(|u| u.slot == Some(1)));
+    }
+
+    #[tokio::test]
+    async fn test_failures_and_timeouts() {
+        let failing = Arc::new(RpcClient::new_mock("instruction_error".to_string()));
+        let updates = updates(&[subscribe(&Signature::default())], CONFIG, |_| {
+            failing.clone()
+        })
+        .await;
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].status, ConfirmationStage::Failed);
+        assert!(updates[0].err.is_some());
+
+        let missing = Arc::new(RpcClient::new_mock("sig_not_found".to_string()));
+        let messages = [
+            "not json".to_string(),
+            r#"{ "signature": "1111", "net": "devnet" }"#.to_string(),
+            // No `net` and no default network
+            serde_json::json!({ "signature": Signature::default().to_string() }).to_string(),
+            subscribe(&Signature::from([1; 64])),
+            subscribe(&Signature::from([2; 64])),
+            subscribe(&Signature::from([3; 64])),
+        ];
+        let updates = updates(&messages, CONFIG, |_| missing.clone()).await;
+        let codes: Vec<_> = updates.iter().map(|u| u.error_code.as_deref()).collect();
+        assert_eq!(
+            codes,
+            [
+                Some("INVALID_REQUEST"),
+                Some("INVALID_TRANSACTION_ID"),
+                Some("MISSING_NETWORK"),
+                Some("TOO_MANY_SIGNATURES"),
+                None,
+                None,
+            ]
+        );
+        assert!(
+            updates[..4]
+                .iter()
+                .all(|u| u.status == ConfirmationStage::Rejected)
+        );
+        assert!(
+            updates[4..]
+                .iter()
+                .all(|u| u.status == ConfirmationStage::TimedOut)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closes_when_nothing_is_sent() {
+        let client = Arc::new(RpcClient::new_mock("succeeds".to_string()));
+        let config = ConfirmationStreamConfig {
+            timeout_ms: 20,
+            ..CONFIG
+        };
+        assert!(updates(&[], config, |_| client.clone()).await.is_empty());
+    }
+}
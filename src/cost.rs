@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_request::RpcRequest, rpc_response::Response,
+};
+use solana_sdk::{message::Message, pubkey::Pubkey, rent::Rent};
+
+use crate::{
+    Error, funding::message_fee, message_review::encode_message, spl_token_utils::TokenProgram,
+};
+
+// A cost preview reads everything off the message the transfer would send rather than off the
+// request: who pays the fee is the message's fee payer, who pays a token account's rent is the
+// funding account of its create instruction. The preview builds that message with the same
+// functions the transfer does, so the two can't disagree. `/api/estimate_fee` asks the node
+// instead, which also knows about fees the signature count doesn't tell.
+
+/// Blockhashes a fee estimate tries. Behind a load balancer the node pricing the message can
+/// lag the one that handed out the blockhash and not know it yet.
+const FEE_ATTEMPTS: usize = 2;
+
+/// Rent of a token account a message creates, and who pays it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RentCharge {
+    pub account: Pubkey,
+    pub payer: Pubkey,
+    pub lamports: u64,
+}
+
+/// The lamports a message costs its signers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCost {
+    pub fee_payer: Pubkey,
+    pub fee_lamports: u64,
+    pub rent: Vec<RentCharge>,
+}
+
+/// What `message` costs. `exists` says whether an account is known to exist, an idempotent
+/// create of an account that isn't known to is charged its rent.
+pub fn message_cost(message: &Message, exists: impl Fn(&Pubkey) -> Option<bool>) -> MessageCost {
+    let key = |index: &u8| message.account_keys[*index as usize];
+    let rent = message
+        .instructions
+        .iter()
+        .filter(|ix| key(&ix.program_id_index) == spl_associated_token_account::id())
+        .filter_map(|ix| match ix.accounts.as_slice() {
+            [payer, account, _, _, _, program, ..] => {
+                Some((key(payer), key(account), TokenProgram::of(&key(program))))
+            }
+            _ => None,
+        })
+        .filter(|(_, account, _)| exists(account) != Some(true))
+        .map(|(payer, account, program)| RentCharge {
+            account,
+            payer,
+            lamports: Rent::default()
+                .minimum_balance(program.unwrap_or_default().associated_account_len()),
+        })
+        .collect();
+    MessageCost {
+        fee_payer: message.account_keys[0],
+        fee_lamports: message_fee(message),
+        rent,
+    }
+}
+
+/// The fee the node charges for `message` with its latest blockhash, which replaces the one
+/// of `message`.
+pub async fn estimate_fee(rpc_client: &RpcClient, message: &mut Message) -> Result<u64, Error> {
+    for _ in 0..FEE_ATTEMPTS {
+        message.recent_blockhash = rpc_client
+            .get_latest_blockhash()
+            .await
+            .map_err(Error::RecentHashFailed)?;
+        if let Some(fee) = fee_for_message(rpc_client, message).await? {
+            return Ok(fee);
+        }
+    }
+    Err(Error::BlockhashNotFound(message.recent_blockhash))
+}
+
+/// `getFeeForMessage`, `None` when the node doesn't know the blockhash of `message`. The
+/// client's own method turns that into an error that can't be told from others.
+#[tracing::instrument(name = "rpc.get_fee_for_message", skip_all)]
+async fn fee_for_message(rpc_client: &RpcClient, message: &Message) -> Result<Option<u64>, Error> {
+    let params = json!([encode_message(message), rpc_client.commitment()]);
+    let response: Response<Option<u64>> = rpc_client
+        .send(RpcRequest::GetFeeForMessage, params)
+        .await
+        .map_err(Error::FeeUnavailable)?;
+    Ok(response.value)
+}
+
+/// Lamports every paying account of `costs` is debited, fees and rent together.
+pub fn debits<'a>(costs: impl IntoIterator<Item = &'a MessageCost>) -> BTreeMap<Pubkey, u64> {
+    let mut debits = BTreeMap::new();
+    for cost in costs {
+        *debits.entry(cost.fee_payer).or_default() += cost.fee_lamports;
+        for charge in &cost.rent {
+            *debits.entry(charge.payer).or_default() += charge.lamports;
+        }
+    }
+    debits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+    use solana_client::{nonblocking::rpc_client::RpcClient, rpc_request::RpcRequest};
+    use solana_sdk::{
+        message::Message, program_pack::Pack, pubkey::Pubkey, rent::Rent, system_instruction,
+    };
+    use spl_associated_token_account::get_associated_token_address;
+    use spl_token::state::Account;
+
+    use crate::{
+        Error,
+        cost::{RentCharge, debits, estimate_fee, message_cost},
+        spl_token_utils::TokenProgram,
+        transaction_builder::SplTransferBuilder,
+        units::{Decimals, RawTokenAmount},
+    };
+
+    #[test]
+    fn test_sponsored_rent_is_debited_from_the_sponsor() {
+        let owner = Pubkey::new_unique();
+        let sponsor = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let rent = Rent::default().minimum_balance(Account::LEN);
+        let transfer = |to| {
+            SplTransferBuilder::new(
+                owner,
+                to,
+                mint,
+                RawTokenAmount::new(5),
+                Decimals::new(6).unwrap(),
+            )
+            .create_recipient_ata(true)
+            .rent_sponsor(Some(sponsor))
+            .build()
+            .unwrap()
+        };
+        let (to, existing) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let created = message_cost(&transfer(to), |_| None);
+        assert_eq!(created.fee_payer, owner);
+        assert_eq!(created.fee_lamports, 10_000);
+        assert_eq!(
+            created.rent,
+            [RentCharge {
+                account: get_associated_token_address(&to, &mint),
+                payer: sponsor,
+                lamports: rent,
+            }]
+        );
+        // The idempotent create of an account that exists costs nothing
+        let existing_ata = get_associated_token_address(&existing, &mint);
+        let kept = message_cost(&transfer(existing), |account| {
+            Some(*account == existing_ata)
+        });
+        assert!(kept.rent.is_empty());
+
+        let debits = debits([&created, &kept]);
+        assert_eq!(debits[&owner], 20_000);
+        assert_eq!(debits[&sponsor], rent);
+
+        // A Token-2022 account is created with its `ImmutableOwner` extension, a bit more rent
+        let token_2022 = SplTransferBuilder::new(
+            owner,
+            to,
+            mint,
+            RawTokenAmount::new(5),
+            Decimals::new(6).unwrap(),
+        )
+        .token_program(TokenProgram::Token2022)
+        .create_recipient_ata(true)
+        .build()
+        .unwrap();
+        let rent_2022 = Rent::default().minimum_balance(Account::LEN + 5);
+        assert!(rent_2022 > rent);
+        assert_eq!(
+            message_cost(&token_2022, |_| None).rent[0].lamports,
+            rent_2022
+        );
+    }
+
+    fn node(fee: serde_json::Value) -> RpcClient {
+        RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetFeeForMessage,
+                json!({ "context": { "slot": 1 }, "value": fee }),
+            )]),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_estimate_fee() {
+        let payer = Pubkey::new_unique();
+        let transfer = system_instruction::transfer(&payer, &Pubkey::new_unique(), 1);
+        let mut message = Message::new(&[transfer], Some(&payer));
+
+        assert_eq!(
+            estimate_fee(&node(json!(10_000)), &mut message)
+                .await
+                .unwrap(),
+            10_000
+        );
+
+        // A blockhash the node doesn't know yet gets a second try, which the mock answers with
+        // its default fee
+        assert_eq!(
+            estimate_fee(&node(json!(null)), &mut message)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let unreachable = RpcClient::new_mock("fails".to_string());
+        assert!(matches!(
+            estimate_fee(&unreachable, &mut message).await,
+            Err(Error::RecentHashFailed(_))
+        ));
+    }
+}
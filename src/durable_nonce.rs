@@ -0,0 +1,404 @@
+use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
+use solana_sdk::{
+    account::Account,
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    message::Message,
+    nonce::{
+        NONCED_TX_MARKER_IX_INDEX,
+        state::{State, Versions},
+    },
+    program_utils::limited_deserialize,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::{self, SystemInstruction},
+    system_program,
+    transaction::Transaction,
+};
+
+use crate::Error;
+
+// A multi-party transfer signed against a recent blockhash has about a minute from the
+// blockhash to the broadcast, less than a signing round between people can take. Signed against
+// a durable nonce instead it stays valid until the nonce account is advanced: the transaction
+// advances it itself as its first instruction, and carries the nonce where the blockhash would
+// be. The aggregated key is the only signer a TSS transfer has, so it must be the nonce
+// authority. Once the nonce moved on, by this transfer landing or by anything else advancing
+// the account, every signature over the old nonce is worthless: the parties sign again with the
+// current one. `/api/nonce/*` creates such accounts, reads them and withdraws from them.
+
+/// The nonce account a transfer is signed against and the authority that advances it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DurableNonce {
+    pub account: Pubkey,
+    pub authority: Pubkey,
+}
+
+impl DurableNonce {
+    /// The durable nonce of a request's `nonce_account` and `nonce_authority`, `None` without
+    /// an account, whatever the authority. The authority defaults to `signer`, the transfer's
+    /// only signer, and can't be anyone else.
+    pub fn resolve(
+        account: Option<Pubkey>,
+        authority: Option<Pubkey>,
+        signer: Pubkey,
+    ) -> Result<Option<Self>, Error> {
+        let Some(account) = account else {
+            return Ok(None);
+        };
+        match authority {
+            Some(authority) if authority != signer => Err(Error::InvalidNonceAccount {
+                nonce_account: account,
+                reason: format!(
+                    "its authority must be the signer {} of the transfer, not {}",
+                    signer, authority
+                ),
+            }),
+            _ => Ok(Some(Self {
+                account,
+                authority: signer,
+            })),
+        }
+    }
+}
+
+/// An initialized nonce account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonceAccount {
+    /// The durable nonce a transaction signed against the account carries
+    pub nonce: Hash,
+    pub authority: Pubkey,
+    pub lamports: u64,
+}
+
+/// Lamports a nonce account needs to be rent exempt. Every cluster runs the default rent, no
+/// node has to be asked.
+pub fn nonce_account_rent() -> u64 {
+    Rent::default().minimum_balance(State::size())
+}
+
+/// What the initialized nonce account at `nonce_account` holds.
+pub fn nonce_state(nonce_account: &Pubkey, account: &Account) -> Result<NonceAccount, Error> {
+    let invalid = |reason: &str| Error::InvalidNonceAccount {
+        nonce_account: *nonce_account,
+        reason: reason.to_string(),
+    };
+    if account.owner != system_program::id() {
+        return Err(invalid("not owned by the system program"));
+    }
+    let versions: Versions = limited_deserialize(&account.data, State::size() as u64)
+        .map_err(|_| invalid("not a nonce account"))?;
+    match versions.state() {
+        State::Initialized(data) => Ok(NonceAccount {
+            nonce: data.blockhash(),
+            authority: data.authority,
+            lamports: account.lamports,
+        }),
+        State::Uninitialized => Err(invalid("not initialized")),
+    }
+}
+
+/// The nonce account at `nonce_account` as the node holds it now.
+pub async fn fetch_nonce_account(
+    rpc_client: &RpcClient,
+    nonce_account: &Pubkey,
+) -> Result<NonceAccount, Error> {
+    let account = rpc_client
+        .get_account_with_commitment(nonce_account, rpc_client.commitment())
+        .await
+        .map_err(Error::AccountFetchFailed)?
+        .value
+        .ok_or_else(|| Error::InvalidNonceAccount {
+            nonce_account: *nonce_account,
+            reason: "no such account".to_string(),
+        })?;
+    nonce_state(nonce_account, &account)
+}
+
+/// The nonce `nonce_account` holds now, fails unless it can carry a transfer of `authority`.
+pub async fn fetch_nonce(
+    rpc_client: &RpcClient,
+    nonce_account: &Pubkey,
+    authority: &Pubkey,
+) -> Result<Hash, Error> {
+    let held = fetch_nonce_account(rpc_client, nonce_account).await?;
+    check_authority(nonce_account, &held, authority)?;
+    Ok(held.nonce)
+}
+
+/// Fail unless `authority` is the one advancing and withdrawing from `held`.
+pub fn check_authority(
+    nonce_account: &Pubkey,
+    held: &NonceAccount,
+    authority: &Pubkey,
+) -> Result<(), Error> {
+    if held.authority != *authority {
+        return Err(Error::InvalidNonceAccount {
+            nonce_account: *nonce_account,
+            reason: format!("its authority is {}, not {}", held.authority, authority),
+        });
+    }
+    Ok(())
+}
+
+/// Fail with `NonceAdvanced` unless `durable_nonce` still holds the `expected` nonce a
+/// transfer is signed with.
+pub async fn check_nonce(
+    rpc_client: &RpcClient,
+    durable_nonce: &DurableNonce,
+    expected: &Hash,
+) -> Result<(), Error> {
+    let found = fetch_nonce(rpc_client, &durable_nonce.account, &durable_nonce.authority).await?;
+    if found != *expected {
+        return Err(Error::NonceAdvanced {
+            nonce_account: durable_nonce.account,
+            expected: *expected,
+            found,
+        });
+    }
+    Ok(())
+}
+
+/// Whether `nonce_account` still holds `nonce` at `commitment`, a transaction signed with it
+/// can land until it doesn't.
+pub async fn nonce_holds(
+    rpc_client: &RpcClient,
+    nonce_account: &Pubkey,
+    nonce: &Hash,
+    commitment: CommitmentConfig,
+) -> Result<bool, ClientError> {
+    let account = rpc_client
+        .get_account_with_commitment(nonce_account, commitment)
+        .await?
+        .value;
+    Ok(account.is_some_and(|account| {
+        nonce_state(nonce_account, &account).is_ok_and(|held| held.nonce == *nonce)
+    }))
+}
+
+/// Create `nonce_account` with the rent-exempt minimum from `payer`, advanced by `authority`.
+/// Both `payer` and `nonce_account` sign.
+pub fn create_nonce_account_transaction(
+    payer: &Pubkey,
+    nonce_account: &Pubkey,
+    authority: &Pubkey,
+) -> Transaction {
+    let instructions = system_instruction::create_nonce_account(
+        payer,
+        nonce_account,
+        authority,
+        nonce_account_rent(),
+    );
+    Transaction::new_unsigned(Message::new(&instructions, Some(payer)))
+}
+
+/// Withdraw `lamports` from `nonce_account` to `destination`, signed and paid for by its
+/// `authority`. Withdrawing the whole balance closes the account.
+pub fn withdraw_nonce_transaction(
+    nonce_account: &Pubkey,
+    authority: &Pubkey,
+    destination: &Pubkey,
+    lamports: u64,
+) -> Transaction {
+    let withdraw =
+        system_instruction::withdraw_nonce_account(nonce_account, authority, destination, lamports);
+    Transaction::new_unsigned(Message::new(&[withdraw], Some(authority)))
+}
+
+/// Lamports to withdraw from `held`, all of them, closing it, when `lamports` is `None`. A
+/// partial withdrawal has to leave the account rent exempt.
+pub fn withdrawal(
+    nonce_account: &Pubkey,
+    held: &NonceAccount,
+    lamports: Option<u64>,
+) -> Result<u64, Error> {
+    let Some(lamports) = lamports else {
+        return Ok(held.lamports);
+    };
+    if lamports > held.lamports {
+        return Err(Error::InvalidAmount(format!(
+            "nonce account {} holds {} lamports, not {}",
+            nonce_account, held.lamports, lamports
+        )));
+    }
+    let left = held.lamports - lamports;
+    if left != 0 && left < nonce_account_rent() {
+        return Err(Error::InvalidAmount(format!(
+            "withdrawing {} lamports leaves nonce account {} with {}, it needs {} to stay rent \
+             exempt, or none to close",
+            lamports,
+            nonce_account,
+            left,
+            nonce_account_rent()
+        )));
+    }
+    Ok(lamports)
+}
+
+/// The nonce account `message` advances first, `None` for a message signed against a recent
+/// blockhash.
+pub fn advanced_nonce_account(message: &Message) -> Option<Pubkey> {
+    let instruction = message
+        .instructions
+        .get(usize::from(NONCED_TX_MARKER_IX_INDEX))?;
+    let program = message
+        .account_keys
+        .get(usize::from(instruction.program_id_index))?;
+    if *program != system_program::id() {
+        return None;
+    }
+    match limited_deserialize(&instruction.data, 4) {
+        Ok(SystemInstruction::AdvanceNonceAccount) => {
+            let account = instruction.accounts.first()?;
+            message.account_keys.get(usize::from(*account)).copied()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{message::Message, system_instruction};
+
+    use super::*;
+    use crate::fixtures::{Accounts, nonce_account};
+
+    #[test]
+    fn test_resolve() {
+        let signer = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        assert_eq!(DurableNonce::resolve(None, None, signer).unwrap(), None);
+        let expected = Some(DurableNonce {
+            account,
+            authority: signer,
+        });
+        for authority in [None, Some(signer)] {
+            assert_eq!(
+                DurableNonce::resolve(Some(account), authority, signer).unwrap(),
+                expected
+            );
+        }
+        assert!(matches!(
+            DurableNonce::resolve(Some(account), Some(Pubkey::new_unique()), signer),
+            Err(Error::InvalidNonceAccount { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_check_nonce() {
+        let authority = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let (nonce_data, nonce) = nonce_account(&authority);
+        let plain = Pubkey::new_unique();
+        let rpc_client = Accounts::default()
+            .with(account, nonce_data)
+            .with(plain, Account::new(1_000_000, 0, &system_program::id()))
+            .async_rpc_client();
+        let durable_nonce = DurableNonce { account, authority };
+
+        check_nonce(&rpc_client, &durable_nonce, &nonce)
+            .await
+            .unwrap();
+        let signed_with = Hash::new_unique();
+        match check_nonce(&rpc_client, &durable_nonce, &signed_with).await {
+            Err(Error::NonceAdvanced {
+                expected, found, ..
+            }) => assert_eq!((expected, found), (signed_with, nonce)),
+            other => panic!("expected an advanced nonce, got {:?}", other),
+        }
+        let processed = CommitmentConfig::processed();
+        assert!(
+            nonce_holds(&rpc_client, &account, &nonce, processed)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !nonce_holds(&rpc_client, &account, &signed_with, processed)
+                .await
+                .unwrap()
+        );
+
+        let someone_else = DurableNonce {
+            account,
+            authority: Pubkey::new_unique(),
+        };
+        for durable_nonce in [
+            someone_else,
+            DurableNonce {
+                account: plain,
+                authority,
+            },
+            DurableNonce {
+                account: Pubkey::new_unique(),
+                authority,
+            },
+        ] {
+            assert!(matches!(
+                check_nonce(&rpc_client, &durable_nonce, &nonce).await,
+                Err(Error::InvalidNonceAccount { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_nonce_account_transactions() {
+        let (payer, nonce_account, authority) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let create = create_nonce_account_transaction(&payer, &nonce_account, &authority);
+        assert_eq!(create.message.account_keys[..2], [payer, nonce_account]);
+        assert_eq!(create.message.header.num_required_signatures, 2);
+        assert_eq!(create.message.instructions.len(), 2);
+
+        let withdraw = withdraw_nonce_transaction(&nonce_account, &authority, &payer, 1_000);
+        assert_eq!(withdraw.message.account_keys[0], authority);
+        assert_eq!(withdraw.message.header.num_required_signatures, 1);
+        assert_eq!(
+            limited_deserialize::<SystemInstruction>(&withdraw.message.instructions[0].data, 12)
+                .unwrap(),
+            SystemInstruction::WithdrawNonceAccount(1_000)
+        );
+    }
+
+    #[test]
+    fn test_withdrawal() {
+        let account = Pubkey::new_unique();
+        let rent = nonce_account_rent();
+        let held = NonceAccount {
+            nonce: Hash::new_unique(),
+            authority: Pubkey::new_unique(),
+            lamports: rent + 1_000,
+        };
+        assert_eq!(withdrawal(&account, &held, None).unwrap(), rent + 1_000);
+        for lamports in [1_000, rent + 1_000] {
+            assert_eq!(
+                withdrawal(&account, &held, Some(lamports)).unwrap(),
+                lamports
+            );
+        }
+        for lamports in [1_001, rent + 1_001] {
+            assert!(matches!(
+                withdrawal(&account, &held, Some(lamports)),
+                Err(Error::InvalidAmount(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_advanced_nonce_account() {
+        let payer = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let transfer = system_instruction::transfer(&payer, &Pubkey::new_unique(), 1);
+        let advance = system_instruction::advance_nonce_account(&account, &payer);
+
+        let nonced = Message::new(&[advance.clone(), transfer.clone()], Some(&payer));
+        assert_eq!(advanced_nonce_account(&nonced), Some(account));
+        // Only as the first instruction
+        for instructions in [vec![transfer.clone()], vec![transfer, advance]] {
+            let message = Message::new(&instructions, Some(&payer));
+            assert_eq!(advanced_nonce_account(&message), None);
+        }
+    }
+}
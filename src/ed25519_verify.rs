@@ -0,0 +1,193 @@
+use solana_sdk::{ed25519_program, instruction::Instruction, pubkey::Pubkey, signature::Signature};
+
+use crate::Error;
+
+// A program can require proof that a key signed some bytes, checked by the Ed25519Program
+// precompile in the same transaction; the program then reads the precompile's instruction
+// through the instructions sysvar. The precompile's data starts with a header counting the
+// signatures, then one table of offsets per signature. Each offset is a byte position in the
+// data of the instruction its index names, `u16::MAX` naming the precompile's own data. The
+// instructions built here carry one signature with its pubkey, signature and message right
+// after the table, the layout the SDK's own `new_ed25519_instruction` uses.
+
+/// The signature count and a padding byte.
+pub const HEADER_SIZE: usize = 2;
+/// Seven little-endian `u16`, see `Ed25519Offsets`.
+pub const OFFSETS_SIZE: usize = 14;
+/// Where the pubkey of a single signature starts.
+pub const DATA_START: usize = HEADER_SIZE + OFFSETS_SIZE;
+/// Instruction index naming the precompile instruction itself.
+pub const THIS_INSTRUCTION: u16 = u16::MAX;
+
+const PUBKEY_SIZE: usize = 32;
+const SIGNATURE_SIZE: usize = 64;
+
+/// Longest message a single signature's offsets can point at.
+pub const MAX_MESSAGE_SIZE: usize = u16::MAX as usize - DATA_START - PUBKEY_SIZE - SIGNATURE_SIZE;
+
+/// Where the precompile finds the signature, pubkey and message of one signature, in the
+/// order they're serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ed25519Offsets {
+    pub signature_offset: u16,
+    pub signature_instruction_index: u16,
+    pub public_key_offset: u16,
+    pub public_key_instruction_index: u16,
+    pub message_data_offset: u16,
+    pub message_data_size: u16,
+    pub message_instruction_index: u16,
+}
+
+impl Ed25519Offsets {
+    /// The offsets of a single signature whose pubkey, signature and `message_len` bytes of
+    /// message follow the table in the precompile's own data.
+    pub fn single(message_len: usize) -> Result<Self, Error> {
+        if message_len > MAX_MESSAGE_SIZE {
+            return Err(Error::InvalidPayload(format!(
+                "{} bytes, the precompile takes at most {}",
+                message_len, MAX_MESSAGE_SIZE
+            )));
+        }
+        let public_key_offset = DATA_START;
+        let signature_offset = public_key_offset + PUBKEY_SIZE;
+        let message_data_offset = signature_offset + SIGNATURE_SIZE;
+        Ok(Self {
+            signature_offset: signature_offset as u16,
+            signature_instruction_index: THIS_INSTRUCTION,
+            public_key_offset: public_key_offset as u16,
+            public_key_instruction_index: THIS_INSTRUCTION,
+            message_data_offset: message_data_offset as u16,
+            message_data_size: message_len as u16,
+            message_instruction_index: THIS_INSTRUCTION,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; OFFSETS_SIZE] {
+        let fields = [
+            self.signature_offset,
+            self.signature_instruction_index,
+            self.public_key_offset,
+            self.public_key_instruction_index,
+            self.message_data_offset,
+            self.message_data_size,
+            self.message_instruction_index,
+        ];
+        let mut bytes = [0; OFFSETS_SIZE];
+        for (chunk, field) in bytes.chunks_exact_mut(2).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// The table of the `index`th signature in the data of a precompile instruction.
+    pub fn read(data: &[u8], index: usize) -> Option<Self> {
+        let start = HEADER_SIZE + index * OFFSETS_SIZE;
+        let table = data.get(start..start + OFFSETS_SIZE)?;
+        let field = |i: usize| u16::from_le_bytes([table[2 * i], table[2 * i + 1]]);
+        Some(Self {
+            signature_offset: field(0),
+            signature_instruction_index: field(1),
+            public_key_offset: field(2),
+            public_key_instruction_index: field(3),
+            message_data_offset: field(4),
+            message_data_size: field(5),
+            message_instruction_index: field(6),
+        })
+    }
+}
+
+/// An Ed25519Program instruction proving that `pubkey` signed `message` with `signature`.
+/// The precompile only checks the signature, whoever relies on it checks the pubkey and
+/// message.
+pub fn ed25519_verify_instruction(
+    pubkey: &Pubkey,
+    signature: &Signature,
+    message: &[u8],
+) -> Result<Instruction, Error> {
+    let offsets = Ed25519Offsets::single(message.len())?;
+    let mut data = Vec::with_capacity(DATA_START + PUBKEY_SIZE + SIGNATURE_SIZE + message.len());
+    data.extend_from_slice(&[1, 0]);
+    data.extend_from_slice(&offsets.to_bytes());
+    data.extend_from_slice(pubkey.as_ref());
+    data.extend_from_slice(signature.as_ref());
+    data.extend_from_slice(message);
+    Ok(Instruction {
+        program_id: ed25519_program::id(),
+        accounts: vec![],
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::Signer as _;
+    use solana_sdk::{
+        ed25519_instruction::{new_ed25519_instruction, verify},
+        ed25519_program,
+        feature_set::FeatureSet,
+        pubkey::Pubkey,
+        signature::Signature,
+    };
+
+    use crate::ed25519_verify::{
+        DATA_START, Ed25519Offsets, MAX_MESSAGE_SIZE, THIS_INSTRUCTION, ed25519_verify_instruction,
+    };
+
+    fn dalek_keypair() -> ed25519_dalek::Keypair {
+        ed25519_dalek::Keypair::generate(&mut rand07::thread_rng())
+    }
+
+    #[test]
+    fn test_offsets_match_the_precompile_layout() {
+        // signature count, padding, then the table
+        let offsets = Ed25519Offsets::single(5).unwrap();
+        assert_eq!(
+            offsets,
+            Ed25519Offsets {
+                signature_offset: 48,
+                signature_instruction_index: THIS_INSTRUCTION,
+                public_key_offset: 16,
+                public_key_instruction_index: THIS_INSTRUCTION,
+                message_data_offset: 112,
+                message_data_size: 5,
+                message_instruction_index: THIS_INSTRUCTION,
+            }
+        );
+        assert_eq!(
+            offsets.to_bytes(),
+            [48, 0, 255, 255, 16, 0, 255, 255, 112, 0, 5, 0, 255, 255]
+        );
+
+        for message in [&b""[..], &b"payload"[..], &[7; 900][..]] {
+            let keypair = dalek_keypair();
+            let signature = keypair.sign(message);
+            let pubkey = Pubkey::new_from_array(keypair.public.to_bytes());
+            let signature = Signature::new(&signature.to_bytes());
+            let instruction = ed25519_verify_instruction(&pubkey, &signature, message).unwrap();
+
+            // Byte for byte what the SDK builds from the keypair
+            assert_eq!(instruction, new_ed25519_instruction(&keypair, message));
+            assert_eq!(instruction.program_id, ed25519_program::id());
+            assert!(instruction.accounts.is_empty());
+            let data = &instruction.data;
+            assert_eq!(data[..2], [1, 0]);
+            let offsets = Ed25519Offsets::read(data, 0).unwrap();
+            assert_eq!(offsets, Ed25519Offsets::single(message.len()).unwrap());
+            let at = |offset: u16, len: usize| &data[offset as usize..offset as usize + len];
+            assert_eq!(at(offsets.public_key_offset, 32), pubkey.as_ref());
+            assert_eq!(at(offsets.signature_offset, 64), signature.as_ref());
+            assert_eq!(at(offsets.message_data_offset, message.len()), message);
+            assert_eq!(data.len(), DATA_START + 96 + message.len());
+
+            // The precompile accepts it, and refuses another signature
+            verify(data, &[data], &FeatureSet::all_enabled()).unwrap();
+            let mut tampered = data.clone();
+            tampered[offsets.signature_offset as usize + 40] ^= 1;
+            assert!(verify(&tampered, &[&tampered], &FeatureSet::all_enabled()).is_err());
+        }
+
+        assert!(Ed25519Offsets::single(MAX_MESSAGE_SIZE).is_ok());
+        let error = Ed25519Offsets::single(MAX_MESSAGE_SIZE + 1).unwrap_err();
+        assert_eq!(error.error_code(), "INVALID_PAYLOAD");
+    }
+}
@@ -0,0 +1,31 @@
+use poem::{Body, Response, http::header};
+use serde_json::Value;
+
+// Some fields of a response aren't the handler's to fill in: the debug timings and the
+// configured `environment` are added to the JSON object body once it is done.
+
+/// `resp` with `field` set to `value` in its JSON object body, other bodies are left alone.
+pub async fn insert_field(resp: Response, field: &str, value: Value) -> Response {
+    // Streamed bodies such as the audit export aren't JSON and shouldn't be buffered
+    let json = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !json {
+        return resp;
+    }
+    let (parts, body) = resp.into_parts();
+    let bytes = match body.into_bytes().await {
+        Ok(bytes) => bytes,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let body = match serde_json::from_slice(&bytes) {
+        Ok(Value::Object(mut object)) => {
+            object.insert(field.to_string(), value);
+            Body::from(serde_json::to_vec(&object).unwrap_or_default())
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
@@ -1,16 +1,98 @@
 use std::fmt::{Display, Formatter};
 
 use bs58::decode::Error as Bs58Error;
-use solana_client::client_error::ClientError;
-use solana_sdk::program_error::ProgramError;
+use poem::http::StatusCode;
+use solana_client::{client_error::ClientError, rpc_request::RpcRequest};
+use solana_sdk::{hash::Hash, program_error::ProgramError, pubkey::Pubkey, signature::Signature};
 
+use crate::confirmation::ConfirmationOutcome;
+use crate::input::InteriorWhitespace;
+use crate::models::Network;
+use crate::policy::PolicyRule;
+use crate::recipient::SuspiciousRecipient;
+use crate::rpc_methods::{hint, rpc_error_class, rpc_error_code, unsupported_method};
 use crate::serialization::Error as DeserializationError;
+use crate::transaction_builder::Oversize;
+use crate::tss::MIN_KEYS;
+
+/// How an error came about, which decides whether sending the same request again can
+/// succeed. Listed with their `retryable` flag by `/api/capabilities`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Input,
+    Protocol,
+    Refused,
+    Transaction,
+    Node,
+    Transport,
+    RateLimited,
+    Timeout,
+    Internal,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 9] = [
+        Self::Input,
+        Self::Protocol,
+        Self::Refused,
+        Self::Transaction,
+        Self::Node,
+        Self::Transport,
+        Self::RateLimited,
+        Self::Timeout,
+        Self::Internal,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Input => "input",
+            Self::Protocol => "protocol",
+            Self::Refused => "refused",
+            Self::Transaction => "transaction",
+            Self::Node => "node",
+            Self::Transport => "transport",
+            Self::RateLimited => "rate_limited",
+            Self::Timeout => "timeout",
+            Self::Internal => "internal",
+        }
+    }
+
+    /// Whether the same request may succeed when sent again unchanged.
+    pub fn retryable(self) -> bool {
+        matches!(self, Self::Transport | Self::RateLimited | Self::Timeout)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Input => "a field doesn't parse or is out of range, fix the request",
+            Self::Protocol => {
+                "the signing inputs don't fit together or went stale, restart the ceremony"
+            }
+            Self::Refused => {
+                "the server's policy or the state of the chain refuses the request as it is"
+            }
+            Self::Transaction => {
+                "the transaction was rejected or failed on chain, or a split transfer went out \
+                 only in part"
+            }
+            Self::Node => "the RPC node refused the call itself",
+            Self::Transport => "the RPC node couldn't be reached or isn't ready yet",
+            Self::RateLimited => {
+                "the server is rate limiting the client, or the RPC node or its faucet the server"
+            }
+            Self::Timeout => "the transaction wasn't confirmed in time, it may still land",
+            Self::Internal => "a bug in the server, report it with the request that caused it",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum Error {
     WrongNetwork(String),
     BadBase58(Bs58Error),
     WrongKeyPair(ed25519_dalek::SignatureError),
+    /// Keypair bytes of the wrong length, or a JSON keypair that isn't an array of bytes
+    InvalidKeypair(String),
     AirdropFailed(ClientError),
     RecentHashFailed(ClientError),
     ConfirmingTransactionFailed(ClientError),
@@ -35,8 +117,722 @@ pub enum Error {
     DeactivationFailed(String),
     WithdrawalFailed(String),
     InvalidPublicKey(String),
+    InvalidBlockHash(String),
+    InteriorWhitespace(InteriorWhitespace),
     InsufficientBalance(String),
     BalanceCheckFailed(String),
+    InvalidAmount(String),
+    /// The amount in request field `field` can't be sent: not a number, not more than zero or
+    /// too large for a 64 bit amount
+    AmountRejected {
+        field: String,
+        reason: String,
+    },
+    /// A JSON request body with bytes that aren't UTF-8, at the offset of the first one
+    BodyNotUtf8(usize),
+    TokenAccountScanFailed(ClientError),
+    StalePlan(String),
+    SimulationFailed(String),
+    InvalidComputeUnitLimit(String),
+    InvalidMemoProgram(String),
+    FaucetLimitExceeded(String),
+    InvalidTransactionId(String),
+    AccountFetchFailed(ClientError),
+    SignatureStatusFailed(ClientError),
+    SlotFetchFailed(ClientError),
+    /// The node couldn't simulate a transaction, as opposed to a simulation that failed
+    SimulationUnavailable(ClientError),
+    /// The node couldn't price a message
+    FeeUnavailable(ClientError),
+    /// The node couldn't price a message with the blockhash it handed out itself
+    BlockhashNotFound(Hash),
+    InvalidBroadcastAt(String),
+    ScheduledBroadcastNotFound(String),
+    /// No audit entry with this id, or it was evicted
+    AuditEntryNotFound(u64),
+    /// A `secret_state_id` that was already taken, expired or wasn't kept for this signer
+    SecretStateNotFound,
+    /// An audit export's time range, cursor or limit
+    InvalidExportRange(String),
+    /// A Solana Pay URL that isn't a valid transfer request, or doesn't fit the request it came in
+    InvalidSolanaPay(String),
+    /// A mnemonic phrase that isn't valid BIP39: unknown words, a wrong word count or checksum
+    InvalidMnemonic(String),
+    /// A derivation path that isn't of the form `m/44'/501'/0'/0'`
+    InvalidDerivationPath(String),
+    /// A request's `rpc_url` that isn't an http(s) URL or can't be used with the request
+    InvalidRpcUrl(String),
+    /// A request's `rpc_url` names a host outside `request_rpc_url_hosts`
+    RpcUrlNotAllowed(String),
+    SourceAccountNotFound(Pubkey),
+    SourceMintMismatch {
+        account: Pubkey,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    SourceOwnerMismatch {
+        account: Pubkey,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    SourceAccountFrozen(Pubkey),
+    /// A wrapped SOL transfer larger than the account's token amount, it would need lamports
+    /// of the rent-exempt reserve or ones added without `SyncNative`
+    NativeReserve {
+        account: Pubkey,
+        requested: u64,
+        spendable: u64,
+        unsynced: u64,
+    },
+    /// A Token-2022 mint with an extension a plain `transfer_checked` can't move tokens under
+    UnsupportedMintExtension {
+        mint: Pubkey,
+        extension: &'static str,
+    },
+    TooManyParties {
+        field: &'static str,
+        len: usize,
+        max: usize,
+    },
+    TooManySignatures {
+        len: usize,
+        max: usize,
+    },
+    TooManyRecipients {
+        len: usize,
+        max: usize,
+    },
+    /// The transfer with its memo is over the packet size. Single-key transfers can be
+    /// `splittable` into several transactions, TSS ones never
+    TransactionTooLarge {
+        oversize: Oversize,
+        splittable: bool,
+    },
+    /// A split transfer failed after its `sent` transactions had been sent
+    SplitIncomplete {
+        sent: Vec<Signature>,
+        total: usize,
+        error: Box<Error>,
+    },
+    /// There is no faucet on the network
+    AirdropUnavailable(Network),
+    /// `keys` names fewer than the two parties a TSS signature needs
+    TooFewKeys(usize),
+    /// The endpoint needs an admin API key
+    AdminOnly(&'static str),
+    /// `require_api_key` is on and the request sent no key, or one that isn't known
+    Unauthorized {
+        key_sent: bool,
+        /// Latest skew of the system clock, so a client can tell a bad key from a bad clock
+        clock_skew_ms: Option<i64>,
+    },
+    SpendLimitExceeded {
+        scope: &'static str,
+        asset: String,
+        limit: u64,
+        remaining: u64,
+    },
+    InvalidToken(String),
+    UnknownToken {
+        symbol: String,
+        known: Vec<String>,
+    },
+    /// A deleted token named by its symbol, `purge_at` in Unix milliseconds
+    TokenDeleted {
+        symbol: String,
+        purge_at: u64,
+    },
+    TokenLimitExceeded {
+        token: String,
+        amount: String,
+        max: f64,
+    },
+    AtaCreationNotAllowed {
+        token: String,
+        account: String,
+    },
+    MessageHashMismatch {
+        expected: String,
+        actual: String,
+    },
+    SignerNotInKeySet {
+        signer: Pubkey,
+        keys: Vec<Pubkey>,
+    },
+    AggAccountUnfunded {
+        address: Pubkey,
+        minimum_lamports: u64,
+    },
+    /// The rent sponsor of a created ATA must sign too
+    RentSponsorSignatureMissing(Pubkey),
+    /// A blob produced by a server with another `environment` label
+    EnvironmentMismatch {
+        field: &'static str,
+        expected: String,
+        found: Option<String>,
+    },
+    /// The `context` of a submitted partial signature disagrees with the aggregation or with the
+    /// context of another submission
+    SignatureContextMismatch {
+        submission: usize,
+        field: &'static str,
+        expected: String,
+        found: String,
+    },
+    SuspiciousRecipient {
+        recipient: Pubkey,
+        owner: Pubkey,
+        kind: SuspiciousRecipient,
+    },
+    InvalidParty(String),
+    /// An `amount` sent as a JSON float while `strict_amounts` is on
+    FloatAmount {
+        field: String,
+        amount: f64,
+    },
+    /// A message that would write to an account the writable policy keeps it from
+    PolicyViolation {
+        account: Pubkey,
+        rule: PolicyRule,
+    },
+    /// A request without `net` while no `default_network` is configured
+    MissingNetwork,
+    /// A payload to sign that isn't base64 or can't be verified on chain
+    InvalidPayload(String),
+    /// A payload that is a transaction message the aggregated key signs
+    PayloadIsMessage(Pubkey),
+    /// An instruction needing a signature no party of the transaction gives
+    UnexpectedSigner(Pubkey),
+    InvalidChaosConfig(String),
+    /// Everything wrong with a config that was reloaded, the running one stays active
+    ConfigReloadFailed(Vec<String>),
+    /// The RPC node answered the method with "method not found"
+    RpcMethodUnsupported(RpcRequest),
+    /// Bytes sent to `/api/broadcast` that aren't exactly one transaction
+    MalformedTransaction(String),
+    /// A transaction sent to `/api/broadcast` without a valid signature of `signer`
+    TransactionSignatureInvalid {
+        signer: Pubkey,
+        missing: bool,
+    },
+    /// The durable nonce a transfer was signed with isn't the one its nonce account holds
+    /// anymore, the transaction can never land
+    NonceAdvanced {
+        nonce_account: Pubkey,
+        expected: Hash,
+        found: Hash,
+    },
+    /// `nonce_account` can't carry the transfer's durable nonce
+    InvalidNonceAccount {
+        nonce_account: Pubkey,
+        reason: String,
+    },
+    /// A threshold, or a set of parties signing with a threshold key, that doesn't fit the key
+    InvalidThreshold(String),
+    /// A message of `party` that fails verification during a threshold keygen
+    ThresholdKeygenFailed {
+        party: Pubkey,
+        reason: String,
+    },
+    /// Sent, but the confirmation policy ended without it confirming
+    NotConfirmed {
+        signature: Signature,
+        outcome: ConfirmationOutcome,
+    },
+    /// The client sent more requests than `rate_limits` allows, a token is back after
+    /// `retry_after_seconds`
+    RateLimited {
+        retry_after_seconds: u64,
+    },
+    /// Fields of a request that don't fit together, such as two that exclude each other
+    InvalidRequest(String),
+    /// A bug in the server, such as a response that can't be serialized
+    Internal(String),
+}
+
+impl Error {
+    /// The error of an RPC call the node doesn't serve as `RpcMethodUnsupported`, anything
+    /// else unchanged.
+    pub fn or_unsupported(self) -> Self {
+        let method = match &self {
+            Self::AirdropFailed(e)
+            | Self::RecentHashFailed(e)
+            | Self::ConfirmingTransactionFailed(e)
+            | Self::BalaceFailed(e)
+            | Self::SendTransactionFailed(e)
+            | Self::TokenAccountScanFailed(e)
+            | Self::AccountFetchFailed(e)
+            | Self::SignatureStatusFailed(e)
+            | Self::SlotFetchFailed(e)
+            | Self::SimulationUnavailable(e)
+            | Self::FeeUnavailable(e) => unsupported_method(e),
+            _ => None,
+        };
+        match method {
+            Some(method) => Self::RpcMethodUnsupported(method),
+            None => self,
+        }
+    }
+
+    /// The class of the error, every variant is assigned one explicitly so a new one can't
+    /// end up retryable by accident.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::AirdropFailed(e)
+            | Self::RecentHashFailed(e)
+            | Self::ConfirmingTransactionFailed(e)
+            | Self::BalaceFailed(e)
+            | Self::SendTransactionFailed(e)
+            | Self::TokenAccountScanFailed(e)
+            | Self::AccountFetchFailed(e)
+            | Self::SignatureStatusFailed(e)
+            | Self::SlotFetchFailed(e)
+            | Self::SimulationUnavailable(e)
+            | Self::FeeUnavailable(e) => rpc_error_class(e),
+            Self::NotConfirmed { outcome, .. } => match outcome {
+                ConfirmationOutcome::TimedOut => ErrorClass::Timeout,
+                // A new blockhash means a new message to sign
+                ConfirmationOutcome::Expired => ErrorClass::Protocol,
+                ConfirmationOutcome::Failed { .. } | ConfirmationOutcome::Confirmed { .. } => {
+                    ErrorClass::Transaction
+                }
+            },
+            // Some transactions went out already, sending the request again would repeat them
+            Self::SplitIncomplete { .. } => ErrorClass::Transaction,
+            Self::SimulationFailed(_) => ErrorClass::Transaction,
+            // The string of an RPC error, whether it was transient isn't known anymore
+            Self::StakeAccountCreationFailed(_) | Self::RpcMethodUnsupported(_) => ErrorClass::Node,
+            // A node behind the one that handed out the blockhash, another may know it
+            Self::BlockhashNotFound(_) => ErrorClass::Transport,
+            Self::MismatchMessages
+            | Self::InvalidSignature
+            | Self::KeyPairIsNotInKeys
+            | Self::SignerNotInKeySet { .. }
+            | Self::MessageHashMismatch { .. }
+            | Self::EnvironmentMismatch { .. }
+            | Self::SignatureContextMismatch { .. }
+            | Self::RentSponsorSignatureMissing(_)
+            | Self::NonceAdvanced { .. }
+            | Self::ThresholdKeygenFailed { .. }
+            | Self::SecretStateNotFound
+            | Self::PayloadIsMessage(_) => ErrorClass::Protocol,
+            Self::TokenAccountNotFound
+            | Self::TokenMintNotFound
+            | Self::InsufficientBalance(_)
+            | Self::BalanceCheckFailed(_)
+            | Self::StalePlan(_)
+            | Self::FaucetLimitExceeded(_)
+            | Self::ScheduledBroadcastNotFound(_)
+            | Self::AuditEntryNotFound(_)
+            | Self::SourceAccountNotFound(_)
+            | Self::SourceMintMismatch { .. }
+            | Self::SourceOwnerMismatch { .. }
+            | Self::SourceAccountFrozen(_)
+            | Self::NativeReserve { .. }
+            | Self::AirdropUnavailable(_)
+            | Self::AdminOnly(_)
+            | Self::Unauthorized { .. }
+            | Self::RpcUrlNotAllowed(_)
+            | Self::SpendLimitExceeded { .. }
+            | Self::TokenLimitExceeded { .. }
+            | Self::AtaCreationNotAllowed { .. }
+            | Self::AggAccountUnfunded { .. }
+            | Self::InvalidNonceAccount { .. }
+            | Self::SuspiciousRecipient { .. }
+            | Self::UnsupportedMintExtension { .. }
+            | Self::PolicyViolation { .. } => ErrorClass::Refused,
+            Self::WrongNetwork(_)
+            | Self::BadBase58(_)
+            | Self::WrongKeyPair(_)
+            | Self::InvalidKeypair(_)
+            | Self::DeserializationFailed { .. }
+            | Self::TransactionCreationFailed(_)
+            | Self::SplTokenError(_)
+            | Self::ProgramError(_)
+            | Self::InvalidStakeAccountSeed(_)
+            | Self::StakeDelegationFailed(_)
+            | Self::DeactivationFailed(_)
+            | Self::WithdrawalFailed(_)
+            | Self::InvalidPublicKey(_)
+            | Self::InvalidBlockHash(_)
+            | Self::InteriorWhitespace(_)
+            | Self::InvalidAmount(_)
+            | Self::AmountRejected { .. }
+            | Self::BodyNotUtf8(_)
+            | Self::InvalidComputeUnitLimit(_)
+            | Self::InvalidMemoProgram(_)
+            | Self::InvalidTransactionId(_)
+            | Self::InvalidBroadcastAt(_)
+            | Self::InvalidExportRange(_)
+            | Self::InvalidSolanaPay(_)
+            | Self::InvalidMnemonic(_)
+            | Self::InvalidDerivationPath(_)
+            | Self::InvalidRpcUrl(_)
+            | Self::MalformedTransaction(_)
+            | Self::TransactionSignatureInvalid { .. }
+            | Self::TooManyParties { .. }
+            | Self::TooManySignatures { .. }
+            | Self::TooManyRecipients { .. }
+            | Self::TransactionTooLarge { .. }
+            | Self::TooFewKeys(_)
+            | Self::InvalidToken(_)
+            | Self::UnknownToken { .. }
+            | Self::TokenDeleted { .. }
+            | Self::InvalidParty(_)
+            | Self::InvalidThreshold(_)
+            | Self::FloatAmount { .. }
+            | Self::MissingNetwork
+            | Self::InvalidPayload(_)
+            | Self::UnexpectedSigner(_)
+            | Self::InvalidChaosConfig(_)
+            | Self::InvalidRequest(_)
+            | Self::ConfigReloadFailed(_) => ErrorClass::Input,
+            Self::RateLimited { .. } => ErrorClass::RateLimited,
+            Self::Internal(_) => ErrorClass::Internal,
+        }
+    }
+
+    /// Whether the same request may succeed when sent again, see `ErrorClass::retryable`.
+    pub fn retryable(&self) -> bool {
+        self.class().retryable()
+    }
+
+    /// Stable code for `ErrorResponse::error_code`, every variant has one so clients never
+    /// need to match on the message.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::AirdropFailed(e)
+            | Self::RecentHashFailed(e)
+            | Self::ConfirmingTransactionFailed(e)
+            | Self::BalaceFailed(e)
+            | Self::SendTransactionFailed(e)
+            | Self::TokenAccountScanFailed(e)
+            | Self::AccountFetchFailed(e)
+            | Self::SignatureStatusFailed(e)
+            | Self::SlotFetchFailed(e) => rpc_error_code(e),
+            Self::WrongNetwork(_) => "INVALID_NETWORK",
+            Self::BadBase58(_) => "INVALID_BASE58",
+            Self::WrongKeyPair(_) => "INVALID_KEYPAIR",
+            Self::DeserializationFailed { .. } => "DESERIALIZATION_FAILED",
+            Self::MismatchMessages => "MISMATCHED_MESSAGES",
+            Self::InvalidSignature => "INVALID_SIGNATURE",
+            Self::KeyPairIsNotInKeys => "KEYPAIR_NOT_IN_KEYS",
+            Self::TransactionCreationFailed(_) => "TRANSACTION_CREATION_FAILED",
+            Self::SplTokenError(_) => "SPL_TOKEN_ERROR",
+            Self::TokenAccountNotFound => "TOKEN_ACCOUNT_NOT_FOUND",
+            Self::TokenMintNotFound => "TOKEN_MINT_NOT_FOUND",
+            Self::ProgramError(_) => "PROGRAM_ERROR",
+            Self::StakeAccountCreationFailed(_) => "STAKE_ACCOUNT_CREATION_FAILED",
+            Self::InvalidStakeAccountSeed(_) => "INVALID_STAKE_SEED",
+            Self::StakeDelegationFailed(_) => "STAKE_DELEGATION_FAILED",
+            Self::DeactivationFailed(_) => "STAKE_DEACTIVATION_FAILED",
+            Self::WithdrawalFailed(_) => "STAKE_WITHDRAWAL_FAILED",
+            Self::InvalidPublicKey(_) => "INVALID_PUBKEY",
+            Self::InvalidBlockHash(_) => "INVALID_BLOCKHASH",
+            Self::InteriorWhitespace(_) => "INTERIOR_WHITESPACE",
+            Self::InsufficientBalance(_) => "INSUFFICIENT_FUNDS",
+            Self::BalanceCheckFailed(_) => "BALANCE_CHECK_FAILED",
+            Self::InvalidAmount(_) => "INVALID_AMOUNT",
+            Self::StalePlan(_) => "STALE_PLAN",
+            Self::SimulationFailed(_) => "SIMULATION_FAILED",
+            Self::InvalidComputeUnitLimit(_) => "INVALID_COMPUTE_UNIT_LIMIT",
+            Self::InvalidMemoProgram(_) => "INVALID_MEMO_PROGRAM",
+            Self::FaucetLimitExceeded(_) => "FAUCET_LIMIT_EXCEEDED",
+            Self::InvalidTransactionId(_) => "INVALID_TRANSACTION_ID",
+            Self::InvalidRequest(_) => "INVALID_REQUEST",
+            Self::RateLimited { .. } => "RATE_LIMITED",
+            Self::Internal(_) => "INTERNAL_ERROR",
+            Self::SourceAccountNotFound(_) => "SOURCE_ACCOUNT_NOT_FOUND",
+            Self::SourceMintMismatch { .. } => "SOURCE_MINT_MISMATCH",
+            Self::SourceOwnerMismatch { .. } => "SOURCE_OWNER_MISMATCH",
+            Self::SourceAccountFrozen(_) => "SOURCE_ACCOUNT_FROZEN",
+            Self::NativeReserve { .. } => "WRAPPED_SOL_RESERVE",
+            Self::UnsupportedMintExtension { .. } => "UNSUPPORTED_MINT_EXTENSION",
+            Self::TooManyParties { .. } => "TOO_MANY_PARTIES",
+            Self::TooManySignatures { .. } => "TOO_MANY_SIGNATURES",
+            Self::TooManyRecipients { .. } => "TOO_MANY_RECIPIENTS",
+            Self::TransactionTooLarge { .. } => "TRANSACTION_TOO_LARGE",
+            Self::SplitIncomplete { .. } => "SPLIT_INCOMPLETE",
+            Self::AirdropUnavailable(_) => "AIRDROP_UNAVAILABLE",
+            Self::TooFewKeys(_) => "MIN_TWO_KEYS",
+            Self::AdminOnly(_) => "ADMIN_ONLY",
+            Self::Unauthorized { .. } => "UNAUTHORIZED",
+            Self::InvalidBroadcastAt(_) => "INVALID_BROADCAST_AT",
+            Self::ScheduledBroadcastNotFound(_) => "SCHEDULED_BROADCAST_NOT_FOUND",
+            Self::AuditEntryNotFound(_) => "AUDIT_ENTRY_NOT_FOUND",
+            Self::SecretStateNotFound => "SECRET_STATE_NOT_FOUND",
+            Self::InvalidExportRange(_) => "INVALID_EXPORT_RANGE",
+            Self::InvalidSolanaPay(_) => "INVALID_SOLANA_PAY",
+            Self::InvalidMnemonic(_) => "INVALID_MNEMONIC",
+            Self::InvalidKeypair(_) => "INVALID_KEYPAIR",
+            Self::InvalidDerivationPath(_) => "INVALID_DERIVATION_PATH",
+            Self::InvalidRpcUrl(_) => "INVALID_RPC_URL",
+            Self::MalformedTransaction(_) => "MALFORMED_TRANSACTION",
+            Self::TransactionSignatureInvalid { .. } => "TRANSACTION_SIGNATURE_INVALID",
+            Self::AmountRejected { .. } => "INVALID_AMOUNT",
+            Self::SimulationUnavailable(_) => "SIMULATION_UNAVAILABLE",
+            Self::FeeUnavailable(_) => "FEE_UNAVAILABLE",
+            Self::BlockhashNotFound(_) => "BLOCKHASH_NOT_FOUND",
+            Self::RpcUrlNotAllowed(_) => "RPC_URL_NOT_ALLOWED",
+            Self::SpendLimitExceeded { .. } => "SPEND_LIMIT_EXCEEDED",
+            Self::InvalidToken(_) => "INVALID_TOKEN",
+            Self::UnknownToken { .. } => "UNKNOWN_TOKEN",
+            Self::TokenDeleted { .. } => "TOKEN_DELETED",
+            Self::TokenLimitExceeded { .. } => "TOKEN_LIMIT_EXCEEDED",
+            Self::AtaCreationNotAllowed { .. } => "ATA_CREATION_NOT_ALLOWED",
+            Self::MessageHashMismatch { .. } => "MESSAGE_HASH_MISMATCH",
+            Self::SignerNotInKeySet { .. } => "SIGNER_NOT_IN_KEY_SET",
+            Self::AggAccountUnfunded { .. } => "AGG_ACCOUNT_UNFUNDED",
+            Self::NonceAdvanced { .. } => "NONCE_ADVANCED",
+            Self::InvalidNonceAccount { .. } => "INVALID_NONCE_ACCOUNT",
+            Self::RentSponsorSignatureMissing(_) => "RENT_SPONSOR_SIGNATURE_MISSING",
+            Self::EnvironmentMismatch { .. } => "ENVIRONMENT_MISMATCH",
+            Self::SignatureContextMismatch { .. } => "SIGNATURE_CONTEXT_MISMATCH",
+            Self::SuspiciousRecipient { .. } => "SUSPICIOUS_RECIPIENT",
+            Self::InvalidParty(_) => "INVALID_PARTY",
+            Self::InvalidThreshold(_) => "INVALID_THRESHOLD",
+            Self::ThresholdKeygenFailed { .. } => "THRESHOLD_KEYGEN_FAILED",
+            Self::BodyNotUtf8(_) => "INVALID_UTF8",
+            Self::FloatAmount { .. } => "FLOAT_AMOUNT",
+            Self::MissingNetwork => "MISSING_NETWORK",
+            Self::InvalidPayload(_) => "INVALID_PAYLOAD",
+            Self::PayloadIsMessage(_) => "PAYLOAD_IS_MESSAGE",
+            Self::UnexpectedSigner(_) => "UNEXPECTED_SIGNER",
+            Self::PolicyViolation { .. } => "POLICY_VIOLATION",
+            Self::InvalidChaosConfig(_) => "INVALID_CHAOS_CONFIG",
+            Self::ConfigReloadFailed(_) => "CONFIG_RELOAD_FAILED",
+            Self::RpcMethodUnsupported(_) => "RPC_METHOD_UNSUPPORTED",
+            Self::NotConfirmed { outcome, .. } => match outcome {
+                ConfirmationOutcome::Failed { .. } => "TRANSACTION_FAILED",
+                ConfirmationOutcome::Expired => "TRANSACTION_EXPIRED",
+                ConfirmationOutcome::TimedOut => "CONFIRMATION_TIMED_OUT",
+                ConfirmationOutcome::Confirmed { .. } => "NOT_CONFIRMED",
+            },
+        }
+    }
+
+    /// Status the error is reported with: 502 or 503 when the RPC node failed the call or
+    /// couldn't be reached, 404 for accounts that don't exist, 500 for bugs of the server and
+    /// a plain 400 for anything not listed.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::AirdropFailed(e)
+            | Self::RecentHashFailed(e)
+            | Self::ConfirmingTransactionFailed(e)
+            | Self::BalaceFailed(e)
+            | Self::SendTransactionFailed(e)
+            | Self::TokenAccountScanFailed(e)
+            | Self::AccountFetchFailed(e)
+            | Self::SignatureStatusFailed(e)
+            | Self::SlotFetchFailed(e) => match rpc_error_class(e) {
+                ErrorClass::Transport | ErrorClass::RateLimited => StatusCode::SERVICE_UNAVAILABLE,
+                ErrorClass::Node => StatusCode::BAD_GATEWAY,
+                // The transaction itself was refused
+                _ => StatusCode::BAD_REQUEST,
+            },
+            Self::TooManyParties { .. }
+            | Self::TooManySignatures { .. }
+            | Self::TooManyRecipients { .. }
+            | Self::TransactionTooLarge { .. }
+            | Self::TooFewKeys(_)
+            | Self::UnknownToken { .. }
+            | Self::TokenDeleted { .. }
+            | Self::TokenLimitExceeded { .. }
+            | Self::AtaCreationNotAllowed { .. }
+            | Self::SignerNotInKeySet { .. }
+            | Self::AggAccountUnfunded { .. }
+            | Self::RentSponsorSignatureMissing(_)
+            | Self::SuspiciousRecipient { .. }
+            | Self::NativeReserve { .. }
+            | Self::UnsupportedMintExtension { .. }
+            | Self::ConfigReloadFailed(_)
+            | Self::FloatAmount { .. }
+            | Self::MissingNetwork
+            | Self::InvalidPayload(_)
+            | Self::PayloadIsMessage(_)
+            | Self::UnexpectedSigner(_)
+            | Self::TransactionSignatureInvalid { .. }
+            | Self::InvalidNonceAccount { .. }
+            | Self::InvalidThreshold(_)
+            | Self::ThresholdKeygenFailed { .. }
+            | Self::InvalidParty(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::SpendLimitExceeded { .. }
+            | Self::AdminOnly(_)
+            | Self::RpcUrlNotAllowed(_)
+            | Self::PolicyViolation { .. } => StatusCode::FORBIDDEN,
+            Self::MessageHashMismatch { .. }
+            | Self::EnvironmentMismatch { .. }
+            | Self::SignatureContextMismatch { .. }
+            | Self::NonceAdvanced { .. } => StatusCode::CONFLICT,
+            Self::ScheduledBroadcastNotFound(_)
+            | Self::AuditEntryNotFound(_)
+            | Self::SecretStateNotFound
+            | Self::TokenAccountNotFound
+            | Self::TokenMintNotFound
+            | Self::SourceAccountNotFound(_) => StatusCode::NOT_FOUND,
+            Self::RpcMethodUnsupported(_)
+            | Self::StakeAccountCreationFailed(_)
+            | Self::SimulationUnavailable(_)
+            | Self::FeeUnavailable(_)
+            | Self::BlockhashNotFound(_) => StatusCode::BAD_GATEWAY,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            Self::SplitIncomplete { error, .. } => error.status(),
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Structured fields for `ErrorResponse::details`: the offending field and value where the
+    /// error knows them, and what clients act on.
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::WrongNetwork(net) => Some(serde_json::json!({
+                "field": "net",
+                "value": net,
+                "accepted": Network::ALL.iter().map(Network::as_str).collect::<Vec<_>>(),
+            })),
+            Self::DeserializationFailed { field_name, .. } => {
+                Some(serde_json::json!({ "field": field_name }))
+            }
+            Self::InvalidPublicKey(value) | Self::InvalidBlockHash(value) => {
+                Some(serde_json::json!({ "value": value }))
+            }
+            Self::InteriorWhitespace(e) => Some(serde_json::json!({
+                "index": e.index,
+                "prefix": e.prefix,
+            })),
+            Self::TooManyParties { field, len, max } => Some(serde_json::json!({
+                "field": field,
+                "len": len,
+                "max": max,
+            })),
+            Self::TooManySignatures { len, max } => Some(serde_json::json!({
+                "field": "signatures",
+                "len": len,
+                "max": max,
+            })),
+            Self::TooManyRecipients { len, max } => Some(serde_json::json!({
+                "field": "recipients",
+                "len": len,
+                "max": max,
+            })),
+            Self::TooFewKeys(len) => Some(serde_json::json!({
+                "field": "keys",
+                "len": len,
+                "min": MIN_KEYS,
+            })),
+            Self::FloatAmount { field, amount } => Some(serde_json::json!({
+                "field": field,
+                "value": amount,
+            })),
+            Self::EnvironmentMismatch {
+                field,
+                expected,
+                found,
+            } => Some(serde_json::json!({
+                "field": field,
+                "expected": expected,
+                "found": found,
+            })),
+            Self::SourceAccountNotFound(account) | Self::SourceAccountFrozen(account) => {
+                Some(serde_json::json!({ "account": account.to_string() }))
+            }
+            Self::UnsupportedMintExtension { mint, extension } => Some(serde_json::json!({
+                "mint": mint.to_string(),
+                "extension": extension,
+            })),
+            Self::SignerNotInKeySet { signer, .. } => Some(serde_json::json!({
+                "signer": signer.to_string(),
+            })),
+            Self::UnexpectedSigner(signer) => Some(serde_json::json!({
+                "signer": signer.to_string(),
+            })),
+            Self::UnknownToken { symbol, known } => Some(serde_json::json!({
+                "field": "token",
+                "value": symbol,
+                "known": known,
+            })),
+            Self::TokenDeleted { symbol, purge_at } => Some(serde_json::json!({
+                "field": "token",
+                "value": symbol,
+                "purge_at": purge_at,
+            })),
+            Self::SpendLimitExceeded {
+                scope,
+                asset,
+                limit,
+                remaining,
+            } => Some(serde_json::json!({
+                "scope": scope,
+                "asset": asset,
+                "limit": limit,
+                "remaining": remaining,
+            })),
+            Self::TransactionTooLarge {
+                oversize,
+                splittable,
+            } => Some(serde_json::json!({
+                "size": oversize.size,
+                "size_without_memo": oversize.size_without_memo,
+                "limit": oversize.limit,
+                "max_recipients": oversize.max_recipients,
+                "splittable": splittable,
+            })),
+            Self::SplitIncomplete { sent, total, error } => Some(serde_json::json!({
+                "sent": sent.iter().map(Signature::to_string).collect::<Vec<_>>(),
+                "total": total,
+                "error_code": error.error_code(),
+            })),
+            Self::PolicyViolation { account, rule } => Some(serde_json::json!({
+                "account": account.to_string(),
+                "rule": rule.as_str(),
+            })),
+            Self::SignatureContextMismatch {
+                submission,
+                field,
+                expected,
+                found,
+            } => Some(serde_json::json!({
+                "submission": submission,
+                "field": field,
+                "expected": expected,
+                "found": found,
+            })),
+            Self::AmountRejected { field, .. } => Some(serde_json::json!({ "field": field })),
+            Self::TransactionSignatureInvalid { signer, missing } => Some(serde_json::json!({
+                "signer": signer.to_string(),
+                "missing": missing,
+            })),
+            Self::NonceAdvanced {
+                nonce_account,
+                expected,
+                found,
+            } => Some(serde_json::json!({
+                "nonce_account": nonce_account.to_string(),
+                "expected": expected.to_string(),
+                "found": found.to_string(),
+            })),
+            Self::InvalidNonceAccount { nonce_account, .. } => Some(serde_json::json!({
+                "nonce_account": nonce_account.to_string(),
+            })),
+            Self::ThresholdKeygenFailed { party, .. } => Some(serde_json::json!({
+                "party": party.to_string(),
+            })),
+            Self::RateLimited {
+                retry_after_seconds,
+            } => Some(serde_json::json!({ "retry_after_seconds": retry_after_seconds })),
+            Self::Unauthorized {
+                clock_skew_ms: Some(clock_skew_ms),
+                ..
+            } => Some(serde_json::json!({ "clock_skew_ms": clock_skew_ms })),
+            Self::MissingNetwork => Some(serde_json::json!({
+                "field": "net",
+                "accepted": Network::ALL.iter().map(Network::as_str).collect::<Vec<_>>(),
+            })),
+            _ => None,
+        }
+    }
 }
 
 impl Display for Error {
@@ -49,6 +845,7 @@ impl Display for Error {
             ),
             Self::BadBase58(e) => write!(f, "Based58 Error: {}", e),
             Self::WrongKeyPair(e) => write!(f, "Failed deserializing keypair: {}", e),
+            Self::InvalidKeypair(e) => write!(f, "invalid keypair: {}", e),
             Self::AirdropFailed(e) => write!(f, "Failed asking for an airdrop: {}", e),
             Self::RecentHashFailed(e) => write!(f, "Failed recieving the latest hash: {}", e),
             Self::ConfirmingTransactionFailed(e) => {
@@ -85,8 +882,398 @@ impl Display for Error {
             Self::DeactivationFailed(e) => write!(f, "Failed to deactivate stake: {}", e),
             Self::WithdrawalFailed(e) => write!(f, "Failed to withdraw stake: {}", e),
             Self::InvalidPublicKey(e) => write!(f, "invalid public key: {}", e),
+            Self::InvalidBlockHash(e) => write!(f, "invalid block hash: {}", e),
+            Self::InteriorWhitespace(e) => write!(f, "{}", e),
             Self::InsufficientBalance(e) => write!(f, "insufficient balance: {}", e),
             Self::BalanceCheckFailed(e) => write!(f, " balance check fail: {}", e),
+            Self::InvalidAmount(e) => write!(f, "invalid amount: {}", e),
+            Self::AmountRejected { field, reason } => write!(f, "invalid {}: {}", field, reason),
+            Self::BodyNotUtf8(offset) => write!(
+                f,
+                "the request body isn't valid UTF-8, the first invalid byte is at offset {}; \
+                 memos and other text must be sent as UTF-8",
+                offset
+            ),
+            Self::InvalidTransactionId(e) => write!(f, "invalid transaction id: {}", e),
+            Self::TokenAccountScanFailed(e) => write!(f, "Failed scanning token accounts: {}", e),
+            Self::StalePlan(id) => write!(
+                f,
+                "Chunk {} is not part of the current plan, request a new spl_rent_summary",
+                id
+            ),
+            Self::SimulationFailed(e) => write!(f, "Transaction simulation failed: {}", e),
+            Self::InvalidComputeUnitLimit(e) => write!(f, "invalid compute_unit_limit: {}", e),
+            Self::InvalidMemoProgram(e) => write!(
+                f,
+                "invalid memo_program: {} is not a known memo program, custom ones need --allow-custom-memo-program",
+                e
+            ),
+            Self::FaucetLimitExceeded(e) => write!(f, "internal faucet limit reached: {}", e),
+            Self::AccountFetchFailed(e) => write!(f, "Failed fetching account: {}", e),
+            Self::SignatureStatusFailed(e) => {
+                write!(f, "Failed fetching signature statuses: {}", e)
+            }
+            Self::SlotFetchFailed(e) => write!(f, "Failed fetching the current slot: {}", e),
+            Self::SimulationUnavailable(e) => {
+                write!(f, "the RPC node couldn't simulate the transaction: {}", e)
+            }
+            Self::FeeUnavailable(e) => write!(f, "the RPC node couldn't price the message: {}", e),
+            Self::NonceAdvanced {
+                nonce_account,
+                expected,
+                found,
+            } => write!(
+                f,
+                "nonce account {} holds the nonce {} now, not {} the transaction was signed with: \
+                 it was advanced, sign again with the current one",
+                nonce_account, found, expected
+            ),
+            Self::InvalidNonceAccount {
+                nonce_account,
+                reason,
+            } => write!(f, "invalid nonce account {}: {}", nonce_account, reason),
+            Self::BlockhashNotFound(hash) => write!(
+                f,
+                "the RPC node doesn't know its own latest blockhash {} yet, it can't price the \
+                 message",
+                hash
+            ),
+            Self::InvalidBroadcastAt(e) => write!(f, "invalid broadcast_at: {}", e),
+            Self::ScheduledBroadcastNotFound(e) => {
+                write!(f, "no scheduled broadcast {} is waiting", e)
+            }
+            Self::SecretStateNotFound => write!(
+                f,
+                "no secret state kept for this signer under secret_state_id, it was used, \
+                 expired or never kept; start again with step one"
+            ),
+            Self::AuditEntryNotFound(id) => {
+                write!(f, "no audit entry {}, it may have been evicted", id)
+            }
+            Self::InvalidExportRange(e) => write!(f, "invalid audit export: {}", e),
+            Self::InvalidSolanaPay(e) => write!(f, "invalid Solana Pay request: {}", e),
+            Self::InvalidMnemonic(e) => write!(f, "invalid mnemonic: {}", e),
+            Self::InvalidDerivationPath(e) => write!(f, "invalid derivation path: {}", e),
+            Self::InvalidRpcUrl(e) => write!(f, "rpc_url: {}", e),
+            Self::MalformedTransaction(e) => write!(f, "malformed transaction: {}", e),
+            Self::TransactionSignatureInvalid {
+                signer,
+                missing: true,
+            } => write!(f, "the transaction is missing the signature of {}", signer),
+            Self::TransactionSignatureInvalid {
+                signer,
+                missing: false,
+            } => write!(
+                f,
+                "the signature of {} doesn't verify against the transaction's message",
+                signer
+            ),
+            Self::RpcUrlNotAllowed(host) => write!(
+                f,
+                "rpc_url names host {:?}, which isn't in request_rpc_url_hosts",
+                host
+            ),
+            Self::SourceAccountNotFound(account) => {
+                write!(f, "source token account {} doesn't exist", account)
+            }
+            Self::SourceMintMismatch {
+                account,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "source token account {} holds mint {}, not {}",
+                account, actual, expected
+            ),
+            Self::SourceOwnerMismatch {
+                account,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "source token account {} is owned by {}, not the signer {}",
+                account, actual, expected
+            ),
+            Self::SourceAccountFrozen(account) => {
+                write!(f, "source token account {} is frozen", account)
+            }
+            Self::UnsupportedMintExtension { mint, extension } => write!(
+                f,
+                "mint {} has the Token-2022 {} extension, transfers of it aren't supported",
+                mint, extension
+            ),
+            Self::NativeReserve {
+                account,
+                requested,
+                spendable,
+                unsynced,
+            } => {
+                write!(
+                    f,
+                    "wrapped SOL account {} can send {} lamports, not {}: the rest is its \
+                     rent-exempt reserve",
+                    account, spendable, requested
+                )?;
+                if *unsynced > 0 {
+                    write!(
+                        f,
+                        ", and {} lamports sent to it count only after a SyncNative instruction",
+                        unsynced
+                    )?;
+                }
+                Ok(())
+            }
+            Self::TooManyParties { field, len, max } => write!(
+                f,
+                "{} has {} entries, at most {} parties are allowed",
+                field, len, max
+            ),
+            Self::TooFewKeys(len) => write!(
+                f,
+                "keys has {} entries, a TSS signature needs at least {} parties",
+                len, MIN_KEYS
+            ),
+            Self::AdminOnly(endpoint) => write!(f, "{} needs an admin API key", endpoint),
+            Self::Unauthorized {
+                key_sent: false, ..
+            } => write!(
+                f,
+                "an API key is required, send it as Authorization: Bearer <key> or X-Api-Key"
+            ),
+            Self::Unauthorized { key_sent: true, .. } => write!(f, "the API key isn't known"),
+            Self::TooManySignatures { len, max } => write!(
+                f,
+                "signatures has {} entries, at most {} are allowed",
+                len, max
+            ),
+            Self::TooManyRecipients { len, max } => write!(
+                f,
+                "recipients has {} entries, at most {} are allowed",
+                len, max
+            ),
+            Self::TransactionTooLarge {
+                oversize,
+                splittable,
+            } => {
+                write!(
+                    f,
+                    "the transaction would be {} bytes, {} without the memo, but at most {} fit; \
+                     with this memo {} recipients fit into one transaction",
+                    oversize.size,
+                    oversize.size_without_memo,
+                    oversize.limit,
+                    oversize.max_recipients
+                )?;
+                match splittable {
+                    true => write!(f, ", pass auto_split: true to send several"),
+                    false => write!(
+                        f,
+                        ", TSS transfers are never split since every message needs its own \
+                         signing ceremony"
+                    ),
+                }
+            }
+            Self::SplitIncomplete { sent, total, error } => write!(
+                f,
+                "{} of {} transactions were sent before the next one failed: {}",
+                sent.len(),
+                total,
+                error
+            ),
+            Self::AirdropUnavailable(net) => {
+                write!(
+                    f,
+                    "there are no airdrops on {}, only devnet and testnet",
+                    net
+                )
+            }
+            Self::SpendLimitExceeded {
+                scope,
+                asset,
+                limit,
+                remaining,
+            } => write!(
+                f,
+                "{} spend limit for {} exceeded, {} of {} {} left in the last 24 hours",
+                scope,
+                asset,
+                remaining,
+                limit,
+                if asset == "SOL" {
+                    "lamports"
+                } else {
+                    "base units"
+                }
+            ),
+            Self::InvalidToken(e) => write!(f, "invalid token: {}", e),
+            Self::UnknownToken { symbol, known } if known.is_empty() => {
+                write!(f, "unknown token {}, no tokens are registered", symbol)
+            }
+            Self::UnknownToken { symbol, known } => write!(
+                f,
+                "unknown token {}, known tokens are {}",
+                symbol,
+                known.join(", ")
+            ),
+            Self::TokenDeleted { symbol, .. } => write!(
+                f,
+                "token {} was deleted, restore it through /api/restore_token to use it",
+                symbol
+            ),
+            Self::TokenLimitExceeded { token, amount, max } => write!(
+                f,
+                "amount {} of {} is above its limit of {} per transaction",
+                amount, token, max
+            ),
+            Self::AtaCreationNotAllowed { token, account } => write!(
+                f,
+                "recipient token account {} doesn't exist and {} doesn't allow creating it",
+                account, token
+            ),
+            Self::MessageHashMismatch { expected, actual } => write!(
+                f,
+                "the message to sign hashes to {}, not the expected_message_hash {}",
+                actual, expected
+            ),
+            Self::SignerNotInKeySet { signer, keys } => write!(
+                f,
+                "keypair {} is not one of the keys [{}]",
+                signer,
+                keys.iter()
+                    .map(|k| k.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::EnvironmentMismatch {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} come from the {} environment, this server is {}",
+                field,
+                found.as_deref().unwrap_or("unlabeled"),
+                expected
+            ),
+            Self::SignatureContextMismatch {
+                submission,
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "signatures[{}] was made for {} {}, expected {}",
+                submission, field, found, expected
+            ),
+            Self::FloatAmount { field, amount } => write!(
+                f,
+                "{} is the float {}, this server refuses float amounts; send it as a decimal \
+                 string such as \"{}\" or as an integer, or SOL as lamports",
+                field, amount, amount
+            ),
+            Self::RentSponsorSignatureMissing(sponsor) => write!(
+                f,
+                "rent sponsor {} pays for the recipient token account and must sign, pass its \
+                 signature of the message",
+                sponsor
+            ),
+            Self::AggAccountUnfunded {
+                address,
+                minimum_lamports,
+            } => write!(
+                f,
+                "aggregated account {} has never been funded, send it at least {} lamports \
+                 (fee, amount and rent) first",
+                address, minimum_lamports
+            ),
+            Self::SuspiciousRecipient {
+                recipient,
+                owner,
+                kind,
+            } => write!(
+                f,
+                "recipient {} {} (owner {}), SOL sent to it can't be moved out again; pass \
+                 allow_non_system_recipient: true to send anyway",
+                recipient,
+                kind.describe(),
+                owner
+            ),
+            Self::InvalidParty(e) => write!(f, "invalid party: {}", e),
+            Self::InvalidThreshold(e) => write!(f, "invalid threshold: {}", e),
+            Self::ThresholdKeygenFailed { party, reason } => {
+                write!(f, "threshold keygen failed, {} sent {}", party, reason)
+            }
+            Self::PolicyViolation { account, rule } => match rule {
+                PolicyRule::Deny => write!(
+                    f,
+                    "the message writes to {}, which the writable policy denies",
+                    account
+                ),
+                PolicyRule::Allow => write!(
+                    f,
+                    "the message writes to {}, which isn't on the writable policy's allow list",
+                    account
+                ),
+            },
+            Self::MissingNetwork => write!(
+                f,
+                "net is missing and this server has no default_network, pass one of {}",
+                Network::ALL.map(|net| net.as_str()).join(", ")
+            ),
+            Self::InvalidPayload(e) => write!(f, "invalid payload: {}", e),
+            Self::PayloadIsMessage(signer) => write!(
+                f,
+                "the payload is a transaction message signed by {}, transactions are only \
+                 signed through their own step two",
+                signer
+            ),
+            Self::UnexpectedSigner(signer) => write!(
+                f,
+                "the instruction needs a signature of {}, only the fee payer signs this \
+                 transaction",
+                signer
+            ),
+            Self::InvalidChaosConfig(e) => write!(f, "invalid chaos config: {}", e),
+            Self::ConfigReloadFailed(errors) => write!(
+                f,
+                "config not reloaded, the running config stays active: {}",
+                errors.join("; ")
+            ),
+            Self::RpcMethodUnsupported(method) => write!(
+                f,
+                "the RPC node doesn't serve {}: {}",
+                method,
+                hint(*method)
+            ),
+            Self::NotConfirmed { signature, outcome } => match outcome {
+                ConfirmationOutcome::Failed { slot, error } => write!(
+                    f,
+                    "transaction {} failed in slot {}: {}",
+                    signature, slot, error
+                ),
+                ConfirmationOutcome::Expired => write!(
+                    f,
+                    "transaction {} expired, its blockhash is no longer valid",
+                    signature
+                ),
+                ConfirmationOutcome::TimedOut => write!(
+                    f,
+                    "transaction {} isn't confirmed yet, it may still land",
+                    signature
+                ),
+                ConfirmationOutcome::Confirmed { slot, .. } => {
+                    write!(f, "transaction {} confirmed in slot {}", signature, slot)
+                }
+            },
+            Self::InvalidRequest(e) => f.write_str(e),
+            Self::RateLimited {
+                retry_after_seconds,
+            } => write!(
+                f,
+                "too many requests from this client, retry after {} seconds",
+                retry_after_seconds
+            ),
+            Self::Internal(e) => write!(f, "internal error: {}", e),
         }
     }
 }
@@ -97,6 +1284,12 @@ impl From<Bs58Error> for Error {
     }
 }
 
+impl From<InteriorWhitespace> for Error {
+    fn from(e: InteriorWhitespace) -> Self {
+        Self::InteriorWhitespace(e)
+    }
+}
+
 impl From<ed25519_dalek::SignatureError> for Error {
     fn from(e: ed25519_dalek::SignatureError) -> Self {
         Self::WrongKeyPair(e)
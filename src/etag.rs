@@ -0,0 +1,78 @@
+use poem::{
+    Body, Response,
+    http::{HeaderMap, StatusCode, header},
+};
+use sha2::{Digest, Sha256};
+
+// Read-only `GET /api/*` responses carry a strong ETag of their body, and a request whose
+// `If-None-Match` names it is answered with a bodiless 304. That only works because the same
+// state always serializes to the same bytes: every list is in a fixed order and maps are kept
+// sorted, nothing in a response takes the iteration order of a `HashMap`.
+
+/// Strong ETag of `body`.
+pub fn strong(body: &[u8]) -> String {
+    let hash = Sha256::digest(body);
+    let hex: String = hash[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Whether `If-None-Match` in `headers` names `etag`. Weak comparison, as the header asks for.
+pub fn matches(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// `resp` with the ETag of its body, or a 304 when `If-None-Match` in `headers` names it. Only
+/// successful responses get one, and one that has an ETag already keeps it.
+pub async fn conditional(headers: &HeaderMap, resp: Response) -> Response {
+    if resp.status() != StatusCode::OK || resp.headers().contains_key(header::ETAG) {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    let bytes = match body.into_bytes().await {
+        Ok(bytes) => bytes,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let etag = strong(&bytes);
+    let not_modified = matches(headers, &etag);
+    if let Ok(value) = etag.parse() {
+        parts.headers.insert(header::ETAG, value);
+    }
+    if not_modified {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_TYPE);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::{HeaderMap, HeaderValue, header};
+
+    use crate::etag::{matches, strong};
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = strong(b"{}");
+        assert_eq!(etag, strong(b"{}"));
+        assert_ne!(etag, strong(b"[]"));
+
+        let with = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        assert!(!matches(&HeaderMap::new(), &etag));
+        assert!(matches(&with(&etag), &etag));
+        assert!(matches(&with(&format!("\"other\", W/{}", etag)), &etag));
+        assert!(matches(&with("*"), &etag));
+        assert!(!matches(&with("\"other\""), &etag));
+    }
+}
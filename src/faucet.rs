@@ -0,0 +1,306 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient,
+    rpc_request::RpcError,
+};
+use solana_sdk::{
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, read_keypair_file},
+};
+
+use crate::{Error, models::Network};
+
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Most recipients of one `/api/airdrop_batch`.
+pub const MAX_BATCH_RECIPIENTS: usize = 20;
+
+/// Faucet requests of one batch in flight at once.
+pub const BATCH_CONCURRENCY: usize = 4;
+
+/// First wait of a batch after the public faucet rate-limited it, doubling with every retry.
+pub const BATCH_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Tries of a rate-limited public faucet request in a batch, before falling back to the
+/// internal faucet.
+const BATCH_ATTEMPTS: u32 = 3;
+
+/// Funded devnet key used when the public faucet rate-limits us.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaucetConfig {
+    /// solana-keygen JSON keypair file
+    pub keypair_path: PathBuf,
+    pub per_recipient_lamports_per_hour: u64,
+    pub lamports_per_hour: u64,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        Self {
+            keypair_path: PathBuf::new(),
+            per_recipient_lamports_per_hour: 2 * LAMPORTS_PER_SOL,
+            lamports_per_hour: 20 * LAMPORTS_PER_SOL,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Grant {
+    at: Instant,
+    to: Pubkey,
+    lamports: u64,
+}
+
+pub struct InternalFaucet {
+    keypair: Keypair,
+    config: FaucetConfig,
+    grants: Mutex<VecDeque<Grant>>,
+}
+
+impl InternalFaucet {
+    pub fn load(config: &FaucetConfig) -> Result<Self, String> {
+        let keypair = read_keypair_file(&config.keypair_path).map_err(|e| {
+            format!(
+                "failed reading faucet keypair {}: {}",
+                config.keypair_path.display(),
+                e
+            )
+        })?;
+        Ok(Self::new(keypair, config.clone()))
+    }
+
+    pub fn new(keypair: Keypair, config: FaucetConfig) -> Self {
+        Self {
+            keypair,
+            config,
+            grants: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn keypair(&self) -> &Keypair {
+        &self.keypair
+    }
+
+    /// Only devnet may ever be funded from the internal faucet.
+    pub fn serves(&self, net: Network) -> bool {
+        net == Network::Devnet
+    }
+
+    /// Book `lamports` for `to` against the hourly caps. Call `release` if the transfer fails.
+    pub fn reserve(&self, to: &Pubkey, lamports: u64, now: Instant) -> Result<(), Error> {
+        let mut grants = self.grants.lock().unwrap();
+        while grants
+            .front()
+            .is_some_and(|g| now.duration_since(g.at) >= WINDOW)
+        {
+            grants.pop_front();
+        }
+
+        let total: u64 = grants.iter().map(|g| g.lamports).sum();
+        let to_recipient: u64 = grants
+            .iter()
+            .filter(|g| &g.to == to)
+            .map(|g| g.lamports)
+            .sum();
+        if to_recipient.saturating_add(lamports) > self.config.per_recipient_lamports_per_hour {
+            return Err(Error::FaucetLimitExceeded(format!(
+                "{} already received {} of {} lamports this hour",
+                to, to_recipient, self.config.per_recipient_lamports_per_hour
+            )));
+        }
+        if total.saturating_add(lamports) > self.config.lamports_per_hour {
+            return Err(Error::FaucetLimitExceeded(format!(
+                "{} of {} lamports already handed out this hour",
+                total, self.config.lamports_per_hour
+            )));
+        }
+
+        grants.push_back(Grant {
+            at: now,
+            to: *to,
+            lamports,
+        });
+        Ok(())
+    }
+
+    pub fn release(&self, to: &Pubkey, lamports: u64, at: Instant) {
+        let mut grants = self.grants.lock().unwrap();
+        if let Some(i) = grants
+            .iter()
+            .rposition(|g| g.at == at && &g.to == to && g.lamports == lamports)
+        {
+            grants.remove(i);
+        }
+    }
+}
+
+/// Backoff shared by the faucet requests of a batch. The public faucet limits by caller, so a
+/// request it rate-limits holds back all the others too instead of each retrying on its own.
+pub struct Backoff {
+    base: Duration,
+    until: Mutex<Option<tokio::time::Instant>>,
+}
+
+impl Backoff {
+    pub fn new(base: Duration) -> Self {
+        Self {
+            base,
+            until: Mutex::new(None),
+        }
+    }
+
+    async fn wait(&self) {
+        let until = *self.until.lock().unwrap();
+        if let Some(until) = until {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+
+    /// Push the next requests back, doubling with every `attempt` of the one rate-limited.
+    fn rate_limited(&self, attempt: u32) {
+        let until = tokio::time::Instant::now() + self.base * 2u32.pow(attempt);
+        let mut current = self.until.lock().unwrap();
+        *current = Some(current.map_or(until, |current| current.max(until)));
+    }
+}
+
+/// Ask the public faucet for `lamports` to `to`, retrying after `backoff` while it rate-limits.
+/// The last error is returned as it is, for the caller to fall back on the internal faucet.
+pub async fn request_with_backoff(
+    rpc_client: &RpcClient,
+    backoff: &Backoff,
+    to: &Pubkey,
+    lamports: u64,
+) -> Result<Signature, ClientError> {
+    let mut attempt = 0;
+    loop {
+        backoff.wait().await;
+        match rpc_client.request_airdrop(to, lamports).await {
+            Err(e) if is_rate_limited(&e) && attempt + 1 < BATCH_ATTEMPTS => {
+                backoff.rate_limited(attempt);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether `request_airdrop` failed because the public faucet is rate limiting us,
+/// as opposed to a bad request or an unreachable node.
+pub fn is_rate_limited(err: &ClientError) -> bool {
+    let mentions_limit = |message: &str| {
+        let message = message.to_lowercase();
+        message.contains("rate limit")
+            || message.contains("too many requests")
+            || message.contains("airdrop limit")
+    };
+    match err.kind() {
+        ClientErrorKind::Reqwest(e) => e.status().map(|s| s.as_u16()) == Some(429),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, message, .. }) => {
+            *code == 429 || mentions_limit(message)
+        }
+        ClientErrorKind::RpcError(RpcError::ForUser(message)) => mentions_limit(message),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use solana_client::{
+        client_error::{ClientError, ClientErrorKind},
+        rpc_request::{RpcError, RpcResponseErrorData},
+    };
+    use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+
+    use crate::faucet::{FaucetConfig, InternalFaucet, is_rate_limited};
+    use crate::models::Network;
+
+    fn faucet() -> InternalFaucet {
+        InternalFaucet::new(
+            Keypair::new(),
+            FaucetConfig {
+                per_recipient_lamports_per_hour: 100,
+                lamports_per_hour: 250,
+                ..FaucetConfig::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_caps() {
+        let faucet = faucet();
+        let (alice, bob, carol) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let start = Instant::now();
+
+        faucet.reserve(&alice, 60, start).unwrap();
+        assert!(faucet.reserve(&alice, 50, start).is_err());
+        faucet.reserve(&alice, 40, start).unwrap();
+        faucet.reserve(&bob, 100, start).unwrap();
+        // 200 of 250 handed out, carol is under the per-recipient cap but the global one is hit
+        assert!(faucet.reserve(&carol, 60, start).is_err());
+        faucet.reserve(&carol, 50, start).unwrap();
+
+        // Released grants don't count
+        faucet.release(&carol, 50, start);
+        faucet.reserve(&carol, 50, start).unwrap();
+
+        // Everything expires after an hour
+        let later = start + Duration::from_secs(60 * 60);
+        faucet.reserve(&alice, 100, later).unwrap();
+        faucet.reserve(&carol, 100, later).unwrap();
+    }
+
+    #[test]
+    fn test_only_devnet() {
+        let faucet = faucet();
+        assert!(faucet.serves(Network::Devnet));
+        assert!(!faucet.serves(Network::Testnet));
+        assert!(!faucet.serves(Network::Mainnet));
+    }
+
+    #[test]
+    fn test_rate_limit_classification() {
+        let response_error = |code, message: &str| {
+            ClientError::from(ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                code,
+                message: message.to_string(),
+                data: RpcResponseErrorData::Empty,
+            }))
+        };
+        assert!(is_rate_limited(&response_error(
+            -32603,
+            "Internal error: airdrop request failed. This can happen when the rate limit is reached."
+        )));
+        assert!(is_rate_limited(&response_error(429, "Too Many Requests")));
+        assert!(is_rate_limited(&ClientError::from(
+            ClientErrorKind::RpcError(RpcError::ForUser(
+                "You've either reached your airdrop limit today or the airdrop faucet has run dry"
+                    .to_string()
+            ))
+        )));
+
+        assert!(!is_rate_limited(&response_error(
+            -32602,
+            "Invalid param: Invalid"
+        )));
+        assert!(!is_rate_limited(&ClientError::from(
+            ClientErrorKind::Custom("connection refused".to_string())
+        )));
+    }
+}
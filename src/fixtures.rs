@@ -0,0 +1,454 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::{Value, json};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient as AsyncRpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_sdk::{
+    account::Account as SolanaAccount,
+    hash::Hash,
+    nonce::state::{Data, DurableNonce, State, Versions},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::Signature,
+    system_program,
+    transaction::TransactionError,
+};
+use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
+use spl_token::state::{Account, AccountState, Mint};
+
+use crate::rpc_methods::METHOD_NOT_FOUND;
+
+// Packed SPL accounts for tests, served by an in-memory RPC node. `new_mock_with_mocks` answers
+// every call of a method with the same value, these fixtures answer per address, so code that
+// reads a token account and then its mint sees both.
+
+/// A packed, initialized mint with `decimals`.
+pub fn mint(decimals: u8) -> SolanaAccount {
+    packed(Mint {
+        mint_authority: COption::Some(Pubkey::new_unique()),
+        supply: 1_000_000,
+        decimals,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    })
+}
+
+/// An initialized token account of `mint` holding `amount` for `owner`.
+pub fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+    Account {
+        mint,
+        owner,
+        amount,
+        state: AccountState::Initialized,
+        ..Account::default()
+    }
+}
+
+/// A wrapped SOL account of `owner` holding `lamports` on top of its rent reserve.
+pub fn native_account(owner: Pubkey, lamports: u64) -> Account {
+    Account {
+        is_native: COption::Some(Rent::default().minimum_balance(Account::LEN)),
+        ..token_account(spl_token::native_mint::id(), owner, lamports)
+    }
+}
+
+/// `state` as the SPL token program stores it.
+pub fn packed<T: Pack>(state: T) -> SolanaAccount {
+    let mut data = vec![0; T::LEN];
+    T::pack(state, &mut data).expect("fixture state packs");
+    SolanaAccount {
+        lamports: Rent::default().minimum_balance(T::LEN),
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// A `packed` mint or token account moved over to Token-2022, with one extension past the
+/// base state: `MintCloseAuthority` on a mint, `ImmutableOwner` on a token account.
+pub fn token_2022(account: SolanaAccount) -> SolanaAccount {
+    let mut data = account.data;
+    // Mints are padded to the length of an account, the type byte and the extensions follow
+    let (account_type, extension, length): (u8, u16, u16) = match data.len() == Mint::LEN {
+        true => (1, 3, 32),
+        false => (2, 7, 0),
+    };
+    data.resize(Account::LEN, 0);
+    data.push(account_type);
+    data.extend_from_slice(&extension.to_le_bytes());
+    data.extend_from_slice(&length.to_le_bytes());
+    data.resize(data.len() + usize::from(length), 0);
+    SolanaAccount {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: spl_token_2022::id(),
+        ..account
+    }
+}
+
+/// A `token_2022` account with one more extension of type `extension`, its value `length`
+/// zero bytes.
+pub fn with_extension(account: SolanaAccount, extension: u16, length: u16) -> SolanaAccount {
+    let mut data = account.data;
+    data.extend_from_slice(&extension.to_le_bytes());
+    data.extend_from_slice(&length.to_le_bytes());
+    data.resize(data.len() + usize::from(length), 0);
+    SolanaAccount {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        ..account
+    }
+}
+
+/// The wrapped SOL account of `native_account` as stored on chain, with its reserve, `amount`
+/// and `unsynced` lamports sent to it without a `SyncNative`.
+pub fn wrapped_sol(owner: Pubkey, amount: u64, unsynced: u64) -> SolanaAccount {
+    let mut account = packed(native_account(owner, amount));
+    account.lamports += amount + unsynced;
+    account
+}
+
+/// An initialized nonce account of `authority`, and the nonce it holds.
+pub fn nonce_account(authority: &Pubkey) -> (SolanaAccount, Hash) {
+    let durable_nonce = DurableNonce::from_blockhash(&Hash::new_unique());
+    let state = Versions::new(State::Initialized(Data::new(
+        *authority,
+        durable_nonce,
+        5_000,
+    )));
+    let lamports = Rent::default().minimum_balance(State::size());
+    let account = SolanaAccount::new_data(lamports, &state, &system_program::id())
+        .expect("fixture nonce state serializes");
+    (account, *durable_nonce.as_hash())
+}
+
+/// Accounts by address, answering `getAccountInfo`, `getMultipleAccounts` and `getBalance`.
+#[derive(Debug, Clone, Default)]
+pub struct Accounts(HashMap<Pubkey, SolanaAccount>);
+
+impl Accounts {
+    pub fn with(mut self, address: Pubkey, account: SolanaAccount) -> Self {
+        self.0.insert(address, account);
+        self
+    }
+
+    pub fn async_rpc_client(self) -> AsyncRpcClient {
+        AsyncRpcClient::new_sender(self, RpcClientConfig::default())
+    }
+
+    fn ui_account(&self, address: &Value) -> Value {
+        let account = address
+            .as_str()
+            .and_then(|address| address.parse::<Pubkey>().ok())
+            .and_then(|address| Some((address, self.0.get(&address)?)));
+        match account {
+            Some((address, account)) => json!(UiAccount::encode(
+                &address,
+                account,
+                UiAccountEncoding::Base64,
+                None,
+                None
+            )),
+            None => Value::Null,
+        }
+    }
+}
+
+#[async_trait]
+impl RpcSender for Accounts {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let value = match request {
+            // Asked before calls whose config depends on the node version
+            RpcRequest::GetVersion => return Ok(json!({ "solana-core": "1.18.26" })),
+            RpcRequest::GetAccountInfo => self.ui_account(&params[0]),
+            RpcRequest::GetMultipleAccounts => params[0]
+                .as_array()
+                .map(|addresses| addresses.iter().map(|a| self.ui_account(a)).collect())
+                .unwrap_or_default(),
+            RpcRequest::GetBalance => json!(
+                self.ui_account(&params[0])
+                    .get("lamports")
+                    .cloned()
+                    .unwrap_or(json!(0))
+            ),
+            _ => {
+                return Err(ClientError::from(ClientErrorKind::Custom(format!(
+                    "{} isn't served by the fixtures",
+                    request
+                ))));
+            }
+        };
+        Ok(json!({ "context": { "slot": 1 }, "value": value }))
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "fixtures".to_string()
+    }
+}
+
+/// A status seen at `slot` with `confirmation_status`.
+pub fn status(
+    slot: u64,
+    confirmation_status: TransactionConfirmationStatus,
+    result: Result<(), TransactionError>,
+) -> TransactionStatus {
+    TransactionStatus {
+        slot,
+        confirmations: (confirmation_status != TransactionConfirmationStatus::Finalized)
+            .then_some(1),
+        err: result.clone().err(),
+        status: result,
+        confirmation_status: Some(confirmation_status),
+    }
+}
+
+/// Scripted `getSignatureStatuses` and `isBlockhashValid` answers for code polling a
+/// transaction, one per call with the last one repeating, the same for every signature of a
+/// call. The blockhash is valid until scripted
+/// otherwise, `sendTransaction` calls are counted.
+#[derive(Debug, Clone, Default)]
+pub struct Statuses {
+    statuses: Arc<Mutex<VecDeque<Option<TransactionStatus>>>>,
+    blockhash_valid: Arc<Mutex<VecDeque<bool>>>,
+    sent: Arc<AtomicUsize>,
+    already_processed: bool,
+}
+
+impl Statuses {
+    pub fn then(self, status: Option<TransactionStatus>) -> Self {
+        self.statuses.lock().unwrap().push_back(status);
+        self
+    }
+
+    pub fn blockhash_valid(self, answers: impl IntoIterator<Item = bool>) -> Self {
+        self.blockhash_valid.lock().unwrap().extend(answers);
+        self
+    }
+
+    /// Refuse sends as a node does that processed the transaction before.
+    pub fn already_processed(mut self) -> Self {
+        self.already_processed = true;
+        self
+    }
+
+    pub fn sent(&self) -> usize {
+        self.sent.load(Ordering::SeqCst)
+    }
+
+    pub fn async_rpc_client(&self) -> AsyncRpcClient {
+        AsyncRpcClient::new_sender(self.clone(), RpcClientConfig::default())
+    }
+}
+
+/// The fee payer's signature of a base64 wire transaction, the node answers a send with it.
+/// It follows the one byte count of signatures.
+fn first_signature(transaction: &Value) -> Signature {
+    transaction
+        .as_str()
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .and_then(|bytes| Signature::try_from(bytes.get(1..65)?).ok())
+        .unwrap_or_default()
+}
+
+fn next<T: Clone>(answers: &Mutex<VecDeque<T>>) -> Option<T> {
+    let mut answers = answers.lock().unwrap();
+    if answers.len() > 1 {
+        answers.pop_front()
+    } else {
+        answers.front().cloned()
+    }
+}
+
+#[async_trait]
+impl RpcSender for Statuses {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let value = match request {
+            RpcRequest::GetVersion => return Ok(json!({ "solana-core": "1.18.26" })),
+            RpcRequest::GetSignatureStatuses => {
+                let asked = params[0].as_array().map_or(1, Vec::len);
+                json!(vec![next(&self.statuses).flatten(); asked])
+            }
+            RpcRequest::IsBlockhashValid => json!(next(&self.blockhash_valid).unwrap_or(true)),
+            RpcRequest::SendTransaction => {
+                self.sent.fetch_add(1, Ordering::SeqCst);
+                if self.already_processed {
+                    return Err(preflight_failure(
+                        json!({ "err": "AlreadyProcessed", "logs": [] }),
+                    ));
+                }
+                return Ok(json!(first_signature(&params[0]).to_string()));
+            }
+            _ => {
+                return Err(ClientError::from(ClientErrorKind::Custom(format!(
+                    "{} isn't served by the fixtures",
+                    request
+                ))));
+            }
+        };
+        Ok(json!({ "context": { "slot": 1 }, "value": value }))
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "fixtures".to_string()
+    }
+}
+
+/// The error of a send whose preflight simulation ended with `simulation`, an
+/// `RpcSimulateTransactionResult` as the node sends it.
+pub fn preflight_failure(simulation: Value) -> ClientError {
+    let message = match simulation["err"].as_str() {
+        Some("AlreadyProcessed") => "This transaction has already been processed".to_string(),
+        _ => format!("{}", simulation["err"]),
+    };
+    ClientError::from(ClientErrorKind::RpcError(RpcError::RpcResponseError {
+        code: -32002,
+        message: format!("Transaction simulation failed: {}", message),
+        data: RpcResponseErrorData::SendTransactionPreflightFailure(
+            serde_json::from_value(simulation).expect("a simulation result"),
+        ),
+    }))
+}
+
+/// `sender` with `methods` disabled, answering them with "method not found" like a
+/// self-hosted node that doesn't serve them.
+pub fn disabled<S>(sender: S, methods: &[RpcRequest]) -> Disabled<S> {
+    Disabled {
+        sender,
+        methods: methods.to_vec(),
+    }
+}
+
+pub struct Disabled<S> {
+    sender: S,
+    methods: Vec<RpcRequest>,
+}
+
+impl<S: RpcSender + Send + Sync + 'static> Disabled<S> {
+    pub fn async_rpc_client(self) -> AsyncRpcClient {
+        AsyncRpcClient::new_sender(self, RpcClientConfig::default())
+    }
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for Disabled<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        if self.methods.contains(&request) {
+            return Err(ClientError::from(ClientErrorKind::RpcError(
+                RpcError::RpcResponseError {
+                    code: METHOD_NOT_FOUND,
+                    message: "Method not found".to_string(),
+                    data: RpcResponseErrorData::Empty,
+                },
+            )));
+        }
+        self.sender.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.sender.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.sender.url()
+    }
+}
+
+/// `sender` counting the calls it answers, for code that should make fewer of them.
+pub fn counting<S>(sender: S) -> Counting<S> {
+    Counting {
+        sender,
+        calls: Arc::default(),
+    }
+}
+
+#[derive(Clone)]
+pub struct Counting<S> {
+    sender: S,
+    calls: Arc<Mutex<Vec<RpcRequest>>>,
+}
+
+impl<S: RpcSender + Clone + Send + Sync + 'static> Counting<S> {
+    pub fn calls(&self, method: RpcRequest) -> usize {
+        let calls = self.calls.lock().unwrap();
+        calls.iter().filter(|call| **call == method).count()
+    }
+
+    pub fn async_rpc_client(&self) -> AsyncRpcClient {
+        AsyncRpcClient::new_sender(self.clone(), RpcClientConfig::default())
+    }
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for Counting<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        self.calls.lock().unwrap().push(request);
+        self.sender.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.sender.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.sender.url()
+    }
+}
+
+/// `sender` answering as the node at `url`, for code that has to tell nodes apart.
+pub fn at<S>(url: &str, sender: S) -> At<S> {
+    At {
+        url: url.to_string(),
+        sender,
+    }
+}
+
+pub struct At<S> {
+    url: String,
+    sender: S,
+}
+
+impl<S: RpcSender + Send + Sync + 'static> At<S> {
+    pub fn async_rpc_client(self) -> AsyncRpcClient {
+        AsyncRpcClient::new_sender(self, RpcClientConfig::default())
+    }
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for At<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        self.sender.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.sender.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
@@ -0,0 +1,160 @@
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{message::Message, program_pack::Pack, pubkey::Pubkey, rent::Rent};
+use spl_token::state::Account;
+
+use crate::{
+    Error,
+    compute_budget::priority_fee,
+    rpc_methods::unsupported_method,
+    warning::{Warning, WarningCode},
+};
+
+// First-timers often aggregate keys and sign without ever funding the aggregated address, the
+// node then rejects the transaction with a preflight error about the fee payer that doesn't
+// say what's wrong. The aggregation endpoints look at the fee payer first and explain it.
+
+/// Fee per signature charged by the cluster, a compute unit price comes on top.
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+/// Signature fees and priority fee of `message`.
+pub fn message_fee(message: &Message) -> u64 {
+    LAMPORTS_PER_SIGNATURE * u64::from(message.header.num_required_signatures)
+        + priority_fee(message)
+}
+
+/// Fail with `AggAccountUnfunded` when the fee payer of `message` doesn't exist or holds no
+/// lamports. `lamports` is what the message transfers out of it, and when
+/// `recipient_token_account` is given and doesn't exist yet the payer also funds its rent. The
+/// warning says the check was skipped.
+pub async fn check_funded(
+    rpc_client: &RpcClient,
+    message: &Message,
+    lamports: u64,
+    recipient_token_account: Option<&Pubkey>,
+) -> Result<Option<Warning>, Error> {
+    let payer = message.account_keys[0];
+    let addresses: Vec<Pubkey> = std::iter::once(payer)
+        .chain(recipient_token_account.copied())
+        .collect();
+    let accounts = match rpc_client
+        .get_multiple_accounts_with_commitment(&addresses, rpc_client.commitment())
+        .await
+    {
+        Ok(accounts) => accounts.value,
+        // Only a friendlier error, the node's own preflight still catches an unfunded payer
+        Err(e) if unsupported_method(&e).is_some() => {
+            let warning = WarningCode::CheckSkipped.warning(format!(
+                "the aggregated account {} wasn't checked for funds: {}",
+                payer, e
+            ));
+            return Ok(Some(warning));
+        }
+        Err(e) => return Err(Error::AccountFetchFailed(e)),
+    };
+    if accounts[0]
+        .as_ref()
+        .is_some_and(|account| account.lamports > 0)
+    {
+        return Ok(None);
+    }
+
+    let fee = message_fee(message);
+    let rent = match accounts.get(1) {
+        Some(None) => Rent::default().minimum_balance(Account::LEN),
+        _ => 0,
+    };
+    Err(Error::AggAccountUnfunded {
+        address: payer,
+        minimum_lamports: fee + lamports + rent,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{hash::Hash, program_pack::Pack, pubkey::Pubkey, rent::Rent};
+    use spl_associated_token_account::get_associated_token_address;
+    use spl_token::state::Account;
+
+    use crate::{
+        Error,
+        fixtures::{Accounts, packed, token_account},
+        funding::check_funded,
+        transaction_builder::{SplTransferBuilder, TransferBuilder},
+        tss::{spl_transfer_message, transfer_message},
+        units::{Decimals, Lamports, RawTokenAmount},
+        warning::Warning,
+    };
+
+    #[tokio::test]
+    async fn test_unfunded_payer() {
+        let (payer, to, mint) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let message = transfer_message(
+            &TransferBuilder::new(payer, to, Lamports::new(1_000)),
+            Hash::new_unique(),
+        );
+        let spl_message = spl_transfer_message(
+            &SplTransferBuilder::new(
+                payer,
+                to,
+                mint,
+                RawTokenAmount::new(1_000_000),
+                Decimals::new(6).unwrap(),
+            ),
+            Hash::new_unique(),
+        )
+        .unwrap();
+        let ata = get_associated_token_address(&to, &mint);
+        let funded = |lamports| solana_sdk::account::Account {
+            lamports,
+            ..Default::default()
+        };
+        let rent = Rent::default().minimum_balance(Account::LEN);
+
+        let minimum = |result: Result<Option<Warning>, Error>| match result {
+            Ok(warning) => {
+                assert_eq!(warning, None);
+                None
+            }
+            Err(Error::AggAccountUnfunded {
+                address,
+                minimum_lamports,
+            }) => {
+                assert_eq!(address, payer);
+                Some(minimum_lamports)
+            }
+            Err(e) => panic!("{}", e),
+        };
+        for (accounts, expected) in [
+            (Accounts::default(), Some(5_000 + 1_000)),
+            (
+                Accounts::default().with(payer, funded(0)),
+                Some(5_000 + 1_000),
+            ),
+            (Accounts::default().with(payer, funded(1)), None),
+        ] {
+            let rpc_client = accounts.async_rpc_client();
+            assert_eq!(
+                minimum(check_funded(&rpc_client, &message, 1_000, None).await),
+                expected
+            );
+        }
+
+        // The recipient's token account is created at the payer's cost when it's missing
+        let existing_ata = packed(token_account(mint, to, 0));
+        for (accounts, expected) in [
+            (Accounts::default(), Some(5_000 + rent)),
+            (Accounts::default().with(ata, existing_ata), Some(5_000)),
+            (Accounts::default().with(payer, funded(10)), None),
+        ] {
+            let rpc_client = accounts.async_rpc_client();
+            assert_eq!(
+                minimum(check_funded(&rpc_client, &spl_message, 0, Some(&ata)).await),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter},
+};
+
+use serde::{
+    Deserialize, Deserializer,
+    de::{self, IgnoredAny, SeqAccess, Visitor},
+};
+use solana_sdk::pubkey::Pubkey;
+
+// Values pasted from chat apps pick up trailing newlines, spaces and invisible characters.
+// Those are cleaned up before parsing, whitespace inside a value is still an error since it
+// usually means two values were pasted together.
+
+/// Zero-width space, non-joiner, joiner, word joiner and the byte order mark.
+const ZERO_WIDTH: [char; 5] = ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InteriorWhitespace {
+    /// Character index of the whitespace in the cleaned-up input
+    pub index: usize,
+    /// What came before it, left out for secrets
+    pub prefix: Option<String>,
+}
+
+impl Display for InteriorWhitespace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.prefix {
+            Some(prefix) => write!(
+                f,
+                "unexpected whitespace at position {} after {:?}",
+                self.index, prefix
+            ),
+            None => write!(f, "unexpected whitespace at position {}", self.index),
+        }
+    }
+}
+
+impl std::error::Error for InteriorWhitespace {}
+
+fn clean(s: &str, secret: bool) -> Result<Cow<'_, str>, InteriorWhitespace> {
+    let cleaned: Cow<str> = if s.contains(ZERO_WIDTH) {
+        s.replace(ZERO_WIDTH, "").into()
+    } else {
+        s.into()
+    };
+    let trimmed = cleaned.trim_ascii();
+    if let Some(index) = trimmed.chars().position(char::is_whitespace) {
+        return Err(InteriorWhitespace {
+            index,
+            prefix: (!secret).then(|| trimmed.chars().take(index).collect()),
+        });
+    }
+    Ok(match cleaned {
+        Cow::Borrowed(s) => Cow::Borrowed(s.trim_ascii()),
+        Cow::Owned(s) => Cow::Owned(s.trim_ascii().to_string()),
+    })
+}
+
+/// Trim ASCII whitespace, drop zero-width characters and reject whitespace left inside.
+pub fn normalize(s: &str) -> Result<Cow<'_, str>, InteriorWhitespace> {
+    clean(s, false)
+}
+
+/// `normalize` for keypairs and secret state, the error doesn't echo any of the input.
+pub fn normalize_secret(s: &str) -> Result<Cow<'_, str>, InteriorWhitespace> {
+    clean(s, true)
+}
+
+// Integrators holding pubkeys as bytes can send them as a JSON array of 32 numbers wherever a
+// request takes a pubkey. They are turned into base58 while deserializing, so the handlers
+// parse and report both forms the same way.
+
+/// A pubkey field given as base58 or as 32 bytes, always base58 afterwards.
+struct PubkeyInput(String);
+
+impl<'de> Deserialize<'de> for PubkeyInput {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(PubkeyVisitor).map(PubkeyInput)
+    }
+}
+
+struct PubkeyVisitor;
+
+impl<'de> Visitor<'de> for PubkeyVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "a base58 pubkey or an array of 32 bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<String, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<String, A::Error> {
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        let mut len = bytes.len();
+        while seq.next_element::<IgnoredAny>()?.is_some() {
+            len += 1;
+        }
+        if len != bytes.len() {
+            return Err(de::Error::invalid_length(len, &self));
+        }
+        Ok(Pubkey::from(bytes).to_string())
+    }
+}
+
+/// `deserialize_with` for a pubkey given as base58 or 32 bytes.
+pub fn pubkey<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    PubkeyInput::deserialize(deserializer).map(|key| key.0)
+}
+
+/// `pubkey` for every entry of an array.
+pub fn pubkeys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Vec::<PubkeyInput>::deserialize(deserializer)
+        .map(|keys| keys.into_iter().map(|key| key.0).collect())
+}
+
+/// `pubkey` for an optional field, use with `#[serde(default)]`.
+pub fn optional_pubkey<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<PubkeyInput>::deserialize(deserializer).map(|key| key.map(|key| key.0))
+}
+
+// Keypairs come as base58 or, the way `solana-keygen` writes them to `id.json`, as a JSON array
+// of their 64 bytes. An array is kept as its JSON text, so the handlers tell the forms apart by
+// the leading `[` whether the array was sent as such or pasted into a string.
+
+/// A keypair field given as base58 or as an array of bytes, the array as JSON text afterwards.
+struct KeypairInput(String);
+
+impl<'de> Deserialize<'de> for KeypairInput {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_any(KeypairVisitor)
+            .map(KeypairInput)
+    }
+}
+
+struct KeypairVisitor;
+
+impl<'de> Visitor<'de> for KeypairVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "a base58 keypair or an array of 64 bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<String, E> {
+        Ok(v)
+    }
+
+    // Any length, the handlers name the expected one
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<String, A::Error> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(64);
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(format!("{:?}", bytes))
+    }
+}
+
+/// `deserialize_with` for a keypair given as base58 or as its bytes.
+pub fn keypair<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    KeypairInput::deserialize(deserializer).map(|keypair| keypair.0)
+}
+
+/// `keypair` for an optional field, use with `#[serde(default)]`.
+pub fn optional_keypair<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<KeypairInput>::deserialize(deserializer)
+        .map(|keypair| keypair.map(|keypair| keypair.0))
+}
+
+/// The bytes of a keypair written as a JSON array, whitespace and all.
+pub fn keypair_json_bytes(s: &str) -> Option<Vec<u8>> {
+    let s = s.replace(ZERO_WIDTH, "");
+    let inner = s.trim().strip_prefix('[')?.strip_suffix(']')?;
+    if inner.trim().is_empty() {
+        return Some(Vec::new());
+    }
+    inner
+        .split(',')
+        .map(|byte| byte.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand07::Rng;
+    use serde_json::json;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::{
+        input::{InteriorWhitespace, keypair_json_bytes, normalize, normalize_secret},
+        models::{AggregateKeysRequest, SendSingleRequest, SplTokenBalanceRequest},
+    };
+
+    #[test]
+    fn test_normalize() {
+        let key = "4Nd1mBQtrMJVYVfKf2PJy9NZUZdTAsp7D4xWLs4gDB4T";
+        for contaminated in [
+            format!("{}\n", key),
+            format!("  {}\t\r\n", key),
+            format!("\u{FEFF}{}", key),
+            format!("{}\u{200B}", key),
+            "4Nd1mBQtrMJ\u{200D}VYVfKf2PJy9NZUZdTAsp7D4xWLs4gDB4T ".to_string(),
+        ] {
+            assert_eq!(normalize(&contaminated).unwrap(), key);
+        }
+
+        assert_eq!(
+            normalize(" 4Nd1mB Qtr\n").unwrap_err(),
+            InteriorWhitespace {
+                index: 6,
+                prefix: Some("4Nd1mB".to_string()),
+            }
+        );
+        // A non-breaking space is whitespace too, even though it isn't trimmed
+        assert!(normalize("4Nd1mB\u{00A0}").is_err());
+        assert_eq!(normalize_secret("secret part").unwrap_err().prefix, None);
+    }
+
+    #[test]
+    fn test_pubkeys_as_bytes() {
+        let mut rng = rand07::thread_rng();
+        for _ in 0..200 {
+            // Random bytes, on the curve or not, with the all-zero and all-0xff edges
+            let keys: Vec<Pubkey> = (0..rng.gen_range(1, 8))
+                .map(|_| Pubkey::from(rng.r#gen::<[u8; 32]>()))
+                .chain([Pubkey::from([0; 32]), Pubkey::from([0xff; 32])])
+                .collect();
+            let base58: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+            // Mixed forms in the same array
+            let mixed: Vec<serde_json::Value> = keys
+                .iter()
+                .enumerate()
+                .map(|(i, k)| match i % 2 {
+                    0 => json!(k.to_bytes()),
+                    _ => json!(k.to_string()),
+                })
+                .collect();
+            for keys in [json!(base58), json!(mixed)] {
+                let req: AggregateKeysRequest =
+                    serde_json::from_value(json!({ "keys": keys })).unwrap();
+                assert_eq!(req.keys, base58);
+            }
+
+            let req: SplTokenBalanceRequest = serde_json::from_value(json!({
+                "owner": keys[0].to_bytes(),
+                "token_mint": keys[1].to_string(),
+                "net": "devnet",
+            }))
+            .unwrap();
+            assert_eq!(req.owner, base58[0]);
+            assert_eq!(req.token_mint.as_deref(), Some(base58[1].as_str()));
+        }
+
+        // Missing optional pubkeys stay missing
+        let req: SplTokenBalanceRequest = serde_json::from_value(
+            json!({ "owner": vec![1u8; 32], "token": "USDC", "net": "devnet" }),
+        )
+        .unwrap();
+        assert_eq!(req.token_mint, None);
+        for bad in [
+            json!(vec![1u8; 31]),
+            json!(vec![1u8; 33]),
+            json!(vec![256u16; 32]),
+            json!(7),
+        ] {
+            assert!(
+                serde_json::from_value::<AggregateKeysRequest>(json!({ "keys": [bad] })).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_keypairs_as_bytes() {
+        let bytes: Vec<u8> = (0..64).map(|i| i * 4).collect();
+        let req: SendSingleRequest = serde_json::from_value(json!({
+            "keypair": bytes,
+            "to": Pubkey::new_unique().to_string(),
+        }))
+        .unwrap();
+        assert!(req.keypair.starts_with('['));
+        assert_eq!(keypair_json_bytes(&req.keypair), Some(bytes.clone()));
+        // Formatted the way id.json is, or split over lines when pasted
+        let pasted = format!("{:?}", bytes).replace(", ", ",\n  ");
+        assert_eq!(keypair_json_bytes(&pasted), Some(bytes));
+
+        assert_eq!(keypair_json_bytes("[]"), Some(Vec::new()));
+        assert_eq!(keypair_json_bytes("[1, 2,]"), None);
+        assert_eq!(keypair_json_bytes("[1, 256]"), None);
+        assert_eq!(keypair_json_bytes("[1, 2"), None);
+    }
+}
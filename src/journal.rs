@@ -0,0 +1,652 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
+use solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature, transaction::Transaction};
+
+use crate::{
+    audit::{AuditLog, Outcome},
+    broadcast::redacted_url,
+    confirmation::Lifetime,
+    durable_nonce::advanced_nonce_account,
+    models::{BroadcastAt, Network},
+    signature_status::{classify, fetch_statuses},
+};
+
+/// How often the startup reconciliation looks again at broadcasts whose blockhash is still valid.
+pub const RECONCILE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Failed lookups after which the startup reconciliation gives up on a broadcast and records
+/// it as expired, five minutes at `RECONCILE_INTERVAL`.
+pub const MAX_RECONCILE_FAILURES: u32 = 60;
+
+/// A blockhash lasts 150 slots, about a minute. One journalled longer ago than this has
+/// expired whatever the RPC node answers.
+const BLOCKHASH_MAX_AGE: Duration = Duration::from_secs(180);
+
+/// A broadcast that had not been confirmed yet when it was journalled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingBroadcast {
+    pub signature: String,
+    pub endpoint: String,
+    pub request_digest: Option<String>,
+    pub net: Network,
+    /// Or the durable nonce the transaction was signed with
+    pub recent_blockhash: String,
+    /// Set when `recent_blockhash` is the nonce of this account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce_account: Option<String>,
+    /// Unix timestamp in milliseconds
+    pub at: u64,
+    /// Tenant of the request, journals written before tenants existed belong to the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Set while the transaction waits for its `broadcast_at`, it hasn't been sent then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduled: Option<ScheduledBroadcast>,
+}
+
+impl PendingBroadcast {
+    /// How long the transaction can still land, `None` for a malformed entry.
+    pub fn lifetime(&self) -> Option<Lifetime> {
+        let hash = self.recent_blockhash.parse::<Hash>().ok()?;
+        Some(match &self.nonce_account {
+            Some(account) => Lifetime::DurableNonce {
+                account: account.parse::<Pubkey>().ok()?,
+                nonce: hash,
+            },
+            None => Lifetime::Blockhash(hash),
+        })
+    }
+
+    /// Whether its blockhash has expired by now, judging by when it was journalled. Durable
+    /// nonces don't expire with age.
+    fn outlived_blockhash(&self, now: u64) -> bool {
+        self.nonce_account.is_none()
+            && now.saturating_sub(self.at) > BLOCKHASH_MAX_AGE.as_millis() as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledBroadcast {
+    pub broadcast_at: BroadcastAt,
+    pub idempotency_key: Option<String>,
+    pub transaction: Transaction,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// JSON file of broadcasts awaiting confirmation, so a restart can still settle them.
+///
+/// Every change is made in memory and numbered under `entries`, then the file is rewritten on
+/// a blocking thread. `written` orders the writes, a change whose successor is on disk
+/// already isn't written anymore.
+pub struct BroadcastJournal {
+    path: PathBuf,
+    entries: Mutex<Vec<PendingBroadcast>>,
+    changes: AtomicU64,
+    written: tokio::sync::Mutex<u64>,
+}
+
+impl BroadcastJournal {
+    /// Open the journal at `path`, a missing file is an empty journal.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let entries = match fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| format!("failed parsing {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("failed reading {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+            changes: AtomicU64::new(0),
+            written: tokio::sync::Mutex::new(0),
+        })
+    }
+
+    pub fn entries(&self) -> Vec<PendingBroadcast> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Journal a broadcast about to be sent, replacing the entry it had while scheduled. It is
+    /// on disk when this returns.
+    pub async fn add(
+        &self,
+        tenant: Option<&str>,
+        signature: &Signature,
+        endpoint: &str,
+        request_digest: Option<&str>,
+        net: Network,
+        lifetime: &Lifetime,
+    ) {
+        let nonce_account = match lifetime {
+            Lifetime::DurableNonce { account, .. } => Some(account.to_string()),
+            Lifetime::Blockhash(_) => None,
+        };
+        self.push(PendingBroadcast {
+            signature: signature.to_string(),
+            endpoint: endpoint.to_string(),
+            request_digest: request_digest.map(str::to_string),
+            net,
+            recent_blockhash: lifetime.recent_blockhash().to_string(),
+            nonce_account,
+            at: unix_millis(),
+            tenant: tenant.map(str::to_string),
+            scheduled: None,
+        })
+        .await;
+    }
+
+    /// Hold a signed transaction until `scheduled.broadcast_at`.
+    pub async fn schedule(
+        &self,
+        tenant: Option<&str>,
+        endpoint: &str,
+        request_digest: Option<&str>,
+        net: Network,
+        scheduled: ScheduledBroadcast,
+    ) {
+        let tx = &scheduled.transaction;
+        self.push(PendingBroadcast {
+            signature: tx
+                .signatures
+                .first()
+                .copied()
+                .unwrap_or_default()
+                .to_string(),
+            endpoint: endpoint.to_string(),
+            request_digest: request_digest.map(str::to_string),
+            net,
+            recent_blockhash: tx.message.recent_blockhash.to_string(),
+            nonce_account: advanced_nonce_account(&tx.message).map(|account| account.to_string()),
+            at: unix_millis(),
+            tenant: tenant.map(str::to_string),
+            scheduled: Some(scheduled),
+        })
+        .await;
+    }
+
+    async fn push(&self, entry: PendingBroadcast) {
+        self.update(|entries| {
+            entries.retain(|e| e.signature != entry.signature);
+            entries.push(entry);
+            Some(())
+        })
+        .await;
+    }
+
+    pub async fn remove(&self, signature: &str) {
+        self.update(|entries| {
+            entries.retain(|e| e.signature != signature);
+            Some(())
+        })
+        .await;
+    }
+
+    /// Entries still waiting for their `broadcast_at`.
+    pub fn scheduled(&self) -> Vec<PendingBroadcast> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|e| e.scheduled.is_some())
+            .cloned()
+            .collect()
+    }
+
+    /// Take the transaction of a scheduled entry for sending, `None` if it was cancelled or
+    /// claimed already. The entry stays as an ordinary pending broadcast.
+    pub async fn claim_scheduled(&self, signature: &str) -> Option<ScheduledBroadcast> {
+        self.update(|entries| {
+            entries
+                .iter_mut()
+                .find(|e| e.signature == signature)?
+                .scheduled
+                .take()
+        })
+        .await
+    }
+
+    /// Drop a scheduled entry that hasn't been claimed for sending yet.
+    pub async fn cancel_scheduled(&self, signature: &str) -> Option<PendingBroadcast> {
+        self.update(|entries| {
+            let i = entries
+                .iter()
+                .position(|e| e.signature == signature && e.scheduled.is_some())?;
+            Some(entries.remove(i))
+        })
+        .await
+    }
+
+    /// Apply `change` and write the entries out, unless it returns `None` for no change.
+    async fn update<T>(
+        &self,
+        change: impl FnOnce(&mut Vec<PendingBroadcast>) -> Option<T>,
+    ) -> Option<T> {
+        let (result, change, raw) = {
+            let mut entries = self.entries.lock().unwrap();
+            let result = change(&mut entries)?;
+            let change = self.changes.fetch_add(1, Ordering::Relaxed) + 1;
+            (result, change, serde_json::to_vec(&*entries))
+        };
+        self.persist(change, raw).await;
+        Some(result)
+    }
+
+    // A failed write only costs the ability to settle these after a restart, so it is reported
+    // rather than failing the request that triggered it.
+    async fn persist(&self, change: u64, raw: serde_json::Result<Vec<u8>>) {
+        let mut written = self.written.lock().await;
+        if *written >= change {
+            return;
+        }
+        let path = self.path.clone();
+        let result = match raw {
+            Ok(raw) => tokio::task::spawn_blocking(move || write_file(&path, &raw))
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e))),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) => *written = change,
+            Err(e) => tracing::error!(
+                path = %self.path.display(),
+                error = %e,
+                "failed writing broadcast journal"
+            ),
+        }
+    }
+}
+
+/// Replace the file at `path` with `raw`. The new file is synced before it takes the old one's
+/// place, a crash leaves one or the other but never a torn file.
+fn write_file(path: &Path, raw: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(raw)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+async fn signature_outcome(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+) -> Result<Option<(Outcome, Option<String>)>, ClientError> {
+    let statuses = fetch_statuses(rpc_client, &[*signature]).await?;
+    Ok(
+        match classify(
+            statuses.first().and_then(Option::as_ref),
+            rpc_client.commitment(),
+        ) {
+            // Seen below our commitment, it can still be rolled back
+            Some((Outcome::Broadcast, _)) => None,
+            outcome => outcome,
+        },
+    )
+}
+
+/// Final outcome of a journalled broadcast, `None` while it can still land.
+async fn settle(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    lifetime: &Lifetime,
+) -> Result<Option<(Outcome, Option<String>)>, ClientError> {
+    if let Some(outcome) = signature_outcome(rpc_client, signature).await? {
+        return Ok(Some(outcome));
+    }
+    if lifetime
+        .valid_at(rpc_client, rpc_client.commitment())
+        .await?
+    {
+        return Ok(None);
+    }
+    // It can't land anymore, look once more in case it did right before the blockhash expired
+    Ok(Some(
+        signature_outcome(rpc_client, signature)
+            .await?
+            .unwrap_or_else(|| {
+                (
+                    Outcome::Expired,
+                    Some("blockhash expired before the transaction landed".to_string()),
+                )
+            }),
+    ))
+}
+
+/// Settle whatever of `pending` has an outcome by now, recording it in the audit log and
+/// dropping it from the journal. Returns the entries that are still pending.
+///
+/// `failures` counts the failed lookups of each entry across calls. An entry whose lookup
+/// failed `MAX_RECONCILE_FAILURES` times, or whose blockhash expired by age, is recorded as
+/// expired with the last error rather than tried again.
+pub async fn reconcile_once(
+    journal: &BroadcastJournal,
+    audit: &AuditLog,
+    pending: Vec<PendingBroadcast>,
+    failures: &mut HashMap<String, u32>,
+    rpc_client: impl Fn(Network) -> Arc<RpcClient>,
+) -> Vec<PendingBroadcast> {
+    let mut still_pending = Vec::new();
+    for entry in pending {
+        let (Ok(signature), Some(lifetime)) =
+            (entry.signature.parse::<Signature>(), entry.lifetime())
+        else {
+            tracing::warn!(?entry, "dropping malformed broadcast journal entry");
+            journal.remove(&entry.signature).await;
+            continue;
+        };
+        let rpc_client = rpc_client(entry.net);
+        let (outcome, error) = match settle(&rpc_client, &signature, &lifetime).await {
+            Ok(Some(settled)) => settled,
+            Ok(None) => {
+                still_pending.push(entry);
+                continue;
+            }
+            Err(e) => {
+                let failed = failures.entry(entry.signature.clone()).or_default();
+                *failed += 1;
+                if *failed < MAX_RECONCILE_FAILURES && !entry.outlived_blockhash(unix_millis()) {
+                    tracing::warn!(
+                        signature = %entry.signature,
+                        error = %e,
+                        "failed reconciling broadcast"
+                    );
+                    still_pending.push(entry);
+                    continue;
+                }
+                tracing::warn!(
+                    signature = %entry.signature,
+                    error = %e,
+                    failures = *failed,
+                    "giving up reconciling broadcast"
+                );
+                (
+                    Outcome::Expired,
+                    Some(format!(
+                        "gave up looking it up after {} failed attempts, the last one: {}",
+                        failed, e
+                    )),
+                )
+            }
+        };
+        audit.record(
+            entry.tenant.as_deref(),
+            &entry.endpoint,
+            Some(&signature),
+            entry.request_digest.as_deref(),
+            Some(&redacted_url(&rpc_client.url())),
+            outcome,
+            error,
+            None,
+        );
+        failures.remove(&entry.signature);
+        journal.remove(&entry.signature).await;
+    }
+    still_pending
+}
+
+/// Settle every broadcast journalled before startup, retrying until none is left or each was
+/// given up on. Broadcasts made after startup are confirmed by their own `Broadcast` and never
+/// looked at here.
+pub async fn reconcile(
+    journal: Arc<BroadcastJournal>,
+    audit: Arc<AuditLog>,
+    rpc_client: impl Fn(Network) -> Arc<RpcClient>,
+) {
+    // Scheduled entries were never sent, the scheduler picks those up
+    let mut pending: Vec<_> = journal
+        .entries()
+        .into_iter()
+        .filter(|e| e.scheduled.is_none())
+        .collect();
+    let mut failures = HashMap::new();
+    while !pending.is_empty() {
+        pending = reconcile_once(&journal, &audit, pending, &mut failures, &rpc_client).await;
+        if !pending.is_empty() {
+            tokio::time::sleep(RECONCILE_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use serde_json::json;
+    use solana_client::{nonblocking::rpc_client::RpcClient, rpc_request::RpcRequest};
+    use solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature};
+
+    use crate::{
+        audit::{AuditLog, Outcome},
+        confirmation::Lifetime,
+        journal::{BroadcastJournal, MAX_RECONCILE_FAILURES, reconcile_once},
+        models::Network,
+    };
+
+    fn blockhash_valid(valid: bool) -> HashMap<RpcRequest, serde_json::Value> {
+        HashMap::from([(
+            RpcRequest::IsBlockhashValid,
+            json!({ "context": { "slot": 1 }, "value": valid }),
+        )])
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_after_restart() {
+        let path = std::env::temp_dir().join(format!("journal-{}.json", Hash::new_unique()));
+        let (confirmed, failed, expired) = (
+            Signature::from([1; 64]),
+            Signature::from([2; 64]),
+            Signature::from([3; 64]),
+        );
+        {
+            let journal = BroadcastJournal::load(&path).unwrap();
+            let lifetime = Lifetime::Blockhash(Hash::new_unique());
+            journal
+                .add(
+                    Some("team-a"),
+                    &confirmed,
+                    "send_single",
+                    None,
+                    Network::Devnet,
+                    &lifetime,
+                )
+                .await;
+            journal
+                .add(
+                    None,
+                    &failed,
+                    "spl_send_single",
+                    None,
+                    Network::Testnet,
+                    &lifetime,
+                )
+                .await;
+            journal
+                .add(
+                    None,
+                    &expired,
+                    "aggregate_signatures",
+                    Some("digest"),
+                    Network::Mainnet,
+                    &lifetime,
+                )
+                .await;
+        }
+
+        // The server restarted, each network's mock stands in for one outcome
+        let journal = BroadcastJournal::load(&path).unwrap();
+        let audit = AuditLog::default();
+        let still_pending = reconcile_once(
+            &journal,
+            &audit,
+            journal.entries(),
+            &mut HashMap::new(),
+            |net| {
+                Arc::new(match net {
+                    Network::Devnet => RpcClient::new_mock("succeeds".to_string()),
+                    Network::Testnet => RpcClient::new_mock("instruction_error".to_string()),
+                    Network::Mainnet | Network::Localnet => RpcClient::new_mock_with_mocks(
+                        "sig_not_found".to_string(),
+                        blockhash_valid(false),
+                    ),
+                })
+            },
+        )
+        .await;
+        assert!(still_pending.is_empty());
+
+        let outcome = |signature| {
+            let entries = audit.for_signature(signature);
+            assert_eq!(entries.len(), 1);
+            entries[0].clone()
+        };
+        assert_eq!(outcome(&confirmed).outcome, Outcome::Confirmed);
+        assert_eq!(outcome(&confirmed).tenant.as_deref(), Some("team-a"));
+        assert_eq!(outcome(&failed).tenant, None);
+        assert_eq!(outcome(&failed).outcome, Outcome::Failed);
+        assert!(outcome(&failed).error.is_some());
+        let expired = outcome(&expired);
+        assert_eq!(expired.outcome, Outcome::Expired);
+        assert_eq!(expired.endpoint, "aggregate_signatures");
+        assert_eq!(expired.request_digest.as_deref(), Some("digest"));
+        assert!(BroadcastJournal::load(&path).unwrap().entries().is_empty());
+
+        // A broadcast whose blockhash is still valid stays in the journal
+        journal
+            .add(
+                None,
+                &confirmed,
+                "send_single",
+                None,
+                Network::Devnet,
+                &Lifetime::Blockhash(Hash::new_unique()),
+            )
+            .await;
+        let still_pending = reconcile_once(
+            &journal,
+            &audit,
+            journal.entries(),
+            &mut HashMap::new(),
+            |_| {
+                Arc::new(RpcClient::new_mock_with_mocks(
+                    "sig_not_found".to_string(),
+                    blockhash_valid(true),
+                ))
+            },
+        )
+        .await;
+        assert_eq!(still_pending.len(), 1);
+        assert_eq!(BroadcastJournal::load(&path).unwrap().entries().len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_journal_without_tenants() {
+        // Written before tenants existed
+        let path = std::env::temp_dir().join(format!("journal-{}.json", Hash::new_unique()));
+        std::fs::write(
+            &path,
+            format!(
+                r#"[{{"signature":"{}","endpoint":"send_single","request_digest":null,"net":"devnet","recent_blockhash":"{}","at":0}}]"#,
+                Signature::from([1; 64]),
+                Hash::new_unique()
+            ),
+        )
+        .unwrap();
+        let entries = BroadcastJournal::load(&path).unwrap().entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].tenant, None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_durable_nonce_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("journal-{}.json", Hash::new_unique()));
+        let lifetime = Lifetime::DurableNonce {
+            account: Pubkey::new_unique(),
+            nonce: Hash::new_unique(),
+        };
+        BroadcastJournal::load(&path)
+            .unwrap()
+            .add(
+                None,
+                &Signature::from([1; 64]),
+                "aggregate_signatures",
+                None,
+                Network::Devnet,
+                &lifetime,
+            )
+            .await;
+        let entries = BroadcastJournal::load(&path).unwrap().entries();
+        assert_eq!(entries[0].lifetime(), Some(lifetime));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_gives_up() {
+        let path = std::env::temp_dir().join(format!("journal-{}.json", Hash::new_unique()));
+        let (recent, old) = (Signature::from([1; 64]), Signature::from([2; 64]));
+        // Journalled long before the restart, its blockhash is gone whatever the node says
+        std::fs::write(
+            &path,
+            format!(
+                r#"[{{"signature":"{}","endpoint":"send_single","request_digest":null,"net":"devnet","recent_blockhash":"{}","at":0}}]"#,
+                old,
+                Hash::new_unique()
+            ),
+        )
+        .unwrap();
+        let journal = BroadcastJournal::load(&path).unwrap();
+        let lifetime = Lifetime::DurableNonce {
+            account: Pubkey::new_unique(),
+            nonce: Hash::new_unique(),
+        };
+        journal
+            .add(
+                None,
+                &recent,
+                "send_single",
+                None,
+                Network::Devnet,
+                &lifetime,
+            )
+            .await;
+
+        let audit = AuditLog::default();
+        let failing = |_: Network| Arc::new(RpcClient::new_mock("fails".to_string()));
+        let mut failures = HashMap::new();
+        let mut pending = journal.entries();
+        for _ in 1..MAX_RECONCILE_FAILURES {
+            pending = reconcile_once(&journal, &audit, pending, &mut failures, failing).await;
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].signature, recent.to_string());
+        }
+        assert_eq!(audit.for_signature(&old)[0].outcome, Outcome::Expired);
+
+        // The durable nonce never expires by age, the failures bound it
+        assert!(audit.for_signature(&recent).is_empty());
+        let pending = reconcile_once(&journal, &audit, pending, &mut failures, failing).await;
+        assert!(pending.is_empty());
+        let entry = &audit.for_signature(&recent)[0];
+        assert_eq!(entry.outcome, Outcome::Expired);
+        assert!(entry.error.as_deref().unwrap().contains("failed attempts"));
+        assert!(BroadcastJournal::load(&path).unwrap().entries().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+pub mod access_log;
+pub mod account_batch;
+pub mod audit;
+pub mod audit_export;
+pub mod bounded_store;
+pub mod broadcast;
+pub mod case;
+pub mod ceremony;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod clock;
+pub mod compute_budget;
+pub mod config;
+pub mod confirmation;
+pub mod confirmation_stream;
+pub mod cost;
+pub mod durable_nonce;
+pub mod ed25519_verify;
+pub mod envelope;
+pub mod error;
+pub mod etag;
+pub mod faucet;
+#[cfg(test)]
+pub(crate) mod fixtures;
+pub mod funding;
+pub mod input;
+pub mod journal;
+pub mod message_review;
+pub mod mnemonic;
+pub mod models;
+pub mod openapi;
+pub mod policy;
+pub mod presigned;
+pub mod rate_limit;
+pub mod recipient;
+pub mod rent_reclaim;
+pub mod request_digest;
+pub mod rpc_clients;
+pub mod rpc_health;
+pub mod rpc_methods;
+pub mod rpc_override;
+pub mod scheduler;
+pub mod secret_states;
+pub mod serialization;
+pub mod signature_context;
+pub mod signature_status;
+pub mod simulation;
+pub mod solana_pay;
+pub mod spend_limit;
+pub mod spl_token_utils;
+pub mod staking;
+pub mod startup;
+pub mod state;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod tenant;
+pub mod threshold;
+pub mod timing;
+pub mod tls;
+pub mod token_registry;
+pub mod transaction_builder;
+pub mod tss;
+pub mod ui;
+pub mod units;
+pub mod usage;
+pub mod version;
+pub mod warning;
+
+pub use error::Error;
@@ -1,1271 +1,10842 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::Parser;
+use ed25519_dalek::KEYPAIR_LENGTH;
+use futures_util::StreamExt;
 use poem::{
-    IntoResponse, Response, Route, Server, get, handler, listener::TcpListener, post, web::Json,
+    Body, Endpoint, EndpointExt, IntoResponse, Response, Route, Server, get, handler,
+    http::HeaderMap,
+    http::{HeaderValue, Method, StatusCode, header},
+    listener::Listener,
+    post,
+    web::{Data, Json, Path, Query, websocket::WebSocket},
 };
 use serde_json;
-use solana_client::rpc_client::RpcClient;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient as AsyncRpcClient,
+};
 use solana_sdk::{
     hash::Hash as SolanaHash,
-    native_token,
-    program_pack::Pack,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
     pubkey::Pubkey,
-    signature::{Keypair, Signer},
+    signature::{Keypair, Signature, Signer},
     transaction::Transaction,
 };
-use std::str::FromStr;
-
-use crate::{
-    error::Error,
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+#[cfg(feature = "chaos")]
+use solana_tss_api_backend::chaos::ChaosConfig;
+#[cfg(feature = "otel")]
+use solana_tss_api_backend::telemetry;
+use solana_tss_api_backend::{
+    access_log,
+    audit::Outcome,
+    audit_export::{self, ExportRange},
+    broadcast::{Broadcast, Sent},
+    case::{self, FieldCase},
+    clock::{self, ClockSkew, SkewReading, measure_skew},
+    compute_budget::{ComputeUnitReport, resolve_compute_unit_limit},
+    config::{Args, Config, ConfigSource},
+    confirmation::{ConfirmationOutcome, Lifetime, confirm, confirm_all},
+    confirmation_stream,
+    cost::{self, debits, message_cost},
+    durable_nonce::{
+        DurableNonce, check_authority, check_nonce, create_nonce_account_transaction,
+        fetch_nonce_account, nonce_account_rent, withdraw_nonce_transaction, withdrawal,
+    },
+    envelope,
+    error::{Error, ErrorClass},
+    etag,
+    faucet::{
+        BATCH_BACKOFF, BATCH_CONCURRENCY, Backoff, InternalFaucet, MAX_BATCH_RECIPIENTS,
+        is_rate_limited, request_with_backoff,
+    },
+    funding::check_funded,
+    input::{keypair_json_bytes, normalize, normalize_secret},
+    journal::{ScheduledBroadcast, reconcile},
+    message_review::{
+        CompiledMessage, account_keys, check_message_hash, encode_transaction, message_hash, review,
+    },
+    mnemonic::{DEFAULT_DERIVATION_PATH, derive_keypair, generate_mnemonic, parse_mnemonic},
     models::*,
-    serialization::{AggMessage1, PartialSignature, SecretAggStepOne, Serialize},
+    openapi,
+    presigned::{decode_transaction, verify_signatures},
+    rate_limit::{self, Class},
+    recipient::check_recipient,
+    rent_reclaim::{
+        DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE, TokenAccountStatus, plan_chunks, scan_token_accounts,
+    },
+    request_digest::{Transfer, request_digest, resolve_lamports, resolve_token_amount},
+    rpc_clients, rpc_override,
+    serialization::{
+        AggMessage1, Error as DeserializationError, FieldError, KeygenMessage1, PartialSignature,
+        SecretAggStepOne, Serialize, ThresholdKeyShare,
+    },
+    signature_context::{self, Expected},
+    signature_status::{MAX_SIGNATURES, transaction_statuses as signature_statuses},
+    simulation,
+    solana_pay::{self, TransferRequest},
+    spend_limit::{Asset, Reservation, api_key_id},
     staking::{
-        create_deactivate_stake_transaction, create_stake_account_transaction,
-        create_withdraw_stake_transaction,
+        NewStake, StakeWithdrawal, create_deactivate_stake_transaction,
+        create_stake_account_transaction, create_withdraw_stake_transaction,
+    },
+    tenant,
+    threshold::{
+        ThresholdSigners, check_confirmations, confirm_keygen, group_pubkey, keygen_step_one,
+        keygen_step_two, keygen_transcript, signer_indices,
+    },
+    timing::{self, Collector, TimingLayer, add_timings},
+    tls,
+    token_registry::{
+        ResolvedToken, TokenConfig, TokenRegistry, chain_mismatch, fetch_mint_decimals,
     },
     tss::{
-        aggregate_deactivate_stake_signatures_and_broadcast,
+        MIN_KEYS, aggregate_deactivate_stake_signatures_and_broadcast, aggregate_payload_signature,
         aggregate_stake_signatures_and_broadcast,
-        aggregate_withdraw_stake_signatures_and_broadcast, deactivate_stake_step_two, key_agg,
-        sign_and_broadcast, spl_sign_and_broadcast, spl_step_two, stake_step_two, step_one,
-        step_two, withdraw_stake_step_two,
+        aggregate_withdraw_stake_signatures_and_broadcast, aggregated_pubkey, check_not_message,
+        check_transfer_size, deactivate_stake_step_two, key_agg, party_index, payload_step_two,
+        sign_and_broadcast, spl_sign_and_broadcast, spl_step_two, spl_transfer_message,
+        stake_step_two, step_one, step_two, threshold_step_two, transfer_message,
+        withdraw_stake_step_two,
     },
+    version::version_info,
+    warning::{Warned, WarningCode},
 };
 
-use spl_token::state::{Account, Mint};
-
-use crate::{
+use solana_tss_api_backend::{
     models::{
         SplAggSendStepTwoRequest, SplAggSendStepTwoResponse, SplAggregateSignaturesRequest,
         SplAggregateSignaturesResponse, SplSendSingleRequest, SplSendSingleResponse,
-        SplTokenBalanceRequest, SplTokenBalanceResponse,
+        SplTokenBalanceRequest, UsageStatsResponse,
+    },
+    spl_token_utils::{
+        TokenProgram, account_exists, fetch_token_program, token_balance, validate_source_account,
     },
-    spl_token_utils::create_spl_token_transaction,
+    startup::{Severity, StartupReport, run_startup_checks},
+    state::{AppState, Snapshot},
+    transaction_builder::{
+        MAX_TRANSFER_RECIPIENTS, ProgramCallBuilder, SplTransferBuilder, TransferBuilder,
+        resolve_memo_program,
+    },
+    ui,
+    units::{Decimals, Lamports, RawTokenAmount, UiAmount, float_amount},
 };
-use spl_associated_token_account::get_associated_token_address;
-mod error;
-mod models;
-mod serialization;
-mod spl_token_utils;
-mod staking;
-mod tss;
-
-pub fn create_unsigned_transaction(
-    amount: f64,
-    to: &Pubkey,
-    memo: Option<String>,
-    payer: &Pubkey,
-) -> Transaction {
-    let amount = native_token::sol_to_lamports(amount);
-    let transfer_ins = solana_sdk::system_instruction::transfer(payer, to, amount);
-    let msg = match memo {
-        None => solana_sdk::message::Message::new(&[transfer_ins], Some(payer)),
-        Some(memo) => {
-            let memo_ins = solana_sdk::instruction::Instruction {
-                program_id: spl_memo::id(),
-                accounts: Vec::new(),
-                data: memo.into_bytes(),
-            };
-            solana_sdk::message::Message::new(&[transfer_ins, memo_ins], Some(payer))
-        }
-    };
-    Transaction::new_unsigned(msg)
-}
 
-fn parse_keypair_bs58(s: &str) -> Result<Keypair, Error> {
-    let decoded = bs58::decode(s).into_vec()?;
+/// A keypair as base58 or as the JSON array of its bytes `solana-keygen` writes.
+fn parse_keypair(s: &str) -> Result<Keypair, Error> {
+    let decoded = match s.trim_start().starts_with('[') {
+        true => keypair_json_bytes(s).ok_or_else(|| {
+            Error::InvalidKeypair(format!(
+                "a JSON keypair is an array of {} numbers from 0 to 255",
+                KEYPAIR_LENGTH
+            ))
+        })?,
+        false => bs58::decode(normalize_secret(s)?.as_ref())
+            .into_vec()
+            .map_err(secret_base58_error)?,
+    };
+    if decoded.len() != KEYPAIR_LENGTH {
+        return Err(Error::InvalidKeypair(format!(
+            "expected {} bytes, got {}",
+            KEYPAIR_LENGTH,
+            decoded.len()
+        )));
+    }
     Ok(Keypair::from_bytes(&decoded)?)
 }
 
+/// Like `normalize_secret`, a bad character in a secret is reported by its position only.
+fn secret_base58_error(e: bs58::decode::Error) -> Error {
+    match e {
+        bs58::decode::Error::InvalidCharacter { index, .. }
+        | bs58::decode::Error::NonAsciiCharacter { index } => {
+            Error::InvalidKeypair(format!("invalid base58 character at position {}", index))
+        }
+        _ => Error::InvalidKeypair("not valid base58".to_string()),
+    }
+}
+
 fn parse_pubkey(s: &str) -> Result<Pubkey, Error> {
-    Pubkey::from_str(s).map_err(|_| {
-        Error::BadBase58(bs58::decode::Error::InvalidCharacter {
-            character: ' ',
-            index: 0,
-        })
-    })
+    let s = normalize(s)?;
+    bs58::decode(s.as_ref())
+        .into_vec()
+        .ok()
+        .and_then(|decoded| Pubkey::try_from(decoded.as_slice()).ok())
+        .ok_or_else(|| Error::InvalidPublicKey(s.to_string()))
+}
+
+fn parse_pubkeys(keys: &[String]) -> Result<Vec<Pubkey>, Error> {
+    keys.iter().map(|k| parse_pubkey(k)).collect()
 }
 
 fn parse_hash(s: &str) -> Result<SolanaHash, Error> {
-    SolanaHash::from_str(s).map_err(|_| {
-        Error::BadBase58(bs58::decode::Error::InvalidCharacter {
-            character: ' ',
-            index: 0,
+    let s = normalize(s)?;
+    bs58::decode(s.as_ref())
+        .into_vec()
+        .ok()
+        .and_then(|decoded| <[u8; 32]>::try_from(decoded.as_slice()).ok())
+        .map(SolanaHash::new_from_array)
+        .ok_or_else(|| Error::InvalidBlockHash(s.to_string()))
+}
+
+fn parse_payload(s: &str) -> Result<Vec<u8>, Error> {
+    STANDARD
+        .decode(s.trim())
+        .map_err(|e| Error::InvalidPayload(format!("not base64: {}", e)))
+}
+
+fn parse_instruction(instruction: &ProgramInstruction) -> Result<Instruction, Error> {
+    let accounts = instruction
+        .accounts
+        .iter()
+        .map(|account| {
+            Ok(AccountMeta {
+                pubkey: parse_pubkey(&account.pubkey)?,
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
         })
+        .collect::<Result<_, Error>>()?;
+    let data = STANDARD.decode(instruction.data.trim()).map_err(|e| {
+        Error::TransactionCreationFailed(format!("instruction data isn't base64: {}", e))
+    })?;
+    Ok(Instruction {
+        program_id: parse_pubkey(&instruction.program_id)?,
+        accounts,
+        data,
     })
 }
 
-//  function to create error responses
-fn error_response(error: String) -> Response {
-    let error_resp = ErrorResponse { error };
-    Response::builder()
-        .status(poem::http::StatusCode::BAD_REQUEST)
-        .content_type("application/json")
-        .body(serde_json::to_string(&error_resp).unwrap_or_default())
+/// Bound the party arrays before any of them is parsed or fed to the EC math, `keys` needs at
+/// least `MIN_KEYS` entries as well.
+fn check_party_count(config: &Config, field: &'static str, len: usize) -> Result<(), Error> {
+    if field == "keys" && len < MIN_KEYS {
+        return Err(Error::TooFewKeys(len));
+    }
+    if len > config.max_parties {
+        return Err(Error::TooManyParties {
+            field,
+            len,
+            max: config.max_parties,
+        });
+    }
+    Ok(())
 }
 
-//  function to create success responses
-fn success_response<T: serde::Serialize>(data: T) -> Response {
-    Response::builder()
-        .status(poem::http::StatusCode::OK)
-        .content_type("application/json")
-        .body(serde_json::to_string(&data).unwrap_or_default())
+/// `blob` in base58, labeled with the configured environment.
+fn labeled(config: &Config, blob: &impl Serialize) -> String {
+    blob.serialize_bs58_in(config.environment.as_deref())
 }
 
-#[handler]
-async fn generate_keypair() -> impl IntoResponse {
-    let keypair = Keypair::generate(&mut rand07::thread_rng());
-    let response = GenerateKeypairResponse {
-        secret_share: keypair.to_base58_string(),
-        public_share: keypair.pubkey().to_string(),
-    };
-    success_response(response)
+/// Refuse `blobs` labeled with another environment than the configured one, or with none.
+/// Without a configured environment every blob is accepted.
+fn check_environment<T: Serialize>(
+    config: &Config,
+    field: &'static str,
+    blobs: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<(), Error> {
+    let expected = match config.environment.clone() {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    for blob in blobs {
+        let found = match T::environment_bs58(blob) {
+            Ok(found) => found,
+            Err(error @ DeserializationError::BadEnvironment) => {
+                return Err(Error::DeserializationFailed {
+                    error,
+                    field_name: field,
+                });
+            }
+            // Reported by the deserialization of the blob
+            Err(_) => continue,
+        };
+        if found.as_deref() != Some(expected.as_str()) {
+            return Err(Error::EnvironmentMismatch {
+                field,
+                expected,
+                found,
+            });
+        }
+    }
+    Ok(())
 }
 
-#[handler]
-async fn balance(req: Json<BalanceRequest>) -> impl IntoResponse {
-    let address = match parse_pubkey(&req.address) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
-    };
+/// The `keys` of the parties, as many as `check_party_count` allows.
+fn parse_keys(config: &Config, keys: &[String]) -> Result<Vec<Pubkey>, Error> {
+    check_party_count(config, "keys", keys.len())?;
+    parse_pubkeys(keys)
+}
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let balance = match rpc_client.get_balance(&address) {
-        Ok(bal) => bal,
-        Err(e) => return error_response(Error::BalaceFailed(e).to_string()),
-    };
+/// The `first_messages` of a step two, refusing those labeled with another environment.
+fn parse_first_messages<T: Serialize>(config: &Config, blobs: &[String]) -> Result<Vec<T>, Error> {
+    check_party_count(config, "first_messages", blobs.len())?;
+    check_environment::<T>(config, "first_messages", blobs)?;
+    blobs
+        .iter()
+        .map(|m| T::deserialize_bs58(m).with_field("first_messages"))
+        .collect()
+}
 
-    let response = BalanceResponse {
-        address: address.to_string(),
-        balance,
-    };
-    success_response(response)
+/// The partial `signatures` of an aggregation, refusing those labeled with another
+/// environment.
+fn parse_signatures(
+    config: &Config,
+    signatures: &[impl AsRef<str>],
+) -> Result<Vec<PartialSignature>, Error> {
+    check_party_count(config, "signatures", signatures.len())?;
+    check_environment::<PartialSignature>(config, "signatures", signatures)?;
+    signatures
+        .iter()
+        .map(|s| PartialSignature::deserialize_bs58(s.as_ref()).with_field("signatures"))
+        .collect()
 }
 
-#[handler]
-async fn airdrop(req: Json<AirdropRequest>) -> impl IntoResponse {
-    let to = match parse_pubkey(&req.to) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
-    };
+/// The memo program and the Solana Pay `references` of a transfer.
+fn transfer_extras(
+    config: &Config,
+    memo_program: Option<&str>,
+    references: &[String],
+) -> Result<(Pubkey, Vec<Pubkey>), Error> {
+    let memo_program = resolve_memo_program(memo_program, config.allow_custom_memo_program)?;
+    Ok((memo_program, parse_pubkeys(references)?))
+}
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let amount = native_token::sol_to_lamports(req.amount);
+/// A keypair outside `keys` would sign a share that can never aggregate, catch it before any
+/// cryptography runs.
+fn check_signer_in_keys(signer: &Pubkey, keys: &[Pubkey]) -> Result<usize, Error> {
+    keys.iter()
+        .position(|key| key == signer)
+        .ok_or_else(|| Error::SignerNotInKeySet {
+            signer: *signer,
+            keys: keys.to_vec(),
+        })
+}
 
-    let sig = match rpc_client.request_airdrop(&to, amount) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::AirdropFailed(e).to_string()),
+/// Compare the `context` of every signature with the aggregation of `request_digest` by `keys`
+/// for `aggregated_pubkey` before any of them is combined.
+fn check_signature_contexts(
+    config: &Config,
+    signatures: &[SignatureSubmission],
+    session_id: Option<&str>,
+    request_digest: &str,
+    aggregated_pubkey: &Pubkey,
+    keys: &[Pubkey],
+) -> Result<(), Error> {
+    if signatures.iter().all(|s| s.context().is_none()) {
+        return Ok(());
+    }
+    let expected = Expected {
+        request_digest,
+        aggregated_pubkey,
+        environment: config.environment.as_deref(),
+        session_id,
+        keys,
     };
+    signature_context::check(&expected, signatures)
+}
 
-    let recent_hash = match rpc_client.get_latest_blockhash() {
-        Ok(hash) => hash,
-        Err(e) => return error_response(Error::RecentHashFailed(e).to_string()),
+/// Resolve the token of an SPL transfer through the registry.
+fn resolve_token(
+    tokens: &TokenRegistry,
+    token: Option<&str>,
+    token_mint: Option<&str>,
+    decimals: Option<u8>,
+    net: Option<Network>,
+) -> Result<ResolvedToken, Error> {
+    tokens.resolve(token, token_mint, decimals, net)
+}
+
+/// Base units of a transfer of `token` given as `amount` or `amount_base_units`, at most its
+/// `max_per_transaction`.
+fn spl_amount(
+    token: &ResolvedToken,
+    amount: Option<&UiAmount>,
+    base_units: Option<u64>,
+) -> Result<RawTokenAmount, Error> {
+    let amount = resolve_token_amount(amount, base_units, token.decimals)?;
+    token.check_amount(amount)?;
+    Ok(amount)
+}
+
+/// `error` about the amount of `recipients[i]`, naming the field from the request's root.
+fn in_recipient(i: usize, error: Error) -> Error {
+    match error {
+        Error::AmountRejected { field, reason } => Error::AmountRejected {
+            field: format!("recipients[{}].{}", i, field),
+            reason,
+        },
+        error => error,
+    }
+}
+
+/// The TSS transfer always carries an idempotent create of the recipient's token account, for
+/// a token that doesn't allow creating one the account has to exist already.
+async fn check_recipient_ata(
+    token: &ResolvedToken,
+    to: &Pubkey,
+    rpc_client: Option<&AsyncRpcClient>,
+) -> Result<(), Error> {
+    if token.allow_create_ata {
+        return Ok(());
+    }
+    let recipient_ata = token.ata(to);
+    let exists = match rpc_client {
+        Some(rpc_client) => Some(account_exists(rpc_client, &recipient_ata).await?),
+        None => None,
     };
+    token.check_recipient_ata(&recipient_ata, exists)
+}
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
+/// Settle the program of `token`: the one the request names, else the owner of the mint when
+/// there's a node to ask, else the classic token program. With a node the mint is looked up
+/// either way, one with an extension transfers don't support is refused before anything else.
+async fn resolve_token_program(
+    token: &mut ResolvedToken,
+    requested: Option<TokenProgram>,
+    rpc_client: Option<&AsyncRpcClient>,
+) -> Result<(), Error> {
+    let owner = match rpc_client {
+        Some(rpc_client) => Some(fetch_token_program(rpc_client, &token.mint).await?),
+        None => None,
+    };
+    token.program = requested.or(owner).unwrap_or(TokenProgram::SplToken);
+    Ok(())
+}
+
+/// Book a transfer against the spend limits before broadcasting it, `None` when none are set.
+fn reserve_spend(
+    snapshot: &Snapshot,
+    headers: &HeaderMap,
+    asset: Asset,
+    amount: u64,
+) -> Result<Option<Reservation>, Error> {
+    let Some(guard) = &snapshot.spend_guard else {
+        return Ok(None);
+    };
+    guard
+        .reserve(
+            Some(api_key_id(&snapshot.tenants, headers).as_str()),
+            asset,
+            amount,
+            Instant::now(),
+        )
+        .map(Some)
+}
+
+/// Hand the booking back if the node rejected the transaction, anything that got past
+/// `send_transaction` may still land and keeps counting.
+fn release_spend(snapshot: &Snapshot, reservation: Option<Reservation>, error: &Error) {
+    if let (Some(guard), Some(reservation), Error::SendTransactionFailed(_)) =
+        (&snapshot.spend_guard, reservation, error)
     {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+        guard.release(reservation);
     }
+}
 
-    let response = AirdropResponse {
-        transaction_id: sig.to_string(),
-    };
-    success_response(response)
+fn idempotency_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("Idempotency-Key")?
+        .to_str()
+        .ok()
+        .map(str::to_string)
 }
 
-#[handler]
-async fn send_single(req: Json<SendSingleRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
-    };
+/// Hand a signed transaction to the scheduler instead of sending it now. Its spend limit
+/// booking is kept until it is sent or cancelled.
+async fn schedule_broadcast(
+    state: &AppState,
+    snapshot: &Snapshot,
+    headers: &HeaderMap,
+    endpoint: &str,
+    request_digest: Option<&str>,
+    net: Network,
+    scheduled: ScheduledBroadcast,
+    reservation: Option<Reservation>,
+) -> Result<Signature, Error> {
+    let result = match &state.scheduler {
+        Some(scheduler) => {
+            scheduler
+                .hold(
+                    &async_rpc_client(state, &snapshot.config, net),
+                    snapshot.tenants.caller(headers).tenant.as_deref(),
+                    endpoint,
+                    request_digest,
+                    net,
+                    scheduled,
+                )
+                .await
+        }
+        None => Err(Error::InvalidBroadcastAt(
+            "scheduling needs a broadcast_journal in the config".to_string(),
+        )),
+    };
+    match (result, reservation) {
+        (Ok(signature), Some(reservation)) => {
+            if let Some(scheduler) = &state.scheduler {
+                scheduler.hold_reservation(signature, reservation);
+            }
+            Ok(signature)
+        }
+        (Err(e), Some(reservation)) => {
+            if let Some(guard) = &snapshot.spend_guard {
+                guard.release(reservation);
+            }
+            Err(e)
+        }
+        (result, None) => result,
+    }
+}
 
-    let to = match parse_pubkey(&req.to) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
-    };
+/// The network a request asked for, or the configured `default_network` when it left `net` out.
+fn resolve_network(config: &Config, net: Option<Network>) -> Result<Network, Error> {
+    net.or(config.default_network).ok_or(Error::MissingNetwork)
+}
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let mut tx = create_unsigned_transaction(req.amount, &to, req.memo.clone(), &keypair.pubkey());
+/// Client for `net`, through the request's `rpc_url` when it sent one. Shared with every other
+/// request to the same URL.
+fn async_rpc_client(state: &AppState, config: &Config, net: Network) -> Arc<AsyncRpcClient> {
+    #[cfg(feature = "chaos")]
+    let build = |url: &str| state.chaos.async_rpc_client(net, url);
+    #[cfg(not(feature = "chaos"))]
+    let build = |url: &str| rpc_clients::traced(url.to_string());
+    match rpc_override::current() {
+        Some(url) => state
+            .rpc_clients
+            .requested(net, &url, || build(&url), Instant::now()),
+        None => {
+            let url = config.cluster_url(net);
+            state.rpc_clients.configured(net, url, || build(url))
+        }
+    }
+}
 
-    let recent_hash = match rpc_client.get_latest_blockhash() {
-        Ok(hash) => hash,
-        Err(e) => return error_response(Error::RecentHashFailed(e).to_string()),
+//  function to create error responses, with the status, code and class of the error
+fn error_response(error: Error) -> Response {
+    let error = error.or_unsupported();
+    let (status, error_code) = (error.status(), error.error_code());
+    match status.is_server_error() {
+        true => tracing::warn!(error_code, error = %error, "request failed"),
+        false => tracing::info!(error_code, error = %error, "request failed"),
+    }
+    // What a node named in `rpc_url` answered, or why it couldn't be reached, stays in the log:
+    // the caller could otherwise probe hosts through this server
+    let requested_node = rpc_override::current().is_some()
+        && matches!(
+            error.class(),
+            ErrorClass::Node
+                | ErrorClass::Transport
+                | ErrorClass::RateLimited
+                | ErrorClass::Timeout
+        );
+    let (message, details) = match requested_node {
+        true => ("the node of rpc_url failed".to_string(), None),
+        false => (error.to_string(), error.details()),
     };
+    let error_resp = ErrorResponse {
+        error: message,
+        error_code: Some(error_code.to_string()),
+        details,
+        error_class: error.class().as_str().to_string(),
+        retryable: error.retryable(),
+    };
+    Response::builder()
+        .status(status)
+        .content_type("application/json")
+        .body(serde_json::to_string(&error_resp).unwrap_or_default())
+}
 
-    tx.sign(&[&keypair], recent_hash);
+//  function to create success responses
+fn success_response<T: serde::Serialize>(data: T) -> Response {
+    let _span = tracing::info_span!("response.serialize").entered();
+    match serde_json::to_string(&data) {
+        Ok(body) => Response::builder()
+            .status(poem::http::StatusCode::OK)
+            .content_type("application/json")
+            .body(body),
+        Err(e) => error_response(Error::Internal(format!("serializing the response: {}", e))),
+    }
+}
 
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
-    };
+/// Response header naming the network a request was served on.
+const NETWORK_HEADER: &str = "X-Network";
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
-    {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+//  function to create success responses of requests served on `net`
+fn network_response<T: serde::Serialize>(net: Network, data: T) -> Response {
+    let mut resp = success_response(data);
+    resp.headers_mut()
+        .insert(NETWORK_HEADER, HeaderValue::from_static(net.as_str()));
+    resp
+}
+
+/// Audit the partial signature a step two made, with the message it signed. Returns the id
+/// of the entry.
+fn audit_signed(
+    state: &AppState,
+    snapshot: &Snapshot,
+    headers: &HeaderMap,
+    endpoint: &str,
+    request_digest: Option<&str>,
+    message: &CompiledMessage,
+) -> u64 {
+    state.audit.record(
+        snapshot.tenants.caller(headers).tenant.as_deref(),
+        endpoint,
+        None,
+        request_digest,
+        None,
+        Outcome::Signed,
+        None,
+        Some(message),
+    )
+}
+
+/// Audit the transaction a `dry_run` aggregation handed back instead of sending it.
+fn audit_dry_run(
+    state: &AppState,
+    snapshot: &Snapshot,
+    headers: &HeaderMap,
+    endpoint: &str,
+    request_digest: &str,
+    tx: &Transaction,
+) {
+    state.audit.record(
+        snapshot.tenants.caller(headers).tenant.as_deref(),
+        endpoint,
+        tx.signatures.first(),
+        Some(request_digest),
+        None,
+        Outcome::DryRun,
+        None,
+        Some(&CompiledMessage::new(tx.message_data())),
+    );
+}
+
+/// Audit a change of the token registry by the caller of `headers`.
+fn audit_token(
+    state: &AppState,
+    snapshot: &Snapshot,
+    headers: &HeaderMap,
+    endpoint: &str,
+    outcome: Outcome,
+    symbol: &str,
+) {
+    state.audit.record_token(
+        snapshot.tenants.caller(headers).tenant.as_deref(),
+        endpoint,
+        outcome,
+        symbol,
+    );
+}
+
+fn serve_ui(name: &str, headers: &HeaderMap) -> Response {
+    match ui::asset(name) {
+        Some(asset) => asset.response(headers),
+        None => Response::builder().status(StatusCode::NOT_FOUND).finish(),
     }
+}
 
-    let response = SendSingleResponse {
-        transaction_id: sig.to_string(),
-    };
-    success_response(response)
+#[handler]
+fn ui_index(headers: &HeaderMap) -> Response {
+    serve_ui("index.html", headers)
 }
 
 #[handler]
-async fn recent_block_hash(req: Json<RecentBlockHashRequest>) -> impl IntoResponse {
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let recent_hash = match rpc_client.get_latest_blockhash() {
-        Ok(hash) => hash,
-        Err(e) => return error_response(Error::RecentHashFailed(e).to_string()),
-    };
+fn ui_asset(Path(name): Path<String>, headers: &HeaderMap) -> Response {
+    serve_ui(&name, headers)
+}
 
-    let response = RecentBlockHashResponse {
-        recent_block_hash: recent_hash.to_string(),
-    };
-    success_response(response)
+#[handler]
+async fn generate_keypair(Query(query): Query<GenerateKeypairQuery>) -> impl IntoResponse {
+    if !query.mnemonic {
+        let keypair = Keypair::generate(&mut rand07::thread_rng());
+        return success_response(GenerateKeypairResponse {
+            secret_share: keypair.to_base58_string(),
+            public_share: keypair.pubkey().to_string(),
+            mnemonic: None,
+            derivation_path: None,
+        });
+    }
+    let mnemonic = generate_mnemonic();
+    match derive_keypair(&mnemonic, "", None) {
+        Ok(keypair) => success_response(GenerateKeypairResponse {
+            secret_share: keypair.to_base58_string(),
+            public_share: keypair.pubkey().to_string(),
+            mnemonic: Some(mnemonic.phrase().to_string()),
+            derivation_path: Some(DEFAULT_DERIVATION_PATH.to_string()),
+        }),
+        Err(e) => error_response(e),
+    }
 }
 
 #[handler]
-async fn aggregate_keys(req: Json<AggregateKeysRequest>) -> impl IntoResponse {
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
+async fn keypair_from_mnemonic(req: Json<KeypairFromMnemonicRequest>) -> impl IntoResponse {
+    let keypair = match parse_mnemonic(&req.mnemonic)
+        .and_then(|m| derive_keypair(&m, &req.passphrase, req.derivation_path.as_deref()))
     {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+        Ok(keypair) => keypair,
+        Err(e) => return error_response(e),
     };
+    success_response(GenerateKeypairResponse {
+        secret_share: keypair.to_base58_string(),
+        public_share: keypair.pubkey().to_string(),
+        mnemonic: None,
+        derivation_path: None,
+    })
+}
 
-    let aggkey = match key_agg(keys, None) {
-        Ok(key) => key,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn convert_keypair(req: Json<ConvertKeypairRequest>) -> impl IntoResponse {
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
     };
+    success_response(ConvertKeypairResponse {
+        public_share: keypair.pubkey().to_string(),
+        secret_share: keypair.to_base58_string(),
+        bytes: keypair.to_bytes().to_vec(),
+    })
+}
 
-    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
-    let response = AggregateKeysResponse {
-        aggregated_public_key: aggpubkey.to_string(),
+#[handler]
+async fn capabilities(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let response = CapabilitiesResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        serialization: SerializationCapabilities::current(),
+        networks: Network::ALL.to_vec(),
+        default_network: snapshot.config.default_network,
+        features: snapshot.config.enabled_features(),
+        limits: snapshot.config.limits(),
+        endpoints: ENDPOINTS
+            .iter()
+            .map(|(method, path, _)| EndpointInfo {
+                method: method.to_string(),
+                path: path.to_string(),
+            })
+            .collect(),
+        warning_codes: WarningCode::ALL
+            .iter()
+            .map(|code| WarningCodeInfo {
+                code: code.as_str().to_string(),
+                description: code.description().to_string(),
+            })
+            .collect(),
+        error_classes: ErrorClass::ALL
+            .iter()
+            .map(|class| ErrorClassInfo {
+                class: class.as_str().to_string(),
+                retryable: class.retryable(),
+                description: class.description().to_string(),
+            })
+            .collect(),
     };
     success_response(response)
 }
 
 #[handler]
-async fn agg_send_step_one(req: Json<AggSendStepOneRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
-    };
+async fn version(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    success_response(version_info(&snapshot.config))
+}
 
-    let (first_msg, secret) = step_one(keypair);
-    let response = AggSendStepOneResponse {
-        message_1: first_msg.serialize_bs58(),
-        secret_state: secret.serialize_bs58(),
-    };
-    success_response(response)
+#[handler]
+async fn openapi_json() -> impl IntoResponse {
+    success_response(openapi::document())
 }
 
 #[handler]
-async fn agg_send_step_two(req: Json<AggSendStepTwoRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
-    };
+fn openapi_docs() -> Response {
+    Response::builder()
+        .content_type("text/html; charset=utf-8")
+        .header(header::CONTENT_SECURITY_POLICY, openapi::docs_csp())
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(openapi::docs_page())
+}
 
-    let to = match parse_pubkey(&req.to) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
-    };
+#[handler]
+fn openapi_docs_file(Path(name): Path<String>) -> Response {
+    match openapi::docs_file(&name) {
+        Some((content_type, body)) => Response::builder()
+            .content_type(content_type)
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .body(body),
+        None => Response::builder().status(StatusCode::NOT_FOUND).finish(),
+    }
+}
 
-    let block_hash = match parse_hash(&req.recent_block_hash) {
-        Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
-    };
+#[handler]
+async fn spend_limits(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    match &snapshot.spend_guard {
+        Some(guard) => success_response(guard.consumption(Instant::now())),
+        None => success_response(SpendLimitsResponse::default()),
+    }
+}
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
-    {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
-    };
+#[handler]
+async fn state_stats(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let mut stores = vec![
+        state.audit.stats(),
+        state.audit.ceremonies().stats(),
+        state.rpc_clients.stats(),
+        state.secret_states.stats(),
+        state.rate_limiter.stats(),
+    ];
+    if let Some(scheduler) = &state.scheduler {
+        stores.push(scheduler.idempotency_key_stats());
+    }
+    success_response(StateStatsResponse { stores })
+}
 
-    let first_messages: Vec<AggMessage1> = match req
-        .first_messages
-        .iter()
-        .map(|m| AggMessage1::deserialize_bs58(m))
-        .collect::<Result<_, _>>()
-    {
-        Ok(msgs) => msgs,
-        Err(e) => return error_response(e.to_string()),
-    };
+/// Always a 200 when the server answers at all, an unreachable node only degrades the status.
+#[handler]
+async fn health(
+    Query(query): Query<HealthQuery>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let config = &snapshot.config;
+    let reading = state.clock.latest();
+    let clock_skewed = match (&config.clock_check, reading) {
+        (Some(check), Some(reading)) => clock::exceeds(check, reading.skew_ms),
+        _ => false,
+    };
+    let networks = match query.rpc {
+        true => {
+            // The local validator is only probed once one is configured
+            let clients = Network::ALL
+                .into_iter()
+                .filter(|net| *net != Network::Localnet || config.rpc_urls.contains_key(net))
+                .map(|net| (net, async_rpc_client(&state, &snapshot.config, net)))
+                .collect();
+            state.rpc_health.check(clients, Instant::now()).await
+        }
+        false => Vec::new(),
+    };
+    let unavailable = !networks.is_empty() && networks.iter().all(|net| !net.reachable);
+    let degraded = clock_skewed || networks.iter().any(|net| !net.reachable);
+    let status = match (unavailable, degraded) {
+        (true, _) => HealthStatus::Unavailable,
+        (false, true) => HealthStatus::Degraded,
+        (false, false) => HealthStatus::Ok,
+    };
+    let mut resp = success_response(HealthResponse {
+        status,
+        clock_skew_ms: reading.map(|reading| reading.skew_ms),
+        clock_checked_at: reading.map(|reading| reading.at),
+        clock_skewed,
+        uptime_secs: state.started.elapsed().as_secs(),
+        networks,
+    });
+    if status == HealthStatus::Unavailable {
+        resp.set_status(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    resp
+}
 
-    let secret_state = match SecretAggStepOne::deserialize_bs58(&req.secret_state) {
-        Ok(state) => state,
-        Err(e) => return error_response(e.to_string()),
-    };
+#[handler]
+async fn rpc_stats(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    success_response(RpcStatsResponse {
+        account_fetches: state.accounts.stats(),
+        broadcasts: state.audit.endpoint_outcomes(),
+    })
+}
 
-    let sig = match step_two(
-        keypair,
-        req.amount,
-        to,
-        req.memo.clone(),
-        block_hash,
-        keys,
-        first_messages,
-        secret_state,
-    ) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(e.to_string()),
-    };
+/// Prometheus metrics, scraped so often that an ETag would only cost a hash.
+#[handler]
+async fn metrics(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    Response::builder()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.audit.ceremonies().render() + &state.usage.render())
+}
 
-    let response = AggSendStepTwoResponse {
-        partial_signature: sig.serialize_bs58(),
-    };
-    success_response(response)
+#[cfg(feature = "chaos")]
+#[handler]
+async fn chaos_report(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    success_response(state.chaos.report())
 }
 
+#[cfg(feature = "chaos")]
 #[handler]
-async fn aggregate_signatures(req: Json<AggregateSignaturesRequest>) -> impl IntoResponse {
-    let to = match parse_pubkey(&req.to) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
-    };
+async fn set_chaos(req: Json<ChaosConfig>, state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    if let Err(e) = state.chaos.set(req.0) {
+        return error_response(e);
+    }
+    success_response(state.chaos.report())
+}
 
-    let block_hash = match parse_hash(&req.recent_block_hash) {
-        Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
-    };
+#[handler]
+async fn reload_config(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    match state.reload().await {
+        Ok(response) => success_response(response),
+        Err(e) => error_response(e),
+    }
+}
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
-    {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
-    };
+#[handler]
+async fn usage_stats(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    success_response(UsageStatsResponse {
+        enabled: snapshot.config.usage_stats,
+        fields: state.usage.fields(),
+    })
+}
 
-    let signatures: Vec<PartialSignature> = match req
-        .signatures
-        .iter()
-        .map(|s| PartialSignature::deserialize_bs58(s))
-        .collect::<Result<_, _>>()
-    {
-        Ok(sigs) => sigs,
-        Err(e) => return error_response(e.to_string()),
-    };
+#[handler]
+async fn flush_caches(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    success_response(state.flush_caches())
+}
 
-    let tx = match sign_and_broadcast(
-        req.amount,
-        to,
-        req.memo.clone(),
-        block_hash,
-        keys,
-        signatures,
-    ) {
-        Ok(transaction) => transaction,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn balance(req: Json<BalanceRequest>, state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
     };
-
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+    let address = match parse_pubkey(&req.address) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
     };
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &block_hash, rpc_client.commitment())
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let balance = match state
+        .accounts
+        .lookup(rpc_client, net, address, req.fresh)
+        .await
     {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
-    }
+        Ok(account) => account.map_or(0, |account| account.lamports),
+        Err(e) => return error_response(Error::BalaceFailed(e)),
+    };
 
-    let response = AggregateSignaturesResponse {
-        transaction_id: sig.to_string(),
+    let response = BalanceResponse {
+        address: address.to_string(),
+        balance,
     };
-    success_response(response)
+    network_response(net, response)
 }
 
-//////////////////////// spl /////////////////////////////
-
-// token_mint = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"
-// 6A2GHg17A2YUbLp7qma1pbvnS7deav7Tq3tthQHa8zt5
 #[handler]
-async fn spl_token_balance(req: Json<SplTokenBalanceRequest>) -> impl IntoResponse {
-    let owner = match parse_pubkey(&req.owner) {
+async fn airdrop(req: Json<AirdropRequest>, state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let to = match parse_pubkey(&req.to) {
         Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
+    if net == Network::Mainnet {
+        return error_response(Error::AirdropUnavailable(net));
+    }
 
-    let token_mint = match parse_pubkey(&req.token_mint) {
-        Ok(mint) => mint,
-        Err(e) => return error_response(e.to_string()),
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let amount = match req.amount.lamports_in("amount") {
+        Ok(amount) => amount,
+        Err(e) => return error_response(e),
     };
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
+    let (sig, source) = match rpc_client.request_airdrop(&to, amount.get()).await {
+        Ok(signature) => (signature, faucet_source(net)),
+        Err(e) => match &state.faucet {
+            Some(faucet) if faucet.serves(net) && is_rate_limited(&e) => {
+                match internal_airdrop(&rpc_client, faucet, &to, amount).await {
+                    Ok(signature) => (signature, AirdropSource::InternalFaucet),
+                    Err(e) => return error_response(e),
+                }
+            }
+            _ => return error_response(Error::AirdropFailed(e)),
+        },
+    };
 
-    // Get the associated token address
-    let token_account = get_associated_token_address(&owner, &token_mint);
+    if req.wait_for_confirmation == Some(false) {
+        let response = AirdropResponse {
+            transaction_id: sig.to_string(),
+            source,
+            status: Some(SendStatus::Submitted),
+        };
+        return network_response(net, response);
+    }
 
-    // Get token account info
-    let account_info = match rpc_client.get_account(&token_account) {
-        Ok(account) => account,
-        Err(_) => return error_response("Token account not found".to_string()),
-    };
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+
+    // The faucet's transaction isn't ours to send again
+    let policy = snapshot
+        .config
+        .confirmation_on(net, req.confirmation.as_ref());
+    match confirm(
+        &rpc_client,
+        &sig,
+        &Lifetime::Blockhash(recent_hash),
+        None,
+        &policy,
+    )
+    .await
+    {
+        Ok(ConfirmationOutcome::Confirmed { .. }) => {}
+        Ok(outcome) => {
+            return error_response(Error::NotConfirmed {
+                signature: sig,
+                outcome,
+            });
+        }
+        Err(e) => return error_response(Error::ConfirmingTransactionFailed(e)),
+    }
 
-    // Parse the token account data
-    let token_account_data = match Account::unpack(&account_info.data) {
-        Ok(data) => data,
-        Err(e) => return error_response(format!("Failed to parse token account: {}", e)),
+    let response = AirdropResponse {
+        transaction_id: sig.to_string(),
+        source,
+        status: None,
     };
+    network_response(net, response)
+}
 
-    // Get mint info to get decimals
-    let mint_info = match rpc_client.get_account(&token_mint) {
-        Ok(account) => account,
-        Err(_) => return error_response("Token mint not found".to_string()),
-    };
+#[handler]
+async fn airdrop_batch(
+    req: Json<AirdropBatchRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    if req.recipients.is_empty() {
+        return error_response(Error::InvalidRequest(
+            "recipients can't be empty".to_string(),
+        ));
+    }
+    if req.recipients.len() > MAX_BATCH_RECIPIENTS {
+        return error_response(Error::TooManyRecipients {
+            len: req.recipients.len(),
+            max: MAX_BATCH_RECIPIENTS,
+        });
+    }
+    if net == Network::Mainnet {
+        return error_response(Error::AirdropUnavailable(net));
+    }
+    let recipients: Vec<Pubkey> = match req.recipients.iter().map(|to| parse_pubkey(to)).collect() {
+        Ok(recipients) => recipients,
+        Err(e) => return error_response(e),
+    };
+    let amount = match req.amount.lamports_in("amount") {
+        Ok(amount) => amount,
+        Err(e) => return error_response(e),
+    };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let backoff = Arc::new(Backoff::new(BATCH_BACKOFF));
+    let permits = Arc::new(Semaphore::new(BATCH_CONCURRENCY));
+    let mut requests = tokio::task::JoinSet::new();
+    for (i, to) in recipients.iter().copied().enumerate() {
+        let (state, rpc_client) = (Arc::clone(&state), rpc_client.clone());
+        let (backoff, permits) = (backoff.clone(), permits.clone());
+        requests.spawn(async move {
+            let _permit = permits.acquire().await;
+            // The local faucet doesn't rate-limit, what it refuses it refuses for good
+            let requested = match net.is_local() {
+                true => rpc_client.request_airdrop(&to, amount.get()).await,
+                false => request_with_backoff(&rpc_client, &backoff, &to, amount.get()).await,
+            };
+            let sent = match requested {
+                Ok(signature) => Ok((signature, faucet_source(net))),
+                Err(e) => match &state.faucet {
+                    // Booked against the same hourly caps as single airdrops
+                    Some(faucet) if faucet.serves(net) && is_rate_limited(&e) => {
+                        internal_airdrop(&rpc_client, faucet, &to, amount)
+                            .await
+                            .map(|signature| (signature, AirdropSource::InternalFaucet))
+                    }
+                    _ => Err(Error::AirdropFailed(e)),
+                },
+            };
+            (i, sent)
+        });
+    }
+    let mut sent: Vec<Option<Result<(Signature, AirdropSource), Error>>> =
+        recipients.iter().map(|_| None).collect();
+    while let Some(joined) = requests.join_next().await {
+        if let Ok((i, result)) = joined {
+            sent[i] = Some(result);
+        }
+    }
 
-    let mint_data = match Mint::unpack(&mint_info.data) {
-        Ok(data) => data,
-        Err(e) => return error_response(format!("Failed to parse mint account: {}", e)),
-    };
+    let signatures: Vec<Signature> = sent
+        .iter()
+        .filter_map(|result| Some(result.as_ref()?.as_ref().ok()?.0))
+        .collect();
+    let mut outcomes = Vec::new();
+    if !signatures.is_empty() {
+        // Fetched after the requests, so it is no older than the faucets' blockhashes
+        let recent_hash = match rpc_client.get_latest_blockhash().await {
+            Ok(hash) => hash,
+            Err(e) => return error_response(Error::RecentHashFailed(e)),
+        };
+        let policy = snapshot
+            .config
+            .confirmation_on(net, req.confirmation.as_ref());
+        outcomes = match confirm_all(&rpc_client, &signatures, &recent_hash, &policy).await {
+            Ok(outcomes) => outcomes,
+            Err(e) => return error_response(Error::ConfirmingTransactionFailed(e)),
+        };
+    }
 
-    let response = SplTokenBalanceResponse {
-        owner: owner.to_string(),
-        token_mint: token_mint.to_string(),
-        balance: token_account_data.amount,
-        decimals: mint_data.decimals,
+    let mut outcomes = outcomes.into_iter();
+    let results = recipients
+        .iter()
+        .zip(sent)
+        .map(|(to, sent)| {
+            let sent = sent.unwrap_or_else(|| {
+                Err(Error::AirdropFailed(ClientError::from(
+                    ClientErrorKind::Custom("the airdrop request was dropped".to_string()),
+                )))
+            });
+            let (signature, source) = match sent {
+                Ok(sent) => sent,
+                Err(e) => {
+                    return AirdropResult {
+                        to: to.to_string(),
+                        signature: None,
+                        status: AirdropStatus::NotSent,
+                        source: None,
+                        error: Some(e.to_string()),
+                        error_code: Some(e.error_code().to_string()),
+                    };
+                }
+            };
+            let (status, error) = match outcomes.next() {
+                Some(ConfirmationOutcome::Confirmed { .. }) => (AirdropStatus::Confirmed, None),
+                Some(ConfirmationOutcome::Failed { error, .. }) => {
+                    (AirdropStatus::Failed, Some(error))
+                }
+                Some(ConfirmationOutcome::Expired) => (AirdropStatus::Expired, None),
+                Some(ConfirmationOutcome::TimedOut) | None => (AirdropStatus::TimedOut, None),
+            };
+            AirdropResult {
+                to: to.to_string(),
+                signature: Some(signature.to_string()),
+                status,
+                source: Some(source),
+                error,
+                error_code: None,
+            }
+        })
+        .collect();
+    network_response(net, AirdropBatchResponse { results })
+}
+
+/// Where `requestAirdrop` on `net` gets its lamports from.
+fn faucet_source(net: Network) -> AirdropSource {
+    match net.is_local() {
+        true => AirdropSource::LocalFaucet,
+        false => AirdropSource::PublicFaucet,
+    }
+}
+
+/// Send `lamports` from the internal faucet, booked against its hourly caps.
+async fn internal_airdrop(
+    rpc_client: &AsyncRpcClient,
+    faucet: &InternalFaucet,
+    to: &Pubkey,
+    lamports: Lamports,
+) -> Result<Signature, Error> {
+    let now = Instant::now();
+    faucet.reserve(to, lamports.get(), now)?;
+
+    let keypair = faucet.keypair();
+    let sent = match rpc_client.get_latest_blockhash().await {
+        Ok(recent_hash) => {
+            let message = TransferBuilder::new(keypair.pubkey(), *to, lamports).build();
+            let mut tx = Transaction::new_unsigned(message);
+            tx.sign(&[keypair], recent_hash);
+            rpc_client
+                .send_transaction(&tx)
+                .await
+                .map_err(Error::SendTransactionFailed)
+        }
+        Err(e) => Err(Error::RecentHashFailed(e)),
     };
-    success_response(response)
+    if sent.is_err() {
+        faucet.release(to, lamports.get(), now);
+    }
+    sent
 }
 
 #[handler]
-async fn spl_send_single(req: Json<SplSendSingleRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
+async fn send_single(
+    req: Json<SendSingleRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
         Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
     let to = match parse_pubkey(&req.to) {
         Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
-    };
-
-    let token_mint = match parse_pubkey(&req.token_mint) {
-        Ok(mint) => mint,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-
-    // Convert amount to proper token units
-    let token_amount = (req.amount * 10_f64.powi(req.decimals as i32)) as u64;
-
-    //Derive ATAs
-    let from_ata =
-        spl_associated_token_account::get_associated_token_address(&keypair.pubkey(), &token_mint);
-    let to_ata = spl_associated_token_account::get_associated_token_address(&to, &token_mint);
-
-    //checking if destination ATA exists
-    let to_ata_exists = match rpc_client.get_account(&to_ata) {
-        Ok(_) => true,
-        Err(_) => false,
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return error_response(e),
     };
 
-    let mut instructions = vec![];
-
-    // Create destination ATA if it doesn't exist
-    if !to_ata_exists {
-        let create_ata_instruction =
-            spl_associated_token_account::instruction::create_associated_token_account(
-                &keypair.pubkey(), // Payer
-                &to,               // Owner
-                &token_mint,       // Mint
-                &spl_token::id(),  // Token program
-            );
-        instructions.push(create_ata_instruction);
+    if req.recipients.len() > MAX_TRANSFER_RECIPIENTS {
+        return error_response(Error::TooManyRecipients {
+            len: req.recipients.len(),
+            max: MAX_TRANSFER_RECIPIENTS,
+        });
+    }
+    // Solana Pay pays one recipient, a split transfer would carry the references twice
+    if !references.is_empty() && !req.recipients.is_empty() {
+        return error_response(Error::InvalidSolanaPay(
+            "references can't be combined with recipients".to_string(),
+        ));
     }
 
-    // Create transfer instruction
-    let transfer_instruction = match spl_token::instruction::transfer(
-        &spl_token::id(),
-        &from_ata,
-        &to_ata,
-        &keypair.pubkey(),
-        &[],
-        token_amount,
-    ) {
-        Ok(instr) => instr,
-        Err(e) => return error_response(e.to_string()),
+    let lamports = match resolve_lamports(req.amount.as_ref(), req.lamports) {
+        Ok(lamports) => lamports,
+        Err(e) => return error_response(e),
     };
 
-    instructions.push(transfer_instruction);
-
-    // Add memo if provided
-    if let Some(memo) = req.memo.clone() {
-        instructions.push(spl_memo::build_memo(memo.as_bytes(), &[]));
+    let mut recipients = vec![(to, lamports)];
+    for (i, recipient) in req.recipients.iter().enumerate() {
+        let to = match parse_pubkey(&recipient.to) {
+            Ok(addr) => addr,
+            Err(e) => return error_response(e),
+        };
+        match resolve_lamports(recipient.amount.as_ref(), recipient.lamports) {
+            Ok(lamports) => recipients.push((to, lamports)),
+            Err(e) => return error_response(in_recipient(i, e)),
+        }
     }
 
-    // Create and sign transaction
-    let recent_hash = match rpc_client.get_latest_blockhash() {
-        Ok(hash) => hash,
-        Err(e) => return error_response(Error::RecentHashFailed(e).to_string()),
-    };
+    let mut warnings = Vec::new();
+    for (to, _) in &recipients {
+        match check_recipient(
+            &state.accounts,
+            async_rpc_client(&state, &snapshot.config, net),
+            net,
+            to,
+            req.allow_non_system_recipient,
+        )
+        .await
+        {
+            Ok(warning) => warnings.extend(warning),
+            Err(e) => return error_response(e),
+        }
+    }
 
-    let mut tx = Transaction::new_with_payer(&instructions, Some(&keypair.pubkey()));
-    tx.sign(&[&keypair], recent_hash);
+    let builder = recipients[1..].iter().fold(
+        TransferBuilder::new(keypair.pubkey(), to, lamports)
+            .memo(req.memo.clone())
+            .signed_memo(req.signed_memo)
+            .memo_program(memo_program)
+            .compute_unit_price(req.priority_fee_micro_lamports)
+            .references(references),
+        |builder, &(to, lamports)| builder.recipient(to, lamports),
+    );
 
-    // Send transaction
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => {
-            if let Some(rpc_err) = e.get_transaction_error() {
-                eprintln!("Transaction error details: {:?}", rpc_err);
+    // Any limit takes the same room, the size doesn't depend on the simulation
+    let sized = builder
+        .clone()
+        .compute_unit_limit(req.compute_unit_limit.map(|_| 0));
+    let transfers = match (sized.oversize(), req.auto_split) {
+        (None, _) => vec![builder],
+        (Some(oversize), false) => {
+            return error_response(Error::TransactionTooLarge {
+                oversize,
+                splittable: true,
+            });
+        }
+        (Some(_), true) => match sized.split() {
+            Ok(transfers) => transfers
+                .into_iter()
+                .map(|transfer| transfer.compute_unit_limit(None))
+                .collect(),
+            Err(e) => return error_response(e),
+        },
+    };
+
+    let total = transfers.len();
+    let mut sent = Vec::with_capacity(total);
+    for transfer in transfers {
+        match send_transfer(&state, &snapshot, headers, &req, net, &keypair, transfer).await {
+            Ok(transaction) => sent.push(transaction),
+            Err(e) if sent.is_empty() => return error_response(e),
+            Err(e) => {
+                return error_response(Error::SplitIncomplete {
+                    sent: sent.iter().map(|(sent, _)| sent.signature).collect(),
+                    total,
+                    error: Box::new(e),
+                });
             }
-            return error_response(Error::SendTransactionFailed(e).to_string());
         }
+    }
+
+    let transaction_ids = match total {
+        1 => vec![],
+        _ => sent
+            .iter()
+            .map(|(sent, _)| sent.signature.to_string())
+            .collect(),
+    };
+    let (first, compute_unit_limit) = sent.swap_remove(0);
+    let response = SendSingleResponse {
+        transaction_id: first.signature.to_string(),
+        status: first.status(),
+        rpc_endpoint: first.rpc_endpoint,
+        compute_unit_limit,
+        transaction_ids,
     };
+    network_response(net, Warned::new(response, warnings))
+}
 
-    // Confirm transaction
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
+/// Sign and send one transaction of a `/api/send_single` transfer.
+async fn send_transfer(
+    state: &AppState,
+    snapshot: &Snapshot,
+    headers: &HeaderMap,
+    req: &SendSingleRequest,
+    net: Network,
+    keypair: &Keypair,
+    builder: TransferBuilder,
+) -> Result<(Sent, Option<ComputeUnitReport>), Error> {
+    let rpc_client = async_rpc_client(state, &snapshot.config, net);
+    let compute_units = resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        Some(&rpc_client),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| Ok(builder.clone().compute_unit_limit(limit).build()),
+    )
+    .await?;
+
+    let lamports: u64 = builder
+        .recipients()
+        .iter()
+        .map(|(_, lamports)| lamports.get())
+        .sum();
+    let message = builder.compute_unit_limit(compute_units.map(|r| r.limit));
+    let mut tx = Transaction::new_unsigned(message.build());
+
+    let recent_hash = rpc_client
+        .get_latest_blockhash()
+        .await
+        .map_err(Error::RecentHashFailed)?;
+
+    tx.sign(&[keypair], recent_hash);
+
+    let reservation = reserve_spend(snapshot, headers, Asset::Sol, lamports)?;
+
+    let broadcast = Broadcast::new(state.audit.clone(), "send_single", None, tx);
+    match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .wait(req.wait_for_confirmation)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
     {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+        Ok(sent) => Ok((sent, compute_units)),
+        Err(e) => {
+            release_spend(snapshot, reservation, &e);
+            Err(e)
+        }
     }
-
-    let response = SplSendSingleResponse {
-        transaction_id: sig.to_string(),
-    };
-    success_response(response)
 }
 
 #[handler]
-async fn spl_agg_send_step_two(req: Json<SplAggSendStepTwoRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
+async fn broadcast_status(
+    req: Json<BroadcastStatusRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let signature = match Signature::from_str(&req.signature) {
+        Ok(signature) => signature,
+        Err(e) => return error_response(Error::InvalidTransactionId(e.to_string())),
     };
 
-    let to = match parse_pubkey(&req.to) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+    let response = BroadcastStatusResponse {
+        signature: signature.to_string(),
+        entries: state
+            .audit
+            .for_signature_as(&signature, &snapshot.tenants.caller(headers)),
     };
+    success_response(response)
+}
 
-    let token_mint = match parse_pubkey(&req.token_mint) {
-        Ok(mint) => mint,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn broadcast(
+    req: Json<BroadcastRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
     };
-
-    let block_hash = match parse_hash(&req.recent_block_hash) {
-        Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+    let tx = match decode_transaction(&req.transaction, req.encoding) {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
     };
+    if let Err(e) = verify_signatures(&tx) {
+        return error_response(e);
+    }
+    if let Err(e) = snapshot.config.writable_policy.check(&tx.message) {
+        return error_response(e);
+    }
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
+    let broadcast = Broadcast::new(state.audit.clone(), "broadcast", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .wait(req.wait_for_confirmation)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(async_rpc_client(&state, &snapshot.config, net))
+        .await
     {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+        Ok(sent) => sent,
+        Err(e) => return error_response(e),
     };
 
-    let first_messages: Vec<AggMessage1> = match req
-        .first_messages
-        .iter()
-        .map(|m| AggMessage1::deserialize_bs58(m))
-        .collect::<Result<_, _>>()
-    {
-        Ok(msgs) => msgs,
-        Err(e) => return error_response(e.to_string()),
+    let response = BroadcastResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: sent.rpc_endpoint,
+        commitment: sent.commitment,
+        status: sent.status(),
+        duplicate: sent.duplicate,
     };
+    network_response(net, response)
+}
+
+#[handler]
+async fn audit_message(Path(id): Path<u64>, state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let Some(entry) = state.audit.entry(id) else {
+        return error_response(Error::AuditEntryNotFound(id));
+    };
+    success_response(AuditMessageResponse {
+        id,
+        endpoint: entry.endpoint,
+        signature: entry.signature,
+        message_hash: entry.message_hash,
+        message: entry.message,
+    })
+}
 
-    let secret_state = match SecretAggStepOne::deserialize_bs58(&req.secret_state) {
-        Ok(state) => state,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn audit_export(
+    Query(query): Query<AuditExportQuery>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let range = match ExportRange::new(query.from, query.to, query.cursor.as_deref(), query.limit) {
+        Ok(range) => range,
+        Err(e) => return error_response(e),
     };
+    let gzip = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| encoding.split(';').next().unwrap_or_default().trim() == "gzip");
+
+    // Written as it is read from the log, the pipe holds back the export while the client is
+    // slower than the server and ends it when the client goes away
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let audit = state.audit.clone();
+    tokio::spawn(async move {
+        if let Err(e) = audit_export::export(&audit, range, gzip, writer).await {
+            tracing::debug!(error = %e, "audit export stopped");
+        }
+    });
+    let resp = Response::builder()
+        .content_type("application/x-ndjson")
+        .header(header::CACHE_CONTROL, "no-store");
+    let resp = match gzip {
+        true => resp.header(header::CONTENT_ENCODING, "gzip"),
+        false => resp,
+    };
+    resp.body(Body::from_async_read(reader))
+}
 
-    let sig = match spl_step_two(
-        keypair,
-        req.amount,
-        to,
-        token_mint,
-        req.decimals,
-        req.memo.clone(),
-        block_hash,
-        keys,
-        first_messages,
-        secret_state,
-    ) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn transaction_statuses(
+    req: Json<TransactionStatusesRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    if req.signatures.len() > MAX_SIGNATURES {
+        return error_response(Error::TooManySignatures {
+            len: req.signatures.len(),
+            max: MAX_SIGNATURES,
+        });
+    }
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let statuses = match signature_statuses(&rpc_client, &req.signatures).await {
+        Ok(statuses) => statuses,
+        Err(e) => return error_response(Error::SignatureStatusFailed(e)),
     };
+    network_response(net, TransactionStatusesResponse { statuses })
+}
 
-    let response = SplAggSendStepTwoResponse {
-        partial_signature: sig.serialize_bs58(),
+/// Streams the commitment of every signature the client sends until it is finalized, failed or
+/// given up on. The connection carries on in the request's span.
+#[handler]
+fn confirmations_socket(ws: WebSocket, state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let state = state.clone();
+    let span = tracing::Span::current();
+    ws.on_upgrade(move |socket| {
+        let (stream, default_network) = (
+            snapshot.config.confirmation_stream,
+            snapshot.config.default_network,
+        );
+        let (outgoing, incoming) = socket.split();
+        confirmation_stream::serve(incoming, outgoing, stream, default_network, move |net| {
+            async_rpc_client(&state, &snapshot.config, net)
+        })
+        .instrument(span)
+    })
+}
+
+#[handler]
+async fn scheduled_broadcast_status(
+    req: Json<ScheduledBroadcastStatusRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let caller = snapshot.tenants.caller(headers);
+    let Some((scheduler, signature)) = state.scheduler.as_ref().and_then(|scheduler| {
+        scheduler
+            .lookup(&caller, &req.idempotency_key)
+            .map(|signature| (scheduler, signature))
+    }) else {
+        return error_response(Error::ScheduledBroadcastNotFound(
+            req.idempotency_key.clone(),
+        ));
+    };
+
+    let response = ScheduledBroadcastStatusResponse {
+        signature: signature.to_string(),
+        broadcast_at: scheduler.pending(&signature),
+        entries: state.audit.for_signature_as(&signature, &caller),
     };
     success_response(response)
 }
 
 #[handler]
-async fn spl_aggregate_signatures(req: Json<SplAggregateSignaturesRequest>) -> impl IntoResponse {
-    let to = match parse_pubkey(&req.to) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+async fn cancel_scheduled_broadcast(
+    req: Json<CancelScheduledBroadcastRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let caller = snapshot.tenants.caller(headers);
+    let signature = match (&req.signature, &req.idempotency_key) {
+        (Some(signature), _) => match Signature::from_str(signature) {
+            Ok(signature) => signature,
+            Err(e) => {
+                return error_response(Error::InvalidTransactionId(e.to_string()));
+            }
+        },
+        (None, Some(key)) => match state
+            .scheduler
+            .as_ref()
+            .and_then(|s| s.lookup(&caller, key))
+        {
+            Some(signature) => signature,
+            None => return error_response(Error::ScheduledBroadcastNotFound(key.clone())),
+        },
+        (None, None) => {
+            return error_response(Error::InvalidRequest(
+                "pass the signature or the idempotency_key".to_string(),
+            ));
+        }
     };
 
-    let token_mint = match parse_pubkey(&req.token_mint) {
-        Ok(mint) => mint,
-        Err(e) => return error_response(e.to_string()),
+    let cancelled = match &state.scheduler {
+        Some(scheduler) => scheduler.cancel(&caller, &signature).await,
+        None => Err(Error::ScheduledBroadcastNotFound(signature.to_string())),
     };
+    match cancelled {
+        Ok(reservation) => {
+            if let (Some(guard), Some(reservation)) = (&snapshot.spend_guard, reservation) {
+                guard.release(reservation);
+            }
+            success_response(CancelScheduledBroadcastResponse {
+                signature: signature.to_string(),
+            })
+        }
+        Err(e) => error_response(e),
+    }
+}
 
-    let block_hash = match parse_hash(&req.recent_block_hash) {
+#[handler]
+async fn recent_block_hash(
+    req: Json<RecentBlockHashRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
         Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
     };
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
-    {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+    let response = RecentBlockHashResponse {
+        recent_block_hash: recent_hash.to_string(),
     };
+    network_response(net, response)
+}
 
-    let signatures: Vec<PartialSignature> = match req
-        .signatures
-        .iter()
-        .map(|s| PartialSignature::deserialize_bs58(s))
-        .collect::<Result<_, _>>()
-    {
-        Ok(sigs) => sigs,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn aggregate_keys(
+    req: Json<AggregateKeysRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
     };
 
-    let tx = match spl_sign_and_broadcast(
-        req.amount,
-        to,
-        token_mint,
-        req.decimals,
-        req.memo.clone(),
-        block_hash,
-        keys,
-        signatures,
-    ) {
-        Ok(transaction) => transaction,
-        Err(e) => return error_response(e.to_string()),
+    let party_key = match req.party_key.as_deref().map(parse_pubkey).transpose() {
+        Ok(key) => key,
+        Err(e) => return error_response(e),
     };
-
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+    let party = match party_index(&keys, party_key.as_ref(), req.party_index) {
+        Ok(party) => party,
+        Err(e) => return error_response(e),
+    };
+    let index = party.unwrap_or_default();
+    let Some(&party_key) = keys.get(index) else {
+        return error_response(Error::InvalidParty("keys is empty".to_string()));
     };
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &block_hash, rpc_client.commitment())
-    {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
-    }
+    let aggkey = match key_agg(keys, Some(party_key)) {
+        Ok(key) => key,
+        Err(e) => return error_response(e),
+    };
 
-    let response = SplAggregateSignaturesResponse {
-        transaction_id: sig.to_string(),
+    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
+    let response = AggregateKeysResponse {
+        aggregated_public_key: aggpubkey.to_string(),
+        mode: match party {
+            Some(_) => KeyAggMode::Party,
+            None => KeyAggMode::FirstKey,
+        },
+        party_index: index,
+        party_key: party_key.to_string(),
+        musig_coefficient: aggkey
+            .musig_coefficient
+            .to_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
     };
     success_response(response)
 }
 
-// -------------------------- staking -----------------------//
-//
-
 #[handler]
-async fn stake_account(req: Json<StakeAccountRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
+async fn agg_send_step_one(
+    req: Json<AggSendStepOneRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
         Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let vote_account = match parse_pubkey(&req.validator_vote_accont) {
-        Ok(vc) => vc,
-        Err(e) => return error_response(e.to_string()),
+    let signer = keypair.pubkey();
+    let (first_msg, secret) = step_one(keypair);
+    let response = match snapshot.config.server_side_secret_state {
+        true => AggSendStepOneResponse {
+            message_1: labeled(&snapshot.config, &first_msg),
+            secret_state: None,
+            secret_state_id: Some(state.secret_states.keep(signer, &secret, Instant::now())),
+        },
+        false => AggSendStepOneResponse {
+            message_1: labeled(&snapshot.config, &first_msg),
+            secret_state: Some(labeled(&snapshot.config, &secret)),
+            secret_state_id: None,
+        },
     };
+    success_response(response)
+}
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let mut tx = match create_stake_account_transaction(
-        req.stake_amount,
-        &req.seed,
-        &keypair.pubkey(),
-        &vote_account,
-    ) {
-        Ok(tx) => tx,
-        Err(e) => return error_response(e.to_string()),
-    };
+/// The secret state of step one for `signer`'s step two: sent back as `secret_state`, or kept
+/// by step one as `secret_state_id`, which is taken and can't sign again. Called last, right
+/// before signing, so a request refused by any other check can be resent with the same id.
+fn take_secret_state(
+    state: &AppState,
+    snapshot: &Snapshot,
+    signer: &Pubkey,
+    secret_state: Option<&str>,
+    secret_state_id: Option<&str>,
+) -> Result<SecretAggStepOne, Response> {
+    match (secret_state, secret_state_id) {
+        (Some(secret_state), None) => {
+            check_environment::<SecretAggStepOne>(&snapshot.config, "secret_state", [secret_state])
+                .map_err(error_response)?;
+            SecretAggStepOne::deserialize_bs58(secret_state)
+                .with_field("secret_state")
+                .map_err(error_response)
+        }
+        (None, Some(id)) => state
+            .secret_states
+            .take(id, signer, Instant::now())
+            .map_err(error_response),
+        (Some(_), Some(_)) => Err(error_response(Error::InvalidRequest(
+            "send either secret_state or secret_state_id, not both".to_string(),
+        ))),
+        (None, None) => Err(error_response(Error::InvalidRequest(
+            "secret_state or secret_state_id is required".to_string(),
+        ))),
+    }
+}
 
-    let recent_hash = match rpc_client.get_latest_blockhash() {
-        Ok(hash) => hash,
-        Err(e) => return error_response(Error::RecentHashFailed(e).to_string()),
-    };
+/// The unsigned message step two would sign for these parameters, for review before any
+/// party signs. Keys and blockhash have to be the ones step two will get.
+#[handler]
+async fn build_message(
+    req: Json<BuildMessageRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    match transfer_to_sign(&req, &state).await {
+        Ok((aggpubkey, message, compute_units)) => {
+            success_response(review(&aggpubkey, &message, compute_units))
+        }
+        Err(resp) => resp,
+    }
+}
 
-    tx.sign(&[&keypair], recent_hash);
+/// The durable nonce a SOL transfer of `aggpubkey` is signed against, `None` for a recent
+/// blockhash.
+fn resolve_durable_nonce(
+    nonce_account: Option<&str>,
+    nonce_authority: Option<&str>,
+    aggpubkey: Pubkey,
+) -> Result<Option<DurableNonce>, Response> {
+    let account = match nonce_account.map(parse_pubkey).transpose() {
+        Ok(account) => account,
+        Err(e) => return Err(error_response(e)),
+    };
+    let authority = match nonce_authority.map(parse_pubkey).transpose() {
+        Ok(authority) => authority,
+        Err(e) => return Err(error_response(e)),
+    };
+    DurableNonce::resolve(account, authority, aggpubkey).map_err(error_response)
+}
 
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+/// The signers of a SOL ceremony for a threshold key of `keys`, `None` when every one of
+/// `keys` signs for their aggregated key.
+fn resolve_threshold(
+    threshold: Option<&ThresholdSigning>,
+    keys: &[Pubkey],
+) -> Result<Option<ThresholdSigners>, Response> {
+    let Some(threshold) = threshold else {
+        return Ok(None);
     };
+    let group_key = match parse_pubkey(&threshold.group_key) {
+        Ok(key) => key,
+        Err(e) => return Err(error_response(e)),
+    };
+    let signers = match parse_pubkeys(&threshold.signers) {
+        Ok(signers) => signers,
+        Err(e) => return Err(error_response(e)),
+    };
+    let indices = match signer_indices(keys, &signers, threshold.threshold) {
+        Ok(indices) => indices,
+        Err(e) => return Err(error_response(e)),
+    };
+    Ok(Some(ThresholdSigners {
+        threshold: threshold.threshold,
+        group_key,
+        signers,
+        indices,
+    }))
+}
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
+/// The address a SOL ceremony spends from: the threshold key, or the aggregated key of `keys`.
+fn ceremony_pubkey(keys: &[Pubkey], threshold: Option<&ThresholdSigners>) -> Result<Pubkey, Error> {
+    match threshold {
+        Some(threshold) => Ok(threshold.group_key),
+        None => aggregated_pubkey(keys.to_vec()),
+    }
+}
+
+/// The `key_share` the party at `key_index` signs for `threshold` with, after checking it is a
+/// share of that key and that `first_messages` are those of the other signers.
+fn threshold_key_share(
+    state: &AppState,
+    snapshot: &Snapshot,
+    key_share: Option<&str>,
+    key_index: usize,
+    threshold: &ThresholdSigners,
+    first_messages: &[AggMessage1],
+) -> Result<ThresholdKeyShare, Error> {
+    let Some(key_share) = key_share else {
+        return Err(Error::InvalidThreshold(
+            "key_share is needed to sign for a threshold key".to_string(),
+        ));
+    };
+    check_environment::<ThresholdKeyShare>(&snapshot.config, "key_share", [key_share])?;
+    let share = ThresholdKeyShare::deserialize_bs58(key_share).with_field("key_share")?;
+    if group_pubkey(&share) != threshold.group_key
+        || usize::from(share.threshold) != threshold.threshold
     {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+        return Err(Error::InvalidThreshold(format!(
+            "key_share is a share of another key than {} with threshold {}",
+            threshold.group_key, threshold.threshold
+        )));
     }
+    if usize::from(share.index) != key_index {
+        return Err(Error::InvalidThreshold(format!(
+            "key_share belongs to keys[{}], not to keys[{}] signing with it",
+            share.index, key_index
+        )));
+    }
+    let Some(position) = threshold.indices.iter().position(|i| *i == key_index) else {
+        return Err(Error::InvalidThreshold(format!(
+            "keys[{}] isn't one of signers",
+            key_index
+        )));
+    };
+    let mut senders: Vec<_> = first_messages.iter().map(|m| m.sender).collect();
+    let mut others = threshold.signers.clone();
+    others.remove(position);
+    senders.sort();
+    others.sort();
+    if senders != others {
+        return Err(Error::InvalidThreshold(
+            "first_messages have to be those of the other signers, one each".to_string(),
+        ));
+    }
+    Ok(share)
+}
 
-    let stake_account = match Pubkey::create_with_seed(
-        &keypair.pubkey(),
-        &req.seed,
-        &solana_sdk::stake::program::id(),
-    ) {
+/// The message of a SOL transfer from the aggregated key of `req.keys`, what every party of the
+/// ceremony will sign.
+async fn transfer_to_sign(
+    req: &BuildMessageRequest,
+    state: &AppState,
+    snapshot: &Snapshot,
+) -> Result<(Pubkey, Message, Option<ComputeUnitReport>), Response> {
+    let to = match parse_pubkey(&req.to) {
         Ok(addr) => addr,
-        Err(_) => {
-            return error_response("Invalid stake account seed".to_string());
-        }
+        Err(e) => return Err(error_response(e)),
     };
 
-    let response = StakeAccountResponse {
-        stake_account_address: stake_account.to_string(),
-        transaction_id: sig.to_string(),
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return Err(error_response(e)),
     };
-    success_response(response)
-}
 
-#[handler]
-async fn deactivate_stake(req: Json<DeactivateStakeRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return Err(error_response(e)),
     };
 
-    let stake_accountt = match parse_pubkey(&req.stake_account) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return Err(error_response(e)),
     };
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let mut tx = create_deactivate_stake_transaction(&stake_accountt, &keypair.pubkey());
-
-    let recent_hash = match rpc_client.get_latest_blockhash() {
-        Ok(hash) => hash,
-        Err(e) => return error_response(Error::RecentHashFailed(e).to_string()),
+    let lamports = match resolve_lamports(req.amount.as_ref(), req.lamports) {
+        Ok(lamports) => lamports,
+        Err(e) => return Err(error_response(e)),
     };
 
-    tx.sign(&[&keypair], recent_hash);
-
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+    let threshold = resolve_threshold(req.threshold.as_ref(), &keys)?;
+    let aggpubkey = match ceremony_pubkey(&keys, threshold.as_ref()) {
+        Ok(key) => key,
+        Err(e) => return Err(error_response(e)),
     };
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
+    let durable_nonce = match resolve_durable_nonce(
+        req.nonce_account.as_deref(),
+        req.nonce_authority.as_deref(),
+        aggpubkey,
+    ) {
+        Ok(durable_nonce) => durable_nonce,
+        Err(resp) => return Err(resp),
+    };
+
+    let transfer = TransferBuilder::new(aggpubkey, to, lamports)
+        .memo(req.memo.clone())
+        .signed_memo(req.signed_memo)
+        .memo_program(memo_program)
+        .compute_unit_price(req.priority_fee_micro_lamports)
+        .durable_nonce(durable_nonce)
+        .references(references);
+    let rpc_client = req
+        .net
+        .map(|net| async_rpc_client(state, &snapshot.config, net));
+    let compute_units = match resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        rpc_client.as_deref(),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| Ok(transfer.clone().compute_unit_limit(limit).build()),
+    )
+    .await
     {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+        Ok(report) => report,
+        Err(e) => return Err(error_response(e)),
+    };
+
+    let transfer = transfer.compute_unit_limit(compute_units.as_ref().map(|r| r.limit));
+    if let Err(e) = check_transfer_size(&transfer) {
+        return Err(error_response(e));
     }
 
-    let response = DeactivateStakeResponse {
-        transaction_id: sig.to_string(),
-    };
-    success_response(response)
+    let message = transfer_message(&transfer, block_hash);
+    Ok((aggpubkey, message, compute_units))
 }
 
 #[handler]
-async fn withdraw_stake(req: Json<WithdrawStakeRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
-    };
+async fn spl_build_message(
+    req: Json<SplBuildMessageRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    match spl_transfer_to_sign(&req, &state, req.net).await {
+        Ok((aggpubkey, message, compute_units)) => {
+            success_response(review(&aggpubkey, &message, compute_units))
+        }
+        Err(resp) => resp,
+    }
+}
 
-    let stake_accountt = match parse_pubkey(&req.stake_account) {
+/// The message of an SPL transfer from the aggregated key of `req.keys`, what every party of the
+/// ceremony will sign. The node of `net` tells the program owning the mint when the request
+/// doesn't.
+async fn spl_transfer_to_sign(
+    req: &SplBuildMessageRequest,
+    state: &AppState,
+    snapshot: &Snapshot,
+    net: Option<Network>,
+) -> Result<(Pubkey, Message, Option<ComputeUnitReport>), Response> {
+    let to = match parse_pubkey(&req.to) {
         Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return Err(error_response(e)),
     };
 
-    let destination = match parse_pubkey(&req.destination) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return Err(error_response(e)),
     };
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let mut tx = create_withdraw_stake_transaction(
-        &stake_accountt,
-        &destination,
-        &keypair.pubkey(),
-        req.amount,
-    );
+    let mut token = match resolve_token(
+        &snapshot.tokens,
+        req.token.as_deref(),
+        req.token_mint.as_deref(),
+        req.decimals,
+        net,
+    ) {
+        Ok(token) => token,
+        Err(e) => return Err(error_response(e)),
+    };
+
+    let rent_sponsor = match req.rent_sponsor.as_deref().map(parse_pubkey).transpose() {
+        Ok(sponsor) => sponsor,
+        Err(e) => return Err(error_response(e)),
+    };
 
-    let recent_hash = match rpc_client.get_latest_blockhash() {
+    let block_hash = match parse_hash(&req.recent_block_hash) {
         Ok(hash) => hash,
-        Err(e) => return error_response(Error::RecentHashFailed(e).to_string()),
+        Err(e) => return Err(error_response(e)),
     };
 
-    tx.sign(&[&keypair], recent_hash);
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return Err(error_response(e)),
+    };
 
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+    let aggpubkey = match aggregated_pubkey(keys) {
+        Ok(key) => key,
+        Err(e) => return Err(error_response(e)),
     };
 
+    let rpc_client = net.map(|net| async_rpc_client(state, &snapshot.config, net));
+
     if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
+        resolve_token_program(&mut token, req.token_program, rpc_client.as_deref()).await
     {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+        return Err(error_response(e));
     }
 
-    let response = WithdrawStakeResponse {
-        transaction_id: sig.to_string(),
+    if let Err(e) = check_recipient_ata(&token, &to, rpc_client.as_deref()).await {
+        return Err(error_response(e));
+    }
+
+    let token_amount = match spl_amount(&token, req.amount.as_ref(), req.amount_base_units) {
+        Ok(amount) => amount,
+        Err(e) => return Err(error_response(e)),
+    };
+    let transfer = SplTransferBuilder::new(aggpubkey, to, token.mint, token_amount, token.decimals)
+        .token_program(token.program)
+        .memo(req.memo.clone())
+        .signed_memo(req.signed_memo)
+        .memo_program(memo_program)
+        .compute_unit_price(req.priority_fee_micro_lamports)
+        .references(references)
+        .create_recipient_ata(true)
+        .rent_sponsor(rent_sponsor);
+    let compute_units = match resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        rpc_client.as_deref(),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| transfer.clone().compute_unit_limit(limit).build(),
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => return Err(error_response(e)),
     };
-    success_response(response)
+
+    let transfer = transfer.compute_unit_limit(compute_units.as_ref().map(|r| r.limit));
+    let message = match spl_transfer_message(&transfer, block_hash) {
+        Ok(message) => message,
+        Err(e) => return Err(error_response(e)),
+    };
+    Ok((aggpubkey, message, compute_units))
+}
+
+/// Run the transfer of `build_message` past the node of `net` before anyone signs it.
+#[handler]
+async fn simulate(
+    req: Json<BuildMessageRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    match transfer_to_sign(&req, &state).await {
+        Ok((aggpubkey, message, _)) => {
+            simulation_response(&state, &snapshot, net, &aggpubkey, &message).await
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// Run the transfer of `spl_build_message` past the node of `net` before anyone signs it.
+#[handler]
+async fn spl_simulate(
+    req: Json<SplBuildMessageRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    match spl_transfer_to_sign(&req, &state, Some(net)).await {
+        Ok((aggpubkey, message, _)) => {
+            simulation_response(&state, &snapshot, net, &aggpubkey, &message).await
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// A transfer that would fail is still a 200, only a node that couldn't simulate it is an error.
+async fn simulation_response(
+    state: &AppState,
+    snapshot: &Snapshot,
+    net: Network,
+    aggpubkey: &Pubkey,
+    message: &Message,
+) -> Response {
+    let rpc_client = async_rpc_client(state, &snapshot.config, net);
+    match simulation::simulate(&rpc_client, message).await {
+        Ok(simulation) => network_response(
+            net,
+            SimulationResponse {
+                aggregated_pubkey: aggpubkey.to_string(),
+                message_hash: message_hash(message),
+                would_succeed: simulation.failure.is_none(),
+                failure: simulation.failure,
+                units_consumed: simulation.units_consumed,
+                logs: simulation.logs,
+            },
+        ),
+        Err(e) => error_response(e),
+    }
 }
 
 #[handler]
-async fn agg_stake_step_two(req: Json<AggStakeStepTwoRequest>) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
+async fn agg_send_step_two(
+    req: Json<AggSendStepTwoRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
         Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let vote_account = match parse_pubkey(&req.validator_vote_accont) {
-        Ok(vc) => vc,
-        Err(e) => return error_response(e.to_string()),
+    let to = match parse_pubkey(&req.to) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return error_response(e),
     };
 
     let block_hash = match parse_hash(&req.recent_block_hash) {
         Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
-    {
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
         Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let first_messages: Vec<AggMessage1> = match req
-        .first_messages
-        .iter()
-        .map(|m| AggMessage1::deserialize_bs58(m))
-        .collect::<Result<_, _>>()
+    let key_index = match check_signer_in_keys(&keypair.pubkey(), &keys) {
+        Ok(index) => index,
+        Err(e) => return error_response(e),
+    };
+
+    let threshold = match resolve_threshold(req.threshold.as_ref(), &keys) {
+        Ok(threshold) => threshold,
+        Err(resp) => return resp,
+    };
+
+    let first_messages: Vec<AggMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+
+    let key_share = match threshold
+        .as_ref()
+        .map(|threshold| {
+            threshold_key_share(
+                &state,
+                &snapshot,
+                req.key_share.as_deref(),
+                key_index,
+                threshold,
+                &first_messages,
+            )
+        })
+        .transpose()
     {
-        Ok(msgs) => msgs,
-        Err(e) => return error_response(e.to_string()),
+        Ok(key_share) => key_share,
+        Err(e) => return error_response(e),
     };
 
-    let secret_state = match SecretAggStepOne::deserialize_bs58(&req.secret_state) {
-        Ok(state) => state,
-        Err(e) => return error_response(e.to_string()),
+    let lamports = match resolve_lamports(req.amount.as_ref(), req.lamports) {
+        Ok(lamports) => lamports,
+        Err(e) => return error_response(e),
     };
 
-    let sig = match stake_step_two(
-        keypair,
-        req.stake_amount,
-        req.seed.clone(),
-        vote_account,
-        block_hash,
-        keys,
-        first_messages,
-        secret_state,
-    ) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(e.to_string()),
+    let aggpubkey = match ceremony_pubkey(&keys, threshold.as_ref()) {
+        Ok(key) => key,
+        Err(e) => return error_response(e),
     };
 
-    let response = AggStakeStepTwoResponse {
-        partial_signature: sig.serialize_bs58(),
+    let durable_nonce = match resolve_durable_nonce(
+        req.nonce_account.as_deref(),
+        req.nonce_authority.as_deref(),
+        aggpubkey,
+    ) {
+        Ok(durable_nonce) => durable_nonce,
+        Err(resp) => return resp,
+    };
+
+    let recipient_checked = match req.net {
+        Some(net) => {
+            check_recipient(
+                &state.accounts,
+                async_rpc_client(&state, &snapshot.config, net),
+                net,
+                &to,
+                req.allow_non_system_recipient,
+            )
+            .await
+        }
+        None => Ok(None),
+    };
+    let warnings = match recipient_checked {
+        Ok(warning) => Vec::from_iter(warning),
+        Err(e) => return error_response(e),
+    };
+
+    // Signatures over a nonce that was advanced already could never be used
+    let nonce_checked = match (req.net, durable_nonce) {
+        (Some(net), Some(durable_nonce)) => {
+            check_nonce(
+                &async_rpc_client(&state, &snapshot.config, net),
+                &durable_nonce,
+                &block_hash,
+            )
+            .await
+        }
+        _ => Ok(()),
     };
-    success_response(response)
+    if let Err(e) = nonce_checked {
+        return error_response(e);
+    }
+
+    let builder = TransferBuilder::new(aggpubkey, to, lamports)
+        .memo(req.memo.clone())
+        .signed_memo(req.signed_memo)
+        .memo_program(memo_program)
+        .compute_unit_price(req.priority_fee_micro_lamports)
+        .durable_nonce(durable_nonce)
+        .references(references.clone());
+    // Resolved once here, the aggregation step reuses the number instead of simulating again
+    let rpc_client = req
+        .net
+        .map(|net| async_rpc_client(&state, &snapshot.config, net));
+    let compute_units = match resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        rpc_client.as_deref(),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| Ok(builder.clone().compute_unit_limit(limit).build()),
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => return error_response(e),
+    };
+    let compute_unit_limit = compute_units.map(|r| r.limit);
+    let builder = builder.compute_unit_limit(compute_unit_limit);
+
+    let message = transfer_message(&builder, block_hash);
+    if let Err(e) = snapshot
+        .config
+        .writable_policy
+        .check(&message)
+        .and_then(|()| check_message_hash(&message, req.expected_message_hash.as_deref()))
+    {
+        return error_response(e);
+    }
+    let account_keys = req.include_account_keys.then(|| account_keys(&message));
+
+    let transfer = Transfer::Sol {
+        to: &to,
+        lamports,
+        memo: req.memo.as_deref(),
+        signed_memo: req.signed_memo,
+        memo_program: &memo_program,
+        compute_unit_limit,
+        compute_unit_price: req.priority_fee_micro_lamports,
+        references: &references,
+        nonce_account: durable_nonce.as_ref().map(|nonce| &nonce.account),
+    };
+    // A threshold ceremony is that of its signers, the other parties of the key never take part
+    let ceremony_keys = threshold
+        .as_ref()
+        .map_or(&keys, |threshold| &threshold.signers);
+    let digest = request_digest(&transfer, &block_hash, ceremony_keys);
+    let parties = ceremony_keys.len();
+    let signer = keypair.pubkey();
+
+    let secret_state = match take_secret_state(
+        &state,
+        &snapshot,
+        &keypair.pubkey(),
+        req.secret_state.as_deref(),
+        req.secret_state_id.as_deref(),
+    ) {
+        Ok(secret_state) => secret_state,
+        Err(resp) => return resp,
+    };
+
+    let signed = match (key_share, &threshold) {
+        (Some(key_share), Some(threshold)) => threshold_step_two(
+            keypair,
+            &builder,
+            block_hash,
+            key_share,
+            &threshold.indices,
+            first_messages,
+            secret_state,
+        ),
+        _ => step_two(
+            keypair,
+            &builder,
+            block_hash,
+            keys,
+            first_messages,
+            secret_state,
+        ),
+    };
+    let (sig, message) = match signed {
+        Ok(signed) => signed,
+        Err(e) => return error_response(e),
+    };
+    let audit_id = audit_signed(
+        &state,
+        &snapshot,
+        headers,
+        "agg_send_step_two",
+        Some(digest.as_str()),
+        &message,
+    );
+    state
+        .audit
+        .ceremonies()
+        .started(&digest, parties, Instant::now());
+
+    let context = SignatureContext::new(
+        &digest,
+        &aggpubkey,
+        &signer,
+        snapshot.config.environment.as_deref(),
+        req.session_id.clone(),
+    );
+    let response = AggSendStepTwoResponse {
+        partial_signature: labeled(&snapshot.config, &sig),
+        key_index,
+        audit_id,
+        request_digest: digest,
+        compute_unit_limit: compute_units,
+        account_keys,
+        context,
+    };
+    success_response(Warned::new(response, warnings))
 }
 
 #[handler]
-async fn agg_deactivate_stake_step_two(
-    req: Json<AggDeactivateStakeStepTwoRequest>,
+async fn aggregate_signatures(
+    req: Json<AggregateSignaturesRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
 ) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    if req.dry_run && req.broadcast_at.is_some() {
+        return error_response(Error::InvalidBroadcastAt(
+            "can't be combined with dry_run, which sends nothing".to_string(),
+        ));
+    }
+    let to = match parse_pubkey(&req.to) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
     };
 
-    let stake_accountt = match parse_pubkey(&req.stake_account) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return error_response(e),
     };
 
     let block_hash = match parse_hash(&req.recent_block_hash) {
         Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
-    {
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
         Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let first_messages: Vec<AggMessage1> = match req
-        .first_messages
-        .iter()
-        .map(|m| AggMessage1::deserialize_bs58(m))
-        .collect::<Result<_, _>>()
-    {
-        Ok(msgs) => msgs,
-        Err(e) => return error_response(e.to_string()),
+    let threshold = match resolve_threshold(req.threshold.as_ref(), &keys) {
+        Ok(threshold) => threshold,
+        Err(resp) => return resp,
     };
+    if let Some(threshold) = &threshold {
+        if req.signatures.len() != threshold.signers.len() {
+            return error_response(Error::InvalidThreshold(format!(
+                "{} signatures for {} signers, one of each is needed",
+                req.signatures.len(),
+                threshold.signers.len()
+            )));
+        }
+    }
 
-    let secret_state = match SecretAggStepOne::deserialize_bs58(&req.secret_state) {
-        Ok(state) => state,
-        Err(e) => return error_response(e.to_string()),
+    let submitted: Vec<&str> = req
+        .signatures
+        .iter()
+        .map(SignatureSubmission::partial_signature)
+        .collect();
+    let signatures = match parse_signatures(&snapshot.config, &submitted) {
+        Ok(sigs) => sigs,
+        Err(e) => return error_response(e),
     };
 
-    let sig = match deactivate_stake_step_two(
-        keypair,
-        stake_accountt,
-        block_hash,
-        keys,
-        first_messages,
-        secret_state,
-    ) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(e.to_string()),
+    let lamports = match resolve_lamports(req.amount.as_ref(), req.lamports) {
+        Ok(lamports) => lamports,
+        Err(e) => return error_response(e),
     };
 
-    let response = AggDeactivateStakeStepTwoResponse {
-        partial_signature: sig.serialize_bs58(),
+    let aggpubkey = match ceremony_pubkey(&keys, threshold.as_ref()) {
+        Ok(key) => key,
+        Err(e) => return error_response(e),
     };
-    success_response(response)
-}
 
-#[handler]
-async fn agg_withdraw_stake_step_two(
-    req: Json<AggWithdrawStakeStepTwoRequest>,
-) -> impl IntoResponse {
-    let keypair = match parse_keypair_bs58(&req.keypair) {
-        Ok(kp) => kp,
-        Err(e) => return error_response(e.to_string()),
-    };
+    let durable_nonce = match resolve_durable_nonce(
+        req.nonce_account.as_deref(),
+        req.nonce_authority.as_deref(),
+        aggpubkey,
+    ) {
+        Ok(durable_nonce) => durable_nonce,
+        Err(resp) => return resp,
+    };
+
+    let transfer = Transfer::Sol {
+        to: &to,
+        lamports,
+        memo: req.memo.as_deref(),
+        signed_memo: req.signed_memo,
+        memo_program: &memo_program,
+        compute_unit_limit: req.compute_unit_limit,
+        compute_unit_price: req.priority_fee_micro_lamports,
+        references: &references,
+        nonce_account: durable_nonce.as_ref().map(|nonce| &nonce.account),
+    };
+    let ceremony_keys = threshold
+        .as_ref()
+        .map_or(&keys, |threshold| &threshold.signers);
+    let digest = request_digest(&transfer, &block_hash, ceremony_keys);
+
+    if let Err(e) = check_signature_contexts(
+        &snapshot.config,
+        &req.signatures,
+        req.session_id.as_deref(),
+        &digest,
+        &aggpubkey,
+        ceremony_keys,
+    ) {
+        return error_response(e);
+    }
 
-    let stake_accountt = match parse_pubkey(&req.stake_account) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+    let mut warnings = match check_recipient(
+        &state.accounts,
+        async_rpc_client(&state, &snapshot.config, net),
+        net,
+        &to,
+        req.allow_non_system_recipient,
+    )
+    .await
+    {
+        Ok(warning) => Vec::from_iter(warning),
+        Err(e) => return error_response(e),
     };
 
-    let destination = match parse_pubkey(&req.destination) {
-        Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
-    };
+    if let Some(durable_nonce) = durable_nonce {
+        let nonce_checked = check_nonce(
+            &async_rpc_client(&state, &snapshot.config, net),
+            &durable_nonce,
+            &block_hash,
+        )
+        .await;
+        if let Err(e) = nonce_checked {
+            return error_response(e);
+        }
+    }
 
-    let block_hash = match parse_hash(&req.recent_block_hash) {
-        Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+    // Paid by the ceremony's key, the group key when it is a threshold ceremony
+    let builder = TransferBuilder::new(aggpubkey, to, lamports)
+        .memo(req.memo.clone())
+        .signed_memo(req.signed_memo)
+        .memo_program(memo_program)
+        .compute_unit_limit(req.compute_unit_limit)
+        .compute_unit_price(req.priority_fee_micro_lamports)
+        .durable_nonce(durable_nonce)
+        .references(references);
+    let tx = match sign_and_broadcast(&builder, block_hash, signatures) {
+        Ok(transaction) => transaction,
+        Err(e) => return error_response(e),
     };
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
-    {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+    if let Err(e) = snapshot.config.writable_policy.check(&tx.message) {
+        return error_response(e);
+    }
+
+    // Nothing is spent by a dry run, it's handed back instead of being sent
+    if req.dry_run {
+        audit_dry_run(
+            &state,
+            &snapshot,
+            headers,
+            "aggregate_signatures",
+            &digest,
+            &tx,
+        );
+        let response = AggregateSignaturesResponse {
+            transaction_id: tx.signatures[0].to_string(),
+            rpc_endpoint: None,
+            commitment: None,
+            request_digest: digest,
+            broadcast_at: None,
+            duplicate: false,
+            status: None,
+            transaction: Some(encode_transaction(&tx)),
+        };
+        return network_response(net, Warned::new(response, warnings));
+    }
+
+    // Scheduled transactions aren't checked, the account can still be funded before they go out
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let funded = match req.broadcast_at {
+        None => check_funded(&rpc_client, &tx.message, lamports.get(), None).await,
+        Some(_) => Ok(None),
     };
+    match funded {
+        Ok(warning) => warnings.extend(warning),
+        Err(e) => return error_response(e),
+    }
 
-    let first_messages: Vec<AggMessage1> = match req
-        .first_messages
-        .iter()
-        .map(|m| AggMessage1::deserialize_bs58(m))
-        .collect::<Result<_, _>>()
+    let reservation = match reserve_spend(&snapshot, headers, Asset::Sol, lamports.get()) {
+        Ok(reservation) => reservation,
+        Err(e) => return error_response(e),
+    };
+
+    if let Some(broadcast_at) = req.broadcast_at {
+        let scheduled = ScheduledBroadcast {
+            broadcast_at,
+            idempotency_key: idempotency_key(headers),
+            transaction: tx,
+        };
+        return match schedule_broadcast(
+            &state,
+            &snapshot,
+            headers,
+            "aggregate_signatures",
+            Some(&digest),
+            net,
+            scheduled,
+            reservation,
+        )
+        .await
+        {
+            Ok(signature) => {
+                let response = AggregateSignaturesResponse {
+                    transaction_id: signature.to_string(),
+                    request_digest: digest,
+                    rpc_endpoint: None,
+                    commitment: None,
+                    broadcast_at: Some(broadcast_at),
+                    duplicate: false,
+                    status: None,
+                    transaction: None,
+                };
+                network_response(net, Warned::new(response, warnings))
+            }
+            Err(e) => error_response(e),
+        };
+    }
+
+    let broadcast = Broadcast::new(
+        state.audit.clone(),
+        "aggregate_signatures",
+        Some(digest.clone()),
+        tx,
+    );
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .wait(req.wait_for_confirmation)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
     {
-        Ok(msgs) => msgs,
-        Err(e) => return error_response(e.to_string()),
+        Ok(sent) => sent,
+        Err(e) => {
+            release_spend(&snapshot, reservation, &e);
+            return error_response(e);
+        }
     };
 
-    let secret_state = match SecretAggStepOne::deserialize_bs58(&req.secret_state) {
-        Ok(state) => state,
-        Err(e) => return error_response(e.to_string()),
-    };
+    let response = AggregateSignaturesResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: Some(sent.rpc_endpoint),
+        commitment: sent.commitment,
+        status: sent.status(),
+        request_digest: digest,
+        broadcast_at: None,
+        duplicate: sent.duplicate,
+        transaction: None,
+    };
+    network_response(net, Warned::new(response, warnings))
+}
 
-    let sig = match withdraw_stake_step_two(
-        keypair,
-        stake_accountt,
-        destination,
-        req.amount,
-        block_hash,
-        keys,
-        first_messages,
-        secret_state,
-    ) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(e.to_string()),
-    };
+//////////////////////// threshold keygen /////////////////////////////
 
-    let response = AggWithdrawStakeStepTwoResponse {
-        partial_signature: sig.serialize_bs58(),
+#[handler]
+async fn threshold_keygen_step_one(
+    req: Json<ThresholdKeygenStepOneRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
     };
-    success_response(response)
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    match keygen_step_one(&keypair, &keys, req.threshold) {
+        Ok(message) => success_response(ThresholdKeygenStepOneResponse {
+            message_1: labeled(&snapshot.config, &message),
+        }),
+        Err(e) => error_response(e),
+    }
 }
 
 #[handler]
-async fn aggregate_stake_signatures(
-    req: Json<AggregateStakeSignaturesRequest>,
+async fn threshold_keygen_step_two(
+    req: Json<ThresholdKeygenStepTwoRequest>,
+    state: Data<&Arc<AppState>>,
 ) -> impl IntoResponse {
-    let vote_account = match parse_pubkey(&req.validator_vote_accont) {
-        Ok(vc) => vc,
-        Err(e) => return error_response(e.to_string()),
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
     };
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+    let first_messages: Vec<KeygenMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+
+    // The share stays here until every party confirmed it saw the same keygen
+    match keygen_step_two(&keypair, &keys, req.threshold, &first_messages) {
+        Ok(share) => {
+            let transcript = keygen_transcript(&keys, req.threshold, &first_messages, &share);
+            success_response(ThresholdKeygenStepTwoResponse {
+                group_key: group_pubkey(&share).to_string(),
+                threshold: req.threshold,
+                key_index: usize::from(share.index),
+                transcript_hash: transcript.iter().map(|b| format!("{:02x}", b)).collect(),
+                confirmation: confirm_keygen(&keypair, &transcript).to_string(),
+            })
+        }
+        Err(e) => error_response(e),
+    }
+}
 
-    let block_hash = match parse_hash(&req.recent_block_hash) {
-        Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn threshold_keygen_step_three(
+    req: Json<ThresholdKeygenStepThreeRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
     };
-
-    let keys: Vec<Pubkey> = match req
-        .keys
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+    let first_messages: Vec<KeygenMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+    let confirmations: Vec<Signature> = match req
+        .confirmations
         .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
+        .enumerate()
+        .map(|(i, confirmation)| {
+            Signature::from_str(confirmation).map_err(|e| {
+                Error::InvalidThreshold(format!("confirmation {} isn't a signature: {}", i, e))
+            })
+        })
+        .collect()
     {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+        Ok(confirmations) => confirmations,
+        Err(e) => return error_response(e),
     };
 
-    let signatures: Vec<PartialSignature> = match req
-        .signatures
-        .iter()
-        .map(|s| PartialSignature::deserialize_bs58(s))
-        .collect::<Result<_, _>>()
-    {
-        Ok(sigs) => sigs,
-        Err(e) => return error_response(e.to_string()),
+    let share = match keygen_step_two(&keypair, &keys, req.threshold, &first_messages) {
+        Ok(share) => share,
+        Err(e) => return error_response(e),
     };
+    let transcript = keygen_transcript(&keys, req.threshold, &first_messages, &share);
+    if let Err(e) = check_confirmations(&keys, &transcript, &confirmations) {
+        return error_response(e);
+    }
+    success_response(ThresholdKeygenStepThreeResponse {
+        group_key: group_pubkey(&share).to_string(),
+        threshold: req.threshold,
+        key_index: usize::from(share.index),
+        key_share: labeled(&snapshot.config, &share),
+    })
+}
 
-    let tx = match aggregate_stake_signatures_and_broadcast(
-        req.stake_amount,
-        req.seed.clone(),
-        vote_account,
-        block_hash,
-        keys,
-        signatures,
-    ) {
-        Ok(tx) => tx,
-        Err(e) => return error_response(e.to_string()),
+//////////////////////// spl /////////////////////////////
+
+// token_mint = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"
+// 6A2GHg17A2YUbLp7qma1pbvnS7deav7Tq3tthQHa8zt5
+#[handler]
+async fn list_tokens(state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    success_response(TokensResponse {
+        tokens: snapshot.tokens.list(),
+        deleted: snapshot.tokens.deleted(),
+    })
+}
+
+#[handler]
+async fn upsert_token(req: Json<TokenEntry>, state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let mint = match parse_pubkey(&req.mint) {
+        Ok(mint) => mint,
+        Err(e) => return error_response(e),
     };
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+    // Same check as at startup, so an entry added here can't disagree with its mint either
+    let rpc_client = async_rpc_client(&state, &snapshot.config, req.net);
+    match fetch_mint_decimals(&rpc_client, &mint).await {
+        Ok(decimals) => {
+            if let Some(e) = chain_mismatch(&req, decimals) {
+                return error_response(Error::InvalidToken(e));
+            }
+        }
+        Err(e) => return error_response(Error::AccountFetchFailed(e)),
+    }
+
+    let config = TokenConfig {
+        mint: mint.to_string(),
+        decimals: req.decimals,
+        net: req.net,
+        max_per_transaction: req.max_per_transaction,
+        allow_create_ata: req.allow_create_ata,
     };
+    match snapshot.tokens.upsert(&req.symbol, &config) {
+        Ok(entry) => success_response(entry),
+        Err(e) => error_response(e),
+    }
+}
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &block_hash, rpc_client.commitment())
-    {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+/// Set a token aside, or forget one that was set aside with `purge`. Both are audited.
+#[handler]
+async fn delete_token(
+    req: Json<DeleteTokenRequest>,
+    headers: &HeaderMap,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let (deleted, outcome) = match req.purge {
+        false => (snapshot.tokens.remove(&req.symbol), Outcome::Deleted),
+        true => (snapshot.tokens.purge(&req.symbol), Outcome::Purged),
+    };
+    match deleted {
+        Ok(entry) => {
+            audit_token(
+                &state,
+                &snapshot,
+                headers,
+                "delete_token",
+                outcome,
+                &entry.token.symbol,
+            );
+            success_response(entry)
+        }
+        Err(e) => error_response(e),
     }
+}
 
-    let response = AggregateStakeSignaturesResponse {
-        transaction_id: sig.to_string(),
+#[handler]
+async fn restore_token(
+    req: Json<RestoreTokenRequest>,
+    headers: &HeaderMap,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    match snapshot.tokens.restore(&req.symbol) {
+        Ok(entry) => {
+            audit_token(
+                &state,
+                &snapshot,
+                headers,
+                "restore_token",
+                Outcome::Restored,
+                &entry.symbol,
+            );
+            success_response(entry)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+#[handler]
+async fn spl_token_balance(
+    req: Json<SplTokenBalanceRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
     };
-    success_response(response)
+    let owner = match parse_pubkey(&req.owner) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let token_mint =
+        match snapshot
+            .tokens
+            .resolve_mint(req.token.as_deref(), req.token_mint.as_deref(), net)
+        {
+            Ok(mint) => mint,
+            Err(e) => return error_response(e),
+        };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let registered_decimals = snapshot.tokens.registered_decimals(&token_mint, net);
+    match token_balance(
+        &state.accounts,
+        rpc_client,
+        net,
+        &owner,
+        &token_mint,
+        registered_decimals,
+        req.fresh,
+    )
+    .await
+    {
+        Ok(response) => network_response(net, response),
+        Err(e) => error_response(e),
+    }
+}
+
+/// The transfer `spl_send_single` signs, its cost preview prices the same one.
+#[allow(clippy::too_many_arguments)]
+fn spl_single_transfer(
+    owner: Pubkey,
+    to: Pubkey,
+    token: &ResolvedToken,
+    amount: RawTokenAmount,
+    memo: Option<String>,
+    signed_memo: bool,
+    memo_program: Pubkey,
+    to_ata_exists: bool,
+    rent_sponsor: Option<Pubkey>,
+) -> SplTransferBuilder {
+    SplTransferBuilder::new(owner, to, token.mint, amount, token.decimals)
+        .token_program(token.program)
+        .memo(memo)
+        .signed_memo(signed_memo)
+        .memo_program(memo_program)
+        .create_recipient_ata(!to_ata_exists)
+        .rent_sponsor(rent_sponsor)
 }
 
 #[handler]
-async fn aggregate_deactivate_stake_signatures(
-    req: Json<AggregateDeactivateStakeSignaturesRequest>,
+async fn spl_send_single(
+    req: Json<SplSendSingleRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
 ) -> impl IntoResponse {
-    let stake_accountt = match parse_pubkey(&req.stake_account) {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let to = match parse_pubkey(&req.to) {
         Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let block_hash = match parse_hash(&req.recent_block_hash) {
-        Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+    let rent_sponsor = match req.rent_sponsor_keypair.as_deref().map(parse_keypair) {
+        None => None,
+        Some(Ok(kp)) => Some(kp),
+        Some(Err(e)) => {
+            return error_response(Error::InvalidKeypair(format!(
+                "rent_sponsor_keypair: {}",
+                e
+            )));
+        }
     };
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return error_response(e),
+    };
+
+    let mut token = match resolve_token(
+        &snapshot.tokens,
+        req.token.as_deref(),
+        req.token_mint.as_deref(),
+        req.decimals,
+        Some(net),
+    ) {
+        Ok(token) => token,
+        Err(e) => return error_response(e),
+    };
+    let token_mint = token.mint;
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+
+    if let Err(e) = resolve_token_program(&mut token, None, Some(&rpc_client)).await {
+        return error_response(e);
+    }
+
+    let token_amount = match spl_amount(&token, req.amount.as_ref(), req.amount_base_units) {
+        Ok(amount) => amount,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = validate_source_account(
+        &rpc_client,
+        &token.ata(&keypair.pubkey()),
+        &token_mint,
+        &keypair.pubkey(),
+        req.allow_delegate,
+        token_amount,
+    )
+    .await
     {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+        return error_response(e);
+    }
+
+    // Create destination ATA if it doesn't exist
+    let recipient_ata = token.ata(&to);
+    let to_ata_exists = match account_exists(&rpc_client, &recipient_ata).await {
+        Ok(exists) => exists,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = token.check_recipient_ata(&recipient_ata, Some(to_ata_exists)) {
+        return error_response(e);
+    }
+    let builder = spl_single_transfer(
+        keypair.pubkey(),
+        to,
+        &token,
+        token_amount,
+        req.memo.clone(),
+        req.signed_memo,
+        memo_program,
+        to_ata_exists,
+        rent_sponsor.as_ref().map(|kp| kp.pubkey()),
+    )
+    .compute_unit_price(req.priority_fee_micro_lamports)
+    .references(references);
+    // Only a sponsor whose funds are used signs
+    let sponsor = rent_sponsor
+        .as_ref()
+        .filter(|_| builder.sponsor_signer().is_some());
+
+    let compute_units = match resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        Some(&rpc_client),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| builder.clone().compute_unit_limit(limit).build(),
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => return error_response(e),
     };
 
-    let signatures: Vec<PartialSignature> = match req
-        .signatures
-        .iter()
-        .map(|s| PartialSignature::deserialize_bs58(s))
-        .collect::<Result<_, _>>()
+    let message = match builder
+        .compute_unit_limit(compute_units.map(|r| r.limit))
+        .build()
     {
-        Ok(sigs) => sigs,
-        Err(e) => return error_response(e.to_string()),
+        Ok(message) => message,
+        Err(e) => return error_response(e),
     };
 
-    let tx = match aggregate_deactivate_stake_signatures_and_broadcast(
-        stake_accountt,
-        block_hash,
-        keys,
-        signatures,
+    // Create and sign transaction
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+
+    let mut tx = Transaction::new_unsigned(message);
+    match sponsor {
+        Some(sponsor) => tx.sign(&[&keypair, sponsor], recent_hash),
+        None => tx.sign(&[&keypair], recent_hash),
+    }
+
+    let reservation = match reserve_spend(
+        &snapshot,
+        headers,
+        Asset::Token(token_mint),
+        token_amount.get(),
     ) {
-        Ok(tx) => tx,
-        Err(e) => return error_response(e.to_string()),
+        Ok(reservation) => reservation,
+        Err(e) => return error_response(e),
+    };
+
+    let broadcast = Broadcast::new(state.audit.clone(), "spl_send_single", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .wait(req.wait_for_confirmation)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => {
+            release_spend(&snapshot, reservation, &e);
+            return error_response(e);
+        }
     };
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+    let response = SplSendSingleResponse {
+        transaction_id: sent.signature.to_string(),
+        status: sent.status(),
+        rpc_endpoint: sent.rpc_endpoint,
+        compute_unit_limit: compute_units,
+    };
+    network_response(net, response)
+}
+
+/// What a proposed SPL transfer costs, read off the messages it would send. Nothing is signed.
+#[handler]
+async fn spl_cost_preview(
+    req: Json<SplCostPreviewRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    if req.recipients.len() > MAX_TRANSFER_RECIPIENTS {
+        return error_response(Error::TooManyRecipients {
+            len: req.recipients.len(),
+            max: MAX_TRANSFER_RECIPIENTS,
+        });
+    }
+    let memo_program = match resolve_memo_program(
+        req.memo_program.as_deref(),
+        snapshot.config.allow_custom_memo_program,
+    ) {
+        Ok(program_id) => program_id,
+        Err(e) => return error_response(e),
+    };
+
+    let mut token = match resolve_token(
+        &snapshot.tokens,
+        req.token.as_deref(),
+        req.token_mint.as_deref(),
+        req.decimals,
+        req.net,
+    ) {
+        Ok(token) => token,
+        Err(e) => return error_response(e),
+    };
+
+    let rent_sponsor = match req.rent_sponsor.as_deref().map(parse_pubkey).transpose() {
+        Ok(sponsor) => sponsor,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match req.recent_block_hash.as_deref().map(parse_hash).transpose() {
+        Ok(hash) => hash.unwrap_or_default(),
+        Err(e) => return error_response(e),
+    };
+
+    // The aggregated key of a TSS transfer, or the owner of a single-key one
+    let (payer, tss) = match (&req.owner, req.keys.is_empty()) {
+        (Some(owner), true) => match parse_pubkey(owner) {
+            Ok(owner) => (owner, false),
+            Err(e) => return error_response(e),
+        },
+        (None, false) => {
+            let keys = match parse_keys(&snapshot.config, &req.keys) {
+                Ok(keys) => keys,
+                Err(e) => return error_response(e),
+            };
+            match aggregated_pubkey(keys) {
+                Ok(key) => (key, true),
+                Err(e) => return error_response(e),
+            }
+        }
+        _ => {
+            return error_response(Error::InvalidRequest(
+                "exactly one of owner and keys is required".to_string(),
+            ));
+        }
     };
 
+    let rpc_client = req
+        .net
+        .map(|net| async_rpc_client(&state, &snapshot.config, net));
     if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &block_hash, rpc_client.commitment())
+        resolve_token_program(&mut token, req.token_program, rpc_client.as_deref()).await
     {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+        return error_response(e);
+    }
+    let recipients = std::iter::once((
+        None,
+        req.to.as_str(),
+        req.amount.as_ref(),
+        req.amount_base_units,
+    ))
+    .chain(req.recipients.iter().enumerate().map(|(i, r)| {
+        (
+            Some(i),
+            r.to.as_str(),
+            r.amount.as_ref(),
+            r.amount_base_units,
+        )
+    }));
+    let mut parsed = Vec::new();
+    for (entry, to, amount, base_units) in recipients {
+        let to = match parse_pubkey(to) {
+            Ok(addr) => addr,
+            Err(e) => return error_response(e),
+        };
+        match spl_amount(&token, amount, base_units) {
+            Ok(amount) => parsed.push((to, amount)),
+            Err(e) => {
+                return error_response(match entry {
+                    Some(i) => in_recipient(i, e),
+                    None => e,
+                });
+            }
+        }
     }
 
-    let response = AggregateDeactivateStakeSignaturesResponse {
-        transaction_id: sig.to_string(),
+    // Every recipient ATA is read at once, so they share one batch of the account batcher
+    let existing: Vec<Option<bool>> = match (&rpc_client, req.net) {
+        (Some(rpc_client), Some(net)) => {
+            let reads = parsed.iter().map(|(to, _)| {
+                state
+                    .accounts
+                    .get_account(rpc_client.clone(), net, token.ata(to))
+            });
+            match futures_util::future::join_all(reads)
+                .await
+                .into_iter()
+                .map(|account| account.map(|account| Some(account.is_some())))
+                .collect::<Result<_, _>>()
+            {
+                Ok(existing) => existing,
+                Err(e) => return error_response(Error::AccountFetchFailed(e)),
+            }
+        }
+        _ => vec![None; parsed.len()],
+    };
+
+    let mut transfers = Vec::new();
+    let mut costs = Vec::new();
+    let mut raw_amount: u64 = 0;
+    for ((to, token_amount), exists) in parsed.into_iter().zip(existing) {
+        let recipient_ata = token.ata(&to);
+        if let Err(e) = token.check_recipient_ata(&recipient_ata, exists) {
+            return error_response(e);
+        }
+
+        let builder = spl_single_transfer(
+            payer,
+            to,
+            &token,
+            token_amount,
+            req.memo.clone(),
+            req.signed_memo,
+            memo_program,
+            exists.unwrap_or(false),
+            rent_sponsor,
+        )
+        .compute_unit_limit(req.compute_unit_limit);
+        // A TSS transfer always creates the recipient ATA, see `spl_transfer_message`
+        let message = match tss {
+            true => spl_transfer_message(&builder, block_hash),
+            false => builder.build().map(|mut message| {
+                message.recent_blockhash = block_hash;
+                message
+            }),
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => return error_response(e),
+        };
+
+        let cost = message_cost(&message, |account| {
+            exists.filter(|_| *account == recipient_ata)
+        });
+        raw_amount = raw_amount.saturating_add(token_amount.get());
+        transfers.push(TransferCost {
+            to: to.to_string(),
+            recipient_ata: recipient_ata.to_string(),
+            amount: token_amount.to_ui_string(token.decimals),
+            raw_amount: token_amount.get(),
+            recipient_ata_exists: exists,
+            fee_payer: cost.fee_payer.to_string(),
+            fee_lamports: cost.fee_lamports,
+            rent: cost
+                .rent
+                .iter()
+                .map(|charge| RentCost {
+                    account: charge.account.to_string(),
+                    payer: charge.payer.to_string(),
+                    lamports: charge.lamports,
+                })
+                .collect(),
+            message_hash: message_hash(&message),
+        });
+        costs.push(cost);
+    }
+
+    let debits = debits(&costs);
+    let response = SplCostPreviewResponse {
+        token_mint: token.mint.to_string(),
+        raw_amount,
+        transfers,
+        total_lamports: debits.values().sum(),
+        debits: debits
+            .into_iter()
+            .map(|(account, lamports)| AccountDebit {
+                account: account.to_string(),
+                lamports,
+            })
+            .collect(),
     };
     success_response(response)
 }
 
+/// The fee of a `send_single` transfer, or with a token of a `spl_send_single` one, as the node
+/// prices it. Nothing is signed.
 #[handler]
-async fn aggregate_withdraw_stake_signatures(
-    req: Json<AggregateWithdrawStakeSignaturesRequest>,
+async fn estimate_fee(
+    req: Json<EstimateFeeRequest>,
+    state: Data<&Arc<AppState>>,
 ) -> impl IntoResponse {
-    let stake_accountt = match parse_pubkey(&req.stake_account) {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let payer = match parse_pubkey(&req.payer) {
+        Ok(payer) => payer,
+        Err(e) => return error_response(e),
+    };
+    let to = match parse_pubkey(&req.to) {
         Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let destination = match parse_pubkey(&req.destination) {
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return error_response(e),
+    };
+
+    let built = match req.token.is_some() || req.token_mint.is_some() {
+        true => {
+            spl_fee_message(
+                &state,
+                &snapshot,
+                &req,
+                net,
+                payer,
+                to,
+                memo_program,
+                references,
+            )
+            .await
+        }
+        false => {
+            sol_fee_message(
+                &state,
+                &snapshot,
+                &req,
+                net,
+                payer,
+                to,
+                memo_program,
+                references,
+            )
+            .await
+        }
+    };
+    let (mut message, compute_unit_limit) = match built {
+        Ok(built) => built,
+        Err(response) => return response,
+    };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let fee_lamports = match cost::estimate_fee(&rpc_client, &mut message).await {
+        Ok(fee) => fee,
+        Err(e) => return error_response(e),
+    };
+
+    let response = EstimateFeeResponse {
+        fee_lamports,
+        fee_payer: message.account_keys[0].to_string(),
+        signatures: message.header.num_required_signatures,
+        recent_block_hash: message.recent_blockhash.to_string(),
+        compute_unit_limit,
+    };
+    network_response(net, response)
+}
+
+/// The message of the SOL transfer `/api/estimate_fee` prices, built as `send_single` builds it.
+async fn sol_fee_message(
+    state: &AppState,
+    snapshot: &Snapshot,
+    req: &EstimateFeeRequest,
+    net: Network,
+    payer: Pubkey,
+    to: Pubkey,
+    memo_program: Pubkey,
+    references: Vec<Pubkey>,
+) -> Result<(Message, Option<ComputeUnitReport>), Response> {
+    if req.amount_base_units.is_some() {
+        return Err(error_response(Error::InvalidRequest(
+            "amount_base_units is for token transfers, a SOL amount goes in lamports".to_string(),
+        )));
+    }
+    if req.recipients.len() > MAX_TRANSFER_RECIPIENTS {
+        return Err(error_response(Error::TooManyRecipients {
+            len: req.recipients.len(),
+            max: MAX_TRANSFER_RECIPIENTS,
+        }));
+    }
+    if !references.is_empty() && !req.recipients.is_empty() {
+        return Err(error_response(Error::InvalidSolanaPay(
+            "references can't be combined with recipients".to_string(),
+        )));
+    }
+
+    let lamports = resolve_lamports(req.amount.as_ref(), req.lamports).map_err(error_response)?;
+    let mut builder = TransferBuilder::new(payer, to, lamports)
+        .memo(req.memo.clone())
+        .signed_memo(req.signed_memo)
+        .memo_program(memo_program)
+        .compute_unit_price(req.priority_fee_micro_lamports)
+        .references(references);
+    for (i, recipient) in req.recipients.iter().enumerate() {
+        let to = parse_pubkey(&recipient.to).map_err(error_response)?;
+        let lamports = resolve_lamports(recipient.amount.as_ref(), recipient.lamports)
+            .map_err(|e| error_response(in_recipient(i, e)))?;
+        builder = builder.recipient(to, lamports);
+    }
+
+    // Split transfers are sent as several transactions, each with a fee of its own
+    let sized = builder
+        .clone()
+        .compute_unit_limit(req.compute_unit_limit.map(|_| 0));
+    if let Some(oversize) = sized.oversize() {
+        return Err(error_response(Error::TransactionTooLarge {
+            oversize,
+            splittable: true,
+        }));
+    }
+
+    let rpc_client = async_rpc_client(state, &snapshot.config, net);
+    let compute_units = resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        Some(&rpc_client),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| Ok(builder.clone().compute_unit_limit(limit).build()),
+    )
+    .await
+    .map_err(error_response)?;
+    let message = builder
+        .compute_unit_limit(compute_units.map(|r| r.limit))
+        .build();
+    Ok((message, compute_units))
+}
+
+/// The message of the token transfer `/api/estimate_fee` prices, built as `spl_send_single`
+/// builds it.
+async fn spl_fee_message(
+    state: &AppState,
+    snapshot: &Snapshot,
+    req: &EstimateFeeRequest,
+    net: Network,
+    payer: Pubkey,
+    to: Pubkey,
+    memo_program: Pubkey,
+    references: Vec<Pubkey>,
+) -> Result<(Message, Option<ComputeUnitReport>), Response> {
+    if req.lamports.is_some() {
+        return Err(error_response(Error::InvalidRequest(
+            "lamports is for SOL transfers, a token amount goes in amount_base_units".to_string(),
+        )));
+    }
+    if !req.recipients.is_empty() {
+        return Err(error_response(Error::InvalidRequest(
+            "recipients are only paid by SOL transfers".to_string(),
+        )));
+    }
+
+    let mut token = resolve_token(
+        &snapshot.tokens,
+        req.token.as_deref(),
+        req.token_mint.as_deref(),
+        req.decimals,
+        Some(net),
+    )
+    .map_err(error_response)?;
+    let rent_sponsor = req
+        .rent_sponsor
+        .as_deref()
+        .map(parse_pubkey)
+        .transpose()
+        .map_err(error_response)?;
+    let token_amount =
+        spl_amount(&token, req.amount.as_ref(), req.amount_base_units).map_err(error_response)?;
+
+    let rpc_client = async_rpc_client(state, &snapshot.config, net);
+    resolve_token_program(&mut token, None, Some(&rpc_client))
+        .await
+        .map_err(error_response)?;
+    let recipient_ata = token.ata(&to);
+    let to_ata_exists = account_exists(&rpc_client, &recipient_ata)
+        .await
+        .map_err(error_response)?;
+    token
+        .check_recipient_ata(&recipient_ata, Some(to_ata_exists))
+        .map_err(error_response)?;
+    let builder = spl_single_transfer(
+        payer,
+        to,
+        &token,
+        token_amount,
+        req.memo.clone(),
+        req.signed_memo,
+        memo_program,
+        to_ata_exists,
+        rent_sponsor,
+    )
+    .compute_unit_price(req.priority_fee_micro_lamports)
+    .references(references);
+
+    let compute_units = resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        Some(&rpc_client),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| builder.clone().compute_unit_limit(limit).build(),
+    )
+    .await
+    .map_err(error_response)?;
+    let message = builder
+        .compute_unit_limit(compute_units.map(|r| r.limit))
+        .build()
+        .map_err(error_response)?;
+    Ok((message, compute_units))
+}
+
+/// The transfer a Solana Pay URL requests, in the fields of the endpoint that sends it.
+#[handler]
+async fn solana_pay_parse(req: Json<SolanaPayParseRequest>) -> impl IntoResponse {
+    let request = match TransferRequest::parse(&req.url) {
+        Ok(request) => request,
+        Err(e) => return error_response(e),
+    };
+    let endpoint = match request.spl_token {
+        Some(_) => "/api/spl_send_single",
+        None => "/api/send_single",
+    };
+    success_response(SolanaPayParseResponse {
+        endpoint: endpoint.to_string(),
+        to: request.recipient.to_string(),
+        amount: request.amount,
+        token_mint: request.spl_token.map(|mint| mint.to_string()),
+        memo: request.memo,
+        references: request.references.iter().map(Pubkey::to_string).collect(),
+        label: request.label,
+        message: request.message,
+    })
+}
+
+/// A Solana Pay URL requesting a transfer, a token one when `token` or `token_mint` is set.
+#[handler]
+async fn solana_pay_create(
+    req: Json<SolanaPayCreateRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let recipient = match parse_pubkey(&req.to) {
         Ok(addr) => addr,
-        Err(e) => return error_response(e.to_string()),
+        Err(e) => return error_response(e),
     };
 
-    let block_hash = match parse_hash(&req.recent_block_hash) {
-        Ok(hash) => hash,
-        Err(e) => return error_response(e.to_string()),
+    let references = match parse_pubkeys(&req.references) {
+        Ok(references) => references,
+        Err(e) => return error_response(e),
     };
 
-    let keys: Vec<Pubkey> = match req
-        .keys
-        .iter()
-        .map(|k| parse_pubkey(k))
-        .collect::<Result<_, _>>()
+    let token = match (req.token.as_deref(), req.token_mint.as_deref()) {
+        (None, None) => None,
+        (token, token_mint) => {
+            match snapshot
+                .tokens
+                .resolve(token, token_mint, req.decimals, req.net)
+            {
+                Ok(token) => Some(token),
+                Err(e) => return error_response(e),
+            }
+        }
+    };
+    let decimals = token.as_ref().map_or(Decimals::SOL, |token| token.decimals);
+    let amount = match req
+        .amount
+        .as_ref()
+        .map(|amount| solana_pay::url_amount(amount, decimals))
+        .transpose()
     {
-        Ok(keys) => keys,
-        Err(e) => return error_response(e.to_string()),
+        Ok(amount) => amount,
+        Err(e) => return error_response(e),
     };
 
-    let signatures: Vec<PartialSignature> = match req
-        .signatures
-        .iter()
-        .map(|s| PartialSignature::deserialize_bs58(s))
-        .collect::<Result<_, _>>()
-    {
-        Ok(sigs) => sigs,
-        Err(e) => return error_response(e.to_string()),
+    let request = TransferRequest {
+        recipient,
+        amount,
+        spl_token: token.map(|token| token.mint),
+        references,
+        label: req.label.clone(),
+        message: req.message.clone(),
+        memo: req.memo.clone(),
     };
+    success_response(SolanaPayCreateResponse {
+        url: request.to_url(),
+    })
+}
 
-    let tx = match aggregate_withdraw_stake_signatures_and_broadcast(
-        stake_accountt,
-        destination,
-        req.amount,
-        block_hash,
-        keys,
-        signatures,
-    ) {
-        Ok(tx) => tx,
-        Err(e) => return error_response(e.to_string()),
+#[handler]
+async fn spl_rent_summary(
+    req: Json<SplRentSummaryRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let owner = match parse_pubkey(&req.owner) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
     };
 
-    let rpc_client = RpcClient::new(req.net.get_cluster_url().to_string());
-    let sig = match rpc_client.send_transaction(&tx) {
-        Ok(signature) => signature,
-        Err(e) => return error_response(Error::SendTransactionFailed(e).to_string()),
+    let cursor = match req.cursor.as_deref().map(parse_pubkey).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => return error_response(e),
     };
 
-    if let Err(e) =
-        rpc_client.confirm_transaction_with_spinner(&sig, &block_hash, rpc_client.commitment())
-    {
-        return error_response(Error::ConfirmingTransactionFailed(e).to_string());
+    let limit = req.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return error_response(Error::InvalidRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
     }
 
-    let response = AggregateWithdrawStakeSignaturesResponse {
-        transaction_id: sig.to_string(),
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let accounts = match scan_token_accounts(&rpc_client, &owner).await {
+        Ok(accounts) => accounts,
+        Err(e) => return error_response(e),
     };
-    success_response(response)
+
+    let chunks = match plan_chunks(&owner, &accounts) {
+        Ok(chunks) => chunks,
+        Err(e) => return error_response(e),
+    };
+
+    let mut counts = TokenAccountCounts::default();
+    for account in &accounts {
+        match account.status(&owner) {
+            TokenAccountStatus::Closable => counts.closable += 1,
+            TokenAccountStatus::NonEmpty => counts.non_empty += 1,
+            TokenAccountStatus::Frozen => counts.frozen += 1,
+            TokenAccountStatus::Delegated => counts.delegated += 1,
+            TokenAccountStatus::ForeignCloseAuthority => counts.foreign_close_authority += 1,
+        }
+    }
+
+    // Accounts are sorted by address, so the cursor is simply the last address returned
+    let page: Vec<_> = accounts
+        .iter()
+        .filter(|a| cursor.is_none_or(|cursor| a.address > cursor))
+        .take(limit + 1)
+        .collect();
+    let next_cursor = (page.len() > limit).then(|| page[limit - 1].address.to_string());
+
+    let response = SplRentSummaryResponse {
+        owner: owner.to_string(),
+        total_accounts: accounts.len(),
+        counts,
+        reclaimable_lamports: chunks.iter().map(|c| c.lamports).sum(),
+        accounts: page
+            .into_iter()
+            .take(limit)
+            .map(|a| TokenAccountInfo {
+                address: a.address.to_string(),
+                mint: a.account.mint.to_string(),
+                amount: a.account.amount,
+                lamports: a.lamports,
+                status: a.status(&owner),
+                chunk_id: chunks
+                    .iter()
+                    .find(|c| c.accounts.contains(&a.address))
+                    .map(|c| c.id.clone()),
+            })
+            .collect(),
+        chunks: chunks
+            .iter()
+            .map(|c| CloseChunkInfo {
+                id: c.id.clone(),
+                accounts: c.accounts.len(),
+                lamports: c.lamports,
+            })
+            .collect(),
+        next_cursor,
+    };
+    network_response(net, response)
 }
 
-//staking end her
+#[handler]
+async fn spl_close_empty_accounts(
+    req: Json<SplCloseEmptyAccountsRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let app = Route::new()
-        .at("/api/generate", get(generate_keypair))
-        .at("/api/balance", post(balance))
-        .at("/api/airdrop", post(airdrop))
-        .at("/api/send_single", post(send_single))
-        .at("/api/recent_block_hash", post(recent_block_hash))
-        .at("/api/aggregate_keys", post(aggregate_keys))
-        .at("/api/agg_send_step_one", post(agg_send_step_one))
-        .at("/api/agg_send_step_two", post(agg_send_step_two))
-        .at("/api/aggregate_signatures", post(aggregate_signatures))
-        .at("/api/spl_token_balance", post(spl_token_balance))
-        .at("/api/spl_send_single", post(spl_send_single))
-        .at("/api/spl_agg_send_step_two", post(spl_agg_send_step_two))
-        .at(
-            "/api/spl_aggregate_signatures",
-            post(spl_aggregate_signatures),
+    let owner = keypair.pubkey();
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+
+    // Rebuild the plan and only execute chunks that are still exactly what was reviewed
+    let accounts = match scan_token_accounts(&rpc_client, &owner).await {
+        Ok(accounts) => accounts,
+        Err(e) => return error_response(e),
+    };
+
+    let plan = match plan_chunks(&owner, &accounts) {
+        Ok(chunks) => chunks,
+        Err(e) => return error_response(e),
+    };
+
+    let mut chunks = Vec::new();
+    for id in &req.chunk_ids {
+        match plan.iter().find(|c| &c.id == id) {
+            Some(chunk) => chunks.push(chunk),
+            None => return error_response(Error::StalePlan(id.clone())),
+        }
+    }
+
+    let mut closed = Vec::new();
+    for chunk in chunks {
+        let message = match chunk.message(&owner) {
+            Ok(message) => message,
+            Err(e) => return error_response(e),
+        };
+
+        let recent_hash = match rpc_client.get_latest_blockhash().await {
+            Ok(hash) => hash,
+            Err(e) => return error_response(Error::RecentHashFailed(e)),
+        };
+
+        let mut tx = Transaction::new_unsigned(message);
+        tx.sign(&[&keypair], recent_hash);
+
+        let broadcast = Broadcast::new(state.audit.clone(), "spl_close_empty_accounts", None, tx);
+        let sent = match broadcast
+            .tenant(snapshot.tenants.caller(headers).tenant)
+            .journal(state.journal.clone(), net)
+            .confirmation(
+                snapshot
+                    .config
+                    .confirmation_on(net, req.confirmation.as_ref()),
+            )
+            .send_and_confirm(rpc_client.clone())
+            .await
+        {
+            Ok(sent) => sent,
+            Err(e) => return error_response(e),
+        };
+
+        closed.push(ClosedChunk {
+            chunk_id: chunk.id.clone(),
+            transaction_id: sent.signature.to_string(),
+            rpc_endpoint: sent.rpc_endpoint,
+            lamports: chunk.lamports,
+        });
+    }
+
+    let response = SplCloseEmptyAccountsResponse {
+        reclaimed_lamports: closed.iter().map(|c| c.lamports).sum(),
+        closed,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn spl_agg_send_step_two(
+    req: Json<SplAggSendStepTwoRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let to = match parse_pubkey(&req.to) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return error_response(e),
+    };
+
+    let mut token = match resolve_token(
+        &snapshot.tokens,
+        req.token.as_deref(),
+        req.token_mint.as_deref(),
+        req.decimals,
+        req.net,
+    ) {
+        Ok(token) => token,
+        Err(e) => return error_response(e),
+    };
+    let token_mint = token.mint;
+
+    let rent_sponsor = match req.rent_sponsor.as_deref().map(parse_pubkey).transpose() {
+        Ok(sponsor) => sponsor,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    let key_index = match check_signer_in_keys(&keypair.pubkey(), &keys) {
+        Ok(index) => index,
+        Err(e) => return error_response(e),
+    };
+
+    let first_messages: Vec<AggMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+
+    let rpc_client = req
+        .net
+        .map(|net| async_rpc_client(&state, &snapshot.config, net));
+
+    if let Err(e) =
+        resolve_token_program(&mut token, req.token_program, rpc_client.as_deref()).await
+    {
+        return error_response(e);
+    }
+
+    let token_amount = match spl_amount(&token, req.amount.as_ref(), req.amount_base_units) {
+        Ok(amount) => amount,
+        Err(e) => return error_response(e),
+    };
+
+    let aggpubkey = match aggregated_pubkey(keys.clone()) {
+        Ok(key) => key,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = check_recipient_ata(&token, &to, rpc_client.as_deref()).await {
+        return error_response(e);
+    }
+
+    // Refuse to sign a transfer out of an account that can't fund it
+    if let Some(rpc_client) = &rpc_client {
+        let source_ata = token.ata(&aggpubkey);
+        if let Err(e) = validate_source_account(
+            rpc_client,
+            &source_ata,
+            &token_mint,
+            &aggpubkey,
+            req.allow_delegate,
+            token_amount,
+        )
+        .await
+        {
+            return error_response(e);
+        }
+    }
+
+    let builder = SplTransferBuilder::new(aggpubkey, to, token_mint, token_amount, token.decimals)
+        .token_program(token.program)
+        .memo(req.memo.clone())
+        .signed_memo(req.signed_memo)
+        .memo_program(memo_program)
+        .compute_unit_price(req.priority_fee_micro_lamports)
+        .references(references.clone())
+        .create_recipient_ata(true)
+        .rent_sponsor(rent_sponsor);
+    // Resolved once here, the aggregation step reuses the number instead of simulating again
+    let compute_units = match resolve_compute_unit_limit(
+        req.compute_unit_limit,
+        rpc_client.as_deref(),
+        snapshot.config.compute_unit_margin_percent,
+        |limit| builder.clone().compute_unit_limit(limit).build(),
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => return error_response(e),
+    };
+    let compute_unit_limit = compute_units.map(|r| r.limit);
+    let builder = builder.compute_unit_limit(compute_unit_limit);
+
+    let message = match spl_transfer_message(&builder, block_hash) {
+        Ok(message) => message,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = snapshot
+        .config
+        .writable_policy
+        .check(&message)
+        .and_then(|()| check_message_hash(&message, req.expected_message_hash.as_deref()))
+    {
+        return error_response(e);
+    }
+    let account_keys = req.include_account_keys.then(|| account_keys(&message));
+
+    let transfer = Transfer::Spl {
+        to: &to,
+        token_mint: &token_mint,
+        token_program: token.program,
+        amount: token_amount,
+        decimals: token.decimals,
+        memo: req.memo.as_deref(),
+        signed_memo: req.signed_memo,
+        memo_program: &memo_program,
+        compute_unit_limit,
+        compute_unit_price: req.priority_fee_micro_lamports,
+        references: &references,
+        rent_sponsor: rent_sponsor.as_ref(),
+    };
+    let digest = request_digest(&transfer, &block_hash, &keys);
+    let parties = keys.len();
+    let signer = keypair.pubkey();
+
+    let secret_state = match take_secret_state(
+        &state,
+        &snapshot,
+        &keypair.pubkey(),
+        req.secret_state.as_deref(),
+        req.secret_state_id.as_deref(),
+    ) {
+        Ok(secret_state) => secret_state,
+        Err(resp) => return resp,
+    };
+
+    let (sig, message) = match spl_step_two(
+        keypair,
+        &builder,
+        block_hash,
+        keys,
+        first_messages,
+        secret_state,
+    ) {
+        Ok(signed) => signed,
+        Err(e) => return error_response(e),
+    };
+    let audit_id = audit_signed(
+        &state,
+        &snapshot,
+        headers,
+        "spl_agg_send_step_two",
+        Some(digest.as_str()),
+        &message,
+    );
+    state
+        .audit
+        .ceremonies()
+        .started(&digest, parties, Instant::now());
+
+    let context = SignatureContext::new(
+        &digest,
+        &aggpubkey,
+        &signer,
+        snapshot.config.environment.as_deref(),
+        req.session_id.clone(),
+    );
+    let response = SplAggSendStepTwoResponse {
+        partial_signature: labeled(&snapshot.config, &sig),
+        key_index,
+        audit_id,
+        request_digest: digest,
+        compute_unit_limit: compute_units,
+        account_keys,
+        context,
+    };
+    success_response(response)
+}
+
+#[handler]
+async fn spl_aggregate_signatures(
+    req: Json<SplAggregateSignaturesRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    if req.dry_run && req.broadcast_at.is_some() {
+        return error_response(Error::InvalidBroadcastAt(
+            "can't be combined with dry_run, which sends nothing".to_string(),
+        ));
+    }
+    let to = match parse_pubkey(&req.to) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let (memo_program, references) = match transfer_extras(
+        &snapshot.config,
+        req.memo_program.as_deref(),
+        &req.references,
+    ) {
+        Ok(extras) => extras,
+        Err(e) => return error_response(e),
+    };
+
+    let mut token = match resolve_token(
+        &snapshot.tokens,
+        req.token.as_deref(),
+        req.token_mint.as_deref(),
+        req.decimals,
+        Some(net),
+    ) {
+        Ok(token) => token,
+        Err(e) => return error_response(e),
+    };
+    let token_mint = token.mint;
+
+    let rent_sponsor = match req.rent_sponsor.as_deref().map(parse_pubkey).transpose() {
+        Ok(sponsor) => sponsor,
+        Err(e) => return error_response(e),
+    };
+
+    let sponsor_signature = match (&req.rent_sponsor_signature, rent_sponsor) {
+        (None, _) => None,
+        (Some(_), None) => {
+            return error_response(Error::InvalidRequest(
+                "rent_sponsor_signature needs a rent_sponsor".to_string(),
+            ));
+        }
+        (Some(signature), Some(_)) => match Signature::from_str(signature) {
+            Ok(signature) => Some(signature),
+            Err(e) => {
+                return error_response(Error::InvalidRequest(format!(
+                    "invalid rent_sponsor_signature: {}",
+                    e
+                )));
+            }
+        },
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    let submitted: Vec<&str> = req
+        .signatures
+        .iter()
+        .map(SignatureSubmission::partial_signature)
+        .collect();
+    let signatures = match parse_signatures(&snapshot.config, &submitted) {
+        Ok(sigs) => sigs,
+        Err(e) => return error_response(e),
+    };
+
+    let token_amount = match spl_amount(&token, req.amount.as_ref(), req.amount_base_units) {
+        Ok(amount) => amount,
+        Err(e) => return error_response(e),
+    };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    if let Err(e) = resolve_token_program(&mut token, req.token_program, Some(&rpc_client)).await {
+        return error_response(e);
+    }
+
+    let transfer = Transfer::Spl {
+        to: &to,
+        token_mint: &token_mint,
+        token_program: token.program,
+        amount: token_amount,
+        decimals: token.decimals,
+        memo: req.memo.as_deref(),
+        signed_memo: req.signed_memo,
+        memo_program: &memo_program,
+        compute_unit_limit: req.compute_unit_limit,
+        compute_unit_price: req.priority_fee_micro_lamports,
+        references: &references,
+        rent_sponsor: rent_sponsor.as_ref(),
+    };
+    let digest = request_digest(&transfer, &block_hash, &keys);
+
+    let aggpubkey = match aggregated_pubkey(keys.clone()) {
+        Ok(key) => key,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = check_signature_contexts(
+        &snapshot.config,
+        &req.signatures,
+        req.session_id.as_deref(),
+        &digest,
+        &aggpubkey,
+        &keys,
+    ) {
+        return error_response(e);
+    }
+
+    if let Err(e) = check_recipient_ata(&token, &to, Some(&rpc_client)).await {
+        return error_response(e);
+    }
+
+    let builder = SplTransferBuilder::new(aggpubkey, to, token_mint, token_amount, token.decimals)
+        .token_program(token.program)
+        .memo(req.memo.clone())
+        .signed_memo(req.signed_memo)
+        .memo_program(memo_program)
+        .compute_unit_limit(req.compute_unit_limit)
+        .compute_unit_price(req.priority_fee_micro_lamports)
+        .references(references)
+        .rent_sponsor(rent_sponsor);
+    let tx = match spl_sign_and_broadcast(&builder, sponsor_signature, block_hash, signatures) {
+        Ok(transaction) => transaction,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = snapshot.config.writable_policy.check(&tx.message) {
+        return error_response(e);
+    }
+
+    if req.dry_run {
+        audit_dry_run(
+            &state,
+            &snapshot,
+            headers,
+            "spl_aggregate_signatures",
+            &digest,
+            &tx,
+        );
+        let response = SplAggregateSignaturesResponse {
+            transaction_id: tx.signatures[0].to_string(),
+            rpc_endpoint: None,
+            commitment: None,
+            request_digest: digest,
+            broadcast_at: None,
+            duplicate: false,
+            status: None,
+            transaction: Some(encode_transaction(&tx)),
+        };
+        return network_response(net, response);
+    }
+
+    // Scheduled transactions aren't checked, the account can still be funded before they go out.
+    // A sponsored ATA is no rent the aggregated account needs.
+    let recipient_ata = token.ata(&to);
+    let rent_ata = rent_sponsor.is_none().then_some(&recipient_ata);
+    let funded = match req.broadcast_at {
+        None => check_funded(&rpc_client, &tx.message, 0, rent_ata).await,
+        Some(_) => Ok(None),
+    };
+    let warnings = match funded {
+        Ok(warning) => Vec::from_iter(warning),
+        Err(e) => return error_response(e),
+    };
+
+    let reservation = match reserve_spend(
+        &snapshot,
+        headers,
+        Asset::Token(token_mint),
+        token_amount.get(),
+    ) {
+        Ok(reservation) => reservation,
+        Err(e) => return error_response(e),
+    };
+
+    if let Some(broadcast_at) = req.broadcast_at {
+        let scheduled = ScheduledBroadcast {
+            broadcast_at,
+            idempotency_key: idempotency_key(headers),
+            transaction: tx,
+        };
+        return match schedule_broadcast(
+            &state,
+            &snapshot,
+            headers,
+            "spl_aggregate_signatures",
+            Some(&digest),
+            net,
+            scheduled,
+            reservation,
+        )
+        .await
+        {
+            Ok(signature) => network_response(
+                net,
+                SplAggregateSignaturesResponse {
+                    transaction_id: signature.to_string(),
+                    request_digest: digest,
+                    rpc_endpoint: None,
+                    commitment: None,
+                    broadcast_at: Some(broadcast_at),
+                    duplicate: false,
+                    status: None,
+                    transaction: None,
+                },
+            ),
+            Err(e) => error_response(e),
+        };
+    }
+
+    let broadcast = Broadcast::new(
+        state.audit.clone(),
+        "spl_aggregate_signatures",
+        Some(digest.clone()),
+        tx,
+    );
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .wait(req.wait_for_confirmation)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => {
+            release_spend(&snapshot, reservation, &e);
+            return error_response(e);
+        }
+    };
+
+    let response = SplAggregateSignaturesResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: Some(sent.rpc_endpoint),
+        commitment: sent.commitment,
+        status: sent.status(),
+        request_digest: digest,
+        broadcast_at: None,
+        duplicate: sent.duplicate,
+        transaction: None,
+    };
+    network_response(net, Warned::new(response, warnings))
+}
+
+// -------------------------- nonce accounts -----------------------//
+//
+
+#[handler]
+async fn nonce_create(
+    req: Json<NonceCreateRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let authority = match (req.nonce_authority.as_deref(), req.keys.is_empty()) {
+        (None, true) => keypair.pubkey(),
+        (Some(authority), true) => match parse_pubkey(authority) {
+            Ok(authority) => authority,
+            Err(e) => return error_response(e),
+        },
+        (None, false) => {
+            let keys = match parse_keys(&snapshot.config, &req.keys) {
+                Ok(keys) => keys,
+                Err(e) => return error_response(e),
+            };
+            match aggregated_pubkey(keys) {
+                Ok(key) => key,
+                Err(e) => return error_response(e),
+            }
+        }
+        (Some(_), false) => {
+            return error_response(Error::InvalidRequest(
+                "pass nonce_authority or keys, the aggregated key of keys is the authority then"
+                    .to_string(),
+            ));
+        }
+    };
+
+    // Only needed to create the account, the authority is what controls it afterwards
+    let nonce_keypair = Keypair::new();
+    let nonce_account = nonce_keypair.pubkey();
+    let mut tx = create_nonce_account_transaction(&keypair.pubkey(), &nonce_account, &authority);
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+
+    tx.sign(&[&keypair, &nonce_keypair], recent_hash);
+
+    let broadcast = Broadcast::new(state.audit.clone(), "nonce_create", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client.clone())
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => return error_response(e),
+    };
+
+    // The node answering at a lower commitment than the policy's may not show it yet
+    let nonce = fetch_nonce_account(&rpc_client, &nonce_account)
+        .await
+        .ok()
+        .map(|held| held.nonce.to_string());
+
+    let response = NonceCreateResponse {
+        nonce_account: nonce_account.to_string(),
+        nonce_authority: authority.to_string(),
+        nonce,
+        lamports: nonce_account_rent(),
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: sent.rpc_endpoint,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn nonce_info(req: Json<NonceInfoRequest>, state: Data<&Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let nonce_account = match parse_pubkey(&req.nonce_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let held = match fetch_nonce_account(
+        &async_rpc_client(&state, &snapshot.config, net),
+        &nonce_account,
+    )
+    .await
+    {
+        Ok(held) => held,
+        Err(e) => return error_response(e),
+    };
+
+    let response = NonceInfoResponse {
+        nonce_account: nonce_account.to_string(),
+        nonce: held.nonce.to_string(),
+        nonce_authority: held.authority.to_string(),
+        lamports: held.lamports,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn nonce_withdraw(
+    req: Json<NonceWithdrawRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let nonce_account = match parse_pubkey(&req.nonce_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let destination = match parse_pubkey(&req.destination) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let held = match fetch_nonce_account(&rpc_client, &nonce_account).await {
+        Ok(held) => held,
+        Err(e) => return error_response(e),
+    };
+    let lamports = match check_authority(&nonce_account, &held, &keypair.pubkey())
+        .and_then(|()| withdrawal(&nonce_account, &held, req.lamports))
+    {
+        Ok(lamports) => lamports,
+        Err(e) => return error_response(e),
+    };
+
+    let mut tx =
+        withdraw_nonce_transaction(&nonce_account, &keypair.pubkey(), &destination, lamports);
+
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+
+    tx.sign(&[&keypair], recent_hash);
+
+    let reservation = match reserve_spend(&snapshot, headers, Asset::Sol, lamports) {
+        Ok(reservation) => reservation,
+        Err(e) => return error_response(e),
+    };
+
+    let broadcast = Broadcast::new(state.audit.clone(), "nonce_withdraw", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => {
+            release_spend(&snapshot, reservation, &e);
+            return error_response(e);
+        }
+    };
+
+    let response = NonceWithdrawResponse {
+        nonce_account: nonce_account.to_string(),
+        lamports,
+        closed: lamports == held.lamports,
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: sent.rpc_endpoint,
+    };
+    network_response(net, response)
+}
+
+// -------------------------- staking -----------------------//
+//
+
+#[handler]
+async fn stake_account(
+    req: Json<StakeAccountRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let vote_account = match parse_pubkey(&req.validator_vote_accont) {
+        Ok(vc) => vc,
+        Err(e) => return error_response(e),
+    };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let mut tx = match create_stake_account_transaction(
+        req.stake_amount,
+        &req.seed,
+        &keypair.pubkey(),
+        &vote_account,
+    ) {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+
+    tx.sign(&[&keypair], recent_hash);
+
+    let broadcast = Broadcast::new(state.audit.clone(), "stake_account", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => return error_response(e),
+    };
+
+    let stake_account = match Pubkey::create_with_seed(
+        &keypair.pubkey(),
+        &req.seed,
+        &solana_sdk::stake::program::id(),
+    ) {
+        Ok(addr) => addr,
+        Err(_) => {
+            return error_response(Error::InvalidStakeAccountSeed(req.seed.clone()));
+        }
+    };
+
+    let response = StakeAccountResponse {
+        stake_account_address: stake_account.to_string(),
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: sent.rpc_endpoint,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn deactivate_stake(
+    req: Json<DeactivateStakeRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let stake_accountt = match parse_pubkey(&req.stake_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let mut tx = create_deactivate_stake_transaction(&stake_accountt, &keypair.pubkey());
+
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+
+    tx.sign(&[&keypair], recent_hash);
+
+    let broadcast = Broadcast::new(state.audit.clone(), "deactivate_stake", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => return error_response(e),
+    };
+
+    let response = DeactivateStakeResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: sent.rpc_endpoint,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn withdraw_stake(
+    req: Json<WithdrawStakeRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let stake_accountt = match parse_pubkey(&req.stake_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let destination = match parse_pubkey(&req.destination) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let mut tx = create_withdraw_stake_transaction(
+        &stake_accountt,
+        &destination,
+        &keypair.pubkey(),
+        req.amount,
+    );
+
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+
+    tx.sign(&[&keypair], recent_hash);
+
+    let reservation = match reserve_spend(&snapshot, headers, Asset::Sol, req.amount) {
+        Ok(reservation) => reservation,
+        Err(e) => return error_response(e),
+    };
+
+    let broadcast = Broadcast::new(state.audit.clone(), "withdraw_stake", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => {
+            release_spend(&snapshot, reservation, &e);
+            return error_response(e);
+        }
+    };
+
+    let response = WithdrawStakeResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: sent.rpc_endpoint,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn agg_stake_step_two(
+    req: Json<AggStakeStepTwoRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let vote_account = match parse_pubkey(&req.validator_vote_accont) {
+        Ok(vc) => vc,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = check_signer_in_keys(&keypair.pubkey(), &keys) {
+        return error_response(e);
+    }
+
+    let first_messages: Vec<AggMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+
+    let stake = NewStake {
+        amount: Lamports::new(req.stake_amount),
+        seed: req.seed.clone(),
+        vote_account,
+    };
+    let checked = aggregated_pubkey(keys.clone())
+        .and_then(|aggpubkey| {
+            create_stake_account_transaction(
+                stake.amount.get(),
+                &stake.seed,
+                &aggpubkey,
+                &stake.vote_account,
+            )
+        })
+        .and_then(|tx| snapshot.config.writable_policy.check(&tx.message));
+    if let Err(e) = checked {
+        return error_response(e);
+    }
+
+    let secret_state = match take_secret_state(
+        &state,
+        &snapshot,
+        &keypair.pubkey(),
+        req.secret_state.as_deref(),
+        req.secret_state_id.as_deref(),
+    ) {
+        Ok(secret_state) => secret_state,
+        Err(resp) => return resp,
+    };
+
+    let (sig, message) = match stake_step_two(
+        keypair,
+        &stake,
+        block_hash,
+        keys,
+        first_messages,
+        secret_state,
+        &snapshot.config.writable_policy,
+    ) {
+        Ok(signed) => signed,
+        Err(e) => return error_response(e),
+    };
+    let audit_id = audit_signed(
+        &state,
+        &snapshot,
+        headers,
+        "agg_stake_step_two",
+        None,
+        &message,
+    );
+
+    let response = AggStakeStepTwoResponse {
+        partial_signature: labeled(&snapshot.config, &sig),
+        audit_id,
+    };
+    success_response(response)
+}
+
+#[handler]
+async fn agg_deactivate_stake_step_two(
+    req: Json<AggDeactivateStakeStepTwoRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let stake_accountt = match parse_pubkey(&req.stake_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = check_signer_in_keys(&keypair.pubkey(), &keys) {
+        return error_response(e);
+    }
+
+    let first_messages: Vec<AggMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+
+    let checked = aggregated_pubkey(keys.clone()).and_then(|aggpubkey| {
+        let tx = create_deactivate_stake_transaction(&stake_accountt, &aggpubkey);
+        snapshot.config.writable_policy.check(&tx.message)
+    });
+    if let Err(e) = checked {
+        return error_response(e);
+    }
+
+    let secret_state = match take_secret_state(
+        &state,
+        &snapshot,
+        &keypair.pubkey(),
+        req.secret_state.as_deref(),
+        req.secret_state_id.as_deref(),
+    ) {
+        Ok(secret_state) => secret_state,
+        Err(resp) => return resp,
+    };
+
+    let (sig, message) = match deactivate_stake_step_two(
+        keypair,
+        stake_accountt,
+        block_hash,
+        keys,
+        first_messages,
+        secret_state,
+        &snapshot.config.writable_policy,
+    ) {
+        Ok(signed) => signed,
+        Err(e) => return error_response(e),
+    };
+    let audit_id = audit_signed(
+        &state,
+        &snapshot,
+        headers,
+        "agg_deactivate_stake_step_two",
+        None,
+        &message,
+    );
+
+    let response = AggDeactivateStakeStepTwoResponse {
+        partial_signature: labeled(&snapshot.config, &sig),
+        audit_id,
+    };
+    success_response(response)
+}
+
+#[handler]
+async fn agg_withdraw_stake_step_two(
+    req: Json<AggWithdrawStakeStepTwoRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+
+    let stake_accountt = match parse_pubkey(&req.stake_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let destination = match parse_pubkey(&req.destination) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = check_signer_in_keys(&keypair.pubkey(), &keys) {
+        return error_response(e);
+    }
+
+    let first_messages: Vec<AggMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+
+    let withdrawal = StakeWithdrawal {
+        stake_account: stake_accountt,
+        destination,
+        amount: Lamports::new(req.amount),
+    };
+    let checked = aggregated_pubkey(keys.clone()).and_then(|aggpubkey| {
+        let tx = create_withdraw_stake_transaction(
+            &withdrawal.stake_account,
+            &withdrawal.destination,
+            &aggpubkey,
+            withdrawal.amount.get(),
+        );
+        snapshot.config.writable_policy.check(&tx.message)
+    });
+    if let Err(e) = checked {
+        return error_response(e);
+    }
+
+    let secret_state = match take_secret_state(
+        &state,
+        &snapshot,
+        &keypair.pubkey(),
+        req.secret_state.as_deref(),
+        req.secret_state_id.as_deref(),
+    ) {
+        Ok(secret_state) => secret_state,
+        Err(resp) => return resp,
+    };
+
+    let (sig, message) = match withdraw_stake_step_two(
+        keypair,
+        &withdrawal,
+        block_hash,
+        keys,
+        first_messages,
+        secret_state,
+        &snapshot.config.writable_policy,
+    ) {
+        Ok(signed) => signed,
+        Err(e) => return error_response(e),
+    };
+    let audit_id = audit_signed(
+        &state,
+        &snapshot,
+        headers,
+        "agg_withdraw_stake_step_two",
+        None,
+        &message,
+    );
+
+    let response = AggWithdrawStakeStepTwoResponse {
+        partial_signature: labeled(&snapshot.config, &sig),
+        audit_id,
+    };
+    success_response(response)
+}
+
+#[handler]
+async fn aggregate_stake_signatures(
+    req: Json<AggregateStakeSignaturesRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let vote_account = match parse_pubkey(&req.validator_vote_accont) {
+        Ok(vc) => vc,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    let signatures = match parse_signatures(&snapshot.config, &req.signatures) {
+        Ok(sigs) => sigs,
+        Err(e) => return error_response(e),
+    };
+
+    let tx = match aggregate_stake_signatures_and_broadcast(
+        Lamports::new(req.stake_amount),
+        req.seed.clone(),
+        vote_account,
+        block_hash,
+        keys,
+        signatures,
+    ) {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = snapshot.config.writable_policy.check(&tx.message) {
+        return error_response(e);
+    }
+
+    if let Some(broadcast_at) = req.broadcast_at {
+        let scheduled = ScheduledBroadcast {
+            broadcast_at,
+            idempotency_key: idempotency_key(headers),
+            transaction: tx,
+        };
+        return match schedule_broadcast(
+            &state,
+            &snapshot,
+            headers,
+            "aggregate_stake_signatures",
+            None,
+            net,
+            scheduled,
+            None,
+        )
+        .await
+        {
+            Ok(signature) => network_response(
+                net,
+                AggregateStakeSignaturesResponse {
+                    transaction_id: signature.to_string(),
+                    rpc_endpoint: None,
+                    broadcast_at: Some(broadcast_at),
+                },
+            ),
+            Err(e) => error_response(e),
+        };
+    }
+
+    let broadcast = Broadcast::new(state.audit.clone(), "aggregate_stake_signatures", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(async_rpc_client(&state, &snapshot.config, net))
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => return error_response(e),
+    };
+
+    let response = AggregateStakeSignaturesResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: Some(sent.rpc_endpoint),
+        broadcast_at: None,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn aggregate_deactivate_stake_signatures(
+    req: Json<AggregateDeactivateStakeSignaturesRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let stake_accountt = match parse_pubkey(&req.stake_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    let signatures = match parse_signatures(&snapshot.config, &req.signatures) {
+        Ok(sigs) => sigs,
+        Err(e) => return error_response(e),
+    };
+
+    let tx = match aggregate_deactivate_stake_signatures_and_broadcast(
+        stake_accountt,
+        block_hash,
+        keys,
+        signatures,
+    ) {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = snapshot.config.writable_policy.check(&tx.message) {
+        return error_response(e);
+    }
+
+    if let Some(broadcast_at) = req.broadcast_at {
+        let scheduled = ScheduledBroadcast {
+            broadcast_at,
+            idempotency_key: idempotency_key(headers),
+            transaction: tx,
+        };
+        return match schedule_broadcast(
+            &state,
+            &snapshot,
+            headers,
+            "aggregate_deactivate_stake_signatures",
+            None,
+            net,
+            scheduled,
+            None,
+        )
+        .await
+        {
+            Ok(signature) => network_response(
+                net,
+                AggregateDeactivateStakeSignaturesResponse {
+                    transaction_id: signature.to_string(),
+                    rpc_endpoint: None,
+                    broadcast_at: Some(broadcast_at),
+                },
+            ),
+            Err(e) => error_response(e),
+        };
+    }
+
+    let broadcast = Broadcast::new(
+        state.audit.clone(),
+        "aggregate_deactivate_stake_signatures",
+        None,
+        tx,
+    );
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(async_rpc_client(&state, &snapshot.config, net))
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => return error_response(e),
+    };
+
+    let response = AggregateDeactivateStakeSignaturesResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: Some(sent.rpc_endpoint),
+        broadcast_at: None,
+    };
+    network_response(net, response)
+}
+
+#[handler]
+async fn aggregate_withdraw_stake_signatures(
+    req: Json<AggregateWithdrawStakeSignaturesRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let stake_accountt = match parse_pubkey(&req.stake_account) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let destination = match parse_pubkey(&req.destination) {
+        Ok(addr) => addr,
+        Err(e) => return error_response(e),
+    };
+
+    let block_hash = match parse_hash(&req.recent_block_hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    let signatures = match parse_signatures(&snapshot.config, &req.signatures) {
+        Ok(sigs) => sigs,
+        Err(e) => return error_response(e),
+    };
+
+    let tx = match aggregate_withdraw_stake_signatures_and_broadcast(
+        stake_accountt,
+        destination,
+        Lamports::new(req.amount),
+        block_hash,
+        keys,
+        signatures,
+    ) {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = snapshot.config.writable_policy.check(&tx.message) {
+        return error_response(e);
+    }
+
+    let reservation = match reserve_spend(&snapshot, headers, Asset::Sol, req.amount) {
+        Ok(reservation) => reservation,
+        Err(e) => return error_response(e),
+    };
+
+    if let Some(broadcast_at) = req.broadcast_at {
+        let scheduled = ScheduledBroadcast {
+            broadcast_at,
+            idempotency_key: idempotency_key(headers),
+            transaction: tx,
+        };
+        return match schedule_broadcast(
+            &state,
+            &snapshot,
+            headers,
+            "aggregate_withdraw_stake_signatures",
+            None,
+            net,
+            scheduled,
+            reservation,
+        )
+        .await
+        {
+            Ok(signature) => network_response(
+                net,
+                AggregateWithdrawStakeSignaturesResponse {
+                    transaction_id: signature.to_string(),
+                    rpc_endpoint: None,
+                    broadcast_at: Some(broadcast_at),
+                },
+            ),
+            Err(e) => error_response(e),
+        };
+    }
+
+    let broadcast = Broadcast::new(
+        state.audit.clone(),
+        "aggregate_withdraw_stake_signatures",
+        None,
+        tx,
+    );
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(async_rpc_client(&state, &snapshot.config, net))
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => {
+            release_spend(&snapshot, reservation, &e);
+            return error_response(e);
+        }
+    };
+
+    let response = AggregateWithdrawStakeSignaturesResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: Some(sent.rpc_endpoint),
+        broadcast_at: None,
+    };
+    network_response(net, response)
+}
+
+//staking end her
+
+/// `keys` and the partial `signatures` of an aggregation, checked the way every aggregation
+/// checks them.
+fn parse_partial_signatures(
+    config: &Config,
+    keys: &[String],
+    signatures: &[String],
+) -> Result<(Vec<Pubkey>, Vec<PartialSignature>), Response> {
+    let keys = parse_keys(config, keys).map_err(error_response)?;
+    let signatures = parse_signatures(config, signatures).map_err(error_response)?;
+    Ok((keys, signatures))
+}
+
+#[handler]
+async fn agg_payload_step_two(
+    req: Json<AggPayloadStepTwoRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let keypair = match parse_keypair(&req.keypair) {
+        Ok(kp) => kp,
+        Err(e) => return error_response(e),
+    };
+    let payload = match parse_payload(&req.payload) {
+        Ok(payload) => payload,
+        Err(e) => return error_response(e),
+    };
+
+    let keys = match parse_keys(&snapshot.config, &req.keys) {
+        Ok(keys) => keys,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = check_signer_in_keys(&keypair.pubkey(), &keys) {
+        return error_response(e);
+    }
+
+    let first_messages: Vec<AggMessage1> =
+        match parse_first_messages(&snapshot.config, &req.first_messages) {
+            Ok(msgs) => msgs,
+            Err(e) => return error_response(e),
+        };
+
+    let checked = aggregated_pubkey(keys.clone())
+        .and_then(|aggpubkey| check_not_message(&payload, &aggpubkey));
+    if let Err(e) = checked {
+        return error_response(e);
+    }
+
+    let secret_state = match take_secret_state(
+        &state,
+        &snapshot,
+        &keypair.pubkey(),
+        req.secret_state.as_deref(),
+        req.secret_state_id.as_deref(),
+    ) {
+        Ok(secret_state) => secret_state,
+        Err(resp) => return resp,
+    };
+
+    let sig = match payload_step_two(keypair, &payload, keys, first_messages, secret_state) {
+        Ok(sig) => sig,
+        Err(e) => return error_response(e),
+    };
+    let audit_id = audit_signed(
+        &state,
+        &snapshot,
+        headers,
+        "agg_payload_step_two",
+        None,
+        &CompiledMessage::new(payload),
+    );
+
+    let response = AggPayloadStepTwoResponse {
+        partial_signature: labeled(&snapshot.config, &sig),
+        audit_id,
+    };
+    success_response(response)
+}
+
+#[handler]
+async fn aggregate_payload_signatures(
+    req: Json<AggregatePayloadSignaturesRequest>,
+    state: Data<&Arc<AppState>>,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let payload = match parse_payload(&req.payload) {
+        Ok(payload) => payload,
+        Err(e) => return error_response(e),
+    };
+    let (keys, signatures) =
+        match parse_partial_signatures(&snapshot.config, &req.keys, &req.signatures) {
+            Ok(parsed) => parsed,
+            Err(response) => return response,
+        };
+
+    match aggregate_payload_signature(&payload, keys, signatures) {
+        Ok((aggpubkey, signature)) => success_response(AggregatePayloadSignaturesResponse {
+            aggregated_public_key: aggpubkey.to_string(),
+            signature: signature.to_string(),
+        }),
+        Err(e) => error_response(e),
+    }
+}
+
+#[handler]
+async fn ed25519_verify_send(
+    req: Json<Ed25519VerifySendRequest>,
+    state: Data<&Arc<AppState>>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = state.snapshot();
+    let net = match resolve_network(&snapshot.config, req.net) {
+        Ok(net) => net,
+        Err(e) => return error_response(e),
+    };
+    let fee_payer = match parse_keypair(&req.fee_payer_keypair) {
+        Ok(kp) => kp,
+        Err(e) => {
+            return error_response(Error::InvalidKeypair(format!("fee_payer_keypair: {}", e)));
+        }
+    };
+    let payload = match parse_payload(&req.payload) {
+        Ok(payload) => payload,
+        Err(e) => return error_response(e),
+    };
+    let instruction = match parse_instruction(&req.instruction) {
+        Ok(instruction) => instruction,
+        Err(e) => return error_response(e),
+    };
+    let (keys, signatures) =
+        match parse_partial_signatures(&snapshot.config, &req.keys, &req.signatures) {
+            Ok(parsed) => parsed,
+            Err(response) => return response,
+        };
+
+    let (aggpubkey, signature) = match aggregate_payload_signature(&payload, keys, signatures) {
+        Ok(signed) => signed,
+        Err(e) => return error_response(e),
+    };
+    let message = match ProgramCallBuilder::new(fee_payer.pubkey(), instruction)
+        .and_then(|call| call.ed25519_verify(aggpubkey, signature, payload).build())
+    {
+        Ok(message) => message,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = snapshot.config.writable_policy.check(&message) {
+        return error_response(e);
+    }
+
+    let rpc_client = async_rpc_client(&state, &snapshot.config, net);
+    let recent_hash = match rpc_client.get_latest_blockhash().await {
+        Ok(hash) => hash,
+        Err(e) => return error_response(Error::RecentHashFailed(e)),
+    };
+    let mut tx = Transaction::new_unsigned(message);
+    tx.sign(&[&fee_payer], recent_hash);
+
+    let broadcast = Broadcast::new(state.audit.clone(), "ed25519_verify_send", None, tx);
+    let sent = match broadcast
+        .tenant(snapshot.tenants.caller(headers).tenant)
+        .journal(state.journal.clone(), net)
+        .confirmation(
+            snapshot
+                .config
+                .confirmation_on(net, req.confirmation.as_ref()),
+        )
+        .send_and_confirm(rpc_client)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(e) => return error_response(e),
+    };
+
+    let response = Ed25519VerifySendResponse {
+        transaction_id: sent.signature.to_string(),
+        rpc_endpoint: sent.rpc_endpoint,
+        aggregated_public_key: aggpubkey.to_string(),
+        signature: signature.to_string(),
+    };
+    network_response(net, response)
+}
+
+/// Every route served by `app`, advertised through `/api/capabilities`, with the rate limit
+/// class of its requests: `Local` for those that never call a node.
+const ENDPOINTS: &[(&str, &str, Class)] = &[
+    ("GET", "/api/generate", Class::Local),
+    ("POST", "/api/keypair_from_mnemonic", Class::Local),
+    ("POST", "/api/convert_keypair", Class::Local),
+    ("GET", "/api/capabilities", Class::Local),
+    ("GET", "/api/version", Class::Local),
+    ("GET", "/api/spend_limits", Class::Local),
+    // Only `?rpc=true` calls the nodes, and those answers are cached
+    ("GET", "/api/health", Class::Local),
+    ("GET", "/api/rpc_stats", Class::Local),
+    ("GET", "/metrics", Class::Local),
+    ("GET", "/api/state_stats", Class::Local),
+    ("POST", "/api/admin/reload_config", Class::Rpc),
+    ("POST", "/api/admin/flush_caches", Class::Local),
+    ("GET", "/api/admin/usage_stats", Class::Local),
+    ("GET", "/api/audit/:id/message", Class::Local),
+    ("GET", "/api/audit/export", Class::Local),
+    ("POST", "/api/balance", Class::Rpc),
+    ("POST", "/api/airdrop", Class::Rpc),
+    ("POST", "/api/airdrop_batch", Class::Rpc),
+    ("POST", "/api/send_single", Class::Rpc),
+    ("POST", "/api/recent_block_hash", Class::Rpc),
+    ("POST", "/api/aggregate_keys", Class::Local),
+    ("POST", "/api/agg_send_step_one", Class::Local),
+    ("POST", "/api/build_message", Class::Rpc),
+    ("POST", "/api/simulate", Class::Rpc),
+    ("POST", "/api/agg_send_step_two", Class::Rpc),
+    ("POST", "/api/threshold/keygen_step_one", Class::Local),
+    ("POST", "/api/threshold/keygen_step_two", Class::Local),
+    ("POST", "/api/threshold/keygen_step_three", Class::Local),
+    ("POST", "/api/aggregate_signatures", Class::Rpc),
+    ("POST", "/api/broadcast", Class::Rpc),
+    ("POST", "/api/broadcast_status", Class::Local),
+    ("POST", "/api/transaction_statuses", Class::Rpc),
+    ("GET", "/ws/confirmations", Class::Rpc),
+    ("POST", "/api/scheduled_broadcast_status", Class::Local),
+    ("POST", "/api/cancel_scheduled_broadcast", Class::Local),
+    ("GET", "/api/tokens", Class::Local),
+    // Adding a token reads its mint
+    ("POST", "/api/tokens", Class::Rpc),
+    ("POST", "/api/delete_token", Class::Local),
+    ("POST", "/api/restore_token", Class::Local),
+    ("POST", "/api/spl_token_balance", Class::Rpc),
+    ("POST", "/api/spl_send_single", Class::Rpc),
+    ("POST", "/api/spl_rent_summary", Class::Rpc),
+    ("POST", "/api/spl_close_empty_accounts", Class::Rpc),
+    ("POST", "/api/spl_build_message", Class::Rpc),
+    ("POST", "/api/spl_cost_preview", Class::Rpc),
+    ("POST", "/api/estimate_fee", Class::Rpc),
+    ("POST", "/api/spl_simulate", Class::Rpc),
+    ("POST", "/api/solana_pay/parse", Class::Local),
+    ("POST", "/api/solana_pay/create", Class::Local),
+    ("POST", "/api/spl_agg_send_step_two", Class::Rpc),
+    ("POST", "/api/spl_aggregate_signatures", Class::Rpc),
+    ("POST", "/api/stake", Class::Rpc),
+    ("POST", "/api/deactivate_stake", Class::Rpc),
+    ("POST", "/api/withdraw_stake", Class::Rpc),
+    ("POST", "/api/nonce/create", Class::Rpc),
+    ("POST", "/api/nonce/info", Class::Rpc),
+    ("POST", "/api/nonce/withdraw", Class::Rpc),
+    ("POST", "/api/agg_stake_step_two", Class::Local),
+    ("POST", "/api/agg_deactivate_stake_step_two", Class::Local),
+    ("POST", "/api/agg_withdraw_stake_step_two", Class::Local),
+    ("POST", "/api/aggregate_stake_signatures", Class::Rpc),
+    (
+        "POST",
+        "/api/aggregate_deactivate_stake_signatures",
+        Class::Rpc,
+    ),
+    (
+        "POST",
+        "/api/aggregate_withdraw_stake_signatures",
+        Class::Rpc,
+    ),
+    ("POST", "/api/agg_payload_step_two", Class::Local),
+    ("POST", "/api/aggregate_payload_signatures", Class::Local),
+    ("POST", "/api/ed25519_verify_send", Class::Rpc),
+    ("GET", "/api/openapi.json", Class::Local),
+    ("GET", "/api/docs", Class::Local),
+    #[cfg(feature = "chaos")]
+    ("GET", "/api/chaos", Class::Local),
+    #[cfg(feature = "chaos")]
+    ("POST", "/api/chaos", Class::Local),
+];
+
+/// The UI's routes, served unless the config turns it off and not advertised.
+const UI_ENDPOINTS: &[(&str, &str, Class)] = &[
+    ("GET", "/ui", Class::Local),
+    ("GET", "/ui/:name", Class::Local),
+];
+
+/// The files of the Swagger UI of `/api/docs`, not advertised.
+const DOCS_ENDPOINTS: &[(&str, &str, Class)] = &[("GET", "/api/docs/:name", Class::Local)];
+
+/// The rate limit class of a request to `path` with `method`, that of the route serving it.
+/// `None` when no route does.
+fn endpoint_class(method: &str, path: &str) -> Option<Class> {
+    ENDPOINTS
+        .iter()
+        .chain(UI_ENDPOINTS)
+        .chain(DOCS_ENDPOINTS)
+        .find(|(endpoint_method, pattern, _)| {
+            *endpoint_method == method && rate_limit::matches(pattern, path)
+        })
+        .map(|(_, _, class)| *class)
+}
+
+// Routes are grouped by what they do, and each group carries the middleware that fits it:
+// read-only routes answer conditional GETs, signing routes keep their secret-laden responses
+// out of caches, admin routes refuse callers without an admin API key before the handler runs.
+// What applies to every request (body limit, debug timings, API keys, rate limits, tracing)
+// wraps the whole app. `build_app` is what the server runs and what the tests drive, so they
+// see the same stack.
+
+/// Read-only routes: keys, balances, statuses and what the service knows about itself.
+fn read_routes(route: Route) -> Route {
+    route
+        .at("/api/capabilities", get(read(capabilities)))
+        .at("/api/version", get(read(version)))
+        .at("/api/health", get(read(health)))
+        .at("/api/rpc_stats", get(read(rpc_stats)))
+        .at("/metrics", get(metrics))
+        .at("/api/balance", post(balance))
+        .at("/api/recent_block_hash", post(recent_block_hash))
+        .at("/api/aggregate_keys", post(aggregate_keys))
+        .at("/api/build_message", post(build_message))
+        .at("/api/simulate", post(simulate))
+        .at("/api/broadcast_status", post(broadcast_status))
+        .at("/api/nonce/info", post(nonce_info))
+        .at("/api/transaction_statuses", post(transaction_statuses))
+        .at("/ws/confirmations", get(confirmations_socket))
+        .at(
+            "/api/scheduled_broadcast_status",
+            post(scheduled_broadcast_status),
+        )
+        .at("/api/spl_token_balance", post(spl_token_balance))
+        .at("/api/spl_rent_summary", post(spl_rent_summary))
+        .at("/api/spl_build_message", post(spl_build_message))
+        .at("/api/spl_cost_preview", post(spl_cost_preview))
+        .at("/api/estimate_fee", post(estimate_fee))
+        .at("/api/spl_simulate", post(spl_simulate))
+        .at("/api/solana_pay/parse", post(solana_pay_parse))
+        .at("/api/solana_pay/create", post(solana_pay_create))
+        .at("/api/openapi.json", get(read(openapi_json)))
+        .at("/api/docs", get(read(openapi_docs)))
+        .at("/api/docs/:name", get(read(openapi_docs_file)))
+        // A path takes one set of methods, adding a token shares it and is guarded the way the
+        // admin routes are
+        .at(
+            "/api/tokens",
+            get(read(list_tokens)).post(admin("/api/tokens", upsert_token)),
+        )
+}
+
+/// Routes answering with key material or partial signatures, nothing is sent.
+fn signing_routes(route: Route) -> Route {
+    route
+        .at("/api/generate", get(signing(generate_keypair)))
+        .at(
+            "/api/keypair_from_mnemonic",
+            post(signing(keypair_from_mnemonic)),
+        )
+        .at("/api/convert_keypair", post(signing(convert_keypair)))
+        .at("/api/agg_send_step_one", post(signing(agg_send_step_one)))
+        .at("/api/agg_send_step_two", post(signing(agg_send_step_two)))
+        .at(
+            "/api/threshold/keygen_step_one",
+            post(signing(threshold_keygen_step_one)),
+        )
+        .at(
+            "/api/threshold/keygen_step_two",
+            post(signing(threshold_keygen_step_two)),
+        )
+        .at(
+            "/api/threshold/keygen_step_three",
+            post(signing(threshold_keygen_step_three)),
+        )
+        .at(
+            "/api/spl_agg_send_step_two",
+            post(signing(spl_agg_send_step_two)),
+        )
+        .at("/api/agg_stake_step_two", post(signing(agg_stake_step_two)))
+        .at(
+            "/api/agg_deactivate_stake_step_two",
+            post(signing(agg_deactivate_stake_step_two)),
+        )
+        .at(
+            "/api/agg_withdraw_stake_step_two",
+            post(signing(agg_withdraw_stake_step_two)),
+        )
+        .at(
+            "/api/agg_payload_step_two",
+            post(signing(agg_payload_step_two)),
+        )
+        .at(
+            "/api/aggregate_payload_signatures",
+            post(signing(aggregate_payload_signatures)),
+        )
+}
+
+/// Routes sending transactions, or changing what will be sent.
+fn broadcasting_routes(route: Route) -> Route {
+    route
+        .at("/api/airdrop", post(airdrop))
+        .at("/api/airdrop_batch", post(airdrop_batch))
+        .at("/api/send_single", post(send_single))
+        .at("/api/aggregate_signatures", post(aggregate_signatures))
+        .at("/api/broadcast", post(broadcast))
+        .at(
+            "/api/cancel_scheduled_broadcast",
+            post(cancel_scheduled_broadcast),
+        )
+        .at("/api/spl_send_single", post(spl_send_single))
+        .at(
+            "/api/spl_close_empty_accounts",
+            post(spl_close_empty_accounts),
+        )
+        .at(
+            "/api/spl_aggregate_signatures",
+            post(spl_aggregate_signatures),
+        )
+        .at("/api/stake", post(stake_account))
+        .at("/api/deactivate_stake", post(deactivate_stake))
+        .at("/api/withdraw_stake", post(withdraw_stake))
+        .at("/api/nonce/create", post(nonce_create))
+        .at("/api/nonce/withdraw", post(nonce_withdraw))
+        .at(
+            "/api/aggregate_stake_signatures",
+            post(aggregate_stake_signatures),
+        )
+        .at(
+            "/api/aggregate_deactivate_stake_signatures",
+            post(aggregate_deactivate_stake_signatures),
+        )
+        .at(
+            "/api/aggregate_withdraw_stake_signatures",
+            post(aggregate_withdraw_stake_signatures),
+        )
+        .at("/api/ed25519_verify_send", post(ed25519_verify_send))
+}
+
+/// Routes for admin API keys only.
+fn admin_routes(route: Route) -> Route {
+    let route = route
+        .at(
+            "/api/state_stats",
+            admin("/api/state_stats", get(state_stats)),
+        )
+        .at(
+            "/api/admin/reload_config",
+            admin("/api/admin/reload_config", post(reload_config)),
+        )
+        .at(
+            "/api/admin/flush_caches",
+            admin("/api/admin/flush_caches", post(flush_caches)),
+        )
+        .at(
+            "/api/admin/usage_stats",
+            admin("/api/admin/usage_stats", get(usage_stats)),
+        )
+        .at(
+            "/api/spend_limits",
+            admin("/api/spend_limits", get(spend_limits)),
+        )
+        .at(
+            "/api/audit/:id/message",
+            admin("/api/audit/:id/message", get(audit_message)),
+        )
+        .at(
+            "/api/audit/export",
+            admin("/api/audit/export", get(audit_export)),
+        )
+        .at(
+            "/api/delete_token",
+            admin("/api/delete_token", post(delete_token)),
+        )
+        .at(
+            "/api/restore_token",
+            admin("/api/restore_token", post(restore_token)),
+        );
+    #[cfg(feature = "chaos")]
+    let route = route.at(
+        "/api/chaos",
+        admin("/api/chaos", get(chaos_report).post(set_chaos)),
+    );
+    route
+}
+
+/// `ep` answering GETs with the ETag of their body, a 304 when `If-None-Match` names it.
+fn read<E: Endpoint + 'static>(ep: E) -> impl Endpoint {
+    ep.map_to_response().around(|ep, req| async move {
+        if req.method() != Method::GET {
+            return ep.call(req).await;
+        }
+        let headers = req.headers().clone();
+        let resp = ep.call(req).await?;
+        Ok(etag::conditional(&headers, resp).await)
+    })
+}
+
+/// `ep` with responses no cache may keep.
+fn signing<E: Endpoint + 'static>(ep: E) -> impl Endpoint {
+    ep.map_to_response().around(|ep, req| async move {
+        let mut resp = ep.call(req).await?;
+        resp.headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        Ok(resp)
+    })
+}
+
+/// `ep` refusing callers without a known API key as `Unauthorized`, and those whose key isn't
+/// an admin's with `AdminOnly(path)`.
+fn admin<E: Endpoint + 'static>(path: &'static str, ep: E) -> impl Endpoint {
+    ep.map_to_response().around(move |ep, req| async move {
+        let Some(state) = req.data::<Arc<AppState>>().cloned() else {
+            return Ok(unauthorized(&ClockSkew::default(), req.headers()));
+        };
+        match state.snapshot().tenants.known(req.headers()) {
+            Some(caller) if caller.admin => ep.call(req).await,
+            Some(_) => Ok(error_response(Error::AdminOnly(path))),
+            None => Ok(unauthorized(&state.clock, req.headers())),
+        }
+    })
+}
+
+/// The answer to a request without a known API key, with the latest skew of `clock` so a
+/// client can tell a bad key from a bad clock.
+fn unauthorized(clock: &ClockSkew, headers: &HeaderMap) -> Response {
+    let key_sent = tenant::request_key(headers).is_some();
+    let clock_skew_ms = clock.latest().map(|reading| reading.skew_ms);
+    let mut resp = error_response(Error::Unauthorized {
+        key_sent,
+        clock_skew_ms,
+    });
+    resp.headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    resp
+}
+
+fn build_app(state: Arc<AppState>) -> impl Endpoint {
+    let snapshot = state.snapshot();
+    let max_body_size = snapshot.config.max_body_size;
+    let (body_state, timing_state, auth_state, limit_state, case_state, environment_state) = (
+        state.clone(),
+        state.clone(),
+        state.clone(),
+        state.clone(),
+        state.clone(),
+        state.clone(),
+    );
+    let app = Route::new();
+    let app = read_routes(app);
+    let app = signing_routes(app);
+    let app = broadcasting_routes(app);
+    let app = admin_routes(app);
+    let app = match snapshot.config.ui {
+        true => app.at("/ui", get(ui_index)).at("/ui/:name", get(ui_asset)),
+        false => app,
+    };
+    let app = app
+        .around(move |ep, mut req| {
+            let state = body_state.clone();
+            async move {
+                let snapshot = state.snapshot();
+                let config = &snapshot.config;
+                let endpoint = ENDPOINTS
+                    .iter()
+                    .find(|(method, path, _)| {
+                        req.method().as_str() == *method && req.uri().path() == *path
+                    })
+                    .map(|(_, path, _)| *path);
+                let counted = endpoint.filter(|_| config.usage_stats);
+                let solana_pay = endpoint.filter(|endpoint| solana_pay::accepts(endpoint));
+                // Any request body can carry an `rpc_url`, read only where it is used
+                let posted = config.allow_request_rpc_url && req.method() == Method::POST;
+                if !config.strict_amounts && counted.is_none() && solana_pay.is_none() && !posted {
+                    return ep.call(req).await;
+                }
+                // Runs within the body limit below, the body is already bounded
+                let body = req.take_body().into_bytes().await?;
+                let json: Option<serde_json::Value> = serde_json::from_slice(&body).ok();
+                if let Some(json) = &json {
+                    tracing::debug!(body = %access_log::Redacted(json), "request body");
+                }
+                if let (Some(endpoint), Some(json)) = (counted, &json) {
+                    state.usage.record(endpoint, json);
+                }
+                let float = json.as_ref().filter(|_| config.strict_amounts);
+                if let Some((field, amount)) = float.and_then(float_amount) {
+                    return Ok(error_response(Error::FloatAmount { field, amount }));
+                }
+                let requested = json.as_ref().filter(|_| config.allow_request_rpc_url);
+                let rpc_url = match requested
+                    .map(|json| rpc_override::requested(json, &config.request_rpc_url_hosts))
+                {
+                    Some(Ok(url)) => url,
+                    Some(Err(e)) => return Ok(error_response(e)),
+                    None => None,
+                };
+                // The handlers see the fields of a `solana_pay_url` as if they had been sent
+                match (solana_pay, json) {
+                    (Some(endpoint), Some(mut json)) => {
+                        if let Err(e) = solana_pay::expand(endpoint, &mut json) {
+                            return Ok(error_response(e));
+                        }
+                        req.set_body(serde_json::to_vec(&json).unwrap_or_default());
+                    }
+                    _ => req.set_body(body),
+                }
+                match rpc_url {
+                    Some(url) => rpc_override::scope(url, ep.call(req)).await,
+                    None => ep.call(req).await,
+                }
+            }
+        })
+        .around(move |ep, mut req| async move {
+            // Unlike poem's `SizeLimit` this doesn't require a Content-Length header
+            let body = req.take_body().into_bytes_limit(max_body_size).await?;
+            // JSON is UTF-8, a body that isn't is refused here rather than as a parse error
+            let json = req
+                .content_type()
+                .is_some_and(|content_type| content_type.starts_with("application/json"));
+            if let Some(Err(e)) = json.then(|| std::str::from_utf8(&body)) {
+                return Ok(error_response(Error::BodyNotUtf8(e.valid_up_to())));
+            }
+            req.set_body(body);
+            ep.call(req).await
+        })
+        .around(move |ep, req| {
+            let state = timing_state.clone();
+            async move {
+                let snapshot = state.snapshot();
+                let debug_timing = snapshot.config.debug_timing
+                    || (timing::requested(req.headers())
+                        && snapshot.tenants.caller(req.headers()).admin);
+                if !debug_timing {
+                    return ep.call(req).await;
+                }
+                let collector = Arc::new(Collector::default());
+                let span = tracing::info_span!("debug_timing");
+                collector.attach(&span);
+                let start = Instant::now();
+                let resp = ep.call(req).instrument(span).await?;
+                Ok(add_timings(resp, &collector.timings(start.elapsed())).await)
+            }
+        })
+        .around(move |ep, req| {
+            let state = auth_state.clone();
+            async move {
+                let path = req.uri().path();
+                let open = !(path.starts_with("/api/") || path.starts_with("/ws/"))
+                    || path == "/api/health";
+                let snapshot = state.snapshot();
+                if open || !snapshot.config.require_api_key {
+                    return ep.call(req).await;
+                }
+                if snapshot.tenants.known(req.headers()).is_some() {
+                    return ep.call(req).await;
+                }
+                Ok(unauthorized(&state.clock, req.headers()))
+            }
+        })
+        .around(move |ep, req| {
+            let state = limit_state.clone();
+            async move {
+                let Some(limits) = state.snapshot().config.rate_limits else {
+                    return ep.call(req).await;
+                };
+                let client = req.remote_addr().as_socket_addr().map(|addr| addr.ip());
+                let class =
+                    endpoint_class(req.method().as_str(), req.uri().path()).unwrap_or(Class::Rpc);
+                match state
+                    .rate_limiter
+                    .check(&limits, client, class, Instant::now())
+                {
+                    Ok(()) => ep.call(req).await,
+                    Err(retry_after_seconds) => {
+                        let mut resp = error_response(Error::RateLimited {
+                            retry_after_seconds,
+                        });
+                        resp.headers_mut()
+                            .insert(header::RETRY_AFTER, retry_after_seconds.into());
+                        Ok(resp)
+                    }
+                }
+            }
+        })
+        .around(move |ep, mut req| {
+            let state = case_state.clone();
+            async move {
+                let case =
+                    FieldCase::requested(req.headers(), state.snapshot().config.response_case);
+                let headers = req.headers().clone();
+                // The routes' ETags are those of the snake_case body, `render` answers
+                // conditional requests for the renamed one
+                if case == FieldCase::Camel {
+                    req.headers_mut().remove(header::IF_NONE_MATCH);
+                }
+                let resp = ep.call(req).await?;
+                Ok(case::render(&headers, resp, case).await)
+            }
+        })
+        .around(move |ep, req| {
+            let state = environment_state.clone();
+            async move {
+                let resp = match ep.call(req).await {
+                    Ok(resp) => resp,
+                    Err(e) => e.into_response(),
+                };
+                match state.snapshot().config.environment.clone() {
+                    Some(environment) => {
+                        Ok(envelope::insert_field(resp, "environment", environment.into()).await)
+                    }
+                    None => Ok(resp),
+                }
+            }
+        })
+        .around(|ep, req| async move {
+            let request_id = access_log::request_id(req.headers());
+            let span = tracing::info_span!(
+                "request",
+                request_id = %request_id,
+                method = %req.method(),
+                path = req.uri().path(),
+            );
+            let start = Instant::now();
+            let mut resp = match ep.call(req).instrument(span.clone()).await {
+                Ok(resp) => resp,
+                Err(e) => e.into_response(),
+            };
+            let (status, latency_ms) = (resp.status().as_u16(), start.elapsed().as_millis() as u64);
+            span.in_scope(|| match resp.status().is_server_error() {
+                true => tracing::warn!(status, latency_ms, "request finished"),
+                false => tracing::info!(status, latency_ms, "request finished"),
+            });
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                resp.headers_mut().insert(access_log::HEADER, value);
+            }
+            Ok(resp)
+        })
+        .data(state);
+    #[cfg(feature = "otel")]
+    let app = app.with(poem::middleware::OpenTelemetryTracing::new(
+        opentelemetry::global::tracer(telemetry::SERVICE_NAME),
+    ));
+    app
+}
+
+/// Measure the clock skew every `clock_check.interval_secs`, from the config in effect then.
+async fn watch_clock(state: Arc<AppState>) {
+    loop {
+        let snapshot = state.snapshot();
+        let Some(check) = snapshot.config.clock_check else {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            continue;
+        };
+        match measure_skew(&async_rpc_client(&state, &snapshot.config, check.net)).await {
+            Ok(skew_ms) => {
+                if clock::exceeds(&check, skew_ms) {
+                    tracing::warn!(
+                        skew_ms,
+                        net = %check.net,
+                        "the system clock is off from the cluster's, timestamps can't be trusted"
+                    );
+                }
+                state.clock.record(SkewReading {
+                    skew_ms,
+                    at: clock::system_ms(),
+                });
+            }
+            Err(e) => tracing::debug!(error = %e, net = %check.net, "clock check failed"),
+        }
+        tokio::time::sleep(Duration::from_secs(check.interval_secs.max(1))).await;
+    }
+}
+
+/// Reload the config on every SIGHUP, like `/api/admin/reload_config`.
+#[cfg(unix)]
+async fn reload_on_sighup(state: Arc<AppState>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!(error = %e, "can't listen for SIGHUP, reload through the API instead");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = state.reload().await {
+            tracing::error!(error = %e, "SIGHUP reload failed");
+        }
+    }
+}
+
+/// Report a startup check that failed as the only issue and stop the startup.
+fn startup_failure(check: &str, err: impl ToString) -> anyhow::Error {
+    let mut report = StartupReport::default();
+    report.push(Severity::Hard, check, err.to_string());
+    report.log();
+    anyhow::anyhow!("startup checks failed")
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    // Installed first, everything after it reports through tracing
+    #[cfg(feature = "otel")]
+    let tracer_provider = telemetry::init().map_err(|e| anyhow::anyhow!("telemetry: {}", e))?;
+
+    let filter = std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_else(|_| "info".to_string());
+    let log_layer = access_log::layer(args.log_format, &filter, std::io::stderr)
+        .map_err(|e| anyhow::anyhow!("tracing: {}", e))?;
+    let subscriber = tracing_subscriber::registry()
+        .with(TimingLayer)
+        .with(log_layer);
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(tracer_provider.as_ref().map(telemetry::layer));
+    subscriber
+        .try_init()
+        .map_err(|e| anyhow::anyhow!("failed installing the tracing subscriber: {}", e))?;
+
+    let source = ConfigSource::new(&args);
+    let config = source.load().map_err(|e| startup_failure("config", e))?;
+
+    if !args.skip_startup_checks {
+        let report = run_startup_checks(&config).await;
+        report.log();
+        if report.has_hard_failures() {
+            anyhow::bail!("startup checks failed");
+        }
+    }
+
+    // Listening before anything else starts, a certificate rustls can't parse or a port in use
+    // fails the startup like the checks do
+    let listener = tls::listener(&args, &config.bind).map_err(|e| startup_failure("tls", e))?;
+    let acceptor = listener
+        .into_acceptor()
+        .await
+        .map_err(|e| startup_failure("listener", format!("can't listen: {}", e)))?;
+
+    let state = AppState::new(config).map_err(|e| startup_failure("state", e))?;
+    let state = Arc::new(state.config_source(source));
+
+    let info = version_info(&state.snapshot().config);
+    tracing::info!(
+        version = info.version.as_str(),
+        git_commit = info.git_commit.as_str(),
+        version_info = %serde_json::to_string(&info).unwrap_or_default(),
+        "starting"
+    );
+    if let Some(journal) = &state.journal {
+        let (journal, audit, reconcile_state) =
+            (journal.clone(), state.audit.clone(), state.clone());
+        tokio::spawn(async move {
+            reconcile(journal, audit, |net| {
+                async_rpc_client(&reconcile_state, &reconcile_state.snapshot().config, net)
+            })
+            .await
+        });
+    }
+    if let Some(scheduler) = &state.scheduler {
+        let (scheduler, scheduler_state) = (scheduler.clone(), state.clone());
+        tokio::spawn(scheduler.run(move |net| {
+            async_rpc_client(&scheduler_state, &scheduler_state.snapshot().config, net)
+        }));
+    }
+    tokio::spawn(watch_clock(state.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.clone()));
+    let result = Server::new_with_acceptor(acceptor)
+        .run(build_app(state))
+        .await;
+    // Flush the spans still queued in the batch exporter
+    #[cfg(feature = "otel")]
+    if let Some(provider) = tracer_provider {
+        let _ = provider.shutdown();
+    }
+    result?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Read,
+        sync::Arc,
+        time::{Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use poem::http::{Method, StatusCode, header};
+    use poem::test::TestClient;
+
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use flate2::read::GzDecoder;
+    use sha2::{Digest, Sha256};
+    use solana_client::nonblocking::rpc_client::RpcClient;
+    use solana_sdk::signature::Signature;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::{hash::Hash, pubkey::Pubkey};
+    use solana_tss_api_backend::access_log::{self, LogFormat};
+    use solana_tss_api_backend::audit::Outcome;
+    use solana_tss_api_backend::audit_export;
+    use solana_tss_api_backend::config::{Config, ConfigSource};
+    use solana_tss_api_backend::durable_nonce::{DurableNonce, advanced_nonce_account};
+    use solana_tss_api_backend::message_review::{encode_message, message_hash};
+    use solana_tss_api_backend::models::{
+        AggPayloadStepTwoResponse, AggSendStepOneResponse, AggSendStepTwoResponse,
+        AggregateKeysResponse, AggregatePayloadSignaturesResponse, AuditMessageResponse,
+        BroadcastStatusResponse, BuildMessageResponse, CapabilitiesResponse,
+        ConvertKeypairResponse, ErrorResponse, GenerateKeypairResponse, HealthResponse,
+        HealthStatus, KeyAggMode, Network, ReloadConfigResponse, SolanaPayCreateResponse,
+        SolanaPayParseResponse, SplCostPreviewResponse, StateStatsResponse,
+        ThresholdKeygenStepOneResponse, ThresholdKeygenStepThreeResponse,
+        ThresholdKeygenStepTwoResponse, Timings, TokensResponse, UsageStatsResponse,
+        VersionResponse,
+    };
+    use solana_tss_api_backend::openapi;
+    use solana_tss_api_backend::policy::WritablePolicy;
+    use solana_tss_api_backend::rate_limit::{BucketConfig, Class, RateLimitConfig};
+    use solana_tss_api_backend::spend_limit::Asset;
+    use solana_tss_api_backend::state::AppState;
+    use solana_tss_api_backend::tenant::{self, ApiKeyConfig};
+    use solana_tss_api_backend::token_registry::TokenConfig;
+    use solana_tss_api_backend::warning::WarningCode;
+
+    use solana_tss_api_backend::error::{Error, ErrorClass};
+    use solana_tss_api_backend::serialization::{
+        AggMessage1, PartialSignature, SecretAggStepOne, Serialize,
+    };
+    use solana_tss_api_backend::transaction_builder::{MAX_TRANSFER_RECIPIENTS, TransferBuilder};
+    use solana_tss_api_backend::tss::{
+        aggregated_pubkey, key_agg, sign_and_broadcast, step_one, transfer_message,
+    };
+    use solana_tss_api_backend::units::Lamports;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::{ENDPOINTS, build_app, endpoint_class, parse_hash, parse_keypair, parse_pubkey};
+
+    fn test_client() -> TestClient<impl poem::Endpoint> {
+        client_with(Config::default())
+    }
+
+    fn client_with(config: Config) -> TestClient<impl poem::Endpoint> {
+        TestClient::new(build_app(Arc::new(AppState::new(config).unwrap())))
+    }
+
+    /// `n` new parties with the first message and secret state of their step one.
+    fn ceremony(n: usize) -> (Vec<Keypair>, Vec<AggMessage1>, Vec<SecretAggStepOne>) {
+        let keys: Vec<Keypair> = (0..n).map(|_| Keypair::new()).collect();
+        let (first_messages, secret_states) = keys
+            .iter()
+            .map(|key| step_one(key.insecure_clone()))
+            .unzip();
+        (keys, first_messages, secret_states)
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_match_routes() {
+        let cli = test_client();
+        let resp = cli.get("/api/capabilities").send().await;
+        resp.assert_status_is_ok();
+        let capabilities: CapabilitiesResponse = resp.json().await.value().deserialize();
+        assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(capabilities.endpoints.len(), ENDPOINTS.len());
+        assert_eq!(capabilities.warning_codes.len(), WarningCode::ALL.len());
+        let retryable: Vec<_> = capabilities
+            .error_classes
+            .iter()
+            .filter(|class| class.retryable)
+            .map(|class| class.class.as_str())
+            .collect();
+        assert_eq!(retryable, ["transport", "rate_limited", "timeout"]);
+        assert_eq!(capabilities.error_classes.len(), ErrorClass::ALL.len());
+
+        // Every advertised endpoint must be routed with the advertised method. POST bodies are
+        // left empty so the handlers bail out before touching the network.
+        for endpoint in &capabilities.endpoints {
+            let method = Method::from_bytes(endpoint.method.as_bytes()).unwrap();
+            let resp = cli.request(method, &endpoint.path).send().await;
+            assert_ne!(resp.0.status(), StatusCode::NOT_FOUND, "{}", endpoint.path);
+            assert_ne!(
+                resp.0.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{}",
+                endpoint.path
+            );
+        }
+        cli.get("/api/not_advertised")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_openapi_lists_every_endpoint() {
+        let cli = test_client();
+        let resp = cli.get("/api/openapi.json").send().await;
+        resp.assert_status_is_ok();
+        let document: serde_json::Value = resp.json().await.value().deserialize();
+        assert_eq!(document["openapi"], "3.0.3");
+        for (method, path, _) in ENDPOINTS {
+            let operation = &document["paths"][openapi::openapi_path(path)][method.to_lowercase()];
+            assert!(operation.is_object(), "{} {}", method, path);
+            assert_eq!(
+                operation["responses"]["400"]["content"]["application/json"]["schema"]["$ref"],
+                "#/components/schemas/ErrorResponse",
+                "{} {}",
+                method,
+                path
+            );
+        }
+        let schemas = &document["components"]["schemas"];
+        assert_eq!(
+            schemas["Network"]["enum"],
+            serde_json::json!(["mainnet", "testnet", "devnet", "localnet"])
+        );
+        // The spec follows the serde attributes of the models
+        let send = &schemas["AggSendStepTwoRequest"]["properties"];
+        assert!(send.get("recent_block_hash").is_some());
+        assert!(send.get("recentBlockHash").is_none());
+
+        let resp = cli.get("/api/docs").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_content_type("text/html; charset=utf-8");
+        let csp = resp.0.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(csp.contains("'sha256-"));
+        assert!(!csp.contains("https://"));
+        assert!(
+            resp.0
+                .into_body()
+                .into_string()
+                .await
+                .unwrap()
+                .contains("/api/openapi.json")
+        );
+
+        let resp = cli.get("/api/docs/swagger-ui-bundle.js").send().await;
+        resp.assert_status_is_ok();
+        cli.get("/api/docs/index.html")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_every_endpoint_is_classified() {
+        for (method, path, class) in ENDPOINTS {
+            let path = path.replace(":id", "7");
+            assert_eq!(
+                endpoint_class(method, &path),
+                Some(*class),
+                "{} {}",
+                method,
+                path
+            );
+        }
+        assert_eq!(endpoint_class("GET", "/api/tokens"), Some(Class::Local));
+        assert_eq!(endpoint_class("POST", "/api/tokens"), Some(Class::Rpc));
+        assert_eq!(endpoint_class("GET", "/ui/app.js"), Some(Class::Local));
+        assert_eq!(
+            endpoint_class("GET", "/api/docs/swagger-ui.css"),
+            Some(Class::Local)
+        );
+        assert_eq!(endpoint_class("GET", "/api/audit//message"), None);
+        assert_eq!(endpoint_class("POST", "/api/not_an_endpoint"), None);
+    }
+
+    #[tokio::test]
+    async fn test_keypair_from_mnemonic() {
+        let cli = test_client();
+        let resp = cli
+            .get("/api/generate")
+            .query("mnemonic", &true)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let generated: GenerateKeypairResponse = resp.json().await.value().deserialize();
+        let mnemonic = generated.mnemonic.unwrap();
+        assert_eq!(mnemonic.split(' ').count(), 24);
+        assert_eq!(
+            generated.derivation_path.as_deref(),
+            Some("m/44'/501'/0'/0'")
+        );
+
+        // The phrase gives back the same keypair, at the default path or named explicitly
+        for path in [None, Some("m/44'/501'/0'/0'")] {
+            let resp = cli
+                .post("/api/keypair_from_mnemonic")
+                .body_json(&serde_json::json!({ "mnemonic": mnemonic, "derivation_path": path }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let derived: GenerateKeypairResponse = resp.json().await.value().deserialize();
+            assert_eq!(derived.secret_share, generated.secret_share);
+            assert_eq!(derived.public_share, generated.public_share);
+            assert_eq!(derived.mnemonic, None);
+        }
+
+        let resp = cli.get("/api/generate").send().await;
+        let plain: GenerateKeypairResponse = resp.json().await.value().deserialize();
+        assert_eq!(plain.mnemonic, None);
+
+        // Twelve times "abandon" fails the checksum, "abandon ... about" would pass
+        let invalid = [
+            (
+                ["abandon"; 12].join(" "),
+                "m/44'/501'/0'/0'",
+                "INVALID_MNEMONIC",
+            ),
+            (mnemonic.clone(), "44'/501'", "INVALID_DERIVATION_PATH"),
+        ];
+        for (phrase, path, code) in invalid {
+            let resp = cli
+                .post("/api/keypair_from_mnemonic")
+                .body_json(&serde_json::json!({ "mnemonic": phrase, "derivation_path": path }))
+                .send()
+                .await;
+            resp.assert_status(StatusCode::BAD_REQUEST);
+            resp.json()
+                .await
+                .value()
+                .object()
+                .get("error_code")
+                .assert_string(code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keypair_json_format() {
+        let cli = test_client();
+        let keypair = Keypair::new();
+        let bytes = keypair.to_bytes().to_vec();
+        // The array itself, pasted into a string from id.json, or base58
+        for sent in [
+            serde_json::json!(bytes),
+            serde_json::json!(format!("{:?}\n", bytes)),
+            serde_json::json!(keypair.to_base58_string()),
+        ] {
+            let resp = cli
+                .post("/api/convert_keypair")
+                .body_json(&serde_json::json!({ "keypair": sent }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let converted: ConvertKeypairResponse = resp.json().await.value().deserialize();
+            assert_eq!(converted.public_share, keypair.pubkey().to_string());
+            assert_eq!(converted.secret_share, keypair.to_base58_string());
+            assert_eq!(converted.bytes, bytes);
+
+            let resp = cli
+                .post("/api/agg_send_step_one")
+                .body_json(&serde_json::json!({ "keypair": sent }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let step_one: AggSendStepOneResponse = resp.json().await.value().deserialize();
+            let message = AggMessage1::deserialize_bs58(&step_one.message_1).unwrap();
+            assert_eq!(message.sender, keypair.pubkey());
+        }
+
+        for short in [
+            serde_json::json!(bytes[..32]),
+            serde_json::json!(bs58::encode(&bytes[..63]).into_string()),
+        ] {
+            let resp = cli
+                .post("/api/convert_keypair")
+                .body_json(&serde_json::json!({ "keypair": short }))
+                .send()
+                .await;
+            resp.assert_status(StatusCode::BAD_REQUEST);
+            let error: ErrorResponse = resp.json().await.value().deserialize();
+            assert_eq!(error.error_code.as_deref(), Some("INVALID_KEYPAIR"));
+            assert!(error.error.contains("expected 64 bytes"), "{}", error.error);
+        }
+        let resp = cli
+            .post("/api/convert_keypair")
+            .body_json(&serde_json::json!({ "keypair": "[1, 2, 256]" }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_version_matches_capabilities() {
+        let cli = test_client();
+        let resp = cli.get("/api/version").send().await;
+        resp.assert_status_is_ok();
+        let version: VersionResponse = resp.json().await.value().deserialize();
+        let resp = cli.get("/api/capabilities").send().await;
+        let capabilities: CapabilitiesResponse = resp.json().await.value().deserialize();
+
+        assert_eq!(version.version, capabilities.version);
+        assert!(!version.git_commit.is_empty());
+        assert!(version.dependencies.solana_sdk.starts_with("1."));
+        assert_eq!(
+            version.serialization.emitted_version,
+            capabilities.serialization.emitted_version
+        );
+        assert_eq!(version.limits.max_parties, capabilities.limits.max_parties);
+        assert_eq!(version.networks.len(), capabilities.networks.len());
+        assert!(version.networks.iter().all(|n| !n.custom_rpc));
+    }
+
+    #[test]
+    fn test_contaminated_inputs() {
+        let contaminate = |value: &str| {
+            [
+                format!("{}\n", value),
+                format!("  {}\r\n", value),
+                format!("\u{FEFF}{}", value),
+                format!("{}\u{200B} ", value),
+            ]
+        };
+        let keypair = Keypair::new();
+        let hash = solana_sdk::hash::Hash::new_unique();
+        let (first_message, secret_state) = step_one(Keypair::new());
+        let partial_signature = PartialSignature(solana_sdk::signature::Signature::from([7; 64]));
+
+        for pasted in contaminate(&keypair.to_base58_string()) {
+            assert_eq!(parse_keypair(&pasted).unwrap(), keypair);
+        }
+        let mut leaked = keypair.to_base58_string();
+        leaked.replace_range(5..6, "l");
+        let err = parse_keypair(&leaked).unwrap_err().to_string();
+        assert!(
+            err.contains("position 5") && !err.contains("'l'"),
+            "{}",
+            err
+        );
+        // `to` and `keys[]`
+        for pasted in contaminate(&keypair.pubkey().to_string()) {
+            assert_eq!(parse_pubkey(&pasted).unwrap(), keypair.pubkey());
+        }
+        for pasted in contaminate(&hash.to_string()) {
+            assert_eq!(parse_hash(&pasted).unwrap(), hash);
+        }
+        for pasted in contaminate(&first_message.serialize_bs58()) {
+            assert_eq!(
+                AggMessage1::deserialize_bs58(&pasted).unwrap(),
+                first_message
+            );
+        }
+        for pasted in contaminate(&partial_signature.serialize_bs58()) {
+            assert_eq!(
+                PartialSignature::deserialize_bs58(&pasted).unwrap(),
+                partial_signature
+            );
+        }
+        for pasted in contaminate(&secret_state.serialize_bs58()) {
+            assert_eq!(
+                SecretAggStepOne::deserialize_bs58(&pasted)
+                    .unwrap()
+                    .serialize_bs58(),
+                secret_state.serialize_bs58()
+            );
+        }
+
+        let two_keys = format!("{} {}", keypair.pubkey(), Keypair::new().pubkey());
+        assert!(
+            parse_pubkey(&two_keys)
+                .unwrap_err()
+                .to_string()
+                .contains(&format!("after \"{}\"", keypair.pubkey()))
+        );
+        // Secrets are never echoed back
+        let split_secret = secret_state.serialize_bs58().replacen('1', " 1", 1);
+        let error = SecretAggStepOne::deserialize_bs58(&split_secret)
+            .unwrap_err()
+            .to_string();
+        assert!(!error.contains(&secret_state.serialize_bs58()[..4]));
+    }
+
+    // Exports to memory instead of OTLP and checks where the step-two spans end up
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_step_two_trace() {
+        use opentelemetry::trace::{SpanId, SpanKind, TraceId, TracerProvider};
+        use opentelemetry_sdk::{
+            propagation::TraceContextPropagator,
+            trace::{InMemorySpanExporter, SdkTracerProvider},
+        };
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        opentelemetry::global::set_tracer_provider(provider.clone());
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry()
+                .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test"))),
+        );
+
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let cli = test_client();
+        cli.post("/api/agg_send_step_two")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body_json(&serde_json::json!({
+                "keypair": keys[0].to_base58_string(),
+                "lamports": 1_000,
+                "to": Keypair::new().pubkey().to_string(),
+                "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+                "keys": keys.iter().map(|k| k.pubkey().to_string()).collect::<Vec<_>>(),
+                "first_messages": [first_messages[1].serialize_bs58()],
+                "secret_state": secret_states[0].serialize_bs58(),
+            }))
+            .send()
+            .await
+            .assert_status_is_ok();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = |name: &str| {
+            spans
+                .iter()
+                .find(|s| s.name == name)
+                .unwrap_or_else(|| panic!("no {} span in {:?}", name, spans))
+        };
+        let server = spans
+            .iter()
+            .find(|s| s.span_kind == SpanKind::Server)
+            .unwrap();
+        // The request span continues the caller's trace
+        assert_eq!(
+            server.span_context.trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        assert_eq!(
+            server.parent_span_id,
+            SpanId::from_hex("00f067aa0ba902b7").unwrap()
+        );
+        let step_two = span("tss.step_two");
+        assert_eq!(step_two.parent_span_id, server.span_context.span_id());
+        assert_eq!(
+            step_two.span_context.trace_id(),
+            server.span_context.trace_id()
+        );
+        // The handler aggregates the keys too, one of them happens inside step two
+        assert!(spans.iter().any(
+            |s| s.name == "tss.key_agg" && s.parent_span_id == step_two.span_context.span_id()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_keys_by_party() {
+        let cli = test_client();
+        let keys: Vec<String> = (0..3)
+            .map(|_| Keypair::new().pubkey().to_string())
+            .collect();
+        let aggregate = |body: serde_json::Value| {
+            let cli = &cli;
+            async move {
+                let resp = cli
+                    .post("/api/aggregate_keys")
+                    .body_json(&body)
+                    .send()
+                    .await;
+                resp.assert_status_is_ok();
+                resp.json()
+                    .await
+                    .value()
+                    .deserialize::<AggregateKeysResponse>()
+            }
+        };
+
+        let default = aggregate(serde_json::json!({ "keys": keys })).await;
+        assert_eq!(default.mode, KeyAggMode::FirstKey);
+        assert_eq!((default.party_index, &default.party_key), (0, &keys[0]));
+        let mut coefficients = vec![default.musig_coefficient.clone()];
+        for (i, key) in keys.iter().enumerate() {
+            for body in [
+                serde_json::json!({ "keys": keys, "party_index": i }),
+                serde_json::json!({ "keys": keys, "party_key": key }),
+                serde_json::json!({ "keys": keys, "party_key": key, "party_index": i }),
+            ] {
+                let party = aggregate(body).await;
+                // Only the coefficient depends on the designated party
+                assert_eq!(party.aggregated_public_key, default.aggregated_public_key);
+                assert_eq!(party.mode, KeyAggMode::Party);
+                assert_eq!((party.party_index, &party.party_key), (i, key));
+                coefficients.push(party.musig_coefficient);
+            }
+        }
+        assert!(coefficients[..4].iter().all(|c| *c == coefficients[0]));
+        assert_ne!(coefficients[1], coefficients[4]);
+        assert_ne!(coefficients[4], coefficients[7]);
+
+        for body in [
+            serde_json::json!({ "keys": keys, "party_index": 3 }),
+            serde_json::json!({ "keys": keys, "party_key": Keypair::new().pubkey().to_string() }),
+            serde_json::json!({ "keys": keys, "party_key": keys[1], "party_index": 2 }),
+        ] {
+            let resp = cli
+                .post("/api/aggregate_keys")
+                .body_json(&body)
+                .send()
+                .await;
+            resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+            resp.json()
+                .await
+                .value()
+                .object()
+                .get("error_code")
+                .assert_string("INVALID_PARTY");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_body_size_limit() {
+        let cli = test_client();
+        let limit = Config::default().max_body_size;
+        cli.post("/api/aggregate_keys")
+            .content_type("application/json")
+            .body(vec![b' '; limit + 1])
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_party_limit() {
+        let cli = test_client();
+        let max = Config::default().max_parties;
+        // Real keys, `Pubkey::new_unique` is usually off the curve
+        let keys = |n: usize| -> Vec<String> {
+            (0..n)
+                .map(|_| Keypair::new().pubkey().to_string())
+                .collect()
+        };
+
+        // The largest allowed party still aggregates quickly
+        let start = std::time::Instant::now();
+        cli.post("/api/aggregate_keys")
+            .body_json(&serde_json::json!({ "keys": keys(max) }))
+            .send()
+            .await
+            .assert_status_is_ok();
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        let resp = cli
+            .post("/api/aggregate_keys")
+            .body_json(&serde_json::json!({ "keys": keys(max + 1) }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let json = resp.json().await;
+        json.value()
+            .object()
+            .get("error_code")
+            .assert_string("TOO_MANY_PARTIES");
+        assert!(
+            json.value()
+                .object()
+                .get("error")
+                .string()
+                .contains(&max.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_key_is_rejected() {
+        let key = Keypair::new();
+        let common = serde_json::json!({
+            "keys": [key.pubkey().to_string()],
+            "to": Keypair::new().pubkey().to_string(),
+            "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+            "net": "devnet",
+            "lamports": 1_000,
+            "amount": 1.0,
+            "token_mint": Keypair::new().pubkey().to_string(),
+            "decimals": 6,
+            "keypair": key.to_base58_string(),
+            "first_messages": [],
+            "secret_state": step_one(key.insecure_clone()).1.serialize_bs58(),
+            "signatures": [],
+        });
+        let cli = test_client();
+        for path in [
+            "/api/aggregate_keys",
+            "/api/agg_send_step_two",
+            "/api/spl_agg_send_step_two",
+            "/api/aggregate_signatures",
+            "/api/spl_aggregate_signatures",
+        ] {
+            let resp = cli.post(path).body_json(&common).send().await;
+            resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+            resp.json()
+                .await
+                .value()
+                .object()
+                .get("error_code")
+                .assert_string("MIN_TWO_KEYS");
+        }
+        assert!(matches!(
+            key_agg(vec![key.pubkey()], None),
+            Err(Error::TooFewKeys(1))
+        ));
+        let resp = cli
+            .post("/api/aggregate_keys")
+            .body_json(&serde_json::json!({ "keys": [] }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_error_codes() {
+        let cli = test_client();
+        let key = Keypair::new();
+        let resp = cli
+            .post("/api/aggregate_keys")
+            .body_json(&serde_json::json!({
+                "keys": [key.pubkey().to_string(), "not-a-pubkey"],
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("INVALID_PUBKEY"));
+        assert_eq!(error.details.unwrap()["value"], "not-a-pubkey");
+        assert!(error.error.contains("not-a-pubkey"));
+
+        let other = Keypair::new();
+        let resp = cli
+            .post("/api/agg_send_step_two")
+            .body_json(&serde_json::json!({
+                "keys": [key.pubkey().to_string(), other.pubkey().to_string()],
+                "to": Keypair::new().pubkey().to_string(),
+                "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+                "net": "devnet",
+                "amount": 1.0,
+                "keypair": key.to_base58_string(),
+                "first_messages": ["garbage"],
+                "secret_state": step_one(key.insecure_clone()).1.serialize_bs58(),
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("DESERIALIZATION_FAILED"));
+        assert_eq!(error.details.unwrap()["field"], "first_messages");
+        assert_eq!(error.error_class, "input");
+    }
+
+    /// An RPC node on which no account exists.
+    async fn empty_rpc_node() -> String {
+        use poem::{
+            Server,
+            listener::{Acceptor, Listener, TcpListener},
+            web::Json,
+        };
+
+        #[poem::handler]
+        fn node(Json(request): Json<serde_json::Value>) -> Json<serde_json::Value> {
+            let value = match request["method"].as_str() {
+                Some("getMultipleAccounts") => {
+                    let addresses = request["params"][0].as_array().map_or(0, Vec::len);
+                    serde_json::json!(vec![serde_json::Value::Null; addresses])
+                }
+                _ => serde_json::Value::Null,
+            };
+            Json(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": { "context": { "slot": 1 }, "value": value },
+            }))
+        }
+
+        let acceptor = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = acceptor.local_addr()[0].as_socket_addr().cloned().unwrap();
+        tokio::spawn(Server::new_with_acceptor(acceptor).run(poem::post(node)));
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_error_statuses() {
+        let client = |devnet_url: String| {
+            client_with(Config {
+                rpc_urls: std::collections::HashMap::from([(Network::Devnet, devnet_url)]),
+                ..Config::default()
+            })
+        };
+        let balance = |address: String| serde_json::json!({ "net": "devnet", "address": address });
+
+        // A request that has to change
+        let resp = test_client()
+            .post("/api/balance")
+            .body_json(&balance("not-a-pubkey".to_string()))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("INVALID_PUBKEY"));
+
+        // A cluster that can't be reached, nothing listens on port 1
+        let resp = client("http://127.0.0.1:1".to_string())
+            .post("/api/balance")
+            .body_json(&balance(Pubkey::new_unique().to_string()))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("RPC_UNAVAILABLE"));
+        assert!(error.retryable);
+
+        // A token account that doesn't exist
+        let resp = client(empty_rpc_node().await)
+            .post("/api/spl_token_balance")
+            .body_json(&serde_json::json!({
+                "net": "devnet",
+                "owner": Pubkey::new_unique().to_string(),
+                "token_mint": Pubkey::new_unique().to_string(),
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NOT_FOUND);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("TOKEN_ACCOUNT_NOT_FOUND"));
+    }
+
+    #[tokio::test]
+    async fn test_non_ascii_memos_sign_the_same_bytes() {
+        let cli = test_client();
+        let (to, recent_block_hash) = (Pubkey::new_unique(), Hash::new_unique());
+        // An emoji with a skin tone modifier, a decomposed "é" that NFC would compose, and a
+        // code point outside the BMP that JSON escapes as a surrogate pair
+        for memo in ["paid \u{1f44d}\u{1f3fd}", "cafe\u{301}", "clef \u{1d11e}"] {
+            let (keys, first_messages, secret_states) = ceremony(2);
+            let pubkeys: Vec<Pubkey> = keys.iter().map(|k| k.pubkey()).collect();
+            let mut signatures = Vec::new();
+            for i in 0..2 {
+                let body = serde_json::json!({
+                    "keypair": keys[i].to_base58_string(),
+                    "lamports": 1_000,
+                    "to": to.to_string(),
+                    "memo": memo,
+                    "recent_block_hash": recent_block_hash.to_string(),
+                    "keys": pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+                    "first_messages": [first_messages[1 - i].serialize_bs58()],
+                    "secret_state": secret_states[i].serialize_bs58(),
+                });
+                // The escaped form of the same memo must sign the same bytes
+                let escaped: String = memo
+                    .encode_utf16()
+                    .map(|unit| format!("\\u{:04x}", unit))
+                    .collect();
+                let body = match i {
+                    0 => body.to_string(),
+                    _ => body.to_string().replace(memo, &escaped),
+                };
+                let resp = cli
+                    .post("/api/agg_send_step_two")
+                    .content_type("application/json")
+                    .body(body)
+                    .send()
+                    .await;
+                resp.assert_status_is_ok();
+                let step_two: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+                signatures
+                    .push(PartialSignature::deserialize_bs58(&step_two.partial_signature).unwrap());
+            }
+            // The aggregation rebuilds the message and verifies the signature against it
+            let transfer = TransferBuilder::new(
+                aggregated_pubkey(pubkeys.clone()).unwrap(),
+                to,
+                Lamports::new(1_000),
+            )
+            .memo(Some(memo.to_string()));
+            let tx = sign_and_broadcast(&transfer, recent_block_hash, signatures).unwrap();
+            assert_eq!(tx.message.instructions[1].data, memo.as_bytes(), "{}", memo);
+        }
+
+        let resp = cli
+            .post("/api/build_message")
+            .content_type("application/json")
+            .body(b"{\"memo\": \"caf\xe9\"}".to_vec())
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("INVALID_UTF8"));
+        assert!(error.error.contains("offset 13"), "{}", error.error);
+    }
+
+    #[tokio::test]
+    async fn test_priority_fee_is_signed_by_every_party() {
+        let cli = test_client();
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let pubkeys: Vec<Pubkey> = keys.iter().map(|k| k.pubkey()).collect();
+        let (to, recent_block_hash) = (Pubkey::new_unique(), Hash::new_unique());
+        let params = serde_json::json!({
+            "lamports": 1_000,
+            "to": to.to_string(),
+            "memo": null,
+            "recent_block_hash": recent_block_hash.to_string(),
+            "keys": pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+            "compute_unit_limit": 1_000,
+            "priority_fee_micro_lamports": 25_000,
+        });
+        let resp = cli
+            .post("/api/build_message")
+            .body_json(&params)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let review: BuildMessageResponse = resp.json().await.value().deserialize();
+        assert_eq!(review.instructions.len(), 3);
+
+        let step_two = |i: usize, priority_fee: Option<u64>| {
+            let mut req = params.clone();
+            req["priority_fee_micro_lamports"] = serde_json::json!(priority_fee);
+            req["keypair"] = keys[i].to_base58_string().into();
+            req["first_messages"] = serde_json::json!([first_messages[1 - i].serialize_bs58()]);
+            req["secret_state"] = secret_states[i].serialize_bs58().into();
+            cli.post("/api/agg_send_step_two").body_json(&req).send()
+        };
+        let mut signatures = Vec::new();
+        let mut digests = Vec::new();
+        for i in 0..2 {
+            let resp = step_two(i, Some(25_000)).await;
+            resp.assert_status_is_ok();
+            let step_two: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+            signatures
+                .push(PartialSignature::deserialize_bs58(&step_two.partial_signature).unwrap());
+            digests.push(step_two.request_digest);
+        }
+        assert_eq!(digests[0], digests[1]);
+        // A party that left the fee out signed another transfer
+        let resp = step_two(0, None).await;
+        let step_two: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+        assert_ne!(step_two.request_digest, digests[0]);
+
+        let transfer = TransferBuilder::new(
+            aggregated_pubkey(pubkeys.clone()).unwrap(),
+            to,
+            Lamports::new(1_000),
+        )
+        .compute_unit_limit(Some(1_000));
+        let aggregate = |priority_fee| {
+            sign_and_broadcast(
+                &transfer.clone().compute_unit_price(priority_fee),
+                recent_block_hash,
+                signatures.iter().map(|s| PartialSignature(s.0)).collect(),
+            )
+        };
+        let tx = aggregate(Some(25_000)).unwrap();
+        assert_eq!(message_hash(&tx.message), review.message_hash);
+        assert!(matches!(aggregate(None), Err(Error::InvalidSignature)));
+        assert!(matches!(aggregate(Some(1)), Err(Error::InvalidSignature)));
+    }
+
+    #[tokio::test]
+    async fn test_durable_nonce_is_signed_by_every_party() {
+        let cli = test_client();
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let pubkeys: Vec<Pubkey> = keys.iter().map(|k| k.pubkey()).collect();
+        let aggpubkey = aggregated_pubkey(pubkeys.clone()).unwrap();
+        let (to, nonce_account, nonce) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Hash::new_unique(),
+        );
+        let params = serde_json::json!({
+            "lamports": 1_000,
+            "to": to.to_string(),
+            "memo": null,
+            "recent_block_hash": nonce.to_string(),
+            "keys": pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+            "nonce_account": nonce_account.to_string(),
+        });
+        let resp = cli
+            .post("/api/build_message")
+            .body_json(&params)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let review: BuildMessageResponse = resp.json().await.value().deserialize();
+        assert_eq!(review.instructions.len(), 2);
+
+        let step_two = |i: usize, nonce_account: Option<String>, authority: Option<String>| {
+            let mut req = params.clone();
+            req["nonce_account"] = serde_json::json!(nonce_account);
+            req["nonce_authority"] = serde_json::json!(authority);
+            req["keypair"] = keys[i].to_base58_string().into();
+            req["first_messages"] = serde_json::json!([first_messages[1 - i].serialize_bs58()]);
+            req["secret_state"] = secret_states[i].serialize_bs58().into();
+            cli.post("/api/agg_send_step_two").body_json(&req).send()
+        };
+        let mut signatures = Vec::new();
+        let mut digests = Vec::new();
+        // Naming the aggregated key as the authority is the same as leaving it out
+        for (i, authority) in [(0, None), (1, Some(aggpubkey.to_string()))] {
+            let resp = step_two(i, Some(nonce_account.to_string()), authority).await;
+            resp.assert_status_is_ok();
+            let step_two: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+            signatures
+                .push(PartialSignature::deserialize_bs58(&step_two.partial_signature).unwrap());
+            digests.push(step_two.request_digest);
+        }
+        assert_eq!(digests[0], digests[1]);
+        let resp = step_two(0, None, None).await;
+        let step_two_response: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+        assert_ne!(step_two_response.request_digest, digests[0]);
+        // The aggregated key is the only signer, nobody else can advance the nonce
+        let resp = step_two(
+            0,
+            Some(nonce_account.to_string()),
+            Some(pubkeys[0].to_string()),
+        )
+        .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        resp.json()
+            .await
+            .value()
+            .object()
+            .get("error_code")
+            .assert_string("INVALID_NONCE_ACCOUNT");
+
+        let aggregate = |durable_nonce| {
+            sign_and_broadcast(
+                &TransferBuilder::new(aggpubkey, to, Lamports::new(1_000))
+                    .durable_nonce(durable_nonce),
+                nonce,
+                signatures.iter().map(|s| PartialSignature(s.0)).collect(),
+            )
+        };
+        let tx = aggregate(Some(DurableNonce {
+            account: nonce_account,
+            authority: aggpubkey,
+        }))
+        .unwrap();
+        assert_eq!(message_hash(&tx.message), review.message_hash);
+        assert_eq!(advanced_nonce_account(&tx.message), Some(nonce_account));
+        assert_eq!(tx.message.recent_blockhash, nonce);
+        assert!(matches!(aggregate(None), Err(Error::InvalidSignature)));
+    }
+
+    #[tokio::test]
+    async fn test_threshold_key_is_signed_by_any_two_of_three() {
+        let cli = test_client();
+        let keys: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<String> = keys.iter().map(|k| k.pubkey().to_string()).collect();
+        let mut first_messages = Vec::new();
+        for key in &keys {
+            let req = serde_json::json!({
+                "keypair": key.to_base58_string(),
+                "keys": pubkeys,
+                "threshold": 2,
+            });
+            let resp = cli
+                .post("/api/threshold/keygen_step_one")
+                .body_json(&req)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let step_one: ThresholdKeygenStepOneResponse = resp.json().await.value().deserialize();
+            first_messages.push(step_one.message_1);
+        }
+        let keygen = |key: &Keypair| {
+            serde_json::json!({
+                "keypair": key.to_base58_string(),
+                "keys": pubkeys,
+                "threshold": 2,
+                "first_messages": first_messages,
+            })
+        };
+        let mut confirmations = Vec::new();
+        for key in &keys {
+            let resp = cli
+                .post("/api/threshold/keygen_step_two")
+                .body_json(&keygen(key))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let step_two: ThresholdKeygenStepTwoResponse = resp.json().await.value().deserialize();
+            confirmations.push(step_two.confirmation);
+        }
+
+        // No share before every party confirmed the same keygen
+        let mut req = keygen(&keys[0]);
+        req["confirmations"] = serde_json::json!(confirmations[..2]);
+        let resp = cli
+            .post("/api/threshold/keygen_step_three")
+            .body_json(&req)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("INVALID_THRESHOLD"));
+        req["confirmations"] = serde_json::json!([
+            confirmations[0],
+            confirmations[1],
+            Keypair::new().sign_message(b"no keygen").to_string(),
+        ]);
+        let resp = cli
+            .post("/api/threshold/keygen_step_three")
+            .body_json(&req)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("THRESHOLD_KEYGEN_FAILED"));
+
+        let mut shares = Vec::new();
+        for key in &keys {
+            let mut req = keygen(key);
+            req["confirmations"] = serde_json::json!(confirmations);
+            let resp = cli
+                .post("/api/threshold/keygen_step_three")
+                .body_json(&req)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let step_three: ThresholdKeygenStepThreeResponse =
+                resp.json().await.value().deserialize();
+            shares.push(step_three);
+        }
+        let group_key: Pubkey = shares[0].group_key.parse().unwrap();
+        assert!(
+            shares
+                .iter()
+                .all(|share| share.group_key == shares[0].group_key)
+        );
+        assert_eq!(shares[2].key_index, 2);
+        assert_ne!(
+            group_key,
+            aggregated_pubkey(keys.iter().map(|k| k.pubkey()).collect()).unwrap()
+        );
+
+        // The third party takes no part in signing
+        let (to, recent_block_hash) = (Pubkey::new_unique(), Hash::new_unique());
+        let signers = [0, 2];
+        let (nonces, secret_states): (Vec<_>, Vec<_>) = signers
+            .iter()
+            .map(|i| step_one(keys[*i].insecure_clone()))
+            .unzip();
+        let params = serde_json::json!({
+            "lamports": 1_000,
+            "to": to.to_string(),
+            "recent_block_hash": recent_block_hash.to_string(),
+            "keys": pubkeys,
+            "threshold": {
+                "threshold": 2,
+                "group_key": group_key.to_string(),
+                "signers": [pubkeys[0], pubkeys[2]],
+            },
+        });
+        let resp = cli
+            .post("/api/build_message")
+            .body_json(&params)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let review: BuildMessageResponse = resp.json().await.value().deserialize();
+
+        let step_two = |n: usize, key: usize, share: usize| {
+            let mut req = params.clone();
+            req["keypair"] = keys[key].to_base58_string().into();
+            req["key_share"] = shares[share].key_share.clone().into();
+            req["first_messages"] = serde_json::json!([nonces[1 - n].serialize_bs58()]);
+            req["secret_state"] = secret_states[n].serialize_bs58().into();
+            cli.post("/api/agg_send_step_two").body_json(&req).send()
+        };
+        let mut signatures = Vec::new();
+        let mut digests = Vec::new();
+        for (n, key) in signers.iter().enumerate() {
+            let resp = step_two(n, *key, *key).await;
+            resp.assert_status_is_ok();
+            let step_two: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+            assert_eq!(step_two.context.aggregated_pubkey, group_key.to_string());
+            signatures
+                .push(PartialSignature::deserialize_bs58(&step_two.partial_signature).unwrap());
+            digests.push(step_two.request_digest);
+        }
+        assert_eq!(digests[0], digests[1]);
+        // Neither a party outside the signers nor another party's share can sign
+        for (key, share) in [(1, 1), (2, 0)] {
+            let resp = step_two(1, key, share).await;
+            resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+            resp.json()
+                .await
+                .value()
+                .object()
+                .get("error_code")
+                .assert_string("INVALID_THRESHOLD");
+        }
+
+        let tx = sign_and_broadcast(
+            &TransferBuilder::new(group_key, to, Lamports::new(1_000)),
+            recent_block_hash,
+            signatures,
+        )
+        .unwrap();
+        assert_eq!(message_hash(&tx.message), review.message_hash);
+    }
+
+    #[tokio::test]
+    async fn test_server_side_secret_state() {
+        let state = Arc::new(
+            AppState::new(Config {
+                server_side_secret_state: true,
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        let cli = TestClient::new(build_app(state.clone()));
+        let keys: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<Pubkey> = keys.iter().map(|k| k.pubkey()).collect();
+        let mut step_ones = Vec::new();
+        for key in &keys {
+            let resp = cli
+                .post("/api/agg_send_step_one")
+                .body_json(&serde_json::json!({ "keypair": key.to_base58_string() }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let value = resp.json().await;
+            assert!(value.value().object().get_opt("secret_state").is_none());
+            let step_one: AggSendStepOneResponse = value.value().deserialize();
+            step_ones.push(step_one);
+        }
+
+        let (to, recent_block_hash) = (Pubkey::new_unique(), Hash::new_unique());
+        let step_two = |party: usize, secret_state_id: &str| {
+            let req = serde_json::json!({
+                "keypair": keys[party].to_base58_string(),
+                "lamports": 1_000,
+                "to": to.to_string(),
+                "recent_block_hash": recent_block_hash.to_string(),
+                "keys": pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+                "first_messages": [step_ones[1 - party].message_1],
+                "secret_state_id": secret_state_id,
+            });
+            cli.post("/api/agg_send_step_two").body_json(&req).send()
+        };
+        let not_found = async |resp: poem::test::TestResponse| {
+            resp.assert_status(StatusCode::NOT_FOUND);
+            resp.json()
+                .await
+                .value()
+                .object()
+                .get("error_code")
+                .assert_string("SECRET_STATE_NOT_FOUND");
+        };
+        // Another party's id is used up without signing
+        let ids: Vec<_> = step_ones
+            .iter()
+            .map(|s| s.secret_state_id.clone().unwrap())
+            .collect();
+        not_found(step_two(1, &ids[0]).await).await;
+        not_found(step_two(0, &ids[0]).await).await;
+
+        let resp = step_two(1, &ids[1]).await;
+        resp.assert_status_is_ok();
+        let signed: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+        assert_eq!(
+            signed.context.aggregated_pubkey,
+            aggregated_pubkey(pubkeys.clone()).unwrap().to_string()
+        );
+        // The nonces of an id sign once
+        not_found(step_two(1, &ids[1]).await).await;
+
+        assert_eq!(state.secret_states.stats().len, 0);
+    }
+
+    #[tokio::test]
+    async fn test_step_two_signs_only_the_reviewed_message() {
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let params = serde_json::json!({
+            "lamports": 1_000,
+            "to": Keypair::new().pubkey().to_string(),
+            "memo": "invoice 7",
+            "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+            "keys": keys.iter().map(|k| k.pubkey().to_string()).collect::<Vec<_>>(),
+        });
+        let cli = test_client();
+        let resp = cli
+            .post("/api/build_message")
+            .body_json(&params)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let review: BuildMessageResponse = resp.json().await.value().deserialize();
+        assert_eq!(review.instructions.len(), 2);
+
+        let step_two = |lamports: u64| {
+            let mut req = params.clone();
+            req["lamports"] = lamports.into();
+            req["keypair"] = keys[0].to_base58_string().into();
+            req["first_messages"] = serde_json::json!([first_messages[1].serialize_bs58()]);
+            req["secret_state"] = secret_states[0].serialize_bs58().into();
+            req["expected_message_hash"] = review.message_hash.clone().into();
+            cli.post("/api/agg_send_step_two").body_json(&req).send()
+        };
+        step_two(1_000).await.assert_status_is_ok();
+        let resp = step_two(1_001).await;
+        resp.assert_status(StatusCode::CONFLICT);
+        resp.json()
+            .await
+            .value()
+            .object()
+            .get("error_code")
+            .assert_string("MESSAGE_HASH_MISMATCH");
+    }
+
+    #[tokio::test]
+    async fn test_oversize_transfers() {
+        let cli = test_client();
+        let recipients: Vec<_> = (0..12)
+            .map(|_| serde_json::json!({ "to": Keypair::new().pubkey().to_string(), "amount": 0.001 }))
+            .collect();
+        let resp = cli
+            .post("/api/send_single")
+            .body_json(&serde_json::json!({
+                "keypair": Keypair::new().to_base58_string(),
+                "amount": 0.001,
+                "to": Keypair::new().pubkey().to_string(),
+                "recipients": recipients,
+                "net": "devnet",
+                "memo": "m".repeat(600),
+                "allow_non_system_recipient": true,
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let json = resp.json().await;
+        let error = json.value().object();
+        error
+            .get("error_code")
+            .assert_string("TRANSACTION_TOO_LARGE");
+        let details = error.get("details").object();
+        details.get("splittable").assert_bool(true);
+        details.get("limit").assert_i64(1232);
+        assert!(details.get("max_recipients").i64() < 13);
+        assert!(details.get("size_without_memo").i64() < details.get("size").i64());
+
+        // Nothing is split behind the back of the other parties
+        let resp = cli
+            .post("/api/build_message")
+            .body_json(&serde_json::json!({
+                "lamports": 1_000,
+                "to": Keypair::new().pubkey().to_string(),
+                "memo": "m".repeat(1_200),
+                "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+                "keys": [Keypair::new().pubkey().to_string(), Keypair::new().pubkey().to_string()],
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let json = resp.json().await;
+        let error = json.value().object();
+        error
+            .get("details")
+            .object()
+            .get("splittable")
+            .assert_bool(false);
+        assert!(error.get("error").string().contains("never split"));
+    }
+
+    #[tokio::test]
+    async fn test_solana_pay() {
+        let cli = test_client();
+        let merchant = "mvines9iiHiQTysrwkJjGf2gb9Ex9jXJX8ns3qwf2kN";
+        let resp = cli
+            .post("/api/solana_pay/parse")
+            .body_json(&serde_json::json!({
+                "url": "solana:mvines9iiHiQTysrwkJjGf2gb9Ex9jXJX8ns3qwf2kN?amount=1&label=Michael&message=Thanks%20for%20all%20the%20fish&memo=OrderId12345",
+            }))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let parsed: SolanaPayParseResponse = resp.json().await.value().deserialize();
+        assert_eq!(parsed.endpoint, "/api/send_single");
+        assert_eq!(parsed.to, merchant);
+        assert_eq!(parsed.amount.as_deref(), Some("1"));
+        assert_eq!(parsed.memo.as_deref(), Some("OrderId12345"));
+        assert_eq!(parsed.message.as_deref(), Some("Thanks for all the fish"));
+
+        let create = |amount: &str| {
+            cli.post("/api/solana_pay/create")
+                .body_json(&serde_json::json!({
+                    "to": merchant,
+                    "amount": amount,
+                    "token_mint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+                    "decimals": 6,
+                }))
+                .send()
+        };
+        let resp = create("0.010").await;
+        resp.assert_status_is_ok();
+        let created: SolanaPayCreateResponse = resp.json().await.value().deserialize();
+        assert_eq!(
+            created.url,
+            "solana:mvines9iiHiQTysrwkJjGf2gb9Ex9jXJX8ns3qwf2kN?amount=0.01&spl-token=EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"
+        );
+        let resp = create("0.0000001").await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("INVALID_SOLANA_PAY"));
+
+        // A URL sent instead of the fields signs the same message, references included
+        let reference = Pubkey::new_unique();
+        let url = format!(
+            "solana:{}?amount=0.5&reference={}&memo=OrderId12345",
+            merchant, reference
+        );
+        let common = serde_json::json!({
+            "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+            "keys": [Keypair::new().pubkey().to_string(), Keypair::new().pubkey().to_string()],
+        });
+        let build = |fields: serde_json::Value| {
+            let mut body = common.clone();
+            body.as_object_mut()
+                .unwrap()
+                .extend(fields.as_object().unwrap().clone());
+            cli.post("/api/build_message").body_json(&body).send()
+        };
+        let resp = build(serde_json::json!({ "solana_pay_url": url })).await;
+        resp.assert_status_is_ok();
+        let from_url: BuildMessageResponse = resp.json().await.value().deserialize();
+        let resp = build(serde_json::json!({
+            "to": merchant,
+            "amount": "0.5",
+            "memo": "OrderId12345",
+            "references": [reference.to_string()],
+        }))
+        .await;
+        resp.assert_status_is_ok();
+        let from_fields: BuildMessageResponse = resp.json().await.value().deserialize();
+        assert_eq!(from_url.message_hash, from_fields.message_hash);
+        // The memo comes right before the transfer, the reference last among its accounts
+        assert_eq!(from_url.memos, ["OrderId12345"]);
+        assert!(from_url.instructions[0].memo.is_some());
+        let transfer = &from_url.instructions[1];
+        assert_eq!(
+            transfer.program_id,
+            solana_sdk::system_program::id().to_string()
+        );
+        let last = transfer.accounts.last().unwrap();
+        assert_eq!(last.pubkey, reference.to_string());
+        assert!(!last.is_signer && !last.is_writable);
+
+        let resp = build(serde_json::json!({ "solana_pay_url": url, "to": merchant })).await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("INVALID_SOLANA_PAY"));
+        assert!(
+            error.error.contains("to is set by solana_pay_url"),
+            "{}",
+            error.error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_export() {
+        let config = Config {
+            api_keys: vec![api_key("key-ops", "ops", true)],
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config).unwrap());
+        for i in 0..3u8 {
+            state.audit.record(
+                None,
+                "send_single",
+                Some(&Signature::from([i; 64])),
+                None,
+                None,
+                Outcome::Broadcast,
+                None,
+                None,
+            );
+        }
+        let cli = TestClient::new(build_app(state));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let to = now.as_millis() as u64 + 60_000;
+        let export = |query: String| {
+            cli.get(format!("/api/audit/export?{}", query))
+                .header("authorization", "Bearer key-ops")
+                .header("accept-encoding", "gzip")
+                .send()
+        };
+
+        cli.get(format!("/api/audit/export?from=0&to={}", to))
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let resp = export(format!("from={}&to={}", to, to)).await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("INVALID_EXPORT_RANGE"));
+
+        // The whole log within the last week, gzip compressed as the client accepts it
+        let from = to - audit_export::MAX_EXPORT_RANGE_MS;
+        let resp = export(format!("from={}&to={}&limit=2", from, to)).await;
+        resp.assert_status_is_ok();
+        resp.assert_header("content-encoding", "gzip");
+        resp.assert_content_type("application/x-ndjson");
+        let body = resp.0.into_body().into_bytes().await.unwrap();
+        let mut text = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut text).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["type"], "header");
+        assert_eq!(lines[0]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(lines[1]["type"], "entry");
+        assert_eq!(lines[2]["id"], 2);
+        let trailer_start = text[..text.len() - 1].rfind('\n').unwrap() + 1;
+        let hash: String = Sha256::digest(&text.as_bytes()[..trailer_start])
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(lines[3]["sha256"], hash);
+        assert_eq!(lines[3]["entries"], 2);
+        let cursor = lines[3]["next_cursor"].as_str().unwrap().to_string();
+
+        // The rest, uncompressed for a client that doesn't ask for gzip
+        let resp = cli
+            .get(format!(
+                "/api/audit/export?from={}&to={}&cursor={}",
+                from, to, cursor
+            ))
+            .header("authorization", "Bearer key-ops")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        assert!(resp.0.headers().get("content-encoding").is_none());
+        let text = resp.0.into_body().into_string().await.unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["cursor"], "2");
+        assert_eq!(lines[1]["id"], 3);
+        assert_eq!(lines[2]["type"], "trailer");
+        assert!(lines[2].get("next_cursor").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_audited_message_is_the_signed_one() {
+        let config = Config {
+            api_keys: vec![api_key("key-ops", "ops", true)],
+            audit_full_messages: true,
+            ..Config::default()
+        };
+        let cli = client_with(config);
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let (to, recent_block_hash) = (Keypair::new().pubkey(), Hash::new_unique());
+        let resp = cli
+            .post("/api/agg_send_step_two")
+            .body_json(&serde_json::json!({
+                "keypair": keys[0].to_base58_string(),
+                "lamports": 1_000,
+                "to": to.to_string(),
+                "memo": "invoice 8",
+                "recent_block_hash": recent_block_hash.to_string(),
+                "keys": pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+                "first_messages": [first_messages[1].serialize_bs58()],
+                "secret_state": secret_states[0].serialize_bs58(),
+            }))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let step_two: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+
+        cli.get(format!("/api/audit/{}/message", step_two.audit_id))
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let audited = |id: u64| {
+            cli.get(format!("/api/audit/{}/message", id))
+                .header("authorization", "Bearer key-ops")
+                .send()
+        };
+        let resp = audited(step_two.audit_id).await;
+        resp.assert_status_is_ok();
+        let entry: AuditMessageResponse = resp.json().await.value().deserialize();
+        assert_eq!(entry.endpoint, "agg_send_step_two");
+
+        // Compiled again from the request, apart from what step two signed
+        let aggpubkey = Pubkey::new(
+            &*key_agg(pubkeys, None)
+                .unwrap()
+                .agg_public_key
+                .to_bytes(true),
+        );
+        let message = transfer_message(
+            &TransferBuilder::new(aggpubkey, to, Lamports::new(1_000))
+                .memo(Some("invoice 8".to_string())),
+            recent_block_hash,
+        );
+        assert_eq!(entry.message_hash, Some(message_hash(&message)));
+        assert_eq!(entry.message, Some(encode_message(&message)));
+
+        audited(step_two.audit_id + 1)
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_signer_must_be_in_keys() {
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let cli = test_client();
+        let step_two = |path: &'static str, keypair: &Keypair| {
+            cli.post(path)
+                .body_json(&serde_json::json!({
+                    "keypair": keypair.to_base58_string(),
+                    "amount": 1.0,
+                    "to": Keypair::new().pubkey().to_string(),
+                    "token_mint": Keypair::new().pubkey().to_string(),
+                    "decimals": 6,
+                    "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+                    "keys": keys.iter().map(|k| k.pubkey().to_string()).collect::<Vec<_>>(),
+                    "first_messages": [first_messages[1].serialize_bs58()],
+                    "secret_state": secret_states[0].serialize_bs58(),
+                }))
+                .send()
+        };
+
+        let outsider = Keypair::new();
+        for path in ["/api/agg_send_step_two", "/api/spl_agg_send_step_two"] {
+            let resp = step_two(path, &outsider).await;
+            resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+            let json = resp.json().await;
+            json.value()
+                .object()
+                .get("error_code")
+                .assert_string("SIGNER_NOT_IN_KEY_SET");
+            assert!(
+                json.value()
+                    .object()
+                    .get("error")
+                    .string()
+                    .contains(&outsider.pubkey().to_string())
+            );
+        }
+
+        // The other party's keypair with this party's state is listed, so the check stays quiet
+        let resp = step_two("/api/agg_send_step_two", &keys[1]).await;
+        let json = resp.json().await;
+        assert_ne!(
+            json.value()
+                .object()
+                .get_opt("error_code")
+                .map(|c| c.string()),
+            Some("SIGNER_NOT_IN_KEY_SET")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_environment_mismatch() {
+        let client = |environment: &str| {
+            client_with(Config {
+                environment: Some(environment.to_string()),
+                ..Config::default()
+            })
+        };
+        let (staging, prod) = (client("staging"), client("prod"));
+        let keys: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let mut step_ones = Vec::new();
+        for key in &keys {
+            let resp = staging
+                .post("/api/agg_send_step_one")
+                .body_json(&serde_json::json!({ "keypair": key.to_base58_string() }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let body: serde_json::Value = resp.json().await.value().deserialize();
+            assert_eq!(body["environment"], "staging");
+            step_ones.push(serde_json::from_value::<AggSendStepOneResponse>(body).unwrap());
+        }
+        let transfer = serde_json::json!({
+            "amount": 1.0,
+            "to": Keypair::new().pubkey().to_string(),
+            "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+            "keys": keys.iter().map(|k| k.pubkey().to_string()).collect::<Vec<_>>(),
+        });
+        let step_two = |first_message: &str, secret_state: &str| {
+            let mut req = transfer.clone();
+            req["keypair"] = keys[0].to_base58_string().into();
+            req["first_messages"] = serde_json::json!([first_message]);
+            req["secret_state"] = secret_state.into();
+            req
+        };
+        let assert_mismatch = |json: serde_json::Value, field: &str, found: &str| {
+            assert_eq!(json["error_code"], "ENVIRONMENT_MISMATCH");
+            let error = json["error"].as_str().unwrap();
+            assert!(
+                error.starts_with(field) && error.contains(found),
+                "{}",
+                error
+            );
+        };
+
+        // Step one blobs of staging pasted into the production coordinator
+        let pasted = step_two(
+            &step_ones[1].message_1,
+            step_ones[0].secret_state.as_deref().unwrap(),
+        );
+        let resp = prod
+            .post("/api/agg_send_step_two")
+            .body_json(&pasted)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::CONFLICT);
+        let body: serde_json::Value = resp.json().await.value().deserialize();
+        assert_eq!(body["environment"], "prod");
+        assert_mismatch(body, "first_messages", "staging");
+
+        // As are blobs without a label
+        let (first_message, secret_state) = step_one(keys[1].insecure_clone());
+        let unlabeled = step_two(
+            &first_message.serialize_bs58(),
+            &secret_state.serialize_bs58(),
+        );
+        let resp = prod
+            .post("/api/agg_send_step_two")
+            .body_json(&unlabeled)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::CONFLICT);
+        assert_mismatch(
+            resp.json().await.value().deserialize(),
+            "first_messages",
+            "unlabeled",
+        );
+
+        // Within staging they sign, but the partial signature doesn't aggregate in production
+        let resp = staging
+            .post("/api/agg_send_step_two")
+            .body_json(&pasted)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let signed: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+        let mut aggregate = transfer.clone();
+        aggregate["net"] = "devnet".into();
+        aggregate["signatures"] =
+            serde_json::json!([signed.partial_signature, signed.partial_signature]);
+        let resp = prod
+            .post("/api/aggregate_signatures")
+            .body_json(&aggregate)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::CONFLICT);
+        assert_mismatch(
+            resp.json().await.value().deserialize(),
+            "signatures",
+            "staging",
+        );
+
+        // Without a label of its own a server takes any blob
+        let resp = test_client()
+            .post("/api/agg_send_step_two")
+            .body_json(&pasted)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let body: serde_json::Value = resp.json().await.value().deserialize();
+        assert!(body.get("environment").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_step_two_reports_aggregated_key() {
+        let cli = test_client();
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let pubkeys: Vec<Pubkey> = keys.iter().map(|k| k.pubkey()).collect();
+        let to = Keypair::new().pubkey().to_string();
+        let block_hash = solana_sdk::hash::Hash::new_unique().to_string();
+        let step_two = async |party: usize, keys_order: &[Pubkey]| {
+            let req = serde_json::json!({
+                "keypair": keys[party].to_base58_string(),
+                "amount": 1.0,
+                "to": to,
+                "recent_block_hash": block_hash,
+                "keys": keys_order.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+                "first_messages": [first_messages[1 - party].serialize_bs58()],
+                "secret_state": secret_states[party].serialize_bs58(),
+            });
+            let resp = cli
+                .post("/api/agg_send_step_two")
+                .body_json(&req)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            resp.json()
+                .await
+                .value()
+                .deserialize::<AggSendStepTwoResponse>()
+        };
+
+        let first = step_two(0, &pubkeys).await;
+        let aggpubkey = aggregated_pubkey(pubkeys.clone()).unwrap().to_string();
+        assert_eq!(first.context.aggregated_pubkey, aggpubkey);
+        assert_eq!(first.key_index, 0);
+        let second = step_two(1, &pubkeys).await;
+        assert_eq!(second.context.aggregated_pubkey, aggpubkey);
+        assert_eq!(second.key_index, 1);
+
+        // A party with the keys in another order would spend from another address
+        let reversed: Vec<Pubkey> = pubkeys.iter().rev().copied().collect();
+        let swapped = step_two(1, &reversed).await;
+        assert_ne!(swapped.context.aggregated_pubkey, aggpubkey);
+        assert_eq!(swapped.key_index, 0);
+    }
+
+    #[tokio::test]
+    async fn test_partial_signature_context() {
+        let cli = test_client();
+        let (keys, first_messages, secret_states) = ceremony(2);
+        let pubkeys: Vec<Pubkey> = keys.iter().map(|k| k.pubkey()).collect();
+        let transfer = serde_json::json!({
+            "amount": 1.0,
+            "to": Keypair::new().pubkey().to_string(),
+            "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+            "keys": pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+        });
+        let mut signed = Vec::new();
+        for (party, key) in keys.iter().enumerate() {
+            let mut req = transfer.clone();
+            req["keypair"] = key.to_base58_string().into();
+            req["first_messages"] = serde_json::json!([first_messages[1 - party].serialize_bs58()]);
+            req["secret_state"] = secret_states[party].serialize_bs58().into();
+            req["session_id"] = "ceremony-1".into();
+            let resp = cli
+                .post("/api/agg_send_step_two")
+                .body_json(&req)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            signed.push(
+                resp.json()
+                    .await
+                    .value()
+                    .deserialize::<AggSendStepTwoResponse>(),
+            );
+        }
+        let context = &signed[1].context;
+        assert_eq!(context.request_digest, signed[1].request_digest);
+        assert_eq!(
+            context.aggregated_pubkey,
+            aggregated_pubkey(pubkeys.clone()).unwrap().to_string()
+        );
+        assert_eq!(context.signer, pubkeys[1].to_string());
+        assert_eq!(context.environment, None);
+        assert_eq!(context.session_id.as_deref(), Some("ceremony-1"));
+
+        let submissions: Vec<serde_json::Value> = signed
+            .iter()
+            .map(|s| {
+                serde_json::json!({
+                    "partial_signature": s.partial_signature,
+                    "context": s.context,
+                })
+            })
+            .collect();
+        let aggregate = |edit: &dyn Fn(&mut serde_json::Value)| {
+            let mut req = transfer.clone();
+            req["net"] = "devnet".into();
+            req["signatures"] = submissions.clone().into();
+            edit(&mut req);
+            let cli = &cli;
+            async move {
+                let resp = cli
+                    .post("/api/aggregate_signatures")
+                    .body_json(&req)
+                    .send()
+                    .await;
+                resp.assert_status(StatusCode::CONFLICT);
+                let json: serde_json::Value = resp.json().await.value().deserialize();
+                assert_eq!(json["error_code"], "SIGNATURE_CONTEXT_MISMATCH");
+                json["details"].clone()
+            }
+        };
+
+        // The signatures are for 1 SOL, not 2
+        let details = aggregate(&|req| req["amount"] = 2.0.into()).await;
+        assert_eq!(details["submission"], 0);
+        assert_eq!(details["field"], "request_digest");
+        assert_eq!(details["found"], signed[0].request_digest.as_str());
+
+        // A signature of another ceremony
+        let details =
+            aggregate(&|req| req["signatures"][1]["context"]["session_id"] = "ceremony-2".into())
+                .await;
+        assert_eq!(details["submission"], 1);
+        assert_eq!(details["field"], "session_id");
+        assert_eq!(details["expected"], "ceremony-1");
+        assert_eq!(details["found"], "ceremony-2");
+
+        // The session named by the coordinator
+        let details = aggregate(&|req| req["session_id"] = "ceremony-2".into()).await;
+        assert_eq!(details["submission"], 0);
+        assert_eq!(details["field"], "session_id");
+
+        // The same party twice
+        let details = aggregate(&|req| req["signatures"][1] = req["signatures"][0].clone()).await;
+        assert_eq!(details["submission"], 1);
+        assert_eq!(details["field"], "signer");
+
+        // A context made for other keys
+        let details = aggregate(&|req| {
+            req["signatures"][0]["context"]["aggregated_pubkey"] =
+                Pubkey::new_unique().to_string().into()
+        })
+        .await;
+        assert_eq!(details["submission"], 0);
+        assert_eq!(details["field"], "aggregated_pubkey");
+    }
+
+    #[tokio::test]
+    async fn test_request_rpc_url() {
+        // Nothing listens on port 1, reads fail there instead of going to devnet
+        let local = "http://127.0.0.1:1";
+        let client = |allow_request_rpc_url| {
+            client_with(Config {
+                rpc_urls: std::collections::HashMap::from([(Network::Devnet, local.to_string())]),
+                allow_request_rpc_url,
+                request_rpc_url_hosts: vec![
+                    "127.0.0.1".to_string(),
+                    "my-provider.example".to_string(),
+                ],
+                ..Config::default()
+            })
+        };
+        let balance = |rpc_url: serde_json::Value| {
+            serde_json::json!({
+                "net": "devnet",
+                "address": Pubkey::new_unique().to_string(),
+                "rpc_url": rpc_url,
+            })
+        };
+
+        // Without the option the field is ignored, the configured node answers with its error
+        let resp = client(false)
+            .post("/api/balance")
+            .body_json(&balance("http://10.0.0.1/rpc".into()))
+            .send()
+            .await;
+        let json: serde_json::Value = resp.json().await.value().deserialize();
+        assert_ne!(json["error_code"], "RPC_URL_NOT_ALLOWED");
+        assert_ne!(json["error"], "the node of rpc_url failed");
+
+        let cli = client(true);
+        for url in [
+            serde_json::json!("ftp://127.0.0.1:1"),
+            serde_json::json!(8899),
+        ] {
+            let resp = cli
+                .post("/api/balance")
+                .body_json(&balance(url))
+                .send()
+                .await;
+            resp.assert_status(StatusCode::BAD_REQUEST);
+            let json: serde_json::Value = resp.json().await.value().deserialize();
+            assert_eq!(json["error_code"], "INVALID_RPC_URL");
+            assert_eq!(json["error_class"], "input");
+        }
+
+        let resp = cli
+            .post("/api/balance")
+            .body_json(&balance("http://10.0.0.1/rpc".into()))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::FORBIDDEN);
+        let json: serde_json::Value = resp.json().await.value().deserialize();
+        assert_eq!(json["error_code"], "RPC_URL_NOT_ALLOWED");
+
+        // The failure of a requested node doesn't tell what was or wasn't listening there
+        let resp = cli
+            .post("/api/balance")
+            .body_json(&balance(local.into()))
+            .send()
+            .await;
+        let json: serde_json::Value = resp.json().await.value().deserialize();
+        assert_eq!(json["error"], "the node of rpc_url failed");
+        assert!(json["details"].is_null());
+
+        let mut scheduled = balance("https://my-provider.example/rpc".into());
+        scheduled["broadcast_at"] = serde_json::json!({ "slot": 100 });
+        let resp = cli
+            .post("/api/aggregate_signatures")
+            .body_json(&scheduled)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let json: serde_json::Value = resp.json().await.value().deserialize();
+        assert!(json["error"].as_str().unwrap().contains("broadcast_at"));
+
+        let resp = cli.get("/api/capabilities").send().await;
+        let capabilities: CapabilitiesResponse = resp.json().await.value().deserialize();
+        assert!(
+            capabilities
+                .features
+                .contains(&"request_rpc_url".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_network() {
+        let client = |default_network| {
+            client_with(Config {
+                default_network,
+                ..Config::default()
+            })
+        };
+        // Without signatures the statuses need no RPC call
+        let statuses = |net: Option<&str>| {
+            let mut req = serde_json::json!({ "signatures": [] });
+            if let Some(net) = net {
+                req["net"] = net.into();
+            }
+            req
+        };
+
+        let cli = client(Some(Network::Devnet));
+        let resp = cli
+            .post("/api/transaction_statuses")
+            .body_json(&statuses(None))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header("X-Network", "devnet");
+        // A net the request sends wins over the default
+        let resp = cli
+            .post("/api/transaction_statuses")
+            .body_json(&statuses(Some("localnet")))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header("X-Network", "localnet");
+        let resp = cli.get("/api/capabilities").send().await;
+        let capabilities: CapabilitiesResponse = resp.json().await.value().deserialize();
+        assert_eq!(capabilities.default_network, Some(Network::Devnet));
+
+        let cli = client(None);
+        let resp = cli
+            .post("/api/transaction_statuses")
+            .body_json(&statuses(None))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(resp.0.headers().get("X-Network").is_none());
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("MISSING_NETWORK"));
+        let details = error.details.unwrap();
+        assert_eq!(details["field"], "net");
+        assert_eq!(
+            details["accepted"],
+            serde_json::json!(["mainnet", "testnet", "devnet", "localnet"])
+        );
+        let resp = cli
+            .post("/api/transaction_statuses")
+            .body_json(&statuses(Some("testnet")))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header("X-Network", "testnet");
+    }
+
+    #[tokio::test]
+    async fn test_writable_policy_refuses_to_sign() {
+        let to = Keypair::new().pubkey();
+        let guarded = client_with(Config {
+            writable_policy: WritablePolicy {
+                deny: vec![to.to_string()],
+                allow: None,
+            },
+            ..Config::default()
+        });
+        let cli = test_client();
+        let keys: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let transfer = serde_json::json!({
+            "lamports": 5000,
+            "to": to.to_string(),
+            "memo": "invoice 7",
+            "recent_block_hash": Hash::new_unique().to_string(),
+            "keys": keys.iter().map(|k| k.pubkey().to_string()).collect::<Vec<_>>(),
+        });
+
+        let resp = cli
+            .post("/api/build_message")
+            .body_json(&transfer)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let built: BuildMessageResponse = resp.json().await.value().deserialize();
+        let writable: Vec<_> = built
+            .account_keys
+            .iter()
+            .filter(|key| key.is_writable)
+            .map(|key| key.pubkey.as_str())
+            .collect();
+        assert_eq!(
+            writable,
+            [built.aggregated_pubkey.as_str(), to.to_string().as_str()]
+        );
+
+        let (first_message, secret_state) = step_one(keys[0].insecure_clone());
+        let mut step_two = transfer.clone();
+        step_two["keypair"] = keys[0].to_base58_string().into();
+        step_two["first_messages"] = serde_json::json!([first_message.serialize_bs58()]);
+        step_two["secret_state"] = secret_state.serialize_bs58().into();
+        step_two["include_account_keys"] = true.into();
+        let resp = guarded
+            .post("/api/agg_send_step_two")
+            .body_json(&step_two)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::FORBIDDEN);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("POLICY_VIOLATION"));
+        let details = error.details.unwrap();
+        assert_eq!(details["account"], to.to_string());
+        assert_eq!(details["rule"], "deny");
+
+        // Without the policy it signs, and reports the accounts of the message it signed
+        let resp = cli
+            .post("/api/agg_send_step_two")
+            .body_json(&step_two)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let signed: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+        assert_eq!(signed.account_keys, Some(built.account_keys));
+        step_two["include_account_keys"] = false.into();
+        let resp = cli
+            .post("/api/agg_send_step_two")
+            .body_json(&step_two)
+            .send()
+            .await;
+        let signed: AggSendStepTwoResponse = resp.json().await.value().deserialize();
+        assert_eq!(signed.account_keys, None);
+    }
+
+    #[tokio::test]
+    async fn test_payload_signature_for_ed25519_verify() {
+        let cli = test_client();
+        let keys: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey().to_string()).collect();
+        let step_two = |payload: &[u8]| {
+            let step_ones: Vec<_> = keys.iter().map(|k| step_one(k.insecure_clone())).collect();
+            keys.iter()
+                .enumerate()
+                .map(|(i, key)| {
+                    let first_messages: Vec<_> = step_ones
+                        .iter()
+                        .enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .map(|(_, (first_message, _))| first_message.serialize_bs58())
+                        .collect();
+                    serde_json::json!({
+                        "keypair": key.to_base58_string(),
+                        "payload": STANDARD.encode(payload),
+                        "keys": pubkeys,
+                        "first_messages": first_messages,
+                        "secret_state": step_ones[i].1.serialize_bs58(),
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let payload = b"claim 42";
+        let mut partial_signatures = Vec::new();
+        for body in step_two(payload) {
+            let resp = cli
+                .post("/api/agg_payload_step_two")
+                .body_json(&body)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let signed: AggPayloadStepTwoResponse = resp.json().await.value().deserialize();
+            partial_signatures.push(signed.partial_signature);
+        }
+        let resp = cli
+            .post("/api/aggregate_payload_signatures")
+            .body_json(&serde_json::json!({
+                "payload": STANDARD.encode(payload),
+                "keys": pubkeys,
+                "signatures": partial_signatures,
+            }))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let aggregated: AggregatePayloadSignaturesResponse =
+            resp.json().await.value().deserialize();
+        let aggpubkey: Pubkey = aggregated.aggregated_public_key.parse().unwrap();
+        let signature: Signature = aggregated.signature.parse().unwrap();
+        assert!(signature.verify(aggpubkey.as_ref(), payload));
+
+        // Refused before anything is sent when the instruction needs another signature
+        let resp = cli
+            .post("/api/ed25519_verify_send")
+            .body_json(&serde_json::json!({
+                "net": "devnet",
+                "payload": STANDARD.encode(payload),
+                "keys": pubkeys,
+                "signatures": partial_signatures,
+                "instruction": {
+                    "program_id": Pubkey::new_unique().to_string(),
+                    "accounts": [
+                        { "pubkey": aggpubkey.to_string(), "is_signer": true, "is_writable": false },
+                    ],
+                    "data": STANDARD.encode([1, 2, 3]),
+                },
+                "fee_payer_keypair": Keypair::new().to_base58_string(),
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("UNEXPECTED_SIGNER"));
+
+        // A transaction of the aggregated key isn't signed as a payload
+        let transfer = transfer_message(
+            &TransferBuilder::new(aggpubkey, Pubkey::new_unique(), Lamports::new(1)),
+            Hash::new_unique(),
+        );
+        let resp = cli
+            .post("/api/agg_payload_step_two")
+            .body_json(&step_two(&transfer.serialize())[0])
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("PAYLOAD_IS_MESSAGE"));
+    }
+
+    #[tokio::test]
+    async fn test_spl_cost_preview_prices_the_built_message() {
+        use solana_sdk::{program_pack::Pack, rent::Rent};
+
+        let cli = test_client();
+        let keys: Vec<String> = (0..2)
+            .map(|_| Keypair::new().pubkey().to_string())
+            .collect();
+        let sponsor = Keypair::new().pubkey();
+        let (to, other) = (Keypair::new().pubkey(), Keypair::new().pubkey());
+        let transfer = serde_json::json!({
+            "amount": 2.5,
+            "to": to.to_string(),
+            "token_mint": Keypair::new().pubkey().to_string(),
+            "decimals": 6,
+            "memo": "invoice 12",
+            "rent_sponsor": sponsor.to_string(),
+            "compute_unit_limit": 40_000,
+            "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+            "keys": keys,
+        });
+        let resp = cli
+            .post("/api/spl_build_message")
+            .body_json(&transfer)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let built: BuildMessageResponse = resp.json().await.value().deserialize();
+
+        let mut preview = transfer.clone();
+        preview["recipients"] = serde_json::json!([{ "to": other.to_string(), "amount": 1 }]);
+        let resp = cli
+            .post("/api/spl_cost_preview")
+            .body_json(&preview)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let preview: SplCostPreviewResponse = resp.json().await.value().deserialize();
+        assert_eq!(preview.transfers[0].message_hash, built.message_hash);
+        assert_eq!(preview.raw_amount, 3_500_000);
+
+        // The aggregated key pays both signatures of each transfer, the sponsor both ATAs
+        let rent = Rent::default().minimum_balance(spl_token::state::Account::LEN);
+        let debit = |account: &str| {
+            preview
+                .debits
+                .iter()
+                .find(|debit| debit.account == account)
+                .map(|debit| debit.lamports)
+        };
+        assert_eq!(debit(&built.aggregated_pubkey), Some(2 * 10_000));
+        assert_eq!(debit(&sponsor.to_string()), Some(2 * rent));
+        assert_eq!(preview.total_lamports, 20_000 + 2 * rent);
+        for transfer in &preview.transfers {
+            assert_eq!(transfer.fee_payer, built.aggregated_pubkey);
+            assert_eq!(transfer.rent[0].payer, sponsor.to_string());
+            assert_eq!(transfer.recipient_ata_exists, None);
+        }
+
+        // A single-key transfer without a sponsor pays its own rent and one signature
+        let mut single = transfer;
+        single.as_object_mut().unwrap().remove("keys");
+        single.as_object_mut().unwrap().remove("rent_sponsor");
+        let owner = Keypair::new().pubkey().to_string();
+        single["owner"] = owner.clone().into();
+        let resp = cli
+            .post("/api/spl_cost_preview")
+            .body_json(&single)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let preview: SplCostPreviewResponse = resp.json().await.value().deserialize();
+        assert_eq!(preview.debits.len(), 1);
+        assert_eq!(preview.debits[0].account, owner);
+        assert_eq!(preview.total_lamports, 5_000 + rent);
+
+        // Recipients are capped as they are for a send
+        let mut crowded = single.clone();
+        crowded["recipients"] = (0..=MAX_TRANSFER_RECIPIENTS)
+            .map(|_| serde_json::json!({ "to": other.to_string(), "amount": 1 }))
+            .collect();
+        let resp = cli
+            .post("/api/spl_cost_preview")
+            .body_json(&crowded)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("TOO_MANY_RECIPIENTS"));
+    }
+
+    fn api_key(key: &str, tenant: &str, admin: bool) -> ApiKeyConfig {
+        ApiKeyConfig {
+            sha256: Sha256::digest(key.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            tenant: tenant.to_string(),
+            admin,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strict_amounts_refuse_floats() {
+        let client = |strict_amounts| {
+            client_with(Config {
+                strict_amounts,
+                ..Config::default()
+            })
+        };
+        let (strict, lenient) = (client(true), client(false));
+        let keys: Vec<String> = (0..2)
+            .map(|_| Keypair::new().pubkey().to_string())
+            .collect();
+        let transfer = |amount: serde_json::Value| {
+            serde_json::json!({
+                "amount": amount,
+                "to": Keypair::new().pubkey().to_string(),
+                "token_mint": "So11111111111111111111111111111111111111112",
+                "decimals": 9,
+                "recent_block_hash": "11111111111111111111111111111111",
+                "keys": keys,
+            })
+        };
+        async fn built_hash(resp: poem::test::TestResponse) -> String {
+            resp.assert_status_is_ok();
+            let built: BuildMessageResponse = resp.json().await.value().deserialize();
+            built.message_hash
+        }
+
+        let float = transfer(2.5.into());
+        for (path, body) in [
+            ("/api/build_message", float.clone()),
+            ("/api/spl_build_message", float.clone()),
+        ] {
+            let resp = strict.post(path).body_json(&body).send().await;
+            resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+            let error: ErrorResponse = resp.json().await.value().deserialize();
+            assert_eq!(error.error_code.as_deref(), Some("FLOAT_AMOUNT"));
+            assert!(error.error.contains("\"2.5\""), "{}", error.error);
+        }
+        let mut nested = transfer("1".into());
+        nested["recipients"] = serde_json::json!([{ "to": keys[0], "amount": 1.0 }]);
+        let resp = strict
+            .post("/api/spl_cost_preview")
+            .body_json(&nested)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert!(
+            error.error.starts_with("recipients[0].amount "),
+            "{}",
+            error.error
+        );
+
+        // Strings and integers are the same amount as the float the default server accepts
+        let mut as_string = float.clone();
+        as_string["amount"] = "2.5".into();
+        let strict_hash = built_hash(
+            strict
+                .post("/api/spl_build_message")
+                .body_json(&as_string)
+                .send()
+                .await,
         )
-        .at("/api/stake", post(stake_account))
-        .at("/api/deactivate_stake", post(deactivate_stake))
-        .at("/api/withdraw_stake", post(withdraw_stake))
-        .at("/api/agg_stake_step_two", post(agg_stake_step_two))
-        .at(
-            "/api/agg_deactivate_stake_step_two",
-            post(agg_deactivate_stake_step_two),
+        .await;
+        let lenient_hash = built_hash(
+            lenient
+                .post("/api/spl_build_message")
+                .body_json(&float)
+                .send()
+                .await,
         )
-        .at(
-            "/api/agg_withdraw_stake_step_two",
-            post(agg_withdraw_stake_step_two),
+        .await;
+        assert_eq!(strict_hash, lenient_hash);
+        let mut whole = float.clone();
+        whole["amount"] = 2.into();
+        let strict_hash = built_hash(
+            strict
+                .post("/api/build_message")
+                .body_json(&whole)
+                .send()
+                .await,
         )
-        .at(
-            "/api/aggregate_stake_signatures",
-            post(aggregate_stake_signatures),
+        .await;
+        whole["amount"] = 2.0.into();
+        let lenient_hash = built_hash(
+            lenient
+                .post("/api/build_message")
+                .body_json(&whole)
+                .send()
+                .await,
         )
-        .at(
-            "/api/aggregate_deactivate_stake_signatures",
-            post(aggregate_deactivate_stake_signatures),
+        .await;
+        assert_eq!(strict_hash, lenient_hash);
+
+        for (cli, formats) in [
+            (&strict, vec!["decimal_string", "integer"]),
+            (&lenient, vec!["decimal_string", "integer", "float"]),
+        ] {
+            let resp = cli.get("/api/capabilities").send().await;
+            let capabilities: CapabilitiesResponse = resp.json().await.value().deserialize();
+            assert_eq!(capabilities.limits.amount_formats, formats);
+            assert_eq!(
+                capabilities
+                    .features
+                    .contains(&"strict_amounts".to_string()),
+                formats.len() == 2
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exact_base_unit_amounts() {
+        let cli = test_client();
+        let keys: Vec<String> = (0..2)
+            .map(|_| Keypair::new().pubkey().to_string())
+            .collect();
+        let to = Keypair::new().pubkey().to_string();
+        let transfer = |amount: serde_json::Value| {
+            let mut body = serde_json::json!({
+                "to": to,
+                "token_mint": "So11111111111111111111111111111111111111112",
+                "decimals": 9,
+                "recent_block_hash": "11111111111111111111111111111111",
+                "keys": keys,
+            });
+            body.as_object_mut()
+                .unwrap()
+                .extend(amount.as_object().unwrap().clone());
+            body
+        };
+        async fn built(resp: poem::test::TestResponse) -> String {
+            resp.assert_status_is_ok();
+            let built: BuildMessageResponse = resp.json().await.value().deserialize();
+            built.message_hash
+        }
+
+        // The integer builds the same message as the UI amount, alone or next to it
+        for (path, exact) in [
+            ("/api/build_message", "amount_lamports"),
+            ("/api/spl_build_message", "amount_base_units"),
+        ] {
+            let ui = transfer(serde_json::json!({ "amount": 0.1 }));
+            let expected = built(cli.post(path).body_json(&ui).send().await).await;
+            for amount in [
+                serde_json::json!({ exact: 100_000_000 }),
+                serde_json::json!({ "amount": "0.1", exact: 100_000_000 }),
+            ] {
+                let body = transfer(amount);
+                let hash = built(cli.post(path).body_json(&body).send().await).await;
+                assert_eq!(hash, expected, "{} {}", path, body);
+            }
+
+            let conflicting = transfer(serde_json::json!({ "amount": 0.1, exact: 100_000_001 }));
+            let resp = cli.post(path).body_json(&conflicting).send().await;
+            resp.assert_status(StatusCode::BAD_REQUEST);
+            let error: ErrorResponse = resp.json().await.value().deserialize();
+            assert_eq!(error.error_code.as_deref(), Some("INVALID_AMOUNT"));
+            assert!(
+                error.error.contains("conflicts with amount 0.1"),
+                "{}",
+                error.error
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_stats_and_spend_limits_are_admin_only() {
+        let config = Config {
+            api_keys: vec![
+                api_key("key-a", "team-a", false),
+                api_key("key-ops", "ops", true),
+            ],
+            ..Config::default()
+        };
+        let cli = client_with(config);
+        for (path, key, status, code) in [
+            (
+                "/api/state_stats",
+                "key-a",
+                StatusCode::FORBIDDEN,
+                "ADMIN_ONLY",
+            ),
+            (
+                "/api/state_stats",
+                "other",
+                StatusCode::UNAUTHORIZED,
+                "UNAUTHORIZED",
+            ),
+            (
+                "/api/spend_limits",
+                "key-a",
+                StatusCode::FORBIDDEN,
+                "ADMIN_ONLY",
+            ),
+        ] {
+            let resp = cli
+                .get(path)
+                .header("authorization", format!("Bearer {}", key))
+                .send()
+                .await;
+            resp.assert_status(status);
+            resp.json()
+                .await
+                .value()
+                .object()
+                .get("error_code")
+                .assert_string(code);
+        }
+        let resp = cli
+            .get("/api/state_stats")
+            .header("authorization", "Bearer key-ops")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let stats: StateStatsResponse = resp.json().await.value().deserialize();
+        assert_eq!(stats.stores[0].name, "audit_log");
+        assert_eq!(
+            stats.stores[0].capacity,
+            Config::default().state_limits.audit_log.capacity
+        );
+        assert!(stats.stores.iter().any(|store| store.name == "rpc_clients"));
+        let resp = cli
+            .get("/api/spend_limits")
+            .header("authorization", "Bearer key-ops")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+    }
+
+    #[tokio::test]
+    async fn test_token_registry_writes_are_admin_only() {
+        let mint = Keypair::new().pubkey().to_string();
+        let config = Config {
+            api_keys: vec![
+                api_key("key-a", "team-a", false),
+                api_key("key-ops", "ops", true),
+            ],
+            tokens: HashMap::from([(
+                "TOK".to_string(),
+                TokenConfig {
+                    mint: mint.clone(),
+                    decimals: 6,
+                    net: Network::Devnet,
+                    max_per_transaction: None,
+                    allow_create_ata: true,
+                },
+            )]),
+            ..Config::default()
+        };
+        let cli = client_with(config);
+        let entry = serde_json::json!({
+            "symbol": "TOK",
+            "mint": mint,
+            "decimals": 6,
+            "net": "devnet",
+            "max_per_transaction": 1_000_000.0,
+        });
+        for (key, status, code) in [
+            (None, StatusCode::UNAUTHORIZED, "UNAUTHORIZED"),
+            (Some("key-a"), StatusCode::FORBIDDEN, "ADMIN_ONLY"),
+            (Some("other"), StatusCode::UNAUTHORIZED, "UNAUTHORIZED"),
+        ] {
+            for (path, body) in [
+                ("/api/tokens", entry.clone()),
+                ("/api/delete_token", serde_json::json!({ "symbol": "TOK" })),
+            ] {
+                let req = cli.post(path).body_json(&body);
+                let req = match key {
+                    Some(key) => req.header("authorization", format!("Bearer {}", key)),
+                    None => req,
+                };
+                let resp = req.send().await;
+                resp.assert_status(status);
+                resp.json()
+                    .await
+                    .value()
+                    .object()
+                    .get("error_code")
+                    .assert_string(code);
+            }
+        }
+        // Reading stays open, the refused writes changed nothing
+        let resp = cli.get("/api/tokens").send().await;
+        resp.assert_status_is_ok();
+        let tokens: TokensResponse = resp.json().await.value().deserialize();
+        assert_eq!(tokens.tokens.len(), 1);
+        assert_eq!(tokens.tokens[0].max_per_transaction, None);
+
+        let resp = cli
+            .post("/api/delete_token")
+            .header("authorization", "Bearer key-ops")
+            .body_json(&serde_json::json!({ "symbol": "TOK" }))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+    }
+
+    #[tokio::test]
+    async fn test_deleted_tokens_can_be_restored_or_purged() {
+        let mint = Keypair::new().pubkey().to_string();
+        let config = Config {
+            api_keys: vec![api_key("key-ops", "ops", true)],
+            tokens: HashMap::from([(
+                "TOK".to_string(),
+                TokenConfig {
+                    mint: mint.clone(),
+                    decimals: 6,
+                    net: Network::Devnet,
+                    max_per_transaction: None,
+                    allow_create_ata: true,
+                },
+            )]),
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config).unwrap());
+        let cli = TestClient::new(build_app(state.clone()));
+        let call = |path: &'static str, body: serde_json::Value| {
+            cli.post(path)
+                .header("authorization", "Bearer key-ops")
+                .body_json(&body)
+                .send()
+        };
+        let tok = serde_json::json!({ "symbol": "TOK" });
+        let purge = serde_json::json!({ "symbol": "TOK", "purge": true });
+
+        // Only a deleted token can be purged
+        let resp = call("/api/delete_token", purge.clone()).await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        call("/api/delete_token", tok.clone())
+            .await
+            .assert_status_is_ok();
+
+        // Signing with it names the deletion, the listing keeps it aside
+        let resp = cli
+            .post("/api/spl_build_message")
+            .body_json(&serde_json::json!({
+                "amount": 1,
+                "to": Keypair::new().pubkey().to_string(),
+                "token": "TOK",
+                "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+                "keys": [Keypair::new().pubkey().to_string()],
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        resp.json()
+            .await
+            .value()
+            .object()
+            .get("error_code")
+            .assert_string("TOKEN_DELETED");
+        let resp = cli.get("/api/tokens").send().await;
+        let tokens: TokensResponse = resp.json().await.value().deserialize();
+        assert!(tokens.tokens.is_empty());
+        assert_eq!(tokens.deleted[0].token.mint, mint);
+
+        call("/api/restore_token", tok.clone())
+            .await
+            .assert_status_is_ok();
+        let resp = cli.get("/api/tokens").send().await;
+        let tokens: TokensResponse = resp.json().await.value().deserialize();
+        assert_eq!((tokens.tokens.len(), tokens.deleted.len()), (1, 0));
+
+        call("/api/delete_token", tok.clone())
+            .await
+            .assert_status_is_ok();
+        call("/api/delete_token", purge).await.assert_status_is_ok();
+        let resp = call("/api/restore_token", tok).await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+        let entries = state.audit.page(0, u64::MAX, 0, 10);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.outcome, e.token.as_deref(), e.tenant.as_deref()))
+                .collect::<Vec<_>>(),
+            [
+                (Outcome::Deleted, Some("TOK"), Some("ops")),
+                (Outcome::Restored, Some("TOK"), Some("ops")),
+                (Outcome::Deleted, Some("TOK"), Some("ops")),
+                (Outcome::Purged, Some("TOK"), Some("ops")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() {
+        let client = |rate_limits| {
+            client_with(Config {
+                rate_limits,
+                ..Config::default()
+            })
+        };
+        let limits = RateLimitConfig {
+            rpc: BucketConfig {
+                burst: 2,
+                per_second: 0.01,
+            },
+            ..RateLimitConfig::default()
+        };
+        let cli = client(Some(limits));
+        // Counted whether or not the request gets far enough to call the node
+        for _ in 0..2 {
+            let resp = cli
+                .post("/api/balance")
+                .body_json(&serde_json::json!({}))
+                .send()
+                .await;
+            assert_ne!(resp.0.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        let resp = cli
+            .post("/api/airdrop")
+            .body_json(&serde_json::json!({}))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        resp.assert_header(header::RETRY_AFTER, "100");
+        let error: ErrorResponse = resp.json().await.value().deserialize();
+        assert_eq!(error.error_code.as_deref(), Some("RATE_LIMITED"));
+        assert_eq!(error.error_class, "rate_limited");
+        assert!(error.retryable);
+        assert_eq!(
+            error.details,
+            Some(serde_json::json!({ "retry_after_seconds": 100 }))
+        );
+        // Local endpoints have a bucket of their own
+        cli.get("/api/generate").send().await.assert_status_is_ok();
+
+        let cli = client(None);
+        for _ in 0..3 {
+            let resp = cli
+                .post("/api/balance")
+                .body_json(&serde_json::json!({}))
+                .send()
+                .await;
+            assert_ne!(resp.0.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_are_required() {
+        let source = ConfigSource {
+            access_keys: vec![tenant::key_hash("plain")],
+            ..ConfigSource::default()
+        };
+        let config = Config {
+            api_keys: vec![api_key("key-ops", "ops", true)],
+            ..source.load().unwrap()
+        };
+        assert!(config.require_api_key);
+        let cli = client_with(config);
+
+        cli.get("/api/health").send().await.assert_status_is_ok();
+        cli.get("/ui").send().await.assert_status_is_ok();
+        for (header, key, message) in [
+            (None, "", "is required"),
+            (Some("authorization"), "Bearer other", "isn't known"),
+            (Some("x-api-key"), "", "is required"),
+        ] {
+            let mut req = cli.get("/api/generate");
+            if let Some(header) = header {
+                req = req.header(header, key);
+            }
+            let resp = req.send().await;
+            resp.assert_status(StatusCode::UNAUTHORIZED);
+            resp.assert_header(header::WWW_AUTHENTICATE, "Bearer");
+            let error: ErrorResponse = resp.json().await.value().deserialize();
+            assert_eq!(error.error_code.as_deref(), Some("UNAUTHORIZED"));
+            assert!(error.error.contains(message), "{}", error.error);
+        }
+
+        for (header, key) in [("authorization", "Bearer plain"), ("x-api-key", "plain")] {
+            cli.get("/api/generate")
+                .header(header, key)
+                .send()
+                .await
+                .assert_status_is_ok();
+        }
+        // Keys of `api_keys` keep their tenant, and are accepted in either header
+        cli.get("/api/state_stats")
+            .header("x-api-key", "key-ops")
+            .send()
+            .await
+            .assert_status_is_ok();
+        cli.get("/api/state_stats")
+            .header("x-api-key", "plain")
+            .send()
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        // The key is checked before the WebSocket upgrade
+        cli.get("/ws/confirmations")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        cli.get("/ws/confirmations")
+            .header("x-api-key", "plain")
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_usage_stats_count_request_fields() {
+        let client = |usage_stats| {
+            client_with(Config {
+                api_keys: vec![api_key("key-ops", "ops", true)],
+                usage_stats,
+                ..Config::default()
+            })
+        };
+        let body = serde_json::json!({
+            "lamports": 1_000,
+            "to": Keypair::new().pubkey().to_string(),
+            "recentBlockHash": Hash::new_unique().to_string(),
+            "keys": [Keypair::new().pubkey().to_string(), Keypair::new().pubkey().to_string()],
+        });
+        for usage_stats in [true, false] {
+            let cli = client(usage_stats);
+            for _ in 0..2 {
+                let resp = cli.post("/api/build_message").body_json(&body).send().await;
+                resp.assert_status_is_ok();
+            }
+            let resp = cli
+                .get("/api/admin/usage_stats")
+                .header("authorization", "Bearer key-ops")
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let stats: UsageStatsResponse = resp.json().await.value().deserialize();
+            assert_eq!(stats.enabled, usage_stats);
+            let count = |field: &str| {
+                stats
+                    .fields
+                    .iter()
+                    .find(|usage| usage.field == field && usage.endpoint == "/api/build_message")
+                    .map(|usage| (usage.representation.as_str(), usage.count))
+            };
+            match usage_stats {
+                true => {
+                    assert_eq!(count("lamports"), Some(("integer", 2)));
+                    assert_eq!(count("keys"), Some(("array", 2)));
+                    assert_eq!(count("amount"), None);
+                    let resp = cli.get("/metrics").send().await;
+                    let metrics = resp.0.into_body().into_string().await.unwrap();
+                    assert!(metrics.contains(
+                        "tss_api_request_fields_total{endpoint=\"/api/build_message\",\
+                         field=\"lamports\",representation=\"integer\"} 2\n"
+                    ));
+                }
+                false => assert!(stats.fields.is_empty()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_reload() {
+        let path =
+            std::env::temp_dir().join(format!("solana-tss-reload-{}.json", std::process::id()));
+        let admin = api_key("key-ops", "ops", true).sha256;
+        let write = |lamports: u64, max_parties: usize, devnet: &str| {
+            let config = serde_json::json!({
+                "startup_ping": false,
+                "max_parties": max_parties,
+                "rpc_urls": { "devnet": devnet },
+                "api_keys": [{ "sha256": admin, "tenant": "ops", "admin": true }],
+                "spend_limits": { "global": { "lamports": lamports } },
+            });
+            std::fs::write(&path, config.to_string()).unwrap();
+        };
+        write(1_000, 16, "https://api.devnet.solana.com");
+        let source = ConfigSource {
+            path: Some(path.clone()),
+            ..ConfigSource::default()
+        };
+        let state = AppState::new(source.load().unwrap()).unwrap();
+        let state = Arc::new(state.config_source(source));
+        let cli = TestClient::new(build_app(state.clone()));
+        let reload = || {
+            cli.post("/api/admin/reload_config")
+                .header("authorization", "Bearer key-ops")
+                .send()
+        };
+        let max_parties = || async {
+            let resp = cli.get("/api/capabilities").send().await;
+            let capabilities: CapabilitiesResponse = resp.json().await.value().deserialize();
+            capabilities.limits.max_parties
+        };
+
+        let resp = cli.post("/api/admin/reload_config").send().await;
+        resp.assert_status(StatusCode::UNAUTHORIZED);
+
+        // A transfer booked under the old limits is still in flight when they change
+        let old = state.snapshot();
+        let in_flight = old
+            .spend_guard
+            .as_ref()
+            .unwrap()
+            .reserve(None, Asset::Sol, 300, Instant::now())
+            .unwrap();
+        write(400, 8, "https://api.devnet.solana.com");
+        let resp = reload().await;
+        resp.assert_status_is_ok();
+        let reloaded: ReloadConfigResponse = resp.json().await.value().deserialize();
+        assert!(reloaded.restart_required.is_empty());
+        assert_eq!(max_parties().await, 8);
+        // A request that loaded the old snapshot keeps seeing all of it
+        assert_eq!(old.config.max_parties, 16);
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.config.max_parties, 8);
+        let guard = snapshot.spend_guard.as_ref().unwrap();
+        let usage = guard.consumption(Instant::now());
+        assert_eq!(usage.global[0].spent, 300);
+        assert_eq!(usage.global[0].limit, Some(400));
+        assert!(
+            guard
+                .reserve(None, Asset::Sol, 101, Instant::now())
+                .is_err()
+        );
+        guard.release(in_flight);
+
+        // A config failing the startup checks leaves the running one alone
+        write(50, 4, "ftp://example.com");
+        let resp = reload().await;
+        resp.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let json = resp.json().await;
+        let error = json.value().object();
+        error
+            .get("error_code")
+            .assert_string("CONFIG_RELOAD_FAILED");
+        assert!(error.get("error").string().contains("rpc_urls.devnet"));
+        assert_eq!(max_parties().await, 8);
+        assert_eq!(
+            state.snapshot().config.cluster_url(Network::Devnet),
+            "https://api.devnet.solana.com"
+        );
+        assert_eq!(guard.consumption(Instant::now()).global[0].limit, Some(400));
+
+        // Sections set up at startup keep their values until a restart
+        std::fs::write(
+            &path,
+            serde_json::json!({ "startup_ping": false, "bind": "127.0.0.1:9000" }).to_string(),
         )
-        .at(
-            "/api/aggregate_withdraw_stake_signatures",
-            post(aggregate_withdraw_stake_signatures),
+        .unwrap();
+        let reloaded: ReloadConfigResponse = state.reload().await.unwrap();
+        assert_eq!(reloaded.restart_required, ["bind", "spend_limits"]);
+        assert_eq!(state.snapshot().config.bind, Config::default().bind);
+        // The admin key is gone with the new config
+        let resp = reload().await;
+        resp.assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_get_responses_are_stable() {
+        let mints: Vec<_> = (0..8)
+            .map(|_| Keypair::new().pubkey().to_string())
+            .collect();
+        let token = |mint: &String| TokenConfig {
+            mint: mint.clone(),
+            decimals: 6,
+            net: Network::Devnet,
+            max_per_transaction: None,
+            allow_create_ata: false,
+        };
+        // The same tokens given in opposite orders
+        let clients: Vec<_> = [false, true]
+            .into_iter()
+            .map(|reverse| {
+                let mut order: Vec<_> = mints.iter().enumerate().collect();
+                if reverse {
+                    order.reverse();
+                }
+                let config = Config {
+                    tokens: order
+                        .into_iter()
+                        .map(|(i, mint)| (format!("TOK{}", i), token(mint)))
+                        .collect(),
+                    ..Config::default()
+                };
+                let state = Arc::new(AppState::new(config).unwrap());
+                (TestClient::new(build_app(state.clone())), state)
+            })
+            .collect();
+        let mut bodies = Vec::new();
+        for (cli, _) in &clients {
+            let resp = cli.get("/api/tokens").send().await;
+            resp.assert_status_is_ok();
+            bodies.push(resp.0.into_body().into_bytes().await.unwrap());
+        }
+        assert_eq!(bodies[0], bodies[1]);
+
+        let (cli, state) = &clients[0];
+        let etag = || async {
+            let resp = cli.get("/api/tokens").send().await;
+            resp.0.headers()[header::ETAG].to_str().unwrap().to_string()
+        };
+        let first = etag().await;
+        assert_eq!(etag().await, first);
+        let resp = cli
+            .get("/api/tokens")
+            .header(header::IF_NONE_MATCH, &first)
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.0.headers()[header::ETAG], first.as_str());
+        assert!(resp.0.into_body().into_bytes().await.unwrap().is_empty());
+
+        // A change to the list is a new ETag
+        let mint = Keypair::new().pubkey().to_string();
+        state
+            .snapshot()
+            .tokens
+            .upsert("NEW", &token(&mint))
+            .unwrap();
+        assert_ne!(etag().await, first);
+        let resp = cli
+            .get("/api/tokens")
+            .header(header::IF_NONE_MATCH, &first)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+
+        // Only for reads
+        let resp = cli.post("/api/aggregate_keys").send().await;
+        assert!(!resp.0.headers().contains_key(header::ETAG));
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_clock_skew() {
+        use solana_tss_api_backend::clock::{ClockCheckConfig, SkewReading};
+
+        let config = Config {
+            clock_check: Some(ClockCheckConfig::default()),
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config).unwrap());
+        let cli = TestClient::new(build_app(state.clone()));
+        let health = || async {
+            let resp = cli.get("/api/health").send().await;
+            resp.assert_status_is_ok();
+            resp.json().await.value().deserialize::<HealthResponse>()
+        };
+        let unchecked = health().await;
+        assert_eq!(unchecked.status, HealthStatus::Ok);
+        assert_eq!(unchecked.clock_skew_ms, None);
+
+        for (skew_ms, status) in [(-800, HealthStatus::Ok), (90_000, HealthStatus::Degraded)] {
+            state.clock.record(SkewReading { skew_ms, at: 1 });
+            let checked = health().await;
+            assert_eq!(checked.status, status);
+            assert_eq!(checked.clock_skew_ms, Some(skew_ms));
+            assert_eq!(checked.clock_skewed, status == HealthStatus::Degraded);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_failures_carry_the_clock_skew() {
+        use solana_tss_api_backend::clock::SkewReading;
+
+        let config = Config {
+            api_keys: vec![api_key("key-a", "a", false)],
+            require_api_key: true,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config).unwrap());
+        let cli = TestClient::new(build_app(state.clone()));
+        let details = || async {
+            let resp = cli
+                .get("/api/generate")
+                .header("x-api-key", "other")
+                .send()
+                .await;
+            resp.assert_status(StatusCode::UNAUTHORIZED);
+            resp.json()
+                .await
+                .value()
+                .deserialize::<ErrorResponse>()
+                .details
+        };
+        assert_eq!(details().await, None);
+
+        state.clock.record(SkewReading {
+            skew_ms: -90_000,
+            at: 1,
+        });
+        assert_eq!(
+            details().await,
+            Some(serde_json::json!({ "clock_skew_ms": -90_000 }))
         );
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_networks() {
+        let config = Config::default();
+        let state = Arc::new(AppState::new(config.clone()).unwrap());
+        // The probes go to mock nodes instead of the public clusters
+        for net in [Network::Mainnet, Network::Testnet, Network::Devnet] {
+            state
+                .rpc_clients
+                .configured(net, config.cluster_url(net), || {
+                    RpcClient::new_mock("succeeds".to_string())
+                });
+        }
+        let cli = TestClient::new(build_app(state.clone()));
+        let resp = cli.get("/api/health").send().await;
+        resp.assert_status_is_ok();
+        let health: HealthResponse = resp.json().await.value().deserialize();
+        assert!(health.networks.is_empty());
+
+        let resp = cli.get("/api/health").query("rpc", &true).send().await;
+        resp.assert_status_is_ok();
+        let health: HealthResponse = resp.json().await.value().deserialize();
+        let nets: Vec<_> = health.networks.iter().map(|net| net.net).collect();
+        assert_eq!(nets, [Network::Mainnet, Network::Testnet, Network::Devnet]);
+        assert!(health.networks.iter().all(|net| net.reachable));
+        assert!(health.networks.iter().all(|net| net.latency_ms.is_some()));
+        assert_eq!(health.status, HealthStatus::Ok);
+    }
 
-    Server::new(TcpListener::bind("127.0.0.1:8000"))
-        .run(app)
-        .await?;
+    #[tokio::test]
+    async fn test_health_without_nodes() {
+        let config = Config::default();
+        let state = Arc::new(AppState::new(config.clone()).unwrap());
+        let answer = |net: Network, mock: &str| {
+            state
+                .rpc_clients
+                .configured(net, config.cluster_url(net), || {
+                    RpcClient::new_mock(mock.to_string())
+                });
+        };
+        answer(Network::Mainnet, "succeeds");
+        answer(Network::Testnet, "fails");
+        answer(Network::Devnet, "fails");
+        let cli = TestClient::new(build_app(state.clone()));
+
+        // Unreachable nodes are reported while the others still serve
+        let resp = cli.get("/api/health").query("rpc", &true).send().await;
+        resp.assert_status_is_ok();
+        let health: HealthResponse = resp.json().await.value().deserialize();
+        assert_eq!(health.status, HealthStatus::Degraded);
+        for net in &health.networks {
+            assert_eq!(net.reachable, net.net == Network::Mainnet);
+            assert_eq!(net.reachable, net.error.is_none());
+        }
 
-    Ok(())
+        // Once no node answers there is nothing left to serve
+        let state = Arc::new(AppState::new(config.clone()).unwrap());
+        for net in [Network::Mainnet, Network::Testnet, Network::Devnet] {
+            state
+                .rpc_clients
+                .configured(net, config.cluster_url(net), || {
+                    RpcClient::new_mock("fails".to_string())
+                });
+        }
+        let cli = TestClient::new(build_app(state));
+        let resp = cli.get("/api/health").query("rpc", &true).send().await;
+        resp.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        let health: HealthResponse = resp.json().await.value().deserialize();
+        assert_eq!(health.status, HealthStatus::Unavailable);
+        assert!(health.networks.iter().all(|net| !net.reachable));
+    }
+
+    #[tokio::test]
+    async fn test_route_groups() {
+        let config = Config {
+            api_keys: vec![
+                api_key("key-a", "team-a", false),
+                api_key("key-ops", "ops", true),
+            ],
+            ..Config::default()
+        };
+        let cli = client_with(config);
+
+        // Admin routes refuse everyone else before their handler runs: without a known key
+        // as unauthorized, with another key as forbidden
+        for (method, path) in [
+            (Method::GET, "/api/state_stats"),
+            (Method::POST, "/api/admin/reload_config"),
+            (Method::POST, "/api/admin/flush_caches"),
+        ] {
+            for (key, status, code) in [
+                (None, StatusCode::UNAUTHORIZED, "UNAUTHORIZED"),
+                (
+                    Some("Bearer unknown"),
+                    StatusCode::UNAUTHORIZED,
+                    "UNAUTHORIZED",
+                ),
+                (Some("Bearer key-a"), StatusCode::FORBIDDEN, "ADMIN_ONLY"),
+            ] {
+                let mut req = cli.request(method.clone(), path);
+                if let Some(key) = key {
+                    req = req.header("authorization", key);
+                }
+                let resp = req.send().await;
+                resp.assert_status(status);
+                assert!(
+                    resp.0.headers().contains_key(header::WWW_AUTHENTICATE)
+                        == (status == StatusCode::UNAUTHORIZED)
+                );
+                let body: serde_json::Value = resp.json().await.value().deserialize();
+                assert_eq!(body["error_code"], code, "{}", path);
+            }
+        }
+        let resp = cli
+            .post("/api/admin/flush_caches")
+            .header("authorization", "Bearer key-ops")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+
+        // Reads need no key and answer conditional GETs
+        let resp = cli.get("/api/version").send().await;
+        resp.assert_status_is_ok();
+        assert!(resp.0.headers().contains_key(header::ETAG));
+
+        // Key material isn't cached
+        let resp = cli.get("/api/generate").send().await;
+        resp.assert_status_is_ok();
+        assert_eq!(resp.0.headers()[header::CACHE_CONTROL], "no-store");
+        assert!(!resp.0.headers().contains_key(header::ETAG));
+    }
+
+    #[tokio::test]
+    async fn test_response_case() {
+        let keys: Vec<_> = (0..2)
+            .map(|_| Keypair::new().pubkey().to_string())
+            .collect();
+        let cli = test_client();
+        let aggregate = |case: Option<&'static str>| {
+            let mut req = cli
+                .post("/api/aggregate_keys")
+                .body_json(&serde_json::json!({ "keys": keys, "partyIndex": 1 }));
+            if let Some(case) = case {
+                req = req.header("x-response-case", case);
+            }
+            req.send()
+        };
+        let snake: serde_json::Value = aggregate(None).await.json().await.value().deserialize();
+        assert_eq!(snake["party_index"], 1);
+        let camel: serde_json::Value = aggregate(Some("camel"))
+            .await
+            .json()
+            .await
+            .value()
+            .deserialize();
+        assert_eq!(camel["partyIndex"], 1);
+        assert_eq!(camel["aggregatedPublicKey"], snake["aggregated_public_key"]);
+        assert!(camel.get("party_index").is_none());
+
+        // Each casing has its own ETag
+        let version = |case: &'static str, etag: Option<String>| {
+            let mut req = cli.get("/api/version").header("x-response-case", case);
+            if let Some(etag) = etag {
+                req = req.header(header::IF_NONE_MATCH, etag);
+            }
+            req.send()
+        };
+        let etag = |resp: &poem::test::TestResponse| {
+            resp.0.headers()[header::ETAG].to_str().unwrap().to_string()
+        };
+        let (snake, camel) = (version("snake", None).await, version("camel", None).await);
+        assert_ne!(etag(&snake), etag(&camel));
+        version("camel", Some(etag(&camel)))
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+        version("camel", Some(etag(&snake)))
+            .await
+            .assert_status_is_ok();
+        let body: serde_json::Value = camel.json().await.value().deserialize();
+        assert!(body.get("gitCommit").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_status_is_scoped_by_tenant() {
+        let config = Config {
+            api_keys: vec![
+                api_key("key-a", "team-a", false),
+                api_key("key-b", "team-b", false),
+                api_key("key-ops", "ops", true),
+            ],
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config).unwrap());
+        let signature = Signature::from([7; 64]);
+        state.audit.record(
+            Some("team-b"),
+            "send_single",
+            Some(&signature),
+            None,
+            None,
+            Outcome::Broadcast,
+            None,
+            None,
+        );
+        let cli = TestClient::new(build_app(state));
+
+        for (key, visible) in [("key-a", 0), ("key-b", 1), ("key-ops", 1), ("other", 0)] {
+            let resp = cli
+                .post("/api/broadcast_status")
+                .header("authorization", format!("Bearer {}", key))
+                .body_json(&serde_json::json!({ "signature": signature.to_string() }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let status: BroadcastStatusResponse = resp.json().await.value().deserialize();
+            assert_eq!(status.entries.len(), visible, "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ui_assets() {
+        let cli = test_client();
+        for (path, content_type) in [
+            ("/ui", "text/html; charset=utf-8"),
+            ("/ui/index.html", "text/html; charset=utf-8"),
+            ("/ui/app.js", "text/javascript; charset=utf-8"),
+            ("/ui/style.css", "text/css; charset=utf-8"),
+        ] {
+            let resp = cli.get(path).send().await;
+            resp.assert_status_is_ok();
+            resp.assert_content_type(content_type);
+            resp.assert_header("cache-control", "no-cache");
+            resp.assert_header("x-content-type-options", "nosniff");
+            let etag = resp.0.headers()["etag"].to_str().unwrap().to_string();
+            assert!(!resp.0.into_body().into_bytes().await.unwrap().is_empty());
+
+            // Revalidation with the current ETag is answered without a body
+            let resp = cli.get(path).header("if-none-match", &etag).send().await;
+            resp.assert_status(StatusCode::NOT_MODIFIED);
+            resp.assert_header("etag", etag);
+            let resp = cli
+                .get(path)
+                .header("if-none-match", "\"stale\"")
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+        }
+        cli.get("/ui/secrets.json")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        let config = Config {
+            ui: false,
+            ..Config::default()
+        };
+        let cli = client_with(config);
+        for path in ["/ui", "/ui/app.js"] {
+            cli.get(path)
+                .send()
+                .await
+                .assert_status(StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_logging() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let layer = access_log::layer(LogFormat::Json, "debug", move || writer.clone()).unwrap();
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+        let keypair = Keypair::new().to_base58_string();
+        let cli = test_client();
+        let resp = cli
+            .post("/api/convert_keypair")
+            .header(access_log::HEADER, "issue-42")
+            .body_json(&serde_json::json!({ "keypair": keypair }))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header(access_log::HEADER, "issue-42");
+
+        let resp = cli
+            .post("/api/agg_send_step_two")
+            .body_json(&serde_json::json!({
+                "keypair": keypair,
+                "secret_state": "kept-out-of-logs",
+                "first_messages": ["garbage"],
+            }))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::BAD_REQUEST);
+        let generated = resp.0.headers()[access_log::HEADER].to_str().unwrap();
+        assert_eq!(generated.len(), 32);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(!logs.contains(&keypair));
+        assert!(!logs.contains("kept-out-of-logs"));
+        let events: Vec<serde_json::Value> = logs
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let finished: Vec<_> = events
+            .iter()
+            .filter(|event| event["fields"]["message"] == "request finished")
+            .collect();
+        assert_eq!(finished.len(), 2);
+        assert_eq!(finished[0]["span"]["request_id"], "issue-42");
+        assert_eq!(finished[0]["span"]["path"], "/api/convert_keypair");
+        assert_eq!(finished[0]["fields"]["status"], 200);
+        assert_eq!(finished[1]["span"]["request_id"], generated);
+        assert_eq!(finished[1]["fields"]["status"], 400);
+        // The body is logged with its secrets replaced
+        assert!(events.iter().any(|event| {
+            event["fields"]["message"] == "request body"
+                && event["fields"]["body"]
+                    .as_str()
+                    .is_some_and(|body| body.contains("[redacted]") && body.contains("garbage"))
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_debug_timings() {
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(solana_tss_api_backend::timing::TimingLayer),
+        );
+        let body = serde_json::json!({
+            "keys": (0..2).map(|_| Keypair::new().pubkey().to_string()).collect::<Vec<_>>(),
+        });
+        let timings = |debug_timing: bool, key: Option<&'static str>| {
+            let config = Config {
+                api_keys: vec![
+                    api_key("key-a", "team-a", false),
+                    api_key("key-ops", "ops", true),
+                ],
+                debug_timing,
+                ..Config::default()
+            };
+            let cli = client_with(config);
+            let body = body.clone();
+            async move {
+                let mut req = cli
+                    .post("/api/aggregate_keys")
+                    .header("x-debug-timing", "1")
+                    .body_json(&body);
+                if let Some(key) = key {
+                    req = req.header("authorization", format!("Bearer {}", key));
+                }
+                let resp = req.send().await;
+                resp.assert_status_is_ok();
+                let mut json: serde_json::Value = resp.json().await.value().deserialize();
+                assert!(json["aggregated_public_key"].is_string());
+                json.get_mut("timings")
+                    .map(|t| serde_json::from_value::<Timings>(t.take()).unwrap())
+            }
+        };
+
+        // Only admin keys can ask for timings, responses are unchanged otherwise
+        assert!(timings(false, None).await.is_none());
+        assert!(timings(false, Some("key-a")).await.is_none());
+        assert!(timings(false, Some("key-ops")).await.is_some());
+
+        let timings = timings(true, None).await.unwrap();
+        assert!(timings.tss_ms > 0.0 && timings.serialization_ms > 0.0);
+        assert!(timings.rpc.is_empty());
+        let parts = timings.parse_ms + timings.tss_ms + timings.serialization_ms;
+        assert!(timings.total_ms >= timings.tss_ms && (parts - timings.total_ms).abs() < 1e-6);
+    }
+
+    // Drives a real server instance over HTTP, the way other services use the API
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_client_against_server() {
+        use poem::{
+            Server,
+            listener::{Acceptor, Listener, TcpListener},
+        };
+        use solana_tss_api_backend::client::{Client, ClientError};
+        use solana_tss_api_backend::models::AggregateKeysRequest;
+
+        let acceptor = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = acceptor.local_addr()[0].as_socket_addr().cloned().unwrap();
+        let state = Arc::new(AppState::new(Config::default()).unwrap());
+        tokio::spawn(Server::new_with_acceptor(acceptor).run(build_app(state)));
+        let client = Client::new(format!("http://{}", addr));
+
+        let capabilities = client.capabilities().await.unwrap();
+        assert_eq!(capabilities.endpoints.len(), ENDPOINTS.len());
+
+        let keys = vec![
+            client.generate().await.unwrap().public_share,
+            client.generate().await.unwrap().public_share,
+        ];
+        let aggregated = client
+            .aggregate_keys(&AggregateKeysRequest {
+                keys: keys.clone(),
+                party_key: None,
+                party_index: None,
+            })
+            .await
+            .unwrap();
+        assert!(!aggregated.aggregated_public_key.is_empty());
+
+        let bad_keys = AggregateKeysRequest {
+            keys: vec![keys[0].clone(), "not a key".to_string()],
+            party_key: None,
+            party_index: None,
+        };
+        assert!(matches!(
+            client.aggregate_keys(&bad_keys).await,
+            Err(ClientError::Api { status: 400, .. })
+        ));
+    }
+
+    // Failure storms driven through the public handlers against the mock node, checking what
+    // clients see and how many calls the faults cost.
+    #[cfg(feature = "chaos")]
+    mod chaos {
+        use std::collections::HashMap;
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        use poem::http::StatusCode;
+        use poem::test::{TestClient, TestResponse};
+        use serde_json::{Value, json};
+        use solana_sdk::signature::{Keypair, Signer};
+        use solana_tss_api_backend::chaos::ChaosReport;
+        use solana_tss_api_backend::config::Config;
+        use solana_tss_api_backend::models::{ErrorResponse, Network};
+        use solana_tss_api_backend::serialization::Serialize;
+        use solana_tss_api_backend::state::AppState;
+        use solana_tss_api_backend::tss::aggregated_pubkey;
+
+        use super::{api_key, ceremony, client_with};
+
+        const ADMIN: &str = "Bearer key-ops";
+
+        async fn storm(cli: &TestClient<impl poem::Endpoint>, config: Value) {
+            cli.post("/api/chaos")
+                .header("authorization", ADMIN)
+                .body_json(&config)
+                .send()
+                .await
+                .assert_status_is_ok();
+        }
+
+        async fn calls(cli: &TestClient<impl poem::Endpoint>, method: &str) -> (u64, u64) {
+            let resp = cli
+                .get("/api/chaos")
+                .header("authorization", ADMIN)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let report: ChaosReport = resp.json().await.value().deserialize();
+            report
+                .methods
+                .iter()
+                .find(|calls| calls.method == method)
+                .map_or((0, 0), |calls| (calls.calls, calls.faults))
+        }
+
+        async fn failure(resp: TestResponse) -> (StatusCode, String, Option<String>) {
+            let status = resp.0.status();
+            let body: Value = resp.json().await.value().deserialize();
+            (
+                status,
+                body["error"].as_str().unwrap_or_default().to_string(),
+                body["error_code"].as_str().map(str::to_string),
+            )
+        }
+
+        fn send_single(confirmation: Value) -> Value {
+            json!({
+                "keypair": Keypair::new().to_base58_string(),
+                "amount": 0.001,
+                "to": Keypair::new().pubkey().to_string(),
+                "net": "devnet",
+                "memo": null,
+                "compute_unit_limit": null,
+                "confirmation": confirmation,
+            })
+        }
+
+        fn client() -> TestClient<impl poem::Endpoint> {
+            client_with(Config {
+                api_keys: vec![
+                    api_key("key-a", "team-a", false),
+                    api_key("key-ops", "ops", true),
+                ],
+                // Storms send far more requests than any one client may
+                rate_limits: None,
+                ..Config::default()
+            })
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_chaos_is_admin_only_and_refuses_mainnet() {
+            let cli = client();
+            let resp = cli
+                .post("/api/chaos")
+                .header("authorization", "Bearer key-a")
+                .body_json(&json!({ "mock": true }))
+                .send()
+                .await;
+            let (status, _, code) = failure(resp).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert_eq!(code.as_deref(), Some("ADMIN_ONLY"));
+
+            let resp = cli
+                .post("/api/chaos")
+                .header("authorization", ADMIN)
+                .body_json(&json!({
+                    "faults": [{ "method": "*", "fault": "rate_limited", "probability": 2.0 }],
+                }))
+                .send()
+                .await;
+            let (status, _, code) = failure(resp).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(code.as_deref(), Some("INVALID_CHAOS_CONFIG"));
+
+            let config = Config {
+                rpc_urls: HashMap::from([(
+                    Network::Mainnet,
+                    "https://api.mainnet-beta.solana.com".to_string(),
+                )]),
+                ..Config::default()
+            };
+            assert!(AppState::new(config).is_err());
+
+            // Mainnet calls fail without being sent, even to the mock node
+            storm(&cli, json!({ "mock": true })).await;
+            let resp = cli
+                .post("/api/recent_block_hash")
+                .body_json(&json!({ "net": "mainnet" }))
+                .send()
+                .await;
+            let (status, error, _) = failure(resp).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(error.contains("never call mainnet"), "{}", error);
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_airdrop_batch() {
+            let cli = client();
+            storm(&cli, json!({ "mock": true })).await;
+            let batch = |recipients: usize, net: &str| {
+                let recipients: Vec<_> = (0..recipients)
+                    .map(|_| Keypair::new().pubkey().to_string())
+                    .collect();
+                cli.post("/api/airdrop_batch")
+                    .body_json(&json!({ "recipients": recipients, "amount": 1.0, "net": net }))
+                    .send()
+            };
+            let results = |resp: TestResponse| async move {
+                resp.assert_status_is_ok();
+                let body: Value = resp.json().await.value().deserialize();
+                body["results"].as_array().unwrap().clone()
+            };
+
+            let results = results(batch(5, "devnet").await).await;
+            assert_eq!(results.len(), 5);
+            for result in &results {
+                assert_eq!(result["status"], "confirmed");
+                assert_eq!(result["source"], "public_faucet");
+            }
+            assert_eq!(calls(&cli, "requestAirdrop").await, (5, 0));
+
+            // A rate-limiting faucet fails every recipient on its own, not the batch
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{ "method": "requestAirdrop", "fault": "rate_limited", "probability": 1.0 }],
+                }),
+            )
+            .await;
+            let resp = batch(3, "devnet").await;
+            resp.assert_status_is_ok();
+            let body: Value = resp.json().await.value().deserialize();
+            for result in body["results"].as_array().unwrap() {
+                assert_eq!(result["status"], "not_sent");
+                assert!(result.get("signature").is_none());
+            }
+            // Each tried three times behind the shared backoff
+            assert_eq!(calls(&cli, "requestAirdrop").await, (9, 9));
+
+            let (status, _, code) = failure(batch(21, "devnet").await).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(code.as_deref(), Some("TOO_MANY_RECIPIENTS"));
+            let (status, _, code) = failure(batch(2, "mainnet").await).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(code.as_deref(), Some("AIRDROP_UNAVAILABLE"));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_retryable_errors() {
+            let cli = client();
+            for (method, fault, class, retryable, status) in [
+                (
+                    "getLatestBlockhash",
+                    "rate_limited",
+                    "rate_limited",
+                    true,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ),
+                (
+                    "sendTransaction",
+                    "transport_error",
+                    "transport",
+                    true,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ),
+                (
+                    "sendTransaction",
+                    "blockhash_not_found",
+                    "transaction",
+                    false,
+                    StatusCode::BAD_REQUEST,
+                ),
+            ] {
+                storm(
+                    &cli,
+                    json!({
+                        "mock": true,
+                        "faults": [{ "method": method, "fault": fault }],
+                    }),
+                )
+                .await;
+                let resp = cli
+                    .post("/api/send_single")
+                    .body_json(&send_single(Value::Null))
+                    .send()
+                    .await;
+                resp.assert_status(status);
+                let error: ErrorResponse = resp.json().await.value().deserialize();
+                assert_eq!(error.error_class, class, "{}", error.error);
+                assert_eq!(error.retryable, retryable, "{}", error.error);
+            }
+
+            // Retrying a request that can't parse is pointless
+            let mut req = send_single(Value::Null);
+            req["keypair"] = json!("not a keypair");
+            let resp = cli.post("/api/send_single").body_json(&req).send().await;
+            let error: ErrorResponse = resp.json().await.value().deserialize();
+            assert_eq!(error.error_class, "input");
+            assert!(!error.retryable);
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_send_faults() {
+            let cli = client();
+
+            // A rate limited node is reported, not retried
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{ "method": "getLatestBlockhash", "fault": "rate_limited" }],
+                }),
+            )
+            .await;
+            let resp = cli
+                .post("/api/send_single")
+                .body_json(&send_single(Value::Null))
+                .send()
+                .await;
+            let (status, error, _) = failure(resp).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert!(error.contains("Too Many Requests"), "{}", error);
+            assert_eq!(calls(&cli, "getLatestBlockhash").await, (1, 1));
+            assert_eq!(calls(&cli, "sendTransaction").await, (0, 0));
+
+            // A send that never reached the node isn't sent again
+            for (fault, message, expected) in [
+                (
+                    "transport_error",
+                    "connection reset",
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ),
+                (
+                    "blockhash_not_found",
+                    "Blockhash not found",
+                    StatusCode::BAD_REQUEST,
+                ),
+            ] {
+                storm(
+                    &cli,
+                    json!({
+                        "mock": true,
+                        "faults": [{ "method": "sendTransaction", "fault": fault }],
+                    }),
+                )
+                .await;
+                let resp = cli
+                    .post("/api/send_single")
+                    .body_json(&send_single(Value::Null))
+                    .send()
+                    .await;
+                let (status, error, _) = failure(resp).await;
+                assert_eq!(status, expected);
+                assert!(error.contains("Failed sending transaction"), "{}", error);
+                assert!(error.contains(message), "{}", error);
+                assert_eq!(calls(&cli, "sendTransaction").await, (1, 1));
+                assert_eq!(calls(&cli, "getSignatureStatuses").await, (0, 0));
+            }
+
+            // Latency only slows the call down
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{ "method": "sendTransaction", "fault": "latency", "ms": 20 }],
+                }),
+            )
+            .await;
+            cli.post("/api/send_single")
+                .body_json(&send_single(Value::Null))
+                .send()
+                .await
+                .assert_status_is_ok();
+            assert_eq!(calls(&cli, "sendTransaction").await, (1, 1));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_simulation_tells_node_failures_apart() {
+            let cli = client();
+            let keys: Vec<String> = (0..2)
+                .map(|_| Keypair::new().pubkey().to_string())
+                .collect();
+            let transfer = json!({
+                "amount": 0.001,
+                "to": Keypair::new().pubkey().to_string(),
+                "recent_block_hash": "11111111111111111111111111111111",
+                "keys": keys,
+                "net": "devnet",
+            });
+
+            storm(&cli, json!({ "mock": true })).await;
+            let resp = cli.post("/api/simulate").body_json(&transfer).send().await;
+            resp.assert_status_is_ok();
+            let simulation: Value = resp.json().await.value().deserialize();
+            assert_eq!(simulation["would_succeed"], true);
+            assert_eq!(simulation["units_consumed"], 1_000);
+            assert!(simulation.get("failure").is_none());
+            assert_eq!(calls(&cli, "simulateTransaction").await, (1, 0));
+
+            // A node that can't be reached is an error, not a transfer that would fail
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{ "method": "simulateTransaction", "fault": "transport_error" }],
+                }),
+            )
+            .await;
+            let resp = cli.post("/api/simulate").body_json(&transfer).send().await;
+            let (status, _, code) = failure(resp).await;
+            assert_eq!(status, StatusCode::BAD_GATEWAY);
+            assert_eq!(code.as_deref(), Some("SIMULATION_UNAVAILABLE"));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_health_probes_are_cached() {
+            let cli = client();
+            storm(&cli, json!({ "mock": true })).await;
+            let health = || async {
+                let resp = cli.get("/api/health").query("rpc", &true).send().await;
+                resp.assert_status_is_ok();
+                let body: Value = resp.json().await.value().deserialize();
+                body
+            };
+
+            let body = health().await;
+            let networks = body["networks"].as_array().unwrap();
+            assert_eq!(networks[0]["net"], "mainnet");
+            assert_eq!(networks[0]["reachable"], false);
+            for network in &networks[1..] {
+                assert_eq!(network["reachable"], true, "{}", network);
+            }
+            assert_eq!(body["status"], "degraded");
+
+            // Answered from the latest probes, the nodes aren't asked again
+            let probes = calls(&cli, "getLatestBlockhash").await;
+            assert_eq!(health().await["networks"], body["networks"]);
+            assert_eq!(calls(&cli, "getLatestBlockhash").await, probes);
+        }
+
+        // On a single runtime thread, so a handler blocking it would hold up every other request
+        #[tokio::test]
+        async fn test_confirming_does_not_block_other_requests() {
+            let cli = client();
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{ "method": "getSignatureStatuses", "fault": "latency", "ms": 1000 }],
+                }),
+            )
+            .await;
+            let cli = Arc::new(cli);
+
+            let confirming = tokio::spawn({
+                let cli = cli.clone();
+                async move {
+                    cli.post("/api/send_single")
+                        .body_json(&send_single(Value::Null))
+                        .send()
+                        .await
+                        .assert_status_is_ok();
+                    Instant::now()
+                }
+            });
+            while calls(&*cli, "getSignatureStatuses").await.0 == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            let requests: Vec<_> = (0..50)
+                .map(|_| {
+                    let cli = cli.clone();
+                    tokio::spawn(async move {
+                        cli.post("/api/recent_block_hash")
+                            .body_json(&json!({ "net": "devnet" }))
+                            .send()
+                            .await
+                            .assert_status_is_ok();
+                    })
+                })
+                .collect();
+            for request in requests {
+                request.await.unwrap();
+            }
+            let answered = Instant::now();
+            assert!(answered < confirming.await.unwrap());
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_ceremony_duration() {
+            let cli = client();
+            storm(&cli, json!({ "mock": true, "faults": [] })).await;
+
+            let (parties, first_messages, secret_states) = ceremony(2);
+            let resp = cli
+                .post("/api/recent_block_hash")
+                .body_json(&json!({ "net": "devnet" }))
+                .send()
+                .await;
+            let block_hash: Value = resp.json().await.value().deserialize();
+            let transfer = json!({
+                "lamports": 1_000,
+                "to": Keypair::new().pubkey().to_string(),
+                "memo": null,
+                "recent_block_hash": block_hash["recent_block_hash"],
+                "keys": parties.iter().map(|p| p.pubkey().to_string()).collect::<Vec<_>>(),
+            });
+            let mut signatures = Vec::new();
+            for (i, party) in parties.iter().enumerate() {
+                let mut req = transfer.clone();
+                req["keypair"] = party.to_base58_string().into();
+                req["first_messages"] = json!([first_messages[1 - i].serialize_bs58()]);
+                req["secret_state"] = secret_states[i].serialize_bs58().into();
+                let resp = cli
+                    .post("/api/agg_send_step_two")
+                    .body_json(&req)
+                    .send()
+                    .await;
+                resp.assert_status_is_ok();
+                let step_two: Value = resp.json().await.value().deserialize();
+                signatures.push(step_two["partial_signature"].clone());
+            }
+            let mut req = transfer;
+            req["signatures"] = signatures.into();
+            req["net"] = "devnet".into();
+            req["compute_unit_limit"] = Value::Null;
+            let resp = cli
+                .post("/api/aggregate_signatures")
+                .body_json(&req)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let sent: Value = resp.json().await.value().deserialize();
+
+            // The entry that ended the ceremony carries its duration
+            let resp = cli
+                .post("/api/broadcast_status")
+                .body_json(&json!({ "signature": sent["transaction_id"] }))
+                .send()
+                .await;
+            let status: Value = resp.json().await.value().deserialize();
+            let entries = status["entries"].as_array().unwrap();
+            let confirmed = entries
+                .iter()
+                .find(|e| e["outcome"] == "confirmed")
+                .unwrap();
+            assert!(confirmed["ceremony_ms"].is_u64(), "{}", confirmed);
+            assert!(
+                entries
+                    .iter()
+                    .filter(|e| e.get("ceremony_ms").is_some())
+                    .count()
+                    == 1
+            );
+
+            let resp = cli.get("/metrics").send().await;
+            resp.assert_status_is_ok();
+            let metrics = resp.0.into_body().into_string().await.unwrap();
+            let count =
+                "tss_ceremony_duration_seconds_count{parties=\"2\",outcome=\"confirmed\"} 1";
+            assert!(metrics.contains(count), "{}", metrics);
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_dry_run_sends_nothing() {
+            use base64::{Engine, engine::general_purpose::STANDARD};
+
+            let cli = client();
+            storm(&cli, json!({ "mock": true, "faults": [] })).await;
+
+            let (parties, first_messages, secret_states) = ceremony(2);
+            let resp = cli
+                .post("/api/recent_block_hash")
+                .body_json(&json!({ "net": "devnet" }))
+                .send()
+                .await;
+            let block_hash: Value = resp.json().await.value().deserialize();
+            let transfer = json!({
+                "lamports": 1_000,
+                "to": Keypair::new().pubkey().to_string(),
+                "memo": null,
+                "recent_block_hash": block_hash["recent_block_hash"],
+                "keys": parties.iter().map(|p| p.pubkey().to_string()).collect::<Vec<_>>(),
+            });
+            let mut signatures = Vec::new();
+            for (i, party) in parties.iter().enumerate() {
+                let mut req = transfer.clone();
+                req["keypair"] = party.to_base58_string().into();
+                req["first_messages"] = json!([first_messages[1 - i].serialize_bs58()]);
+                req["secret_state"] = secret_states[i].serialize_bs58().into();
+                let resp = cli
+                    .post("/api/agg_send_step_two")
+                    .body_json(&req)
+                    .send()
+                    .await;
+                resp.assert_status_is_ok();
+                let step_two: Value = resp.json().await.value().deserialize();
+                signatures.push(step_two["partial_signature"].clone());
+            }
+            let mut req = transfer;
+            req["signatures"] = signatures.into();
+            req["net"] = "devnet".into();
+            req["dry_run"] = true.into();
+
+            let mut scheduled = req.clone();
+            scheduled["broadcast_at"] = json!({ "slot": 100 });
+            let resp = cli
+                .post("/api/aggregate_signatures")
+                .body_json(&scheduled)
+                .send()
+                .await;
+            let (status, error, code) = failure(resp).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(error.contains("dry_run"), "{}", error);
+            assert_eq!(code.as_deref(), Some("INVALID_BROADCAST_AT"));
+
+            let resp = cli
+                .post("/api/aggregate_signatures")
+                .body_json(&req)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let signed: Value = resp.json().await.value().deserialize();
+            assert_eq!(calls(&cli, "sendTransaction").await, (0, 0));
+            assert!(signed.get("rpc_endpoint").is_none(), "{}", signed);
+
+            // One signature, the one named as the transaction id, ahead of the message
+            let wire = STANDARD
+                .decode(signed["transaction"].as_str().unwrap())
+                .unwrap();
+            assert_eq!(wire[0], 1);
+            let signature = bs58::encode(&wire[1..65]).into_string();
+            assert_eq!(signed["transaction_id"], signature.as_str());
+
+            // The audit log knows what was handed out
+            let resp = cli
+                .post("/api/broadcast_status")
+                .body_json(&json!({ "signature": signature }))
+                .send()
+                .await;
+            let status: Value = resp.json().await.value().deserialize();
+            let entries = status["entries"].as_array().unwrap();
+            assert_eq!(entries.len(), 1, "{}", status);
+            assert_eq!(entries[0]["outcome"], "dry_run");
+            assert_eq!(entries[0]["endpoint"], "aggregate_signatures");
+            assert!(entries[0]["message_hash"].is_string());
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_broadcast_presigned() {
+            use base64::{Engine, engine::general_purpose::STANDARD};
+            use solana_sdk::{hash::Hash, system_transaction};
+            use solana_tss_api_backend::message_review::encode_transaction;
+
+            let cli = client();
+            storm(&cli, json!({ "mock": true, "faults": [] })).await;
+
+            let resp = cli
+                .post("/api/recent_block_hash")
+                .body_json(&json!({ "net": "devnet" }))
+                .send()
+                .await;
+            let block_hash: Value = resp.json().await.value().deserialize();
+            let block_hash: Hash = block_hash["recent_block_hash"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            let payer = Keypair::new();
+            let tx = system_transaction::transfer(&payer, &Keypair::new().pubkey(), 1, block_hash);
+            let bytes = STANDARD.decode(encode_transaction(&tx)).unwrap();
+
+            let mut forged = tx.clone();
+            forged.message.recent_blockhash = Hash::new_unique();
+            for (transaction, code) in [
+                (STANDARD.encode(&bytes[..40]), "MALFORMED_TRANSACTION"),
+                (encode_transaction(&forged), "TRANSACTION_SIGNATURE_INVALID"),
+            ] {
+                let resp = cli
+                    .post("/api/broadcast")
+                    .body_json(&json!({ "net": "devnet", "transaction": transaction }))
+                    .send()
+                    .await;
+                let (_, _, found) = failure(resp).await;
+                assert_eq!(found.as_deref(), Some(code));
+            }
+            assert_eq!(calls(&cli, "sendTransaction").await, (0, 0));
+
+            let resp = cli
+                .post("/api/broadcast")
+                .body_json(&json!({
+                    "net": "devnet",
+                    "transaction": bs58::encode(&bytes).into_string(),
+                    "encoding": "base58",
+                }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let sent: Value = resp.json().await.value().deserialize();
+            assert_eq!(
+                sent["transaction_id"],
+                tx.signatures[0].to_string().as_str()
+            );
+            assert_eq!(calls(&cli, "sendTransaction").await, (1, 0));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_estimate_fee() {
+            let cli = client();
+            storm(&cli, json!({ "mock": true, "faults": [] })).await;
+
+            let payer = Keypair::new().pubkey().to_string();
+            let sol = json!({
+                "net": "devnet",
+                "payer": payer,
+                "to": Keypair::new().pubkey().to_string(),
+                "lamports": 1_000,
+                "memo": "invoice 7",
+                "compute_unit_limit": "auto",
+            });
+            let mut spl = sol.clone();
+            spl["lamports"] = Value::Null;
+            spl["amount_base_units"] = 1_000.into();
+            spl["token_mint"] = Keypair::new().pubkey().to_string().into();
+            spl["decimals"] = 6.into();
+            for req in [&sol, &spl] {
+                let resp = cli.post("/api/estimate_fee").body_json(req).send().await;
+                resp.assert_status_is_ok();
+                let estimate: Value = resp.json().await.value().deserialize();
+                assert_eq!(estimate["fee_lamports"], 5_000);
+                assert_eq!(estimate["fee_payer"], payer.as_str());
+                assert_eq!(estimate["signatures"], 1);
+                assert_eq!(estimate["compute_unit_limit"]["simulated"], 1_000);
+            }
+            assert_eq!(calls(&cli, "getFeeForMessage").await, (2, 0));
+            assert_eq!(calls(&cli, "sendTransaction").await, (0, 0));
+
+            // A token amount doesn't make a SOL transfer
+            let mut mixed = sol;
+            mixed["amount_base_units"] = 1_000.into();
+            let resp = cli.post("/api/estimate_fee").body_json(&mixed).send().await;
+            let (status, error, _) = failure(resp).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(error.contains("amount_base_units"), "{}", error);
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_partial_confirmations() {
+            let cli = client();
+
+            // Not found twice, the transaction is sent again on each of those polls
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{
+                        "method": "getSignatureStatuses",
+                        "fault": "statuses",
+                        "sequence": [null, null, "confirmed"],
+                    }],
+                }),
+            )
+            .await;
+            let confirmation = json!({
+                "commitment": "confirmed",
+                "poll_interval_ms": 1,
+                "rebroadcast": true,
+            });
+            let resp = cli
+                .post("/api/send_single")
+                .body_json(&send_single(confirmation))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            assert_eq!(calls(&cli, "getSignatureStatuses").await, (3, 3));
+            assert_eq!(calls(&cli, "sendTransaction").await, (3, 0));
+
+            // Stuck below the commitment until the timeout
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{
+                        "method": "getSignatureStatuses",
+                        "fault": "statuses",
+                        "sequence": ["processed"],
+                    }],
+                }),
+            )
+            .await;
+            let confirmation = json!({
+                "commitment": "finalized",
+                "timeout_ms": 30,
+                "poll_interval_ms": 5,
+            });
+            let resp = cli
+                .post("/api/send_single")
+                .body_json(&send_single(confirmation))
+                .send()
+                .await;
+            let (_, _, code) = failure(resp).await;
+            assert_eq!(code.as_deref(), Some("CONFIRMATION_TIMED_OUT"));
+            assert_eq!(calls(&cli, "sendTransaction").await, (1, 0));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_without_waiting_for_confirmation() {
+            let cli = client();
+
+            // The transaction never gets past processed
+            storm(
+                &cli,
+                json!({
+                    "mock": true,
+                    "faults": [{
+                        "method": "getSignatureStatuses",
+                        "fault": "statuses",
+                        "sequence": ["processed"],
+                    }],
+                }),
+            )
+            .await;
+            let confirmation = json!({
+                "commitment": "finalized",
+                "timeout_ms": 30,
+                "poll_interval_ms": 5,
+            });
+            let resp = cli
+                .post("/api/send_single")
+                .body_json(&send_single(confirmation.clone()))
+                .send()
+                .await;
+            let (_, _, code) = failure(resp).await;
+            assert_eq!(code.as_deref(), Some("CONFIRMATION_TIMED_OUT"));
+
+            let mut body = send_single(confirmation);
+            body["wait_for_confirmation"] = json!(false);
+            let resp = cli.post("/api/send_single").body_json(&body).send().await;
+            resp.assert_status_is_ok();
+            let sent: Value = resp.json().await.value().deserialize();
+            assert_eq!(sent["status"], "submitted");
+            assert_eq!(calls(&cli, "sendTransaction").await, (2, 0));
+
+            // Where it stands is polled for instead
+            let resp = cli
+                .post("/api/transaction_statuses")
+                .body_json(&json!({ "net": "devnet", "signatures": [sent["transaction_id"]] }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let statuses: Value = resp.json().await.value().deserialize();
+            assert_eq!(statuses["statuses"][0]["confirmation_status"], "processed");
+
+            let resp = cli
+                .post("/api/airdrop")
+                .body_json(&json!({
+                    "to": Keypair::new().pubkey().to_string(),
+                    "amount": 1,
+                    "net": "devnet",
+                    "waitForConfirmation": false,
+                }))
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let airdrop: Value = resp.json().await.value().deserialize();
+            assert_eq!(airdrop["status"], "submitted");
+            assert_eq!(calls(&cli, "requestAirdrop").await, (1, 0));
+        }
+
+        #[tokio::test]
+        async fn test_step_two_checks_the_nonce_account() {
+            let cli = client();
+            storm(&cli, json!({ "mock": true, "faults": [] })).await;
+            let (parties, first_messages, secret_states) = ceremony(2);
+            let nonce_account = Keypair::new().pubkey().to_string();
+            let body = json!({
+                "keypair": parties[0].to_base58_string(),
+                "lamports": 1_000,
+                "to": Keypair::new().pubkey().to_string(),
+                "recent_block_hash": solana_sdk::hash::Hash::new_unique().to_string(),
+                "keys": parties.iter().map(|p| p.pubkey().to_string()).collect::<Vec<_>>(),
+                "first_messages": [first_messages[1].serialize_bs58()],
+                "secret_state": secret_states[0].serialize_bs58(),
+                "net": "devnet",
+                "nonce_account": nonce_account,
+            });
+            // Every address of the mock node is a plain system account without nonce state
+            let resp = cli
+                .post("/api/agg_send_step_two")
+                .body_json(&body)
+                .send()
+                .await;
+            let (status, error, code) = failure(resp).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(code.as_deref(), Some("INVALID_NONCE_ACCOUNT"));
+            assert!(error.contains(&nonce_account), "{}", error);
+        }
+
+        #[tokio::test]
+        async fn test_nonce_account_lifecycle() {
+            let cli = client();
+            storm(&cli, json!({ "mock": true, "faults": [] })).await;
+            let keys: Vec<_> = (0..2).map(|_| Keypair::new().pubkey()).collect();
+            let create = json!({
+                "keypair": Keypair::new().to_base58_string(),
+                "keys": keys.iter().map(|k| k.to_string()).collect::<Vec<_>>(),
+                "net": "devnet",
+            });
+            let resp = cli
+                .post("/api/nonce/create")
+                .body_json(&create)
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            let created: Value = resp.json().await.value().deserialize();
+            assert_eq!(
+                created["nonce_authority"],
+                aggregated_pubkey(keys.clone()).unwrap().to_string()
+            );
+            // Every address of the mock node is a plain system account, none holds a nonce
+            assert_eq!(created["nonce"], Value::Null);
+            assert_eq!(calls(&cli, "sendTransaction").await, (1, 0));
+
+            let mut both = create;
+            both["nonce_authority"] = keys[0].to_string().into();
+            let resp = cli.post("/api/nonce/create").body_json(&both).send().await;
+            let (status, _, _) = failure(resp).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+
+            let nonce_account = created["nonce_account"].clone();
+            let info = json!({ "nonce_account": nonce_account, "net": "devnet" });
+            let withdraw = json!({
+                "keypair": Keypair::new().to_base58_string(),
+                "nonce_account": nonce_account,
+                "destination": keys[0].to_string(),
+                "net": "devnet",
+            });
+            for (path, body) in [("/api/nonce/info", info), ("/api/nonce/withdraw", withdraw)] {
+                let resp = cli.post(path).body_json(&body).send().await;
+                let (status, _, code) = failure(resp).await;
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", path);
+                assert_eq!(code.as_deref(), Some("INVALID_NONCE_ACCOUNT"), "{}", path);
+            }
+            assert_eq!(calls(&cli, "sendTransaction").await, (1, 0));
+        }
+
+        #[tokio::test]
+        async fn test_seeded_storm() {
+            let cli = client();
+            let config = json!({
+                "mock": true,
+                "seed": 7,
+                "faults": [{ "method": "getAccountInfo", "fault": "transport_error", "probability": 0.5 }],
+            });
+            let mut outcomes = Vec::new();
+            for _ in 0..2 {
+                storm(&cli, config.clone()).await;
+                let mut failed = Vec::new();
+                for _ in 0..20 {
+                    let resp = cli
+                        .post("/api/balance")
+                        .body_json(&json!({
+                            "address": Keypair::new().pubkey().to_string(),
+                            "net": "devnet",
+                        }))
+                        .send()
+                        .await;
+                    failed.push(resp.0.status() != StatusCode::OK);
+                }
+                // Every injected fault failed its request, nothing else did
+                let faults = failed.iter().filter(|failed| **failed).count() as u64;
+                assert_eq!(calls(&cli, "getAccountInfo").await, (20, faults));
+                assert!(faults > 0 && faults < 20);
+                outcomes.push(failed);
+            }
+            // The same seed fails the same requests
+            assert_eq!(outcomes[0], outcomes[1]);
+        }
+    }
+
+    // The SOL flows end to end against a `solana-test-validator` spawned for the test, the
+    // binary from `SOLANA_TEST_VALIDATOR` or the PATH. Skipped when there is none.
+    #[cfg(feature = "localnet-tests")]
+    mod localnet {
+        use std::collections::HashMap;
+        use std::net::TcpListener;
+        use std::path::PathBuf;
+        use std::process::{Child, Command, Stdio};
+        use std::time::Duration;
+
+        use poem::test::TestClient;
+        use serde_json::{Value, json};
+        use solana_client::nonblocking::rpc_client::RpcClient;
+        use solana_sdk::commitment_config::CommitmentConfig;
+        use solana_sdk::native_token::LAMPORTS_PER_SOL;
+        use solana_sdk::program_pack::Pack;
+        use solana_sdk::pubkey::Pubkey;
+        use solana_sdk::signature::{Keypair, Signer};
+        use solana_sdk::system_instruction;
+        use solana_sdk::transaction::Transaction;
+        use solana_tss_api_backend::config::Config;
+        use solana_tss_api_backend::models::Network;
+        use solana_tss_api_backend::spl_token_utils::TokenProgram;
+        use spl_associated_token_account::instruction::create_associated_token_account;
+
+        use super::client_with;
+
+        struct Validator {
+            child: Child,
+            ledger: PathBuf,
+            url: String,
+        }
+
+        impl Validator {
+            fn free_port() -> u16 {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                listener.local_addr().unwrap().port()
+            }
+
+            /// A fresh validator answering on its RPC port.
+            async fn spawn() -> Validator {
+                let binary = std::env::var("SOLANA_TEST_VALIDATOR")
+                    .unwrap_or_else(|_| "solana-test-validator".to_string());
+                let (rpc_port, faucet_port) = (Self::free_port(), Self::free_port());
+                let ledger = std::env::temp_dir().join(format!("solana-tss-localnet-{}", rpc_port));
+                let child = Command::new(&binary)
+                    .arg("--reset")
+                    .arg("--quiet")
+                    .args(["--bind-address", "127.0.0.1"])
+                    .args(["--rpc-port", &rpc_port.to_string()])
+                    .args(["--faucet-port", &faucet_port.to_string()])
+                    .arg("--ledger")
+                    .arg(&ledger)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .unwrap_or_else(|e| panic!("can't run {}: {}", binary, e));
+                let validator = Validator {
+                    child,
+                    ledger,
+                    url: format!("http://127.0.0.1:{}", rpc_port),
+                };
+                let rpc_client = RpcClient::new(validator.url.clone());
+                for _ in 0..120 {
+                    if rpc_client.get_health().await.is_ok() {
+                        return validator;
+                    }
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+                panic!("solana-test-validator didn't come up at {}", validator.url);
+            }
+        }
+
+        impl Drop for Validator {
+            fn drop(&mut self) {
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                let _ = std::fs::remove_dir_all(&self.ledger);
+            }
+        }
+
+        async fn post(cli: &TestClient<impl poem::Endpoint>, path: &str, body: Value) -> Value {
+            let resp = cli.post(path).body_json(&body).send().await;
+            resp.assert_status_is_ok();
+            resp.json().await.value().deserialize()
+        }
+
+        async fn balance(cli: &TestClient<impl poem::Endpoint>, address: &Pubkey) -> u64 {
+            let body = json!({ "address": address.to_string(), "net": "localnet" });
+            post(cli, "/api/balance", body).await["balance"]
+                .as_u64()
+                .unwrap()
+        }
+
+        async fn airdrop(cli: &TestClient<impl poem::Endpoint>, to: &Pubkey, amount: f64) {
+            let body = json!({ "to": to.to_string(), "amount": amount, "net": "localnet" });
+            let airdrop = post(cli, "/api/airdrop", body).await;
+            assert_eq!(airdrop["source"], "local_faucet");
+        }
+
+        /// A new Token-2022 mint of `payer` with `decimals`, each of `holders` given the amount
+        /// next to it in its associated token account.
+        async fn token_2022_mint(
+            url: &str,
+            payer: &Keypair,
+            decimals: u8,
+            holders: &[(Pubkey, u64)],
+        ) -> Pubkey {
+            let rpc_client =
+                RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+            let (mint, program) = (Keypair::new(), spl_token_2022::id());
+            let space = spl_token_2022::state::Mint::LEN;
+            let rent = rpc_client
+                .get_minimum_balance_for_rent_exemption(space)
+                .await
+                .unwrap();
+            let mut instructions = vec![
+                system_instruction::create_account(
+                    &payer.pubkey(),
+                    &mint.pubkey(),
+                    rent,
+                    space as u64,
+                    &program,
+                ),
+                spl_token_2022::instruction::initialize_mint2(
+                    &program,
+                    &mint.pubkey(),
+                    &payer.pubkey(),
+                    None,
+                    decimals,
+                )
+                .unwrap(),
+            ];
+            for (holder, amount) in holders {
+                let ata = TokenProgram::Token2022.associated_address(holder, &mint.pubkey());
+                instructions.push(create_associated_token_account(
+                    &payer.pubkey(),
+                    holder,
+                    &mint.pubkey(),
+                    &program,
+                ));
+                instructions.push(
+                    spl_token_2022::instruction::mint_to(
+                        &program,
+                        &mint.pubkey(),
+                        &ata,
+                        &payer.pubkey(),
+                        &[],
+                        *amount,
+                    )
+                    .unwrap(),
+                );
+            }
+            let blockhash = rpc_client.get_latest_blockhash().await.unwrap();
+            let tx = Transaction::new_signed_with_payer(
+                &instructions,
+                Some(&payer.pubkey()),
+                &[payer, &mint],
+                blockhash,
+            );
+            rpc_client.send_and_confirm_transaction(&tx).await.unwrap();
+            mint.pubkey()
+        }
+
+        async fn token_balance(
+            cli: &TestClient<impl poem::Endpoint>,
+            owner: &Pubkey,
+            mint: &Pubkey,
+        ) -> Value {
+            let body = json!({
+                "owner": owner.to_string(),
+                "token_mint": mint.to_string(),
+                "net": "localnet",
+            });
+            post(cli, "/api/spl_token_balance", body).await
+        }
+
+        #[tokio::test]
+        async fn test_sol_transfers_on_localnet() {
+            let validator = Validator::spawn().await;
+            let config = Config {
+                rpc_urls: HashMap::from([(Network::Localnet, validator.url.clone())]),
+                rate_limits: None,
+                ..Config::default()
+            };
+            let cli = client_with(config);
+
+            // Single key
+            let (sender, recipient) = (Keypair::new(), Pubkey::new_unique());
+            airdrop(&cli, &sender.pubkey(), 2.0).await;
+            let body = json!({
+                "keypair": sender.to_base58_string(),
+                "amount": 0.5,
+                "to": recipient.to_string(),
+                "net": "localnet",
+                "memo": "localnet",
+            });
+            post(&cli, "/api/send_single", body).await;
+            assert_eq!(balance(&cli, &recipient).await, LAMPORTS_PER_SOL / 2);
+
+            // 2-party TSS
+            let parties = [Keypair::new(), Keypair::new()];
+            let keys: Vec<String> = parties.iter().map(|p| p.pubkey().to_string()).collect();
+            let aggregated = post(&cli, "/api/aggregate_keys", json!({ "keys": keys })).await;
+            let aggregated: Pubkey = aggregated["aggregated_public_key"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            airdrop(&cli, &aggregated, 1.0).await;
+
+            let mut step_ones = Vec::new();
+            for party in &parties {
+                let body = json!({ "keypair": party.to_base58_string() });
+                step_ones.push(post(&cli, "/api/agg_send_step_one", body).await);
+            }
+            let body = json!({ "net": "localnet" });
+            let block_hash =
+                post(&cli, "/api/recent_block_hash", body).await["recent_block_hash"].clone();
+            let recipient = Pubkey::new_unique();
+            let transfer = json!({
+                "lamports": 1_000_000,
+                "to": recipient.to_string(),
+                "memo": "localnet tss",
+                "recent_block_hash": block_hash,
+                "keys": keys,
+            });
+            let mut signatures = Vec::new();
+            for (i, party) in parties.iter().enumerate() {
+                let mut body = transfer.clone();
+                body["keypair"] = party.to_base58_string().into();
+                body["first_messages"] = json!([step_ones[1 - i]["message_1"]]);
+                body["secret_state"] = step_ones[i]["secret_state"].clone();
+                let step_two = post(&cli, "/api/agg_send_step_two", body).await;
+                signatures.push(step_two["partial_signature"].clone());
+            }
+            let mut body = transfer;
+            body["signatures"] = signatures.into();
+            body["net"] = "localnet".into();
+            body["compute_unit_limit"] = Value::Null;
+            post(&cli, "/api/aggregate_signatures", body).await;
+            assert_eq!(balance(&cli, &recipient).await, 1_000_000);
+        }
+
+        #[tokio::test]
+        async fn test_token_2022_transfers_on_localnet() {
+            let validator = Validator::spawn().await;
+            let config = Config {
+                rpc_urls: HashMap::from([(Network::Localnet, validator.url.clone())]),
+                rate_limits: None,
+                ..Config::default()
+            };
+            let cli = client_with(config);
+
+            let parties = [Keypair::new(), Keypair::new()];
+            let keys: Vec<String> = parties.iter().map(|p| p.pubkey().to_string()).collect();
+            let aggregated = post(&cli, "/api/aggregate_keys", json!({ "keys": keys })).await;
+            let aggregated: Pubkey = aggregated["aggregated_public_key"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            let sender = Keypair::new();
+            airdrop(&cli, &sender.pubkey(), 2.0).await;
+            airdrop(&cli, &aggregated, 1.0).await;
+            // Neither request names the program, the mint's owner on the node picks it
+            let mint = token_2022_mint(
+                &validator.url,
+                &sender,
+                6,
+                &[(sender.pubkey(), 5_000_000), (aggregated, 5_000_000)],
+            )
+            .await;
+            let held = token_balance(&cli, &sender.pubkey(), &mint).await;
+            assert_eq!(held["token_program"], "token_2022");
+
+            // Single key, to a recipient without a token account yet
+            let recipient = Pubkey::new_unique();
+            let body = json!({
+                "keypair": sender.to_base58_string(),
+                "amount": 1.5,
+                "to": recipient.to_string(),
+                "token_mint": mint.to_string(),
+                "net": "localnet",
+            });
+            post(&cli, "/api/spl_send_single", body).await;
+            let received = token_balance(&cli, &recipient, &mint).await;
+            assert_eq!(received["balance"], 1_500_000);
+            assert_eq!(received["token_program"], "token_2022");
+
+            // 2-party TSS
+            let mut step_ones = Vec::new();
+            for party in &parties {
+                let body = json!({ "keypair": party.to_base58_string() });
+                step_ones.push(post(&cli, "/api/agg_send_step_one", body).await);
+            }
+            let body = json!({ "net": "localnet" });
+            let block_hash =
+                post(&cli, "/api/recent_block_hash", body).await["recent_block_hash"].clone();
+            let recipient = Pubkey::new_unique();
+            let transfer = json!({
+                "amount_base_units": 250_000,
+                "to": recipient.to_string(),
+                "token_mint": mint.to_string(),
+                "decimals": 6,
+                "recent_block_hash": block_hash,
+                "keys": keys,
+                "net": "localnet",
+            });
+            let mut signatures = Vec::new();
+            for (i, party) in parties.iter().enumerate() {
+                let mut body = transfer.clone();
+                body["keypair"] = party.to_base58_string().into();
+                body["first_messages"] = json!([step_ones[1 - i]["message_1"]]);
+                body["secret_state"] = step_ones[i]["secret_state"].clone();
+                let step_two = post(&cli, "/api/spl_agg_send_step_two", body).await;
+                signatures.push(step_two["partial_signature"].clone());
+            }
+            let mut body = transfer;
+            body["signatures"] = signatures.into();
+            post(&cli, "/api/spl_aggregate_signatures", body).await;
+            let received = token_balance(&cli, &recipient, &mint).await;
+            assert_eq!(received["balance"], 250_000);
+            assert_eq!(received["token_program"], "token_2022");
+        }
+    }
 }
@@ -0,0 +1,283 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha2::{Digest, Sha256};
+use solana_sdk::{
+    compute_budget, message::Message, pubkey::Pubkey, system_program, transaction::Transaction,
+};
+
+use crate::{
+    Error,
+    compute_budget::ComputeUnitReport,
+    models::{BuildMessageResponse, InstructionAccount, InstructionBreakdown},
+};
+
+// A compliance system can approve the exact bytes of a TSS message before anyone signs it:
+// `/api/build_message` returns them with their hash, and step two refuses to sign when the
+// message it builds itself hashes differently from the approved `expected_message_hash`.
+
+/// The serialized message a signer signs, with its hash. Made from the bytes handed to the
+/// signing code so what the audit log records is what was signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledMessage {
+    pub bytes: Vec<u8>,
+    /// Hex encoded SHA-256 of `bytes`
+    pub hash: String,
+}
+
+impl CompiledMessage {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        let hash = Sha256::digest(&bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Self { bytes, hash }
+    }
+
+    pub fn of(message: &Message) -> Self {
+        Self::new(message.serialize())
+    }
+
+    pub fn encode(&self) -> String {
+        STANDARD.encode(&self.bytes)
+    }
+}
+
+/// Hex encoded SHA-256 of the serialized message, blockhash included.
+pub fn message_hash(message: &Message) -> String {
+    CompiledMessage::of(message).hash
+}
+
+pub fn encode_message(message: &Message) -> String {
+    CompiledMessage::of(message).encode()
+}
+
+/// Base64 of `transaction` as `sendTransaction` takes it: the signatures after their compact-u16
+/// count, then the message.
+pub fn encode_transaction(transaction: &Transaction) -> String {
+    let mut bytes = Vec::new();
+    let mut count = transaction.signatures.len();
+    loop {
+        let low = (count & 0x7f) as u8;
+        count >>= 7;
+        if count == 0 {
+            bytes.push(low);
+            break;
+        }
+        bytes.push(low | 0x80);
+    }
+    for signature in &transaction.signatures {
+        bytes.extend_from_slice(signature.as_ref());
+    }
+    bytes.extend(transaction.message_data());
+    STANDARD.encode(bytes)
+}
+
+/// Refuse to sign anything but the approved message.
+pub fn check_message_hash(message: &Message, expected: Option<&str>) -> Result<(), Error> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let actual = message_hash(message);
+    if !expected.trim().eq_ignore_ascii_case(&actual) {
+        return Err(Error::MessageHashMismatch {
+            expected: expected.trim().to_string(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn program_name(program_id: &Pubkey) -> Option<&'static str> {
+    let name = if *program_id == system_program::id() {
+        "system"
+    } else if *program_id == compute_budget::id() {
+        "compute_budget"
+    } else if *program_id == spl_token::id() {
+        "spl_token"
+    } else if *program_id == spl_token_2022::id() {
+        "token_2022"
+    } else if *program_id == spl_associated_token_account::id() {
+        "spl_associated_token_account"
+    } else if *program_id == spl_memo::id() {
+        "memo"
+    } else if *program_id == spl_memo::v1::id() {
+        "memo_v1"
+    } else {
+        return None;
+    };
+    Some(name)
+}
+
+fn is_memo_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_memo::id() || *program_id == spl_memo::v1::id()
+}
+
+/// Text of every memo instruction of `message`, for either memo program. Memos are meant to be
+/// UTF-8, what isn't is replaced rather than dropped.
+pub fn memos(message: &Message) -> Vec<String> {
+    message
+        .instructions
+        .iter()
+        .filter(|ix| is_memo_program(&message.account_keys[ix.program_id_index as usize]))
+        .map(|ix| String::from_utf8_lossy(&ix.data).into_owned())
+        .collect()
+}
+
+/// Every instruction of `message` with its program, accounts and raw data.
+pub fn instruction_breakdown(message: &Message) -> Vec<InstructionBreakdown> {
+    let keys = &message.account_keys;
+    message
+        .instructions
+        .iter()
+        .map(|ix| {
+            let program_id = keys[ix.program_id_index as usize];
+            InstructionBreakdown {
+                program_id: program_id.to_string(),
+                program: program_name(&program_id).map(str::to_string),
+                accounts: ix
+                    .accounts
+                    .iter()
+                    .map(|&i| InstructionAccount {
+                        pubkey: keys[i as usize].to_string(),
+                        is_signer: message.is_signer(i as usize),
+                        is_writable: message.is_writable(i as usize),
+                    })
+                    .collect(),
+                data: STANDARD.encode(&ix.data),
+                memo: is_memo_program(&program_id)
+                    .then(|| String::from_utf8_lossy(&ix.data).into_owned()),
+            }
+        })
+        .collect()
+}
+
+/// The static account keys of `message` in compiled order, with their signer and writable flags.
+pub fn account_keys(message: &Message) -> Vec<InstructionAccount> {
+    message
+        .account_keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| InstructionAccount {
+            pubkey: key.to_string(),
+            is_signer: message.is_signer(i),
+            is_writable: message.is_writable(i),
+        })
+        .collect()
+}
+
+/// What `/api/build_message` reports about the message `aggregated_pubkey` is going to sign.
+pub fn review(
+    aggregated_pubkey: &Pubkey,
+    message: &Message,
+    compute_unit_limit: Option<ComputeUnitReport>,
+) -> BuildMessageResponse {
+    BuildMessageResponse {
+        aggregated_pubkey: aggregated_pubkey.to_string(),
+        message: encode_message(message),
+        message_hash: message_hash(message),
+        instructions: instruction_breakdown(message),
+        memos: memos(message),
+        compute_unit_limit,
+        account_keys: account_keys(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use solana_sdk::{
+        hash::Hash,
+        instruction::Instruction,
+        message::Message,
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction,
+        transaction::Transaction,
+    };
+
+    use crate::{
+        Error,
+        message_review::{
+            check_message_hash, encode_transaction, instruction_breakdown, memos, message_hash,
+        },
+        transaction_builder::TransferBuilder,
+        tss::transfer_message,
+        units::Lamports,
+    };
+
+    #[test]
+    fn test_review_covers_signed_bytes() {
+        let (from, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let build = |lamports, blockhash| {
+            transfer_message(
+                &TransferBuilder::new(from, to, lamports)
+                    .memo(Some("invoice 7".to_string()))
+                    .compute_unit_limit(Some(1_000)),
+                blockhash,
+            )
+        };
+        let blockhash = Hash::new_unique();
+        let message = build(Lamports::new(5), blockhash);
+        let hash = message_hash(&message);
+        assert_eq!(hash.len(), 64);
+        check_message_hash(&message, None).unwrap();
+        check_message_hash(&message, Some(&hash.to_uppercase())).unwrap();
+
+        // Any difference, down to the blockhash, is refused
+        for other in [
+            build(Lamports::new(6), blockhash),
+            build(Lamports::new(5), Hash::new_unique()),
+        ] {
+            assert!(matches!(
+                check_message_hash(&other, Some(&hash)),
+                Err(Error::MessageHashMismatch { .. })
+            ));
+        }
+
+        let programs: Vec<_> = instruction_breakdown(&message)
+            .into_iter()
+            .map(|ix| ix.program.unwrap())
+            .collect();
+        assert_eq!(programs, ["compute_budget", "system", "memo"]);
+        let transfer = &instruction_breakdown(&message)[1];
+        assert_eq!(transfer.accounts[0].pubkey, from.to_string());
+        assert!(transfer.accounts[0].is_signer && transfer.accounts[0].is_writable);
+        assert!(!transfer.accounts[1].is_signer && transfer.accounts[1].is_writable);
+    }
+
+    #[test]
+    fn test_memos_of_both_programs() {
+        let payer = Pubkey::new_unique();
+        let memo = |program_id, data: &[u8]| Instruction::new_with_bytes(program_id, data, vec![]);
+        let message = Message::new(
+            &[
+                memo(spl_memo::id(), "invoice 7 ✓".as_bytes()),
+                system_instruction::transfer(&payer, &Pubkey::new_unique(), 1),
+                memo(spl_memo::v1::id(), &[b'r', b'e', b'f', 0xff]),
+            ],
+            Some(&payer),
+        );
+        assert_eq!(memos(&message), ["invoice 7 ✓", "ref\u{fffd}"]);
+
+        let breakdown = instruction_breakdown(&message);
+        let texts: Vec<_> = breakdown.iter().map(|ix| ix.memo.as_deref()).collect();
+        assert_eq!(texts, [Some("invoice 7 ✓"), None, Some("ref\u{fffd}")]);
+        // The bytes that were replaced are still there
+        assert_eq!(
+            STANDARD.decode(&breakdown[2].data).unwrap(),
+            [b'r', b'e', b'f', 0xff]
+        );
+    }
+
+    #[test]
+    fn test_encode_transaction() {
+        let payer = Keypair::new();
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        let message = Message::new(&[transfer], Some(&payer.pubkey()));
+        let transaction = Transaction::new(&[&payer], message.clone(), Hash::new_unique());
+
+        let wire = STANDARD.decode(encode_transaction(&transaction)).unwrap();
+        assert_eq!(wire[0], 1);
+        assert_eq!(&wire[1..65], transaction.signatures[0].as_ref());
+        assert_eq!(wire[65..], transaction.message_data());
+    }
+}
@@ -0,0 +1,99 @@
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
+use solana_sdk::{
+    derivation_path::DerivationPath, signature::Keypair,
+    signer::keypair::keypair_from_seed_and_derivation_path,
+};
+
+use crate::Error;
+
+// Keys kept as BIP39 mnemonics are derived the way Phantom and `solana-keygen recover
+// 'prompt://?key=0/0'` do: the 64 byte seed of the phrase and passphrase, then SLIP-0010 ed25519
+// along a fully hardened path, `m/44'/501'/0'/0'` for the first account.
+
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/501'/0'/0'";
+
+/// A fresh 24 word English mnemonic.
+pub fn generate_mnemonic() -> Mnemonic {
+    Mnemonic::new(MnemonicType::Words24, Language::English)
+}
+
+/// Parse `phrase`, ignoring case and how the words are separated.
+pub fn parse_mnemonic(phrase: &str) -> Result<Mnemonic, Error> {
+    let words: Vec<String> = phrase.split_whitespace().map(str::to_lowercase).collect();
+    Mnemonic::from_phrase(&words.join(" "), Language::English)
+        .map_err(|e| Error::InvalidMnemonic(e.to_string()))
+}
+
+/// The keypair at `path` (default `DEFAULT_DERIVATION_PATH`) below the seed of `mnemonic`.
+pub fn derive_keypair(
+    mnemonic: &Mnemonic,
+    passphrase: &str,
+    path: Option<&str>,
+) -> Result<Keypair, Error> {
+    let path = path.unwrap_or(DEFAULT_DERIVATION_PATH).trim();
+    let derivation_path = DerivationPath::from_absolute_path_str(path)
+        .map_err(|e| Error::InvalidDerivationPath(format!("{}: {}", path, e)))?;
+    let seed = Seed::new(mnemonic, passphrase);
+    keypair_from_seed_and_derivation_path(seed.as_bytes(), Some(derivation_path))
+        .map_err(|e| Error::InvalidDerivationPath(format!("{}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signer::Signer;
+
+    use super::*;
+
+    // The BIP39 test vector all of whose entropy is zero
+    const ABANDON: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                           abandon abandon about";
+
+    #[test]
+    fn test_derive_keypair() {
+        let mnemonic = parse_mnemonic(ABANDON).unwrap();
+        let keypair = derive_keypair(&mnemonic, "", None).unwrap();
+        // What Phantom shows for the first account of this phrase
+        assert_eq!(
+            keypair.pubkey().to_string(),
+            "HAgk14JpMQLgt6rVgv7cBQFJWFto5Dqxi472uT3DKpqk"
+        );
+        let explicit = derive_keypair(&mnemonic, "", Some(DEFAULT_DERIVATION_PATH)).unwrap();
+        assert_eq!(explicit.pubkey(), keypair.pubkey());
+
+        let second = derive_keypair(&mnemonic, "", Some("m/44'/501'/1'/0'")).unwrap();
+        assert_ne!(second.pubkey(), keypair.pubkey());
+        let protected = derive_keypair(&mnemonic, "secret", None).unwrap();
+        assert_ne!(protected.pubkey(), keypair.pubkey());
+
+        // Case and spacing don't matter
+        let shouted = parse_mnemonic(&format!("  {}\n", ABANDON.to_uppercase())).unwrap();
+        assert_eq!(shouted.phrase(), mnemonic.phrase());
+
+        assert!(matches!(
+            derive_keypair(&mnemonic, "", Some("44/501")),
+            Err(Error::InvalidDerivationPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_mnemonic() {
+        // The last word carries the checksum
+        let wrong_checksum = ABANDON.replace("about", "abandon");
+        assert!(matches!(
+            parse_mnemonic(&wrong_checksum),
+            Err(Error::InvalidMnemonic(e)) if e.contains("checksum")
+        ));
+        assert!(matches!(
+            parse_mnemonic("abandon abandon"),
+            Err(Error::InvalidMnemonic(_))
+        ));
+        assert!(matches!(
+            parse_mnemonic(&ABANDON.replace("about", "solana")),
+            Err(Error::InvalidMnemonic(_))
+        ));
+
+        let fresh = generate_mnemonic();
+        assert_eq!(fresh.phrase().split(' ').count(), 24);
+        assert!(parse_mnemonic(fresh.phrase()).is_ok());
+    }
+}
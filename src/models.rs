@@ -1,375 +1,2332 @@
+use std::fmt::{Display, Formatter};
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+use crate::audit::{AuditEntry, EndpointOutcomes, Outcome};
+use crate::bounded_store::StoreStats;
+use crate::compute_budget::{ComputeUnitLimit, ComputeUnitReport};
+use crate::confirmation::{Commitment, ConfirmationOverride};
+use crate::input::{keypair, optional_keypair, optional_pubkey, pubkey, pubkeys};
+use crate::rent_reclaim::TokenAccountStatus;
+use crate::serialization::SERIALIZATION_VERSION;
+use crate::simulation::FailureKind;
+use crate::spl_token_utils::TokenProgram;
+use crate::startup::StartupIssue;
+use crate::units::UiAmount;
+
+/// The cluster a request talks to. Requests may leave `net` out when the server has a
+/// `default_network`, responses name the one used in `X-Network`. `localnet` is a
+/// `solana-test-validator` on this machine, see `--localnet-url`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Network {
     Mainnet,
     Testnet,
     Devnet,
+    // Undocumented so the schema lists the networks as a single `enum`
+    Localnet,
 }
 
 impl Network {
+    pub const ALL: [Network; 4] = [Self::Mainnet, Self::Testnet, Self::Devnet, Self::Localnet];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mainnet => "mainnet",
+            Self::Testnet => "testnet",
+            Self::Devnet => "devnet",
+            Self::Localnet => "localnet",
+        }
+    }
+
     pub fn get_cluster_url(&self) -> &'static str {
         match self {
             Self::Mainnet => "https://api.mainnet-beta.solana.com",
             Self::Testnet => "https://api.testnet.solana.com",
             Self::Devnet => "https://api.devnet.solana.com",
+            Self::Localnet => "http://127.0.0.1:8899",
         }
     }
+
+    /// Whether the cluster is a local test validator, whose faucet hands out anything
+    /// without rate limits and which processes transactions as soon as they arrive.
+    pub fn is_local(&self) -> bool {
+        *self == Self::Localnet
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// When a scheduled transaction is sent, `{"slot": n}` or `{"unix_time": seconds}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BroadcastAt {
+    Slot(u64),
+    #[serde(alias = "unixTime")]
+    UnixTime(u64),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Query of `/api/generate`.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct GenerateKeypairQuery {
+    /// Derive the keypair from a fresh 24 word mnemonic and return it too
+    #[serde(default)]
+    pub mnemonic: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct GenerateKeypairResponse {
     pub secret_share: String,
     pub public_share: String,
+    /// The BIP39 phrase the keypair was derived from, along `derivation_path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derivation_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct KeypairFromMnemonicRequest {
+    pub mnemonic: String,
+    /// The optional BIP39 passphrase, empty when left out
+    #[serde(default)]
+    pub passphrase: String,
+    /// Defaults to `m/44'/501'/0'/0'`
+    #[serde(default, alias = "derivationPath")]
+    pub derivation_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ConvertKeypairRequest {
+    /// Base58 or the JSON array of 64 bytes of a `solana-keygen` keypair file
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String,
+}
+
+/// A keypair in both forms.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ConvertKeypairResponse {
+    pub public_share: String,
+    /// Base58, as `/api/generate` returns it
+    pub secret_share: String,
+    /// What `solana-keygen` writes to a keypair file
+    pub bytes: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct BalanceRequest {
     pub address: String,
-    pub net: Network,
+    pub net: Option<Network>,
+    /// Ask the node even when the account was found missing moments ago
+    #[serde(default)]
+    pub fresh: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct BalanceResponse {
     pub address: String,
     pub balance: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AirdropRequest {
+    #[serde(deserialize_with = "pubkey")]
     pub to: String,
-    pub amount: f64,
-    pub net: Network,
+    pub amount: UiAmount,
+    pub net: Option<Network>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+    /// `false` answers as soon as the node accepted the transaction, with `status: "submitted"`
+    #[serde(default, alias = "waitForConfirmation")]
+    pub wait_for_confirmation: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Where a transaction stood when a response that didn't wait for it was sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SendStatus {
+    /// The node accepted it, `/api/transaction_statuses` tells whether it landed
+    Submitted,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AirdropResponse {
     pub transaction_id: String,
+    pub source: AirdropSource,
+    /// `submitted` when the response didn't wait for the transaction, left out when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SendStatus>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AirdropSource {
+    PublicFaucet,
+    /// The faucet of the local test validator
+    LocalFaucet,
+    /// Transfer from the operator's devnet key, see `faucet` in the config
+    InternalFaucet,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AirdropBatchRequest {
+    /// At most 20, each gets `amount`
+    #[serde(deserialize_with = "pubkeys")]
+    pub recipients: Vec<String>,
+    pub amount: UiAmount,
+    pub net: Option<Network>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AirdropBatchResponse {
+    /// One per recipient, in request order
+    pub results: Vec<AirdropResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AirdropResult {
+    pub to: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    pub status: AirdropStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<AirdropSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AirdropStatus {
+    Confirmed,
+    /// Landed with an on-chain error
+    Failed,
+    Expired,
+    /// Still pending when the confirmation timeout ran out
+    TimedOut,
+    /// Neither faucet handed it out, see `error`
+    NotSent,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SendSingleRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String,
-    pub amount: f64,
+    /// Amount in SOL, alternatively pass `lamports`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amount_lamports", alias = "amountLamports")]
+    pub lamports: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
     pub to: String,
-    pub net: Network,
+    /// Paid in the same transaction after `to`
+    #[serde(default)]
+    pub recipients: Vec<Recipient>,
+    /// Send a transfer too large for one transaction as several, one after the other
+    #[serde(default, alias = "autoSplit")]
+    pub auto_split: bool,
+    pub net: Option<Network>,
     pub memo: Option<String>,
+    /// Add the sender as a signer of the memo instruction
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    /// Memo program id, Memo v3 by default
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    /// Number of units or `"auto"` to simulate the transaction first
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Priority fee in micro-lamports per compute unit
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+    /// Send even when `to` is a program, a sysvar or a token account
+    #[serde(default, alias = "allowNonSystemRecipient")]
+    pub allow_non_system_recipient: bool,
+    /// `false` answers as soon as the node accepted the transaction, with `status: "submitted"`
+    #[serde(default, alias = "waitForConfirmation")]
+    pub wait_for_confirmation: Option<bool>,
+}
+
+/// Another recipient of a `/api/send_single` transfer.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Recipient {
+    #[serde(deserialize_with = "pubkey")]
+    pub to: String,
+    /// Amount in SOL, alternatively pass `lamports`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amount_lamports", alias = "amountLamports")]
+    pub lamports: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SendSingleResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
+    /// Of the first transaction when the transfer was split
+    pub compute_unit_limit: Option<ComputeUnitReport>,
+    /// Every transaction of a split transfer in the order they were sent, `transaction_id` is
+    /// the first. Left out when the transfer wasn't split
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transaction_ids: Vec<String>,
+    /// `submitted` when the response didn't wait for the transaction, left out when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SendStatus>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SolanaPayParseRequest {
+    /// A `solana:` transfer request URL
+    pub url: String,
+}
+
+/// A Solana Pay transfer request in the fields of the request of `endpoint`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SolanaPayParseResponse {
+    /// `/api/send_single` for SOL, `/api/spl_send_single` for a token
+    pub endpoint: String,
+    pub to: String,
+    /// In SOL or in tokens, left out when the payer is to choose it
+    pub amount: Option<String>,
+    pub token_mint: Option<String>,
+    pub memo: Option<String>,
+    pub references: Vec<String>,
+    /// Who is asking for the payment, for display
+    pub label: Option<String>,
+    /// What the payment is for, for display
+    pub message: Option<String>,
+}
+
+/// The transfer a Solana Pay URL is to request, in the fields of `/api/send_single` or, with a
+/// token, `/api/spl_send_single`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SolanaPayCreateRequest {
+    #[serde(deserialize_with = "pubkey")]
+    pub to: String,
+    /// Left out to let the payer choose it
+    pub amount: Option<UiAmount>,
+    /// A token transfer needs either `token_mint` and `decimals` or the `token` symbol of a
+    /// registered token, SOL neither
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub decimals: Option<u8>,
+    pub token: Option<String>,
+    /// Network of the registered token, for a mint registered on several
+    pub net: Option<Network>,
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SolanaPayCreateResponse {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct RecentBlockHashRequest {
-    pub net: Network,
+    pub net: Option<Network>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct RecentBlockHashResponse {
     pub recent_block_hash: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateKeysRequest {
+    #[serde(deserialize_with = "pubkeys")]
     pub keys: Vec<String>,
+    /// Aggregate from the perspective of this one of `keys`
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "partyKey")]
+    pub party_key: Option<String>,
+    /// Aggregate from the perspective of `keys[party_index]`
+    #[serde(default, alias = "partyIndex")]
+    pub party_index: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAggMode {
+    /// No party designated, the coefficient is the first key's
+    FirstKey,
+    Party,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateKeysResponse {
     pub aggregated_public_key: String,
+    pub mode: KeyAggMode,
+    pub party_index: usize,
+    pub party_key: String,
+    /// Hex of the designated party's MuSig coefficient
+    pub musig_coefficient: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggSendStepOneRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggSendStepOneResponse {
     pub message_1: String,
-    pub secret_state: String,
+    /// Left out with `server_side_secret_state`, which keeps it on the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_state: Option<String>,
+    /// What step two sends in place of `secret_state` with `server_side_secret_state`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_state_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggSendStepTwoRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String,
-    pub amount: f64,
+    /// Amount in SOL, alternatively pass `lamports`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amount_lamports", alias = "amountLamports")]
+    pub lamports: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
     pub to: String,
     pub memo: Option<String>,
+    /// Add the sender as a signer of the memo instruction
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    /// Memo program id, Memo v3 by default
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    #[serde(alias = "recentBlockHash")]
     pub recent_block_hash: String,
+    #[serde(deserialize_with = "pubkeys")]
     pub keys: Vec<String>,
+    #[serde(alias = "firstMessages")]
     pub first_messages: Vec<String>,
-    pub secret_state: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretState"
+    )]
+    pub secret_state: Option<String>,
+    /// `secret_state_id` of step one in place of `secret_state`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretStateId"
+    )]
+    pub secret_state_id: Option<String>,
+    /// Number of units or `"auto"`, which simulates the transaction on `net`
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Priority fee in micro-lamports per compute unit, every party has to pass the same
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Nonce account to sign against instead of a recent blockhash, `recent_block_hash` is then
+    /// the nonce it holds. The transfer advances it first
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "nonceAccount")]
+    pub nonce_account: Option<String>,
+    /// Authority of `nonce_account`, it has to be the aggregated key, which it defaults to
+    #[serde(
+        default,
+        deserialize_with = "optional_pubkey",
+        alias = "nonceAuthority"
+    )]
+    pub nonce_authority: Option<String>,
+    /// Needed for `compute_unit_limit: "auto"`, also enables the source account and recipient
+    /// checks
+    pub net: Option<Network>,
+    /// Spend from an account owned by someone else that delegated to the aggregated key
+    #[serde(default, alias = "allowDelegate")]
+    pub allow_delegate: bool,
+    /// `message_hash` from `/api/build_message`, refuse to sign any other message
+    #[serde(alias = "expectedMessageHash")]
+    pub expected_message_hash: Option<String>,
+    /// Send even when `to` is a program, a sysvar or a token account
+    #[serde(default, alias = "allowNonSystemRecipient")]
+    pub allow_non_system_recipient: bool,
+    /// Add the accounts of the signed message with their flags to the response
+    #[serde(default, alias = "includeAccountKeys")]
+    pub include_account_keys: bool,
+    /// Coordinator session the signature belongs to, echoed in the response's `context`
+    #[serde(default, alias = "sessionId")]
+    pub session_id: Option<String>,
+    /// Sign for a threshold key of `keys` as one of its signers instead of for the aggregated key
+    #[serde(default)]
+    pub threshold: Option<ThresholdSigning>,
+    /// This party's `key_share` from `/api/threshold/keygen_step_three`, needed with `threshold`
+    #[serde(default, alias = "keyShare")]
+    pub key_share: Option<String>,
+}
+
+/// Who signs for a threshold key: the `signers` taking part, at least `threshold` of the `keys`
+/// of its keygen. Every step two and the aggregation have to name the same ones.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdSigning {
+    pub threshold: usize,
+    /// `group_key` of the keygen, the address that signs
+    #[serde(deserialize_with = "pubkey", alias = "groupKey")]
+    pub group_key: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub signers: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The transfer of `AggSendStepTwoRequest`, for review before anyone signs it.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BuildMessageRequest {
+    /// Amount in SOL, alternatively pass `lamports`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amount_lamports", alias = "amountLamports")]
+    pub lamports: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
+    pub to: String,
+    pub memo: Option<String>,
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    #[serde(alias = "recentBlockHash")]
+    pub recent_block_hash: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    /// Number of units or `"auto"`, pass the resulting `limit` on to step two
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Priority fee in micro-lamports per compute unit, pass the same to step two
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Nonce account to sign against instead of a recent blockhash, `recent_block_hash` is then
+    /// the nonce it holds. The transfer advances it first, pass the same to step two
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "nonceAccount")]
+    pub nonce_account: Option<String>,
+    /// Authority of `nonce_account`, it has to be the aggregated key, which it defaults to
+    #[serde(
+        default,
+        deserialize_with = "optional_pubkey",
+        alias = "nonceAuthority"
+    )]
+    pub nonce_authority: Option<String>,
+    /// Needed for `compute_unit_limit: "auto"`
+    pub net: Option<Network>,
+    /// The `threshold` of step two, the transfer is then from its `group_key`
+    #[serde(default)]
+    pub threshold: Option<ThresholdSigning>,
+}
+
+/// The transfer of `SplAggSendStepTwoRequest`, for review before anyone signs it.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplBuildMessageRequest {
+    /// Amount in whole tokens, alternatively pass `amount_base_units`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amountBaseUnits")]
+    pub amount_base_units: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
+    pub to: String,
+    /// Either `token_mint` and `decimals` or the `token` symbol of a registered token
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub decimals: Option<u8>,
+    pub token: Option<String>,
+    /// `spl_token` or `token_2022`, the owner of the mint on `net` when left out and the classic
+    /// program without `net`. Every party has to sign for the same one
+    #[serde(alias = "tokenProgram")]
+    pub token_program: Option<TokenProgram>,
+    pub memo: Option<String>,
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    #[serde(alias = "recentBlockHash")]
+    pub recent_block_hash: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    /// Number of units or `"auto"`, pass the resulting `limit` on to step two
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Priority fee in micro-lamports per compute unit, pass the same to step two
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Funds the recipient ATA instead of the aggregated key, and signs as well
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "rentSponsor")]
+    pub rent_sponsor: Option<String>,
+    /// Needed for `compute_unit_limit: "auto"`
+    pub net: Option<Network>,
+}
+
+/// A proposed SPL transfer, priced before anyone signs: the TSS transfer of the aggregated key
+/// of `keys`, or with `owner` the single-key one of `/api/spl_send_single`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplCostPreviewRequest {
+    /// Amount in whole tokens, alternatively pass `amount_base_units`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amountBaseUnits")]
+    pub amount_base_units: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
+    pub to: String,
+    /// Further transfers of the same token, each its own transaction
+    #[serde(default)]
+    pub recipients: Vec<SplRecipient>,
+    /// Either `token_mint` and `decimals` or the `token` symbol of a registered token
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub decimals: Option<u8>,
+    pub token: Option<String>,
+    /// `spl_token` or `token_2022`, the owner of the mint on `net` when left out and the classic
+    /// program without `net`
+    #[serde(alias = "tokenProgram")]
+    pub token_program: Option<TokenProgram>,
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    #[serde(default, deserialize_with = "optional_pubkey")]
+    pub owner: Option<String>,
+    pub memo: Option<String>,
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "rentSponsor")]
+    pub rent_sponsor: Option<String>,
+    /// The `limit` step two reports, or the units the single-key transfer sets
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<u32>,
+    /// Only changes `message_hash`, the costs don't depend on it
+    #[serde(alias = "recentBlockHash")]
+    pub recent_block_hash: Option<String>,
+    /// Look up which recipient token accounts exist, otherwise each is priced as created
+    pub net: Option<Network>,
+}
+
+/// Another recipient of an SPL cost preview.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SplRecipient {
+    #[serde(deserialize_with = "pubkey")]
+    pub to: String,
+    /// Amount in whole tokens, alternatively pass `amount_base_units`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amountBaseUnits")]
+    pub amount_base_units: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RentCost {
+    pub account: String,
+    pub payer: String,
+    pub lamports: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransferCost {
+    pub to: String,
+    pub recipient_ata: String,
+    /// Amount in whole tokens, as a decimal string
+    pub amount: String,
+    pub raw_amount: u64,
+    /// Unset without `net`
+    pub recipient_ata_exists: Option<bool>,
+    pub fee_payer: String,
+    pub fee_lamports: u64,
+    /// Token accounts the transaction creates
+    pub rent: Vec<RentCost>,
+    pub message_hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AccountDebit {
+    pub account: String,
+    pub lamports: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplCostPreviewResponse {
+    pub token_mint: String,
+    /// Sum over every transfer
+    pub raw_amount: u64,
+    pub transfers: Vec<TransferCost>,
+    /// Fees and rent by paying account, in account order
+    pub debits: Vec<AccountDebit>,
+    pub total_lamports: u64,
+}
+
+/// The transfer of a `/api/send_single`, or with a token of a `/api/spl_send_single`, from the
+/// address of its keypair.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EstimateFeeRequest {
+    /// Address of the `keypair` that would send the transfer and pay its fee
+    #[serde(deserialize_with = "pubkey")]
+    pub payer: String,
+    /// Amount in SOL, or whole tokens of a token transfer. Alternatively pass `lamports` or
+    /// `amount_base_units`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amount_lamports", alias = "amountLamports")]
+    pub lamports: Option<u64>,
+    #[serde(alias = "amountBaseUnits")]
+    pub amount_base_units: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
+    pub to: String,
+    /// Further recipients of a SOL transfer, paid in the same transaction
+    #[serde(default)]
+    pub recipients: Vec<Recipient>,
+    /// Either `token_mint` and `decimals` or the `token` symbol of a registered token make it
+    /// a token transfer
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub decimals: Option<u8>,
+    pub token: Option<String>,
+    pub net: Option<Network>,
+    pub memo: Option<String>,
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    /// Address of the `rent_sponsor_keypair` of a token transfer
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "rentSponsor")]
+    pub rent_sponsor: Option<String>,
+    /// Number of units or `"auto"` to simulate the transaction first
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Priority fee in micro-lamports per compute unit
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EstimateFeeResponse {
+    /// What the node charges for the transaction, rent of a token account it creates aside
+    pub fee_lamports: u64,
+    pub fee_payer: String,
+    /// Signatures the transaction carries, a rent sponsor's included
+    pub signatures: u8,
+    /// The node's blockhash the fee was looked up with
+    pub recent_block_hash: String,
+    pub compute_unit_limit: Option<ComputeUnitReport>,
+}
+
+/// What the node made of the transfer of `/api/simulate` or `/api/spl_simulate`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SimulationResponse {
+    pub aggregated_pubkey: String,
+    /// `message_hash` of the transfer, the same `build_message` returns
+    pub message_hash: String,
+    /// Whether it would succeed against the accounts as they are now
+    pub would_succeed: bool,
+    /// Why it wouldn't, left out when it would
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<SimulationFailure>,
+    pub units_consumed: Option<u64>,
+    /// Program logs in order
+    pub logs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SimulationFailure {
+    pub kind: FailureKind,
+    /// Index of the failing instruction in the message, for instruction errors
+    pub instruction: Option<u8>,
+    /// The transaction error as the node reported it
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct InstructionAccount {
+    pub pubkey: String,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct InstructionBreakdown {
+    pub program_id: String,
+    /// Name of the program if it is one the service builds instructions for
+    pub program: Option<String>,
+    pub accounts: Vec<InstructionAccount>,
+    /// Base64 instruction data
+    pub data: String,
+    /// Text of a memo instruction of either memo program, bytes that aren't UTF-8 replaced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BuildMessageResponse {
+    pub aggregated_pubkey: String,
+    /// Base64 of the serialized message every party will sign, blockhash included
+    pub message: String,
+    /// Hex SHA-256 of the serialized message, `expected_message_hash` of step two
+    pub message_hash: String,
+    pub instructions: Vec<InstructionBreakdown>,
+    /// Text of every memo instruction in order, the exact bytes are their `data`
+    pub memos: Vec<String>,
+    pub compute_unit_limit: Option<ComputeUnitReport>,
+    /// Every account of the message in compiled order with its signer and writable flags, what
+    /// the `writable_policy` is checked against
+    #[serde(default)]
+    pub account_keys: Vec<InstructionAccount>,
+}
+
+/// What a partial signature was made for. The aggregation checks the contexts of its
+/// signatures against each other and against the transfer it was given before combining them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SignatureContext {
+    #[serde(alias = "requestDigest")]
+    pub request_digest: String,
+    #[serde(alias = "aggregatedPubkey")]
+    pub aggregated_pubkey: String,
+    /// The party that made the signature
+    pub signer: String,
+    /// `environment` of the server that signed, `null` without one
+    pub environment: Option<String>,
+    #[serde(default, alias = "sessionId", skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+/// A partial signature for the aggregation, the bare blob or the blob with its `context` as step
+/// two returned them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SignatureSubmission {
+    Bare(String),
+    WithContext {
+        #[serde(alias = "partialSignature")]
+        partial_signature: String,
+        context: SignatureContext,
+    },
+}
+
+impl SignatureSubmission {
+    pub fn partial_signature(&self) -> &str {
+        match self {
+            Self::Bare(signature)
+            | Self::WithContext {
+                partial_signature: signature,
+                ..
+            } => signature,
+        }
+    }
+
+    pub fn context(&self) -> Option<&SignatureContext> {
+        match self {
+            Self::Bare(_) => None,
+            Self::WithContext { context, .. } => Some(context),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggSendStepTwoResponse {
     pub partial_signature: String,
+    /// Position of this party's key in `keys`. The address it spends from is
+    /// `context.aggregated_pubkey`
+    #[serde(default)]
+    pub key_index: usize,
+    pub request_digest: String,
+    /// Id of the `signed` audit entry, for `/api/audit/{id}/message`
+    #[serde(default)]
+    pub audit_id: u64,
+    /// Pass `limit` on to the aggregation step
+    pub compute_unit_limit: Option<ComputeUnitReport>,
+    /// With `include_account_keys`, every account of the signed message with its flags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_keys: Option<Vec<InstructionAccount>>,
+    /// What the signature was made for, pass it on with `partial_signature` to the aggregation
+    #[serde(default)]
+    pub context: SignatureContext,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateSignaturesRequest {
-    pub signatures: Vec<String>,
-    pub amount: f64,
+    /// Base58 `partial_signature`s, or `{partial_signature, context}` objects as step two
+    /// returned them
+    pub signatures: Vec<SignatureSubmission>,
+    /// Coordinator session of the step two signatures, their contexts must name the same one
+    #[serde(default, alias = "sessionId")]
+    pub session_id: Option<String>,
+    /// Amount in SOL, alternatively pass `lamports`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amount_lamports", alias = "amountLamports")]
+    pub lamports: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
     pub to: String,
     pub memo: Option<String>,
+    /// Add the sender as a signer of the memo instruction
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    /// Memo program id, Memo v3 by default
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    #[serde(alias = "recentBlockHash")]
     pub recent_block_hash: String,
-    pub net: Network,
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "pubkeys")]
     pub keys: Vec<String>,
+    /// The `limit` reported by step two
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<u32>,
+    /// The `priority_fee_micro_lamports` of step two
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// The `nonce_account` of step two
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "nonceAccount")]
+    pub nonce_account: Option<String>,
+    /// The `nonce_authority` of step two
+    #[serde(
+        default,
+        deserialize_with = "optional_pubkey",
+        alias = "nonceAuthority"
+    )]
+    pub nonce_authority: Option<String>,
+    /// Hold the signed transaction and send it at this slot or time instead of right away
+    #[serde(alias = "broadcastAt")]
+    pub broadcast_at: Option<BroadcastAt>,
+    /// Return the signed transaction in `transaction` instead of sending it
+    #[serde(default, alias = "dryRun")]
+    pub dry_run: bool,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+    /// Send even when `to` is a program, a sysvar or a token account
+    #[serde(default, alias = "allowNonSystemRecipient")]
+    pub allow_non_system_recipient: bool,
+    /// `false` answers as soon as the node accepted the transaction, with `status: "submitted"`
+    #[serde(default, alias = "waitForConfirmation")]
+    pub wait_for_confirmation: Option<bool>,
+    /// The `threshold` of step two, `signatures` are then those of its `signers`
+    #[serde(default)]
+    pub threshold: Option<ThresholdSigning>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateSignaturesResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through, unset when it was scheduled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_endpoint: Option<String>,
+    /// Commitment the transaction had reached when the response was sent, at least the one
+    /// of `confirmation`. Unset when it was scheduled or not waited for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<Commitment>,
+    pub request_digest: String,
+    /// Set when the transaction was scheduled rather than sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_at: Option<BroadcastAt>,
+    /// An earlier attempt already sent the transaction, `transaction_id` is its signature
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate: bool,
+    /// Base64 of the signed transaction as `sendTransaction` takes it, only set for `dry_run`,
+    /// which sends nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+    /// `submitted` when the response didn't wait for the transaction, left out when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SendStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CapabilitiesResponse {
+    pub version: String,
+    pub serialization: SerializationCapabilities,
+    pub networks: Vec<Network>,
+    /// Network of requests that leave out `net`, they are refused without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_network: Option<Network>,
+    pub features: Vec<String>,
+    pub limits: CapabilityLimits,
+    pub endpoints: Vec<EndpointInfo>,
+    /// Codes of the `warnings` a successful response can carry
+    pub warning_codes: Vec<WarningCodeInfo>,
+    /// Every `error_class` of error responses and whether it is `retryable`
+    pub error_classes: Vec<ErrorClassInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SerializationCapabilities {
+    pub accepted_versions: Vec<u8>,
+    pub emitted_version: u8,
+}
+
+impl SerializationCapabilities {
+    /// The blob versions this build reads and writes, straight from the serialization module.
+    pub fn current() -> Self {
+        Self {
+            accepted_versions: vec![SERIALIZATION_VERSION],
+            emitted_version: SERIALIZATION_VERSION,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CapabilityLimits {
+    pub max_body_size: usize,
+    pub max_parties: usize,
+    /// JSON forms an `amount` may take, floats are left out under `strict_amounts`
+    pub amount_formats: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct VersionResponse {
+    pub version: String,
+    pub git_commit: String,
+    /// Cargo features compiled in
+    pub cargo_features: Vec<String>,
+    /// Optional features enabled by the config
+    pub features: Vec<String>,
+    pub dependencies: DependencyVersions,
+    pub serialization: SerializationCapabilities,
+    pub limits: CapabilityLimits,
+    pub networks: Vec<NetworkInfo>,
+    /// The configured `environment` label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DependencyVersions {
+    pub solana_sdk: String,
+    pub multi_party_eddsa: String,
+}
+
+/// `/api/state_stats`, every in-memory store with its size and evictions.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StateStatsResponse {
+    pub stores: Vec<StoreStats>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    /// Serving, but something is off, see the other fields
+    Degraded,
+    /// No probed node answered, nothing that reads or sends transactions can be served
+    Unavailable,
+}
+
+/// `/api/health`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct HealthResponse {
+    pub status: HealthStatus,
+    /// How far the system clock is ahead of the `clock_check` cluster's, negative when it is
+    /// behind. Left out until the first check succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew_ms: Option<i64>,
+    /// Unix timestamp in milliseconds of that check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_checked_at: Option<i64>,
+    /// Set when the skew is above `clock_check.threshold_ms`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub clock_skewed: bool,
+    /// Seconds since the server started
+    pub uptime_secs: u64,
+    /// Reachability of each network's RPC node, only with `?rpc=true`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<NetworkHealth>,
+}
+
+/// Query of `/api/health`.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HealthQuery {
+    /// Probe the RPC node of every network
+    #[serde(default)]
+    pub rpc: bool,
+}
+
+/// Whether the RPC node of `net` answered a `getLatestBlockhash`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NetworkHealth {
+    pub net: Network,
+    pub reachable: bool,
+    /// How long the node took to answer, unset when it didn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Unix timestamp in milliseconds of the probe, answers are reused for a few seconds
+    pub checked_at: i64,
+}
+
+/// `/api/admin/usage_stats`, counters since the start.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UsageStatsResponse {
+    /// Whether requests are counted, see `usage_stats`
+    pub enabled: bool,
+    pub fields: Vec<FieldUsage>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Requests to `endpoint` that sent `field` as a JSON `representation`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FieldUsage {
+    pub endpoint: String,
+    pub field: String,
+    /// `string`, `integer`, `float`, `bool`, `array`, `object` or `null`
+    pub representation: String,
+    pub count: u64,
+}
+
+/// `/api/rpc_stats`, counters since the start.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RpcStatsResponse {
+    pub account_fetches: AccountFetchStats,
+    /// Outcomes of the broadcasts by the RPC endpoint they were sent through
+    pub broadcasts: Vec<EndpointOutcomes>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+pub struct AccountFetchStats {
+    /// Reads sent as their own `getAccountInfo`
+    pub direct: u64,
+    /// Reads answered by a shared `getMultipleAccounts`
+    pub coalesced: u64,
+    /// `getMultipleAccounts` calls the coalesced reads took
+    pub batches: u64,
+    /// Balance reads answered from the accounts found missing, see `missing_ttl_ms`
+    pub missing_hits: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NetworkInfo {
+    pub network: Network,
+    pub rpc_host: String,
+    /// Whether the RPC URL comes from the config rather than the public default
+    pub custom_rpc: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SpendLimitsResponse {
+    /// False when no spend limits are configured, everything else is empty then
+    pub enabled: bool,
+    pub global: Vec<SpendUsage>,
+    pub api_keys: Vec<ApiKeySpend>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpendUsage {
+    /// `SOL` or a token mint
+    pub asset: String,
+    /// Lamports or token base units spent in the last 24 hours
+    pub spent: u64,
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKeySpend {
+    /// Truncated SHA-256 of the API key
+    pub api_key_id: String,
+    pub usage: Vec<SpendUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EndpointInfo {
+    pub method: String,
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReloadConfigResponse {
+    /// Changed sections that keep their running values until a restart
+    pub restart_required: Vec<String>,
+    /// Soft issues the startup checks found in the new config
+    pub issues: Vec<StartupIssue>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FlushCachesResponse {
+    /// Caches that were emptied
+    pub flushed: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorClassInfo {
+    pub class: String,
+    pub retryable: bool,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WarningCodeInfo {
+    pub code: String,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ErrorResponse {
     pub error: String,
+    /// Stable machine readable code, branch on this rather than on `error`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// Structured fields of the error, for the errors clients act on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    /// One of the `error_classes` of `/api/capabilities`
+    #[serde(default)]
+    pub error_class: String,
+    /// Whether sending the same request again may succeed, false means something about the
+    /// request has to change first
+    #[serde(default)]
+    pub retryable: bool,
+}
+
+/// Where a request spent its time, added as `timings` to responses in debug timing mode.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Timings {
+    pub total_ms: f64,
+    /// Everything outside RPC, TSS and serialization, mostly parsing and validation
+    pub parse_ms: f64,
+    pub tss_ms: f64,
+    pub serialization_ms: f64,
+    /// One entry per traced RPC call, in the order they finished
+    pub rpc: Vec<RpcTiming>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RpcTiming {
+    pub call: String,
+    pub ms: f64,
 }
 
 // SPL Token Transfer Models
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TokenEntry {
+    pub symbol: String,
+    pub mint: String,
+    pub decimals: u8,
+    pub net: Network,
+    /// Largest `amount` of a single transfer, in whole tokens
+    #[serde(default, alias = "maxPerTransaction")]
+    pub max_per_transaction: Option<f64>,
+    /// Create the recipient's token account when it doesn't exist yet
+    #[serde(default = "default_true", alias = "allowCreateAta")]
+    pub allow_create_ata: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A deleted token, restorable through `/api/restore_token` until `purge_at`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeletedTokenEntry {
+    #[serde(flatten)]
+    pub token: TokenEntry,
+    /// Unix timestamp in milliseconds when it is forgotten, or was by a purge
+    pub purge_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TokensResponse {
+    pub tokens: Vec<TokenEntry>,
+    /// Deleted tokens that can still be restored
+    #[serde(default)]
+    pub deleted: Vec<DeletedTokenEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DeleteTokenRequest {
+    pub symbol: String,
+    /// Forget a token that was deleted before, it can't be restored afterwards
+    #[serde(default)]
+    pub purge: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RestoreTokenRequest {
+    pub symbol: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplTokenBalanceRequest {
+    #[serde(deserialize_with = "pubkey")]
     pub owner: String,
-    pub token_mint: String,
-    pub net: Network,
+    /// Either `token_mint` or the `token` symbol of a registered token
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub token: Option<String>,
+    pub net: Option<Network>,
+    /// Ask the node even when an account was found missing moments ago
+    #[serde(default)]
+    pub fresh: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplTokenBalanceResponse {
     pub owner: String,
     pub token_mint: String,
     pub balance: u64,
     pub decimals: u8,
+    /// False when the derived account's data names someone else as owner (a reassigned account)
+    pub owner_matches: bool,
+    pub account_owner: String,
+    /// A wrapped SOL account, `balance` is in lamports and leaves out its rent-exempt reserve
+    #[serde(default)]
+    pub is_native: bool,
+    /// Lamports of a wrapped SOL account that can't be transferred
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rent_reserve: Option<u64>,
+    /// Lamports sent to a wrapped SOL account since its last SyncNative, not in `balance` yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unsynced_lamports: Option<u64>,
+    /// The program owning the mint, the account is its associated token account under it
+    #[serde(default)]
+    pub token_program: TokenProgram,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplRentSummaryRequest {
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "pubkey")]
+    pub owner: String,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TokenAccountCounts {
+    pub closable: usize,
+    pub non_empty: usize,
+    pub frozen: usize,
+    pub delegated: usize,
+    pub foreign_close_authority: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CloseChunkInfo {
+    pub id: String,
+    pub accounts: usize,
+    pub lamports: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TokenAccountInfo {
+    pub address: String,
+    pub mint: String,
+    pub amount: u64,
+    pub lamports: u64,
+    pub status: TokenAccountStatus,
+    /// Chunk that would close this account, if any
+    pub chunk_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplRentSummaryResponse {
+    pub owner: String,
+    pub total_accounts: usize,
+    pub counts: TokenAccountCounts,
+    pub reclaimable_lamports: u64,
+    pub chunks: Vec<CloseChunkInfo>,
+    pub accounts: Vec<TokenAccountInfo>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplCloseEmptyAccountsRequest {
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String,
+    /// Chunk ids from `spl_rent_summary`
+    #[serde(alias = "chunkIds")]
+    pub chunk_ids: Vec<String>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClosedChunk {
+    pub chunk_id: String,
+    pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
+    pub lamports: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplCloseEmptyAccountsResponse {
+    pub closed: Vec<ClosedChunk>,
+    pub reclaimed_lamports: u64,
+}
+
+/// How `BroadcastRequest::transaction` is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionEncoding {
+    #[default]
+    Base64,
+    Base58,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BroadcastRequest {
+    /// A fully signed transaction, such as the `transaction` of a `dry_run` aggregation
+    pub transaction: String,
+    #[serde(default)]
+    pub encoding: TransactionEncoding,
+    pub net: Option<Network>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+    /// `false` answers as soon as the node accepted the transaction, with `status: "submitted"`
+    #[serde(default, alias = "waitForConfirmation")]
+    pub wait_for_confirmation: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BroadcastResponse {
+    pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
+    /// Commitment the transaction had reached when the response was sent, at least the one
+    /// of `confirmation`. Unset when it didn't wait
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<Commitment>,
+    /// The node had processed the transaction before, `transaction_id` is its signature
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate: bool,
+    /// `submitted` when the response didn't wait for the transaction, left out when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SendStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BroadcastStatusRequest {
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BroadcastStatusResponse {
+    pub signature: String,
+    /// Oldest first, empty if the service never tried to broadcast it
+    pub entries: Vec<AuditEntry>,
+}
+
+/// `/api/audit/{id}/message`, the compiled message an audit entry is about.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AuditMessageResponse {
+    pub id: u64,
+    pub endpoint: String,
+    pub signature: Option<String>,
+    /// Hex encoded SHA-256 of the message, unset for entries not written with one
+    pub message_hash: Option<String>,
+    /// The message, base64 encoded. Only kept with `audit_full_messages`
+    pub message: Option<String>,
+}
+
+/// Query of `/api/audit/export`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AuditExportQuery {
+    /// Unix milliseconds, inclusive
+    pub from: u64,
+    /// Unix milliseconds, exclusive
+    pub to: u64,
+    /// `next_cursor` of the previous export's trailer, or the id of the last entry received
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ScheduledBroadcastStatusRequest {
+    /// The `Idempotency-Key` the aggregation request was sent with
+    #[serde(alias = "idempotencyKey")]
+    pub idempotency_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ScheduledBroadcastStatusResponse {
+    pub signature: String,
+    /// Set while the transaction still waits to be sent
+    pub broadcast_at: Option<BroadcastAt>,
+    /// Audit entries, as `/api/broadcast_status` returns them
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Pass either the transaction's signature or the `Idempotency-Key` it was scheduled with.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CancelScheduledBroadcastRequest {
+    pub signature: Option<String>,
+    #[serde(alias = "idempotencyKey")]
+    pub idempotency_key: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CancelScheduledBroadcastResponse {
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionStatusesRequest {
+    pub net: Option<Network>,
+    /// At most 256
+    pub signatures: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionStatusesResponse {
+    /// One per requested signature, in request order
+    pub statuses: Vec<TransactionStatusEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionStatusEntry {
+    pub signature: String,
+    /// Why `signature` couldn't be parsed, the other fields are empty then
+    pub error: Option<String>,
+    pub found: bool,
+    /// `processed`, `confirmed` or `finalized`
+    pub confirmation_status: Option<String>,
+    pub slot: Option<u64>,
+    /// The transaction error if it landed and failed
+    pub err: Option<String>,
+    /// `broadcast` until it reaches the node's commitment, then `confirmed` or `failed`
+    pub outcome: Option<Outcome>,
+}
+
+/// A text message sent on `/ws/confirmations`, follow `signature` on `net`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ConfirmationSubscription {
+    pub signature: String,
+    pub net: Option<Network>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmationStage {
+    Processed,
+    Confirmed,
+    Finalized,
+    /// Landed with an error, `err` has it
+    Failed,
+    /// Neither finalized nor failed within the configured timeout
+    TimedOut,
+    /// The message wasn't followed, `error` and `error_code` say why
+    Rejected,
+}
+
+/// A text message of `/ws/confirmations`: `signature` reached `status`. `finalized`, `failed`,
+/// `timed_out` and `rejected` are the last one for their signature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ConfirmationUpdate {
+    pub signature: String,
+    pub status: ConfirmationStage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    /// The transaction error of a `failed` transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub err: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplSendSingleRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String,
-    pub amount: f64,
+    /// Amount in whole tokens, alternatively pass `amount_base_units`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amountBaseUnits")]
+    pub amount_base_units: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
     pub to: String,
-    pub token_mint: String,
-    pub decimals: u8,
-    pub net: Network,
+    /// Either `token_mint` and `decimals` or the `token` symbol of a registered token
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub decimals: Option<u8>,
+    pub token: Option<String>,
+    pub net: Option<Network>,
     pub memo: Option<String>,
+    /// Add the sender as a signer of the memo instruction
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    /// Memo program id, Memo v3 by default
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    /// Base58 keypair that pays the rent of a recipient ATA the transfer creates
+    #[serde(
+        default,
+        deserialize_with = "optional_keypair",
+        alias = "rentSponsorKeypair"
+    )]
+    pub rent_sponsor_keypair: Option<String>,
+    /// Number of units or `"auto"` to simulate the transaction first
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Priority fee in micro-lamports per compute unit
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Spend from an account owned by someone else that delegated to the signer
+    #[serde(default, alias = "allowDelegate")]
+    pub allow_delegate: bool,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+    /// `false` answers as soon as the node accepted the transaction, with `status: "submitted"`
+    #[serde(default, alias = "waitForConfirmation")]
+    pub wait_for_confirmation: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplSendSingleResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
+    pub compute_unit_limit: Option<ComputeUnitReport>,
+    /// `submitted` when the response didn't wait for the transaction, left out when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SendStatus>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplAggSendStepTwoRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String,
-    pub amount: f64,
+    /// Amount in whole tokens, alternatively pass `amount_base_units`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amountBaseUnits")]
+    pub amount_base_units: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
     pub to: String,
-    pub token_mint: String,
-    pub decimals: u8,
+    /// Either `token_mint` and `decimals` or the `token` symbol of a registered token
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub decimals: Option<u8>,
+    pub token: Option<String>,
+    /// `spl_token` or `token_2022`, the owner of the mint on `net` when left out and the classic
+    /// program without `net`. Every party has to sign for the same one
+    #[serde(alias = "tokenProgram")]
+    pub token_program: Option<TokenProgram>,
     pub memo: Option<String>,
+    /// Add the sender as a signer of the memo instruction
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    /// Memo program id, Memo v3 by default
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    #[serde(alias = "recentBlockHash")]
     pub recent_block_hash: String,
+    #[serde(deserialize_with = "pubkeys")]
     pub keys: Vec<String>,
+    #[serde(alias = "firstMessages")]
     pub first_messages: Vec<String>,
-    pub secret_state: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretState"
+    )]
+    pub secret_state: Option<String>,
+    /// `secret_state_id` of step one in place of `secret_state`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretStateId"
+    )]
+    pub secret_state_id: Option<String>,
+    /// Number of units or `"auto"`, which simulates the transaction on `net`
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Priority fee in micro-lamports per compute unit, every party has to pass the same
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Funds the recipient ATA instead of the aggregated key, and signs as well
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "rentSponsor")]
+    pub rent_sponsor: Option<String>,
+    /// Needed for `compute_unit_limit: "auto"`, also enables the source account checks
+    pub net: Option<Network>,
+    /// Spend from an account owned by someone else that delegated to the aggregated key
+    #[serde(default, alias = "allowDelegate")]
+    pub allow_delegate: bool,
+    /// `message_hash` from `/api/spl_build_message`, refuse to sign any other message
+    #[serde(alias = "expectedMessageHash")]
+    pub expected_message_hash: Option<String>,
+    /// Add the accounts of the signed message with their flags to the response
+    #[serde(default, alias = "includeAccountKeys")]
+    pub include_account_keys: bool,
+    /// Coordinator session the signature belongs to, echoed in the response's `context`
+    #[serde(default, alias = "sessionId")]
+    pub session_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplAggSendStepTwoResponse {
     pub partial_signature: String,
+    /// Position of this party's key in `keys`. The address it spends from is
+    /// `context.aggregated_pubkey`
+    #[serde(default)]
+    pub key_index: usize,
+    pub request_digest: String,
+    /// Id of the `signed` audit entry, for `/api/audit/{id}/message`
+    #[serde(default)]
+    pub audit_id: u64,
+    /// Pass `limit` on to the aggregation step
+    pub compute_unit_limit: Option<ComputeUnitReport>,
+    /// With `include_account_keys`, every account of the signed message with its flags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_keys: Option<Vec<InstructionAccount>>,
+    /// What the signature was made for, pass it on with `partial_signature` to the aggregation
+    #[serde(default)]
+    pub context: SignatureContext,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplAggregateSignaturesRequest {
-    pub signatures: Vec<String>,
-    pub amount: f64,
+    /// Base58 `partial_signature`s, or `{partial_signature, context}` objects as step two
+    /// returned them
+    pub signatures: Vec<SignatureSubmission>,
+    /// Coordinator session of the step two signatures, their contexts must name the same one
+    #[serde(default, alias = "sessionId")]
+    pub session_id: Option<String>,
+    /// Amount in whole tokens, alternatively pass `amount_base_units`
+    pub amount: Option<UiAmount>,
+    #[serde(alias = "amountBaseUnits")]
+    pub amount_base_units: Option<u64>,
+    #[serde(deserialize_with = "pubkey")]
     pub to: String,
-    pub token_mint: String,
-    pub decimals: u8,
+    /// Either `token_mint` and `decimals` or the `token` symbol of a registered token
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "tokenMint")]
+    pub token_mint: Option<String>,
+    pub decimals: Option<u8>,
+    pub token: Option<String>,
+    /// `spl_token` or `token_2022`, the owner of the mint on `net` when left out and the classic
+    /// program without `net`. Every party has to sign for the same one
+    #[serde(alias = "tokenProgram")]
+    pub token_program: Option<TokenProgram>,
     pub memo: Option<String>,
+    /// Add the sender as a signer of the memo instruction
+    #[serde(default, alias = "signedMemo")]
+    pub signed_memo: bool,
+    /// Memo program id, Memo v3 by default
+    #[serde(default, alias = "memoProgram")]
+    pub memo_program: Option<String>,
+    /// Solana Pay reference accounts, added in order to the transfer as read-only accounts
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub references: Vec<String>,
+    #[serde(alias = "recentBlockHash")]
     pub recent_block_hash: String,
-    pub net: Network,
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "pubkeys")]
     pub keys: Vec<String>,
+    /// The `limit` reported by step two
+    #[serde(alias = "computeUnitLimit")]
+    pub compute_unit_limit: Option<u32>,
+    /// The `priority_fee_micro_lamports` of step two
+    #[serde(default, alias = "priorityFeeMicroLamports")]
+    pub priority_fee_micro_lamports: Option<u64>,
+    /// Funds the recipient ATA instead of the aggregated key, and signs as well
+    #[serde(default, deserialize_with = "optional_pubkey", alias = "rentSponsor")]
+    pub rent_sponsor: Option<String>,
+    /// The rent sponsor's own signature of the message, required with `rent_sponsor`
+    #[serde(alias = "rentSponsorSignature")]
+    pub rent_sponsor_signature: Option<String>,
+    /// Hold the signed transaction and send it at this slot or time instead of right away
+    #[serde(alias = "broadcastAt")]
+    pub broadcast_at: Option<BroadcastAt>,
+    /// Return the signed transaction in `transaction` instead of sending it
+    #[serde(default, alias = "dryRun")]
+    pub dry_run: bool,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+    /// `false` answers as soon as the node accepted the transaction, with `status: "submitted"`
+    #[serde(default, alias = "waitForConfirmation")]
+    pub wait_for_confirmation: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SplAggregateSignaturesResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through, unset when it was scheduled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_endpoint: Option<String>,
+    /// Commitment the transaction had reached when the response was sent, at least the one
+    /// of `confirmation`. Unset when it was scheduled or not waited for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<Commitment>,
+    pub request_digest: String,
+    /// Set when the transaction was scheduled rather than sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_at: Option<BroadcastAt>,
+    /// An earlier attempt already sent the transaction, `transaction_id` is its signature
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate: bool,
+    /// Base64 of the signed transaction as `sendTransaction` takes it, only set for `dry_run`,
+    /// which sends nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+    /// `submitted` when the response didn't wait for the transaction, left out when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SendStatus>,
 }
 
 //-----------------------stake Account Creation
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct StakeAccountRequest {
-    pub net: Network,
-    pub keypair: String,   // Base58 encoded keypair
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String, // Base58 encoded keypair
+    #[serde(alias = "stakeAmount")]
     pub stake_amount: u64, // Amount to stake in lamports
-    pub seed: String,      // Seed for deriving the stake account
+    pub seed: String, // Seed for deriving the stake account
+    #[serde(alias = "validatorVoteAccont")]
     pub validator_vote_accont: String,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct StakeAccountResponse {
     pub stake_account_address: String,
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DeactivateStakeRequest {
-    pub net: Network,
-    pub keypair: String,       // Base58 encoded keypair
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String, // Base58 encoded keypair
+    #[serde(alias = "stakeAccount")]
     pub stake_account: String, // Stake account pubkey
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DeactivateStakeResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct WithdrawStakeRequest {
-    pub net: Network,
-    pub keypair: String,       // Base58 encoded keypair
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String, // Base58 encoded keypair
+    #[serde(alias = "stakeAccount")]
     pub stake_account: String, // Stake account pubkey
-    pub destination: String,   // Destination pubkey for withdrawn funds
-    pub amount: u64,           // Amount to withdraw in lamports
+    pub destination: String, // Destination pubkey for withdrawn funds
+    pub amount: u64,         // Amount to withdraw in lamports
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct WithdrawStakeResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggStakeStepOneRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String, // Base58 encoded keypair
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggStakeStepOneResponse {
     pub message_1: String,    // Base58 encoded AggMessage1
     pub secret_state: String, // Base58 encoded SecretAggStepOne
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggStakeStepTwoRequest {
-    pub net: Network,
-    pub keypair: String,   // Base58 encoded keypair
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String, // Base58 encoded keypair
+    #[serde(alias = "stakeAmount")]
     pub stake_amount: u64, // Amount to stake in lamports
-    pub seed: String,      // Seed for stake account
+    pub seed: String, // Seed for stake account
+    #[serde(alias = "validatorVoteAccont")]
     pub validator_vote_accont: String,
-    pub keys: Vec<String>,           // List of pubkeys for aggregation
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>, // List of pubkeys for aggregation
+    #[serde(alias = "firstMessages")]
     pub first_messages: Vec<String>, // Base58 encoded AggMessage1
-    pub secret_state: String,        // Base58 encoded SecretAggStepOne from step one
-    pub recent_block_hash: String,   // Base58 encoded recent blockhash
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretState"
+    )]
+    pub secret_state: Option<String>, // Base58 encoded SecretAggStepOne from step one
+    /// `secret_state_id` of step one in place of `secret_state`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretStateId"
+    )]
+    pub secret_state_id: Option<String>,
+    #[serde(alias = "recentBlockHash")]
+    pub recent_block_hash: String, // Base58 encoded recent blockhash
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggStakeStepTwoResponse {
     pub partial_signature: String, // Base58 encoded PartialSignature
+    /// Id of the `signed` audit entry, for `/api/audit/{id}/message`
+    #[serde(default)]
+    pub audit_id: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggDeactivateStakeStepOneRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String, // Base58 encoded keypair
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggDeactivateStakeStepOneResponse {
     pub message_1: String,    // Base58 encoded AggMessage1
     pub secret_state: String, // Base58 encoded SecretAggStepOne
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggDeactivateStakeStepTwoRequest {
-    pub net: Network,
-    pub keypair: String,             // Base58 encoded keypair
-    pub stake_account: String,       // Stake account pubkey
-    pub keys: Vec<String>,           // List of pubkeys for aggregation
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String, // Base58 encoded keypair
+    #[serde(alias = "stakeAccount")]
+    pub stake_account: String, // Stake account pubkey
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>, // List of pubkeys for aggregation
+    #[serde(alias = "firstMessages")]
     pub first_messages: Vec<String>, // Base58 encoded AggMessage1
-    pub secret_state: String,        // Base58 encoded SecretAggStepOne from step one
-    pub recent_block_hash: String,   // Base58 encoded recent blockhash
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretState"
+    )]
+    pub secret_state: Option<String>, // Base58 encoded SecretAggStepOne from step one
+    /// `secret_state_id` of step one in place of `secret_state`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretStateId"
+    )]
+    pub secret_state_id: Option<String>,
+    #[serde(alias = "recentBlockHash")]
+    pub recent_block_hash: String, // Base58 encoded recent blockhash
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggDeactivateStakeStepTwoResponse {
     pub partial_signature: String, // Base58 encoded PartialSignature
+    /// Id of the `signed` audit entry, for `/api/audit/{id}/message`
+    #[serde(default)]
+    pub audit_id: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggWithdrawStakeStepOneRequest {
+    #[serde(deserialize_with = "keypair")]
     pub keypair: String, // Base58 encoded keypair
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggWithdrawStakeStepOneResponse {
     pub message_1: String,    // Base58 encoded AggMessage1
     pub secret_state: String, // Base58 encoded SecretAggStepOne
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggWithdrawStakeStepTwoRequest {
-    pub net: Network,
-    pub keypair: String,             // Base58 encoded keypair
-    pub stake_account: String,       // Stake account pubkey
-    pub destination: String,         // Destination pubkey for withdrawn funds
-    pub amount: u64,                 // Amount to withdraw in lamports
-    pub keys: Vec<String>,           // List of pubkeys for aggregation
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String, // Base58 encoded keypair
+    #[serde(alias = "stakeAccount")]
+    pub stake_account: String, // Stake account pubkey
+    pub destination: String, // Destination pubkey for withdrawn funds
+    pub amount: u64,         // Amount to withdraw in lamports
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>, // List of pubkeys for aggregation
+    #[serde(alias = "firstMessages")]
     pub first_messages: Vec<String>, // Base58 encoded AggMessage1
-    pub secret_state: String,        // Base58 encoded SecretAggStepOne from step one
-    pub recent_block_hash: String,   // Base58 encoded recent blockhash
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretState"
+    )]
+    pub secret_state: Option<String>, // Base58 encoded SecretAggStepOne from step one
+    /// `secret_state_id` of step one in place of `secret_state`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretStateId"
+    )]
+    pub secret_state_id: Option<String>,
+    #[serde(alias = "recentBlockHash")]
+    pub recent_block_hash: String, // Base58 encoded recent blockhash
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggWithdrawStakeStepTwoResponse {
     pub partial_signature: String, // Base58 encoded PartialSignature
+    /// Id of the `signed` audit entry, for `/api/audit/{id}/message`
+    #[serde(default)]
+    pub audit_id: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateStakeSignaturesRequest {
-    pub net: Network,
+    pub net: Option<Network>,
+    #[serde(alias = "stakeAmount")]
     pub stake_amount: u64, // Amount to stake in lamports
-    pub seed: String,      // Seed for stake account
+    pub seed: String, // Seed for stake account
+    #[serde(alias = "validatorVoteAccont")]
     pub validator_vote_accont: String,
-    pub keys: Vec<String>,         // List of pubkeys
-    pub signatures: Vec<String>,   // Base58 encoded PartialSignatures
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>, // List of pubkeys
+    pub signatures: Vec<String>, // Base58 encoded PartialSignatures
+    #[serde(alias = "recentBlockHash")]
     pub recent_block_hash: String, // Base58 encoded recent blockhash
+    /// Hold the signed transaction and send it at this slot or time instead of right away
+    #[serde(alias = "broadcastAt")]
+    pub broadcast_at: Option<BroadcastAt>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateStakeSignaturesResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through, unset when it was scheduled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_endpoint: Option<String>,
+    /// Set when the transaction was scheduled rather than sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_at: Option<BroadcastAt>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateDeactivateStakeSignaturesRequest {
-    pub net: Network,
-    pub stake_account: String,     // Stake account pubkey
-    pub keys: Vec<String>,         // List of pubkeys
-    pub signatures: Vec<String>,   // Base58 encoded PartialSignatures
+    pub net: Option<Network>,
+    #[serde(alias = "stakeAccount")]
+    pub stake_account: String, // Stake account pubkey
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>, // List of pubkeys
+    pub signatures: Vec<String>, // Base58 encoded PartialSignatures
+    #[serde(alias = "recentBlockHash")]
     pub recent_block_hash: String, // Base58 encoded recent blockhash
+    /// Hold the signed transaction and send it at this slot or time instead of right away
+    #[serde(alias = "broadcastAt")]
+    pub broadcast_at: Option<BroadcastAt>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateDeactivateStakeSignaturesResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through, unset when it was scheduled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_endpoint: Option<String>,
+    /// Set when the transaction was scheduled rather than sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_at: Option<BroadcastAt>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateWithdrawStakeSignaturesRequest {
-    pub net: Network,
-    pub stake_account: String,     // Stake account pubkey
-    pub destination: String,       // Destination pubkey
-    pub amount: u64,               // Amount to withdraw in lamports
-    pub keys: Vec<String>,         // List of pubkeys
-    pub signatures: Vec<String>,   // Base58 encoded PartialSignatures
+    pub net: Option<Network>,
+    #[serde(alias = "stakeAccount")]
+    pub stake_account: String, // Stake account pubkey
+    pub destination: String, // Destination pubkey
+    pub amount: u64,         // Amount to withdraw in lamports
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>, // List of pubkeys
+    pub signatures: Vec<String>, // Base58 encoded PartialSignatures
+    #[serde(alias = "recentBlockHash")]
     pub recent_block_hash: String, // Base58 encoded recent blockhash
+    /// Hold the signed transaction and send it at this slot or time instead of right away
+    #[serde(alias = "broadcastAt")]
+    pub broadcast_at: Option<BroadcastAt>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AggregateWithdrawStakeSignaturesResponse {
     pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through, unset when it was scheduled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_endpoint: Option<String>,
+    /// Set when the transaction was scheduled rather than sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_at: Option<BroadcastAt>,
+}
+
+/// An instruction of another program, `data` base64 encoded.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ProgramInstruction {
+    #[serde(deserialize_with = "pubkey", alias = "programId")]
+    pub program_id: String,
+    #[serde(default)]
+    pub accounts: Vec<InstructionAccount>,
+    #[serde(default)]
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AggPayloadStepTwoRequest {
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String, // Base58 encoded keypair
+    /// Base64 encoded bytes to sign, anything but a transaction message of the aggregated key
+    pub payload: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    #[serde(alias = "firstMessages")]
+    pub first_messages: Vec<String>, // Base58 encoded AggMessage1
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretState"
+    )]
+    pub secret_state: Option<String>, // Base58 encoded SecretAggStepOne from step one
+    /// `secret_state_id` of step one in place of `secret_state`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "secretStateId"
+    )]
+    pub secret_state_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AggPayloadStepTwoResponse {
+    pub partial_signature: String, // Base58 encoded PartialSignature
+    /// Id of the `signed` audit entry, for `/api/audit/{id}/message`
+    #[serde(default)]
+    pub audit_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AggregatePayloadSignaturesRequest {
+    /// Base64, as signed by step two
+    pub payload: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    pub signatures: Vec<String>, // Base58 encoded PartialSignatures
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AggregatePayloadSignaturesResponse {
+    pub aggregated_public_key: String,
+    /// Base58 encoded signature of the payload by the aggregated key
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Ed25519VerifySendRequest {
+    pub net: Option<Network>,
+    /// Base64, as signed by step two
+    pub payload: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    pub signatures: Vec<String>, // Base58 encoded PartialSignatures
+    /// Called right after the Ed25519Program instruction proving the signature
+    pub instruction: ProgramInstruction,
+    /// Base58 encoded keypair paying for and signing the transaction, the only signer
+    #[serde(deserialize_with = "keypair", alias = "feePayerKeypair")]
+    pub fee_payer_keypair: String,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Ed25519VerifySendResponse {
+    pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
+    pub aggregated_public_key: String,
+    /// The aggregated key's signature of the payload, as the precompile checked it
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NonceCreateRequest {
+    pub net: Option<Network>,
+    /// Base58 encoded keypair paying for the account and its rent-exempt minimum
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String,
+    /// Authority advancing the nonce, the payer by default
+    #[serde(
+        default,
+        deserialize_with = "optional_pubkey",
+        alias = "nonceAuthority"
+    )]
+    pub nonce_authority: Option<String>,
+    /// Keys of a TSS ceremony, their aggregated key becomes the authority instead
+    #[serde(default, deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NonceCreateResponse {
+    pub nonce_account: String,
+    pub nonce_authority: String,
+    /// Base58 durable nonce to sign with, `None` while the node doesn't show the account yet
+    pub nonce: Option<String>,
+    /// The rent-exempt minimum it was funded with
+    pub lamports: u64,
+    pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NonceInfoRequest {
+    pub net: Option<Network>,
+    #[serde(deserialize_with = "pubkey", alias = "nonceAccount")]
+    pub nonce_account: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NonceInfoResponse {
+    pub nonce_account: String,
+    /// Base58 durable nonce a transaction signed now has to carry as its blockhash
+    pub nonce: String,
+    pub nonce_authority: String,
+    pub lamports: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NonceWithdrawRequest {
+    pub net: Option<Network>,
+    /// Base58 encoded keypair of the nonce authority, it signs and pays the fee
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String,
+    #[serde(deserialize_with = "pubkey", alias = "nonceAccount")]
+    pub nonce_account: String,
+    #[serde(deserialize_with = "pubkey")]
+    pub destination: String,
+    /// Lamports to withdraw, the whole balance when left out, which closes the account
+    pub lamports: Option<u64>,
+    /// Overrides the configured confirmation policy field by field
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationOverride>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NonceWithdrawResponse {
+    pub nonce_account: String,
+    pub lamports: u64,
+    /// The whole balance was withdrawn, the account is gone
+    pub closed: bool,
+    pub transaction_id: String,
+    /// Host of the RPC node the transaction was sent through
+    pub rpc_endpoint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdKeygenStepOneRequest {
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String,
+    /// Every party of the key in the same order, this party's own included
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    /// How many of `keys` have to sign
+    pub threshold: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdKeygenStepOneResponse {
+    /// Hand it to every party, its own step two included
+    pub message_1: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdKeygenStepTwoRequest {
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    pub threshold: usize,
+    /// The `message_1` of every one of `keys`
+    #[serde(alias = "firstMessages")]
+    pub first_messages: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdKeygenStepTwoResponse {
+    /// The address of the threshold key, the same for every party
+    pub group_key: String,
+    pub threshold: usize,
+    /// Position of this party's key in `keys`
+    pub key_index: usize,
+    /// Hex encoded SHA-256 of the first messages and the key this party saw
+    pub transcript_hash: String,
+    /// This party's signature of `transcript_hash`, hand it to every party's step three
+    pub confirmation: String,
+}
+
+/// Step two again, with the confirmations of every party.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdKeygenStepThreeRequest {
+    #[serde(deserialize_with = "keypair")]
+    pub keypair: String,
+    #[serde(deserialize_with = "pubkeys")]
+    pub keys: Vec<String>,
+    pub threshold: usize,
+    #[serde(alias = "firstMessages")]
+    pub first_messages: Vec<String>,
+    /// The `confirmation` of every one of `keys`, in their order
+    pub confirmations: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdKeygenStepThreeResponse {
+    pub group_key: String,
+    pub threshold: usize,
+    pub key_index: usize,
+    /// This party's share of the key, secret like `secret_state`. Sign with it in step two
+    pub key_share: String,
 }
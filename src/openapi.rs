@@ -0,0 +1,725 @@
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use schemars::{
+    JsonSchema,
+    r#gen::{SchemaGenerator, SchemaSettings},
+    schema::Schema,
+    visit::Visitor,
+};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "chaos")]
+use crate::chaos::{ChaosConfig, ChaosReport};
+use crate::{audit_export::ExportRecord, models::*, warning::Warned};
+
+// The spec is generated from the same types the handlers deserialize and serialize, through
+// their serde attributes, so it can't drift from what's on the wire. Each endpoint lists the
+// statuses its errors come with, every error body is an `ErrorResponse`.
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+type ParamsFn = fn(&mut SchemaGenerator) -> Vec<Value>;
+
+/// Errors of endpoints that only check their input.
+const INPUT: &[u16] = &[400, 422];
+/// Errors of endpoints that read from the RPC node.
+const READ: &[u16] = &[400, 404, 422, 502, 503];
+/// Errors of endpoints that sign or send, which also answer to policies and spend limits.
+const SEND: &[u16] = &[400, 403, 404, 409, 422, 502, 503];
+/// Errors of endpoints for admin API keys only.
+const ADMIN: &[u16] = &[403];
+
+/// One method of one route.
+pub struct Operation {
+    pub method: &'static str,
+    /// As the router knows it, `:id` rather than `{id}`
+    pub path: &'static str,
+    summary: &'static str,
+    tag: &'static str,
+    query: Option<ParamsFn>,
+    request: Option<SchemaFn>,
+    content_type: &'static str,
+    response: SchemaFn,
+    errors: &'static [u16],
+}
+
+fn schema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.subschema_for::<T>()
+}
+
+/// The fields of `T` as query parameters.
+fn query_params<T: JsonSchema>(generator: &mut SchemaGenerator) -> Vec<Value> {
+    let object = *generator
+        .root_schema_for::<T>()
+        .schema
+        .object
+        .unwrap_or_default();
+    object
+        .properties
+        .into_iter()
+        .map(|(name, schema)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": object.required.contains(&name),
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+fn get<Resp: JsonSchema>(
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+) -> Operation {
+    Operation {
+        method: "GET",
+        path,
+        summary,
+        tag,
+        query: None,
+        request: None,
+        content_type: "application/json",
+        response: schema::<Resp>,
+        errors: &[],
+    }
+}
+
+fn post<Req: JsonSchema, Resp: JsonSchema>(
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+) -> Operation {
+    Operation {
+        method: "POST",
+        request: Some(schema::<Req>),
+        ..get::<Resp>(path, tag, summary)
+    }
+}
+
+/// A POST without a body.
+fn action<Resp: JsonSchema>(
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+) -> Operation {
+    Operation {
+        method: "POST",
+        ..get::<Resp>(path, tag, summary)
+    }
+}
+
+impl Operation {
+    fn query<T: JsonSchema>(self) -> Self {
+        Self {
+            query: Some(query_params::<T>),
+            ..self
+        }
+    }
+
+    fn content_type(self, content_type: &'static str) -> Self {
+        Self {
+            content_type,
+            ..self
+        }
+    }
+
+    fn errors(self, errors: &'static [u16]) -> Self {
+        Self { errors, ..self }
+    }
+
+    fn describe(&self, generator: &mut SchemaGenerator) -> Value {
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            json!({
+                "description": "Success",
+                "content": { self.content_type: { "schema": (self.response)(generator) } },
+            }),
+        );
+        let error = schema::<ErrorResponse>(generator);
+        // Any endpoint can fail on a bug, with a body that isn't what it expects and on the
+        // rate limits
+        let mut errors = self.errors.to_vec();
+        errors.extend([400, 429, 500]);
+        // Only `/api/health` stays open when API keys are required
+        let guarded = self.path.starts_with("/api/") || self.path.starts_with("/ws/");
+        if guarded && self.path != "/api/health" {
+            errors.push(401);
+        }
+        errors.sort_unstable();
+        errors.dedup();
+        for status in errors {
+            responses.insert(
+                status.to_string(),
+                json!({
+                    "description": error_description(status),
+                    "content": { "application/json": { "schema": error } },
+                }),
+            );
+        }
+
+        let mut operation = json!({
+            "operationId": self.path.trim_start_matches('/').replace(['/', ':'], "_")
+                + "_"
+                + &self.method.to_lowercase(),
+            "summary": self.summary,
+            "tags": [self.tag],
+            "responses": responses,
+        });
+        let mut parameters: Vec<Value> = self
+            .path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                })
+            })
+            .collect();
+        if let Some(query) = self.query {
+            parameters.extend(query(generator));
+        }
+        if !parameters.is_empty() {
+            operation["parameters"] = Value::Array(parameters);
+        }
+        if let Some(request) = self.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": request(generator) } },
+            });
+        }
+        if self.tag == "admin" {
+            operation["security"] = json!([{ "api_key": [] }]);
+        }
+        operation
+    }
+}
+
+fn error_description(status: u16) -> &'static str {
+    match status {
+        400 => "The request is malformed or a value in it is invalid",
+        401 => "No API key, or one that isn't known, while keys are required",
+        403 => "Refused by a policy, a spend limit, or for want of an admin API key",
+        404 => "An account, secret state or entry the request names doesn't exist",
+        409 => "The request conflicts with what was signed or sent before",
+        422 => "Well formed, but can't be carried out as asked",
+        429 => "Too many requests from this client, retry after `retry_after_seconds`",
+        500 => "A bug in the service",
+        502 => "The RPC node answered with an error",
+        503 => "The RPC node couldn't be reached or is rate limiting",
+        _ => "Error",
+    }
+}
+
+/// Every endpoint the service serves, in the order of `ENDPOINTS`.
+pub fn operations() -> Vec<Operation> {
+    let operations = vec![
+        get::<GenerateKeypairResponse>("/api/generate", "keys", "Generate a keypair")
+            .query::<GenerateKeypairQuery>(),
+        post::<KeypairFromMnemonicRequest, GenerateKeypairResponse>(
+            "/api/keypair_from_mnemonic",
+            "keys",
+            "Derive a keypair from a BIP39 mnemonic",
+        )
+        .errors(INPUT),
+        post::<ConvertKeypairRequest, ConvertKeypairResponse>(
+            "/api/convert_keypair",
+            "keys",
+            "Convert a keypair between base58 and solana-keygen bytes",
+        )
+        .errors(INPUT),
+        get::<CapabilitiesResponse>(
+            "/api/capabilities",
+            "service",
+            "Endpoints, limits and error classes",
+        ),
+        get::<VersionResponse>("/api/version", "service", "Build and configuration"),
+        get::<SpendLimitsResponse>("/api/spend_limits", "admin", "Consumption of the spend limits")
+            .errors(ADMIN),
+        get::<HealthResponse>("/api/health", "service", "Health, optionally of the RPC nodes")
+            .query::<HealthQuery>(),
+        get::<RpcStatsResponse>("/api/rpc_stats", "service", "RPC call counters"),
+        get::<String>("/metrics", "service", "Prometheus metrics")
+            .content_type("text/plain; version=0.0.4"),
+        get::<StateStatsResponse>("/api/state_stats", "admin", "Sizes of the in-memory stores")
+            .errors(ADMIN),
+        action::<ReloadConfigResponse>(
+            "/api/admin/reload_config",
+            "admin",
+            "Reload the configuration file",
+        )
+        .errors(ADMIN),
+        action::<FlushCachesResponse>("/api/admin/flush_caches", "admin", "Drop cached reads")
+            .errors(ADMIN),
+        get::<UsageStatsResponse>(
+            "/api/admin/usage_stats",
+            "admin",
+            "How often request fields are used",
+        )
+        .errors(ADMIN),
+        get::<AuditMessageResponse>(
+            "/api/audit/:id/message",
+            "admin",
+            "The message of an audit log entry",
+        )
+        .errors(ADMIN),
+        get::<ExportRecord>(
+            "/api/audit/export",
+            "admin",
+            "Export the audit log, one record per line",
+        )
+        .query::<AuditExportQuery>()
+        .content_type("application/x-ndjson")
+        .errors(ADMIN),
+        post::<BalanceRequest, BalanceResponse>("/api/balance", "sol", "SOL balance")
+            .errors(READ),
+        post::<AirdropRequest, AirdropResponse>("/api/airdrop", "sol", "Request an airdrop")
+            .errors(SEND),
+        post::<AirdropBatchRequest, AirdropBatchResponse>(
+            "/api/airdrop_batch",
+            "sol",
+            "Request airdrops to several addresses",
+        )
+        .errors(SEND),
+        post::<SendSingleRequest, Warned<SendSingleResponse>>(
+            "/api/send_single",
+            "sol",
+            "Send SOL signed by a single keypair",
+        )
+        .errors(SEND),
+        post::<RecentBlockHashRequest, RecentBlockHashResponse>(
+            "/api/recent_block_hash",
+            "sol",
+            "Latest blockhash",
+        )
+        .errors(READ),
+        post::<AggregateKeysRequest, AggregateKeysResponse>(
+            "/api/aggregate_keys",
+            "tss",
+            "Aggregate the keys of a TSS group",
+        )
+        .errors(INPUT),
+        post::<AggSendStepOneRequest, AggSendStepOneResponse>(
+            "/api/agg_send_step_one",
+            "tss",
+            "TSS step one: a party's nonce commitment",
+        )
+        .errors(SEND),
+        post::<BuildMessageRequest, BuildMessageResponse>(
+            "/api/build_message",
+            "tss",
+            "The message a SOL transfer signs, for review",
+        )
+        .errors(READ),
+        post::<BuildMessageRequest, SimulationResponse>(
+            "/api/simulate",
+            "tss",
+            "Simulate a SOL transfer",
+        )
+        .errors(READ),
+        post::<AggSendStepTwoRequest, Warned<AggSendStepTwoResponse>>(
+            "/api/agg_send_step_two",
+            "tss",
+            "TSS step two: a party's partial signature",
+        )
+        .errors(SEND),
+        post::<ThresholdKeygenStepOneRequest, ThresholdKeygenStepOneResponse>(
+            "/api/threshold/keygen_step_one",
+            "threshold",
+            "Threshold keygen step one",
+        )
+        .errors(INPUT),
+        post::<ThresholdKeygenStepTwoRequest, ThresholdKeygenStepTwoResponse>(
+            "/api/threshold/keygen_step_two",
+            "threshold",
+            "Threshold keygen step two",
+        )
+        .errors(INPUT),
+        post::<ThresholdKeygenStepThreeRequest, ThresholdKeygenStepThreeResponse>(
+            "/api/threshold/keygen_step_three",
+            "threshold",
+            "Threshold keygen step three",
+        )
+        .errors(INPUT),
+        post::<AggregateSignaturesRequest, Warned<AggregateSignaturesResponse>>(
+            "/api/aggregate_signatures",
+            "tss",
+            "Aggregate the partial signatures and send",
+        )
+        .errors(SEND),
+        post::<BroadcastRequest, BroadcastResponse>(
+            "/api/broadcast",
+            "broadcast",
+            "Send a signed transaction",
+        )
+        .errors(SEND),
+        post::<BroadcastStatusRequest, BroadcastStatusResponse>(
+            "/api/broadcast_status",
+            "broadcast",
+            "Status of a sent transaction",
+        )
+        .errors(READ),
+        post::<TransactionStatusesRequest, TransactionStatusesResponse>(
+            "/api/transaction_statuses",
+            "broadcast",
+            "Statuses of several transactions",
+        )
+        .errors(READ),
+        // A WebSocket, its messages are `ConfirmationSubscription`s one way and these the other
+        get::<ConfirmationUpdate>(
+            "/ws/confirmations",
+            "broadcast",
+            "WebSocket streaming the commitment of the signatures sent on it",
+        ),
+        post::<ScheduledBroadcastStatusRequest, ScheduledBroadcastStatusResponse>(
+            "/api/scheduled_broadcast_status",
+            "broadcast",
+            "Status of a scheduled broadcast",
+        )
+        .errors(READ),
+        post::<CancelScheduledBroadcastRequest, CancelScheduledBroadcastResponse>(
+            "/api/cancel_scheduled_broadcast",
+            "broadcast",
+            "Cancel a scheduled broadcast",
+        )
+        .errors(READ),
+        get::<TokensResponse>("/api/tokens", "tokens", "The token registry"),
+        post::<TokenEntry, TokenEntry>("/api/tokens", "tokens", "Add or change a token")
+            .errors(SEND),
+        post::<DeleteTokenRequest, DeletedTokenEntry>(
+            "/api/delete_token",
+            "tokens",
+            "Delete a token, or purge a deleted one",
+        )
+        .errors(SEND),
+        post::<RestoreTokenRequest, TokenEntry>(
+            "/api/restore_token",
+            "tokens",
+            "Restore a deleted token",
+        )
+        .errors(SEND),
+        post::<SplTokenBalanceRequest, Warned<SplTokenBalanceResponse>>(
+            "/api/spl_token_balance",
+            "spl",
+            "SPL token balance",
+        )
+        .errors(READ),
+        post::<SplSendSingleRequest, SplSendSingleResponse>(
+            "/api/spl_send_single",
+            "spl",
+            "Send SPL tokens signed by a single keypair",
+        )
+        .errors(SEND),
+        post::<SplRentSummaryRequest, SplRentSummaryResponse>(
+            "/api/spl_rent_summary",
+            "spl",
+            "Token accounts and the rent they hold",
+        )
+        .errors(READ),
+        post::<SplCloseEmptyAccountsRequest, SplCloseEmptyAccountsResponse>(
+            "/api/spl_close_empty_accounts",
+            "spl",
+            "Close empty token accounts",
+        )
+        .errors(SEND),
+        post::<SplBuildMessageRequest, BuildMessageResponse>(
+            "/api/spl_build_message",
+            "spl",
+            "The message an SPL transfer signs, for review",
+        )
+        .errors(READ),
+        post::<SplCostPreviewRequest, SplCostPreviewResponse>(
+            "/api/spl_cost_preview",
+            "spl",
+            "Fees and rent of an SPL transfer",
+        )
+        .errors(READ),
+        post::<EstimateFeeRequest, EstimateFeeResponse>(
+            "/api/estimate_fee",
+            "spl",
+            "Fee of a transfer",
+        )
+        .errors(READ),
+        post::<SplBuildMessageRequest, SimulationResponse>(
+            "/api/spl_simulate",
+            "spl",
+            "Simulate an SPL transfer",
+        )
+        .errors(READ),
+        post::<SolanaPayParseRequest, SolanaPayParseResponse>(
+            "/api/solana_pay/parse",
+            "solana_pay",
+            "Parse a Solana Pay transfer request URL",
+        )
+        .errors(INPUT),
+        post::<SolanaPayCreateRequest, SolanaPayCreateResponse>(
+            "/api/solana_pay/create",
+            "solana_pay",
+            "Create a Solana Pay transfer request URL",
+        )
+        .errors(INPUT),
+        post::<SplAggSendStepTwoRequest, SplAggSendStepTwoResponse>(
+            "/api/spl_agg_send_step_two",
+            "spl",
+            "TSS step two of an SPL transfer",
+        )
+        .errors(SEND),
+        post::<SplAggregateSignaturesRequest, Warned<SplAggregateSignaturesResponse>>(
+            "/api/spl_aggregate_signatures",
+            "spl",
+            "Aggregate the partial signatures of an SPL transfer and send",
+        )
+        .errors(SEND),
+        post::<StakeAccountRequest, StakeAccountResponse>(
+            "/api/stake",
+            "stake",
+            "Create and delegate a stake account",
+        )
+        .errors(SEND),
+        post::<DeactivateStakeRequest, DeactivateStakeResponse>(
+            "/api/deactivate_stake",
+            "stake",
+            "Deactivate a stake account",
+        )
+        .errors(SEND),
+        post::<WithdrawStakeRequest, WithdrawStakeResponse>(
+            "/api/withdraw_stake",
+            "stake",
+            "Withdraw from a stake account",
+        )
+        .errors(SEND),
+        post::<NonceCreateRequest, NonceCreateResponse>(
+            "/api/nonce/create",
+            "nonce",
+            "Create a durable nonce account",
+        )
+        .errors(SEND),
+        post::<NonceInfoRequest, NonceInfoResponse>(
+            "/api/nonce/info",
+            "nonce",
+            "The nonce a nonce account holds",
+        )
+        .errors(READ),
+        post::<NonceWithdrawRequest, NonceWithdrawResponse>(
+            "/api/nonce/withdraw",
+            "nonce",
+            "Withdraw from a nonce account",
+        )
+        .errors(SEND),
+        post::<AggStakeStepTwoRequest, AggStakeStepTwoResponse>(
+            "/api/agg_stake_step_two",
+            "stake",
+            "TSS step two of a stake account creation",
+        )
+        .errors(SEND),
+        post::<AggDeactivateStakeStepTwoRequest, AggDeactivateStakeStepTwoResponse>(
+            "/api/agg_deactivate_stake_step_two",
+            "stake",
+            "TSS step two of a stake deactivation",
+        )
+        .errors(SEND),
+        post::<AggWithdrawStakeStepTwoRequest, AggWithdrawStakeStepTwoResponse>(
+            "/api/agg_withdraw_stake_step_two",
+            "stake",
+            "TSS step two of a stake withdrawal",
+        )
+        .errors(SEND),
+        post::<AggregateStakeSignaturesRequest, AggregateStakeSignaturesResponse>(
+            "/api/aggregate_stake_signatures",
+            "stake",
+            "Aggregate and send a stake account creation",
+        )
+        .errors(SEND),
+        post::<
+            AggregateDeactivateStakeSignaturesRequest,
+            AggregateDeactivateStakeSignaturesResponse,
+        >(
+            "/api/aggregate_deactivate_stake_signatures",
+            "stake",
+            "Aggregate and send a stake deactivation",
+        )
+        .errors(SEND),
+        post::<AggregateWithdrawStakeSignaturesRequest, AggregateWithdrawStakeSignaturesResponse>(
+            "/api/aggregate_withdraw_stake_signatures",
+            "stake",
+            "Aggregate and send a stake withdrawal",
+        )
+        .errors(SEND),
+        post::<AggPayloadStepTwoRequest, AggPayloadStepTwoResponse>(
+            "/api/agg_payload_step_two",
+            "tss",
+            "TSS step two over an arbitrary payload",
+        )
+        .errors(SEND),
+        post::<AggregatePayloadSignaturesRequest, AggregatePayloadSignaturesResponse>(
+            "/api/aggregate_payload_signatures",
+            "tss",
+            "Aggregate the partial signatures of a payload",
+        )
+        .errors(INPUT),
+        post::<Ed25519VerifySendRequest, Ed25519VerifySendResponse>(
+            "/api/ed25519_verify_send",
+            "tss",
+            "Send a transaction verifying an ed25519 signature on chain",
+        )
+        .errors(SEND),
+        get::<Map<String, Value>>("/api/openapi.json", "service", "This document"),
+        get::<String>("/api/docs", "service", "Swagger UI for this document")
+            .content_type("text/html; charset=utf-8"),
+    ];
+    #[cfg(feature = "chaos")]
+    let operations = operations
+        .into_iter()
+        .chain([
+            get::<ChaosReport>("/api/chaos", "admin", "Injected RPC faults").errors(ADMIN),
+            post::<ChaosConfig, ChaosReport>("/api/chaos", "admin", "Set the injected RPC faults")
+                .errors(ADMIN),
+        ])
+        .collect();
+    operations
+}
+
+/// `/api/audit/:id/message` as OpenAPI writes it, `/api/audit/{id}/message`.
+pub fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The OpenAPI 3.0 document of the service.
+pub fn document() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    for operation in operations() {
+        let item = paths
+            .entry(openapi_path(operation.path))
+            .or_insert_with(|| json!({}));
+        item[operation.method.to_lowercase()] = operation.describe(&mut generator);
+    }
+    // Subschemas skip the visitors that make them valid OpenAPI, such as nullable `$ref`s
+    let mut schemas = generator.take_definitions();
+    for visitor in generator.visitors_mut() {
+        for schema in schemas.values_mut() {
+            visitor.visit_schema(schema);
+        }
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Solana TSS API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "api_key": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+/// Where `docs_page` loads the files of Swagger UI from, see `docs_file`.
+const SWAGGER_UI: &str = "/api/docs";
+
+/// The files of Swagger UI `docs_page` loads.
+const DOCS_FILES: &[&str] = &["swagger-ui.css", "swagger-ui-bundle.js"];
+
+const DOCS_SCRIPT: &str = "SwaggerUIBundle({ url: '/api/openapi.json', dom_id: '#swagger-ui' });";
+
+/// Swagger UI for `/api/openapi.json`, with its files served by this API.
+pub fn docs_page() -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Solana TSS API</title>\n\
+         <link rel=\"stylesheet\" href=\"{SWAGGER_UI}/swagger-ui.css\">\n</head>\n<body>\n\
+         <div id=\"swagger-ui\"></div>\n<script src=\"{SWAGGER_UI}/swagger-ui-bundle.js\"></script>\n\
+         <script>{DOCS_SCRIPT}</script>\n</body>\n</html>\n"
+    )
+}
+
+/// Content type and body of the Swagger UI file `name` of `docs_page`. The files are compiled
+/// into the binary, the page loads nothing from another origin.
+pub fn docs_file(name: &str) -> Option<(String, Vec<u8>)> {
+    if !DOCS_FILES.contains(&name) {
+        return None;
+    }
+    let config = Arc::new(utoipa_swagger_ui::Config::from("/api/openapi.json"));
+    let file = utoipa_swagger_ui::serve(name, config).ok()??;
+    Some((file.content_type, file.bytes.into_owned()))
+}
+
+/// The `Content-Security-Policy` of `docs_page`: Swagger UI from this origin and the one inline
+/// script that starts it.
+pub fn docs_csp() -> String {
+    let hash = STANDARD.encode(Sha256::digest(DOCS_SCRIPT.as_bytes()));
+    format!(
+        "default-src 'self'; script-src 'self' 'sha256-{hash}'; \
+         style-src 'self' 'unsafe-inline'; img-src 'self' data:; frame-ancestors 'none'"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let document = document();
+        let schemas = &document["components"]["schemas"];
+        assert_eq!(
+            schemas["Network"]["enum"],
+            json!(["mainnet", "testnet", "devnet", "localnet"])
+        );
+        // Aliases and optional fields come from the serde attributes
+        let balance = &schemas["BalanceRequest"];
+        assert_eq!(balance["required"], json!(["address"]));
+        assert_eq!(
+            balance["properties"]["net"]["allOf"][0]["$ref"],
+            "#/components/schemas/Network"
+        );
+        assert_eq!(balance["properties"]["net"]["nullable"], true);
+
+        let audit = &document["paths"]["/api/audit/{id}/message"]["get"];
+        assert_eq!(audit["parameters"][0]["name"], "id");
+        assert_eq!(
+            audit["responses"]["403"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ErrorResponse"
+        );
+        let send = &document["paths"]["/api/send_single"]["post"];
+        for status in ["200", "400", "403", "429", "500", "503"] {
+            assert!(send["responses"].get(status).is_some(), "{}", status);
+        }
+        let warned = send["responses"]["200"]["content"]["application/json"]["schema"]["$ref"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches("#/components/schemas/");
+        assert!(schemas[warned]["properties"].get("warnings").is_some());
+
+        let generate = &document["paths"]["/api/generate"]["get"];
+        assert_eq!(generate["parameters"][0]["name"], "mnemonic");
+        assert_eq!(generate["parameters"][0]["in"], "query");
+    }
+
+    #[test]
+    fn test_docs_files_are_bundled() {
+        let page = docs_page();
+        for name in DOCS_FILES {
+            assert!(page.contains(&format!("{SWAGGER_UI}/{name}")), "{}", name);
+            let (_, body) = docs_file(name).unwrap();
+            assert!(!body.is_empty(), "{}", name);
+        }
+        assert_eq!(docs_file("swagger-initializer.js"), None);
+        assert!(!page.contains("https://"));
+        assert!(!docs_csp().contains("https://"));
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Deserialize;
+use solana_sdk::{message::Message, pubkey::Pubkey};
+
+use crate::Error;
+
+// A policy engine approving transactions goes by what they can change rather than by their
+// description. The writable policy names accounts no message signed here may write to, or the
+// only ones it may. It is checked on the compiled message, the one step two is about to sign
+// and the one an aggregation is about to send, so no instruction can slip past it.
+
+/// Accounts the messages this service signs may write to, see `writable_policy` in the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WritablePolicy {
+    /// Never writable
+    pub deny: Vec<String>,
+    /// When set, the only accounts besides the fee payer that may be writable
+    pub allow: Option<Vec<String>>,
+}
+
+/// Which rule of the policy a writable account broke.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyRule {
+    Deny,
+    Allow,
+}
+
+impl PolicyRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deny => "deny",
+            Self::Allow => "allow",
+        }
+    }
+}
+
+fn parse(list: &[String]) -> Vec<Pubkey> {
+    list.iter()
+        .filter_map(|address| address.trim().parse().ok())
+        .collect()
+}
+
+impl WritablePolicy {
+    /// Every listed address that isn't a pubkey, reported by the startup checks.
+    pub fn validate(&self) -> Result<(), String> {
+        let invalid: Vec<&str> = self
+            .deny
+            .iter()
+            .chain(self.allow.iter().flatten())
+            .filter(|address| address.trim().parse::<Pubkey>().is_err())
+            .map(String::as_str)
+            .collect();
+        match invalid.is_empty() {
+            true => Ok(()),
+            false => Err(format!("not pubkeys: {}", invalid.join(", "))),
+        }
+    }
+
+    /// Refuse `message` when it writes to a denied account, or with an allow list to any
+    /// account but the fee payer that isn't on it.
+    pub fn check(&self, message: &Message) -> Result<(), Error> {
+        let deny = parse(&self.deny);
+        let allow = self.allow.as_deref().map(parse);
+        for (i, account) in message.account_keys.iter().enumerate() {
+            if !message.is_writable(i) {
+                continue;
+            }
+            let rule = if deny.contains(account) {
+                PolicyRule::Deny
+            } else if allow
+                .as_ref()
+                .is_some_and(|allow| i != 0 && !allow.contains(account))
+            {
+                PolicyRule::Allow
+            } else {
+                continue;
+            };
+            return Err(Error::PolicyViolation {
+                account: *account,
+                rule,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{hash::Hash, pubkey::Pubkey};
+
+    use crate::{
+        Error,
+        policy::{PolicyRule, WritablePolicy},
+        transaction_builder::{SplTransferBuilder, TransferBuilder},
+        tss::{spl_transfer_message, transfer_message},
+        units::{Decimals, Lamports, RawTokenAmount},
+    };
+
+    #[test]
+    fn test_writable_accounts_against_the_policy() {
+        let (from, to, mint) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let sol = transfer_message(
+            &TransferBuilder::new(from, to, Lamports::new(5))
+                .memo(Some("invoice 7".to_string()))
+                .signed_memo(true),
+            Hash::new_unique(),
+        );
+        let spl = spl_transfer_message(
+            &SplTransferBuilder::new(
+                from,
+                to,
+                mint,
+                RawTokenAmount::new(5),
+                Decimals::new(6).unwrap(),
+            ),
+            Hash::new_unique(),
+        )
+        .unwrap();
+        let violation = |policy: &WritablePolicy, message| match policy.check(message) {
+            Err(Error::PolicyViolation { account, rule }) => Some((account, rule)),
+            Ok(()) => None,
+            Err(e) => panic!("unexpected {}", e),
+        };
+
+        let open = WritablePolicy::default();
+        assert_eq!(violation(&open, &sol), None);
+        assert_eq!(violation(&open, &spl), None);
+
+        let deny_to = WritablePolicy {
+            deny: vec![format!(" {} ", to)],
+            allow: None,
+        };
+        assert_eq!(violation(&deny_to, &sol), Some((to, PolicyRule::Deny)));
+        // The SPL transfer writes to the token accounts, the owner is only read
+        assert_eq!(violation(&deny_to, &spl), None);
+        // Neither the mint nor the memo program are ever written
+        let deny_read_only = WritablePolicy {
+            deny: vec![mint.to_string(), spl_memo::id().to_string()],
+            allow: None,
+        };
+        assert_eq!(violation(&deny_read_only, &sol), None);
+        assert_eq!(violation(&deny_read_only, &spl), None);
+
+        // The fee payer needn't be allowed, the recipient must
+        let allow_none = WritablePolicy {
+            deny: vec![],
+            allow: Some(vec![]),
+        };
+        assert_eq!(violation(&allow_none, &sol), Some((to, PolicyRule::Allow)));
+        let allow_to = WritablePolicy {
+            deny: vec![],
+            allow: Some(vec![to.to_string()]),
+        };
+        assert_eq!(violation(&allow_to, &sol), None);
+        // Denied wins over allowed, for the fee payer as well
+        let both = WritablePolicy {
+            deny: vec![from.to_string()],
+            allow: Some(vec![from.to_string(), to.to_string()]),
+        };
+        assert_eq!(violation(&both, &sol), Some((from, PolicyRule::Deny)));
+
+        let invalid = WritablePolicy {
+            deny: vec!["not a key".to_string()],
+            allow: Some(vec![to.to_string()]),
+        };
+        assert!(invalid.validate().unwrap_err().contains("not a key"));
+        assert!(both.validate().is_ok());
+    }
+}
@@ -0,0 +1,136 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use solana_sdk::{
+    packet::PACKET_DATA_SIZE, program_utils::limited_deserialize, sanitize::Sanitize,
+    signature::Signature, transaction::Transaction,
+};
+
+use crate::{Error, message_review::encode_transaction, models::TransactionEncoding};
+
+// `/api/broadcast` sends a transaction signed elsewhere, such as one `aggregate_signatures`
+// returned with `dry_run`. The service holds none of its keys, all it can do is make sure the
+// node gets what the caller meant: bytes that are exactly one legacy transaction, with every
+// signature it needs present and valid over its message. A transaction failing either is
+// refused here rather than by the node, whose answer doesn't tell the two apart.
+
+/// The signed transaction `encoded` holds, nothing before or after it.
+pub fn decode_transaction(
+    encoded: &str,
+    encoding: TransactionEncoding,
+) -> Result<Transaction, Error> {
+    let bytes = match encoding {
+        TransactionEncoding::Base64 => STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| Error::MalformedTransaction(format!("not base64: {}", e)))?,
+        TransactionEncoding::Base58 => bs58::decode(encoded.trim())
+            .into_vec()
+            .map_err(|e| Error::MalformedTransaction(format!("not base58: {}", e)))?,
+    };
+    if bytes.len() > PACKET_DATA_SIZE {
+        return Err(Error::MalformedTransaction(format!(
+            "{} bytes, at most {} fit in a packet",
+            bytes.len(),
+            PACKET_DATA_SIZE
+        )));
+    }
+    let transaction: Transaction = limited_deserialize(&bytes, PACKET_DATA_SIZE as u64)
+        .map_err(|_| Error::MalformedTransaction("not a serialized transaction".to_string()))?;
+    transaction
+        .sanitize()
+        .map_err(|e| Error::MalformedTransaction(e.to_string()))?;
+    let len = STANDARD
+        .decode(encode_transaction(&transaction))
+        .map_or(0, |wire| wire.len());
+    if len != bytes.len() {
+        return Err(Error::MalformedTransaction(format!(
+            "{} bytes after the transaction",
+            bytes.len().saturating_sub(len)
+        )));
+    }
+    Ok(transaction)
+}
+
+/// Check each signature of `transaction` against its message, naming the first signer whose
+/// signature is missing or doesn't verify.
+pub fn verify_signatures(transaction: &Transaction) -> Result<(), Error> {
+    let verified = transaction.verify_with_results();
+    match verified.iter().position(|valid| !valid) {
+        Some(i) => Err(Error::TransactionSignatureInvalid {
+            signer: transaction.message.account_keys[i],
+            missing: transaction.signatures[i] == Signature::default(),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        hash::Hash,
+        message::Message,
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction,
+    };
+
+    use super::*;
+
+    fn signed(payer: &Keypair) -> Transaction {
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        let message = Message::new(&[transfer], Some(&payer.pubkey()));
+        Transaction::new(&[payer], message, Hash::new_unique())
+    }
+
+    fn malformed(encoded: &str, encoding: TransactionEncoding) -> String {
+        match decode_transaction(encoded, encoding) {
+            Err(Error::MalformedTransaction(e)) => e,
+            other => panic!("expected a malformed transaction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_transaction() {
+        let transaction = signed(&Keypair::new());
+        let base64 = encode_transaction(&transaction);
+        let bytes = STANDARD.decode(&base64).unwrap();
+        let base58 = bs58::encode(&bytes).into_string();
+        for (encoded, encoding) in [
+            (&base64, TransactionEncoding::Base64),
+            (&base58, TransactionEncoding::Base58),
+        ] {
+            assert_eq!(decode_transaction(encoded, encoding).unwrap(), transaction);
+        }
+
+        assert!(malformed("AQ*=", TransactionEncoding::Base64).contains("base64"));
+        assert!(malformed("0OIl", TransactionEncoding::Base58).contains("base58"));
+        let truncated = STANDARD.encode(&bytes[..bytes.len() - 1]);
+        assert!(malformed(&truncated, TransactionEncoding::Base64).contains("transaction"));
+        let trailing = STANDARD.encode([&bytes[..], &[0, 0]].concat());
+        assert_eq!(
+            malformed(&trailing, TransactionEncoding::Base64),
+            "2 bytes after the transaction"
+        );
+        let oversized = STANDARD.encode(vec![0; PACKET_DATA_SIZE + 1]);
+        assert!(malformed(&oversized, TransactionEncoding::Base64).contains("packet"));
+    }
+
+    #[test]
+    fn test_verify_signatures() {
+        let payer = Keypair::new();
+        let transaction = signed(&payer);
+        verify_signatures(&transaction).unwrap();
+
+        let mut unsigned = transaction.clone();
+        unsigned.signatures[0] = Signature::default();
+        let mut forged = transaction;
+        forged.message.recent_blockhash = Hash::new_unique();
+        for (transaction, missing) in [(unsigned, true), (forged, false)] {
+            match verify_signatures(&transaction) {
+                Err(Error::TransactionSignatureInvalid {
+                    signer,
+                    missing: found,
+                }) => assert_eq!((signer, found), (payer.pubkey(), missing)),
+                other => panic!("expected an invalid signature, got {:?}", other),
+            }
+        }
+    }
+}
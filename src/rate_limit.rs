@@ -0,0 +1,204 @@
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use serde::Deserialize;
+
+use crate::bounded_store::{BoundedStore, StoreConfig, StoreStats};
+
+// Public RPC providers ban the IP that floods them, and that IP is the server's: one client
+// hammering `/api/airdrop` takes every network call down for everyone. Every client IP has a
+// token bucket per class of endpoints, each request takes a token and a request finding the
+// bucket empty is refused with a 429 before its handler runs. Endpoints calling the RPC node
+// get the smaller bucket, the ones answered locally a larger one. The limits are read from
+// the running config on every request, a reload changes them for buckets already filled.
+
+/// Requests a client can send at once, and how many it can send per second after that.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// The `rate_limits` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Endpoints calling the RPC node or its faucet
+    pub rpc: BucketConfig,
+    /// Endpoints answered without calling any node: key generation, aggregation, step one
+    pub local: BucketConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            rpc: BucketConfig {
+                burst: 20,
+                per_second: 2.0,
+            },
+            local: BucketConfig {
+                burst: 100,
+                per_second: 20.0,
+            },
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (class, bucket) in [("rpc", self.rpc), ("local", self.local)] {
+            if bucket.burst == 0 {
+                return Err(format!("{}.burst must be at least 1", class));
+            }
+            if !(bucket.per_second.is_finite() && bucket.per_second > 0.0) {
+                return Err(format!("{}.per_second must be more than 0", class));
+            }
+        }
+        Ok(())
+    }
+
+    fn bucket(&self, class: Class) -> BucketConfig {
+        match class {
+            Class::Rpc => self.rpc,
+            Class::Local => self.local,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Rpc,
+    Local,
+}
+
+/// Whether `path` is one of the paths of the route `pattern`, `:name` matching any one path
+/// segment.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let (mut pattern, mut path) = (pattern.split('/'), path.split('/'));
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(segment)) => {
+                if expected != segment && !(expected.starts_with(':') && !segment.is_empty()) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The buckets of the clients seen recently, requests without a peer IP share one.
+pub struct RateLimiter {
+    buckets: BoundedStore<(Option<IpAddr>, Class), Arc<Mutex<Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: StoreConfig) -> Self {
+        Self {
+            buckets: BoundedStore::new("rate_limit_buckets", config),
+        }
+    }
+
+    /// Take a token for a request of `client` to an endpoint of `class`. When there is none
+    /// left, `Err` with the whole seconds until there is.
+    pub fn check(
+        &self,
+        limits: &RateLimitConfig,
+        client: Option<IpAddr>,
+        class: Class,
+        now: Instant,
+    ) -> Result<(), u64> {
+        let config = limits.bucket(class);
+        let key = (client, class);
+        let bucket = match self.buckets.get(&key, now) {
+            Some(bucket) => bucket,
+            None => {
+                let bucket = Arc::new(Mutex::new(Bucket {
+                    tokens: config.burst as f64,
+                    updated: now,
+                }));
+                self.buckets.insert(key, bucket.clone(), now);
+                bucket
+            }
+        };
+        let mut bucket = bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * config.per_second).min(config.burst as f64);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(((1.0 - bucket.tokens) / config.per_second).ceil().max(1.0) as u64)
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        self.buckets.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        bounded_store::StoreConfig,
+        rate_limit::{BucketConfig, Class, RateLimitConfig, RateLimiter, matches},
+    };
+
+    #[test]
+    fn test_route_patterns() {
+        assert!(matches("/api/generate", "/api/generate"));
+        assert!(matches("/api/audit/:id/message", "/api/audit/7/message"));
+        assert!(!matches("/api/audit/:id/message", "/api/audit//message"));
+        assert!(!matches("/api/audit/:id/message", "/api/audit/7"));
+        assert!(!matches("/api/tokens", "/api/tokens/7"));
+    }
+
+    #[test]
+    fn test_buckets_refill() {
+        let limiter = RateLimiter::new(StoreConfig {
+            capacity: 10,
+            ttl_secs: None,
+        });
+        let limits = RateLimitConfig {
+            rpc: BucketConfig {
+                burst: 2,
+                per_second: 0.5,
+            },
+            ..RateLimitConfig::default()
+        };
+        let (client, other) = (Some([10, 0, 0, 1].into()), Some([10, 0, 0, 2].into()));
+        let now = std::time::Instant::now();
+        assert!(limiter.check(&limits, client, Class::Rpc, now).is_ok());
+        assert!(limiter.check(&limits, client, Class::Rpc, now).is_ok());
+        assert_eq!(limiter.check(&limits, client, Class::Rpc, now), Err(2));
+        // Other clients and the other class have buckets of their own
+        assert!(limiter.check(&limits, other, Class::Rpc, now).is_ok());
+        assert!(limiter.check(&limits, client, Class::Local, now).is_ok());
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check(&limits, client, Class::Rpc, later), Err(1));
+        let later = now + Duration::from_secs(2);
+        assert!(limiter.check(&limits, client, Class::Rpc, later).is_ok());
+        assert_eq!(limiter.stats().len, 3);
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let mut limits = RateLimitConfig::default();
+        assert!(limits.validate().is_ok());
+        limits.local.per_second = 0.0;
+        assert!(limits.validate().is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, system_program, sysvar};
+
+use crate::{
+    Error,
+    account_batch::AccountBatcher,
+    models::Network,
+    rpc_methods::unsupported_method,
+    spl_token_utils::TokenProgram,
+    warning::{Warning, WarningCode},
+};
+
+// A SOL transfer to a program, a sysvar or a token account lands fine and the lamports are
+// stuck there, nobody can sign them back out. The SOL send paths look at the recipient first
+// and refuse such an account unless the request says `allow_non_system_recipient: true`.
+// Addresses without an account are normal recipients, that's a new wallet.
+
+/// What's wrong with a recipient, for `Error::SuspiciousRecipient`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspiciousRecipient {
+    Program,
+    Sysvar,
+    TokenAccount,
+    ProgramOwned,
+}
+
+impl SuspiciousRecipient {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Program => "is an executable program",
+            Self::Sysvar => "is a sysvar",
+            Self::TokenAccount => {
+                "is owned by the SPL token program, send tokens with /api/spl_send_single or \
+                 /api/spl_aggregate_signatures instead"
+            }
+            Self::ProgramOwned => "is owned by a program, not the system program",
+        }
+    }
+}
+
+/// Fail with `SuspiciousRecipient` when `recipient` on `net` is an account lamports sent to it
+/// can't be taken back from, unless `allow_non_system` is set. The warning says the check was
+/// skipped.
+pub async fn check_recipient(
+    accounts: &AccountBatcher,
+    rpc_client: Arc<RpcClient>,
+    net: Network,
+    recipient: &Pubkey,
+    allow_non_system: bool,
+) -> Result<Option<Warning>, Error> {
+    if allow_non_system {
+        return Ok(None);
+    }
+    let account = match accounts.get_account(rpc_client, net, *recipient).await {
+        Ok(account) => account,
+        // Only a precaution, the transfer itself works without it
+        Err(e) if unsupported_method(&e).is_some() => {
+            let warning = WarningCode::CheckSkipped
+                .warning(format!("the recipient {} wasn't checked: {}", recipient, e))
+                .field("to");
+            return Ok(Some(warning));
+        }
+        Err(e) => return Err(Error::AccountFetchFailed(e)),
+    };
+    let Some(account) = account else {
+        return Ok(None);
+    };
+    let kind = if account.executable {
+        SuspiciousRecipient::Program
+    } else if account.owner == system_program::id() {
+        return Ok(None);
+    } else if account.owner == sysvar::id() {
+        SuspiciousRecipient::Sysvar
+    } else if TokenProgram::of(&account.owner).is_some() {
+        SuspiciousRecipient::TokenAccount
+    } else {
+        SuspiciousRecipient::ProgramOwned
+    };
+    Err(Error::SuspiciousRecipient {
+        recipient: *recipient,
+        owner: account.owner,
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::{
+        account::Account, bpf_loader_upgradeable, pubkey::Pubkey, system_program, sysvar,
+    };
+
+    use crate::{
+        Error,
+        account_batch::{AccountBatchConfig, AccountBatcher},
+        fixtures::{Accounts, disabled, packed, token_account},
+        models::Network,
+        recipient::{SuspiciousRecipient, check_recipient},
+    };
+
+    fn account(owner: Pubkey, executable: bool) -> Account {
+        Account {
+            lamports: 1_000_000,
+            data: vec![],
+            owner,
+            executable,
+            rent_epoch: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suspicious_recipients() {
+        let (program, wallet, token, missing) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let rpc_client = Arc::new(
+            Accounts::default()
+                .with(program, account(bpf_loader_upgradeable::id(), true))
+                .with(wallet, account(system_program::id(), false))
+                .with(sysvar::clock::id(), account(sysvar::id(), false))
+                .with(
+                    token,
+                    packed(token_account(Pubkey::new_unique(), wallet, 10)),
+                )
+                .async_rpc_client(),
+        );
+        let accounts = AccountBatcher::new(&AccountBatchConfig {
+            window_ms: 0,
+            ..AccountBatchConfig::default()
+        });
+        let check = |recipient: Pubkey, allow: bool| {
+            let (accounts, rpc_client) = (&accounts, rpc_client.clone());
+            async move {
+                check_recipient(accounts, rpc_client, Network::Devnet, &recipient, allow).await
+            }
+        };
+
+        assert_eq!(check(wallet, false).await.unwrap(), None);
+        assert_eq!(check(missing, false).await.unwrap(), None);
+        for (recipient, owner, expected) in [
+            (
+                program,
+                bpf_loader_upgradeable::id(),
+                SuspiciousRecipient::Program,
+            ),
+            (
+                sysvar::clock::id(),
+                sysvar::id(),
+                SuspiciousRecipient::Sysvar,
+            ),
+            (token, spl_token::id(), SuspiciousRecipient::TokenAccount),
+        ] {
+            let error = check(recipient, false).await.unwrap_err();
+            assert!(
+                matches!(error, Error::SuspiciousRecipient { kind, .. } if kind == expected),
+                "{}",
+                error
+            );
+            assert_eq!(error.error_code(), "SUSPICIOUS_RECIPIENT");
+            assert!(error.to_string().contains(&owner.to_string()), "{}", error);
+            check(recipient, true).await.unwrap();
+        }
+        let error = check(token, false).await.unwrap_err().to_string();
+        assert!(error.contains("/api/spl_send_single"), "{}", error);
+
+        // Skipped on a node that can't tell
+        let rpc_client = Arc::new(
+            disabled(
+                Accounts::default().with(program, account(bpf_loader_upgradeable::id(), true)),
+                &[RpcRequest::GetAccountInfo],
+            )
+            .async_rpc_client(),
+        );
+        let warning = check_recipient(&accounts, rpc_client, Network::Devnet, &program, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(warning.code, "CHECK_SKIPPED");
+        assert_eq!(warning.field.as_deref(), Some("to"));
+    }
+}
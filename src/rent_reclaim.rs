@@ -0,0 +1,259 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    message::Message, packet::PACKET_DATA_SIZE, program_option::COption, program_pack::Pack,
+    pubkey::Pubkey,
+};
+use spl_token::state::Account;
+
+use crate::{Error, transaction_builder::transaction_size};
+
+/// Offset of the owner field in an SPL token account.
+const OWNER_OFFSET: usize = 32;
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenAccountStatus {
+    /// Zero balance, can be closed by the owner
+    Closable,
+    NonEmpty,
+    Frozen,
+    /// A delegate is set, left alone so an existing approval isn't silently revoked
+    Delegated,
+    /// Someone other than the owner is the close authority
+    ForeignCloseAuthority,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScannedAccount {
+    pub address: Pubkey,
+    pub lamports: u64,
+    pub account: Account,
+}
+
+impl ScannedAccount {
+    pub fn status(&self, owner: &Pubkey) -> TokenAccountStatus {
+        let account = &self.account;
+        if account.is_frozen() {
+            TokenAccountStatus::Frozen
+        } else if account.amount > 0 {
+            TokenAccountStatus::NonEmpty
+        } else if account.delegate.is_some() {
+            TokenAccountStatus::Delegated
+        } else if account.close_authority.is_some()
+            && account.close_authority != COption::Some(*owner)
+        {
+            TokenAccountStatus::ForeignCloseAuthority
+        } else {
+            TokenAccountStatus::Closable
+        }
+    }
+}
+
+/// A group of closable accounts that fits in a single transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseChunk {
+    /// Derived from the chunk's accounts, so it stays the same as long as the plan does
+    pub id: String,
+    pub accounts: Vec<Pubkey>,
+    pub lamports: u64,
+}
+
+impl CloseChunk {
+    pub fn message(&self, owner: &Pubkey) -> Result<Message, Error> {
+        close_message(owner, &self.accounts)
+    }
+}
+
+fn close_message(owner: &Pubkey, accounts: &[Pubkey]) -> Result<Message, Error> {
+    let instructions = accounts
+        .iter()
+        .map(|account| {
+            spl_token::instruction::close_account(&spl_token::id(), account, owner, owner, &[])
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Message::new(&instructions, Some(owner)))
+}
+
+fn chunk_id(accounts: &[Pubkey]) -> String {
+    let mut hasher = Sha256::new();
+    for account in accounts {
+        hasher.update(account.as_ref());
+    }
+    hasher.finalize()[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Split the closable accounts into as few transactions as possible. Every transaction closes
+/// accounts in address order and sends the rent back to the owner.
+pub fn plan_chunks(owner: &Pubkey, accounts: &[ScannedAccount]) -> Result<Vec<CloseChunk>, Error> {
+    let mut chunks = Vec::new();
+    let mut current: Vec<&ScannedAccount> = Vec::new();
+    let finish = |current: &[&ScannedAccount]| {
+        let addresses: Vec<_> = current.iter().map(|a| a.address).collect();
+        CloseChunk {
+            id: chunk_id(&addresses),
+            lamports: current.iter().map(|a| a.lamports).sum(),
+            accounts: addresses,
+        }
+    };
+    for account in accounts
+        .iter()
+        .filter(|a| a.status(owner) == TokenAccountStatus::Closable)
+    {
+        current.push(account);
+        let addresses: Vec<_> = current.iter().map(|a| a.address).collect();
+        if current.len() > 1
+            && transaction_size(&close_message(owner, &addresses)?) > PACKET_DATA_SIZE
+        {
+            current.pop();
+            chunks.push(finish(&current));
+            current = vec![account];
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(finish(&current));
+    }
+    Ok(chunks)
+}
+
+/// Fetch every SPL token account of `owner`, sorted by address.
+pub async fn scan_token_accounts(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+) -> Result<Vec<ScannedAccount>, Error> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::DataSize(Account::LEN as u64),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                OWNER_OFFSET,
+                owner.to_bytes().to_vec(),
+            )),
+        ]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let mut accounts = rpc_client
+        .get_program_accounts_with_config(&spl_token::id(), config)
+        .await
+        .map_err(Error::TokenAccountScanFailed)?
+        .into_iter()
+        .map(|(address, account)| {
+            Ok(ScannedAccount {
+                address,
+                lamports: account.lamports,
+                account: Account::unpack(&account.data)?,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    accounts.sort_by_key(|a| a.address);
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{packet::PACKET_DATA_SIZE, program_option::COption, pubkey::Pubkey};
+    use spl_token::state::{Account, AccountState};
+
+    use crate::{
+        rent_reclaim::{ScannedAccount, TokenAccountStatus, plan_chunks},
+        transaction_builder::transaction_size,
+    };
+
+    fn scanned(owner: Pubkey, amount: u64) -> ScannedAccount {
+        ScannedAccount {
+            address: Pubkey::new_unique(),
+            lamports: 2_039_280,
+            account: Account {
+                mint: Pubkey::new_unique(),
+                owner,
+                amount,
+                state: AccountState::Initialized,
+                ..Account::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_classification() {
+        let owner = Pubkey::new_unique();
+        assert_eq!(
+            scanned(owner, 0).status(&owner),
+            TokenAccountStatus::Closable
+        );
+        assert_eq!(
+            scanned(owner, 1).status(&owner),
+            TokenAccountStatus::NonEmpty
+        );
+
+        let mut frozen = scanned(owner, 0);
+        frozen.account.state = AccountState::Frozen;
+        assert_eq!(frozen.status(&owner), TokenAccountStatus::Frozen);
+
+        let mut delegated = scanned(owner, 0);
+        delegated.account.delegate = COption::Some(Pubkey::new_unique());
+        assert_eq!(delegated.status(&owner), TokenAccountStatus::Delegated);
+
+        let mut own_authority = scanned(owner, 0);
+        own_authority.account.close_authority = COption::Some(owner);
+        assert_eq!(own_authority.status(&owner), TokenAccountStatus::Closable);
+        own_authority.account.close_authority = COption::Some(Pubkey::new_unique());
+        assert_eq!(
+            own_authority.status(&owner),
+            TokenAccountStatus::ForeignCloseAuthority
+        );
+    }
+
+    #[test]
+    fn test_chunks_fit_and_are_stable() {
+        let owner = Pubkey::new_unique();
+        let mut accounts: Vec<_> = (0..100).map(|i| scanned(owner, i % 3)).collect();
+        accounts.sort_by_key(|a| a.address);
+        let closable: Vec<_> = accounts
+            .iter()
+            .filter(|a| a.account.amount == 0)
+            .map(|a| a.address)
+            .collect();
+
+        let chunks = plan_chunks(&owner, &accounts).unwrap();
+        assert!(chunks.len() > 1);
+        let planned: Vec<_> = chunks.iter().flat_map(|c| c.accounts.clone()).collect();
+        assert_eq!(planned, closable);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let size = transaction_size(&chunk.message(&owner).unwrap());
+            assert!(size <= PACKET_DATA_SIZE);
+            assert_eq!(chunk.lamports, 2_039_280 * chunk.accounts.len() as u64);
+            // Only the last chunk may have room left for another account
+            if i + 1 < chunks.len() {
+                let mut bigger = chunk.accounts.clone();
+                bigger.push(chunks[i + 1].accounts[0]);
+                let next = super::close_message(&owner, &bigger).unwrap();
+                assert!(transaction_size(&next) > PACKET_DATA_SIZE);
+            }
+        }
+
+        assert_eq!(plan_chunks(&owner, &accounts).unwrap(), chunks);
+        accounts.remove(
+            accounts
+                .iter()
+                .position(|a| a.address == closable[0])
+                .unwrap(),
+        );
+        assert_ne!(plan_chunks(&owner, &accounts).unwrap()[0].id, chunks[0].id);
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use solana_sdk::{hash::Hash, pubkey::Pubkey};
+
+use crate::{
+    Error,
+    spl_token_utils::TokenProgram,
+    units::{Decimals, Lamports, RawTokenAmount, UiAmount, base_units_in},
+};
+
+// The digest identifies "the transfer being signed" across every party and service, so only
+// the fields that end up in the message are hashed: no keypairs, nonces, signatures or network.
+// Bump `DIGEST_VERSION` whenever the canonical form below changes.
+pub const DIGEST_VERSION: u8 = 8;
+
+/// The transfer parameters of a signing ceremony, amounts are always in base units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer<'a> {
+    Sol {
+        to: &'a Pubkey,
+        lamports: Lamports,
+        memo: Option<&'a str>,
+        signed_memo: bool,
+        memo_program: &'a Pubkey,
+        compute_unit_limit: Option<u32>,
+        compute_unit_price: Option<u64>,
+        references: &'a [Pubkey],
+        /// Signed against its nonce instead of a recent blockhash
+        nonce_account: Option<&'a Pubkey>,
+    },
+    Spl {
+        to: &'a Pubkey,
+        token_mint: &'a Pubkey,
+        token_program: TokenProgram,
+        amount: RawTokenAmount,
+        decimals: Decimals,
+        memo: Option<&'a str>,
+        signed_memo: bool,
+        memo_program: &'a Pubkey,
+        compute_unit_limit: Option<u32>,
+        compute_unit_price: Option<u64>,
+        references: &'a [Pubkey],
+        rent_sponsor: Option<&'a Pubkey>,
+    },
+}
+
+/// Resolve a SOL amount given as `amount` (SOL), as `lamports` or as both naming the same
+/// number of lamports.
+pub fn resolve_lamports(
+    amount: Option<&UiAmount>,
+    lamports: Option<u64>,
+) -> Result<Lamports, Error> {
+    resolve_base_units(amount, lamports, "lamports", Decimals::SOL).map(Lamports::new)
+}
+
+/// Resolve a token amount given as `amount` (whole tokens), as `amount_base_units` or as both
+/// naming the same number of base units.
+pub fn resolve_token_amount(
+    amount: Option<&UiAmount>,
+    base_units: Option<u64>,
+    decimals: Decimals,
+) -> Result<RawTokenAmount, Error> {
+    resolve_base_units(amount, base_units, "amount_base_units", decimals).map(RawTokenAmount::new)
+}
+
+/// Every step of a ceremony goes through here, a party given the exact integer and one given
+/// the UI amount sign the same message as long as both name the same base units.
+fn resolve_base_units(
+    amount: Option<&UiAmount>,
+    units: Option<u64>,
+    field: &str,
+    decimals: Decimals,
+) -> Result<u64, Error> {
+    let from_amount = amount
+        .map(|amount| amount.raw_in("amount", decimals))
+        .transpose()?;
+    let exact = units.map(|units| base_units_in(field, units)).transpose()?;
+    match (from_amount, exact) {
+        (None, None) => Err(Error::InvalidAmount(format!(
+            "one of amount or {} is required",
+            field
+        ))),
+        (Some(raw), None) => Ok(raw.get()),
+        (None, Some(units)) => Ok(units),
+        (Some(raw), Some(units)) if raw.get() == units => Ok(units),
+        (Some(raw), Some(units)) => Err(Error::AmountRejected {
+            field: field.to_string(),
+            reason: format!(
+                "{} conflicts with amount {}, which is {} base units",
+                units,
+                amount.map(UiAmount::to_string).unwrap_or_default(),
+                raw
+            ),
+        }),
+    }
+}
+
+/// Canonical JSON of a ceremony: object keys sorted, no whitespace, pubkeys and hashes in
+/// base58, absent optional fields written out as `null`. `keys` keeps the caller's order since
+/// the aggregated key depends on it, `references` keep theirs since the transfer lists them so.
+pub fn canonical_json(transfer: &Transfer, recent_block_hash: &Hash, keys: &[Pubkey]) -> String {
+    let mut fields: BTreeMap<&str, Value> = BTreeMap::new();
+    fields.insert("version", json!(DIGEST_VERSION));
+    fields.insert("recent_block_hash", json!(recent_block_hash.to_string()));
+    fields.insert("keys", pubkeys(keys));
+    match transfer {
+        Transfer::Sol {
+            to,
+            lamports,
+            memo,
+            signed_memo,
+            memo_program,
+            compute_unit_limit,
+            compute_unit_price,
+            references,
+            nonce_account,
+        } => {
+            fields.insert("kind", json!("sol_transfer"));
+            fields.insert("to", json!(to.to_string()));
+            fields.insert("lamports", json!(lamports));
+            fields.insert("memo", json!(memo));
+            fields.insert("signed_memo", json!(signed_memo));
+            fields.insert("memo_program", json!(memo_program.to_string()));
+            fields.insert("compute_unit_limit", json!(compute_unit_limit));
+            fields.insert("compute_unit_price", json!(compute_unit_price));
+            fields.insert("references", pubkeys(references));
+            fields.insert("nonce_account", json!(nonce_account.map(Pubkey::to_string)));
+        }
+        Transfer::Spl {
+            to,
+            token_mint,
+            token_program,
+            amount,
+            decimals,
+            memo,
+            signed_memo,
+            memo_program,
+            compute_unit_limit,
+            compute_unit_price,
+            references,
+            rent_sponsor,
+        } => {
+            fields.insert("kind", json!("spl_transfer"));
+            fields.insert("to", json!(to.to_string()));
+            fields.insert("token_mint", json!(token_mint.to_string()));
+            fields.insert("amount", json!(amount));
+            fields.insert("decimals", json!(decimals));
+            fields.insert("memo", json!(memo));
+            fields.insert("signed_memo", json!(signed_memo));
+            fields.insert("memo_program", json!(memo_program.to_string()));
+            fields.insert("compute_unit_limit", json!(compute_unit_limit));
+            fields.insert("compute_unit_price", json!(compute_unit_price));
+            fields.insert("references", pubkeys(references));
+            fields.insert("rent_sponsor", json!(rent_sponsor.map(Pubkey::to_string)));
+            // Only written out for Token-2022, classic transfers keep the digests they had
+            if *token_program == TokenProgram::Token2022 {
+                fields.insert("token_program", json!(token_program));
+            }
+        }
+    }
+    // A BTreeMap serializes its keys in order whatever serde_json features are enabled
+    serde_json::to_string(&fields).unwrap_or_default()
+}
+
+fn pubkeys(keys: &[Pubkey]) -> Value {
+    json!(keys.iter().map(Pubkey::to_string).collect::<Vec<_>>())
+}
+
+/// Hex encoded SHA-256 of `canonical_json`.
+pub fn request_digest(transfer: &Transfer, recent_block_hash: &Hash, keys: &[Pubkey]) -> String {
+    let hash = Sha256::digest(canonical_json(transfer, recent_block_hash, keys).as_bytes());
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{hash::Hash, pubkey::Pubkey};
+
+    use crate::Error;
+    use crate::models::AggSendStepTwoRequest;
+    use crate::request_digest::{
+        Transfer, canonical_json, request_digest, resolve_lamports, resolve_token_amount,
+    };
+    use crate::spl_token_utils::TokenProgram;
+    use crate::transaction_builder::resolve_memo_program;
+    use crate::units::{Decimals, Lamports, RawTokenAmount, UiAmount};
+
+    fn digest_of(req: &AggSendStepTwoRequest) -> String {
+        let to: Pubkey = req.to.parse().unwrap();
+        let keys: Vec<Pubkey> = req.keys.iter().map(|k| k.parse().unwrap()).collect();
+        let memo_program = resolve_memo_program(req.memo_program.as_deref(), false).unwrap();
+        let transfer = Transfer::Sol {
+            to: &to,
+            lamports: resolve_lamports(req.amount.as_ref(), req.lamports).unwrap(),
+            memo: req.memo.as_deref(),
+            signed_memo: req.signed_memo,
+            memo_program: &memo_program,
+            compute_unit_limit: None,
+            compute_unit_price: req.priority_fee_micro_lamports,
+            references: &[],
+            nonce_account: None,
+        };
+        request_digest(&transfer, &req.recent_block_hash.parse().unwrap(), &keys)
+    }
+
+    #[test]
+    fn test_equivalent_requests_hash_identically() {
+        let to = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let hash = Hash::new_unique();
+        let request =
+            |body: String| -> AggSendStepTwoRequest { serde_json::from_str(&body).unwrap() };
+        let common = format!(
+            r#""keypair": "", "to": "{}", "recent_block_hash": "{}", "keys": ["{}", "{}"],
+               "first_messages": [], "secret_state": """#,
+            to, hash, keys[0], keys[1]
+        );
+
+        let by_amount = request(format!(r#"{{ "amount": 1.5, {} }}"#, common));
+        let by_lamports = request(format!(r#"{{ "lamports": 1500000000, {} }}"#, common));
+        let explicit_memo = request(format!(r#"{{ "memo": null, "amount": 1.5, {} }}"#, common));
+        assert_eq!(digest_of(&by_amount), digest_of(&by_lamports));
+        assert_eq!(digest_of(&by_amount), digest_of(&explicit_memo));
+
+        let with_memo = request(format!(r#"{{ "memo": "", "amount": 1.5, {} }}"#, common));
+        assert_ne!(digest_of(&by_amount), digest_of(&with_memo));
+        let signed_memo = request(format!(
+            r#"{{ "memo": "", "signed_memo": true, "amount": 1.5, {} }}"#,
+            common
+        ));
+        assert_ne!(digest_of(&with_memo), digest_of(&signed_memo));
+        let default_program = request(format!(
+            r#"{{ "memo": "", "memo_program": "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr", "amount": 1.5, {} }}"#,
+            common
+        ));
+        assert_eq!(digest_of(&with_memo), digest_of(&default_program));
+        let memo_v1 = request(format!(
+            r#"{{ "memo": "", "memo_program": "Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo", "amount": 1.5, {} }}"#,
+            common
+        ));
+        assert_ne!(digest_of(&with_memo), digest_of(&memo_v1));
+        let priority_fee = request(format!(
+            r#"{{ "amount": 1.5, "priority_fee_micro_lamports": 25000, {} }}"#,
+            common
+        ));
+        assert_ne!(digest_of(&by_amount), digest_of(&priority_fee));
+        let swapped_keys = request(format!(
+            r#"{{ "amount": 1.5, "keypair": "", "to": "{}", "recent_block_hash": "{}",
+                 "keys": ["{}", "{}"], "first_messages": [], "secret_state": "" }}"#,
+            to, hash, keys[1], keys[0]
+        ));
+        assert_ne!(digest_of(&by_amount), digest_of(&swapped_keys));
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let to = Pubkey::new_from_array([1; 32]);
+        let mint = Pubkey::new_from_array([2; 32]);
+        let key = Pubkey::new_from_array([3; 32]);
+        let hash = Hash::new_from_array([4; 32]);
+        let transfer = Transfer::Spl {
+            to: &to,
+            token_mint: &mint,
+            token_program: TokenProgram::SplToken,
+            amount: RawTokenAmount::new(2_500_000),
+            decimals: Decimals::new(6).unwrap(),
+            memo: None,
+            signed_memo: false,
+            memo_program: &spl_memo::id(),
+            compute_unit_limit: None,
+            compute_unit_price: None,
+            references: &[],
+            rent_sponsor: None,
+        };
+        assert_eq!(
+            canonical_json(&transfer, &hash, &[key]),
+            format!(
+                r#"{{"amount":2500000,"compute_unit_limit":null,"compute_unit_price":null,"decimals":6,"keys":["{}"],"kind":"spl_transfer","memo":null,"memo_program":"{}","recent_block_hash":"{}","references":[],"rent_sponsor":null,"signed_memo":false,"to":"{}","token_mint":"{}","version":8}}"#,
+                key,
+                spl_memo::id(),
+                hash,
+                to,
+                mint
+            )
+        );
+        // Pinned so an accidental change of the canonical form shows up as a test failure
+        assert_eq!(
+            request_digest(&transfer, &hash, &[key]),
+            "1258294b72913e8caa8c0fb448aba2d74208d482d206c11122dbd17f064f72c3"
+        );
+        let token_2022 = Transfer::Spl {
+            to: &to,
+            token_mint: &mint,
+            token_program: TokenProgram::Token2022,
+            amount: RawTokenAmount::new(2_500_000),
+            decimals: Decimals::new(6).unwrap(),
+            memo: None,
+            signed_memo: false,
+            memo_program: &spl_memo::id(),
+            compute_unit_limit: None,
+            compute_unit_price: None,
+            references: &[],
+            rent_sponsor: None,
+        };
+        assert!(
+            canonical_json(&token_2022, &hash, &[key]).contains(r#""token_program":"token_2022""#)
+        );
+        assert_ne!(
+            request_digest(&transfer, &hash, &[key]),
+            request_digest(&token_2022, &hash, &[key])
+        );
+        assert_ne!(
+            request_digest(&transfer, &hash, &[key]),
+            request_digest(
+                &Transfer::Sol {
+                    to: &to,
+                    lamports: Lamports::new(2_500_000),
+                    memo: None,
+                    signed_memo: false,
+                    memo_program: &spl_memo::id(),
+                    compute_unit_limit: None,
+                    compute_unit_price: None,
+                    references: &[],
+                    nonce_account: None,
+                },
+                &hash,
+                &[key]
+            )
+        );
+    }
+
+    #[test]
+    fn test_resolve_lamports() {
+        let sol = |amount: f64| Some(UiAmount::Number(amount));
+        assert_eq!(
+            resolve_lamports(sol(0.5).as_ref(), None).unwrap().get(),
+            500_000_000
+        );
+        assert_eq!(
+            resolve_lamports(Some(&"0.000000007".into()), None)
+                .unwrap()
+                .get(),
+            7
+        );
+        assert_eq!(resolve_lamports(None, Some(7)).unwrap().get(), 7);
+        assert!(resolve_lamports(None, None).is_err());
+        // Both are fine as long as they agree
+        assert_eq!(
+            resolve_lamports(sol(0.1).as_ref(), Some(100_000_000))
+                .unwrap()
+                .get(),
+            100_000_000
+        );
+        match resolve_lamports(sol(0.1).as_ref(), Some(100_000_001)) {
+            Err(Error::AmountRejected { field, reason }) => {
+                assert_eq!(field, "lamports");
+                assert_eq!(
+                    reason,
+                    "100000001 conflicts with amount 0.1, which is 100000000 base units"
+                );
+            }
+            other => panic!("expected conflicting amounts, got {:?}", other),
+        }
+        assert!(resolve_lamports(sol(-1.0).as_ref(), None).is_err());
+        assert!(resolve_lamports(sol(f64::NAN).as_ref(), None).is_err());
+        for zero in [
+            resolve_lamports(sol(0.0).as_ref(), None),
+            resolve_lamports(None, Some(0)),
+        ] {
+            assert!(matches!(zero, Err(Error::AmountRejected { .. })));
+        }
+    }
+
+    #[test]
+    fn test_resolve_token_amount() {
+        let usdc = Decimals::new(6).unwrap();
+        let amount: UiAmount = "1.5".into();
+        assert_eq!(
+            resolve_token_amount(Some(&amount), None, usdc).unwrap(),
+            RawTokenAmount::new(1_500_000)
+        );
+        assert_eq!(
+            resolve_token_amount(None, Some(1_500_000), usdc).unwrap(),
+            RawTokenAmount::new(1_500_000)
+        );
+        assert!(resolve_token_amount(Some(&amount), Some(1_500_000), usdc).is_ok());
+        match resolve_token_amount(Some(&amount), Some(1_499_999), usdc) {
+            Err(Error::AmountRejected { field, .. }) => assert_eq!(field, "amount_base_units"),
+            other => panic!("expected conflicting amounts, got {:?}", other),
+        }
+        assert!(resolve_token_amount(None, None, usdc).is_err());
+        assert!(resolve_token_amount(None, Some(0), usdc).is_err());
+    }
+}
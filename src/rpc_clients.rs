@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use serde_json::Value;
+use solana_client::{
+    client_error::Result as ClientResult,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_request::RpcRequest,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use tracing::Instrument;
+
+use crate::{
+    bounded_store::{BoundedStore, StoreConfig, StoreStats},
+    models::Network,
+};
+
+// An `RpcClient` owns an HTTP client with its own connection pool, building one per request
+// opens a new connection and goes through a TLS handshake for every call. Handlers share the
+// clients kept here instead: one per network for its configured URL, and the most recently
+// used ones of the URLs requests named in `rpc_url`, bounded by `state_limits.rpc_clients`.
+// A reload that points a network at another URL gets a new client on the next request.
+// Every call a client built by `traced` makes runs in an `rpc` span naming the method, and
+// failed calls are logged.
+
+pub struct RpcClients {
+    configured: Mutex<HashMap<Network, (String, Arc<RpcClient>)>>,
+    requested: BoundedStore<(Network, String), Arc<RpcClient>>,
+}
+
+impl RpcClients {
+    pub fn new(requested: StoreConfig) -> Self {
+        Self {
+            configured: Mutex::new(HashMap::new()),
+            requested: BoundedStore::new("rpc_clients", requested),
+        }
+    }
+
+    /// The client of `net` at its configured `url`, made by `build` on first use and when the
+    /// URL changed.
+    pub fn configured(
+        &self,
+        net: Network,
+        url: &str,
+        build: impl FnOnce() -> RpcClient,
+    ) -> Arc<RpcClient> {
+        let mut clients = self.configured.lock().unwrap();
+        match clients.get(&net) {
+            Some((current, rpc_client)) if current == url => rpc_client.clone(),
+            _ => {
+                let rpc_client = Arc::new(build());
+                clients.insert(net, (url.to_string(), rpc_client.clone()));
+                rpc_client
+            }
+        }
+    }
+
+    /// The client of a `url` a request sent in `rpc_url` for `net`, made by `build` unless it's
+    /// still kept.
+    pub fn requested(
+        &self,
+        net: Network,
+        url: &str,
+        build: impl FnOnce() -> RpcClient,
+        now: Instant,
+    ) -> Arc<RpcClient> {
+        let key = (net, url.to_string());
+        if let Some(rpc_client) = self.requested.get(&key, now) {
+            return rpc_client;
+        }
+        let rpc_client = Arc::new(build());
+        self.requested.insert(key, rpc_client.clone(), now);
+        rpc_client
+    }
+
+    /// Size and evictions of the clients of requested URLs.
+    pub fn stats(&self) -> StoreStats {
+        self.requested.stats()
+    }
+}
+
+/// A client for the node at `url` whose calls are traced.
+pub fn traced(url: String) -> RpcClient {
+    RpcClient::new_sender(
+        TracedSender {
+            upstream: RpcClient::new(url.clone()),
+            url,
+        },
+        RpcClientConfig::default(),
+    )
+}
+
+struct TracedSender {
+    upstream: RpcClient,
+    url: String,
+}
+
+#[async_trait]
+impl RpcSender for TracedSender {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let span = tracing::info_span!("rpc", method = %request);
+        let start = Instant::now();
+        let result = self
+            .upstream
+            .send(request, params)
+            .instrument(span.clone())
+            .await;
+        let elapsed_ms = start.elapsed().as_millis() as u64;
+        span.in_scope(|| match &result {
+            Ok(_) => tracing::debug!(elapsed_ms, "rpc call"),
+            Err(e) => tracing::warn!(elapsed_ms, error = %e, "rpc call failed"),
+        });
+        result
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.upstream.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(url: &str) -> impl FnOnce() -> RpcClient {
+        let url = url.to_string();
+        move || RpcClient::new(url)
+    }
+
+    #[test]
+    fn test_configured_clients_are_shared() {
+        let clients = RpcClients::new(StoreConfig {
+            capacity: 1,
+            ttl_secs: None,
+        });
+        let devnet = Network::Devnet.get_cluster_url();
+        let first = clients.configured(Network::Devnet, devnet, build(devnet));
+        let again = clients.configured(Network::Devnet, devnet, || unreachable!());
+        assert!(Arc::ptr_eq(&first, &again));
+
+        let testnet = Network::Testnet.get_cluster_url();
+        let other = clients.configured(Network::Testnet, testnet, build(testnet));
+        assert!(!Arc::ptr_eq(&first, &other));
+        assert_eq!(other.url(), testnet);
+
+        // A reloaded URL replaces the network's client
+        let moved = "https://my-provider.example/rpc";
+        let replaced = clients.configured(Network::Devnet, moved, build(moved));
+        assert_eq!(replaced.url(), moved);
+        let again = clients.configured(Network::Devnet, moved, || unreachable!());
+        assert!(Arc::ptr_eq(&replaced, &again));
+    }
+
+    #[test]
+    fn test_requested_clients_are_bounded() {
+        let now = Instant::now();
+        let clients = RpcClients::new(StoreConfig {
+            capacity: 2,
+            ttl_secs: None,
+        });
+        let urls = ["http://a.example", "http://b.example", "http://c.example"];
+        let a = clients.requested(Network::Devnet, urls[0], build(urls[0]), now);
+        let again = clients.requested(Network::Devnet, urls[0], || unreachable!(), now);
+        assert!(Arc::ptr_eq(&a, &again));
+
+        // The same URL on another network is a client of its own
+        let mainnet = clients.requested(Network::Mainnet, urls[0], build(urls[0]), now);
+        assert!(!Arc::ptr_eq(&a, &mainnet));
+
+        clients.requested(Network::Devnet, urls[1], build(urls[1]), now);
+        clients.requested(Network::Devnet, urls[2], build(urls[2]), now);
+        let stats = clients.stats();
+        assert_eq!((stats.name.as_str(), stats.len), ("rpc_clients", 2));
+        assert_eq!(stats.evictions, 2);
+        let rebuilt = clients.requested(Network::Devnet, urls[0], build(urls[0]), now);
+        assert!(!Arc::ptr_eq(&a, &rebuilt));
+    }
+}
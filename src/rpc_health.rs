@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use solana_client::nonblocking::rpc_client::RpcClient;
+use tokio::task::JoinSet;
+
+use crate::{
+    clock::system_ms,
+    models::{Network, NetworkHealth},
+};
+
+// `/api/health?rpc=true` asks every network's node for a blockhash. Load balancers poll health
+// endpoints every few seconds, so an answer is reused for `CACHE_TTL` instead of going to the
+// node again, and a node that doesn't answer within `PROBE_TIMEOUT` counts as unreachable.
+
+pub const CACHE_TTL: Duration = Duration::from_secs(5);
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The latest probe of each network.
+#[derive(Default)]
+pub struct RpcHealth {
+    probes: Mutex<HashMap<Network, (Instant, NetworkHealth)>>,
+}
+
+impl RpcHealth {
+    /// Reachability of each of `clients`, probed concurrently unless the last probe of the
+    /// network is younger than `CACHE_TTL`. In the order of `clients`.
+    pub async fn check(
+        &self,
+        clients: Vec<(Network, Arc<RpcClient>)>,
+        now: Instant,
+    ) -> Vec<NetworkHealth> {
+        let nets: Vec<_> = clients.iter().map(|(net, _)| *net).collect();
+        let mut probes = JoinSet::new();
+        {
+            let cached = self.probes.lock().unwrap();
+            for (net, rpc_client) in clients {
+                let fresh = cached
+                    .get(&net)
+                    .is_some_and(|(at, _)| now.saturating_duration_since(*at) < CACHE_TTL);
+                if !fresh {
+                    probes.spawn(probe(net, rpc_client));
+                }
+            }
+        }
+        while let Some(answer) = probes.join_next().await {
+            if let Ok(health) = answer {
+                let mut cached = self.probes.lock().unwrap();
+                cached.insert(health.net, (now, health));
+            }
+        }
+        let cached = self.probes.lock().unwrap();
+        nets.iter()
+            .filter_map(|net| cached.get(net).map(|(_, health)| health.clone()))
+            .collect()
+    }
+}
+
+async fn probe(net: Network, rpc_client: Arc<RpcClient>) -> NetworkHealth {
+    let started = Instant::now();
+    let result = tokio::time::timeout(PROBE_TIMEOUT, rpc_client.get_latest_blockhash()).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let error = match result {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("no answer within {} ms", PROBE_TIMEOUT.as_millis())),
+    };
+    NetworkHealth {
+        net,
+        reachable: error.is_none(),
+        latency_ms: error.is_none().then_some(latency_ms),
+        error,
+        checked_at: system_ms(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock(url: &str) -> Arc<RpcClient> {
+        Arc::new(RpcClient::new_mock(url.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_reachability_per_network() {
+        let health = RpcHealth::default();
+        let now = Instant::now();
+        let checked = health
+            .check(
+                vec![
+                    (Network::Devnet, mock("succeeds")),
+                    (Network::Testnet, mock("fails")),
+                ],
+                now,
+            )
+            .await;
+        assert_eq!(checked.len(), 2);
+        assert_eq!(checked[0].net, Network::Devnet);
+        assert!(checked[0].reachable);
+        assert!(checked[0].latency_ms.is_some());
+        assert_eq!(checked[0].error, None);
+        assert_eq!(checked[1].net, Network::Testnet);
+        assert!(!checked[1].reachable);
+        assert_eq!(checked[1].latency_ms, None);
+        assert!(checked[1].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_probes_are_cached() {
+        let health = RpcHealth::default();
+        let now = Instant::now();
+        health
+            .check(vec![(Network::Devnet, mock("fails"))], now)
+            .await;
+
+        // A node that recovered only shows up once the cached probe is too old
+        let checked = health
+            .check(
+                vec![(Network::Devnet, mock("succeeds"))],
+                now + CACHE_TTL / 2,
+            )
+            .await;
+        assert!(!checked[0].reachable);
+        let checked = health
+            .check(vec![(Network::Devnet, mock("succeeds"))], now + CACHE_TTL)
+            .await;
+        assert!(checked[0].reachable);
+    }
+}
@@ -0,0 +1,295 @@
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+};
+use solana_sdk::transaction::TransactionError;
+
+use crate::{error::ErrorClass, faucet::is_rate_limited};
+
+// Self-hosted RPC nodes often run with some methods disabled and answer them with JSON-RPC's
+// "method not found". Requests that can't work without the method fail with
+// `RPC_METHOD_UNSUPPORTED` naming it, checks that only add to a request are skipped instead.
+
+/// JSON-RPC error code of a method the node doesn't serve.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Code of a node that fell behind the cluster and refuses calls until it caught up.
+pub const NODE_UNHEALTHY: i64 = -32005;
+
+/// The method the node refused to serve, if that's why `error` happened.
+pub fn unsupported_method(error: &ClientError) -> Option<RpcRequest> {
+    match error.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == METHOD_NOT_FOUND =>
+        {
+            Some(
+                error
+                    .request()
+                    .copied()
+                    .unwrap_or(RpcRequest::Custom { method: "unknown" }),
+            )
+        }
+        // `get_account_with_commitment` folds the node's answer into a message of its own
+        ClientErrorKind::RpcError(RpcError::ForUser(message))
+            if message.contains(&format!("RPC response error {METHOD_NOT_FOUND}:")) =>
+        {
+            Some(RpcRequest::GetAccountInfo)
+        }
+        _ => None,
+    }
+}
+
+/// Whether a failed call is worth repeating: unreachable, lagging or rate limiting nodes are,
+/// an answer about the call or its transaction isn't.
+pub fn rpc_error_class(error: &ClientError) -> ErrorClass {
+    if is_rate_limited(error) {
+        return ErrorClass::RateLimited;
+    }
+    match error.kind() {
+        ClientErrorKind::Io(_) => ErrorClass::Transport,
+        // Timeouts and refused connections have no status
+        ClientErrorKind::Reqwest(e) => match e.status() {
+            Some(status) if !status.is_server_error() => ErrorClass::Node,
+            _ => ErrorClass::Transport,
+        },
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == NODE_UNHEALTHY =>
+        {
+            ErrorClass::Transport
+        }
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(_),
+            ..
+        })
+        | ClientErrorKind::TransactionError(_) => ErrorClass::Transaction,
+        _ => ErrorClass::Node,
+    }
+}
+
+/// `ErrorResponse::error_code` of a failed call, by why it failed rather than which call it was.
+pub fn rpc_error_code(error: &ClientError) -> &'static str {
+    if let Some(
+        TransactionError::InsufficientFundsForFee
+        | TransactionError::InsufficientFundsForRent { .. },
+    ) = error.get_transaction_error()
+    {
+        return "INSUFFICIENT_FUNDS";
+    }
+    match rpc_error_class(error) {
+        ErrorClass::Transport => "RPC_UNAVAILABLE",
+        ErrorClass::RateLimited => "RPC_RATE_LIMITED",
+        ErrorClass::Transaction => "TRANSACTION_REJECTED",
+        _ => "RPC_ERROR",
+    }
+}
+
+/// What needs the method, and what to do about it.
+pub fn hint(method: RpcRequest) -> &'static str {
+    match method {
+        RpcRequest::GetProgramAccounts => {
+            "spl_rent_summary and spl_close_empty_accounts scan token accounts with it, point \
+             rpc_urls at a node with getProgramAccounts enabled"
+        }
+        RpcRequest::SimulateTransaction => {
+            "compute_unit_limit \"auto\" simulates the transaction, pass a number of units instead"
+        }
+        RpcRequest::RequestAirdrop => "airdrops need a node with a faucet, configure `faucet`",
+        RpcRequest::GetSignatureStatuses => {
+            "confirmation and transaction_statuses poll it, point rpc_urls at a full RPC node"
+        }
+        _ => "point rpc_urls for this network at a full RPC node",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use serde_json::json;
+    use solana_client::{
+        client_error::{ClientError, ClientErrorKind},
+        rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    };
+    use solana_sdk::{
+        hash::Hash,
+        signature::{Keypair, Signature, Signer},
+        system_transaction,
+    };
+
+    use crate::{
+        Error,
+        confirmation::{ConfirmationOutcome, ConfirmationPolicy, Lifetime, confirm},
+        error::ErrorClass,
+        fixtures::{Accounts, Statuses, disabled, preflight_failure},
+        funding::check_funded,
+        models::Network,
+        rent_reclaim::scan_token_accounts,
+        rpc_methods::{METHOD_NOT_FOUND, rpc_error_class, rpc_error_code},
+        transaction_builder::TransferBuilder,
+        tss::transfer_message,
+        units::Lamports,
+    };
+
+    #[tokio::test]
+    async fn test_required_method() {
+        // Needed for the request, it fails and says which method is missing
+        let rpc_client = disabled(Accounts::default(), &[RpcRequest::GetProgramAccounts]);
+        let error = scan_token_accounts(&rpc_client.async_rpc_client(), &Keypair::new().pubkey())
+            .await
+            .unwrap_err()
+            .or_unsupported();
+        assert!(matches!(
+            error,
+            Error::RpcMethodUnsupported(RpcRequest::GetProgramAccounts)
+        ));
+        assert_eq!(error.error_code(), "RPC_METHOD_UNSUPPORTED");
+        assert!(error.to_string().contains("getProgramAccounts"));
+    }
+
+    #[tokio::test]
+    async fn test_optional_methods() {
+        // Only a precaution, skipped
+        let message = transfer_message(
+            &TransferBuilder::new(
+                Keypair::new().pubkey(),
+                Keypair::new().pubkey(),
+                Lamports::new(1_000),
+            ),
+            Hash::new_unique(),
+        );
+        let rpc_client = disabled(Accounts::default(), &[RpcRequest::GetMultipleAccounts]);
+        let warning = check_funded(&rpc_client.async_rpc_client(), &message, 1_000, None)
+            .await
+            .unwrap();
+        assert_eq!(warning.map(|w| w.code).as_deref(), Some("CHECK_SKIPPED"));
+        let rpc_client = Accounts::default().async_rpc_client();
+        assert!(matches!(
+            check_funded(&rpc_client, &message, 1_000, None).await,
+            Err(Error::AggAccountUnfunded { .. })
+        ));
+
+        // Without isBlockhashValid confirmation can't tell an expired transaction, it waits
+        // until the timeout instead
+        let payer = Keypair::new();
+        let tx = system_transaction::transfer(&payer, &payer.pubkey(), 1, Hash::new_unique());
+        let rpc_client = disabled(
+            Statuses::default().then(None),
+            &[RpcRequest::IsBlockhashValid],
+        );
+        let policy = ConfirmationPolicy {
+            timeout_ms: 20,
+            poll_interval_ms: 1,
+            ..ConfirmationPolicy::default()
+        };
+        let outcome = confirm(
+            &rpc_client.async_rpc_client(),
+            &tx.signatures[0],
+            &Lifetime::of(&tx.message),
+            None,
+            &policy,
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome, ConfirmationOutcome::TimedOut);
+    }
+
+    #[test]
+    fn test_error_classes() {
+        let response_error = |code, message: &str| {
+            ClientError::from(ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                code,
+                message: message.to_string(),
+                data: RpcResponseErrorData::Empty,
+            }))
+        };
+        let cases = [
+            (
+                ClientError::from(ClientErrorKind::Io(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "connection refused",
+                ))),
+                ErrorClass::Transport,
+                "RPC_UNAVAILABLE",
+            ),
+            (
+                response_error(-32005, "Node is behind by 42 slots"),
+                ErrorClass::Transport,
+                "RPC_UNAVAILABLE",
+            ),
+            (
+                response_error(429, "Too Many Requests"),
+                ErrorClass::RateLimited,
+                "RPC_RATE_LIMITED",
+            ),
+            (
+                preflight_failure(json!({ "err": "BlockhashNotFound" })),
+                ErrorClass::Transaction,
+                "TRANSACTION_REJECTED",
+            ),
+            (
+                preflight_failure(json!({ "err": "InsufficientFundsForFee" })),
+                ErrorClass::Transaction,
+                "INSUFFICIENT_FUNDS",
+            ),
+            (
+                response_error(-32602, "Invalid param: Invalid"),
+                ErrorClass::Node,
+                "RPC_ERROR",
+            ),
+        ];
+        for (error, class, code) in cases {
+            assert_eq!(rpc_error_class(&error), class, "{}", error);
+            assert_eq!(rpc_error_code(&error), code, "{}", error);
+            let error = Error::RecentHashFailed(error);
+            assert_eq!(error.class(), class);
+            assert_eq!(error.error_code(), code);
+            assert_eq!(error.retryable(), class.retryable());
+        }
+        // A node without the method won't serve it on the next try either
+        let error = Error::AccountFetchFailed(response_error(METHOD_NOT_FOUND, "Method not found"))
+            .or_unsupported();
+        assert_eq!(error.class(), ErrorClass::Node);
+        assert!(!error.retryable());
+
+        // Bad input and inconsistent ceremonies need a new request
+        assert_eq!(
+            Error::InvalidPublicKey("x".to_string()).class(),
+            ErrorClass::Input
+        );
+        assert_eq!(Error::MismatchMessages.class(), ErrorClass::Protocol);
+        assert_eq!(Error::InvalidSignature.class(), ErrorClass::Protocol);
+        assert!(!Error::MissingNetwork.retryable());
+        assert!(!Error::AirdropUnavailable(Network::Mainnet).retryable());
+
+        // A timed out transaction may still land, an expired one needs a new blockhash
+        let not_confirmed = |outcome| Error::NotConfirmed {
+            signature: Signature::default(),
+            outcome,
+        };
+        assert!(not_confirmed(ConfirmationOutcome::TimedOut).retryable());
+        assert_eq!(
+            not_confirmed(ConfirmationOutcome::Expired).class(),
+            ErrorClass::Protocol
+        );
+        assert!(!not_confirmed(ConfirmationOutcome::Expired).retryable());
+
+        // Sending a partly sent split again would pay twice, whatever stopped it
+        let error = Error::SplitIncomplete {
+            sent: vec![Signature::default()],
+            total: 2,
+            error: Box::new(Error::SendTransactionFailed(response_error(
+                429,
+                "Too Many Requests",
+            ))),
+        };
+        assert!(!error.retryable());
+
+        assert_eq!(
+            ErrorClass::ALL
+                .iter()
+                .filter(|class| class.retryable())
+                .map(|class| class.as_str())
+                .collect::<Vec<_>>(),
+            ["transport", "rate_limited", "timeout"]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::future::Future;
+
+use poem::http::Uri;
+use serde_json::Value;
+
+use crate::{Error, config::validate_rpc_url};
+
+// With `allow_request_rpc_url` a request can name the RPC node it is served through in
+// `rpc_url`, in place of the configured URL of its network, on one of the hosts of
+// `request_rpc_url_hosts`. Without the option the field is ignored. The body middleware reads the field
+// and runs the handler in its scope, every RPC client built meanwhile talks to that URL. Work
+// done outside of a request (scheduled broadcasts, the journal after a restart, the clock check)
+// keeps using `rpc_urls`, so `rpc_url` can't be combined with `broadcast_at`.
+
+tokio::task_local! {
+    static RPC_URL: String;
+}
+
+/// The `rpc_url` of a request body, `None` when it has none. Its host must be one of `hosts`.
+pub fn requested(json: &Value, hosts: &[String]) -> Result<Option<String>, Error> {
+    let Some(url) = ["rpc_url", "rpcUrl"]
+        .iter()
+        .find_map(|field| json.get(field).filter(|url| !url.is_null()))
+    else {
+        return Ok(None);
+    };
+    let url = url
+        .as_str()
+        .ok_or_else(|| Error::InvalidRpcUrl("must be a string".to_string()))?;
+    validate_rpc_url(url).map_err(Error::InvalidRpcUrl)?;
+    let host = url
+        .parse::<Uri>()
+        .ok()
+        .and_then(|uri| uri.host().map(str::to_string))
+        .unwrap_or_default();
+    if !hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(&host))
+    {
+        return Err(Error::RpcUrlNotAllowed(host));
+    }
+    let scheduled = ["broadcast_at", "broadcastAt"]
+        .iter()
+        .any(|field| json.get(field).is_some_and(|at| !at.is_null()));
+    if scheduled {
+        return Err(Error::InvalidRpcUrl(
+            "can't be combined with broadcast_at, scheduled transactions are sent through the \
+             configured rpc_urls"
+                .to_string(),
+        ));
+    }
+    Ok(Some(url.to_string()))
+}
+
+/// Run `f` with `url` in place of the RPC URL of every network.
+pub async fn scope<F: Future>(url: String, f: F) -> F::Output {
+    RPC_URL.scope(url, f).await
+}
+
+/// The `rpc_url` of the request being served, if it sent one.
+pub fn current() -> Option<String> {
+    RPC_URL.try_with(String::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts() -> Vec<String> {
+        vec!["my-provider.example".to_string(), "127.0.0.1".to_string()]
+    }
+
+    fn error(json: Value) -> String {
+        match requested(&json, &hosts()) {
+            Err(Error::InvalidRpcUrl(e)) => e,
+            other => panic!("expected an invalid rpc_url, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_requested() {
+        let url = "https://my-provider.example/rpc";
+        let json = serde_json::json!({ "net": "mainnet", "rpc_url": url });
+        assert_eq!(requested(&json, &hosts()).unwrap().as_deref(), Some(url));
+        let json = serde_json::json!({ "rpcUrl": url });
+        assert_eq!(requested(&json, &hosts()).unwrap().as_deref(), Some(url));
+        for json in [
+            serde_json::json!({ "net": "mainnet" }),
+            serde_json::json!({ "rpc_url": null }),
+            serde_json::json!([url]),
+        ] {
+            assert_eq!(requested(&json, &hosts()).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_invalid_urls() {
+        assert_eq!(
+            error(serde_json::json!({ "rpc_url": 8899 })),
+            "must be a string"
+        );
+        assert!(error(serde_json::json!({ "rpc_url": "ftp://node.example" })).contains("http"));
+        assert!(error(serde_json::json!({ "rpc_url": "not a url" })).contains("invalid"));
+        assert!(error(serde_json::json!({ "rpc_url": "/rpc" })).contains("http"));
+        let scheduled = serde_json::json!({
+            "rpc_url": "https://my-provider.example/rpc",
+            "broadcast_at": { "slot": 100 },
+        });
+        assert!(error(scheduled).contains("broadcast_at"));
+    }
+
+    #[test]
+    fn test_hosts() {
+        let json = serde_json::json!({ "rpc_url": "https://MY-PROVIDER.example:8899/rpc" });
+        assert!(requested(&json, &hosts()).unwrap().is_some());
+        for (json, hosts) in [
+            (
+                serde_json::json!({ "rpc_url": "http://169.254.169.254/" }),
+                hosts(),
+            ),
+            (
+                serde_json::json!({ "rpc_url": "https://my-provider.example/rpc" }),
+                vec![],
+            ),
+        ] {
+            assert!(matches!(
+                requested(&json, &hosts),
+                Err(Error::RpcUrlNotAllowed(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert_eq!(current(), None);
+        let url = "http://127.0.0.1:8899".to_string();
+        let seen = scope(url.clone(), async { current() }).await;
+        assert_eq!(seen, Some(url));
+        assert_eq!(current(), None);
+    }
+}
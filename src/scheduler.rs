@@ -0,0 +1,514 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    clock::{DEFAULT_MS_PER_SLOT, MAX_PROCESSING_AGE},
+    signature::Signature,
+};
+
+use crate::{
+    Error,
+    audit::{AuditLog, Outcome},
+    bounded_store::{
+        BoundedStore, IDEMPOTENCY_KEY_MIN_RETENTION, StateLimits, StoreConfig, StoreStats,
+    },
+    broadcast::Broadcast,
+    confirmation::{ConfirmationPolicy, Lifetime},
+    journal::{BroadcastJournal, ScheduledBroadcast},
+    message_review::CompiledMessage,
+    models::{BroadcastAt, Network},
+    spend_limit::Reservation,
+    tenant::Caller,
+};
+
+// Scheduled transactions sit in the broadcast journal with their signed transaction until they
+// are due, so a restart doesn't lose them. Once due the entry is claimed and sent through an
+// ordinary `Broadcast`, from then on it is just another pending broadcast.
+
+/// How often due broadcasts are looked for, about one slot.
+pub const SCHEDULER_TICK: Duration = Duration::from_millis(DEFAULT_MS_PER_SLOT);
+
+/// A blockhash is only accepted for this many slots, so that's as far ahead as a broadcast can
+/// be scheduled.
+pub const MAX_SCHEDULE_SLOTS: u64 = MAX_PROCESSING_AGE as u64;
+
+/// `MAX_SCHEDULE_SLOTS` at the nominal slot time.
+pub const MAX_SCHEDULE_SECONDS: u64 = MAX_SCHEDULE_SLOTS * DEFAULT_MS_PER_SLOT / 1000;
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Check that `broadcast_at` lies ahead of `current_slot` and `now` but within the validity
+/// window of a blockhash fetched right now.
+pub fn check_target(broadcast_at: BroadcastAt, current_slot: u64, now: u64) -> Result<(), Error> {
+    let error = match broadcast_at {
+        BroadcastAt::Slot(slot) if slot < current_slot => format!(
+            "slot {} has passed, the cluster is at slot {}",
+            slot, current_slot
+        ),
+        BroadcastAt::Slot(slot) if slot - current_slot > MAX_SCHEDULE_SLOTS => format!(
+            "slot {} is more than {} slots after the current slot {}, the blockhash would expire first",
+            slot, MAX_SCHEDULE_SLOTS, current_slot
+        ),
+        BroadcastAt::UnixTime(time) if time < now => {
+            format!("unix_time {} has passed, it is {} now", time, now)
+        }
+        BroadcastAt::UnixTime(time) if time - now > MAX_SCHEDULE_SECONDS => format!(
+            "unix_time {} is more than {} seconds away, the blockhash would expire first",
+            time, MAX_SCHEDULE_SECONDS
+        ),
+        _ => return Ok(()),
+    };
+    Err(Error::InvalidBroadcastAt(error))
+}
+
+/// Keys of the scheduled broadcasts in `journal`, from before a restart.
+fn idempotency_keys(
+    journal: &BroadcastJournal,
+    config: StoreConfig,
+) -> BoundedStore<(Option<String>, String), Signature> {
+    let keys =
+        BoundedStore::new("idempotency_keys", config).min_retention(IDEMPOTENCY_KEY_MIN_RETENTION);
+    let now = Instant::now();
+    for entry in journal.scheduled() {
+        let Some(key) = entry.scheduled.and_then(|s| s.idempotency_key) else {
+            continue;
+        };
+        if let Ok(signature) = entry.signature.parse() {
+            keys.insert((entry.tenant, key), signature, now);
+        }
+    }
+    keys
+}
+
+/// Holds signed transactions until their `broadcast_at`.
+pub struct Scheduler {
+    journal: Arc<BroadcastJournal>,
+    audit: Arc<AuditLog>,
+    /// Idempotency key of every scheduled request by tenant, for
+    /// `/api/scheduled_broadcast_status`
+    keys: BoundedStore<(Option<String>, String), Signature>,
+    /// Spend limit bookings of transactions not sent yet, given back when they are cancelled
+    reservations: Mutex<HashMap<Signature, Reservation>>,
+    confirmation: ConfirmationPolicy,
+}
+
+impl Scheduler {
+    pub fn new(journal: Arc<BroadcastJournal>, audit: Arc<AuditLog>) -> Self {
+        let keys = idempotency_keys(&journal, StateLimits::default().idempotency_keys);
+        Self {
+            journal,
+            audit,
+            keys,
+            reservations: Mutex::new(HashMap::new()),
+            confirmation: ConfirmationPolicy::default(),
+        }
+    }
+
+    /// Capacity and TTL of the idempotency keys, `state_limits.idempotency_keys` by default.
+    pub fn idempotency_keys(mut self, config: StoreConfig) -> Self {
+        self.keys = idempotency_keys(&self.journal, config);
+        self
+    }
+
+    /// How due broadcasts wait for confirmation, the default policy if not set.
+    pub fn confirmation(mut self, policy: ConfirmationPolicy) -> Self {
+        self.confirmation = policy;
+        self
+    }
+
+    /// Validate `scheduled` against the cluster and journal it until it is due.
+    pub async fn hold(
+        &self,
+        rpc_client: &RpcClient,
+        tenant: Option<&str>,
+        endpoint: &str,
+        request_digest: Option<&str>,
+        net: Network,
+        scheduled: ScheduledBroadcast,
+    ) -> Result<Signature, Error> {
+        let tx = &scheduled.transaction;
+        let signature = tx.signatures.first().copied().unwrap_or_default();
+        let message = CompiledMessage::new(tx.message_data());
+        let lifetime = Lifetime::of(&tx.message);
+        let valid = lifetime
+            .valid_at(rpc_client, rpc_client.commitment())
+            .await
+            .map_err(Error::RecentHashFailed)?;
+        if !valid {
+            let reason = match lifetime {
+                Lifetime::Blockhash(_) => "recent_block_hash has expired already",
+                Lifetime::DurableNonce { .. } => "the durable nonce was advanced already",
+            };
+            return Err(Error::InvalidBroadcastAt(reason.to_string()));
+        }
+        let current_slot = rpc_client
+            .get_slot()
+            .await
+            .map_err(Error::SlotFetchFailed)?;
+        check_target(scheduled.broadcast_at, current_slot, unix_time())?;
+
+        if let Some(key) = &scheduled.idempotency_key {
+            self.keys.insert(
+                (tenant.map(str::to_string), key.clone()),
+                signature,
+                Instant::now(),
+            );
+        }
+        self.journal
+            .schedule(tenant, endpoint, request_digest, net, scheduled)
+            .await;
+        self.audit.record(
+            tenant,
+            endpoint,
+            Some(&signature),
+            request_digest,
+            None,
+            Outcome::Scheduled,
+            None,
+            Some(&message),
+        );
+        Ok(signature)
+    }
+
+    /// Keep `reservation` until the transaction is sent or cancelled.
+    pub fn hold_reservation(&self, signature: Signature, reservation: Reservation) {
+        self.reservations
+            .lock()
+            .unwrap()
+            .insert(signature, reservation);
+    }
+
+    /// Idempotency keys are per tenant, admins too only find their own tenant's by key.
+    pub fn lookup(&self, caller: &Caller, idempotency_key: &str) -> Option<Signature> {
+        self.keys.get(
+            &(caller.tenant.clone(), idempotency_key.to_string()),
+            Instant::now(),
+        )
+    }
+
+    pub fn idempotency_key_stats(&self) -> StoreStats {
+        self.keys.stats()
+    }
+
+    /// `broadcast_at` of a transaction that still waits to be sent.
+    pub fn pending(&self, signature: &Signature) -> Option<BroadcastAt> {
+        let signature = signature.to_string();
+        self.journal
+            .scheduled()
+            .into_iter()
+            .find(|entry| entry.signature == signature)
+            .and_then(|entry| entry.scheduled)
+            .map(|scheduled| scheduled.broadcast_at)
+    }
+
+    /// Drop a transaction that wasn't sent yet, returns its spend limit booking. Another
+    /// tenant's transaction is reported as not found.
+    pub async fn cancel(
+        &self,
+        caller: &Caller,
+        signature: &Signature,
+    ) -> Result<Option<Reservation>, Error> {
+        let not_found = || Error::ScheduledBroadcastNotFound(signature.to_string());
+        let signature_str = signature.to_string();
+        let visible = self.journal.scheduled().iter().any(|entry| {
+            entry.signature == signature_str && caller.can_access(entry.tenant.as_deref())
+        });
+        if !visible {
+            return Err(not_found());
+        }
+        let entry = self
+            .journal
+            .cancel_scheduled(&signature_str)
+            .await
+            .ok_or_else(not_found)?;
+        self.audit.record(
+            entry.tenant.as_deref(),
+            &entry.endpoint,
+            Some(signature),
+            entry.request_digest.as_deref(),
+            None,
+            Outcome::Cancelled,
+            Some("cancelled before its broadcast_at".to_string()),
+            entry
+                .scheduled
+                .as_ref()
+                .map(|scheduled| CompiledMessage::new(scheduled.transaction.message_data()))
+                .as_ref(),
+        );
+        Ok(self.reservations.lock().unwrap().remove(signature))
+    }
+
+    /// Send every scheduled transaction that is due at `now`, returns how many went out.
+    /// Confirmation runs in the background, the outcome lands in the audit log.
+    pub async fn dispatch_due(
+        &self,
+        now: u64,
+        rpc_client: impl Fn(Network) -> Arc<RpcClient>,
+    ) -> usize {
+        let mut slots = HashMap::new();
+        let mut sent = 0;
+        for entry in self.journal.scheduled() {
+            let Some(scheduled) = &entry.scheduled else {
+                continue;
+            };
+            let rpc_client = rpc_client(entry.net);
+            let due = match scheduled.broadcast_at {
+                BroadcastAt::UnixTime(time) => now >= time,
+                BroadcastAt::Slot(slot) => {
+                    let current_slot = match slots.get(&entry.net) {
+                        Some(&current_slot) => current_slot,
+                        None => match rpc_client.get_slot().await {
+                            Ok(current_slot) => *slots.entry(entry.net).or_insert(current_slot),
+                            Err(e) => {
+                                tracing::warn!(
+                                    net = %entry.net,
+                                    error = %e,
+                                    "failed fetching the slot for scheduled broadcasts"
+                                );
+                                continue;
+                            }
+                        },
+                    };
+                    current_slot >= slot
+                }
+            };
+            if !due {
+                continue;
+            }
+
+            // Cancelled in the meantime
+            let Some(scheduled) = self.journal.claim_scheduled(&entry.signature).await else {
+                continue;
+            };
+            // Sent now, the booking stays
+            if let Ok(signature) = entry.signature.parse() {
+                self.reservations.lock().unwrap().remove(&signature);
+            }
+            let broadcast = Broadcast::new(
+                self.audit.clone(),
+                entry.endpoint,
+                entry.request_digest,
+                scheduled.transaction,
+            )
+            .tenant(entry.tenant)
+            .journal(Some(self.journal.clone()), entry.net)
+            .confirmation(self.confirmation.on(entry.net));
+            tokio::spawn(async move {
+                let _ = broadcast.send_and_confirm(rpc_client).await;
+            });
+            sent += 1;
+        }
+        sent
+    }
+
+    /// Send scheduled transactions as they come due, including ones left over from before a
+    /// restart.
+    pub async fn run(self: Arc<Self>, rpc_client: impl Fn(Network) -> Arc<RpcClient>) {
+        loop {
+            self.dispatch_due(unix_time(), &rpc_client).await;
+            tokio::time::sleep(SCHEDULER_TICK).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use serde_json::json;
+    use solana_client::{nonblocking::rpc_client::RpcClient, rpc_request::RpcRequest};
+    use solana_sdk::{
+        hash::Hash,
+        signature::{Keypair, Signature, Signer},
+        system_transaction,
+    };
+
+    use crate::{
+        Error,
+        audit::{AuditLog, Outcome},
+        bounded_store::StoreConfig,
+        journal::{BroadcastJournal, ScheduledBroadcast},
+        models::{BroadcastAt, Network},
+        scheduler::{MAX_SCHEDULE_SECONDS, MAX_SCHEDULE_SLOTS, Scheduler, check_target, unix_time},
+        tenant::Caller,
+    };
+
+    #[test]
+    fn test_check_target() {
+        let (slot, now) = (1_000, 1_700_000_000);
+        check_target(BroadcastAt::Slot(slot), slot, now).unwrap();
+        check_target(BroadcastAt::Slot(slot + MAX_SCHEDULE_SLOTS), slot, now).unwrap();
+        assert!(check_target(BroadcastAt::Slot(slot + MAX_SCHEDULE_SLOTS + 1), slot, now).is_err());
+        assert!(check_target(BroadcastAt::Slot(slot - 1), slot, now).is_err());
+        check_target(BroadcastAt::UnixTime(now + MAX_SCHEDULE_SECONDS), slot, now).unwrap();
+        assert!(
+            check_target(
+                BroadcastAt::UnixTime(now + MAX_SCHEDULE_SECONDS + 1),
+                slot,
+                now
+            )
+            .is_err()
+        );
+        assert!(check_target(BroadcastAt::UnixTime(now - 1), slot, now).is_err());
+    }
+
+    async fn schedule(
+        scheduler: &Scheduler,
+        tenant: Option<&str>,
+        broadcast_at: BroadcastAt,
+        key: &str,
+    ) -> Result<Signature, Error> {
+        let rpc_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::IsBlockhashValid,
+                json!({ "context": { "slot": 1 }, "value": true }),
+            )]),
+        );
+        let payer = Keypair::new();
+        let transaction =
+            system_transaction::transfer(&payer, &payer.pubkey(), 1, Hash::new_unique());
+        scheduler
+            .hold(
+                &rpc_client,
+                tenant,
+                "aggregate_signatures",
+                None,
+                Network::Devnet,
+                ScheduledBroadcast {
+                    broadcast_at,
+                    idempotency_key: Some(key.to_string()),
+                    transaction,
+                },
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_and_cancel() {
+        let path = std::env::temp_dir().join(format!("scheduled-{}.json", Hash::new_unique()));
+        let audit = Arc::new(AuditLog::default());
+        let now = unix_time();
+        let (by_slot, later, cancelled) = {
+            let journal = Arc::new(BroadcastJournal::load(&path).unwrap());
+            let scheduler = Scheduler::new(journal, audit.clone());
+            // The mock cluster sits at slot 0
+            let by_slot = schedule(&scheduler, None, BroadcastAt::Slot(0), "by-slot").await;
+            let later = schedule(&scheduler, None, BroadcastAt::UnixTime(now + 30), "later").await;
+            let cancelled = schedule(
+                &scheduler,
+                Some("team-b"),
+                BroadcastAt::UnixTime(now + 30),
+                "cancelled",
+            )
+            .await
+            .unwrap();
+            let too_far = BroadcastAt::Slot(MAX_SCHEDULE_SLOTS + 1);
+            assert!(
+                schedule(&scheduler, None, too_far, "too-far")
+                    .await
+                    .is_err()
+            );
+
+            // Only team-b and admins can find or cancel team-b's transaction
+            let team_b = Caller::tenant("team-b");
+            assert_eq!(scheduler.lookup(&team_b, "cancelled"), Some(cancelled));
+            assert_eq!(scheduler.lookup(&Caller::default(), "cancelled"), None);
+            assert_eq!(scheduler.lookup(&team_b, "later"), None);
+            for other in [Caller::default(), Caller::tenant("team-a")] {
+                assert!(matches!(
+                    scheduler.cancel(&other, &cancelled).await,
+                    Err(Error::ScheduledBroadcastNotFound(_))
+                ));
+            }
+            let admin = Caller {
+                tenant: Some("ops".to_string()),
+                admin: true,
+            };
+            scheduler.cancel(&admin, &cancelled).await.unwrap();
+            assert!(scheduler.cancel(&team_b, &cancelled).await.is_err());
+            (by_slot.unwrap(), later.unwrap(), cancelled)
+        };
+
+        // After a restart whatever is still scheduled goes out when it is due
+        let scheduler = Scheduler::new(
+            Arc::new(BroadcastJournal::load(&path).unwrap()),
+            audit.clone(),
+        );
+        assert_eq!(scheduler.lookup(&Caller::default(), "later"), Some(later));
+        let mock = |_| Arc::new(RpcClient::new_mock("succeeds".to_string()));
+        assert_eq!(scheduler.dispatch_due(now, mock).await, 1);
+        assert_eq!(scheduler.pending(&by_slot), None);
+        assert_eq!(
+            scheduler.pending(&later),
+            Some(BroadcastAt::UnixTime(now + 30))
+        );
+        assert!(
+            scheduler
+                .cancel(&Caller::default(), &by_slot)
+                .await
+                .is_err()
+        );
+        assert_eq!(scheduler.dispatch_due(now + 30, mock).await, 1);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let outcomes = |signature| {
+            audit
+                .for_signature(signature)
+                .into_iter()
+                .map(|e| e.outcome)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            outcomes(&by_slot),
+            [Outcome::Scheduled, Outcome::Broadcast, Outcome::Confirmed]
+        );
+        assert_eq!(
+            outcomes(&cancelled),
+            [Outcome::Scheduled, Outcome::Cancelled]
+        );
+        assert!(BroadcastJournal::load(&path).unwrap().entries().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys_at_capacity() {
+        let path = std::env::temp_dir().join(format!("scheduled-{}.json", Hash::new_unique()));
+        let journal = Arc::new(BroadcastJournal::load(&path).unwrap());
+        let scheduler =
+            Scheduler::new(journal, Arc::new(AuditLog::default())).idempotency_keys(StoreConfig {
+                capacity: 2,
+                ttl_secs: None,
+            });
+        let later = BroadcastAt::UnixTime(unix_time() + 30);
+        let first = schedule(&scheduler, None, later, "first").await.unwrap();
+        let second = schedule(&scheduler, None, later, "second").await.unwrap();
+        // A lookup makes `first` the most recently used, `second` makes room for `third`
+        assert_eq!(scheduler.lookup(&Caller::default(), "first"), Some(first));
+        let third = schedule(&scheduler, None, later, "third").await.unwrap();
+
+        assert_eq!(scheduler.lookup(&Caller::default(), "second"), None);
+        assert_eq!(scheduler.lookup(&Caller::default(), "first"), Some(first));
+        assert_eq!(scheduler.lookup(&Caller::default(), "third"), Some(third));
+        // Evicted seconds after it was stored, which is what the warning is about
+        let stats = scheduler.idempotency_key_stats();
+        assert_eq!(
+            (stats.len, stats.evictions, stats.early_evictions),
+            (2, 1, 1)
+        );
+        // Only the key is gone, the transaction itself is still scheduled
+        assert_eq!(scheduler.pending(&second), Some(later));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
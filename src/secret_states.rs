@@ -0,0 +1,96 @@
+use std::time::Instant;
+
+use rand07::Rng;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    Error,
+    bounded_store::{BoundedStore, StoreConfig, StoreStats},
+    serialization::{SecretAggStepOne, Serialize},
+};
+
+// The `secret_state` of step one holds the party's secret nonces, anyone who sees it and the
+// partial signature made with it can compute the party's key. With `server_side_secret_state`
+// step one keeps it here instead and hands out a random `secret_state_id`, which a step two
+// sends in its place. Each one is taken by the first step two naming it, whether that step two
+// signs or fails, so no nonce can sign twice; ids not taken within the TTL of
+// `state_limits.secret_states` are dropped.
+
+/// Bytes of randomness in a `secret_state_id`.
+const ID_LEN: usize = 32;
+
+pub struct SecretStates {
+    kept: BoundedStore<String, (Pubkey, Vec<u8>)>,
+}
+
+impl SecretStates {
+    pub fn new(config: StoreConfig) -> Self {
+        Self {
+            kept: BoundedStore::new("secret_states", config),
+        }
+    }
+
+    /// Keep the `secret_state` of `signer` for its step two, returns the id to take it with.
+    pub fn keep(&self, signer: Pubkey, secret_state: &SecretAggStepOne, now: Instant) -> String {
+        let id = bs58::encode(rand07::thread_rng().r#gen::<[u8; ID_LEN]>()).into_string();
+        let mut bytes = Vec::with_capacity(secret_state.size_hint());
+        secret_state.serialize(&mut bytes);
+        self.kept.insert(id.clone(), (signer, bytes), now);
+        id
+    }
+
+    /// The secret state kept as `id` for `signer`, which can't be taken again afterwards.
+    pub fn take(&self, id: &str, signer: &Pubkey, now: Instant) -> Result<SecretAggStepOne, Error> {
+        match self.kept.take(&id.to_string(), now) {
+            Some((kept_for, bytes)) if kept_for == *signer => {
+                SecretAggStepOne::deserialize(&bytes).map_err(|_| Error::SecretStateNotFound)
+            }
+            _ => Err(Error::SecretStateNotFound),
+        }
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        self.kept.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use super::*;
+    use crate::tss::step_one;
+
+    #[test]
+    fn test_secret_states_are_taken_once() {
+        let states = SecretStates::new(StoreConfig {
+            capacity: 10,
+            ttl_secs: Some(60),
+        });
+        let now = Instant::now();
+        let keypair = Keypair::new();
+        let (_, secret_state) = step_one(keypair.insecure_clone());
+        let id = states.keep(keypair.pubkey(), &secret_state, now);
+        assert_eq!(
+            states.take(&id, &keypair.pubkey(), now).unwrap(),
+            secret_state
+        );
+        assert!(matches!(
+            states.take(&id, &keypair.pubkey(), now),
+            Err(Error::SecretStateNotFound)
+        ));
+
+        // Another party naming the id uses it up too
+        let id = states.keep(keypair.pubkey(), &secret_state, now);
+        assert_ne!(states.keep(keypair.pubkey(), &secret_state, now), id);
+        assert!(states.take(&id, &Pubkey::new_unique(), now).is_err());
+        assert!(states.take(&id, &keypair.pubkey(), now).is_err());
+
+        let id = states.keep(keypair.pubkey(), &secret_state, now);
+        let later = now + Duration::from_secs(60);
+        assert!(states.take(&id, &keypair.pubkey(), later).is_err());
+        assert_eq!(states.stats().expirations, 1);
+    }
+}
@@ -1,10 +1,12 @@
 use std::fmt::{Display, Formatter};
 
-use curv::elliptic::curves::{DeserializationError, Point, PointFromBytesError, Scalar};
+use curv::elliptic::curves::{DeserializationError, Ed25519, Point, PointFromBytesError, Scalar};
 use multi_party_eddsa::protocols::musig2::{PrivatePartialNonces, PublicPartialNonces};
 use solana_sdk::signature::Signature;
 use spl_memo::solana_program::pubkey::Pubkey;
 
+use crate::input::{InteriorWhitespace, normalize, normalize_secret};
+
 #[derive(Debug)]
 pub enum Error {
     InputTooShort { expected: usize, found: usize },
@@ -12,16 +14,52 @@ pub enum Error {
     InvalidPoint(PointFromBytesError),
     InvalidScalar(DeserializationError),
     WrongTag { expected: Tag, found: Tag },
+    InteriorWhitespace(InteriorWhitespace),
+    BadEnvironment,
 }
 
+/// Version of the blob layout (tag byte followed by the fixed-size fields below),
+/// bump it whenever a layout changes.
+pub const SERIALIZATION_VERSION: u8 = 1;
+
 // TODO: Also add a magic tag that is equal to all messages.
 
+// A server with an `environment` label appends it to the blobs it emits: a marker byte, the
+// length and the label. Readers of the fixed-size fields ignore whatever follows them, so
+// labeled blobs still parse everywhere and unlabeled ones are exactly as before.
+
+/// Marks an environment label after the fixed-size fields of a blob.
+const ENVIRONMENT_MARKER: u8 = 0xe7;
+
+/// Longest environment label a blob can carry.
+pub const MAX_ENVIRONMENT_LEN: usize = 32;
+
+/// Whether `label` can be embedded into blobs: 1 to 32 printable ASCII characters.
+pub fn validate_environment(label: &str) -> Result<(), String> {
+    if label.is_empty() || label.len() > MAX_ENVIRONMENT_LEN {
+        return Err(format!(
+            "environment must be 1 to {} characters, got {}",
+            MAX_ENVIRONMENT_LEN,
+            label.len()
+        ));
+    }
+    if !label.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(format!(
+            "environment {:?} may only hold printable ASCII without spaces",
+            label
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Tag {
     AggMessage1 = 0,
     AggMessage2 = 1,
     PartialSignature = 2,
     SecretAggStepOne = 3,
+    KeygenMessage1 = 4,
+    ThresholdKeyShare = 5,
     Unknown,
 }
 
@@ -32,6 +70,8 @@ impl From<u8> for Tag {
             _ if t == Tag::AggMessage2 as u8 => Tag::AggMessage2,
             _ if t == Tag::PartialSignature as u8 => Tag::PartialSignature,
             _ if t == Tag::SecretAggStepOne as u8 => Tag::SecretAggStepOne,
+            _ if t == Tag::KeygenMessage1 as u8 => Tag::KeygenMessage1,
+            _ if t == Tag::ThresholdKeyShare as u8 => Tag::ThresholdKeyShare,
             _ => Tag::Unknown,
         }
     }
@@ -44,6 +84,8 @@ impl Display for Tag {
             Tag::AggMessage2 => f.write_str("Aggregate Message2"),
             Tag::PartialSignature => f.write_str("Partial Signature"),
             Tag::SecretAggStepOne => f.write_str("Secret State Aggregate1"),
+            Tag::KeygenMessage1 => f.write_str("Threshold Keygen Message1"),
+            Tag::ThresholdKeyShare => f.write_str("Threshold Key Share"),
             Tag::Unknown => f.write_str("Unknown"),
         }
     }
@@ -69,6 +111,8 @@ impl Display for Error {
                     expected, found
                 )
             }
+            Self::InteriorWhitespace(e) => write!(f, "{}", e),
+            Self::BadEnvironment => f.write_str("Malformed environment label"),
         }
     }
 }
@@ -96,15 +140,52 @@ impl<T: Serialize> FieldError<T> for Result<T, Error> {
 }
 
 pub trait Serialize: Sized {
+    /// Keeps the blob out of error messages.
+    const SECRET: bool = false;
+
     fn serialize_bs58(&self) -> String {
         let mut vec = Vec::with_capacity(self.size_hint());
         self.serialize(&mut vec);
         bs58::encode(vec).into_string()
     }
+    /// `serialize_bs58` followed by the `environment` label, if any.
+    fn serialize_bs58_in(&self, environment: Option<&str>) -> String {
+        let mut vec = Vec::with_capacity(self.size_hint() + 2 + MAX_ENVIRONMENT_LEN);
+        self.serialize(&mut vec);
+        if let Some(label) = environment {
+            vec.push(ENVIRONMENT_MARKER);
+            vec.push(label.len() as u8);
+            vec.extend(label.as_bytes());
+        }
+        bs58::encode(vec).into_string()
+    }
     fn serialize(&self, append_to: &mut Vec<u8>);
-    fn deserialize_bs58(s: impl AsRef<[u8]>) -> Result<Self, Error> {
-        let out = bs58::decode(s).into_vec().map_err(Error::BadBase58)?;
-        Self::deserialize(&out)
+    fn deserialize_bs58(s: impl AsRef<str>) -> Result<Self, Error> {
+        Self::deserialize(&Self::decode_bs58(s)?)
+    }
+    /// The environment label of a blob, `None` when it has none.
+    fn environment_bs58(s: impl AsRef<str>) -> Result<Option<String>, Error> {
+        let bytes = Self::decode_bs58(s)?;
+        let rest = &bytes[Self::deserialize(&bytes)?.size_hint()..];
+        match rest {
+            [] => Ok(None),
+            [ENVIRONMENT_MARKER, len, label @ ..] if label.len() == *len as usize => {
+                String::from_utf8(label.to_vec())
+                    .map(Some)
+                    .map_err(|_| Error::BadEnvironment)
+            }
+            _ => Err(Error::BadEnvironment),
+        }
+    }
+    fn decode_bs58(s: impl AsRef<str>) -> Result<Vec<u8>, Error> {
+        let s = match Self::SECRET {
+            true => normalize_secret(s.as_ref()),
+            false => normalize(s.as_ref()),
+        }
+        .map_err(Error::InteriorWhitespace)?;
+        bs58::decode(s.as_ref())
+            .into_vec()
+            .map_err(Error::BadBase58)
     }
     // TODO: Use a cursor instead, more fail safe.
     fn deserialize(b: &[u8]) -> Result<Self, Error>;
@@ -194,6 +275,8 @@ pub struct SecretAggStepOne {
 }
 
 impl Serialize for SecretAggStepOne {
+    const SECRET: bool = true;
+
     fn serialize(&self, append_to: &mut Vec<u8>) {
         append_to.reserve(self.size_hint());
         append_to.push(Tag::SecretAggStepOne as u8);
@@ -241,6 +324,132 @@ impl Serialize for SecretAggStepOne {
     }
 }
 
+/// A party's first threshold keygen message: commitments to its polynomial, a proof it knows
+/// the secret of the first one, and the share of every party, each encrypted to its key.
+#[derive(Debug, PartialEq)]
+pub struct KeygenMessage1 {
+    pub sender: Pubkey,
+    pub threshold: u8,
+    pub commitments: Vec<Point<Ed25519>>,
+    pub proof_nonce: Point<Ed25519>,
+    pub proof_response: Scalar<Ed25519>,
+    /// In the order of the keys, the sender's own is zero
+    pub shares: Vec<Scalar<Ed25519>>,
+}
+
+impl KeygenMessage1 {
+    const HEADER: usize = 1 + 32 + 1 + 1;
+}
+
+impl Serialize for KeygenMessage1 {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::KeygenMessage1 as u8);
+        append_to.extend(self.sender.to_bytes());
+        append_to.push(self.threshold);
+        append_to.push(self.shares.len() as u8);
+        for commitment in &self.commitments {
+            append_to.extend(&*commitment.to_bytes(true));
+        }
+        append_to.extend(&*self.proof_nonce.to_bytes(true));
+        append_to.extend(&*self.proof_response.to_bytes());
+        for share in &self.shares {
+            append_to.extend(&*share.to_bytes());
+        }
+    }
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < Self::HEADER {
+            return Err(Error::InputTooShort {
+                expected: Self::HEADER,
+                found: b.len(),
+            });
+        }
+        let tag = Tag::from(b[0]);
+        if tag != Tag::KeygenMessage1 {
+            return Err(Error::WrongTag {
+                expected: Tag::KeygenMessage1,
+                found: tag,
+            });
+        }
+        let sender = Pubkey::new(&b[1..1 + 32]);
+        let (threshold, parties) = (b[1 + 32], usize::from(b[1 + 32 + 1]));
+        let proof = Self::HEADER + 32 * usize::from(threshold);
+        let expected = proof + 64 + 32 * parties;
+        if b.len() < expected {
+            return Err(Error::InputTooShort {
+                expected,
+                found: b.len(),
+            });
+        }
+        let commitments = b[Self::HEADER..proof]
+            .chunks(32)
+            .map(Point::from_bytes)
+            .collect::<Result<_, _>>()?;
+        let shares = b[proof + 64..expected]
+            .chunks(32)
+            .map(Scalar::from_bytes)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            sender,
+            threshold,
+            commitments,
+            proof_nonce: Point::from_bytes(&b[proof..proof + 32])?,
+            proof_response: Scalar::from_bytes(&b[proof + 32..proof + 64])?,
+            shares,
+        })
+    }
+    fn size_hint(&self) -> usize {
+        Self::HEADER + 32 * self.commitments.len() + 64 + 32 * self.shares.len()
+    }
+}
+
+/// A party's share of a threshold key, what it signs with instead of its own key.
+#[derive(Debug, PartialEq)]
+pub struct ThresholdKeyShare {
+    pub threshold: u8,
+    /// Position of the party in the keys of the keygen
+    pub index: u8,
+    pub group_key: Point<Ed25519>,
+    pub share: Scalar<Ed25519>,
+}
+
+impl Serialize for ThresholdKeyShare {
+    const SECRET: bool = true;
+
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::ThresholdKeyShare as u8);
+        append_to.push(self.threshold);
+        append_to.push(self.index);
+        append_to.extend(&*self.group_key.to_bytes(true));
+        append_to.extend(&*self.share.to_bytes());
+    }
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 64 {
+            return Err(Error::InputTooShort {
+                expected: 1 + 2 + 64,
+                found: b.len(),
+            });
+        }
+        let tag = Tag::from(b[0]);
+        if tag != Tag::ThresholdKeyShare {
+            return Err(Error::WrongTag {
+                expected: Tag::ThresholdKeyShare,
+                found: tag,
+            });
+        }
+        Ok(Self {
+            threshold: b[1],
+            index: b[2],
+            group_key: Point::from_bytes(&b[3..3 + 32])?,
+            share: Scalar::from_bytes(&b[3 + 32..3 + 64])?,
+        })
+    }
+    fn size_hint(&self) -> usize {
+        1 + 2 + 64
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
@@ -249,7 +458,13 @@ mod tests {
     use solana_sdk::signature::Signature;
     use spl_memo::solana_program::pubkey::Pubkey;
 
-    use crate::serialization::{AggMessage1, PartialSignature, SecretAggStepOne, Serialize};
+    use curv::BigInt;
+    use curv::elliptic::curves::{Point, Scalar};
+
+    use crate::serialization::{
+        AggMessage1, Error, KeygenMessage1, PartialSignature, SecretAggStepOne, Serialize,
+        ThresholdKeyShare, validate_environment,
+    };
 
     #[derive(PartialEq, Debug)]
     struct PanicEq<T: PartialEq + Debug>(T);
@@ -305,4 +520,69 @@ mod tests {
             assert_eq!(PanicEq(secret_agg1), PanicEq(deserialized));
         }
     }
+
+    #[test]
+    fn test_threshold_blobs() {
+        let point = |i: u64| Point::generator() * Scalar::from_bigint(&BigInt::from(i));
+        let message = KeygenMessage1 {
+            sender: Pubkey::new_unique(),
+            threshold: 2,
+            commitments: vec![point(1), point(2)],
+            proof_nonce: point(3),
+            proof_response: Scalar::random(),
+            shares: vec![Scalar::random(), Scalar::zero(), Scalar::random()],
+        };
+        let serialized = message.serialize_bs58_in(Some("staging"));
+        let deserialized = KeygenMessage1::deserialize_bs58(&serialized).unwrap();
+        assert_eq!(PanicEq(deserialized), PanicEq(message));
+        assert_eq!(
+            KeygenMessage1::environment_bs58(&serialized).unwrap(),
+            Some("staging".to_string())
+        );
+        let mut truncated = bs58::decode(&serialized).into_vec().unwrap();
+        truncated.truncate(100);
+        assert!(matches!(
+            KeygenMessage1::deserialize(&truncated),
+            Err(Error::InputTooShort { .. })
+        ));
+
+        let share = ThresholdKeyShare {
+            threshold: 2,
+            index: 1,
+            group_key: point(4),
+            share: Scalar::random(),
+        };
+        let serialized = share.serialize_bs58();
+        let deserialized = ThresholdKeyShare::deserialize_bs58(serialized).unwrap();
+        assert_eq!(PanicEq(deserialized), PanicEq(share));
+    }
+
+    #[test]
+    fn test_environment_label() {
+        let partial_sig = PartialSignature(Signature::new(&[7; 64]));
+        let plain = partial_sig.serialize_bs58();
+        assert_eq!(partial_sig.serialize_bs58_in(None), plain);
+        assert_eq!(PartialSignature::environment_bs58(&plain).unwrap(), None);
+
+        let labeled = partial_sig.serialize_bs58_in(Some("staging"));
+        assert_ne!(labeled, plain);
+        let deserialized = PartialSignature::deserialize_bs58(&labeled).unwrap();
+        assert_eq!(PanicEq(deserialized), PanicEq(partial_sig));
+        assert_eq!(
+            PartialSignature::environment_bs58(&labeled)
+                .unwrap()
+                .as_deref(),
+            Some("staging")
+        );
+
+        let mut truncated = bs58::decode(&labeled).into_vec().unwrap();
+        truncated.pop();
+        let truncated = bs58::encode(truncated).into_string();
+        assert!(PartialSignature::environment_bs58(&truncated).is_err());
+
+        assert!(validate_environment("prod").is_ok());
+        assert!(validate_environment("").is_err());
+        assert!(validate_environment("prod eu").is_err());
+        assert!(validate_environment(&"x".repeat(33)).is_err());
+    }
 }
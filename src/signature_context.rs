@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    Error,
+    models::{SignatureContext, SignatureSubmission},
+};
+
+// A partial signature blob doesn't say which transfer it signs, a coordinator that mixes up two
+// ceremonies only finds out when the aggregated signature fails to verify. Step two returns a
+// `context` next to the blob, and the aggregation compares the contexts it's given before it
+// combines anything. Bare blobs are still accepted and skip the comparison, the blob format is
+// the same either way.
+
+/// What the aggregation computed itself, every context has to agree with it.
+#[derive(Debug, Clone, Copy)]
+pub struct Expected<'a> {
+    pub request_digest: &'a str,
+    pub aggregated_pubkey: &'a Pubkey,
+    /// `environment` of this server
+    pub environment: Option<&'a str>,
+    /// `session_id` of the aggregation request. Without one the contexts still have to agree
+    /// with each other
+    pub session_id: Option<&'a str>,
+    pub keys: &'a [Pubkey],
+}
+
+/// Compare the contexts of `submissions` in order. The first disagreement is returned, naming
+/// the index of the submission and the field.
+pub fn check(expected: &Expected, submissions: &[SignatureSubmission]) -> Result<(), Error> {
+    let aggregated_pubkey = expected.aggregated_pubkey.to_string();
+    // The session every context names, taken from the first context when the request has none
+    let mut session: Option<Option<&str>> = expected.session_id.map(Some);
+    let mut signers: HashMap<&str, usize> = HashMap::new();
+
+    let contexts = submissions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, submission)| Some((i, submission.context()?)));
+    for (submission, context) in contexts {
+        let mismatch = |field, expected: &str, found: &str| Error::SignatureContextMismatch {
+            submission,
+            field,
+            expected: expected.to_string(),
+            found: found.to_string(),
+        };
+
+        if context.request_digest != expected.request_digest {
+            return Err(mismatch(
+                "request_digest",
+                expected.request_digest,
+                &context.request_digest,
+            ));
+        }
+        if context.aggregated_pubkey != aggregated_pubkey {
+            return Err(mismatch(
+                "aggregated_pubkey",
+                &aggregated_pubkey,
+                &context.aggregated_pubkey,
+            ));
+        }
+        if context.environment.as_deref() != expected.environment {
+            return Err(mismatch(
+                "environment",
+                expected.environment.unwrap_or("unlabeled"),
+                context.environment.as_deref().unwrap_or("unlabeled"),
+            ));
+        }
+        let session_id = context.session_id.as_deref();
+        match session {
+            None => session = Some(session_id),
+            Some(agreed) if agreed != session_id => {
+                return Err(mismatch(
+                    "session_id",
+                    agreed.unwrap_or("none"),
+                    session_id.unwrap_or("none"),
+                ));
+            }
+            Some(_) => {}
+        }
+        if !expected
+            .keys
+            .iter()
+            .any(|key| key.to_string() == context.signer)
+        {
+            return Err(mismatch("signer", "one of keys", &context.signer));
+        }
+        if let Some(first) = signers.insert(&context.signer, submission) {
+            return Err(mismatch(
+                "signer",
+                "a party no other submission came from",
+                &format!("{}, the signer of signatures[{}]", context.signer, first),
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl SignatureContext {
+    /// Context of a partial signature made on this server for `session_id`.
+    pub fn new(
+        request_digest: &str,
+        aggregated_pubkey: &Pubkey,
+        signer: &Pubkey,
+        environment: Option<&str>,
+        session_id: Option<String>,
+    ) -> Self {
+        Self {
+            request_digest: request_digest.to_string(),
+            aggregated_pubkey: aggregated_pubkey.to_string(),
+            signer: signer.to_string(),
+            environment: environment.map(str::to_string),
+            session_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "3b8ad553e145b8d572bb100c30ac8fc2f5dc56187db89a955e11896c84d4da2c";
+
+    struct Ceremony {
+        aggregated_pubkey: Pubkey,
+        keys: Vec<Pubkey>,
+    }
+
+    impl Ceremony {
+        fn new() -> Self {
+            Self {
+                aggregated_pubkey: Pubkey::new_unique(),
+                keys: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            }
+        }
+
+        fn expected(&self) -> Expected<'_> {
+            Expected {
+                request_digest: DIGEST,
+                aggregated_pubkey: &self.aggregated_pubkey,
+                environment: Some("staging"),
+                session_id: None,
+                keys: &self.keys,
+            }
+        }
+
+        fn submission(&self, party: usize) -> SignatureSubmission {
+            SignatureSubmission::WithContext {
+                partial_signature: format!("sig{}", party),
+                context: SignatureContext::new(
+                    DIGEST,
+                    &self.aggregated_pubkey,
+                    &self.keys[party],
+                    Some("staging"),
+                    None,
+                ),
+            }
+        }
+    }
+
+    fn context(submission: &mut SignatureSubmission) -> &mut SignatureContext {
+        match submission {
+            SignatureSubmission::WithContext { context, .. } => context,
+            SignatureSubmission::Bare(_) => unreachable!(),
+        }
+    }
+
+    fn mismatch(result: Result<(), Error>) -> (usize, &'static str, String, String) {
+        match result {
+            Err(Error::SignatureContextMismatch {
+                submission,
+                field,
+                expected,
+                found,
+            }) => (submission, field, expected, found),
+            other => panic!("expected a context mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_agreeing_contexts() {
+        let ceremony = Ceremony::new();
+        let submissions = [ceremony.submission(0), ceremony.submission(1)];
+        assert!(check(&ceremony.expected(), &submissions).is_ok());
+    }
+
+    #[test]
+    fn test_bare_signatures_are_not_checked() {
+        let ceremony = Ceremony::new();
+        let submissions = [
+            SignatureSubmission::Bare("sig0".to_string()),
+            ceremony.submission(1),
+        ];
+        assert!(check(&ceremony.expected(), &submissions).is_ok());
+        let bare = [
+            SignatureSubmission::Bare("sig0".to_string()),
+            SignatureSubmission::Bare("sig0".to_string()),
+        ];
+        assert!(check(&ceremony.expected(), &bare).is_ok());
+    }
+
+    #[test]
+    fn test_request_digest_mismatch() {
+        let ceremony = Ceremony::new();
+        let mut submissions = [ceremony.submission(0), ceremony.submission(1)];
+        context(&mut submissions[1]).request_digest = "00".repeat(32);
+        let (submission, field, expected, found) =
+            mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, field), (1, "request_digest"));
+        assert_eq!(expected, DIGEST);
+        assert_eq!(found, "00".repeat(32));
+    }
+
+    #[test]
+    fn test_aggregated_pubkey_mismatch() {
+        let ceremony = Ceremony::new();
+        let other = Pubkey::new_unique();
+        let mut submissions = [ceremony.submission(0), ceremony.submission(1)];
+        context(&mut submissions[0]).aggregated_pubkey = other.to_string();
+        let (submission, field, expected, found) =
+            mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, field), (0, "aggregated_pubkey"));
+        assert_eq!(expected, ceremony.aggregated_pubkey.to_string());
+        assert_eq!(found, other.to_string());
+    }
+
+    #[test]
+    fn test_environment_mismatch() {
+        let ceremony = Ceremony::new();
+        let mut submissions = [ceremony.submission(0), ceremony.submission(1)];
+        context(&mut submissions[1]).environment = Some("prod".to_string());
+        let (submission, field, expected, found) =
+            mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, field), (1, "environment"));
+        assert_eq!((expected.as_str(), found.as_str()), ("staging", "prod"));
+
+        context(&mut submissions[1]).environment = None;
+        let (_, _, _, found) = mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!(found, "unlabeled");
+    }
+
+    #[test]
+    fn test_session_id_mismatch() {
+        let ceremony = Ceremony::new();
+        let mut submissions = [ceremony.submission(0), ceremony.submission(1)];
+        context(&mut submissions[0]).session_id = Some("a".to_string());
+        context(&mut submissions[1]).session_id = Some("b".to_string());
+        let (submission, field, expected, found) =
+            mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, field), (1, "session_id"));
+        assert_eq!((expected.as_str(), found.as_str()), ("a", "b"));
+
+        // A session on only some of the signatures is a disagreement too
+        context(&mut submissions[1]).session_id = None;
+        let (submission, _, _, found) = mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, found.as_str()), (1, "none"));
+
+        // The session of the request wins over the first context
+        context(&mut submissions[1]).session_id = Some("a".to_string());
+        assert!(check(&ceremony.expected(), &submissions).is_ok());
+        let expected = Expected {
+            session_id: Some("b"),
+            ..ceremony.expected()
+        };
+        let (submission, field, expected, found) = mismatch(check(&expected, &submissions));
+        assert_eq!((submission, field), (0, "session_id"));
+        assert_eq!((expected.as_str(), found.as_str()), ("b", "a"));
+    }
+
+    #[test]
+    fn test_signer_mismatch() {
+        let ceremony = Ceremony::new();
+        let stranger = Pubkey::new_unique();
+        let mut submissions = [ceremony.submission(0), ceremony.submission(1)];
+        context(&mut submissions[1]).signer = stranger.to_string();
+        let (submission, field, expected, found) =
+            mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, field), (1, "signer"));
+        assert_eq!(expected, "one of keys");
+        assert_eq!(found, stranger.to_string());
+    }
+
+    #[test]
+    fn test_duplicate_signer() {
+        let ceremony = Ceremony::new();
+        let submissions = [ceremony.submission(1), ceremony.submission(1)];
+        let (submission, field, _, found) = mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, field), (1, "signer"));
+        assert_eq!(
+            found,
+            format!("{}, the signer of signatures[0]", ceremony.keys[1])
+        );
+    }
+
+    #[test]
+    fn test_first_disagreement_is_reported() {
+        let ceremony = Ceremony::new();
+        let mut submissions = [ceremony.submission(0), ceremony.submission(1)];
+        context(&mut submissions[0]).signer = Pubkey::new_unique().to_string();
+        context(&mut submissions[1]).request_digest = "00".repeat(32);
+        let (submission, field, _, _) = mismatch(check(&ceremony.expected(), &submissions));
+        assert_eq!((submission, field), (0, "signer"));
+    }
+
+    #[test]
+    fn test_submission_forms() {
+        let ceremony = Ceremony::new();
+        let bare: SignatureSubmission = serde_json::from_str("\"sig0\"").unwrap();
+        assert_eq!(bare.partial_signature(), "sig0");
+        assert!(bare.context().is_none());
+
+        let json = serde_json::to_string(&ceremony.submission(0)).unwrap();
+        let parsed: SignatureSubmission = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.partial_signature(), "sig0");
+        assert_eq!(
+            parsed.context().unwrap().signer,
+            ceremony.keys[0].to_string()
+        );
+
+        let camel = serde_json::json!({
+            "partialSignature": "sig1",
+            "context": {
+                "requestDigest": DIGEST,
+                "aggregatedPubkey": ceremony.aggregated_pubkey.to_string(),
+                "signer": ceremony.keys[1].to_string(),
+                "environment": null,
+                "sessionId": "a",
+            },
+        });
+        let parsed: SignatureSubmission = serde_json::from_value(camel).unwrap();
+        assert_eq!(parsed.partial_signature(), "sig1");
+        assert_eq!(parsed.context().unwrap().session_id.as_deref(), Some("a"));
+    }
+}
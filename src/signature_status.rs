@@ -0,0 +1,147 @@
+use solana_client::{
+    client_error::ClientError, nonblocking::rpc_client::RpcClient,
+    rpc_request::MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, signature::Signature};
+use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
+
+use crate::{Error, audit::Outcome, input::normalize, models::TransactionStatusEntry};
+
+/// Most signatures `/api/transaction_statuses` takes in one request.
+pub const MAX_SIGNATURES: usize = 256;
+
+/// What a status means at `commitment`: confirmed or failed once it got there, `Broadcast`
+/// while the cluster has seen it at a lower commitment, `None` if it hasn't seen it at all.
+pub fn classify(
+    status: Option<&TransactionStatus>,
+    commitment: CommitmentConfig,
+) -> Option<(Outcome, Option<String>)> {
+    let status = status?;
+    if !status.satisfies_commitment(commitment) {
+        return Some((Outcome::Broadcast, None));
+    }
+    Some(match &status.status {
+        Ok(()) => (Outcome::Confirmed, None),
+        Err(e) => (Outcome::Failed, Some(e.to_string())),
+    })
+}
+
+/// Statuses of `signatures` in the same order, one `getSignatureStatuses` call per
+/// `MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS` of them.
+pub async fn fetch_statuses(
+    rpc_client: &RpcClient,
+    signatures: &[Signature],
+) -> Result<Vec<Option<TransactionStatus>>, ClientError> {
+    let mut statuses = Vec::with_capacity(signatures.len());
+    for chunk in signatures.chunks(MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS) {
+        statuses.extend(rpc_client.get_signature_statuses(chunk).await?.value);
+    }
+    Ok(statuses)
+}
+
+/// One entry per input, in input order. Malformed signatures get an `error` and aren't sent
+/// to the node, only a failing RPC call fails the whole batch.
+pub async fn transaction_statuses(
+    rpc_client: &RpcClient,
+    signatures: &[String],
+) -> Result<Vec<TransactionStatusEntry>, ClientError> {
+    let parsed: Vec<Result<Signature, Error>> = signatures
+        .iter()
+        .map(|s| {
+            normalize(s)?
+                .parse::<Signature>()
+                .map_err(|e| Error::InvalidTransactionId(e.to_string()))
+        })
+        .collect();
+    let valid: Vec<Signature> = parsed
+        .iter()
+        .filter_map(|p| p.as_ref().ok())
+        .copied()
+        .collect();
+    let mut statuses = fetch_statuses(rpc_client, &valid).await?.into_iter();
+
+    Ok(signatures
+        .iter()
+        .zip(parsed)
+        .map(|(input, parsed)| match parsed {
+            Ok(signature) => {
+                let status = statuses.next().flatten();
+                let outcome = classify(status.as_ref(), rpc_client.commitment());
+                TransactionStatusEntry {
+                    signature: signature.to_string(),
+                    error: None,
+                    found: status.is_some(),
+                    confirmation_status: status
+                        .as_ref()
+                        .and_then(|s| s.confirmation_status.as_ref())
+                        .map(|c| {
+                            match c {
+                                TransactionConfirmationStatus::Processed => "processed",
+                                TransactionConfirmationStatus::Confirmed => "confirmed",
+                                TransactionConfirmationStatus::Finalized => "finalized",
+                            }
+                            .to_string()
+                        }),
+                    slot: status.as_ref().map(|s| s.slot),
+                    err: status.and_then(|s| s.err).map(|e| e.to_string()),
+                    outcome: outcome.map(|(outcome, _)| outcome),
+                }
+            }
+            Err(e) => TransactionStatusEntry {
+                signature: input.clone(),
+                error: Some(e.to_string()),
+                found: false,
+                confirmation_status: None,
+                slot: None,
+                err: None,
+                outcome: None,
+            },
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_client::nonblocking::rpc_client::RpcClient;
+    use solana_sdk::signature::Signature;
+
+    use crate::{audit::Outcome, signature_status::transaction_statuses};
+
+    #[tokio::test]
+    async fn test_batch_keeps_input_order() {
+        // More than one getSignatureStatuses call's worth, with malformed entries in between
+        let mut signatures: Vec<String> = (0..300u16)
+            .map(|i| {
+                let mut bytes = [0; 64];
+                bytes[..2].copy_from_slice(&i.to_le_bytes());
+                Signature::from(bytes).to_string()
+            })
+            .collect();
+        signatures[3] = "not a signature".to_string();
+        signatures[299] = "1111".to_string();
+
+        let rpc_client = RpcClient::new_mock("instruction_error".to_string());
+        let entries = transaction_statuses(&rpc_client, &signatures)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 300);
+        for (i, (entry, input)) in entries.iter().zip(&signatures).enumerate() {
+            assert_eq!(&entry.signature, input);
+            if i == 3 || i == 299 {
+                assert!(entry.error.is_some() && !entry.found);
+            } else {
+                assert!(entry.error.is_none() && entry.found);
+                assert_eq!(entry.confirmation_status.as_deref(), Some("finalized"));
+                assert_eq!(entry.slot, Some(1));
+                assert!(entry.err.is_some());
+                assert_eq!(entry.outcome, Some(Outcome::Failed));
+            }
+        }
+
+        let rpc_client = RpcClient::new_mock("sig_not_found".to_string());
+        let entries = transaction_statuses(&rpc_client, &signatures[..2])
+            .await
+            .unwrap();
+        assert!(entries.iter().all(|e| !e.found && e.outcome.is_none()));
+    }
+}
@@ -0,0 +1,196 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
+use solana_sdk::{
+    instruction::InstructionError,
+    message::Message,
+    system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+use crate::{Error, models::SimulationFailure, spl_token_utils::TokenProgram};
+
+// `/api/simulate` and `/api/spl_simulate` run a transfer past the node before a signing round
+// is spent on it. Nobody signed the message yet, so signatures aren't verified, and the node's
+// latest blockhash stands in for that of the message: a `recent_block_hash` fetched a while ago
+// shouldn't make the simulation fail. A simulation the node ran is an answer whatever its
+// outcome, only not getting one is an error.
+
+/// Custom error the System and SPL Token programs both answer a transfer of more than the source
+/// holds with.
+const CUSTOM_INSUFFICIENT_FUNDS: u32 = 1;
+
+/// Why a simulated transaction would fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// The source holds less than the transfer moves
+    InsufficientFunds,
+    /// The fee payer holds less than the fee
+    InsufficientFundsForFee,
+    /// The fee payer was never funded
+    AccountNotFound,
+    /// An instruction failed for another reason
+    InstructionError,
+    /// The transaction was refused before it ran
+    Other,
+}
+
+/// What the node made of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulation {
+    /// `None` when it would succeed
+    pub failure: Option<SimulationFailure>,
+    pub units_consumed: Option<u64>,
+    pub logs: Vec<String>,
+}
+
+/// Simulate the unsigned `message` as it would run now.
+#[tracing::instrument(name = "rpc.simulate_transaction", skip_all)]
+pub async fn simulate(rpc_client: &RpcClient, message: &Message) -> Result<Simulation, Error> {
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let result = rpc_client
+        .simulate_transaction_with_config(&Transaction::new_unsigned(message.clone()), config)
+        .await
+        .map_err(Error::SimulationUnavailable)?
+        .value;
+    Ok(Simulation {
+        failure: result.err.map(|err| failure(message, err)),
+        units_consumed: result.units_consumed,
+        logs: result.logs.unwrap_or_default(),
+    })
+}
+
+/// `err` of a simulation of `message`, the instruction that failed looked up in it.
+pub fn failure(message: &Message, err: TransactionError) -> SimulationFailure {
+    let (kind, instruction) = match &err {
+        TransactionError::InsufficientFundsForFee => (FailureKind::InsufficientFundsForFee, None),
+        TransactionError::AccountNotFound => (FailureKind::AccountNotFound, None),
+        TransactionError::InstructionError(index, error) => {
+            let program = message.program_id(usize::from(*index));
+            let insufficient = match error {
+                InstructionError::InsufficientFunds => true,
+                InstructionError::Custom(CUSTOM_INSUFFICIENT_FUNDS) => {
+                    program.is_some_and(|program| {
+                        *program == system_program::id() || TokenProgram::of(program).is_some()
+                    })
+                }
+                _ => false,
+            };
+            let kind = if insufficient {
+                FailureKind::InsufficientFunds
+            } else {
+                FailureKind::InstructionError
+            };
+            (kind, Some(*index))
+        }
+        _ => (FailureKind::Other, None),
+    };
+    SimulationFailure {
+        kind,
+        instruction,
+        error: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::{pubkey::Pubkey, system_instruction};
+
+    use super::*;
+
+    fn transfer() -> Message {
+        let payer = Pubkey::new_unique();
+        let memo = spl_memo::build_memo(b"invoice 7", &[]);
+        let transfer = system_instruction::transfer(&payer, &Pubkey::new_unique(), 1);
+        Message::new(&[memo, transfer], Some(&payer))
+    }
+
+    fn node(value: serde_json::Value) -> RpcClient {
+        RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::SimulateTransaction,
+                json!({ "context": { "slot": 1 }, "value": value }),
+            )]),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_simulate() {
+        let message = transfer();
+        let passing = node(json!({
+            "err": null,
+            "logs": ["Program 11111111111111111111111111111111 success"],
+            "unitsConsumed": 450,
+        }));
+        let simulation = simulate(&passing, &message).await.unwrap();
+        assert_eq!(simulation.failure, None);
+        assert_eq!(simulation.units_consumed, Some(450));
+        assert_eq!(simulation.logs.len(), 1);
+
+        let failing = node(json!({
+            "err": { "InstructionError": [1, { "Custom": 1 }] },
+            "logs": ["Transfer: insufficient lamports 0, need 1"],
+            "unitsConsumed": 150,
+        }));
+        let simulation = simulate(&failing, &message).await.unwrap();
+        let failure = simulation.failure.unwrap();
+        assert_eq!(failure.kind, FailureKind::InsufficientFunds);
+        assert_eq!(failure.instruction, Some(1));
+        assert_eq!(simulation.logs.len(), 1);
+
+        let unreachable = RpcClient::new_mock("fails".to_string());
+        assert!(matches!(
+            simulate(&unreachable, &message).await,
+            Err(Error::SimulationUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn test_failure_kinds() {
+        let message = transfer();
+        let kind = |err| failure(&message, err).kind;
+        assert_eq!(
+            kind(TransactionError::InsufficientFundsForFee),
+            FailureKind::InsufficientFundsForFee
+        );
+        assert_eq!(
+            kind(TransactionError::AccountNotFound),
+            FailureKind::AccountNotFound
+        );
+        assert_eq!(
+            kind(TransactionError::InstructionError(
+                1,
+                InstructionError::InsufficientFunds
+            )),
+            FailureKind::InsufficientFunds
+        );
+        // Custom error 1 of the memo program means something else entirely
+        assert_eq!(
+            kind(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(1)
+            )),
+            FailureKind::InstructionError
+        );
+        assert_eq!(kind(TransactionError::AccountInUse), FailureKind::Other);
+        let failure = failure(
+            &message,
+            TransactionError::InstructionError(1, InstructionError::Custom(1)),
+        );
+        assert_eq!(
+            (failure.kind, failure.instruction),
+            (FailureKind::InsufficientFunds, Some(1))
+        );
+        assert!(failure.error.contains("Instruction 1"), "{}", failure.error);
+    }
+}
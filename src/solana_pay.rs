@@ -0,0 +1,490 @@
+use std::str::FromStr;
+
+use serde_json::{Value, json};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    Error,
+    units::{Decimals, UiAmount},
+};
+
+// A Solana Pay transfer request is `solana:<recipient>` with an optional query of `amount`,
+// `spl-token`, any number of `reference`s, `label`, `message` and `memo`. Parsing follows the
+// spec rather than what some wallets let through: the amount is a plain decimal with a leading
+// digit and at most the mint's decimals, never an exponent, and every field but `reference`
+// appears at most once. A URL with a link in place of the recipient is a transaction request,
+// the merchant builds that transaction, so it has nothing to send through this service.
+
+pub const SCHEME: &str = "solana";
+
+/// Endpoints taking a `solana_pay_url` of a SOL transfer in place of the fields it sets.
+pub const SOL_ENDPOINTS: &[&str] = &[
+    "/api/send_single",
+    "/api/build_message",
+    "/api/simulate",
+    "/api/agg_send_step_two",
+    "/api/aggregate_signatures",
+];
+
+/// Endpoints taking a `solana_pay_url` of a token transfer in place of the fields it sets.
+pub const SPL_ENDPOINTS: &[&str] = &[
+    "/api/spl_send_single",
+    "/api/spl_build_message",
+    "/api/spl_simulate",
+    "/api/spl_agg_send_step_two",
+    "/api/spl_aggregate_signatures",
+];
+
+/// Whether `endpoint` takes a `solana_pay_url`.
+pub fn accepts(endpoint: &str) -> bool {
+    SOL_ENDPOINTS.contains(&endpoint) || SPL_ENDPOINTS.contains(&endpoint)
+}
+
+/// The fields of a transfer request URL.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferRequest {
+    pub recipient: Pubkey,
+    /// In SOL or in tokens, as written in the URL. `None` when the wallet is to ask for it
+    pub amount: Option<String>,
+    /// Mint of the token to transfer, `None` for SOL
+    pub spl_token: Option<Pubkey>,
+    /// Added in order to the transfer instruction as read-only accounts
+    pub references: Vec<Pubkey>,
+    /// Who is asking, shown by the wallet
+    pub label: Option<String>,
+    /// What it is for, shown by the wallet
+    pub message: Option<String>,
+    /// Sent along in a memo instruction
+    pub memo: Option<String>,
+}
+
+impl TransferRequest {
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        let rest = url
+            .split_once(':')
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(SCHEME))
+            .map(|(_, rest)| rest)
+            .ok_or_else(|| invalid(format!("{:?} is not a solana: URL", url)))?;
+        let rest = rest.split_once('#').map_or(rest, |(rest, _)| rest);
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let path = decode(path, false)?;
+        if path.contains([':', '/']) {
+            return Err(invalid(format!(
+                "{:?} is a transaction request, only transfer requests are supported",
+                url
+            )));
+        }
+        let mut request = Self {
+            recipient: parse_pubkey("recipient", &path)?,
+            ..Self::default()
+        };
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (name, value) = (decode(name, true)?, decode(value, true)?);
+            let once = |field: &mut Option<String>, value: String| match field.replace(value) {
+                Some(_) => Err(invalid(format!("{} is given more than once", name))),
+                None => Ok(()),
+            };
+            match name.as_str() {
+                "amount" => {
+                    check_format(&value)?;
+                    once(&mut request.amount, value)?;
+                }
+                "spl-token" => {
+                    let mint = parse_pubkey("spl-token", &value)?;
+                    if request.spl_token.replace(mint).is_some() {
+                        return Err(invalid("spl-token is given more than once".to_string()));
+                    }
+                }
+                "reference" => request.references.push(parse_pubkey("reference", &value)?),
+                "label" => once(&mut request.label, value)?,
+                "message" => once(&mut request.message, value)?,
+                "memo" => once(&mut request.memo, value)?,
+                // Left for later versions of the spec, wallets ignore them as well
+                _ => {}
+            }
+        }
+        if let (Some(amount), None) = (&request.amount, request.spl_token) {
+            check_amount(amount, Decimals::SOL)?;
+        }
+        Ok(request)
+    }
+
+    /// The URL with the fields in the order of the spec's examples, values percent-encoded.
+    pub fn to_url(&self) -> String {
+        let mut query = Vec::new();
+        if let Some(amount) = &self.amount {
+            query.push(("amount", amount.clone()));
+        }
+        if let Some(mint) = &self.spl_token {
+            query.push(("spl-token", mint.to_string()));
+        }
+        for reference in &self.references {
+            query.push(("reference", reference.to_string()));
+        }
+        for (name, value) in [
+            ("label", &self.label),
+            ("message", &self.message),
+            ("memo", &self.memo),
+        ] {
+            if let Some(value) = value {
+                query.push((name, value.clone()));
+            }
+        }
+        let query: Vec<_> = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, encode(value)))
+            .collect();
+        match query.is_empty() {
+            true => format!("{}:{}", SCHEME, self.recipient),
+            false => format!("{}:{}?{}", SCHEME, self.recipient, query.join("&")),
+        }
+    }
+}
+
+/// Check `amount` is a non-negative decimal written as the spec allows, with at most `decimals`
+/// decimal places.
+pub fn check_amount(amount: &str, decimals: Decimals) -> Result<(), Error> {
+    check_format(amount)?;
+    let places = amount
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len());
+    if places > usize::from(decimals.get()) {
+        return Err(invalid(format!(
+            "amount {} has more than the {} decimal places of the mint",
+            amount, decimals
+        )));
+    }
+    Ok(())
+}
+
+/// `amount` as a URL writes it, in the fewest digits. Unlike elsewhere, digits past `decimals`
+/// aren't dropped from a JSON number: the URL would request less than was asked for.
+pub fn url_amount(amount: &UiAmount, decimals: Decimals) -> Result<String, Error> {
+    check_amount(&amount.to_string(), decimals)?;
+    Ok(amount.to_raw(decimals)?.to_ui_string(decimals))
+}
+
+/// Digits with an optional decimal point that has digits on both sides: no sign, no exponent,
+/// no separators and no `.5` for `0.5`.
+fn check_format(amount: &str) -> Result<(), Error> {
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let valid = match amount.split_once('.') {
+        Some((whole, fraction)) => digits(whole) && digits(fraction),
+        None => digits(amount),
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(invalid(format!(
+            "amount {:?} is not a decimal number like \"1\" or \"0.01\"",
+            amount
+        ))),
+    }
+}
+
+/// Replace `solana_pay_url` in the JSON body of `endpoint` with the fields the URL sets, which
+/// the request must then leave out. Bodies of other endpoints or without the field are left as
+/// they are.
+pub fn expand(endpoint: &str, body: &mut Value) -> Result<(), Error> {
+    let spl = SPL_ENDPOINTS.contains(&endpoint);
+    if !spl && !SOL_ENDPOINTS.contains(&endpoint) {
+        return Ok(());
+    }
+    let Some(fields) = body.as_object_mut() else {
+        return Ok(());
+    };
+    let Some(url) = fields
+        .remove("solana_pay_url")
+        .or_else(|| fields.remove("solanaPayUrl"))
+    else {
+        return Ok(());
+    };
+    let Some(url) = url.as_str() else {
+        return Err(invalid("solana_pay_url must be a string".to_string()));
+    };
+    let request = TransferRequest::parse(url)?;
+    match (spl, request.spl_token) {
+        (false, Some(_)) => {
+            return Err(invalid(
+                "the URL requests a token transfer, send it to the spl_ endpoint".to_string(),
+            ));
+        }
+        (true, None) => {
+            return Err(invalid(
+                "the URL requests a SOL transfer, send it to the SOL endpoint".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    if fields
+        .get("recipients")
+        .and_then(Value::as_array)
+        .is_some_and(|recipients| !recipients.is_empty())
+    {
+        return Err(invalid(
+            "a solana_pay_url pays one recipient, recipients can't be added".to_string(),
+        ));
+    }
+
+    let mut set = |field: &str, others: &[&str], value: Value| {
+        let given = std::iter::once(&field)
+            .chain(others)
+            .find(|name| fields.get(**name).is_some_and(|value| !value.is_null()));
+        if let Some(name) = given {
+            return Err(invalid(format!(
+                "{} is set by solana_pay_url and can't be sent as well",
+                name
+            )));
+        }
+        fields.insert(field.to_string(), value);
+        Ok(())
+    };
+    set("to", &[], json!(request.recipient.to_string()))?;
+    if let Some(amount) = request.amount {
+        set(
+            "amount",
+            &[
+                "lamports",
+                "amount_lamports",
+                "amountLamports",
+                "amount_base_units",
+                "amountBaseUnits",
+            ],
+            json!(amount),
+        )?;
+    }
+    if let Some(mint) = request.spl_token {
+        set(
+            "token_mint",
+            &["tokenMint", "token"],
+            json!(mint.to_string()),
+        )?;
+    }
+    if let Some(memo) = request.memo {
+        set("memo", &[], json!(memo))?;
+    }
+    if !request.references.is_empty() {
+        let references: Vec<_> = request.references.iter().map(Pubkey::to_string).collect();
+        set("references", &[], json!(references))?;
+    }
+    Ok(())
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidSolanaPay(reason)
+}
+
+fn parse_pubkey(field: &str, value: &str) -> Result<Pubkey, Error> {
+    Pubkey::from_str(value)
+        .map_err(|_| invalid(format!("{} {:?} is not a public key", field, value)))
+}
+
+/// Percent-decode a URL component, in a query `+` is a space as browsers read it.
+fn decode(component: &str, query: bool) -> Result<String, Error> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = component
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| {
+                        invalid(format!("{:?} has an invalid percent-encoding", component))
+                    })?;
+                decoded.push(byte);
+                i += 3;
+            }
+            b'+' if query => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded)
+        .map_err(|_| invalid(format!("{:?} doesn't decode to UTF-8", component)))
+}
+
+/// Percent-encode everything but the characters JavaScript's `encodeURIComponent` keeps, so a
+/// space is `%20` as in the spec's examples.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'!'
+            | b'~'
+            | b'*'
+            | b'\''
+            | b'('
+            | b')' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::Error;
+    use crate::solana_pay::{TransferRequest, check_amount, expand, url_amount};
+    use crate::units::{Decimals, UiAmount};
+
+    const MERCHANT: &str = "mvines9iiHiQTysrwkJjGf2gb9Ex9jXJX8ns3qwf2kN";
+    const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    fn pubkey(s: &str) -> Pubkey {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_spec_examples() {
+        // The transfer request examples of the Solana Pay specification
+        let sol = "solana:mvines9iiHiQTysrwkJjGf2gb9Ex9jXJX8ns3qwf2kN?amount=1&label=Michael&message=Thanks%20for%20all%20the%20fish&memo=OrderId12345";
+        let request = TransferRequest::parse(sol).unwrap();
+        assert_eq!(
+            request,
+            TransferRequest {
+                recipient: pubkey(MERCHANT),
+                amount: Some("1".to_string()),
+                label: Some("Michael".to_string()),
+                message: Some("Thanks for all the fish".to_string()),
+                memo: Some("OrderId12345".to_string()),
+                ..TransferRequest::default()
+            }
+        );
+        assert_eq!(request.to_url(), sol);
+
+        let usdc = "solana:mvines9iiHiQTysrwkJjGf2gb9Ex9jXJX8ns3qwf2kN?amount=0.01&spl-token=EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+        let request = TransferRequest::parse(usdc).unwrap();
+        assert_eq!(request.amount.as_deref(), Some("0.01"));
+        assert_eq!(request.spl_token, Some(pubkey(USDC)));
+        assert_eq!(request.to_url(), usdc);
+
+        // No amount, the wallet asks for it
+        let any_amount = "solana:mvines9iiHiQTysrwkJjGf2gb9Ex9jXJX8ns3qwf2kN?label=Michael";
+        let request = TransferRequest::parse(any_amount).unwrap();
+        assert_eq!(request.amount, None);
+        assert_eq!(request.to_url(), any_amount);
+
+        let bare = format!("solana:{}", MERCHANT);
+        assert_eq!(TransferRequest::parse(&bare).unwrap().to_url(), bare);
+
+        // Transaction requests name a link instead of a recipient
+        for url in [
+            "solana:https://example.com/solana-pay",
+            "solana:https%3A%2F%2Fexample.com%2Fsolana-pay%3Forder%3D12345",
+        ] {
+            assert!(matches!(
+                TransferRequest::parse(url),
+                Err(Error::InvalidSolanaPay(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_references_keep_their_order() {
+        let references = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let url = format!(
+            "solana:{}?amount=0.5&reference={}&reference={}&memo=a+b%26c",
+            MERCHANT, references[1], references[0]
+        );
+        let request = TransferRequest::parse(&url).unwrap();
+        assert_eq!(request.references, [references[1], references[0]]);
+        assert_eq!(request.memo.as_deref(), Some("a b&c"));
+        let reparsed = TransferRequest::parse(&request.to_url()).unwrap();
+        assert_eq!(reparsed, request);
+    }
+
+    #[test]
+    fn test_amounts() {
+        let parse = |amount: &str| {
+            TransferRequest::parse(&format!("solana:{}?amount={}", MERCHANT, amount))
+        };
+        for amount in ["0", "1", "0.000000001", "1000000"] {
+            parse(amount).unwrap();
+        }
+        // The spec forbids a missing leading zero and scientific notation, SOL has 9 decimals
+        for amount in [
+            ".5",
+            "1.",
+            "1e3",
+            "-1",
+            "+1",
+            "1,5",
+            "1_000",
+            "0.0000000001",
+            "",
+        ] {
+            assert!(parse(amount).is_err(), "{}", amount);
+        }
+        // A token amount is checked against the mint's decimals once they are known
+        let usdc = Decimals::new(6).unwrap();
+        check_amount("0.000001", usdc).unwrap();
+        assert!(check_amount("0.0000001", usdc).is_err());
+        assert_eq!(url_amount(&UiAmount::from("1.50"), usdc).unwrap(), "1.5");
+        assert_eq!(url_amount(&UiAmount::from(0.01), usdc).unwrap(), "0.01");
+        assert!(url_amount(&UiAmount::from(0.0000001), usdc).is_err());
+        assert!(url_amount(&UiAmount::from(-1.0), Decimals::SOL).is_err());
+
+        let twice = format!("solana:{}?amount=1&amount=2", MERCHANT);
+        assert!(TransferRequest::parse(&twice).is_err());
+        let bad_reference = format!("solana:{}?reference=nope", MERCHANT);
+        assert!(TransferRequest::parse(&bad_reference).is_err());
+        assert!(TransferRequest::parse("bitcoin:abc").is_err());
+        assert!(TransferRequest::parse(&format!("solana:{}?memo=%ZZ", MERCHANT)).is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let reference = Pubkey::new_unique();
+        let url = format!(
+            "solana:{}?amount=0.01&spl-token={}&reference={}&memo=OrderId12345",
+            MERCHANT, USDC, reference
+        );
+        let mut body = json!({ "keypair": "k", "solana_pay_url": url, "decimals": 6 });
+        expand("/api/spl_send_single", &mut body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "keypair": "k",
+                "decimals": 6,
+                "to": MERCHANT,
+                "amount": "0.01",
+                "token_mint": USDC,
+                "memo": "OrderId12345",
+                "references": [reference.to_string()],
+            })
+        );
+
+        // A field the URL sets can't be sent too, and the kind of transfer has to match
+        let mut conflict = json!({ "solana_pay_url": url, "token": "USDC" });
+        assert!(expand("/api/spl_send_single", &mut conflict).is_err());
+        let mut sol = json!({ "solana_pay_url": url });
+        assert!(expand("/api/send_single", &mut sol).is_err());
+        let mut lamports = json!({
+            "solanaPayUrl": format!("solana:{}?amount=1", MERCHANT),
+            "lamports": 1_000_000_000,
+        });
+        assert!(expand("/api/agg_send_step_two", &mut lamports).is_err());
+
+        // Other endpoints never look at the field
+        let mut other = json!({ "solana_pay_url": 7 });
+        expand("/api/balance", &mut other).unwrap();
+        assert_eq!(other, json!({ "solana_pay_url": 7 }));
+    }
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use poem::http::HeaderMap;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    Error,
+    models::{ApiKeySpend, SpendLimitsResponse, SpendUsage},
+    tenant::{Tenants, request_key},
+};
+
+const WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Caps on what may leave the wallets within any 24 hours, unset means unlimited.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpendLimits {
+    pub lamports: Option<u64>,
+    /// Base units per mint, mints not listed are unlimited
+    pub tokens: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpendLimitConfig {
+    /// Shared by all requests
+    pub global: SpendLimits,
+    /// Applied to each API key on its own, on top of the global limits
+    pub per_api_key: SpendLimits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Asset {
+    Sol,
+    Token(Pubkey),
+}
+
+impl Display for Asset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sol => f.write_str("SOL"),
+            Self::Token(mint) => write!(f, "{}", mint),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Limits {
+    lamports: Option<u64>,
+    tokens: HashMap<Pubkey, u64>,
+}
+
+impl Limits {
+    fn parse(limits: &SpendLimits) -> Result<Self, String> {
+        let tokens = limits
+            .tokens
+            .iter()
+            .map(|(mint, amount)| {
+                mint.parse()
+                    .map(|mint| (mint, *amount))
+                    .map_err(|e| format!("invalid mint {:?} in spend limits: {}", mint, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            lamports: limits.lamports,
+            tokens,
+        })
+    }
+
+    fn get(&self, asset: &Asset) -> Option<u64> {
+        match asset {
+            Asset::Sol => self.lamports,
+            Asset::Token(mint) => self.tokens.get(mint).copied(),
+        }
+    }
+
+    fn assets(&self) -> impl Iterator<Item = Asset> + '_ {
+        self.lamports
+            .map(|_| Asset::Sol)
+            .into_iter()
+            .chain(self.tokens.keys().map(|mint| Asset::Token(*mint)))
+    }
+}
+
+#[derive(Debug)]
+struct Spend {
+    id: u64,
+    at: Instant,
+    api_key: Option<String>,
+    asset: Asset,
+    amount: u64,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    next_id: u64,
+    spends: Vec<Spend>,
+}
+
+/// A booked spend, hand it back to `SpendGuard::release` if the transaction never went out.
+#[derive(Debug)]
+pub struct Reservation {
+    id: u64,
+}
+
+#[derive(Debug, Default)]
+struct Caps {
+    global: Limits,
+    per_api_key: Limits,
+}
+
+/// Rolling 24 hour spend limits, booked before a transfer is broadcast.
+pub struct SpendGuard {
+    caps: Caps,
+    /// Shared with the guards of the configs reloaded since
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+impl SpendGuard {
+    pub fn new(config: &SpendLimitConfig) -> Result<Self, String> {
+        let caps = Caps {
+            global: Limits::parse(&config.global)?,
+            per_api_key: Limits::parse(&config.per_api_key)?,
+        };
+        Ok(Self {
+            caps,
+            ledger: Arc::default(),
+        })
+    }
+
+    /// These limits over what was booked through `previous`, the guard of the config they
+    /// replace. What was spent so far still counts against them, and a reservation made
+    /// through either can be released through the other.
+    pub fn spent_with(self, previous: &SpendGuard) -> Self {
+        Self {
+            ledger: previous.ledger.clone(),
+            ..self
+        }
+    }
+
+    /// Book `amount` of `asset` against the global limits and, with an API key, that key's own
+    /// limits. Both are checked under one lock, so concurrent requests can't overshoot.
+    pub fn reserve(
+        &self,
+        api_key: Option<&str>,
+        asset: Asset,
+        amount: u64,
+        now: Instant,
+    ) -> Result<Reservation, Error> {
+        let caps = &self.caps;
+        let mut ledger = self.ledger.lock().unwrap();
+        prune(&mut ledger.spends, now);
+
+        let spent = |key: Option<&str>| -> u64 {
+            ledger
+                .spends
+                .iter()
+                .filter(|s| s.asset == asset && (key.is_none() || s.api_key.as_deref() == key))
+                .map(|s| s.amount)
+                .sum()
+        };
+        let mut checks = vec![("global", &caps.global, spent(None))];
+        if let Some(key) = api_key {
+            checks.push(("api key", &caps.per_api_key, spent(Some(key))));
+        }
+        for (scope, limits, spent) in checks {
+            let Some(limit) = limits.get(&asset) else {
+                continue;
+            };
+            if spent.saturating_add(amount) > limit {
+                return Err(Error::SpendLimitExceeded {
+                    scope,
+                    asset: asset.to_string(),
+                    limit,
+                    remaining: limit.saturating_sub(spent),
+                });
+            }
+        }
+
+        let id = ledger.next_id;
+        ledger.next_id += 1;
+        ledger.spends.push(Spend {
+            id,
+            at: now,
+            api_key: api_key.map(str::to_string),
+            asset,
+            amount,
+        });
+        Ok(Reservation { id })
+    }
+
+    pub fn release(&self, reservation: Reservation) {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.spends.retain(|s| s.id != reservation.id);
+    }
+
+    /// Spending within the window ending at `now`, for `/api/spend_limits`.
+    pub fn consumption(&self, now: Instant) -> SpendLimitsResponse {
+        let caps = &self.caps;
+        let mut ledger = self.ledger.lock().unwrap();
+        prune(&mut ledger.spends, now);
+
+        let mut global = BTreeMap::new();
+        let mut api_keys: BTreeMap<&str, BTreeMap<Asset, u64>> = BTreeMap::new();
+        for spend in &ledger.spends {
+            *global.entry(spend.asset).or_default() += spend.amount;
+            if let Some(key) = &spend.api_key {
+                *api_keys
+                    .entry(key)
+                    .or_default()
+                    .entry(spend.asset)
+                    .or_default() += spend.amount;
+            }
+        }
+        SpendLimitsResponse {
+            enabled: true,
+            global: usage(&caps.global, global),
+            api_keys: api_keys
+                .into_iter()
+                .map(|(key, spent)| ApiKeySpend {
+                    api_key_id: key.to_string(),
+                    usage: usage(&caps.per_api_key, spent),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Concurrent requests take `now` before the lock, so spends are only roughly in time order
+fn prune(spends: &mut Vec<Spend>, now: Instant) {
+    spends.retain(|s| now.duration_since(s.at) < WINDOW);
+}
+
+/// Every limited asset plus anything else that was spent, SOL first and mints in order.
+fn usage(limits: &Limits, mut spent: BTreeMap<Asset, u64>) -> Vec<SpendUsage> {
+    for asset in limits.assets() {
+        spent.entry(asset).or_default();
+    }
+    spent
+        .into_iter()
+        .map(|(asset, spent)| {
+            let limit = limits.get(&asset);
+            SpendUsage {
+                asset: asset.to_string(),
+                spent,
+                limit,
+                remaining: limit.map(|limit| limit.saturating_sub(spent)),
+            }
+        })
+        .collect()
+}
+
+/// What requests without a configured API key are booked under. They share one allowance, a
+/// fresh or missing key each time doesn't get a new one.
+pub const UNKNOWN_API_KEY_ID: &str = "unknown";
+
+/// Identity of the request's API key. Only a hash of the key is kept, so
+/// `/api/spend_limits` can tell keys apart without revealing them. A key `tenants` doesn't
+/// know, or none at all, is `UNKNOWN_API_KEY_ID`.
+pub fn api_key_id(tenants: &Tenants, headers: &HeaderMap) -> String {
+    let key = request_key(headers).filter(|_| tenants.known(headers).is_some());
+    match key {
+        Some(key) => {
+            let hash = Sha256::digest(key.as_bytes());
+            hash[..8].iter().map(|b| format!("{:02x}", b)).collect()
+        }
+        None => UNKNOWN_API_KEY_ID.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use poem::http::HeaderMap;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::{
+        Error,
+        spend_limit::{
+            Asset, SpendGuard, SpendLimitConfig, SpendLimits, UNKNOWN_API_KEY_ID, api_key_id,
+        },
+        tenant::{Tenants, key_hash},
+    };
+
+    fn guard(mint: &Pubkey) -> SpendGuard {
+        SpendGuard::new(&SpendLimitConfig {
+            global: SpendLimits {
+                lamports: Some(1_000),
+                tokens: HashMap::from([(mint.to_string(), 50)]),
+            },
+            per_api_key: SpendLimits {
+                lamports: Some(400),
+                tokens: HashMap::new(),
+            },
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let mint = Pubkey::new_unique();
+        let guard = guard(&mint);
+        let start = Instant::now();
+        let hour = Duration::from_secs(60 * 60);
+
+        guard.reserve(Some("a"), Asset::Sol, 300, start).unwrap();
+        guard
+            .reserve(Some("a"), Asset::Sol, 100, start + 12 * hour)
+            .unwrap();
+        match guard.reserve(Some("a"), Asset::Sol, 1, start + 23 * hour) {
+            Err(Error::SpendLimitExceeded {
+                scope, remaining, ..
+            }) => assert_eq!((scope, remaining), ("api key", 0)),
+            other => panic!("unexpected {:?}", other),
+        }
+        // Other keys and keyless requests only share the global limit
+        guard
+            .reserve(Some("b"), Asset::Sol, 400, start + 23 * hour)
+            .unwrap();
+        guard
+            .reserve(None, Asset::Sol, 200, start + 23 * hour)
+            .unwrap();
+        match guard.reserve(None, Asset::Sol, 1, start + 23 * hour) {
+            Err(Error::SpendLimitExceeded {
+                scope, remaining, ..
+            }) => assert_eq!((scope, remaining), ("global", 0)),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // 24 hours after the first spend it drops out of the window, the second one still counts
+        let later = start + 24 * hour;
+        assert!(guard.reserve(Some("a"), Asset::Sol, 301, later).is_err());
+        let reservation = guard.reserve(Some("a"), Asset::Sol, 300, later).unwrap();
+        let usage = guard.consumption(later);
+        assert_eq!(usage.global[0].spent, 1_000);
+        assert_eq!(usage.api_keys[0].api_key_id, "a");
+        assert_eq!(usage.api_keys[0].usage[0].remaining, Some(0));
+
+        guard.release(reservation);
+        assert_eq!(guard.consumption(later).global[0].remaining, Some(300));
+
+        // Tokens have their own limits, unlisted mints are unlimited
+        guard.reserve(None, Asset::Token(mint), 50, later).unwrap();
+        assert!(guard.reserve(None, Asset::Token(mint), 1, later).is_err());
+        guard
+            .reserve(None, Asset::Token(Pubkey::new_unique()), u64::MAX, later)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_reservations() {
+        let guard = Arc::new(guard(&Pubkey::new_unique()));
+        let now = Instant::now();
+        let granted: usize = (0..64)
+            .map(|i| {
+                let guard = guard.clone();
+                let key = format!("key-{}", i % 4);
+                thread::spawn(move || guard.reserve(Some(&key), Asset::Sol, 30, now).is_ok())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap() as usize)
+            .sum();
+        // Each of the 4 keys could get 13 of its 16 requests through its 400 lamport limit, the
+        // global 1000 stops them at 33 between them
+        assert_eq!(granted, 33);
+        let usage = guard.consumption(now);
+        assert_eq!(usage.global[0].spent, 990);
+        assert!(usage.api_keys.iter().all(|k| k.usage[0].spent <= 390));
+    }
+
+    #[test]
+    fn test_limits_replaced_mid_flight() {
+        let mint = Pubkey::new_unique();
+        let guard = Arc::new(guard(&mint));
+        let now = Instant::now();
+        let held = guard.reserve(None, Asset::Sol, 300, now).unwrap();
+
+        // Requests that took the old guard keep reserving while the new limits take over, each
+        // one is checked against the limits of its guard and never goes past the old ones
+        let lower = || {
+            SpendGuard::new(&SpendLimitConfig {
+                global: SpendLimits {
+                    lamports: Some(400),
+                    tokens: HashMap::new(),
+                },
+                ..SpendLimitConfig::default()
+            })
+            .unwrap()
+        };
+        let requests: Vec<_> = (0..32)
+            .map(|_| {
+                let guard = guard.clone();
+                thread::spawn(move || guard.reserve(None, Asset::Sol, 10, now).is_ok())
+            })
+            .collect();
+        let lowered = lower().spent_with(&guard);
+        let granted = requests
+            .into_iter()
+            .map(|request| request.join().unwrap())
+            .filter(|granted| *granted)
+            .count() as u64;
+        let spent = lowered.consumption(now).global[0].spent;
+        assert_eq!(spent, 300 + 10 * granted);
+        assert!(spent <= 1_000);
+
+        // What was spent before still counts, against the new limit
+        let usage = lowered.consumption(now);
+        assert_eq!(usage.global[0].limit, Some(400));
+        assert!(lowered.reserve(None, Asset::Sol, 400, now).is_err());
+        // Mints that lost their limit are unlimited now
+        lowered.reserve(None, Asset::Token(mint), 51, now).unwrap();
+        // Booked through the old guard, released through the new one
+        lowered.release(held);
+        assert_eq!(
+            lowered.consumption(now).global[0].remaining,
+            Some(400u64.saturating_sub(10 * granted))
+        );
+    }
+
+    fn bearer(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", format!("Bearer {}", key).parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_api_key_id() {
+        let tenants = Tenants::default().access_keys(&[key_hash("secret-key")]);
+        assert_eq!(api_key_id(&tenants, &HeaderMap::new()), UNKNOWN_API_KEY_ID);
+        let id = api_key_id(&tenants, &bearer("secret-key"));
+        assert_eq!(id.len(), 16);
+        assert!(!id.contains("secret"));
+        assert_eq!(
+            api_key_id(&tenants, &bearer("other-key")),
+            UNKNOWN_API_KEY_ID
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Basic c2VjcmV0".parse().unwrap());
+        assert_eq!(api_key_id(&tenants, &headers), UNKNOWN_API_KEY_ID);
+    }
+
+    #[test]
+    fn test_rotating_keys_share_one_allowance() {
+        let guard = guard(&Pubkey::new_unique());
+        let tenants = Tenants::default().access_keys(&[key_hash("known")]);
+        let now = Instant::now();
+        // A new unknown key, or none, every time still books against the same 400 lamports
+        let reserve = |headers: &HeaderMap| {
+            guard.reserve(
+                Some(api_key_id(&tenants, headers).as_str()),
+                Asset::Sol,
+                200,
+                now,
+            )
+        };
+        reserve(&bearer("fresh-1")).unwrap();
+        reserve(&HeaderMap::new()).unwrap();
+        match reserve(&bearer("fresh-2")) {
+            Err(Error::SpendLimitExceeded { scope, .. }) => assert_eq!(scope, "api key"),
+            other => panic!("unexpected {:?}", other),
+        }
+        // A configured key has an allowance of its own
+        reserve(&bearer("known")).unwrap();
+        let usage = guard.consumption(now);
+        assert!(
+            usage
+                .api_keys
+                .iter()
+                .any(|key| key.api_key_id == UNKNOWN_API_KEY_ID && key.usage[0].spent == 400)
+        );
+    }
+}
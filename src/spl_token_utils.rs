@@ -1,81 +1,888 @@
-use crate::Error;
-use solana_sdk::{
-    instruction::Instruction, message::Message, pubkey::Pubkey, transaction::Transaction,
-};
-use spl_associated_token_account::{
-    get_associated_token_address, instruction::create_associated_token_account,
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_sdk::{program_option::COption, program_pack::Pack, pubkey::Pubkey};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token::state::{Account, Mint};
+use spl_token_2022::extension::StateWithExtensions;
+
+use crate::{
+    Error,
+    account_batch::AccountBatcher,
+    models::{Network, SplTokenBalanceResponse},
+    units::RawTokenAmount,
+    warning::{Warned, WarningCode},
 };
-use spl_token::instruction as token_instruction;
-use solana_client::rpc_client::RpcClient;
 
+// A mint belongs to the classic token program or to Token-2022, and its token accounts, their
+// associated addresses and the instructions moving them belong to the same one. Token-2022
+// keeps the classic layout for the base state and appends its extensions, so both are read as
+// the classic `Account` and `Mint` once Token-2022 data has been checked; the extensions
+// themselves aren't interpreted, except for the few of a mint that change how its tokens move,
+// which are refused.
+
+/// The program owning a mint and its token accounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum TokenProgram {
+    #[default]
+    #[serde(rename = "spl_token")]
+    SplToken,
+    #[serde(rename = "token_2022")]
+    Token2022,
+}
+
+impl TokenProgram {
+    pub fn id(self) -> Pubkey {
+        match self {
+            Self::SplToken => spl_token::id(),
+            Self::Token2022 => spl_token_2022::id(),
+        }
+    }
+
+    /// The token program `program_id` is, `None` for any other program.
+    pub fn of(program_id: &Pubkey) -> Option<Self> {
+        [Self::SplToken, Self::Token2022]
+            .into_iter()
+            .find(|program| program.id() == *program_id)
+    }
+
+    /// The associated token account of `owner` for `mint`.
+    pub fn associated_address(self, owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(owner, mint, &self.id())
+    }
+
+    /// Size of an associated token account the program creates, a Token-2022 one always
+    /// carries the `ImmutableOwner` extension.
+    pub fn associated_account_len(self) -> usize {
+        match self {
+            Self::SplToken => Account::LEN,
+            // The account type byte, then the extension's type and length
+            Self::Token2022 => Account::LEN + 1 + 4,
+        }
+    }
+}
+
+/// The base state of a token account of `program`, Token-2022 extensions past it are skipped.
+pub fn unpack_account(program: TokenProgram, data: &[u8]) -> Result<Account, Error> {
+    let base = match program {
+        TokenProgram::SplToken => data,
+        TokenProgram::Token2022 => {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(data)?;
+            &data[..Account::LEN]
+        }
+    };
+    Ok(Account::unpack(base)?)
+}
+
+/// The base state of a mint of `program`, Token-2022 extensions past it are skipped.
+pub fn unpack_mint(program: TokenProgram, data: &[u8]) -> Result<Mint, Error> {
+    let base = match program {
+        TokenProgram::SplToken => data,
+        TokenProgram::Token2022 => {
+            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(data)?;
+            &data[..Mint::LEN]
+        }
+    };
+    Ok(Mint::unpack(base)?)
+}
 
-pub fn create_spl_token_transaction(
-    amount: u64,
-    from: &Pubkey,
-    to: &Pubkey,
+/// Token-2022 mint extensions, by type number, that a `transfer_checked` built here can't move
+/// tokens under: a transfer hook needs the extra accounts of its program, and confidential
+/// transfers keep balances the instruction doesn't see.
+const UNSUPPORTED_MINT_EXTENSIONS: &[(u16, &str)] = &[
+    (4, "confidential_transfer_mint"),
+    (14, "transfer_hook"),
+    (16, "confidential_transfer_fee_config"),
+];
+
+/// Refuse the Token-2022 mint `mint` with the `data` of its account when it carries one of
+/// `UNSUPPORTED_MINT_EXTENSIONS`. The extensions are walked by their type-length-value
+/// headers, ones this version of `spl-token-2022` doesn't know included.
+fn check_mint_extensions(mint: &Pubkey, data: &[u8]) -> Result<(), Error> {
+    StateWithExtensions::<spl_token_2022::state::Mint>::unpack(data)?;
+    // Past the base state padded to the length of an account and the account type byte
+    let mut offset = Account::LEN + 1;
+    while let Some(header) = data.get(offset..offset + 4) {
+        let extension = u16::from_le_bytes([header[0], header[1]]);
+        let length = usize::from(u16::from_le_bytes([header[2], header[3]]));
+        if let Some((_, name)) = UNSUPPORTED_MINT_EXTENSIONS
+            .iter()
+            .find(|(unsupported, _)| *unsupported == extension)
+        {
+            return Err(Error::UnsupportedMintExtension {
+                mint: *mint,
+                extension: name,
+            });
+        }
+        offset += 4 + length;
+    }
+    Ok(())
+}
+
+/// The program owning `mint`. Anything but a Token-2022 mint, including no account at all, is
+/// taken for the classic program, which is what the transfer was built for before. A
+/// Token-2022 mint goes through `check_mint_extensions`.
+#[tracing::instrument(name = "rpc.get_token_program", skip_all, fields(%mint))]
+pub async fn fetch_token_program(
+    rpc_client: &AsyncRpcClient,
+    mint: &Pubkey,
+) -> Result<TokenProgram, Error> {
+    let account = rpc_client
+        .get_account_with_commitment(mint, rpc_client.commitment())
+        .await
+        .map_err(Error::AccountFetchFailed)?
+        .value;
+    let Some(account) = account else {
+        return Ok(TokenProgram::default());
+    };
+    let program = TokenProgram::of(&account.owner).unwrap_or_default();
+    if program == TokenProgram::Token2022 {
+        check_mint_extensions(mint, &account.data)?;
+    }
+    Ok(program)
+}
+
+/// Whether there's an account at `address`. Only a missing account is `false`, a failed read
+/// is an error rather than taken for one.
+#[tracing::instrument(name = "rpc.get_account", skip_all, fields(%address))]
+pub async fn account_exists(rpc_client: &AsyncRpcClient, address: &Pubkey) -> Result<bool, Error> {
+    Ok(rpc_client
+        .get_account_with_commitment(address, rpc_client.commitment())
+        .await
+        .map_err(Error::AccountFetchFailed)?
+        .value
+        .is_some())
+}
+
+// Wrapped SOL accounts (of `spl_token::native_mint`) hold their balance as lamports. The token
+// program keeps the rent-exempt reserve out of the account's `amount`, and lamports sent to
+// the account directly only count once a `SyncNative` instruction adds them. So `amount` is
+// what a transfer can move, and the lamports past it are reported apart.
+
+/// Lamports of a wrapped SOL account, split the way the token program counts them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NativeBalance {
+    /// Rent-exempt reserve, never transferable
+    pub rent_reserve: u64,
+    /// The account's token `amount`
+    pub spendable: u64,
+    /// Lamports sent to the account since its last `SyncNative`
+    pub unsynced: u64,
+}
+
+impl NativeBalance {
+    /// The split of `account` holding `lamports`, `None` when it isn't a wrapped SOL account.
+    pub fn of(account: &Account, lamports: u64) -> Option<Self> {
+        let COption::Some(rent_reserve) = account.is_native else {
+            return None;
+        };
+        Some(Self {
+            rent_reserve,
+            spendable: account.amount,
+            unsynced: lamports
+                .saturating_sub(rent_reserve)
+                .saturating_sub(account.amount),
+        })
+    }
+
+    /// Fail with `NativeReserve` when sending `amount` from the account at `address` would
+    /// take more than its token amount.
+    pub fn check_transfer(&self, address: &Pubkey, amount: RawTokenAmount) -> Result<(), Error> {
+        if amount.get() <= self.spendable {
+            return Ok(());
+        }
+        Err(Error::NativeReserve {
+            account: *address,
+            requested: amount.get(),
+            spendable: self.spendable,
+            unsynced: self.unsynced,
+        })
+    }
+}
+
+/// Check that the token account at `address` can fund a transfer of `token_mint` signed by
+/// `signer`. The signer has to be the owner in the account data, or its delegate when
+/// `allow_delegate` is set.
+pub fn check_source_account(
+    address: &Pubkey,
+    account: &Account,
     token_mint: &Pubkey,
-    payer: &Pubkey,
-    memo: Option<String>,
-    decimals: u8,
-) -> Result<Transaction, Error> {
-    let mut instructions = Vec::new();
-
-    // Get associated token addresses
-    let from_ata = get_associated_token_address(from, token_mint);
-    let to_ata = get_associated_token_address(to, token_mint);
-
-    let rpc_client = RpcClient::new("https://api.testnet.solana.com".to_string());
-
-    if rpc_client.get_account(&from_ata).is_err() {
-
-    // For now,  always try to create it (instruction will fail if it already exists)
-    let create_ata_instruction = create_associated_token_account(
-        payer, // fee payer
-        to,    // wallet owner
-        token_mint,
-        &spl_token::id(),
+    signer: &Pubkey,
+    allow_delegate: bool,
+) -> Result<(), Error> {
+    if account.mint != *token_mint {
+        return Err(Error::SourceMintMismatch {
+            account: *address,
+            expected: *token_mint,
+            actual: account.mint,
+        });
+    }
+    let delegated = allow_delegate && account.delegate == COption::Some(*signer);
+    if account.owner != *signer && !delegated {
+        return Err(Error::SourceOwnerMismatch {
+            account: *address,
+            expected: *signer,
+            actual: account.owner,
+        });
+    }
+    if account.is_frozen() {
+        return Err(Error::SourceAccountFrozen(*address));
+    }
+    Ok(())
+}
+
+/// Fetch the source token account and run `check_source_account` on it. A wrapped SOL
+/// account also has to hold `amount` outside its rent-exempt reserve.
+#[tracing::instrument(name = "rpc.get_source_account", skip_all, fields(%address))]
+pub async fn validate_source_account(
+    rpc_client: &AsyncRpcClient,
+    address: &Pubkey,
+    token_mint: &Pubkey,
+    signer: &Pubkey,
+    allow_delegate: bool,
+    amount: RawTokenAmount,
+) -> Result<(), Error> {
+    let account = rpc_client
+        .get_account_with_commitment(address, rpc_client.commitment())
+        .await
+        .map_err(Error::AccountFetchFailed)?
+        .value
+        .ok_or(Error::SourceAccountNotFound(*address))?;
+    let program = TokenProgram::of(&account.owner).ok_or(Error::SourceAccountNotFound(*address))?;
+    let token_account = unpack_account(program, &account.data)?;
+    check_source_account(address, &token_account, token_mint, signer, allow_delegate)?;
+    match NativeBalance::of(&token_account, account.lamports) {
+        Some(native) => native.check_transfer(address, amount),
+        None => Ok(()),
+    }
+}
+
+/// Balance of `owner`'s associated token account of `token_mint`, with the mint's decimals.
+/// The mint and the associated accounts under both token programs are looked up through
+/// `accounts`, in the same batch, `fresh` past the ones found missing; the mint's owner picks
+/// the account. When the mint can't be read and `registered_decimals` are known the balance
+/// comes with those and a warning.
+pub async fn token_balance(
+    accounts: &AccountBatcher,
+    rpc_client: Arc<AsyncRpcClient>,
+    net: Network,
+    owner: &Pubkey,
+    token_mint: &Pubkey,
+    registered_decimals: Option<u8>,
+    fresh: bool,
+) -> Result<Warned<SplTokenBalanceResponse>, Error> {
+    let addresses = [TokenProgram::SplToken, TokenProgram::Token2022]
+        .map(|program| program.associated_address(owner, token_mint));
+    let (classic, token_2022, mint) = tokio::join!(
+        accounts.lookup(rpc_client.clone(), net, addresses[0], fresh),
+        accounts.lookup(rpc_client.clone(), net, addresses[1], fresh),
+        accounts.lookup(rpc_client, net, *token_mint, fresh),
     );
-    instructions.push(create_ata_instruction);
+    let mint_program = match &mint {
+        Ok(Some(mint)) => TokenProgram::of(&mint.owner),
+        _ => None,
+    };
+    // Without a readable mint, the account that exists, the classic one if both do
+    let (program, account) = match (mint_program, classic, token_2022) {
+        (Some(TokenProgram::Token2022), _, account) => (TokenProgram::Token2022, account),
+        (Some(TokenProgram::SplToken), account, _) => (TokenProgram::SplToken, account),
+        (None, Ok(None), account) => (TokenProgram::Token2022, account),
+        (None, account, _) => (TokenProgram::SplToken, account),
+    };
+    let token_account = program.associated_address(owner, token_mint);
+    let account = account
+        .map_err(Error::AccountFetchFailed)?
+        .ok_or(Error::TokenAccountNotFound)?;
+    let lamports = account.lamports;
+    let account = unpack_account(program, &account.data)?;
+
+    let decimals = match mint {
+        Ok(Some(mint)) => unpack_mint(program, &mint.data).map(|mint| mint.decimals),
+        Ok(None) => return Err(Error::TokenMintNotFound),
+        Err(e) => Err(Error::AccountFetchFailed(e)),
+    };
+
+    let mut warnings = Vec::new();
+    let decimals = match (decimals, registered_decimals) {
+        (Ok(decimals), _) => decimals,
+        (Err(e), Some(registered)) => {
+            warnings.push(
+                WarningCode::MintFetchFailed
+                    .warning(format!(
+                        "couldn't read the mint {}: {}, using the registered {} decimals",
+                        token_mint, e, registered
+                    ))
+                    .field("token_mint"),
+            );
+            registered
+        }
+        (Err(e), None) => return Err(e),
+    };
+    if account.owner != *owner {
+        warnings.push(
+            WarningCode::OwnerMismatch
+                .warning(format!(
+                    "the token account {} names {} as its owner",
+                    token_account, account.owner
+                ))
+                .field("owner"),
+        );
+    }
+    let native = NativeBalance::of(&account, lamports);
+    if let Some(native) = native.filter(|native| native.unsynced > 0) {
+        warnings.push(WarningCode::NativeUnsynced.warning(format!(
+            "{} lamports were sent to {} without a SyncNative, they can't be transferred until \
+             one is sent",
+            native.unsynced, token_account
+        )));
     }
+    let response = SplTokenBalanceResponse {
+        owner: owner.to_string(),
+        token_mint: token_mint.to_string(),
+        balance: account.amount,
+        decimals,
+        owner_matches: account.owner == *owner,
+        account_owner: account.owner.to_string(),
+        is_native: native.is_some(),
+        rent_reserve: native.map(|native| native.rent_reserve),
+        unsynced_lamports: native.map(|native| native.unsynced),
+        token_program: program,
+    };
+    Ok(Warned::new(response, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::{program_option::COption, program_pack::Pack, pubkey::Pubkey};
+    use spl_associated_token_account::get_associated_token_address;
+    use spl_token::state::{Account, AccountState};
 
+    use crate::{
+        Error,
+        account_batch::{AccountBatchConfig, AccountBatcher},
+        fixtures::{
+            Accounts, disabled, mint, native_account, packed, token_2022, token_account,
+            with_extension, wrapped_sol,
+        },
+        models::Network,
+        spl_token_utils::{
+            NativeBalance, TokenProgram, account_exists, check_source_account, fetch_token_program,
+            token_balance, validate_source_account,
+        },
+        units::RawTokenAmount,
+    };
 
-    if rpc_client.get_account(&to_ata).is_err() {
-    let create_to_ata_instruction = create_associated_token_account(
-            payer,     // fee payer
-            to,        // wallet owner
-            token_mint,
-            &spl_token::id(),
+    #[test]
+    fn test_source_account_checks() {
+        let address = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let account = Account {
+            mint,
+            owner,
+            amount: 10,
+            state: AccountState::Initialized,
+            ..Account::default()
+        };
+        let code = |account: &Account, mint: &Pubkey, signer: &Pubkey| {
+            check_source_account(&address, account, mint, signer, false)
+                .err()
+                .map(|e| e.error_code())
+        };
+
+        assert_eq!(code(&account, &mint, &owner), None);
+        let other_mint = Account {
+            mint: Pubkey::new_unique(),
+            ..account
+        };
+        assert_eq!(
+            code(&other_mint, &mint, &owner),
+            Some("SOURCE_MINT_MISMATCH")
+        );
+        assert_eq!(
+            code(&account, &mint, &Pubkey::new_unique()),
+            Some("SOURCE_OWNER_MISMATCH")
         );
-        instructions.push(create_to_ata_instruction);
-
-    }
-
-    // Create the token transfer instruction
-    let transfer_instruction = token_instruction::transfer(
-        &spl_token::id(),
-        &from_ata, // source token account
-        &to_ata,   // destination token account
-        from,      // source account owner
-        &[],       // signer pubkeys (empty for single signer)
-        amount,
-    )?;
-    instructions.push(transfer_instruction);
-    
-
-    //  memo instruction if provided
-    if let Some(memo_text) = memo {
-        let memo_instruction = Instruction {
-            program_id: spl_memo::id(),
-            accounts: Vec::new(),
-            data: memo_text.into_bytes(),
+        let frozen = Account {
+            state: AccountState::Frozen,
+            ..account
         };
-        instructions.push(memo_instruction);
+        assert_eq!(code(&frozen, &mint, &owner), Some("SOURCE_ACCOUNT_FROZEN"));
     }
 
-    let message = Message::new(&instructions, Some(payer));
-    Ok(Transaction::new_unsigned(message))
-}
+    #[test]
+    fn test_reassigned_account() {
+        let address = Pubkey::new_unique();
+        let signer = Pubkey::new_unique();
+        let new_owner = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        // The signer's ATA after `SetAuthority(AccountOwner)` handed it to someone else
+        let reassigned = Account {
+            mint,
+            owner: new_owner,
+            amount: 10,
+            state: AccountState::Initialized,
+            ..Account::default()
+        };
+        for allow_delegate in [false, true] {
+            assert!(
+                check_source_account(&address, &reassigned, &mint, &signer, allow_delegate)
+                    .is_err()
+            );
+        }
+
+        let delegated = Account {
+            delegate: COption::Some(signer),
+            delegated_amount: 10,
+            ..reassigned
+        };
+        assert!(check_source_account(&address, &delegated, &mint, &signer, false).is_err());
+        assert!(check_source_account(&address, &delegated, &mint, &signer, true).is_ok());
+        let frozen = Account {
+            state: AccountState::Frozen,
+            ..delegated
+        };
+        assert_eq!(
+            check_source_account(&address, &frozen, &mint, &signer, true)
+                .err()
+                .map(|e| e.error_code()),
+            Some("SOURCE_ACCOUNT_FROZEN")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_balance_fixtures() {
+        let owner = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let wsol = spl_token::native_mint::id();
+        let ata = |mint| get_associated_token_address(&owner, &mint);
+        // Name, accounts on chain, mint, (balance, decimals, owner_matches) or error prefix
+        type Case = (
+            &'static str,
+            Accounts,
+            Pubkey,
+            Result<(u64, u8, bool), &'static str>,
+        );
+        let cases: Vec<Case> = vec![
+            (
+                "6 decimals",
+                Accounts::default()
+                    .with(ata(usdc), packed(token_account(usdc, owner, 2_500_000)))
+                    .with(usdc, mint(6)),
+                usdc,
+                Ok((2_500_000, 6, true)),
+            ),
+            (
+                "0 decimals",
+                Accounts::default()
+                    .with(ata(usdc), packed(token_account(usdc, owner, 3)))
+                    .with(usdc, mint(0)),
+                usdc,
+                Ok((3, 0, true)),
+            ),
+            (
+                "native mint",
+                Accounts::default()
+                    .with(ata(wsol), wrapped_sol(owner, 5_000, 0))
+                    .with(wsol, mint(9)),
+                wsol,
+                Ok((5_000, 9, true)),
+            ),
+            (
+                "reassigned ATA",
+                Accounts::default()
+                    .with(
+                        ata(usdc),
+                        packed(token_account(usdc, Pubkey::new_unique(), 1)),
+                    )
+                    .with(usdc, mint(6)),
+                usdc,
+                Ok((1, 6, false)),
+            ),
+            (
+                "frozen",
+                Accounts::default()
+                    .with(
+                        ata(usdc),
+                        packed(Account {
+                            state: AccountState::Frozen,
+                            ..token_account(usdc, owner, 7)
+                        }),
+                    )
+                    .with(usdc, mint(6)),
+                usdc,
+                Ok((7, 6, true)),
+            ),
+            (
+                "no token account",
+                Accounts::default().with(usdc, mint(6)),
+                usdc,
+                Err("Token account not found"),
+            ),
+            (
+                "no mint",
+                Accounts::default().with(ata(usdc), packed(token_account(usdc, owner, 1))),
+                usdc,
+                Err("Token mint not found"),
+            ),
+            (
+                "mint where the token account should be",
+                Accounts::default()
+                    .with(ata(usdc), mint(6))
+                    .with(usdc, mint(6)),
+                usdc,
+                Err("Program error"),
+            ),
+        ];
+
+        // Every case is a chain of its own, nothing found missing carries over
+        let batcher = AccountBatcher::new(&AccountBatchConfig {
+            missing_ttl_ms: 0,
+            ..AccountBatchConfig::default()
+        });
+        for (name, accounts, token_mint, expected) in cases {
+            let rpc_client = Arc::new(accounts.async_rpc_client());
+            let result = token_balance(
+                &batcher,
+                rpc_client,
+                Network::Devnet,
+                &owner,
+                &token_mint,
+                None,
+                false,
+            )
+            .await
+            .map(|warned| {
+                let r = warned.response;
+                // Only the reassigned account warns
+                let codes: Vec<_> = warned.warnings.iter().map(|w| w.code.as_str()).collect();
+                let expected: &[&str] = if r.owner_matches {
+                    &[]
+                } else {
+                    &["OWNER_MISMATCH"]
+                };
+                assert_eq!(codes, expected);
+                (r.balance, r.decimals, r.owner_matches)
+            });
+            match (result, expected) {
+                (Ok(actual), Ok(expected)) => assert_eq!(actual, expected, "{}", name),
+                (Err(e), Err(expected)) => {
+                    assert!(e.to_string().starts_with(expected), "{}: {}", name, e)
+                }
+                (result, _) => panic!("{}: {:?}", name, result),
+            }
+        }
+
+        // A mint that can't be decoded fails the request, unless the registry knows its
+        // decimals
+        let accounts = Accounts::default()
+            .with(ata(usdc), packed(token_account(usdc, owner, 4)))
+            .with(usdc, packed(token_account(usdc, owner, 0)));
+        let rpc_client = Arc::new(accounts.async_rpc_client());
+        let balance = |registered_decimals| {
+            let (batcher, rpc_client) = (&batcher, rpc_client.clone());
+            async move {
+                token_balance(
+                    batcher,
+                    rpc_client,
+                    Network::Devnet,
+                    &owner,
+                    &usdc,
+                    registered_decimals,
+                    false,
+                )
+                .await
+            }
+        };
+        assert!(matches!(balance(None).await, Err(Error::ProgramError(_))));
+        let warned = balance(Some(6)).await.unwrap();
+        assert_eq!((warned.response.balance, warned.response.decimals), (4, 6));
+        assert_eq!(warned.warnings.len(), 1);
+        assert_eq!(warned.warnings[0].code, "MINT_FETCH_FAILED");
+        assert_eq!(warned.warnings[0].field.as_deref(), Some("token_mint"));
+    }
 
-pub fn get_token_amount_with_decimals(amount: f64, decimals: u8) -> u64 {
-    (amount * 10_f64.powi(decimals as i32)) as u64
+    #[tokio::test]
+    async fn test_account_exists() {
+        let address = Pubkey::new_unique();
+        let accounts = Accounts::default().with(address, mint(6));
+        let rpc_client = accounts.clone().async_rpc_client();
+        assert!(account_exists(&rpc_client, &address).await.unwrap());
+        assert!(
+            !account_exists(&rpc_client, &Pubkey::new_unique())
+                .await
+                .unwrap()
+        );
+        // A node that can't be read from doesn't make the account missing
+        let failing = disabled(accounts, &[RpcRequest::GetAccountInfo]).async_rpc_client();
+        assert!(matches!(
+            account_exists(&failing, &address).await,
+            Err(Error::AccountFetchFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_token_2022_balance() {
+        let owner = Pubkey::new_unique();
+        let token = Pubkey::new_unique();
+        let ata = TokenProgram::Token2022.associated_address(&owner, &token);
+        assert_ne!(ata, get_associated_token_address(&owner, &token));
+        let accounts = Accounts::default()
+            .with(ata, token_2022(packed(token_account(token, owner, 42))))
+            .with(token, token_2022(mint(6)));
+        let rpc_client = Arc::new(accounts.clone().async_rpc_client());
+        assert_eq!(
+            fetch_token_program(&rpc_client, &token).await.unwrap(),
+            TokenProgram::Token2022
+        );
+        // Whatever isn't a Token-2022 mint is taken for a classic one
+        assert_eq!(
+            fetch_token_program(&rpc_client, &Pubkey::new_unique())
+                .await
+                .unwrap(),
+            TokenProgram::SplToken
+        );
+
+        // Mints whose tokens don't move by a plain transfer_checked are refused
+        for (extension, name) in [
+            (4, "confidential_transfer_mint"),
+            (14, "transfer_hook"),
+            (16, "confidential_transfer_fee_config"),
+        ] {
+            let hooked = Pubkey::new_unique();
+            let rpc_client = Accounts::default()
+                .with(hooked, with_extension(token_2022(mint(6)), extension, 64))
+                .async_rpc_client();
+            match fetch_token_program(&rpc_client, &hooked).await {
+                Err(Error::UnsupportedMintExtension {
+                    mint: refused,
+                    extension: found,
+                }) => assert_eq!((refused, found), (hooked, name)),
+                other => panic!("{:?}", other),
+            }
+        }
+        // Others, like a transfer fee, are read past
+        let fee = Pubkey::new_unique();
+        let rpc_client = Accounts::default()
+            .with(fee, with_extension(token_2022(mint(6)), 1, 108))
+            .async_rpc_client();
+        assert_eq!(
+            fetch_token_program(&rpc_client, &fee).await.unwrap(),
+            TokenProgram::Token2022
+        );
+
+        let balance = |accounts: Accounts| async {
+            token_balance(
+                &AccountBatcher::new(&AccountBatchConfig {
+                    missing_ttl_ms: 0,
+                    ..AccountBatchConfig::default()
+                }),
+                Arc::new(accounts.async_rpc_client()),
+                Network::Devnet,
+                &owner,
+                &token,
+                None,
+                false,
+            )
+            .await
+        };
+        let response = balance(accounts.clone()).await.unwrap().response;
+        assert_eq!((response.balance, response.decimals), (42, 6));
+        assert_eq!(response.token_program, TokenProgram::Token2022);
+
+        // Without the mint the account that exists is read, and its mint is still missing
+        let e = balance(
+            Accounts::default().with(ata, token_2022(packed(token_account(token, owner, 1)))),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(e, Error::TokenMintNotFound), "{}", e);
+        // Only the Token-2022 account counts for a Token-2022 mint
+        let classic = Accounts::default()
+            .with(
+                get_associated_token_address(&owner, &token),
+                packed(token_account(token, owner, 1)),
+            )
+            .with(token, token_2022(mint(6)));
+        assert!(matches!(
+            balance(classic).await,
+            Err(Error::TokenAccountNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_native_reserve() {
+        let owner = Pubkey::new_unique();
+        let wsol = spl_token::native_mint::id();
+        let address = get_associated_token_address(&owner, &wsol);
+        let reserve = packed(native_account(owner, 0)).lamports;
+
+        // 3_000 lamports wrapped and synced, 500 more sent since
+        let account = wrapped_sol(owner, 3_000, 500);
+        let native =
+            NativeBalance::of(&Account::unpack(&account.data).unwrap(), account.lamports).unwrap();
+        assert_eq!(
+            native,
+            NativeBalance {
+                rent_reserve: reserve,
+                spendable: 3_000,
+                unsynced: 500,
+            }
+        );
+        assert!(NativeBalance::of(&token_account(wsol, owner, 3_000), reserve).is_none());
+
+        let accounts = Accounts::default()
+            .with(address, account)
+            .with(wsol, mint(9));
+        let warned = token_balance(
+            &AccountBatcher::new(&AccountBatchConfig::default()),
+            Arc::new(accounts.clone().async_rpc_client()),
+            Network::Devnet,
+            &owner,
+            &wsol,
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+        let response = warned.response;
+        assert_eq!(response.balance, 3_000);
+        assert!(response.is_native);
+        assert_eq!(response.rent_reserve, Some(reserve));
+        assert_eq!(response.unsynced_lamports, Some(500));
+        assert_eq!(warned.warnings.len(), 1);
+        assert_eq!(warned.warnings[0].code, "NATIVE_UNSYNCED");
+
+        // Up to the synced amount, not into the reserve or the unsynced lamports
+        let rpc_client = accounts.async_rpc_client();
+        let send = |amount| {
+            validate_source_account(
+                &rpc_client,
+                &address,
+                &wsol,
+                &owner,
+                false,
+                RawTokenAmount::new(amount),
+            )
+        };
+        send(3_000).await.unwrap();
+        let error = send(3_001).await.unwrap_err();
+        assert_eq!(error.error_code(), "WRAPPED_SOL_RESERVE");
+        assert!(error.to_string().contains("SyncNative"), "{}", error);
+        assert!(send(3_000 + 500 + reserve).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_source_account_fixtures() {
+        let signer = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let address = get_associated_token_address(&signer, &usdc);
+        let held = token_account(usdc, signer, 10);
+        let delegated = Account {
+            delegate: COption::Some(signer),
+            delegated_amount: 10,
+            ..token_account(usdc, Pubkey::new_unique(), 10)
+        };
+        let mut system_owned = packed(held);
+        system_owned.owner = solana_sdk::system_program::id();
+
+        let code = async |accounts: Accounts, token_mint: &Pubkey, allow_delegate: bool| {
+            validate_source_account(
+                &accounts.async_rpc_client(),
+                &address,
+                token_mint,
+                &signer,
+                allow_delegate,
+                RawTokenAmount::new(1),
+            )
+            .await
+            .err()
+            .map(|e| e.error_code())
+        };
+        let at_address = |account| Accounts::default().with(address, account);
+        let cases = [
+            ("held", at_address(packed(held)), usdc, false, None),
+            (
+                "missing",
+                Accounts::default(),
+                usdc,
+                false,
+                Some("SOURCE_ACCOUNT_NOT_FOUND"),
+            ),
+            (
+                "not a token program account",
+                at_address(system_owned),
+                usdc,
+                false,
+                Some("SOURCE_ACCOUNT_NOT_FOUND"),
+            ),
+            (
+                "other mint",
+                at_address(packed(held)),
+                Pubkey::new_unique(),
+                false,
+                Some("SOURCE_MINT_MISMATCH"),
+            ),
+            (
+                "frozen",
+                at_address(packed(Account {
+                    state: AccountState::Frozen,
+                    ..held
+                })),
+                usdc,
+                false,
+                Some("SOURCE_ACCOUNT_FROZEN"),
+            ),
+            (
+                "delegated without allow_delegate",
+                at_address(packed(delegated)),
+                usdc,
+                false,
+                Some("SOURCE_OWNER_MISMATCH"),
+            ),
+            ("delegated", at_address(packed(delegated)), usdc, true, None),
+            (
+                "native",
+                at_address(wrapped_sol(signer, 1, 0)),
+                spl_token::native_mint::id(),
+                false,
+                None,
+            ),
+            (
+                "token-2022",
+                at_address(token_2022(packed(held))),
+                usdc,
+                false,
+                None,
+            ),
+            ("garbage", at_address(mint(6)), usdc, false, Some("OTHER")),
+        ];
+        for (name, accounts, token_mint, allow_delegate, expected) in cases {
+            assert_eq!(
+                code(accounts, &token_mint, allow_delegate).await,
+                expected,
+                "{}",
+                name
+            );
+        }
+        assert!(matches!(
+            validate_source_account(
+                &Accounts::default()
+                    .with(address, mint(6))
+                    .async_rpc_client(),
+                &address,
+                &usdc,
+                &signer,
+                false,
+                RawTokenAmount::new(1),
+            )
+            .await,
+            Err(Error::ProgramError(_))
+        ));
+    }
 }
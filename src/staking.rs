@@ -1,6 +1,6 @@
-use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
     pubkey::Pubkey,
+    rent::Rent,
     stake::{
         instruction as stake_instruction,
         state::{Authorized, Lockup, StakeStateV2},
@@ -9,7 +9,24 @@ use solana_sdk::{
     transaction::Transaction,
 };
 
-use crate::error::Error;
+use crate::{error::Error, units::Lamports};
+
+/// A stake account funded with `amount` on top of its rent, at the address of `seed`, delegated
+/// to `vote_account`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewStake {
+    pub amount: Lamports,
+    pub seed: String,
+    pub vote_account: Pubkey,
+}
+
+/// `amount` taken out of `stake_account` to `destination`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StakeWithdrawal {
+    pub stake_account: Pubkey,
+    pub destination: Pubkey,
+    pub amount: Lamports,
+}
 
 pub fn create_stake_account_transaction(
     stake_amount: u64,
@@ -20,10 +37,9 @@ pub fn create_stake_account_transaction(
     let stake_account = Pubkey::create_with_seed(payer, seed, &solana_sdk::stake::program::id())
         .map_err(|e| Error::InvalidStakeAccountSeed(e.to_string()))?;
 
+    // Every cluster runs the default rent, no node has to be asked
     let space = std::mem::size_of::<StakeStateV2>() as u64;
-    let rent = RpcClient::new("https://api.testnet.solana.com")
-        .get_minimum_balance_for_rent_exemption(space as usize)
-        .map_err(|e| Error::StakeAccountCreationFailed(e.to_string()))?;
+    let rent = Rent::default().minimum_balance(space as usize);
 
     let create_account_ins = system_instruction::create_account_with_seed(
         payer,
@@ -0,0 +1,255 @@
+use std::{net::ToSocketAddrs, time::Duration};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use tokio::task::JoinSet;
+
+use crate::{
+    clock::{exceeds, measure_skew},
+    config::{Config, validate_rpc_url},
+    models::Network,
+    serialization::validate_environment,
+    token_registry::{TokenRegistry, chain_mismatch, fetch_mint_decimals},
+};
+
+/// Hard issues abort startup, soft ones are reported and ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Hard,
+    Soft,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StartupIssue {
+    pub severity: Severity,
+    pub component: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StartupReport {
+    pub issues: Vec<StartupIssue>,
+}
+
+impl StartupReport {
+    pub fn push(&mut self, severity: Severity, component: impl Into<String>, message: String) {
+        self.issues.push(StartupIssue {
+            severity,
+            component: component.into(),
+            message,
+        });
+    }
+
+    pub fn has_hard_failures(&self) -> bool {
+        self.issues.iter().any(|i| i.severity == Severity::Hard)
+    }
+
+    /// Log every issue, hard ones as errors and soft ones as warnings.
+    pub fn log(&self) {
+        for issue in &self.issues {
+            let (component, message) = (issue.component.as_str(), issue.message.as_str());
+            match issue.severity {
+                Severity::Hard => {
+                    tracing::error!(component, issue = message, "startup check failed")
+                }
+                Severity::Soft => {
+                    tracing::warn!(component, issue = message, "startup check failed")
+                }
+            }
+        }
+    }
+}
+
+/// Validate everything the listener depends on and collect every problem found.
+pub async fn run_startup_checks(config: &Config) -> StartupReport {
+    let mut report = StartupReport::default();
+
+    if let Err(e) = config.bind.to_socket_addrs() {
+        report.push(
+            Severity::Hard,
+            "bind",
+            format!("invalid bind address {:?}: {}", config.bind, e),
+        );
+    }
+
+    if let Some(Err(e)) = config.environment.as_deref().map(validate_environment) {
+        report.push(Severity::Hard, "environment", e);
+    }
+
+    if let Err(e) = config.writable_policy.validate() {
+        report.push(Severity::Hard, "writable_policy", e);
+    }
+
+    if config.require_api_key && config.api_keys.is_empty() && config.access_keys.is_empty() {
+        report.push(
+            Severity::Hard,
+            "require_api_key",
+            "no API key is configured, every request would be refused".to_string(),
+        );
+    }
+
+    if let Some(Err(e)) = config.rate_limits.as_ref().map(|limits| limits.validate()) {
+        report.push(Severity::Hard, "rate_limits", e);
+    }
+
+    let mut valid_urls = Vec::new();
+    for net in Network::ALL {
+        let url = config.cluster_url(net);
+        match validate_rpc_url(url) {
+            Ok(()) => valid_urls.push((net, url.to_string())),
+            Err(e) => report.push(Severity::Hard, format!("rpc_urls.{}", net), e),
+        }
+    }
+
+    let tokens = match TokenRegistry::new(&config.tokens) {
+        Ok(registry) => registry.list(),
+        Err(e) => {
+            report.push(Severity::Hard, "tokens", e);
+            Vec::new()
+        }
+    };
+
+    if config.startup_ping {
+        let timeout = Duration::from_millis(config.startup_ping_timeout_ms);
+
+        // A registry entry that doesn't match its mint would sign transfers with wrong amounts
+        let mut mints = JoinSet::new();
+        for entry in tokens {
+            let Some((_, url)) = valid_urls.iter().find(|(net, _)| *net == entry.net) else {
+                continue;
+            };
+            let rpc_client = RpcClient::new_with_timeout(url.clone(), timeout);
+            mints.spawn(async move {
+                let mint = entry.mint.parse().unwrap_or_default();
+                let decimals = fetch_mint_decimals(&rpc_client, &mint).await;
+                (entry, decimals)
+            });
+        }
+        while let Some(check) = mints.join_next().await {
+            let Ok((entry, decimals)) = check else {
+                continue;
+            };
+            let component = format!("tokens.{}", entry.symbol);
+            match decimals {
+                Ok(decimals) => {
+                    if let Some(e) = chain_mismatch(&entry, decimals) {
+                        report.push(Severity::Hard, component, e);
+                    }
+                }
+                Err(e) => report.push(
+                    Severity::Soft,
+                    component,
+                    format!("couldn't check mint {}: {}", entry.mint, e),
+                ),
+            }
+        }
+
+        if let Some(check) = config.clock_check {
+            let rpc_client =
+                RpcClient::new_with_timeout(config.cluster_url(check.net).to_string(), timeout);
+            match measure_skew(&rpc_client).await {
+                Ok(skew_ms) if exceeds(&check, skew_ms) => report.push(
+                    Severity::Soft,
+                    "clock_check",
+                    format!(
+                        "the system clock is {} ms off from the {} cluster's, more than the {} ms allowed",
+                        skew_ms, check.net, check.threshold_ms
+                    ),
+                ),
+                Ok(_) => {}
+                Err(e) => report.push(
+                    Severity::Soft,
+                    "clock_check",
+                    format!("couldn't compare the clock with {}: {}", check.net, e),
+                ),
+            }
+        }
+
+        let mut pings = JoinSet::new();
+        for (net, url) in valid_urls {
+            // Only expected to be up when someone pointed us at it
+            if net.is_local() && !config.rpc_urls.contains_key(&net) {
+                continue;
+            }
+            pings.spawn(async move {
+                let rpc_client = RpcClient::new_with_timeout(url, timeout);
+                (net, rpc_client.get_version().await)
+            });
+        }
+        while let Some(ping) = pings.join_next().await {
+            if let Ok((net, Err(e))) = ping {
+                report.push(
+                    Severity::Soft,
+                    format!("rpc_urls.{}", net),
+                    format!("RPC endpoint unreachable: {}", e),
+                );
+            }
+        }
+    }
+
+    report.issues.sort_by(|a, b| a.component.cmp(&b.component));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::config::Config;
+    use crate::models::Network;
+    use crate::startup::{Severity, run_startup_checks};
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("solana-tss-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_corrupt_config_file() {
+        let path = write_config("corrupt", "{ \"rpc_urls\": ");
+        assert!(Config::load(Some(&path)).is_err());
+        let path = write_config("unknown-field", "{ \"rpc_url\": {} }");
+        assert!(Config::load(Some(&path)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_broken_urls_are_all_reported() {
+        let path = write_config(
+            "broken-urls",
+            r#"{
+                "bind": "not an address",
+                "startup_ping": false,
+                "rpc_urls": {
+                    "mainnet": "ftp://example.com",
+                    "devnet": "no scheme at all",
+                    "testnet": "http://127.0.0.1:8899"
+                }
+            }"#,
+        );
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(
+            config.cluster_url(Network::Testnet),
+            "http://127.0.0.1:8899"
+        );
+
+        let report = run_startup_checks(&config).await;
+        assert!(report.has_hard_failures());
+        let components: Vec<_> = report.issues.iter().map(|i| i.component.as_str()).collect();
+        assert_eq!(components, ["bind", "rpc_urls.devnet", "rpc_urls.mainnet"]);
+        assert!(report.issues.iter().all(|i| i.severity == Severity::Hard));
+    }
+
+    #[tokio::test]
+    async fn test_default_config_is_valid() {
+        let config = Config {
+            startup_ping: false,
+            ..Config::default()
+        };
+        let report = run_startup_checks(&config).await;
+        assert!(report.issues.is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "chaos")]
+use crate::chaos::{Chaos, check_config};
+use crate::{
+    Error,
+    account_batch::AccountBatcher,
+    audit::AuditLog,
+    clock::ClockSkew,
+    config::{Config, ConfigSource},
+    faucet::InternalFaucet,
+    journal::BroadcastJournal,
+    models::{FlushCachesResponse, ReloadConfigResponse},
+    rate_limit::RateLimiter,
+    rpc_clients::RpcClients,
+    rpc_health::RpcHealth,
+    scheduler::Scheduler,
+    secret_states::SecretStates,
+    spend_limit::SpendGuard,
+    startup::{Severity, run_startup_checks},
+    tenant::Tenants,
+    token_registry::TokenRegistry,
+    usage::UsageStats,
+};
+
+// The config file can be reloaded without a restart, through `/api/admin/reload_config` or a
+// SIGHUP. The new config is built and checked the way startup checks it before anything
+// changes, a config that fails keeps the running one. The config and what is built from it
+// make up one `Snapshot`, swapped as a whole. A handler loads it once, so it never sees the
+// tokens of one config with the limits of another, and a request that started before the
+// swap keeps the snapshot it took.
+
+/// What a reload replaces.
+pub struct Snapshot {
+    pub config: Config,
+    pub tokens: TokenRegistry,
+    pub tenants: Tenants,
+    pub spend_guard: Option<SpendGuard>,
+}
+
+impl Snapshot {
+    fn new(config: Config) -> Result<Self, String> {
+        let tokens = TokenRegistry::new(&config.tokens)?
+            .retention(Duration::from_secs(config.deleted_token_retention_secs));
+        let tenants = Tenants::new(&config.api_keys)?.access_keys(&config.access_keys);
+        let spend_guard = config
+            .spend_limits
+            .as_ref()
+            .map(SpendGuard::new)
+            .transpose()?;
+        Ok(Self {
+            config,
+            tokens,
+            tenants,
+            spend_guard,
+        })
+    }
+}
+
+/// Shared by every handler through poem's `Data` extractor.
+pub struct AppState {
+    snapshot: RwLock<Arc<Snapshot>>,
+    /// Read again by a reload
+    pub config_source: ConfigSource,
+    reloading: tokio::sync::Mutex<()>,
+    pub faucet: Option<InternalFaucet>,
+    pub audit: Arc<AuditLog>,
+    pub journal: Option<Arc<BroadcastJournal>>,
+    /// Only with a journal, that's where scheduled transactions wait
+    pub scheduler: Option<Arc<Scheduler>>,
+    /// Account reads of concurrent requests share `getMultipleAccounts` calls through it
+    pub accounts: AccountBatcher,
+    /// Every RPC call of a handler goes through one of these
+    pub rpc_clients: RpcClients,
+    /// Latest skew of the system clock from the cluster's, see `clock_check`
+    pub clock: ClockSkew,
+    /// Latest answers of the RPC nodes to `/api/health?rpc=true`
+    pub rpc_health: RpcHealth,
+    pub started: Instant,
+    /// Optional fields sent by requests, see `usage_stats`
+    pub usage: UsageStats,
+    /// Step one secret states waiting for their step two, see `server_side_secret_state`
+    pub secret_states: SecretStates,
+    /// Token buckets of the clients, see `rate_limits`
+    pub rate_limiter: RateLimiter,
+    /// Faults injected into every RPC call, set through `/api/chaos`
+    #[cfg(feature = "chaos")]
+    pub chaos: Arc<Chaos>,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Result<Self, String> {
+        #[cfg(feature = "chaos")]
+        check_config(&config.rpc_urls)?;
+        let faucet = config
+            .faucet
+            .as_ref()
+            .map(InternalFaucet::load)
+            .transpose()?;
+        let journal = config
+            .broadcast_journal
+            .as_deref()
+            .map(BroadcastJournal::load)
+            .transpose()?
+            .map(Arc::new);
+        let audit = Arc::new(
+            AuditLog::new(config.state_limits.audit_log)
+                .full_messages(config.audit_full_messages)
+                .ceremony_limits(config.state_limits.ceremonies),
+        );
+        let accounts = AccountBatcher::new(&config.account_batching);
+        let rpc_clients = RpcClients::new(config.state_limits.rpc_clients);
+        let secret_states = SecretStates::new(config.state_limits.secret_states);
+        let rate_limiter = RateLimiter::new(config.state_limits.rate_limit_buckets);
+        let scheduler = journal.as_ref().map(|journal| {
+            Arc::new(
+                Scheduler::new(journal.clone(), audit.clone())
+                    .confirmation(config.confirmation)
+                    .idempotency_keys(config.state_limits.idempotency_keys),
+            )
+        });
+        Ok(Self {
+            snapshot: RwLock::new(Arc::new(Snapshot::new(config)?)),
+            config_source: ConfigSource::default(),
+            reloading: tokio::sync::Mutex::new(()),
+            faucet,
+            audit,
+            journal,
+            scheduler,
+            accounts,
+            rpc_clients,
+            clock: ClockSkew::default(),
+            rpc_health: RpcHealth::default(),
+            started: Instant::now(),
+            usage: UsageStats::default(),
+            secret_states,
+            rate_limiter,
+            #[cfg(feature = "chaos")]
+            chaos: Arc::default(),
+        })
+    }
+
+    /// Reload from `source` instead of the defaults.
+    pub fn config_source(mut self, source: ConfigSource) -> Self {
+        self.config_source = source;
+        self
+    }
+
+    /// The running config and what is built from it, a handler takes it once.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// Read the config again and switch to it once it passes the startup checks. Tokens, API
+    /// keys and spend limits are rebuilt from it; changes made through `/api/tokens` are
+    /// dropped and what was spent so far keeps counting.
+    pub async fn reload(&self) -> Result<ReloadConfigResponse, Error> {
+        let _reloading = self.reloading.lock().await;
+        let new = self
+            .config_source
+            .load()
+            .map_err(|e| Error::ConfigReloadFailed(vec![e]))?;
+        #[cfg(feature = "chaos")]
+        check_config(&new.rpc_urls).map_err(|e| Error::ConfigReloadFailed(vec![e]))?;
+
+        let report = run_startup_checks(&new).await;
+        let (failures, issues): (Vec<_>, Vec<_>) = report
+            .issues
+            .into_iter()
+            .partition(|issue| issue.severity == Severity::Hard);
+        if !failures.is_empty() {
+            let failures = failures
+                .into_iter()
+                .map(|issue| format!("{}: {}", issue.component, issue.message))
+                .collect();
+            return Err(Error::ConfigReloadFailed(failures));
+        }
+
+        let current = self.snapshot();
+        let restart_required = current.config.restart_required(&new);
+        let new = current.config.reloaded(new);
+        let mut snapshot = Snapshot::new(new).map_err(|e| Error::ConfigReloadFailed(vec![e]))?;
+        snapshot.spend_guard = match (snapshot.spend_guard, &current.spend_guard) {
+            (Some(guard), Some(previous)) => Some(guard.spent_with(previous)),
+            (guard, _) => guard,
+        };
+
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+        tracing::info!(?restart_required, "reloaded the config");
+        Ok(ReloadConfigResponse {
+            restart_required: restart_required.into_iter().map(str::to_string).collect(),
+            issues,
+        })
+    }
+
+    /// Empty the caches of chain data, the next request reads it from the node again.
+    pub fn flush_caches(&self) -> FlushCachesResponse {
+        // Blockhashes and mints are read fresh by every request, only the accounts balance
+        // reads found missing are kept
+        self.accounts.flush_missing();
+        FlushCachesResponse {
+            flushed: vec!["missing_accounts".to_string()],
+        }
+    }
+}
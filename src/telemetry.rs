@@ -0,0 +1,40 @@
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use tracing::Subscriber;
+use tracing_subscriber::{Layer, registry::LookupSpan};
+
+// Spans come from the `tracing` macros around the TSS stages and RPC calls, the layer made
+// here turns them into OpenTelemetry spans. The request span made by poem's
+// `OpenTelemetryTracing` middleware is their parent, and it continues the caller's trace when
+// the request carries a W3C `traceparent` header.
+
+pub const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Export spans over OTLP/HTTP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set, the other standard
+/// `OTEL_EXPORTER_OTLP_*` variables apply as well. Without it nothing is exported, incoming
+/// trace context is still honored so outgoing client calls keep propagating it.
+pub fn init() -> Result<Option<SdkTracerProvider>, String> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        return Ok(None);
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| format!("failed creating the OTLP exporter: {}", e))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+/// The layer exporting spans through `provider`, for the subscriber installed at startup.
+pub fn layer<S>(provider: &SdkTracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+}
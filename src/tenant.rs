@@ -0,0 +1,224 @@
+use std::{collections::HashMap, path::Path};
+
+use poem::http::{HeaderMap, header::AUTHORIZATION};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// Tenants share one deployment without seeing each other's transactions. Everything the
+// service keeps about a transaction (audit entries, journal entries, scheduled broadcasts and
+// their idempotency keys) carries the tenant of the API key that made it, and is only found
+// again by keys of the same tenant. Requests with no key or one that isn't configured act for
+// the default tenant, which also owns whatever was stored before tenants existed.
+//
+// With `require_api_key` a request must also send a key that is known: one of `api_keys`, or
+// one given on the command line, in the environment or a key file. Those act for the default
+// tenant, and like `api_keys` only their hashes are kept.
+
+/// Header a key can be sent in instead of `Authorization: Bearer`.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// An API key and the tenant it acts for.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// Hex SHA-256 of the key, so the config never holds the key itself
+    pub sha256: String,
+    pub tenant: String,
+    /// May look at every tenant's transactions
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// Who a request acts for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Caller {
+    /// `None` is the default tenant
+    pub tenant: Option<String>,
+    pub admin: bool,
+}
+
+impl Caller {
+    pub fn tenant(tenant: &str) -> Self {
+        Self {
+            tenant: Some(tenant.to_string()),
+            admin: false,
+        }
+    }
+
+    /// Whether something stored for `tenant` is visible to this caller.
+    pub fn can_access(&self, tenant: Option<&str>) -> bool {
+        self.admin || self.tenant.as_deref() == tenant
+    }
+}
+
+/// Tenants by API key hash, from the `api_keys` config section.
+#[derive(Debug, Default)]
+pub struct Tenants {
+    keys: HashMap<[u8; 32], Caller>,
+}
+
+impl Tenants {
+    pub fn new(config: &[ApiKeyConfig]) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for key in config {
+            let hash = hex_hash(&key.sha256).ok_or_else(|| {
+                format!(
+                    "api_keys: {:?} of tenant {} is not a hex SHA-256",
+                    key.sha256, key.tenant
+                )
+            })?;
+            if key.tenant.trim().is_empty() {
+                return Err("api_keys: tenant can't be empty".to_string());
+            }
+            let caller = Caller {
+                tenant: Some(key.tenant.trim().to_string()),
+                admin: key.admin,
+            };
+            if keys.insert(hash, caller).is_some() {
+                return Err(format!("api_keys: {} is listed twice", key.sha256));
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    /// Also know `hashes`, keys acting for the default tenant. A hash `api_keys` lists keeps
+    /// its tenant.
+    pub fn access_keys(mut self, hashes: &[[u8; 32]]) -> Self {
+        for hash in hashes {
+            self.keys.entry(*hash).or_default();
+        }
+        self
+    }
+
+    pub fn caller(&self, headers: &HeaderMap) -> Caller {
+        self.known(headers).unwrap_or_default()
+    }
+
+    /// The caller of the request's key, `None` without a key or with one that isn't known.
+    pub fn known(&self, headers: &HeaderMap) -> Option<Caller> {
+        let hash = key_hash(request_key(headers)?);
+        self.keys.get(&hash).cloned()
+    }
+}
+
+pub fn key_hash(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// Hashes of the keys in `path`, one per line. Blank lines and lines starting with `#` are
+/// skipped.
+pub fn read_key_file(path: &Path) -> Result<Vec<[u8; 32]>, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed reading {}: {}", path.display(), e))?;
+    Ok(raw
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(key_hash)
+        .collect())
+}
+
+fn hex_hash(s: &str) -> Option<[u8; 32]> {
+    let s = s.trim();
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// The request's `Authorization: Bearer` key, or else its `X-Api-Key`, if it has a non-empty
+/// one.
+pub fn request_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let key = bearer
+        .or_else(|| headers.get(API_KEY_HEADER)?.to_str().ok())?
+        .trim();
+    (!key.is_empty()).then_some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::HeaderMap;
+    use sha2::{Digest, Sha256};
+
+    use crate::tenant::{ApiKeyConfig, Caller, Tenants, key_hash, read_key_file};
+
+    fn key(key: &str, tenant: &str, admin: bool) -> ApiKeyConfig {
+        let hash = Sha256::digest(key.as_bytes());
+        ApiKeyConfig {
+            sha256: hash.iter().map(|b| format!("{:02x}", b)).collect(),
+            tenant: tenant.to_string(),
+            admin,
+        }
+    }
+
+    fn headers(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", format!("Bearer {}", key).parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_callers() {
+        let tenants = Tenants::new(&[
+            key("key-a", "team-a", false),
+            key("key-b", "team-b", false),
+            key("key-ops", "ops", true),
+        ])
+        .unwrap();
+
+        let a = tenants.caller(&headers("key-a"));
+        assert_eq!(a, Caller::tenant("team-a"));
+        assert!(a.can_access(Some("team-a")));
+        assert!(!a.can_access(Some("team-b")) && !a.can_access(None));
+
+        let ops = tenants.caller(&headers("key-ops"));
+        assert!(ops.can_access(Some("team-b")) && ops.can_access(None));
+
+        // Unknown and missing keys act for the default tenant
+        for caller in [
+            tenants.caller(&headers("key-c")),
+            tenants.caller(&HeaderMap::new()),
+        ] {
+            assert_eq!(caller, Caller::default());
+            assert!(caller.can_access(None) && !caller.can_access(Some("team-a")));
+        }
+
+        let mut bad = key("key-a", "team-a", false);
+        bad.sha256.pop();
+        assert!(Tenants::new(&[bad]).is_err());
+        assert!(Tenants::new(&[key("key-a", "team-a", false), key("key-a", "x", false)]).is_err());
+    }
+
+    #[test]
+    fn test_access_keys() {
+        let tenants = Tenants::new(&[key("key-a", "team-a", false)])
+            .unwrap()
+            .access_keys(&[key_hash("key-a"), key_hash("plain")]);
+        assert_eq!(
+            tenants.known(&headers("key-a")),
+            Some(Caller::tenant("team-a"))
+        );
+        assert_eq!(tenants.known(&headers("plain")), Some(Caller::default()));
+        assert_eq!(tenants.known(&headers("other")), None);
+        assert_eq!(tenants.known(&HeaderMap::new()), None);
+
+        let mut x_api_key = HeaderMap::new();
+        x_api_key.insert("x-api-key", "plain".parse().unwrap());
+        assert_eq!(tenants.known(&x_api_key), Some(Caller::default()));
+
+        let path = std::env::temp_dir().join(format!("api-keys-{}", std::process::id()));
+        std::fs::write(&path, "# rotated monthly\nnew-key\n\n  old-key  \n").unwrap();
+        let hashes = read_key_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(hashes, [key_hash("new-key"), key_hash("old-key")]);
+        assert!(read_key_file(&path).is_err());
+    }
+}
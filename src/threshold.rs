@@ -0,0 +1,569 @@
+#![allow(non_snake_case)]
+
+use curv::BigInt;
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use multi_party_eddsa::protocols::musig2::PublicKeyAgg;
+use multi_party_eddsa::protocols::{ExpandedKeyPair, ExpandedPrivateKey};
+use sha2::{Digest, Sha256, Sha512};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+
+use crate::Error;
+use crate::serialization::{
+    Error as DeserializationError, KeygenMessage1, Serialize, ThresholdKeyShare,
+};
+
+// A threshold key is generated by all of its `n` parties together and signed with by any
+// `threshold` of them. Each party deals the others shares of a random polynomial of degree
+// `threshold - 1` (Feldman VSS): step one publishes commitments to its coefficients, a proof it
+// knows the first one and every other party's share encrypted to that party's key. Step two
+// checks what each party sent against its commitments and adds the shares up. The key is the
+// sum of the first commitments, nobody ever holds its secret.
+//
+// The polynomial is derived from the party's key and the keygen's parameters, so step two
+// computes it again instead of carrying secret state. Signing reuses the MuSig2 rounds: a
+// party's share stands in for its key and its Lagrange coefficient among the signing parties
+// for the MuSig2 coefficient, the partial signatures then add up to one of the threshold key.
+//
+// A party that was sent other messages than the rest would end up with a share of another key,
+// or of one only some parties hold shares of. So step two only confirms what the party saw: it
+// signs a hash of every first message and the key they add up to with its own key. Step three
+// takes the confirmations of all parties and hands out the share once every one of them saw the
+// same keygen, there is nothing to sign with before.
+
+/// Fewest parties a threshold key can require.
+pub const MIN_THRESHOLD: usize = 2;
+
+/// Most parties of a threshold keygen, their positions are stored in a byte.
+pub const MAX_THRESHOLD_PARTIES: usize = u8::MAX as usize;
+
+/// Fail unless `threshold` of `parties` is a threshold a key can be generated for.
+pub fn check_threshold(threshold: usize, parties: usize) -> Result<(), Error> {
+    if parties > MAX_THRESHOLD_PARTIES {
+        return Err(Error::InvalidThreshold(format!(
+            "at most {} parties can share a key, got {}",
+            MAX_THRESHOLD_PARTIES, parties
+        )));
+    }
+    if threshold < MIN_THRESHOLD || threshold > parties {
+        return Err(Error::InvalidThreshold(format!(
+            "{} of {} keys, it has to be at least {} and at most the number of keys",
+            threshold, parties, MIN_THRESHOLD
+        )));
+    }
+    Ok(())
+}
+
+fn hash_to_scalar(parts: &[&[u8]]) -> Scalar<Ed25519> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    Scalar::from_bigint(&BigInt::from_bytes(&hasher.finalize()))
+}
+
+/// What a keygen is bound to: its parties in order and the threshold.
+fn keygen_context(keys: &[Pubkey], threshold: usize) -> Vec<u8> {
+    let mut context = b"solana-tss threshold keygen".to_vec();
+    context.push(threshold as u8);
+    for key in keys {
+        context.extend(key.to_bytes());
+    }
+    context
+}
+
+/// The point a party's shares are evaluated at, its position counted from one.
+fn x_of(index: usize) -> Scalar<Ed25519> {
+    Scalar::from_bigint(&BigInt::from(index as u64 + 1))
+}
+
+fn points(keys: &[Pubkey]) -> Result<Vec<Point<Ed25519>>, Error> {
+    keys.iter()
+        .map(|key| {
+            Point::from_bytes(&key.to_bytes()).map_err(|e| Error::DeserializationFailed {
+                error: DeserializationError::InvalidPoint(e),
+                field_name: "keys",
+            })
+        })
+        .collect()
+}
+
+fn position(keypair: &Keypair, keys: &[Pubkey]) -> Result<usize, Error> {
+    keys.iter()
+        .position(|key| *key == keypair.pubkey())
+        .ok_or_else(|| Error::SignerNotInKeySet {
+            signer: keypair.pubkey(),
+            keys: keys.to_vec(),
+        })
+}
+
+fn polynomial(
+    expanded: &ExpandedKeyPair,
+    context: &[u8],
+    threshold: usize,
+) -> Vec<Scalar<Ed25519>> {
+    let private_key = expanded.expanded_private_key.private_key.to_bytes();
+    let prefix = expanded.expanded_private_key.prefix.to_bytes();
+    (0..threshold)
+        .map(|k| hash_to_scalar(&[b"coefficient", context, &*private_key, &*prefix, &[k as u8]]))
+        .collect()
+}
+
+fn evaluate(coefficients: &[Scalar<Ed25519>], index: usize) -> Scalar<Ed25519> {
+    let x = x_of(index);
+    coefficients
+        .iter()
+        .rev()
+        .fold(Scalar::zero(), |acc, coefficient| acc * &x + coefficient)
+}
+
+/// The share of `index` in the exponent, what the commitments promise it to be.
+fn evaluate_commitments(commitments: &[Point<Ed25519>], index: usize) -> Point<Ed25519> {
+    let x = x_of(index);
+    commitments
+        .iter()
+        .rev()
+        .fold(Point::zero(), |acc, commitment| acc * &x + commitment)
+}
+
+/// What the share dealt from `from` to `to` is encrypted with, derived from the Diffie-Hellman
+/// point of their keys which either of them can compute.
+fn share_pad(
+    expanded: &ExpandedKeyPair,
+    other: &Point<Ed25519>,
+    from: usize,
+    to: usize,
+    context: &[u8],
+) -> Scalar<Ed25519> {
+    let shared = other * &expanded.expanded_private_key.private_key;
+    hash_to_scalar(&[
+        b"share",
+        context,
+        &*shared.to_bytes(true),
+        &[from as u8, to as u8],
+    ])
+}
+
+fn proof_challenge(
+    index: usize,
+    commitment: &Point<Ed25519>,
+    R: &Point<Ed25519>,
+    context: &[u8],
+) -> Scalar<Ed25519> {
+    hash_to_scalar(&[
+        b"proof",
+        context,
+        &[index as u8],
+        &*commitment.to_bytes(true),
+        &*R.to_bytes(true),
+    ])
+}
+
+/// The first keygen message of `keypair`, one of `keys`, for a key any `threshold` of them can
+/// sign with. The same parameters always give the same message.
+#[tracing::instrument(name = "threshold.keygen_step_one", skip_all)]
+pub fn keygen_step_one(
+    keypair: &Keypair,
+    keys: &[Pubkey],
+    threshold: usize,
+) -> Result<KeygenMessage1, Error> {
+    check_threshold(threshold, keys.len())?;
+    let index = position(keypair, keys)?;
+    let points = points(keys)?;
+    let expanded = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let context = keygen_context(keys, threshold);
+
+    let coefficients = polynomial(&expanded, &context, threshold);
+    let commitments: Vec<_> = coefficients
+        .iter()
+        .map(|coefficient| Point::generator() * coefficient)
+        .collect();
+    // Schnorr proof of the first coefficient, so no party can pick its commitment to cancel out
+    // the others'
+    let nonce = hash_to_scalar(&[b"proof nonce", &context, &*coefficients[0].to_bytes()]);
+    let R = Point::generator() * &nonce;
+    let challenge = proof_challenge(index, &commitments[0], &R, &context);
+    let shares = points
+        .iter()
+        .enumerate()
+        .map(|(to, point)| match to == index {
+            true => Scalar::zero(),
+            false => evaluate(&coefficients, to) + share_pad(&expanded, point, index, to, &context),
+        })
+        .collect();
+    Ok(KeygenMessage1 {
+        sender: keypair.pubkey(),
+        threshold: threshold as u8,
+        proof_response: nonce + challenge * &coefficients[0],
+        proof_nonce: R,
+        commitments,
+        shares,
+    })
+}
+
+/// The share of `keypair` in the threshold key, from the first messages of every one of `keys`,
+/// its own included. Names the first party whose message doesn't verify.
+#[tracing::instrument(name = "threshold.keygen_step_two", skip_all)]
+pub fn keygen_step_two(
+    keypair: &Keypair,
+    keys: &[Pubkey],
+    threshold: usize,
+    messages: &[KeygenMessage1],
+) -> Result<ThresholdKeyShare, Error> {
+    check_threshold(threshold, keys.len())?;
+    let index = position(keypair, keys)?;
+    let points = points(keys)?;
+    if messages.len() != keys.len() {
+        return Err(Error::InvalidThreshold(format!(
+            "{} messages, one of each of the {} keys is needed",
+            messages.len(),
+            keys.len()
+        )));
+    }
+    let expanded = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let context = keygen_context(keys, threshold);
+    let own = keygen_step_one(keypair, keys, threshold)?;
+
+    let mut share = Scalar::zero();
+    let mut group_key = Point::zero();
+    for (from, key) in keys.iter().enumerate() {
+        let message = messages
+            .iter()
+            .find(|message| message.sender == *key)
+            .ok_or_else(|| Error::InvalidThreshold(format!("no message of {}", key)))?;
+        let failed = |reason: &str| Error::ThresholdKeygenFailed {
+            party: *key,
+            reason: reason.to_string(),
+        };
+        if from == index && *message != own {
+            return Err(failed("a message this party didn't send"));
+        }
+        if usize::from(message.threshold) != threshold
+            || message.commitments.len() != threshold
+            || message.shares.len() != keys.len()
+        {
+            return Err(failed("a message for another threshold or set of keys"));
+        }
+        let challenge = proof_challenge(
+            from,
+            &message.commitments[0],
+            &message.proof_nonce,
+            &context,
+        );
+        if Point::generator() * &message.proof_response
+            != &message.proof_nonce + &message.commitments[0] * &challenge
+        {
+            return Err(failed("an invalid proof of its secret"));
+        }
+        let received = match from == index {
+            true => evaluate(&polynomial(&expanded, &context, threshold), index),
+            false => {
+                &message.shares[index] - share_pad(&expanded, &points[from], from, index, &context)
+            }
+        };
+        if Point::generator() * &received != evaluate_commitments(&message.commitments, index) {
+            return Err(failed("a share that doesn't match its commitments"));
+        }
+        share = share + received;
+        group_key = group_key + &message.commitments[0];
+    }
+    Ok(ThresholdKeyShare {
+        threshold: threshold as u8,
+        index: index as u8,
+        group_key,
+        share,
+    })
+}
+
+/// Hash of the keygen as `keypair` saw it: its parameters, the first message of every one of
+/// `keys` and the key they add up to. The same for every party unless one was deceived.
+pub fn keygen_transcript(
+    keys: &[Pubkey],
+    threshold: usize,
+    messages: &[KeygenMessage1],
+    share: &ThresholdKeyShare,
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(keygen_context(keys, threshold));
+    for key in keys {
+        if let Some(message) = messages.iter().find(|message| message.sender == *key) {
+            let mut bytes = Vec::with_capacity(message.size_hint());
+            message.serialize(&mut bytes);
+            hasher.update(bytes);
+        }
+    }
+    hasher.update(&*share.group_key.to_bytes(true));
+    hasher.finalize().into()
+}
+
+/// What a party signs to confirm `transcript`. The leading 0xff keeps it from being read as a
+/// transaction message, like Solana's off-chain messages.
+fn confirmation_message(transcript: &[u8; 32]) -> Vec<u8> {
+    let mut message = b"\xffsolana-tss threshold keygen confirmation".to_vec();
+    message.extend(transcript);
+    message
+}
+
+/// `keypair`'s confirmation of the keygen of `transcript`, for the other parties' step three.
+pub fn confirm_keygen(keypair: &Keypair, transcript: &[u8; 32]) -> Signature {
+    keypair.sign_message(&confirmation_message(transcript))
+}
+
+/// Fail unless every one of `keys` confirmed `transcript`, `confirmations` in the order of
+/// `keys`. Names the first party that confirmed something else.
+pub fn check_confirmations(
+    keys: &[Pubkey],
+    transcript: &[u8; 32],
+    confirmations: &[Signature],
+) -> Result<(), Error> {
+    if confirmations.len() != keys.len() {
+        return Err(Error::InvalidThreshold(format!(
+            "{} confirmations, one of each of the {} keys is needed",
+            confirmations.len(),
+            keys.len()
+        )));
+    }
+    let message = confirmation_message(transcript);
+    for (key, confirmation) in keys.iter().zip(confirmations) {
+        if !confirmation.verify(key.as_ref(), &message) {
+            return Err(Error::ThresholdKeygenFailed {
+                party: *key,
+                reason: "a confirmation of other first messages or another key".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The threshold key of `share` as a Solana address.
+pub fn group_pubkey(share: &ThresholdKeyShare) -> Pubkey {
+    Pubkey::try_from(&*share.group_key.to_bytes(true))
+        .expect("compressed ed25519 points are 32 bytes")
+}
+
+/// The parties signing for the threshold key `group_key`, resolved against the keys of its
+/// keygen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThresholdSigners {
+    pub threshold: usize,
+    pub group_key: Pubkey,
+    pub signers: Vec<Pubkey>,
+    /// Positions of `signers` in the keys
+    pub indices: Vec<usize>,
+}
+
+/// Positions in `keys` of `signers`, at least `threshold` distinct parties of the keygen.
+pub fn signer_indices(
+    keys: &[Pubkey],
+    signers: &[Pubkey],
+    threshold: usize,
+) -> Result<Vec<usize>, Error> {
+    check_threshold(threshold, keys.len())?;
+    if signers.len() < threshold {
+        return Err(Error::InvalidThreshold(format!(
+            "{} signers, the key needs {}",
+            signers.len(),
+            threshold
+        )));
+    }
+    let mut indices = Vec::with_capacity(signers.len());
+    for signer in signers {
+        let index =
+            keys.iter()
+                .position(|key| key == signer)
+                .ok_or_else(|| Error::SignerNotInKeySet {
+                    signer: *signer,
+                    keys: keys.to_vec(),
+                })?;
+        if indices.contains(&index) {
+            return Err(Error::InvalidThreshold(format!(
+                "{} is a signer twice",
+                signer
+            )));
+        }
+        indices.push(index);
+    }
+    Ok(indices)
+}
+
+/// Lagrange coefficient at zero of the party at `index` among `indices`, which weights its share
+/// so that the shares of `indices` add up to the secret of the key.
+pub fn lagrange_coefficient(index: usize, indices: &[usize]) -> Scalar<Ed25519> {
+    let x = x_of(index);
+    indices.iter().filter(|other| **other != index).fold(
+        Scalar::from_bigint(&BigInt::from(1)),
+        |acc, other| {
+            let x_other = x_of(*other);
+            let denominator = (&x_other - &x).invert().expect("indices are distinct");
+            acc * x_other * denominator
+        },
+    )
+}
+
+/// `share` in the shape `musig2::partial_sign` takes a key in, for signing together with the
+/// parties at `indices`. Its nonces keep coming from `keypair`.
+pub fn signing_key(
+    keypair: &Keypair,
+    share: &ThresholdKeyShare,
+    indices: &[usize],
+) -> (PublicKeyAgg, ExpandedKeyPair) {
+    let expanded = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let aggregated = PublicKeyAgg {
+        agg_public_key: share.group_key.clone(),
+        musig_coefficient: lagrange_coefficient(usize::from(share.index), indices),
+    };
+    let keypair = ExpandedKeyPair {
+        public_key: Point::generator() * &share.share,
+        expanded_private_key: ExpandedPrivateKey {
+            prefix: expanded.expanded_private_key.prefix,
+            private_key: share.share.clone(),
+        },
+    };
+    (aggregated, keypair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keygen(parties: usize, threshold: usize) -> (Vec<Keypair>, Vec<ThresholdKeyShare>) {
+        let keypairs: Vec<_> = (0..parties).map(|_| Keypair::new()).collect();
+        let keys: Vec<_> = keypairs.iter().map(Keypair::pubkey).collect();
+        let messages: Vec<_> = keypairs
+            .iter()
+            .map(|keypair| keygen_step_one(keypair, &keys, threshold).unwrap())
+            .collect();
+        let shares = keypairs
+            .iter()
+            .map(|keypair| keygen_step_two(keypair, &keys, threshold, &messages).unwrap())
+            .collect();
+        (keypairs, shares)
+    }
+
+    #[test]
+    fn test_any_threshold_of_shares_give_the_key() {
+        let (_, shares) = keygen(3, 2);
+        let group_key = shares[0].group_key.clone();
+        assert!(shares.iter().all(|share| share.group_key == group_key));
+        for indices in [vec![0, 1], vec![0, 2], vec![1, 2], vec![2, 0, 1]] {
+            let secret = indices.iter().fold(Scalar::zero(), |acc, i| {
+                acc + lagrange_coefficient(*i, &indices) * &shares[*i].share
+            });
+            assert_eq!(Point::generator() * secret, group_key, "{:?}", indices);
+        }
+        // One share alone is no better than a guess
+        assert_ne!(Point::generator() * &shares[0].share, group_key);
+    }
+
+    #[test]
+    fn test_keygen_names_the_cheating_party() {
+        let keypairs: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let keys: Vec<_> = keypairs.iter().map(Keypair::pubkey).collect();
+        let mut messages: Vec<_> = keypairs
+            .iter()
+            .map(|keypair| keygen_step_one(keypair, &keys, 2).unwrap())
+            .collect();
+        assert_eq!(
+            keygen_step_one(&keypairs[0], &keys, 2).unwrap(),
+            messages[0]
+        );
+
+        messages[1].shares[2] = &messages[1].shares[2] + Scalar::from_bigint(&BigInt::from(1));
+        // Only the party the tampered share was dealt to notices
+        keygen_step_two(&keypairs[0], &keys, 2, &messages).unwrap();
+        match keygen_step_two(&keypairs[2], &keys, 2, &messages) {
+            Err(Error::ThresholdKeygenFailed { party, reason }) => {
+                assert_eq!(party, keys[1]);
+                assert!(reason.contains("commitments"), "{}", reason);
+            }
+            other => panic!("expected a failed keygen, got {:?}", other),
+        }
+
+        messages[1] = keygen_step_one(&keypairs[1], &keys, 2).unwrap();
+        messages[2].proof_response = Scalar::random();
+        match keygen_step_two(&keypairs[0], &keys, 2, &messages) {
+            Err(Error::ThresholdKeygenFailed { party, reason }) => {
+                assert_eq!(party, keys[2]);
+                assert!(reason.contains("proof"), "{}", reason);
+            }
+            other => panic!("expected a failed keygen, got {:?}", other),
+        }
+        assert!(matches!(
+            keygen_step_two(&keypairs[0], &keys, 2, &messages[..2]),
+            Err(Error::InvalidThreshold(_))
+        ));
+    }
+
+    #[test]
+    fn test_every_party_confirms_the_same_keygen() {
+        let keypairs: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let keys: Vec<_> = keypairs.iter().map(Keypair::pubkey).collect();
+        let messages: Vec<_> = keypairs
+            .iter()
+            .map(|keypair| keygen_step_one(keypair, &keys, 2).unwrap())
+            .collect();
+        let transcripts: Vec<_> = keypairs
+            .iter()
+            .map(|keypair| {
+                let share = keygen_step_two(keypair, &keys, 2, &messages).unwrap();
+                keygen_transcript(&keys, 2, &messages, &share)
+            })
+            .collect();
+        assert!(transcripts.iter().all(|t| *t == transcripts[0]));
+        let mut confirmations: Vec<_> = keypairs
+            .iter()
+            .map(|keypair| confirm_keygen(keypair, &transcripts[0]))
+            .collect();
+        check_confirmations(&keys, &transcripts[0], &confirmations).unwrap();
+
+        // The last party saw another first message of the first party
+        let mut seen: Vec<_> = keypairs
+            .iter()
+            .map(|keypair| keygen_step_one(keypair, &keys, 2).unwrap())
+            .collect();
+        seen[0].proof_response = Scalar::random();
+        let share = keygen_step_two(&keypairs[2], &keys, 2, &messages).unwrap();
+        let deceived = keygen_transcript(&keys, 2, &seen, &share);
+        assert_ne!(deceived, transcripts[0]);
+        confirmations[2] = confirm_keygen(&keypairs[2], &deceived);
+        match check_confirmations(&keys, &transcripts[0], &confirmations) {
+            Err(Error::ThresholdKeygenFailed { party, .. }) => assert_eq!(party, keys[2]),
+            other => panic!("expected a failed keygen, got {:?}", other),
+        }
+        assert!(matches!(
+            check_confirmations(&keys, &transcripts[0], &confirmations[..2]),
+            Err(Error::InvalidThreshold(_))
+        ));
+    }
+
+    #[test]
+    fn test_signer_indices() {
+        let keys: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        assert_eq!(
+            signer_indices(&keys, &[keys[2], keys[0]], 2).unwrap(),
+            vec![2, 0]
+        );
+        for (signers, threshold) in [
+            (vec![keys[0]], 2),
+            (vec![keys[0], keys[0]], 2),
+            (vec![keys[0], keys[1]], 1),
+            (vec![keys[0], keys[1]], 4),
+        ] {
+            assert!(
+                matches!(
+                    signer_indices(&keys, &signers, threshold),
+                    Err(Error::InvalidThreshold(_))
+                ),
+                "{:?} of {}",
+                signers,
+                threshold
+            );
+        }
+        assert!(matches!(
+            signer_indices(&keys, &[keys[0], Pubkey::new_unique()], 2),
+            Err(Error::SignerNotInKeySet { .. })
+        ));
+    }
+}
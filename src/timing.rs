@@ -0,0 +1,152 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use poem::{Response, http::HeaderMap};
+use tracing::{Span, Subscriber, span};
+use tracing_subscriber::{
+    Layer, Registry,
+    layer::Context,
+    registry::{LookupSpan, SpanRef},
+};
+
+use crate::{
+    envelope,
+    models::{RpcTiming, Timings},
+};
+
+// Debug timings come from the same spans that are exported as traces: a request in debug timing
+// mode runs under a span carrying a `Collector`, and `TimingLayer` hands it the duration of every
+// `rpc.*`, `tss.*` and `response.*` span closed below it. Only the outermost of nested spans is
+// kept, `tss.key_agg` is already part of `tss.step_two`. Without `TimingLayer` in the installed
+// subscriber only `total_ms` and `parse_ms` are filled in.
+
+/// Request header asking for timings, honored for admin API keys.
+pub const HEADER: &str = "x-debug-timing";
+
+pub fn requested(headers: &HeaderMap) -> bool {
+    headers
+        .get(HEADER)
+        .is_some_and(|value| value.as_bytes() == b"1")
+}
+
+/// Durations of the spans closed under one request.
+#[derive(Debug, Default)]
+pub struct Collector {
+    spans: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl Collector {
+    /// Collect the spans closed under `span` from now on.
+    pub fn attach(self: &Arc<Self>, span: &Span) {
+        span.with_subscriber(|(id, dispatch)| {
+            if let Some(span) = dispatch
+                .downcast_ref::<Registry>()
+                .and_then(|registry| registry.span(id))
+            {
+                span.extensions_mut().insert(self.clone());
+            }
+        });
+    }
+
+    pub fn timings(&self, total: Duration) -> Timings {
+        let mut timings = Timings {
+            total_ms: millis(total),
+            ..Timings::default()
+        };
+        for &(name, elapsed) in self.spans.lock().unwrap().iter() {
+            if name.starts_with("rpc.") {
+                timings.rpc.push(RpcTiming {
+                    call: name.to_string(),
+                    ms: millis(elapsed),
+                });
+            } else if name.starts_with("tss.") {
+                timings.tss_ms += millis(elapsed);
+            } else if name.starts_with("response.") {
+                timings.serialization_ms += millis(elapsed);
+            }
+        }
+        let rpc_ms: f64 = timings.rpc.iter().map(|call| call.ms).sum();
+        timings.parse_ms =
+            (timings.total_ms - rpc_ms - timings.tss_ms - timings.serialization_ms).max(0.0);
+        timings
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn is_timed(name: &str) -> bool {
+    ["rpc.", "tss.", "response."]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+struct Started {
+    at: Instant,
+    collector: Arc<Collector>,
+}
+
+/// Feeds the `Collector` of the enclosing request, a no-op for requests without one.
+pub struct TimingLayer;
+
+impl TimingLayer {
+    fn collector<S>(span: &SpanRef<'_, S>) -> Option<Arc<Collector>>
+    where
+        S: for<'a> LookupSpan<'a>,
+    {
+        for ancestor in span.scope().skip(1) {
+            let extensions = ancestor.extensions();
+            // Nested in a span that is already timed
+            if extensions.get::<Started>().is_some() {
+                return None;
+            }
+            if let Some(collector) = extensions.get::<Arc<Collector>>() {
+                return Some(collector.clone());
+            }
+        }
+        None
+    }
+}
+
+impl<S> Layer<S> for TimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if !is_timed(attrs.metadata().name()) {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if let Some(collector) = Self::collector(&span) {
+            span.extensions_mut().insert(Started {
+                at: Instant::now(),
+                collector,
+            });
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if let Some(started) = span.extensions().get::<Started>() {
+            started
+                .collector
+                .spans
+                .lock()
+                .unwrap()
+                .push((span.name(), started.at.elapsed()));
+        }
+    }
+}
+
+/// `resp` with `timings` added to its JSON object body, other bodies are left alone.
+pub async fn add_timings(resp: Response, timings: &Timings) -> Response {
+    let timings = serde_json::to_value(timings).unwrap_or_default();
+    envelope::insert_field(resp, "timings", timings).await
+}
@@ -0,0 +1,652 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    Error,
+    input::normalize,
+    models::Network,
+    models::{DeletedTokenEntry, TokenEntry},
+    spl_token_utils::{TokenProgram, unpack_mint},
+    units::{Decimals, RawTokenAmount},
+};
+
+/// A token known by its symbol, from the `tokens` config section or `/api/tokens`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub mint: String,
+    pub decimals: u8,
+    /// Network the mint lives on, the startup checks look it up there
+    pub net: Network,
+    /// Largest `amount` of a single transfer, in whole tokens like `amount`
+    #[serde(default)]
+    pub max_per_transaction: Option<f64>,
+    /// Create the recipient's token account when it doesn't exist yet
+    #[serde(default = "default_allow_create_ata")]
+    pub allow_create_ata: bool,
+}
+
+fn default_allow_create_ata() -> bool {
+    true
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    mint: Pubkey,
+    decimals: Decimals,
+    net: Network,
+    max_per_transaction: Option<f64>,
+    allow_create_ata: bool,
+}
+
+impl Token {
+    fn parse(config: &TokenConfig) -> Result<Self, String> {
+        if let Some(max) = config
+            .max_per_transaction
+            .filter(|max| !(max.is_finite() && *max > 0.0))
+        {
+            return Err(format!("max_per_transaction must be positive, got {}", max));
+        }
+        let decimals = Decimals::new(config.decimals).map_err(|e| e.to_string())?;
+        if let Some(max) = config.max_per_transaction {
+            RawTokenAmount::from_ui(max, decimals)
+                .map_err(|e| format!("max_per_transaction: {}", e))?;
+        }
+        Ok(Self {
+            mint: config
+                .mint
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid mint {:?}: {}", config.mint, e))?,
+            decimals,
+            net: config.net,
+            max_per_transaction: config.max_per_transaction,
+            allow_create_ata: config.allow_create_ata,
+        })
+    }
+
+    fn entry(&self, symbol: &str) -> TokenEntry {
+        TokenEntry {
+            symbol: symbol.to_string(),
+            mint: self.mint.to_string(),
+            decimals: self.decimals.get(),
+            net: self.net,
+            max_per_transaction: self.max_per_transaction,
+            allow_create_ata: self.allow_create_ata,
+        }
+    }
+}
+
+/// The token a transfer moves, with the registry's rules for it if it is registered.
+#[derive(Debug, Clone)]
+pub struct ResolvedToken {
+    pub mint: Pubkey,
+    pub decimals: Decimals,
+    /// Set when the mint is in the registry, whether it was named by symbol or by mint
+    pub symbol: Option<String>,
+    pub max_per_transaction: Option<f64>,
+    pub allow_create_ata: bool,
+    /// The classic program until the handler learned otherwise
+    pub program: TokenProgram,
+}
+
+impl ResolvedToken {
+    fn name(&self) -> String {
+        self.symbol.clone().unwrap_or_else(|| self.mint.to_string())
+    }
+
+    /// The associated token account of `owner` for this token.
+    pub fn ata(&self, owner: &Pubkey) -> Pubkey {
+        self.program.associated_address(owner, &self.mint)
+    }
+
+    /// Reject `amount` above the token's `max_per_transaction`, compared in base units.
+    pub fn check_amount(&self, amount: RawTokenAmount) -> Result<(), Error> {
+        let Some(max) = self.max_per_transaction else {
+            return Ok(());
+        };
+        if amount > RawTokenAmount::from_ui(max, self.decimals)? {
+            return Err(Error::TokenLimitExceeded {
+                token: self.name(),
+                amount: amount.to_ui_string(self.decimals),
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reject a transfer that would have to create `recipient_ata` when the token doesn't
+    /// allow it. `exists` is `None` when there was no network to look the account up on.
+    pub fn check_recipient_ata(
+        &self,
+        recipient_ata: &Pubkey,
+        exists: Option<bool>,
+    ) -> Result<(), Error> {
+        match exists {
+            _ if self.allow_create_ata => Ok(()),
+            Some(true) => Ok(()),
+            Some(false) => Err(Error::AtaCreationNotAllowed {
+                token: self.name(),
+                account: recipient_ata.to_string(),
+            }),
+            None => Err(Error::InvalidToken(format!(
+                "{} doesn't allow creating token accounts, pass net so the recipient's can be checked",
+                self.name()
+            ))),
+        }
+    }
+}
+
+/// How long a deleted token can be restored, unless `deleted_token_retention_secs` says
+/// otherwise.
+pub const DELETED_TOKEN_RETENTION_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug)]
+struct Deleted {
+    token: Token,
+    purge_at: SystemTime,
+}
+
+impl Deleted {
+    fn entry(&self, symbol: &str) -> DeletedTokenEntry {
+        DeletedTokenEntry {
+            token: self.token.entry(symbol),
+            purge_at: unix_ms(self.purge_at),
+        }
+    }
+}
+
+/// Tokens by upper case symbol, seeded from the config and edited through `/api/tokens`.
+/// Deleted tokens are kept aside until their retention ends or they are purged, and can be
+/// restored until then.
+#[derive(Debug)]
+pub struct TokenRegistry {
+    tokens: RwLock<BTreeMap<String, Token>>,
+    /// Taken after `tokens` when both are needed
+    deleted: Mutex<BTreeMap<String, Deleted>>,
+    retention: Duration,
+}
+
+impl Default for TokenRegistry {
+    fn default() -> Self {
+        Self {
+            tokens: RwLock::default(),
+            deleted: Mutex::default(),
+            retention: Duration::from_secs(DELETED_TOKEN_RETENTION_SECS),
+        }
+    }
+}
+
+impl TokenRegistry {
+    pub fn new(config: &HashMap<String, TokenConfig>) -> Result<Self, String> {
+        let registry = Self::default();
+        for (symbol, token) in config {
+            registry
+                .upsert(symbol, token)
+                .map_err(|e| format!("tokens.{}: {}", symbol, e))?;
+        }
+        Ok(registry)
+    }
+
+    /// How long deleted tokens can be restored.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// The deleted tokens that can still be restored.
+    pub fn deleted(&self) -> Vec<DeletedTokenEntry> {
+        let deleted = self.live_deleted();
+        deleted.iter().map(|(symbol, d)| d.entry(symbol)).collect()
+    }
+
+    /// The deleted tokens, without those whose retention ended.
+    fn live_deleted(&self) -> MutexGuard<'_, BTreeMap<String, Deleted>> {
+        let mut deleted = self.deleted.lock().unwrap();
+        let now = SystemTime::now();
+        deleted.retain(|_, d| d.purge_at > now);
+        deleted
+    }
+
+    pub fn list(&self) -> Vec<TokenEntry> {
+        let tokens = self.tokens.read().unwrap();
+        tokens
+            .iter()
+            .map(|(symbol, token)| token.entry(symbol))
+            .collect()
+    }
+
+    /// Add or replace `symbol`. A mint is only registered once per network, so a transfer
+    /// naming it by mint finds a single set of rules.
+    pub fn upsert(&self, symbol: &str, config: &TokenConfig) -> Result<TokenEntry, Error> {
+        let symbol = symbol.trim().to_uppercase();
+        if symbol.is_empty() {
+            return Err(Error::InvalidToken("symbol can't be empty".to_string()));
+        }
+        let token = Token::parse(config).map_err(Error::InvalidToken)?;
+
+        let mut tokens = self.tokens.write().unwrap();
+        if let Some((other, _)) = tokens
+            .iter()
+            .find(|(other, t)| **other != symbol && t.mint == token.mint && t.net == token.net)
+        {
+            return Err(Error::InvalidToken(format!(
+                "mint {} is already registered on {} as {}",
+                token.mint, token.net, other
+            )));
+        }
+        let entry = token.entry(&symbol);
+        // A deleted token of the same symbol is replaced for good
+        self.deleted.lock().unwrap().remove(&symbol);
+        tokens.insert(symbol, token);
+        Ok(entry)
+    }
+
+    /// Set `symbol` aside, it can be restored until its retention ends.
+    pub fn remove(&self, symbol: &str) -> Result<DeletedTokenEntry, Error> {
+        let symbol = symbol.trim().to_uppercase();
+        let mut tokens = self.tokens.write().unwrap();
+        let mut deleted = self.live_deleted();
+        let Some(token) = tokens.remove(&symbol) else {
+            return Err(match deleted.get(&symbol) {
+                Some(d) => deleted_error(&symbol, d),
+                None => unknown(&tokens, &symbol),
+            });
+        };
+        let d = Deleted {
+            token,
+            purge_at: SystemTime::now() + self.retention,
+        };
+        let entry = d.entry(&symbol);
+        deleted.insert(symbol, d);
+        Ok(entry)
+    }
+
+    /// Register a deleted token again, unless its mint was registered again since.
+    pub fn restore(&self, symbol: &str) -> Result<TokenEntry, Error> {
+        let symbol = symbol.trim().to_uppercase();
+        let mut tokens = self.tokens.write().unwrap();
+        let mut deleted = self.live_deleted();
+        let Some(d) = deleted.remove(&symbol) else {
+            return Err(unknown_deleted(&symbol));
+        };
+        if let Some((other, _)) = tokens
+            .iter()
+            .find(|(_, t)| t.mint == d.token.mint && t.net == d.token.net)
+        {
+            let e = Error::InvalidToken(format!(
+                "mint {} was registered on {} as {} since {} was deleted",
+                d.token.mint, d.token.net, other, symbol
+            ));
+            deleted.insert(symbol, d);
+            return Err(e);
+        }
+        let entry = d.token.entry(&symbol);
+        tokens.insert(symbol, d.token);
+        Ok(entry)
+    }
+
+    /// Forget a deleted token before its retention ends. Registered tokens have to be deleted
+    /// first, that's the confirmation a purge asks for.
+    pub fn purge(&self, symbol: &str) -> Result<DeletedTokenEntry, Error> {
+        let symbol = symbol.trim().to_uppercase();
+        let tokens = self.tokens.read().unwrap();
+        let mut deleted = self.live_deleted();
+        match deleted.remove(&symbol) {
+            Some(d) => Ok(DeletedTokenEntry {
+                token: d.token.entry(&symbol),
+                purge_at: unix_ms(SystemTime::now()),
+            }),
+            None if tokens.contains_key(&symbol) => Err(Error::InvalidToken(format!(
+                "{} is registered, delete it before purging it",
+                symbol
+            ))),
+            None => Err(unknown_deleted(&symbol)),
+        }
+    }
+
+    /// Find the token of a request that names it by `token` symbol or by `token_mint`. Values
+    /// given next to a symbol have to agree with the registry, an unregistered mint needs its
+    /// `decimals`.
+    pub fn resolve(
+        &self,
+        token: Option<&str>,
+        token_mint: Option<&str>,
+        decimals: Option<u8>,
+        net: Option<Network>,
+    ) -> Result<ResolvedToken, Error> {
+        let token_mint = token_mint.map(parse_mint).transpose()?;
+        let tokens = self.tokens.read().unwrap();
+
+        let registered = match (token, token_mint) {
+            (Some(symbol), _) => {
+                let symbol = normalize(symbol)?.to_uppercase();
+                let Some(registered) = tokens.get(&symbol) else {
+                    return Err(match self.live_deleted().get(&symbol) {
+                        Some(d) => deleted_error(&symbol, d),
+                        None => unknown(&tokens, &symbol),
+                    });
+                };
+                if let Some(net) = net.filter(|net| *net != registered.net) {
+                    return Err(Error::InvalidToken(format!(
+                        "{} is registered on {}, not {}",
+                        symbol, registered.net, net
+                    )));
+                }
+                if let Some(mint) = token_mint.filter(|mint| *mint != registered.mint) {
+                    return Err(Error::InvalidToken(format!(
+                        "token_mint {} isn't the mint of {} ({})",
+                        mint, symbol, registered.mint
+                    )));
+                }
+                Some((symbol, registered))
+            }
+            (None, Some(mint)) => tokens
+                .iter()
+                .find(|(_, t)| t.mint == mint && net.is_none_or(|net| t.net == net))
+                .map(|(symbol, t)| (symbol.clone(), t)),
+            (None, None) => {
+                return Err(Error::InvalidToken(
+                    "either token or token_mint is required".to_string(),
+                ));
+            }
+        };
+
+        match registered {
+            Some((symbol, registered)) => {
+                if let Some(decimals) = decimals.filter(|d| *d != registered.decimals.get()) {
+                    return Err(Error::InvalidToken(format!(
+                        "{} has {} decimals, not {}",
+                        symbol, registered.decimals, decimals
+                    )));
+                }
+                Ok(ResolvedToken {
+                    mint: registered.mint,
+                    decimals: registered.decimals,
+                    symbol: Some(symbol),
+                    max_per_transaction: registered.max_per_transaction,
+                    allow_create_ata: registered.allow_create_ata,
+                    program: TokenProgram::default(),
+                })
+            }
+            None => Ok(ResolvedToken {
+                // Only reachable with a mint, a symbol is always registered
+                mint: token_mint.unwrap_or_default(),
+                decimals: Decimals::new(decimals.ok_or_else(|| {
+                    Error::InvalidToken(
+                        "decimals is required for mints that aren't registered".to_string(),
+                    )
+                })?)?,
+                symbol: None,
+                max_per_transaction: None,
+                allow_create_ata: true,
+                program: TokenProgram::default(),
+            }),
+        }
+    }
+
+    /// Just the mint, for requests that read the decimals from the chain.
+    pub fn resolve_mint(
+        &self,
+        token: Option<&str>,
+        token_mint: Option<&str>,
+        net: Network,
+    ) -> Result<Pubkey, Error> {
+        match token {
+            Some(_) => Ok(self.resolve(token, token_mint, None, Some(net))?.mint),
+            None => parse_mint(token_mint.ok_or_else(|| {
+                Error::InvalidToken("either token or token_mint is required".to_string())
+            })?),
+        }
+    }
+
+    /// Decimals of `mint` when it is registered on `net`.
+    pub fn registered_decimals(&self, mint: &Pubkey, net: Network) -> Option<u8> {
+        let tokens = self.tokens.read().unwrap();
+        tokens
+            .values()
+            .find(|t| t.mint == *mint && t.net == net)
+            .map(|t| t.decimals.get())
+    }
+}
+
+fn unknown(tokens: &BTreeMap<String, Token>, symbol: &str) -> Error {
+    Error::UnknownToken {
+        symbol: symbol.to_string(),
+        known: tokens.keys().cloned().collect(),
+    }
+}
+
+fn deleted_error(symbol: &str, deleted: &Deleted) -> Error {
+    Error::TokenDeleted {
+        symbol: symbol.to_string(),
+        purge_at: unix_ms(deleted.purge_at),
+    }
+}
+
+fn unknown_deleted(symbol: &str) -> Error {
+    Error::InvalidToken(format!("{} isn't a deleted token", symbol))
+}
+
+fn unix_ms(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn parse_mint(s: &str) -> Result<Pubkey, Error> {
+    let s = normalize(s)?;
+    s.parse()
+        .map_err(|_| Error::InvalidPublicKey(s.to_string()))
+}
+
+/// Decimals of the SPL token or Token-2022 mint at `mint`, `None` if there is no such mint.
+pub async fn fetch_mint_decimals(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Option<u8>, ClientError> {
+    let account = rpc_client
+        .get_account_with_commitment(mint, rpc_client.commitment())
+        .await?
+        .value;
+    Ok(account
+        .and_then(|account| unpack_mint(TokenProgram::of(&account.owner)?, &account.data).ok())
+        .map(|mint| mint.decimals))
+}
+
+/// What's wrong with a registered token, given the decimals its mint has on chain (`None`
+/// when there is no such mint).
+pub fn chain_mismatch(entry: &TokenEntry, decimals: Option<u8>) -> Option<String> {
+    match decimals {
+        Some(decimals) if decimals == entry.decimals => None,
+        Some(decimals) => Some(format!(
+            "mint {} has {} decimals on {}, not {}",
+            entry.mint, decimals, entry.net, entry.decimals
+        )),
+        None => Some(format!(
+            "{} is not a token mint on {}",
+            entry.mint, entry.net
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::{
+        Error,
+        fixtures::{Accounts, mint, packed, token_2022, token_account},
+        models::Network,
+        token_registry::{TokenConfig, TokenRegistry, fetch_mint_decimals},
+        units::RawTokenAmount,
+    };
+
+    #[test]
+    fn test_resolve() {
+        let usdc = Pubkey::new_unique();
+        let registry = TokenRegistry::new(&HashMap::from([(
+            "usdc".to_string(),
+            TokenConfig {
+                mint: usdc.to_string(),
+                decimals: 6,
+                net: Network::Mainnet,
+                max_per_transaction: Some(100.0),
+                allow_create_ata: false,
+            },
+        )]))
+        .unwrap();
+
+        // By symbol, in any case, or by its mint, the registry's rules apply
+        let token = registry
+            .resolve(Some(" Usdc"), None, None, Some(Network::Mainnet))
+            .unwrap();
+        assert_eq!((token.mint, token.decimals.get()), (usdc, 6));
+        let by_mint = registry
+            .resolve(None, Some(&usdc.to_string()), None, None)
+            .unwrap();
+        assert_eq!(by_mint.symbol.as_deref(), Some("USDC"));
+        token
+            .check_amount(RawTokenAmount::new(100_000_000))
+            .unwrap();
+        assert!(matches!(
+            token.check_amount(RawTokenAmount::new(100_000_001)),
+            Err(Error::TokenLimitExceeded { .. })
+        ));
+        assert!(matches!(
+            token.check_recipient_ata(&usdc, Some(false)),
+            Err(Error::AtaCreationNotAllowed { .. })
+        ));
+        token.check_recipient_ata(&usdc, Some(true)).unwrap();
+
+        match registry.resolve(Some("USDT"), None, None, None) {
+            Err(e @ Error::UnknownToken { .. }) => {
+                assert_eq!(e.to_string(), "unknown token USDT, known tokens are USDC")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        for (mint, decimals, net) in [
+            (Some(Pubkey::new_unique().to_string()), None, None),
+            (None, Some(9), None),
+            (None, None, Some(Network::Devnet)),
+        ] {
+            assert!(matches!(
+                registry.resolve(Some("USDC"), mint.as_deref(), decimals, net),
+                Err(Error::InvalidToken(_))
+            ));
+        }
+
+        // Unregistered mints are passed through but need their decimals
+        let other = Pubkey::new_unique().to_string();
+        assert!(registry.resolve(None, Some(&other), None, None).is_err());
+        let token = registry.resolve(None, Some(&other), Some(9), None).unwrap();
+        assert!(token.symbol.is_none() && token.allow_create_ata);
+        token.check_amount(RawTokenAmount::new(u64::MAX)).unwrap();
+
+        // The same mint on the same network can't be registered twice
+        let mut config = TokenConfig {
+            mint: usdc.to_string(),
+            decimals: 6,
+            net: Network::Mainnet,
+            max_per_transaction: None,
+            allow_create_ata: true,
+        };
+        assert!(registry.upsert("USDC2", &config).is_err());
+        config.net = Network::Devnet;
+        registry.upsert("USDC2", &config).unwrap();
+        registry.remove("usdc2").unwrap();
+        assert!(registry.remove("usdc2").is_err());
+        assert_eq!(registry.list().len(), 1);
+    }
+
+    #[test]
+    fn test_deleted_tokens() {
+        let usdc = Pubkey::new_unique();
+        let config = TokenConfig {
+            mint: usdc.to_string(),
+            decimals: 6,
+            net: Network::Mainnet,
+            max_per_transaction: None,
+            allow_create_ata: true,
+        };
+        let registry = TokenRegistry::default();
+        registry.upsert("USDC", &config).unwrap();
+
+        let deleted = registry.remove("usdc").unwrap();
+        assert!(registry.list().is_empty());
+        assert_eq!(registry.deleted()[0].purge_at, deleted.purge_at);
+        assert!(matches!(
+            registry.resolve(Some("USDC"), None, None, None),
+            Err(Error::TokenDeleted { .. })
+        ));
+        assert!(matches!(
+            registry.remove("USDC"),
+            Err(Error::TokenDeleted { .. })
+        ));
+        registry.restore("USDC").unwrap();
+        registry.resolve(Some("USDC"), None, None, None).unwrap();
+
+        // Its mint registered again under another symbol keeps it deleted
+        registry.remove("USDC").unwrap();
+        registry.upsert("USDC2", &config).unwrap();
+        assert!(matches!(
+            registry.restore("USDC"),
+            Err(Error::InvalidToken(_))
+        ));
+        registry.remove("USDC2").unwrap();
+        registry.restore("USDC").unwrap();
+
+        // A registered token isn't purged, a deleted one is gone for good
+        assert!(registry.purge("USDC").is_err());
+        registry.remove("USDC").unwrap();
+        registry.purge("USDC").unwrap();
+        assert!(registry.restore("USDC").is_err());
+        assert!(matches!(
+            registry.resolve(Some("USDC"), None, None, None),
+            Err(Error::UnknownToken { .. })
+        ));
+
+        // Nor can it be restored after its retention
+        let registry = TokenRegistry::default().retention(Duration::ZERO);
+        registry.upsert("USDC", &config).unwrap();
+        registry.remove("USDC").unwrap();
+        assert!(registry.deleted().is_empty());
+        assert!(registry.restore("USDC").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_mint_decimals() {
+        let address = spl_token::native_mint::id();
+        let mut system_owned = mint(6);
+        system_owned.owner = solana_sdk::system_program::id();
+        let token_account = packed(token_account(address, Pubkey::new_unique(), 1));
+        for (account, expected) in [
+            (Some(token_2022(mint(2))), Some(2)),
+            (Some(token_2022(token_account.clone())), None),
+            (Some(mint(0)), Some(0)),
+            (Some(mint(6)), Some(6)),
+            (Some(mint(9)), Some(9)),
+            (None, None),
+            (Some(system_owned), None),
+            (Some(token_account), None),
+        ] {
+            let accounts = match account {
+                Some(account) => Accounts::default().with(address, account),
+                None => Accounts::default(),
+            };
+            let decimals = fetch_mint_decimals(&accounts.async_rpc_client(), &address)
+                .await
+                .unwrap();
+            assert_eq!(decimals, expected);
+        }
+    }
+}
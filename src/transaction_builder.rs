@@ -0,0 +1,1133 @@
+use std::str::FromStr;
+
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::Signature,
+    system_instruction,
+};
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use spl_token_2022::instruction as token_instruction;
+
+use crate::{
+    Error,
+    durable_nonce::DurableNonce,
+    ed25519_verify::ed25519_verify_instruction,
+    message_review::account_keys,
+    models::InstructionAccount,
+    spl_token_utils::TokenProgram,
+    units::{Decimals, Lamports, RawTokenAmount},
+};
+
+// Every message is built here so the single-key path, step two and the aggregation step
+// can't drift apart: the same builder inputs always produce the same message bytes.
+
+/// Memo v3, the default, and the legacy Memo v1 some indexers still only track.
+pub fn known_memo_programs() -> [Pubkey; 2] {
+    [spl_memo::id(), spl_memo::v1::id()]
+}
+
+/// The memo program a request asked for, anything but a known one needs `allow_custom`.
+pub fn resolve_memo_program(
+    memo_program: Option<&str>,
+    allow_custom: bool,
+) -> Result<Pubkey, Error> {
+    let Some(memo_program) = memo_program else {
+        return Ok(spl_memo::id());
+    };
+    let program_id = Pubkey::from_str(memo_program)
+        .map_err(|_| Error::InvalidMemoProgram(memo_program.to_string()))?;
+    if !allow_custom && !known_memo_programs().contains(&program_id) {
+        return Err(Error::InvalidMemoProgram(memo_program.to_string()));
+    }
+    Ok(program_id)
+}
+
+/// Append Solana Pay `references` to a transfer instruction as read-only, non-signer accounts,
+/// in order, which the program ignores and a merchant finds the transaction by. A transfer
+/// with references is laid out as Solana Pay wallets do it, with the memo right before the
+/// transfer rather than after, so the merchant's validation of the transfer accepts it.
+fn with_references(transfer: &mut Instruction, references: &[Pubkey]) {
+    transfer.accounts.extend(
+        references
+            .iter()
+            .map(|reference| AccountMeta::new_readonly(*reference, false)),
+    );
+}
+
+/// The data of a memo instruction, the UTF-8 bytes of `memo` exactly as the request sent it.
+/// Nothing is trimmed or normalized: every party's step two and the aggregation build the
+/// memo from their own request, a memo rewritten on one of those paths signs another message.
+pub fn memo_data(memo: &str) -> Vec<u8> {
+    memo.as_bytes().to_vec()
+}
+
+/// With a `signer` the memo program checks its signature and indexers attribute the memo to it.
+fn memo_instruction(memo: &str, signer: Option<&Pubkey>, program_id: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: signer
+            .map(|signer| AccountMeta::new_readonly(*signer, true))
+            .into_iter()
+            .collect(),
+        data: memo_data(memo),
+    }
+}
+
+/// Size of the signed transaction carrying `message`.
+pub fn transaction_size(message: &Message) -> usize {
+    // compact-u16 signature count, one byte for anything below 128 signatures
+    1 + 64 * message.header.num_required_signatures as usize + message.serialize().len()
+}
+
+/// Sizes of a transfer too large for one transaction, for `Error::TransactionTooLarge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Oversize {
+    pub size: usize,
+    pub size_without_memo: usize,
+    pub limit: usize,
+    /// Most recipients one transaction with the memo has room for, from the start of the list
+    pub max_recipients: usize,
+}
+
+/// The limit comes first, then the price, ahead of every other instruction.
+fn compute_budget_instructions(
+    compute_unit_limit: Option<u32>,
+    compute_unit_price: Option<u64>,
+) -> Vec<Instruction> {
+    let limit = compute_unit_limit.map(ComputeBudgetInstruction::set_compute_unit_limit);
+    let price = compute_unit_price.map(ComputeBudgetInstruction::set_compute_unit_price);
+    limit.into_iter().chain(price).collect()
+}
+
+/// Most `recipients` next to `to` of one transfer, split or not.
+pub const MAX_TRANSFER_RECIPIENTS: usize = 100;
+
+/// Native SOL transfer, paid for by the sender. Several recipients are paid in the order
+/// they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferBuilder {
+    from: Pubkey,
+    recipients: Vec<(Pubkey, Lamports)>,
+    memo: Option<String>,
+    signed_memo: bool,
+    memo_program: Pubkey,
+    compute_unit_limit: Option<u32>,
+    compute_unit_price: Option<u64>,
+    durable_nonce: Option<DurableNonce>,
+    references: Vec<Pubkey>,
+}
+
+impl TransferBuilder {
+    pub fn new(from: Pubkey, to: Pubkey, lamports: Lamports) -> Self {
+        Self {
+            from,
+            recipients: vec![(to, lamports)],
+            memo: None,
+            signed_memo: false,
+            memo_program: spl_memo::id(),
+            compute_unit_limit: None,
+            compute_unit_price: None,
+            durable_nonce: None,
+            references: Vec::new(),
+        }
+    }
+
+    /// Also pay `lamports` to `to`, in the same transaction.
+    pub fn recipient(mut self, to: Pubkey, lamports: Lamports) -> Self {
+        self.recipients.push((to, lamports));
+        self
+    }
+
+    pub fn memo(mut self, memo: Option<String>) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    /// Make the fee payer a signer of the memo instruction.
+    pub fn signed_memo(mut self, signed: bool) -> Self {
+        self.signed_memo = signed;
+        self
+    }
+
+    /// Program the memo instruction is addressed to, Memo v3 unless set.
+    pub fn memo_program(mut self, program_id: Pubkey) -> Self {
+        self.memo_program = program_id;
+        self
+    }
+
+    /// Prepend a compute budget instruction setting the limit.
+    pub fn compute_unit_limit(mut self, limit: Option<u32>) -> Self {
+        self.compute_unit_limit = limit;
+        self
+    }
+
+    /// Prepend a compute budget instruction setting the priority fee, in micro-lamports per
+    /// compute unit.
+    pub fn compute_unit_price(mut self, micro_lamports: Option<u64>) -> Self {
+        self.compute_unit_price = micro_lamports;
+        self
+    }
+
+    /// Advance `durable_nonce` first, ahead of the compute budget instructions. The message is
+    /// then signed with the nonce in place of a recent blockhash.
+    pub fn durable_nonce(mut self, durable_nonce: Option<DurableNonce>) -> Self {
+        self.durable_nonce = durable_nonce;
+        self
+    }
+
+    /// Add Solana Pay `references` to the first transfer, see `with_references`.
+    pub fn references(mut self, references: Vec<Pubkey>) -> Self {
+        self.references = references;
+        self
+    }
+
+    /// The sender, which pays the fees.
+    pub fn payer(&self) -> Pubkey {
+        self.from
+    }
+
+    pub fn recipients(&self) -> &[(Pubkey, Lamports)] {
+        &self.recipients
+    }
+
+    pub fn instructions(&self) -> Vec<Instruction> {
+        let mut transfers: Vec<_> = self
+            .recipients
+            .iter()
+            .map(|(to, lamports)| system_instruction::transfer(&self.from, to, lamports.get()))
+            .collect();
+        if let Some(first) = transfers.first_mut() {
+            with_references(first, &self.references);
+        }
+        let memo = self.memo.as_ref().map(|memo| {
+            let signer = self.signed_memo.then_some(&self.from);
+            memo_instruction(memo, signer, &self.memo_program)
+        });
+        let advance = self.durable_nonce.map(|durable_nonce| {
+            system_instruction::advance_nonce_account(
+                &durable_nonce.account,
+                &durable_nonce.authority,
+            )
+        });
+        let mut instructions: Vec<_> = advance.into_iter().collect();
+        instructions.extend(compute_budget_instructions(
+            self.compute_unit_limit,
+            self.compute_unit_price,
+        ));
+        match self.references.is_empty() {
+            true => instructions.extend(transfers.into_iter().chain(memo)),
+            false => instructions.extend(memo.into_iter().chain(transfers)),
+        }
+        instructions
+    }
+
+    pub fn build(&self) -> Message {
+        Message::new(&self.instructions(), Some(&self.from))
+    }
+
+    /// The accounts of the built message with their signer and writable flags.
+    pub fn account_keys(&self) -> Vec<InstructionAccount> {
+        account_keys(&self.build())
+    }
+
+    /// The same transfer paying only `recipients`.
+    fn with_recipients(&self, recipients: &[(Pubkey, Lamports)]) -> Self {
+        Self {
+            recipients: recipients.to_vec(),
+            ..self.clone()
+        }
+    }
+
+    fn size(&self) -> usize {
+        transaction_size(&self.build())
+    }
+
+    /// The sizes when the transaction is over the packet size, `None` when it fits.
+    pub fn oversize(&self) -> Option<Oversize> {
+        let size = self.size();
+        if size <= PACKET_DATA_SIZE {
+            return None;
+        }
+        let max_recipients = (1..self.recipients.len())
+            .rev()
+            .find(|&n| self.with_recipients(&self.recipients[..n]).size() <= PACKET_DATA_SIZE)
+            .unwrap_or(0);
+        Some(Oversize {
+            size,
+            size_without_memo: Self {
+                memo: None,
+                ..self.clone()
+            }
+            .size(),
+            limit: PACKET_DATA_SIZE,
+            max_recipients,
+        })
+    }
+
+    /// Split into as few transfers as fit into one transaction each, every one carrying the
+    /// memo. Fails when the memo leaves no room for even a single recipient.
+    pub fn split(&self) -> Result<Vec<TransferBuilder>, Error> {
+        let mut transfers = Vec::new();
+        let mut start = 0;
+        while start < self.recipients.len() {
+            let mut end = start + 1;
+            let first = self.with_recipients(&self.recipients[start..end]);
+            if let Some(oversize) = first.oversize() {
+                return Err(Error::TransactionTooLarge {
+                    oversize,
+                    splittable: true,
+                });
+            }
+            while end < self.recipients.len()
+                && self
+                    .with_recipients(&self.recipients[start..end + 1])
+                    .size()
+                    <= PACKET_DATA_SIZE
+            {
+                end += 1;
+            }
+            transfers.push(self.with_recipients(&self.recipients[start..end]));
+            start = end;
+        }
+        Ok(transfers)
+    }
+}
+
+/// SPL token transfer between the associated token accounts of `owner` and `to`,
+/// `owner` also pays the fees and, unless there is a rent sponsor, the rent of a created
+/// recipient ATA. Always a `transfer_checked`, so the runtime rejects a source
+/// account of another mint or a wrong `decimals`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplTransferBuilder {
+    owner: Pubkey,
+    to: Pubkey,
+    token_mint: Pubkey,
+    token_program: TokenProgram,
+    amount: RawTokenAmount,
+    decimals: Decimals,
+    memo: Option<String>,
+    signed_memo: bool,
+    memo_program: Pubkey,
+    create_recipient_ata: bool,
+    rent_sponsor: Option<Pubkey>,
+    compute_unit_limit: Option<u32>,
+    compute_unit_price: Option<u64>,
+    references: Vec<Pubkey>,
+}
+
+impl SplTransferBuilder {
+    pub fn new(
+        owner: Pubkey,
+        to: Pubkey,
+        token_mint: Pubkey,
+        amount: RawTokenAmount,
+        decimals: Decimals,
+    ) -> Self {
+        Self {
+            owner,
+            to,
+            token_mint,
+            token_program: TokenProgram::SplToken,
+            amount,
+            decimals,
+            memo: None,
+            signed_memo: false,
+            memo_program: spl_memo::id(),
+            create_recipient_ata: false,
+            rent_sponsor: None,
+            compute_unit_limit: None,
+            compute_unit_price: None,
+            references: Vec::new(),
+        }
+    }
+
+    pub fn memo(mut self, memo: Option<String>) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    /// Make the fee payer a signer of the memo instruction.
+    pub fn signed_memo(mut self, signed: bool) -> Self {
+        self.signed_memo = signed;
+        self
+    }
+
+    /// Program the memo instruction is addressed to, Memo v3 unless set.
+    pub fn memo_program(mut self, program_id: Pubkey) -> Self {
+        self.memo_program = program_id;
+        self
+    }
+
+    /// Program of the mint, the classic token program unless set. The ATAs are derived for it
+    /// and the transfer is addressed to it.
+    pub fn token_program(mut self, program: TokenProgram) -> Self {
+        self.token_program = program;
+        self
+    }
+
+    /// Prepend an idempotent create of the recipient's ATA, which is a no-op when it exists.
+    pub fn create_recipient_ata(mut self, create: bool) -> Self {
+        self.create_recipient_ata = create;
+        self
+    }
+
+    /// Fund a created recipient ATA from `sponsor`, which then signs the transaction too.
+    pub fn rent_sponsor(mut self, sponsor: Option<Pubkey>) -> Self {
+        self.rent_sponsor = sponsor;
+        self
+    }
+
+    /// Prepend a compute budget instruction setting the limit.
+    pub fn compute_unit_limit(mut self, limit: Option<u32>) -> Self {
+        self.compute_unit_limit = limit;
+        self
+    }
+
+    /// Prepend a compute budget instruction setting the priority fee, in micro-lamports per
+    /// compute unit.
+    pub fn compute_unit_price(mut self, micro_lamports: Option<u64>) -> Self {
+        self.compute_unit_price = micro_lamports;
+        self
+    }
+
+    /// Add Solana Pay `references` to the transfer, see `with_references`.
+    pub fn references(mut self, references: Vec<Pubkey>) -> Self {
+        self.references = references;
+        self
+    }
+
+    /// The rent sponsor when the message needs its signature: an ATA is created and the sponsor
+    /// isn't the owner.
+    pub fn sponsor_signer(&self) -> Option<Pubkey> {
+        self.rent_sponsor
+            .filter(|sponsor| self.create_recipient_ata && *sponsor != self.owner)
+    }
+
+    pub fn owner(&self) -> Pubkey {
+        self.owner
+    }
+
+    pub fn source_ata(&self) -> Pubkey {
+        self.token_program
+            .associated_address(&self.owner, &self.token_mint)
+    }
+
+    pub fn recipient_ata(&self) -> Pubkey {
+        self.token_program
+            .associated_address(&self.to, &self.token_mint)
+    }
+
+    pub fn instructions(&self) -> Result<Vec<Instruction>, Error> {
+        let mut instructions =
+            compute_budget_instructions(self.compute_unit_limit, self.compute_unit_price);
+        if self.create_recipient_ata {
+            instructions.push(create_associated_token_account_idempotent(
+                &self.sponsor_signer().unwrap_or(self.owner),
+                &self.to,
+                &self.token_mint,
+                &self.token_program.id(),
+            ));
+        }
+        let mut transfer = token_instruction::transfer_checked(
+            &self.token_program.id(),
+            &self.source_ata(),
+            &self.token_mint,
+            &self.recipient_ata(),
+            &self.owner,
+            &[],
+            self.amount.get(),
+            self.decimals.get(),
+        )?;
+        with_references(&mut transfer, &self.references);
+        let memo = self.memo.as_ref().map(|memo| {
+            let signer = self.signed_memo.then_some(&self.owner);
+            memo_instruction(memo, signer, &self.memo_program)
+        });
+        match self.references.is_empty() {
+            true => instructions.extend([transfer].into_iter().chain(memo)),
+            false => instructions.extend(memo.into_iter().chain([transfer])),
+        }
+        Ok(instructions)
+    }
+
+    pub fn build(&self) -> Result<Message, Error> {
+        Ok(Message::new(&self.instructions()?, Some(&self.owner)))
+    }
+
+    /// The accounts of the built message with their signer and writable flags.
+    pub fn account_keys(&self) -> Result<Vec<InstructionAccount>, Error> {
+        Ok(account_keys(&self.build()?))
+    }
+}
+
+/// One instruction of another program, paid for and signed by `fee_payer` alone. Programs that
+/// check a signature through the instructions sysvar get the Ed25519Program instruction
+/// proving it right before their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramCallBuilder {
+    fee_payer: Pubkey,
+    instruction: Instruction,
+    ed25519_verify: Option<(Pubkey, Signature, Vec<u8>)>,
+}
+
+impl ProgramCallBuilder {
+    /// Fails when `instruction` needs the signature of any account but the fee payer.
+    pub fn new(fee_payer: Pubkey, instruction: Instruction) -> Result<Self, Error> {
+        if let Some(account) = instruction
+            .accounts
+            .iter()
+            .find(|account| account.is_signer && account.pubkey != fee_payer)
+        {
+            return Err(Error::UnexpectedSigner(account.pubkey));
+        }
+        Ok(Self {
+            fee_payer,
+            instruction,
+            ed25519_verify: None,
+        })
+    }
+
+    /// Prepend an Ed25519Program instruction proving that `signer` signed `payload`.
+    pub fn ed25519_verify(
+        mut self,
+        signer: Pubkey,
+        signature: Signature,
+        payload: Vec<u8>,
+    ) -> Self {
+        self.ed25519_verify = Some((signer, signature, payload));
+        self
+    }
+
+    pub fn instructions(&self) -> Result<Vec<Instruction>, Error> {
+        let mut instructions = Vec::new();
+        if let Some((signer, signature, payload)) = &self.ed25519_verify {
+            instructions.push(ed25519_verify_instruction(signer, signature, payload)?);
+        }
+        instructions.push(self.instruction.clone());
+        Ok(instructions)
+    }
+
+    /// Fails when the signed transaction wouldn't fit into a packet.
+    pub fn build(&self) -> Result<Message, Error> {
+        let message = Message::new(&self.instructions()?, Some(&self.fee_payer));
+        let size = transaction_size(&message);
+        if size > PACKET_DATA_SIZE {
+            return Err(Error::InvalidPayload(format!(
+                "the transaction carrying it would be {} bytes, over the {} byte limit",
+                size, PACKET_DATA_SIZE
+            )));
+        }
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        compute_budget, ed25519_program,
+        instruction::{AccountMeta, Instruction},
+        message::Message,
+        pubkey::Pubkey,
+        signature::Signature,
+        system_instruction, system_program, sysvar,
+    };
+    use spl_associated_token_account::get_associated_token_address;
+
+    use crate::Error;
+    use crate::durable_nonce::DurableNonce;
+    use crate::ed25519_verify::ed25519_verify_instruction;
+    use crate::models::InstructionAccount;
+    use crate::spl_token_utils::TokenProgram;
+    use crate::transaction_builder::{
+        ProgramCallBuilder, SplTransferBuilder, TransferBuilder, resolve_memo_program,
+        transaction_size,
+    };
+    use crate::units::{Decimals, Lamports, RawTokenAmount};
+
+    fn decimals(decimals: u8) -> Decimals {
+        Decimals::new(decimals).unwrap()
+    }
+
+    fn account_metas(message: &Message, index: usize) -> Vec<AccountMeta> {
+        message.instructions[index]
+            .accounts
+            .iter()
+            .map(|&i| {
+                let i = i as usize;
+                AccountMeta {
+                    pubkey: message.account_keys[i],
+                    is_signer: message.is_signer(i),
+                    is_writable: message.is_writable(i),
+                }
+            })
+            .collect()
+    }
+
+    fn program_ids(message: &Message) -> Vec<Pubkey> {
+        message
+            .instructions
+            .iter()
+            .map(|ix| *ix.program_id(&message.account_keys))
+            .collect()
+    }
+
+    #[test]
+    fn test_sol_transfer_layout() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        for memo in [None, Some("hello".to_string())] {
+            let message = TransferBuilder::new(from, to, Lamports::new(42))
+                .memo(memo.clone())
+                .build();
+            assert_eq!(message.header.num_required_signatures, 1);
+            assert_eq!(message.account_keys[0], from);
+            assert_eq!(
+                account_metas(&message, 0),
+                [AccountMeta::new(from, true), AccountMeta::new(to, false)]
+            );
+            match memo {
+                None => assert_eq!(program_ids(&message), [system_program::id()]),
+                Some(memo) => {
+                    assert_eq!(
+                        program_ids(&message),
+                        [system_program::id(), spl_memo::id()]
+                    );
+                    assert!(account_metas(&message, 1).is_empty());
+                    assert_eq!(message.instructions[1].data, memo.as_bytes());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversize_transfer_splits() {
+        let limit = solana_sdk::packet::PACKET_DATA_SIZE;
+        let recipients: Vec<_> = (1..=20)
+            .map(|i| (Pubkey::new_unique(), Lamports::new(i)))
+            .collect();
+        let builder = recipients.iter().skip(1).fold(
+            TransferBuilder::new(Pubkey::new_unique(), recipients[0].0, recipients[0].1)
+                .memo(Some("m".repeat(400))),
+            |builder, &(to, lamports)| builder.recipient(to, lamports),
+        );
+        let oversize = builder.oversize().unwrap();
+        assert!(oversize.size > limit && oversize.size_without_memo <= limit);
+        assert_eq!(oversize.size, transaction_size(&builder.build()));
+
+        let transfers = builder.split().unwrap();
+        assert!(transfers.len() > 1);
+        assert_eq!(transfers[0].recipients().len(), oversize.max_recipients);
+        for transfer in &transfers {
+            assert!(transfer.oversize().is_none());
+            assert_eq!(
+                transfer.build().instructions.len(),
+                transfer.recipients().len() + 1
+            );
+        }
+        let paid: Vec<_> = transfers
+            .iter()
+            .flat_map(|transfer| transfer.recipients().to_vec())
+            .collect();
+        assert_eq!(paid, recipients);
+
+        // A memo with no room for anyone
+        let alone = TransferBuilder::new(Pubkey::new_unique(), recipients[0].0, Lamports::new(1))
+            .memo(Some("m".repeat(limit)));
+        assert_eq!(alone.oversize().unwrap().max_recipients, 0);
+        assert!(matches!(
+            alone.split(),
+            Err(Error::TransactionTooLarge {
+                splittable: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_spl_transfer_layout() {
+        let owner = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let source_ata = get_associated_token_address(&owner, &mint);
+        let recipient_ata = get_associated_token_address(&to, &mint);
+        for create_ata in [false, true] {
+            for memo in [None, Some("hello".to_string())] {
+                let builder =
+                    SplTransferBuilder::new(owner, to, mint, RawTokenAmount::new(7), decimals(6))
+                        .memo(memo.clone())
+                        .create_recipient_ata(create_ata);
+                let message = builder.build().unwrap();
+                assert_eq!(message.header.num_required_signatures, 1);
+                assert_eq!(message.account_keys[0], owner);
+
+                let mut expected_programs = Vec::new();
+                if create_ata {
+                    expected_programs.push(spl_associated_token_account::id());
+                }
+                expected_programs.push(spl_token::id());
+                if memo.is_some() {
+                    expected_programs.push(spl_memo::id());
+                }
+                assert_eq!(program_ids(&message), expected_programs);
+
+                let transfer_index = create_ata as usize;
+                if create_ata {
+                    assert_eq!(
+                        account_metas(&message, 0),
+                        [
+                            AccountMeta::new(owner, true),
+                            AccountMeta::new(recipient_ata, false),
+                            AccountMeta::new_readonly(to, false),
+                            AccountMeta::new_readonly(mint, false),
+                            AccountMeta::new_readonly(system_program::id(), false),
+                            AccountMeta::new_readonly(spl_token::id(), false),
+                        ]
+                    );
+                }
+                assert_eq!(
+                    account_metas(&message, transfer_index),
+                    [
+                        AccountMeta::new(source_ata, false),
+                        AccountMeta::new_readonly(mint, false),
+                        AccountMeta::new(recipient_ata, false),
+                        AccountMeta::new(owner, true),
+                    ]
+                );
+                // TransferChecked: tag 12, amount, decimals
+                let mut data = vec![12];
+                data.extend_from_slice(&7u64.to_le_bytes());
+                data.push(6);
+                assert_eq!(message.instructions[transfer_index].data, data);
+                if let Some(memo) = memo {
+                    assert_eq!(
+                        message.instructions[transfer_index + 1].data,
+                        memo.as_bytes()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_token_2022_transfer_layout() {
+        let owner = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let builder = SplTransferBuilder::new(owner, to, mint, RawTokenAmount::new(7), decimals(6))
+            .token_program(TokenProgram::Token2022)
+            .create_recipient_ata(true);
+        // The ATAs of the two programs are different accounts
+        let source_ata = builder.source_ata();
+        let recipient_ata = builder.recipient_ata();
+        assert_ne!(source_ata, get_associated_token_address(&owner, &mint));
+        let message = builder.build().unwrap();
+        assert_eq!(
+            program_ids(&message),
+            [spl_associated_token_account::id(), spl_token_2022::id()]
+        );
+        assert_eq!(
+            account_metas(&message, 0)[5],
+            AccountMeta::new_readonly(spl_token_2022::id(), false)
+        );
+        assert_eq!(
+            account_metas(&message, 1),
+            [
+                AccountMeta::new(source_ata, false),
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new(recipient_ata, false),
+                AccountMeta::new(owner, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rent_sponsor_funds_ata() {
+        let owner = Pubkey::new_unique();
+        let sponsor = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let recipient_ata = get_associated_token_address(&to, &mint);
+        let builder = SplTransferBuilder::new(owner, to, mint, RawTokenAmount::new(7), decimals(6))
+            .create_recipient_ata(true)
+            .rent_sponsor(Some(sponsor));
+        assert_eq!(builder.sponsor_signer(), Some(sponsor));
+        let message = builder.build().unwrap();
+        // The owner stays the fee payer, the sponsor is the second signer
+        assert_eq!(message.header.num_required_signatures, 2);
+        assert_eq!(message.header.num_readonly_signed_accounts, 0);
+        assert_eq!(message.account_keys[..2], [owner, sponsor]);
+        assert_eq!(
+            account_metas(&message, 0),
+            [
+                AccountMeta::new(sponsor, true),
+                AccountMeta::new(recipient_ata, false),
+                AccountMeta::new_readonly(to, false),
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ]
+        );
+        assert_eq!(account_metas(&message, 1)[3], AccountMeta::new(owner, true));
+
+        // Without an ATA to create, or sponsored by the owner, there is nothing to sign for
+        for builder in [
+            builder.clone().create_recipient_ata(false),
+            builder.rent_sponsor(Some(owner)),
+        ] {
+            assert_eq!(builder.sponsor_signer(), None);
+            let message = builder.build().unwrap();
+            assert_eq!(message.header.num_required_signatures, 1);
+            assert!(!message.account_keys.contains(&sponsor));
+        }
+    }
+
+    #[test]
+    fn test_signed_memo() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let memo = Some("from the vault".to_string());
+
+        let sol = TransferBuilder::new(from, to, Lamports::new(1_000)).memo(memo.clone());
+        let spl = SplTransferBuilder::new(from, to, mint, RawTokenAmount::new(1_000), decimals(6))
+            .memo(memo.clone());
+        let unsigned = [sol.clone().build(), spl.clone().build().unwrap()];
+        let signed = [
+            sol.signed_memo(true).build(),
+            spl.signed_memo(true).build().unwrap(),
+        ];
+        for (unsigned, signed) in unsigned.iter().zip(&signed) {
+            let memo_index = signed.instructions.len() - 1;
+            assert!(account_metas(unsigned, memo_index).is_empty());
+            assert_eq!(
+                account_metas(signed, memo_index),
+                [AccountMeta::new(from, true)]
+            );
+            // The payer already signs, so no extra signature is needed
+            assert_eq!(signed.header.num_required_signatures, 1);
+            assert_eq!(
+                signed.instructions[memo_index].data,
+                unsigned.instructions[memo_index].data
+            );
+        }
+        // Without a memo the flag changes nothing
+        assert_eq!(
+            TransferBuilder::new(from, to, Lamports::new(1_000))
+                .signed_memo(true)
+                .build(),
+            TransferBuilder::new(from, to, Lamports::new(1_000)).build()
+        );
+    }
+
+    #[test]
+    fn test_memo_program() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let memo = Some("legacy indexer".to_string());
+        let v1 = resolve_memo_program(Some("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo"), false)
+            .unwrap();
+        assert_eq!(v1, spl_memo::v1::id());
+
+        let sol = TransferBuilder::new(from, to, Lamports::new(1_000)).memo(memo.clone());
+        let spl = SplTransferBuilder::new(from, to, mint, RawTokenAmount::new(1_000), decimals(6))
+            .memo(memo.clone());
+        let v3 = [sol.clone().build(), spl.clone().build().unwrap()];
+        let legacy = [
+            sol.memo_program(v1).signed_memo(true).build(),
+            spl.memo_program(v1).signed_memo(true).build().unwrap(),
+        ];
+        for (v3, legacy) in v3.iter().zip(&legacy) {
+            let memo_index = v3.instructions.len() - 1;
+            assert_eq!(program_ids(v3)[memo_index], spl_memo::id());
+            assert_eq!(program_ids(legacy)[memo_index], v1);
+            assert_eq!(
+                account_metas(legacy, memo_index),
+                [AccountMeta::new(from, true)]
+            );
+            assert_eq!(
+                legacy.instructions[memo_index].data,
+                v3.instructions[memo_index].data
+            );
+        }
+
+        assert_eq!(resolve_memo_program(None, false).unwrap(), spl_memo::id());
+        let custom = Pubkey::new_unique().to_string();
+        assert!(resolve_memo_program(Some(&custom), false).is_err());
+        assert!(resolve_memo_program(Some(&custom), true).is_ok());
+        assert!(resolve_memo_program(Some("not a pubkey"), true).is_err());
+    }
+
+    #[test]
+    fn test_compute_unit_limit_comes_first() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut expected_data = vec![2];
+        expected_data.extend_from_slice(&150_000u32.to_le_bytes());
+
+        let sol = TransferBuilder::new(from, to, Lamports::new(1_000)).memo(Some("cu".to_string()));
+        let spl = SplTransferBuilder::new(from, to, mint, RawTokenAmount::new(1_000), decimals(6))
+            .create_recipient_ata(true);
+        let plain = [sol.instructions(), spl.instructions().unwrap()];
+        let limited = [
+            sol.compute_unit_limit(Some(150_000)).instructions(),
+            spl.compute_unit_limit(Some(150_000))
+                .instructions()
+                .unwrap(),
+        ];
+        for (plain, limited) in plain.iter().zip(&limited) {
+            assert_eq!(limited[0].program_id, compute_budget::id());
+            assert!(limited[0].accounts.is_empty());
+            assert_eq!(limited[0].data, expected_data);
+            assert_eq!(&limited[1..], &plain[..]);
+        }
+    }
+
+    #[test]
+    fn test_compute_unit_price_follows_the_limit() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut price_data = vec![3];
+        price_data.extend_from_slice(&25_000u64.to_le_bytes());
+
+        let sol = TransferBuilder::new(from, to, Lamports::new(1_000));
+        let spl = SplTransferBuilder::new(from, to, mint, RawTokenAmount::new(1_000), decimals(6));
+        let plain = [sol.instructions(), spl.instructions().unwrap()];
+        let priced = [
+            sol.clone().compute_unit_price(Some(25_000)).instructions(),
+            spl.clone()
+                .compute_unit_price(Some(25_000))
+                .instructions()
+                .unwrap(),
+        ];
+        for (plain, priced) in plain.iter().zip(&priced) {
+            assert_eq!(priced[0].program_id, compute_budget::id());
+            assert_eq!(priced[0].data, price_data);
+            assert_eq!(&priced[1..], &plain[..]);
+        }
+
+        let both = sol
+            .compute_unit_limit(Some(150_000))
+            .compute_unit_price(Some(25_000))
+            .instructions();
+        assert_eq!(both[0].data[0], 2);
+        assert_eq!(both[1].data, price_data);
+    }
+
+    #[test]
+    fn test_durable_nonce_is_advanced_first() {
+        let from = Pubkey::new_unique();
+        let durable_nonce = DurableNonce {
+            account: Pubkey::new_unique(),
+            authority: from,
+        };
+        let transfer = TransferBuilder::new(from, Pubkey::new_unique(), Lamports::new(1_000))
+            .compute_unit_price(Some(25_000));
+        let plain = transfer.instructions();
+        let nonced = transfer.durable_nonce(Some(durable_nonce)).instructions();
+        assert_eq!(
+            nonced[0],
+            system_instruction::advance_nonce_account(&durable_nonce.account, &from)
+        );
+        assert_eq!(&nonced[1..], &plain[..]);
+    }
+
+    #[test]
+    fn test_same_inputs_same_message() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let memo = Some("invoice 17".to_string());
+
+        let sol = || TransferBuilder::new(from, to, Lamports::new(1_000)).memo(memo.clone());
+        assert_eq!(sol().build().serialize(), sol().build().serialize());
+        assert_ne!(
+            sol().build().serialize(),
+            sol().memo(None).build().serialize()
+        );
+
+        let spl = || {
+            SplTransferBuilder::new(from, to, mint, RawTokenAmount::new(1_000), decimals(6))
+                .memo(memo.clone())
+                .create_recipient_ata(true)
+        };
+        assert_eq!(
+            spl().build().unwrap().serialize(),
+            spl().build().unwrap().serialize()
+        );
+        assert_ne!(
+            spl().build().unwrap().serialize(),
+            spl()
+                .create_recipient_ata(false)
+                .build()
+                .unwrap()
+                .serialize()
+        );
+    }
+
+    #[test]
+    fn test_account_keys_flags() {
+        let flags = |keys: Vec<InstructionAccount>| -> Vec<(String, bool, bool)> {
+            keys.into_iter()
+                .map(|key| (key.pubkey, key.is_signer, key.is_writable))
+                .collect()
+        };
+        let (from, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let sol = TransferBuilder::new(from, to, Lamports::new(42));
+        assert_eq!(
+            flags(sol.account_keys()),
+            [
+                (from.to_string(), true, true),
+                (to.to_string(), false, true),
+                (system_program::id().to_string(), false, false),
+            ]
+        );
+        // A signed memo adds no writable account, its program is only called
+        let memo = sol
+            .memo(Some("invoice 7".to_string()))
+            .signed_memo(true)
+            .compute_unit_limit(Some(1_000));
+        let keys = memo.account_keys();
+        assert_eq!(keys.len(), 5);
+        let writable: Vec<_> = keys.iter().filter(|key| key.is_writable).collect();
+        assert_eq!(writable.len(), 2);
+        for program in [spl_memo::id(), compute_budget::id()] {
+            let key = keys.iter().find(|key| key.pubkey == program.to_string());
+            assert!(key.is_some_and(|key| !key.is_signer && !key.is_writable));
+        }
+
+        let (owner, mint, sponsor) = (from, Pubkey::new_unique(), Pubkey::new_unique());
+        let spl = SplTransferBuilder::new(owner, to, mint, RawTokenAmount::new(5), decimals(6))
+            .memo(Some("ref".to_string()))
+            .create_recipient_ata(true)
+            .rent_sponsor(Some(sponsor));
+        let keys = flags(spl.account_keys().unwrap());
+        let flags_of = |key: &Pubkey| {
+            keys.iter()
+                .find(|(pubkey, _, _)| *pubkey == key.to_string())
+                .map(|&(_, is_signer, is_writable)| (is_signer, is_writable))
+        };
+        assert_eq!(keys[0].0, owner.to_string());
+        assert_eq!(flags_of(&owner), Some((true, true)));
+        // The sponsor funds the recipient account, the recipient itself is only read
+        assert_eq!(flags_of(&sponsor), Some((true, true)));
+        assert_eq!(flags_of(&spl.source_ata()), Some((false, true)));
+        assert_eq!(flags_of(&spl.recipient_ata()), Some((false, true)));
+        assert_eq!(flags_of(&to), Some((false, false)));
+        assert_eq!(flags_of(&mint), Some((false, false)));
+        assert_eq!(flags_of(&spl_memo::id()), Some((false, false)));
+    }
+
+    #[test]
+    fn test_solana_pay_references() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let references = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        let memo = Some("OrderId12345".to_string());
+        let readonly: Vec<_> = references
+            .iter()
+            .map(|reference| AccountMeta::new_readonly(*reference, false))
+            .collect();
+
+        // Appended in order after the transfer's own accounts, the memo moves before the transfer
+        let sol = TransferBuilder::new(from, to, Lamports::new(1_000))
+            .memo(memo.clone())
+            .references(references.clone())
+            .build();
+        assert_eq!(program_ids(&sol), [spl_memo::id(), system_program::id()]);
+        assert_eq!(account_metas(&sol, 1)[2..], readonly);
+
+        let spl = SplTransferBuilder::new(from, to, mint, RawTokenAmount::new(7), decimals(6))
+            .memo(memo.clone())
+            .create_recipient_ata(true)
+            .references(references.clone())
+            .build()
+            .unwrap();
+        assert_eq!(
+            program_ids(&spl),
+            [
+                spl_associated_token_account::id(),
+                spl_memo::id(),
+                spl_token::id()
+            ]
+        );
+        assert_eq!(account_metas(&spl, 2)[4..], readonly);
+
+        // Without references the layout is the one it always was
+        let plain = TransferBuilder::new(from, to, Lamports::new(1_000))
+            .memo(memo)
+            .references(vec![])
+            .build();
+        assert_eq!(program_ids(&plain), [system_program::id(), spl_memo::id()]);
+    }
+
+    #[test]
+    fn test_program_call_with_ed25519_verify() {
+        let (fee_payer, signer, program, state) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let instruction = Instruction {
+            program_id: program,
+            accounts: vec![
+                AccountMeta::new(state, false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+                AccountMeta::new(fee_payer, true),
+            ],
+            data: vec![1, 2, 3],
+        };
+        let signature = Signature::new(&[9; 64]);
+        let payload = b"claim 42".to_vec();
+        let message = ProgramCallBuilder::new(fee_payer, instruction.clone())
+            .unwrap()
+            .ed25519_verify(signer, signature, payload.clone())
+            .build()
+            .unwrap();
+
+        // The proof right before the call, only the fee payer signs
+        assert_eq!(program_ids(&message), [ed25519_program::id(), program]);
+        assert_eq!(message.header.num_required_signatures, 1);
+        assert_eq!(message.account_keys[0], fee_payer);
+        assert!(account_metas(&message, 0).is_empty());
+        assert_eq!(
+            message.instructions[0].data,
+            ed25519_verify_instruction(&signer, &signature, &payload)
+                .unwrap()
+                .data
+        );
+        assert_eq!(account_metas(&message, 1), instruction.accounts);
+        assert_eq!(message.instructions[1].data, instruction.data);
+        // The signer of the payload isn't an account of the message
+        assert!(!message.account_keys.contains(&signer));
+
+        // Without a proof it's the call alone
+        let message = ProgramCallBuilder::new(fee_payer, instruction.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(program_ids(&message), [program]);
+
+        let mut needs_state = instruction.clone();
+        needs_state.accounts[0].is_signer = true;
+        assert!(matches!(
+            ProgramCallBuilder::new(fee_payer, needs_state),
+            Err(Error::UnexpectedSigner(account)) if account == state
+        ));
+
+        let error = ProgramCallBuilder::new(fee_payer, instruction)
+            .unwrap()
+            .ed25519_verify(signer, signature, vec![0; 1200])
+            .build()
+            .unwrap_err();
+        assert_eq!(error.error_code(), "INVALID_PAYLOAD");
+    }
+}
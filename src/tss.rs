@@ -1,25 +1,40 @@
 #![allow(non_snake_case)]
 
-use crate::spl_token_utils::{create_spl_token_transaction, get_token_amount_with_decimals};
+use crate::transaction_builder::{SplTransferBuilder, TransferBuilder};
 use curv::elliptic::curves::{Ed25519, Point, Scalar};
 use multi_party_eddsa::protocols::ExpandedKeyPair;
 use multi_party_eddsa::protocols::musig2::{self, PrivatePartialNonces, PublicPartialNonces};
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::program_utils::limited_deserialize;
 use solana_sdk::signature::{Keypair, Signature, Signer, SignerError};
-use solana_sdk::{hash::Hash, pubkey::Pubkey, transaction::Transaction};
+use solana_sdk::{hash::Hash, message::Message, pubkey::Pubkey, transaction::Transaction};
 
 use crate::serialization::{
     AggMessage1, Error as DeserializationError, PartialSignature, SecretAggStepOne,
+    ThresholdKeyShare,
 };
 
 use crate::staking::{
-    create_deactivate_stake_transaction, create_stake_account_transaction,
-    create_withdraw_stake_transaction,
+    NewStake, StakeWithdrawal, create_deactivate_stake_transaction,
+    create_stake_account_transaction, create_withdraw_stake_transaction,
 };
 
-use crate::{Error, create_unsigned_transaction};
+use crate::Error;
+use crate::message_review::CompiledMessage;
+use crate::policy::WritablePolicy;
+use crate::threshold::signing_key;
+use crate::units::Lamports;
+
+/// Fewest parties `key_agg` accepts. MuSig2 weights even a single key by its coefficient, so
+/// one "party" would sign for a key that isn't its own, it's rejected instead.
+pub const MIN_KEYS: usize = 2;
 
 /// Create the aggregate public key, pass key=None if you don't care about the coefficient
+#[tracing::instrument(name = "tss.key_agg", skip_all)]
 pub fn key_agg(keys: Vec<Pubkey>, key: Option<Pubkey>) -> Result<musig2::PublicKeyAgg, Error> {
+    if keys.len() < MIN_KEYS {
+        return Err(Error::TooFewKeys(keys.len()));
+    }
     let convert_keys = |k: Pubkey| {
         Point::from_bytes(&k.to_bytes()).map_err(|e| Error::DeserializationFailed {
             error: DeserializationError::InvalidPoint(e),
@@ -36,7 +51,90 @@ pub fn key_agg(keys: Vec<Pubkey>, key: Option<Pubkey>) -> Result<musig2::PublicK
     musig2::PublicKeyAgg::key_aggregation_n(keys, &key).ok_or(Error::KeyPairIsNotInKeys)
 }
 
+/// Index in `keys` of the party `key_agg` should compute the coefficient for, named by key,
+/// by index or both. `None` when no party is designated.
+pub fn party_index(
+    keys: &[Pubkey],
+    party_key: Option<&Pubkey>,
+    party_index: Option<usize>,
+) -> Result<Option<usize>, Error> {
+    let by_key = party_key
+        .map(|key| {
+            keys.iter()
+                .position(|k| k == key)
+                .ok_or_else(|| Error::InvalidParty(format!("party_key {} is not one of keys", key)))
+        })
+        .transpose()?;
+    if let Some(index) = party_index {
+        if index >= keys.len() {
+            return Err(Error::InvalidParty(format!(
+                "party_index {} is out of range for {} keys",
+                index,
+                keys.len()
+            )));
+        }
+        if by_key.is_some_and(|i| i != index) {
+            return Err(Error::InvalidParty(format!(
+                "party_key is keys[{}], not keys[{}]",
+                by_key.unwrap_or_default(),
+                index
+            )));
+        }
+    }
+    Ok(by_key.or(party_index))
+}
+
+/// The aggregated public key of `keys` as a Solana address
+pub fn aggregated_pubkey(keys: Vec<Pubkey>) -> Result<Pubkey, Error> {
+    let aggkey = key_agg(keys, None)?;
+    let bytes = aggkey.agg_public_key.to_bytes(true);
+    Ok(Pubkey::try_from(&*bytes).expect("compressed ed25519 points are 32 bytes"))
+}
+
+/// The message of a TSS SOL transfer, exactly as every party signs it. The payer of `transfer`
+/// is the aggregated pubkey.
+pub fn transfer_message(transfer: &TransferBuilder, recent_block_hash: Hash) -> Message {
+    let mut message = transfer.build();
+    message.recent_blockhash = recent_block_hash;
+    message
+}
+
+/// Fail with `TransactionTooLarge` when the TSS transfer doesn't fit into one transaction.
+/// It is never split, every message needs its own signing ceremony.
+pub fn check_transfer_size(transfer: &TransferBuilder) -> Result<(), Error> {
+    match transfer.oversize() {
+        Some(oversize) => Err(Error::TransactionTooLarge {
+            oversize,
+            splittable: false,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The message of a TSS SPL transfer, exactly as every party signs it. The aggregated pubkey
+/// owns the tokens of `transfer` and pays. The recipient ATA is always created idempotently
+/// so both steps build the same message without looking anything up on chain, its rent comes
+/// from the rent sponsor when set, which then signs second.
+pub fn spl_transfer_message(
+    transfer: &SplTransferBuilder,
+    recent_block_hash: Hash,
+) -> Result<Message, Error> {
+    let mut message = transfer
+        .clone()
+        .create_recipient_ata(true)
+        .build()
+        .map_err(|e| {
+            Error::TransactionCreationFailed(format!(
+                "SPL token transaction creation failed: {:?}",
+                e
+            ))
+        })?;
+    message.recent_blockhash = recent_block_hash;
+    Ok(message)
+}
+
 /// Generate Message1 which contains nonce, public nonce, and commitment to nonces
+#[tracing::instrument(name = "tss.step_one", skip_all)]
 pub fn step_one(keypair: Keypair) -> (AggMessage1, SecretAggStepOne) {
     let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
     // we don't really need to pass a message here.
@@ -54,81 +152,101 @@ pub fn step_one(keypair: Keypair) -> (AggMessage1, SecretAggStepOne) {
     )
 }
 
-#[allow(clippy::too_many_arguments)]
+/// This party's partial signature of the transfer, with the compiled message it signed. Every
+/// step two returns the message that way, for the audit log.
+#[tracing::instrument(name = "tss.step_two", skip_all)]
 pub fn step_two(
     keypair: Keypair,
-    amount: f64,
-    to: Pubkey,
-    memo: Option<String>,
+    transfer: &TransferBuilder,
     recent_block_hash: Hash,
     keys: Vec<Pubkey>,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
-) -> Result<PartialSignature, Error> {
-    let other_nonces: Vec<_> = first_messages
-        .into_iter()
-        .map(|msg1| msg1.public_nonces.R)
-        .collect();
-
+) -> Result<(PartialSignature, CompiledMessage), Error> {
     // Generate the aggregate key together with the coefficient of the current keypair
     let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
-    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
     let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let signer = PartialSigner::new(aggkey, extended_kepair, first_messages, secret_state);
+    sign_transfer(&signer, transfer, recent_block_hash)
+}
+
+/// `step_two` by one of the `signers` of a threshold key, with its `key_share` instead of its
+/// own key. `first_messages` are those of the other signers.
+#[tracing::instrument(name = "tss.threshold_step_two", skip_all)]
+pub fn threshold_step_two(
+    keypair: Keypair,
+    transfer: &TransferBuilder,
+    recent_block_hash: Hash,
+    key_share: ThresholdKeyShare,
+    signers: &[usize],
+    first_messages: Vec<AggMessage1>,
+    secret_state: SecretAggStepOne,
+) -> Result<(PartialSignature, CompiledMessage), Error> {
+    let (aggkey, extended_kepair) = signing_key(&keypair, &key_share, signers);
+    let signer = PartialSigner::new(aggkey, extended_kepair, first_messages, secret_state);
+    sign_transfer(&signer, transfer, recent_block_hash)
+}
+
+/// A transfer paid by another key than the one the parties aggregate would have them sign for
+/// a key that isn't theirs, it's a bug of the caller rather than of the request.
+fn check_payer(payer: Pubkey, aggpubkey: Pubkey) -> Result<(), Error> {
+    if payer != aggpubkey {
+        return Err(Error::Internal(format!(
+            "the transfer is paid by {}, not by the aggregated key {}",
+            payer, aggpubkey
+        )));
+    }
+    Ok(())
+}
+
+fn sign_transfer(
+    signer: &PartialSigner,
+    transfer: &TransferBuilder,
+    recent_block_hash: Hash,
+) -> Result<(PartialSignature, CompiledMessage), Error> {
+    check_payer(transfer.payer(), signer.pubkey())?;
+    check_transfer_size(transfer)?;
 
     // Create the unsigned transaction
-    let mut tx = create_unsigned_transaction(amount, &to, memo, &aggpubkey);
+    let mut tx = Transaction::new_unsigned(transfer_message(transfer, recent_block_hash));
 
-    let signer = PartialSigner {
-        signer_private_nonce: secret_state.private_nonces,
-        signer_public_nonce: secret_state.public_nonces,
-        other_nonces,
-        extended_kepair,
-        aggregated_pubkey: aggkey,
-    };
     // Sign the transaction using a custom `PartialSigner`, this is required to comply with Solana's API.
-    tx.sign(&[&signer], recent_block_hash);
+    tx.sign(&[signer], recent_block_hash);
     let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
+    Ok((
+        PartialSignature(sig),
+        CompiledMessage::new(tx.message_data()),
+    ))
 }
 
-pub fn sign_and_broadcast(
-    amount: f64,
-    to: Pubkey,
-    memo: Option<String>,
-    recent_block_hash: Hash,
-    keys: Vec<Pubkey>,
-    signatures: Vec<PartialSignature>,
-) -> Result<Transaction, Error> {
-    let aggkey = key_agg(keys, None)?;
-    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
-
-    // Make sure all the `R`s are the same
-    if !signatures[1..]
+/// The signature of the aggregated key, added up from every party's partial signature. They
+/// all have to commit to the same `R`, or they signed different messages.
+fn aggregate(signatures: &[PartialSignature]) -> Result<Signature, Error> {
+    let Some((first, others)) = signatures.split_first() else {
+        return Err(Error::InvalidSignature);
+    };
+    if others
         .iter()
-        .map(|s| &s.0.as_ref()[..32])
-        .all(|s| s == &signatures[0].0.as_ref()[..32])
+        .any(|s| s.0.as_ref()[..32] != first.0.as_ref()[..32])
     {
         return Err(Error::MismatchMessages);
     }
-    let deserialize_R = |s| {
-        Point::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: DeserializationError::InvalidPoint(e),
-            field_name: "signatures",
-        })
-    };
     let deserialize_s = |s| {
         Scalar::from_bytes(s).map_err(|e| Error::DeserializationFailed {
             error: DeserializationError::InvalidScalar(e),
             field_name: "signatures",
         })
     };
-
     let first_sig = musig2::PartialSignature {
-        R: deserialize_R(&signatures[0].0.as_ref()[..32])?,
-        my_partial_s: deserialize_s(&signatures[0].0.as_ref()[32..])?,
+        R: Point::from_bytes(&first.0.as_ref()[..32]).map_err(|e| {
+            Error::DeserializationFailed {
+                error: DeserializationError::InvalidPoint(e),
+                field_name: "signatures",
+            }
+        })?,
+        my_partial_s: deserialize_s(&first.0.as_ref()[32..])?,
     };
-
-    let partial_sigs: Vec<_> = signatures[1..]
+    let partial_sigs: Vec<_> = others
         .iter()
         .map(|s| deserialize_s(&s.0.as_ref()[32..]))
         .collect::<Result<_, _>>()?;
@@ -139,12 +257,21 @@ pub fn sign_and_broadcast(
     let mut sig_bytes = [0u8; 64];
     sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
     sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
-    let sig = Signature::new(&sig_bytes);
+    Ok(Signature::new(&sig_bytes))
+}
 
-    // Create the same transaction again
-    let mut tx = create_unsigned_transaction(amount, &to, memo, &aggpubkey);
-    // Insert the recent_block_hash and the signature to the right places
-    tx.message.recent_blockhash = recent_block_hash;
+/// The transfer signed by the aggregated key that pays it, the key of all parties or the
+/// group key of a threshold ceremony.
+#[tracing::instrument(name = "tss.aggregate_signatures", skip_all)]
+pub fn sign_and_broadcast(
+    transfer: &TransferBuilder,
+    recent_block_hash: Hash,
+    signatures: Vec<PartialSignature>,
+) -> Result<Transaction, Error> {
+    let sig = aggregate(&signatures)?;
+
+    // Create the same transaction again, then insert the signature
+    let mut tx = Transaction::new_unsigned(transfer_message(transfer, recent_block_hash));
     assert_eq!(tx.signatures.len(), 1);
     tx.signatures[0] = sig;
 
@@ -156,19 +283,15 @@ pub fn sign_and_broadcast(
 }
 
 /// SPL Token Step Two - generates partial signature for SPL token transfer
-#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "tss.spl_step_two", skip_all)]
 pub fn spl_step_two(
     keypair: Keypair,
-    amount: f64,
-    to: Pubkey,
-    token_mint: Pubkey,
-    decimals: u8,
-    memo: Option<String>,
+    transfer: &SplTransferBuilder,
     recent_block_hash: Hash,
     keys: Vec<Pubkey>,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
-) -> Result<PartialSignature, Error> {
+) -> Result<(PartialSignature, CompiledMessage), Error> {
     let other_nonces: Vec<_> = first_messages
         .into_iter()
         .map(|msg1| msg1.public_nonces.R)
@@ -178,23 +301,10 @@ pub fn spl_step_two(
     let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
     let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
-
-    // Convert amount to proper token units
-    let token_amount = get_token_amount_with_decimals(amount, decimals);
+    check_payer(transfer.owner(), aggpubkey)?;
 
     // Create the unsigned SPL token transaction
-    let mut tx = create_spl_token_transaction(
-        token_amount,
-        &aggpubkey, // from (the aggregated pubkey owns the tokens)
-        &to,
-        &token_mint,
-        &aggpubkey, // payer (same as from in this case)
-        memo,
-        decimals,
-    )
-    .map_err(|e| {
-        Error::TransactionCreationFailed(format!("SPL token transaction creation failed: {:?}", e))
-    })?;
+    let mut tx = Transaction::new_unsigned(spl_transfer_message(transfer, recent_block_hash)?);
 
     let signer = PartialSigner {
         signer_private_nonce: secret_state.private_nonces,
@@ -204,87 +314,33 @@ pub fn spl_step_two(
         aggregated_pubkey: aggkey,
     };
 
-    // Sign the transaction using the partial signer
-    tx.sign(&[&signer], recent_block_hash);
+    // Sign the transaction using the partial signer, a rent sponsor signs on its own
+    tx.partial_sign(&[&signer], recent_block_hash);
     let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
+    Ok((
+        PartialSignature(sig),
+        CompiledMessage::new(tx.message_data()),
+    ))
 }
 
 /// SPL Token Sign and Broadcast - aggregates signatures and broadcasts SPL token transaction
+#[tracing::instrument(name = "tss.spl_aggregate_signatures", skip_all)]
 pub fn spl_sign_and_broadcast(
-    amount: f64,
-    to: Pubkey,
-    token_mint: Pubkey,
-    decimals: u8,
-    memo: Option<String>,
+    transfer: &SplTransferBuilder,
+    sponsor_signature: Option<Signature>,
     recent_block_hash: Hash,
-    keys: Vec<Pubkey>,
     signatures: Vec<PartialSignature>,
 ) -> Result<Transaction, Error> {
-    let aggkey = key_agg(keys, None)?;
-    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
-
-    // Make sure all the `R`s are the same
-    if !signatures[1..]
-        .iter()
-        .map(|s| &s.0.as_ref()[..32])
-        .all(|s| s == &signatures[0].0.as_ref()[..32])
-    {
-        return Err(Error::MismatchMessages);
-    }
-
-    let deserialize_R = |s| {
-        Point::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: DeserializationError::InvalidPoint(e),
-            field_name: "signatures",
-        })
-    };
-    let deserialize_s = |s| {
-        Scalar::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: DeserializationError::InvalidScalar(e),
-            field_name: "signatures",
-        })
-    };
-
-    let first_sig = musig2::PartialSignature {
-        R: deserialize_R(&signatures[0].0.as_ref()[..32])?,
-        my_partial_s: deserialize_s(&signatures[0].0.as_ref()[32..])?,
-    };
-
-    let partial_sigs: Vec<_> = signatures[1..]
-        .iter()
-        .map(|s| deserialize_s(&s.0.as_ref()[32..]))
-        .collect::<Result<_, _>>()?;
-
-    // Add the signatures up
-    let full_sig = musig2::aggregate_partial_signatures(&first_sig, &partial_sigs);
-
-    let mut sig_bytes = [0u8; 64];
-    sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
-    sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
-    let sig = Signature::new(&sig_bytes);
+    let sig = aggregate(&signatures)?;
 
-    // Convert amount to proper token units
-    let token_amount = get_token_amount_with_decimals(amount, decimals);
-
-    // Create the same SPL token transaction again
-    let mut tx = create_spl_token_transaction(
-        token_amount,
-        &aggpubkey,
-        &to,
-        &token_mint,
-        &aggpubkey,
-        memo,
-        decimals,
-    )
-    .map_err(|e| {
-        Error::TransactionCreationFailed(format!("SPL token transaction creation failed: {:?}", e))
-    })?;
-
-    // Insert the recent_block_hash and the signature to the right places
-    tx.message.recent_blockhash = recent_block_hash;
-    assert_eq!(tx.signatures.len(), 1);
+    // Create the same SPL token transaction again, then insert the signature
+    let mut tx = Transaction::new_unsigned(spl_transfer_message(transfer, recent_block_hash)?);
     tx.signatures[0] = sig;
+    // A rent sponsor other than the owner is the second signer
+    if tx.signatures.len() > 1 {
+        let sponsor = tx.message.account_keys[1];
+        tx.signatures[1] = sponsor_signature.ok_or(Error::RentSponsorSignatureMissing(sponsor))?;
+    }
 
     // Make sure the resulting transaction is actually valid.
     if tx.verify().is_err() {
@@ -294,20 +350,16 @@ pub fn spl_sign_and_broadcast(
 }
 
 /// Step two for staking - creates partial signature for stake transaction
-///
-///
-
-#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "tss.stake_step_two", skip_all)]
 pub fn stake_step_two(
     keypair: Keypair,
-    stake_amount: u64,
-    seed: String,
-    validator_vote_accont: Pubkey,
+    stake: &NewStake,
     recent_block_hash: Hash,
     keys: Vec<Pubkey>,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
-) -> Result<PartialSignature, Error> {
+    policy: &WritablePolicy,
+) -> Result<(PartialSignature, CompiledMessage), Error> {
     let other_nonces: Vec<_> = first_messages
         .into_iter()
         .map(|msg1| msg1.public_nonces.R)
@@ -319,8 +371,12 @@ pub fn stake_step_two(
     let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
 
     //creating unsigned transaction
-    let mut tx =
-        create_stake_account_transaction(stake_amount, &seed, &aggpubkey, &validator_vote_accont)?;
+    let mut tx = create_stake_account_transaction(
+        stake.amount.get(),
+        &stake.seed,
+        &aggpubkey,
+        &stake.vote_account,
+    )?;
 
     let signer = PartialSigner {
         signer_private_nonce: secret_state.private_nonces,
@@ -330,12 +386,16 @@ pub fn stake_step_two(
         aggregated_pubkey: aggkey,
     };
 
+    policy.check(&tx.message)?;
     tx.sign(&[&signer], recent_block_hash);
     let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
+    Ok((
+        PartialSignature(sig),
+        CompiledMessage::new(tx.message_data()),
+    ))
 }
 
-#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "tss.deactivate_stake_step_two", skip_all)]
 pub fn deactivate_stake_step_two(
     keypair: Keypair,
     stake_account: Pubkey,
@@ -343,7 +403,8 @@ pub fn deactivate_stake_step_two(
     keys: Vec<Pubkey>,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
-) -> Result<PartialSignature, Error> {
+    policy: &WritablePolicy,
+) -> Result<(PartialSignature, CompiledMessage), Error> {
     let other_nonces: Vec<_> = first_messages
         .into_iter()
         .map(|msg1| msg1.public_nonces.R)
@@ -365,22 +426,25 @@ pub fn deactivate_stake_step_two(
         aggregated_pubkey: aggkey,
     };
 
+    policy.check(&tx.message)?;
     tx.sign(&[&signer], recent_block_hash);
     let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
+    Ok((
+        PartialSignature(sig),
+        CompiledMessage::new(tx.message_data()),
+    ))
 }
 
-#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "tss.withdraw_stake_step_two", skip_all)]
 pub fn withdraw_stake_step_two(
     keypair: Keypair,
-    stake_account: Pubkey,
-    destination: Pubkey,
-    amount: u64,
+    withdrawal: &StakeWithdrawal,
     recent_block_hash: Hash,
     keys: Vec<Pubkey>,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
-) -> Result<PartialSignature, Error> {
+    policy: &WritablePolicy,
+) -> Result<(PartialSignature, CompiledMessage), Error> {
     let other_nonces: Vec<_> = first_messages
         .into_iter()
         .map(|msg1| msg1.public_nonces.R)
@@ -392,8 +456,12 @@ pub fn withdraw_stake_step_two(
     let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
 
     //creating unsigned Transaction
-    let mut tx =
-        create_withdraw_stake_transaction(&stake_account, &destination, &aggpubkey, amount);
+    let mut tx = create_withdraw_stake_transaction(
+        &withdrawal.stake_account,
+        &withdrawal.destination,
+        &aggpubkey,
+        withdrawal.amount.get(),
+    );
 
     let signer = PartialSigner {
         signer_private_nonce: secret_state.private_nonces,
@@ -403,13 +471,18 @@ pub fn withdraw_stake_step_two(
         aggregated_pubkey: aggkey,
     };
 
+    policy.check(&tx.message)?;
     tx.sign(&[&signer], recent_block_hash);
     let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
+    Ok((
+        PartialSignature(sig),
+        CompiledMessage::new(tx.message_data()),
+    ))
 }
 
+#[tracing::instrument(name = "tss.aggregate_stake_signatures", skip_all)]
 pub fn aggregate_stake_signatures_and_broadcast(
-    stake_amount: u64,
+    stake_amount: Lamports,
     seed: String,
     validator_vote_accont: Pubkey,
     recent_block_hash: Hash,
@@ -419,54 +492,21 @@ pub fn aggregate_stake_signatures_and_broadcast(
     let aggkey = key_agg(keys, None)?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
 
-    // Make sure all the `R`s are the same
-    if !signatures[1..]
-        .iter()
-        .map(|s| &s.0.as_ref()[..32])
-        .all(|s| s == &signatures[0].0.as_ref()[..32])
-    {
-        return Err(Error::MismatchMessages);
-    }
-
-    let deserialize_R = |s| {
-        Point::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: DeserializationError::InvalidPoint(e),
-            field_name: "signatures",
-        })
-    };
-    let deserialize_s = |s| {
-        Scalar::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: DeserializationError::InvalidScalar(e),
-            field_name: "signatures",
-        })
-    };
-
-    let first_sig = musig2::PartialSignature {
-        R: deserialize_R(&signatures[0].0.as_ref()[..32])?,
-        my_partial_s: deserialize_s(&signatures[0].0.as_ref()[32..])?,
-    };
-
-    let partial_sigs: Vec<_> = signatures[1..]
-        .iter()
-        .map(|s| deserialize_s(&s.0.as_ref()[32..]))
-        .collect::<Result<_, _>>()?;
-
-    // Add the signatures up
-    let full_sig = musig2::aggregate_partial_signatures(&first_sig, &partial_sigs);
+    let sig = aggregate(&signatures)?;
 
-    let mut sig_bytes = [0u8; 64];
-    sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
-    sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
-    let sig = Signature::new(&sig_bytes);
-
-    println!("reacted in tss before create_stake_account_transaction");
-    println!(
-        "stake_amount: {:?}, seed: {:?}, aggpubkey: {:?}, vote_account: {:?}",
-        stake_amount, seed, aggpubkey, validator_vote_accont
+    tracing::debug!(
+        ?stake_amount,
+        seed = seed.as_str(),
+        %aggpubkey,
+        vote_account = %validator_vote_accont,
+        "creating the stake account transaction"
     );
-    let mut tx =
-        create_stake_account_transaction(stake_amount, &seed, &aggpubkey, &validator_vote_accont)?;
-    println!("print tx: {:?}", tx);
+    let mut tx = create_stake_account_transaction(
+        stake_amount.get(),
+        &seed,
+        &aggpubkey,
+        &validator_vote_accont,
+    )?;
 
     // Insert the recent_block_hash and the signature to the right places
     tx.message.recent_blockhash = recent_block_hash;
@@ -480,6 +520,7 @@ pub fn aggregate_stake_signatures_and_broadcast(
     Ok(tx)
 }
 
+#[tracing::instrument(name = "tss.aggregate_deactivate_stake_signatures", skip_all)]
 pub fn aggregate_deactivate_stake_signatures_and_broadcast(
     stake_account: Pubkey,
     recent_block_hash: Hash,
@@ -489,43 +530,7 @@ pub fn aggregate_deactivate_stake_signatures_and_broadcast(
     let aggkey = key_agg(keys.clone(), None)?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
 
-    if !signatures[1..]
-        .iter()
-        .map(|s| &s.0.as_ref()[..32])
-        .all(|s| s == &signatures[0].0.as_ref()[..32])
-    {
-        return Err(Error::MismatchMessages);
-    }
-
-    let deserialize_R = |s: &[u8]| {
-        Point::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: crate::serialization::Error::InvalidPoint(e),
-            field_name: "signatures",
-        })
-    };
-    let deserialize_s = |s: &[u8]| {
-        Scalar::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: crate::serialization::Error::InvalidScalar(e),
-            field_name: "signatures",
-        })
-    };
-
-    let first_sig = musig2::PartialSignature {
-        R: deserialize_R(&signatures[0].0.as_ref()[..32])?,
-        my_partial_s: deserialize_s(&signatures[0].0.as_ref()[32..])?,
-    };
-
-    let partial_sigs: Vec<_> = signatures[1..]
-        .iter()
-        .map(|s| deserialize_s(&s.0.as_ref()[32..]))
-        .collect::<Result<_, _>>()?;
-
-    let full_sig = musig2::aggregate_partial_signatures(&first_sig, &partial_sigs);
-
-    let mut sig_bytes = [0u8; 64];
-    sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
-    sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
-    let sig = Signature::new(&sig_bytes);
+    let sig = aggregate(&signatures)?;
 
     let mut tx = create_deactivate_stake_transaction(&stake_account, &aggpubkey);
 
@@ -540,10 +545,11 @@ pub fn aggregate_deactivate_stake_signatures_and_broadcast(
     Ok(tx)
 }
 
+#[tracing::instrument(name = "tss.aggregate_withdraw_stake_signatures", skip_all)]
 pub fn aggregate_withdraw_stake_signatures_and_broadcast(
     stake_account: Pubkey,
     destination: Pubkey,
-    amount: u64,
+    amount: Lamports,
     recent_block_hash: Hash,
     keys: Vec<Pubkey>,
     signatures: Vec<PartialSignature>,
@@ -551,46 +557,10 @@ pub fn aggregate_withdraw_stake_signatures_and_broadcast(
     let aggkey = key_agg(keys.clone(), None)?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
 
-    if !signatures[1..]
-        .iter()
-        .map(|s| &s.0.as_ref()[..32])
-        .all(|s| s == &signatures[0].0.as_ref()[..32])
-    {
-        return Err(Error::MismatchMessages);
-    }
-
-    let deserialize_R = |s: &[u8]| {
-        Point::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: crate::serialization::Error::InvalidPoint(e),
-            field_name: "signatures",
-        })
-    };
-    let deserialize_s = |s: &[u8]| {
-        Scalar::from_bytes(s).map_err(|e| Error::DeserializationFailed {
-            error: crate::serialization::Error::InvalidScalar(e),
-            field_name: "signatures",
-        })
-    };
-
-    let first_sig = musig2::PartialSignature {
-        R: deserialize_R(&signatures[0].0.as_ref()[..32])?,
-        my_partial_s: deserialize_s(&signatures[0].0.as_ref()[32..])?,
-    };
-
-    let partial_sigs: Vec<_> = signatures[1..]
-        .iter()
-        .map(|s| deserialize_s(&s.0.as_ref()[32..]))
-        .collect::<Result<_, _>>()?;
-
-    let full_sig = musig2::aggregate_partial_signatures(&first_sig, &partial_sigs);
-
-    let mut sig_bytes = [0u8; 64];
-    sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
-    sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
-    let sig = Signature::new(&sig_bytes);
+    let sig = aggregate(&signatures)?;
 
     let mut tx =
-        create_withdraw_stake_transaction(&stake_account, &destination, &aggpubkey, amount);
+        create_withdraw_stake_transaction(&stake_account, &destination, &aggpubkey, amount.get());
 
     tx.message.recent_blockhash = recent_block_hash;
     assert_eq!(tx.signatures.len(), 1);
@@ -603,6 +573,72 @@ pub fn aggregate_withdraw_stake_signatures_and_broadcast(
     Ok(tx)
 }
 
+/// Refuse to sign a payload that is a transaction message `signer` signs, it would skip every
+/// check of the endpoints building transactions.
+pub fn check_not_message(payload: &[u8], signer: &Pubkey) -> Result<(), Error> {
+    let Ok(message) = limited_deserialize::<VersionedMessage>(payload) else {
+        return Ok(());
+    };
+    let signers = message.header().num_required_signatures as usize;
+    match message
+        .static_account_keys()
+        .iter()
+        .take(signers)
+        .any(|key| key == signer)
+    {
+        true => Err(Error::PayloadIsMessage(*signer)),
+        false => Ok(()),
+    }
+}
+
+/// This party's partial signature of `payload` by the aggregated key, for bytes that aren't a
+/// transaction, such as the payload of an Ed25519Program instruction.
+#[tracing::instrument(name = "tss.payload_step_two", skip_all)]
+pub fn payload_step_two(
+    keypair: Keypair,
+    payload: &[u8],
+    keys: Vec<Pubkey>,
+    first_messages: Vec<AggMessage1>,
+    secret_state: SecretAggStepOne,
+) -> Result<PartialSignature, Error> {
+    let other_nonces: Vec<_> = first_messages
+        .into_iter()
+        .map(|msg1| msg1.public_nonces.R)
+        .collect();
+
+    let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
+    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
+    check_not_message(payload, &aggpubkey)?;
+    let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+
+    let signer = PartialSigner {
+        signer_private_nonce: secret_state.private_nonces,
+        signer_public_nonce: secret_state.public_nonces,
+        other_nonces,
+        extended_kepair,
+        aggregated_pubkey: aggkey,
+    };
+    Ok(PartialSignature(signer.sign_message(payload)))
+}
+
+/// The aggregated key with its signature of `payload`, added up from every party's partial
+/// signature and verified.
+#[tracing::instrument(name = "tss.aggregate_payload_signature", skip_all)]
+pub fn aggregate_payload_signature(
+    payload: &[u8],
+    keys: Vec<Pubkey>,
+    signatures: Vec<PartialSignature>,
+) -> Result<(Pubkey, Signature), Error> {
+    let aggkey = key_agg(keys, None)?;
+    let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
+
+    let sig = aggregate(&signatures)?;
+    if !sig.verify(aggpubkey.as_ref(), payload) {
+        return Err(Error::InvalidSignature);
+    }
+    Ok((aggpubkey, sig))
+}
+
 struct PartialSigner {
     signer_private_nonce: PrivatePartialNonces,
     signer_public_nonce: PublicPartialNonces,
@@ -611,6 +647,26 @@ struct PartialSigner {
     aggregated_pubkey: musig2::PublicKeyAgg,
 }
 
+impl PartialSigner {
+    fn new(
+        aggregated_pubkey: musig2::PublicKeyAgg,
+        extended_kepair: ExpandedKeyPair,
+        first_messages: Vec<AggMessage1>,
+        secret_state: SecretAggStepOne,
+    ) -> Self {
+        Self {
+            signer_private_nonce: secret_state.private_nonces,
+            signer_public_nonce: secret_state.public_nonces,
+            other_nonces: first_messages
+                .into_iter()
+                .map(|msg1| msg1.public_nonces.R)
+                .collect(),
+            extended_kepair,
+            aggregated_pubkey,
+        }
+    }
+}
+
 impl Signer for PartialSigner {
     fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
         Ok(Pubkey::new(
@@ -640,9 +696,21 @@ impl Signer for PartialSigner {
 
 #[cfg(test)]
 mod tests {
-    use crate::native_token::lamports_to_sol;
+    use crate::Error;
+    use crate::ed25519_verify::ed25519_verify_instruction;
+    use crate::serialization::PartialSignature;
     use crate::serialization::Serialize;
-    use crate::tss::{key_agg, sign_and_broadcast, step_one, step_two};
+    use crate::threshold::{group_pubkey, keygen_step_one, keygen_step_two};
+    use crate::transaction_builder::{SplTransferBuilder, TransferBuilder};
+    use crate::tss::{
+        aggregate, aggregate_payload_signature, aggregated_pubkey, key_agg, payload_step_two,
+        sign_and_broadcast, spl_sign_and_broadcast, spl_step_two, spl_transfer_message, step_one,
+        step_two, threshold_step_two, transfer_message,
+    };
+    use crate::units::{Decimals, Lamports, RawTokenAmount};
+    use solana_sdk::ed25519_instruction::verify;
+    use solana_sdk::feature_set::FeatureSet;
+    use solana_sdk::hash::Hash;
     use solana_sdk::pubkey::Pubkey;
     use solana_sdk::signature::{Keypair, Signer};
     use solana_streamer::socket::SocketAddrSpace;
@@ -656,6 +724,20 @@ mod tests {
         t.serialize(&mut v);
         T::deserialize(&v).unwrap()
     }
+    #[test]
+    fn test_aggregate_needs_one_nonce() {
+        assert!(matches!(aggregate(&[]), Err(Error::InvalidSignature)));
+        let partial = |r: u8| {
+            let mut bytes = [0u8; 64];
+            bytes[..32].fill(r);
+            PartialSignature(solana_sdk::signature::Signature::new(&bytes))
+        };
+        assert!(matches!(
+            aggregate(&[partial(1), partial(2)]),
+            Err(Error::MismatchMessages)
+        ));
+    }
+
     #[test]
     fn test_roundtrip() {
         let n = 5;
@@ -678,8 +760,12 @@ mod tests {
 
         let recent_block_hash = rpc_client.get_latest_blockhash().unwrap();
         // step 2
-        let amount = lamports_to_sol(full_amount / 2);
-        let memo = Some("test_roundtrip".to_string());
+        let transfer = TransferBuilder::new(
+            aggpubkey_solana,
+            to.pubkey(),
+            Lamports::new(full_amount / 2),
+        )
+        .memo(Some("test_roundtrip".to_string()));
 
         let partial_sigs: Vec<_> = keys
             .iter()
@@ -691,27 +777,18 @@ mod tests {
                 first_msgs.remove(i);
                 step_two(
                     key,
-                    amount,
-                    to.pubkey(),
-                    memo.clone(),
+                    &transfer,
                     recent_block_hash,
                     pubkeys.clone(),
                     first_msgs,
                     secret,
                 )
                 .unwrap()
+                .0
             })
             .collect();
 
-        let full_tx = sign_and_broadcast(
-            amount,
-            to.pubkey(),
-            memo,
-            recent_block_hash,
-            pubkeys,
-            partial_sigs,
-        )
-        .unwrap();
+        let full_tx = sign_and_broadcast(&transfer, recent_block_hash, partial_sigs).unwrap();
         let sig = rpc_client.send_transaction(&full_tx).unwrap();
 
         // Wait for confirmation
@@ -719,4 +796,289 @@ mod tests {
             .confirm_transaction_with_spinner(&sig, &recent_block_hash, rpc_client.commitment())
             .unwrap();
     }
+
+    #[test]
+    fn test_threshold_signers_aggregate() {
+        let keys: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let messages: Vec<_> = keys
+            .iter()
+            .map(|key| keygen_step_one(key, &pubkeys, 2).unwrap())
+            .collect();
+        let shares: Vec<_> = keys
+            .iter()
+            .map(|key| keygen_step_two(key, &pubkeys, 2, &messages).unwrap())
+            .collect();
+        let group_key = group_pubkey(&shares[0]);
+        let (to, recent_block_hash) = (Pubkey::new_unique(), Hash::new_unique());
+        let transfer = TransferBuilder::new(group_key, to, Lamports::new(1_000));
+
+        // Any two of the three parties sign for the group key, the third one never takes part
+        for signers in [[0, 2], [2, 1]] {
+            let (first_msgs, first_secrets): (Vec<_>, Vec<_>) = signers
+                .iter()
+                .map(|i| step_one(clone_keypair(&keys[*i])))
+                .unzip();
+            let partial_sigs: Vec<_> = signers
+                .iter()
+                .zip(first_secrets)
+                .enumerate()
+                .map(|(n, (i, secret))| {
+                    let mut others: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+                    others.remove(n);
+                    threshold_step_two(
+                        clone_keypair(&keys[*i]),
+                        &transfer,
+                        recent_block_hash,
+                        clone_serialize(&shares[*i]),
+                        &signers,
+                        others,
+                        secret,
+                    )
+                    .unwrap()
+                    .0
+                })
+                .collect();
+            // It verifies the aggregated signature against the message
+            let tx = sign_and_broadcast(&transfer, recent_block_hash, partial_sigs).unwrap();
+            assert_eq!(tx.message.account_keys[0], group_key);
+        }
+    }
+
+    #[test]
+    fn test_step_two_checks_payer() {
+        let keys: Vec<_> = (0..2).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let (mut first_msgs, mut first_secrets): (Vec<_>, Vec<_>) =
+            keys.iter().map(clone_keypair).map(step_one).unzip();
+        // Paid by one of the parties rather than by the key they aggregate
+        let transfer = TransferBuilder::new(pubkeys[0], Pubkey::new_unique(), Lamports::new(1));
+        first_msgs.remove(0);
+        assert!(matches!(
+            step_two(
+                clone_keypair(&keys[0]),
+                &transfer,
+                Hash::new_unique(),
+                pubkeys,
+                first_msgs,
+                first_secrets.remove(0),
+            ),
+            Err(Error::Internal(_))
+        ));
+    }
+
+    #[test]
+    fn test_signed_memo_aggregates() {
+        let mut rng = rand07::thread_rng();
+        let keys: Vec<_> = (0..3).map(|_| Keypair::generate(&mut rng)).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let recent_block_hash = Hash::new_unique();
+        let memo = Some("signed by the vault".to_string());
+        let aggpubkey = aggregated_pubkey(pubkeys.clone()).unwrap();
+        let sol = TransferBuilder::new(aggpubkey, to, Lamports::new(1_000))
+            .memo(memo.clone())
+            .signed_memo(true);
+        // Memo v1 on the SPL side, both steps must address the same program
+        let spl = SplTransferBuilder::new(
+            aggpubkey,
+            to,
+            mint,
+            RawTokenAmount::new(1_500_000),
+            Decimals::new(6).unwrap(),
+        )
+        .memo(memo)
+        .signed_memo(true)
+        .memo_program(spl_memo::v1::id());
+
+        let (first_msgs, first_secrets): (Vec<_>, Vec<_>) =
+            keys.iter().map(clone_keypair).map(step_one).unzip();
+        let others = |i: usize| {
+            let mut msgs: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+            msgs.remove(i);
+            msgs
+        };
+
+        let sol_sigs: Vec<_> = keys
+            .iter()
+            .zip(&first_secrets)
+            .enumerate()
+            .map(|(i, (key, secret))| {
+                step_two(
+                    clone_keypair(key),
+                    &sol,
+                    recent_block_hash,
+                    pubkeys.clone(),
+                    others(i),
+                    clone_serialize(secret),
+                )
+                .unwrap()
+                .0
+            })
+            .collect();
+        // `sign_and_broadcast` verifies the aggregated signature against the message
+        let tx = sign_and_broadcast(&sol, recent_block_hash, sol_sigs).unwrap();
+        let memo_ix = tx.message.instructions.last().unwrap();
+        assert_eq!(memo_ix.accounts, [0]);
+
+        let spl_sigs: Vec<_> = keys
+            .iter()
+            .zip(first_secrets)
+            .enumerate()
+            .map(|(i, (key, secret))| {
+                spl_step_two(
+                    clone_keypair(key),
+                    &spl,
+                    recent_block_hash,
+                    pubkeys.clone(),
+                    others(i),
+                    secret,
+                )
+                .unwrap()
+                .0
+            })
+            .collect();
+        let tx = spl_sign_and_broadcast(&spl, None, recent_block_hash, spl_sigs).unwrap();
+        let memo_ix = tx.message.instructions.last().unwrap();
+        assert_eq!(
+            tx.message.account_keys[memo_ix.program_id_index as usize],
+            spl_memo::v1::id()
+        );
+    }
+
+    #[test]
+    fn test_rent_sponsor_signature() {
+        let mut rng = rand07::thread_rng();
+        let keys: Vec<_> = (0..2).map(|_| Keypair::generate(&mut rng)).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let sponsor = Keypair::generate(&mut rng);
+        let to = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let recent_block_hash = Hash::new_unique();
+        let aggpubkey = aggregated_pubkey(pubkeys.clone()).unwrap();
+        let transfer = SplTransferBuilder::new(
+            aggpubkey,
+            to,
+            mint,
+            RawTokenAmount::new(1_000),
+            Decimals::new(6).unwrap(),
+        )
+        .rent_sponsor(Some(sponsor.pubkey()));
+
+        let (first_msgs, first_secrets): (Vec<_>, Vec<_>) =
+            keys.iter().map(clone_keypair).map(step_one).unzip();
+        let partial_sigs: Vec<_> = keys
+            .iter()
+            .zip(first_secrets)
+            .enumerate()
+            .map(|(i, (key, secret))| {
+                let mut others: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+                others.remove(i);
+                spl_step_two(
+                    clone_keypair(key),
+                    &transfer,
+                    recent_block_hash,
+                    pubkeys.clone(),
+                    others,
+                    secret,
+                )
+                .unwrap()
+                .0
+            })
+            .collect();
+
+        // The sponsor signs the same message on its own
+        let message = spl_transfer_message(&transfer, recent_block_hash).unwrap();
+        let sponsor_signature = sponsor.sign_message(&message.serialize());
+
+        let aggregate = |signature| {
+            spl_sign_and_broadcast(
+                &transfer,
+                signature,
+                recent_block_hash,
+                partial_sigs.iter().map(clone_serialize).collect(),
+            )
+        };
+        assert!(matches!(
+            aggregate(None),
+            Err(Error::RentSponsorSignatureMissing(missing)) if missing == sponsor.pubkey()
+        ));
+        let other_signature = Keypair::generate(&mut rng).sign_message(&message.serialize());
+        assert!(matches!(
+            aggregate(Some(other_signature)),
+            Err(Error::InvalidSignature)
+        ));
+        let tx = aggregate(Some(sponsor_signature)).unwrap();
+        assert_eq!(tx.message.account_keys[..2], [aggpubkey, sponsor.pubkey()]);
+        assert_eq!(tx.signatures[1], sponsor_signature);
+    }
+
+    #[test]
+    fn test_payload_signature_verifies_on_chain() {
+        let mut rng = rand07::thread_rng();
+        let keys: Vec<_> = (0..3).map(|_| Keypair::generate(&mut rng)).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let sign = |payload: &[u8]| {
+            let (first_msgs, first_secrets): (Vec<_>, Vec<_>) =
+                keys.iter().map(clone_keypair).map(step_one).unzip();
+            keys.iter()
+                .zip(first_secrets)
+                .enumerate()
+                .map(|(i, (key, secret))| {
+                    let mut others: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+                    others.remove(i);
+                    payload_step_two(clone_keypair(key), payload, pubkeys.clone(), others, secret)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        let payload = b"withdraw voucher 7".to_vec();
+        let partial_sigs = sign(&payload).unwrap();
+        let (aggpubkey, signature) =
+            aggregate_payload_signature(&payload, pubkeys.clone(), partial_sigs).unwrap();
+        assert_eq!(
+            aggpubkey,
+            Pubkey::new(
+                &*key_agg(pubkeys.clone(), None)
+                    .unwrap()
+                    .agg_public_key
+                    .to_bytes(true)
+            )
+        );
+        // The precompile accepts the aggregated signature
+        let instruction = ed25519_verify_instruction(&aggpubkey, &signature, &payload).unwrap();
+        verify(
+            &instruction.data,
+            &[&instruction.data],
+            &FeatureSet::all_enabled(),
+        )
+        .unwrap();
+
+        // Signatures of another payload don't add up to one of this payload
+        let other_sigs = sign(b"withdraw voucher 8").unwrap();
+        assert!(matches!(
+            aggregate_payload_signature(&payload, pubkeys.clone(), other_sigs),
+            Err(Error::InvalidSignature)
+        ));
+        assert!(matches!(
+            aggregate_payload_signature(&payload, pubkeys.clone(), vec![]),
+            Err(Error::InvalidSignature)
+        ));
+
+        // A transaction of the aggregated key isn't signed as a payload, anyone else's is
+        let transfer = transfer_message(
+            &TransferBuilder::new(aggpubkey, Pubkey::new_unique(), Lamports::new(1)),
+            Hash::new_unique(),
+        );
+        assert!(matches!(
+            sign(&transfer.serialize()),
+            Err(Error::PayloadIsMessage(signer)) if signer == aggpubkey
+        ));
+        let other = transfer_message(
+            &TransferBuilder::new(Pubkey::new_unique(), aggpubkey, Lamports::new(1)),
+            Hash::new_unique(),
+        );
+        sign(&other.serialize()).unwrap();
+    }
 }
@@ -0,0 +1,70 @@
+use poem::{
+    Response,
+    http::{HeaderMap, StatusCode, header},
+};
+
+use crate::etag;
+
+// The ceremony UI in `ui/` is compiled into the binary and served at `/ui`, so it needs no
+// build step and always matches the API it calls. The pages are plain forms posting to the
+// JSON endpoints of the same origin and keep nothing: no storage, no cookies, and the API
+// calls bypass the browser's cache.
+
+/// A file of the UI.
+pub struct Asset {
+    pub name: &'static str,
+    pub content_type: &'static str,
+    pub body: &'static str,
+}
+
+pub const ASSETS: &[Asset] = &[
+    Asset {
+        name: "index.html",
+        content_type: "text/html; charset=utf-8",
+        body: include_str!("../ui/index.html"),
+    },
+    Asset {
+        name: "app.js",
+        content_type: "text/javascript; charset=utf-8",
+        body: include_str!("../ui/app.js"),
+    },
+    Asset {
+        name: "style.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_str!("../ui/style.css"),
+    },
+];
+
+pub fn asset(name: &str) -> Option<&'static Asset> {
+    ASSETS.iter().find(|asset| asset.name == name)
+}
+
+impl Asset {
+    /// Strong ETag of the embedded content, it only changes with the binary.
+    pub fn etag(&self) -> String {
+        etag::strong(self.body.as_bytes())
+    }
+
+    /// The asset, or a 304 when `If-None-Match` already names its ETag.
+    pub fn response(&self, headers: &HeaderMap) -> Response {
+        let etag = self.etag();
+        let cached = etag::matches(headers, &etag);
+        let builder = Response::builder()
+            .header(header::ETAG, etag)
+            // Revalidate every time, a new binary serves new files under the same names
+            .header(header::CACHE_CONTROL, "no-cache")
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'self'; frame-ancestors 'none'",
+            )
+            .header(header::REFERRER_POLICY, "no-referrer");
+        if cached {
+            return builder.status(StatusCode::NOT_MODIFIED).finish();
+        }
+        builder
+            .status(StatusCode::OK)
+            .content_type(self.content_type)
+            .body(self.body)
+    }
+}
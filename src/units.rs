@@ -0,0 +1,705 @@
+use std::fmt::{Display, Formatter};
+
+use schemars::{
+    JsonSchema,
+    r#gen::SchemaGenerator,
+    schema::{Metadata, Schema, SchemaObject, SubschemaValidation},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::Error;
+
+// Amounts on the wire are UI numbers (SOL, whole tokens) or base units, on chain they are
+// always base units. Keeping lamports, raw token amounts and decimals in their own types stops
+// one from being passed where another is expected, and every conversion between UI and base
+// units goes through the decimal digits here rather than through float multiplication.
+
+/// Most decimals a UI amount can have, `10^19` is the largest power of ten a `u64` holds.
+pub const MAX_DECIMALS: u8 = 19;
+
+/// Decimal places of a mint, at most `MAX_DECIMALS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Decimals(u8);
+
+impl Decimals {
+    /// SOL has 9 decimals, a lamport is `10^-9` SOL.
+    pub const SOL: Self = Self(9);
+
+    pub fn new(decimals: u8) -> Result<Self, Error> {
+        if decimals > MAX_DECIMALS {
+            return Err(Error::InvalidAmount(format!(
+                "{} decimals is more than the {} a 64 bit amount can hold",
+                decimals, MAX_DECIMALS
+            )));
+        }
+        Ok(Self(decimals))
+    }
+
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+
+    /// Base units in one whole unit.
+    pub fn scale(self) -> u64 {
+        10u64.pow(u32::from(self.0))
+    }
+}
+
+impl TryFrom<u8> for Decimals {
+    type Error = Error;
+
+    fn try_from(decimals: u8) -> Result<Self, Error> {
+        Self::new(decimals)
+    }
+}
+
+impl From<Decimals> for u8 {
+    fn from(decimals: Decimals) -> Self {
+        decimals.0
+    }
+}
+
+impl Display for Decimals {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How a UI amount string is written, for the errors of one that isn't.
+const UI_FORMAT: &str = "write digits with an optional '.' decimal point and '_' between digits, \
+                         like \"1_000.5\"";
+
+/// `part` of a UI amount without its `_` separators, `None` unless it is digits with single
+/// underscores between them.
+fn digits(part: &str) -> Option<String> {
+    part.split('_')
+        .all(|group| !group.is_empty() && group.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| part.replace('_', ""))
+}
+
+/// Base units in a UI decimal string such as `"1.5"` or `"1_000"`, which may have at most
+/// `decimals` fractional digits. Commas and other locale separators are refused rather than
+/// guessed at, `"1,000"` is a thousand in some places and one in others.
+fn parse_ui(s: &str, decimals: Decimals) -> Result<u64, Error> {
+    let invalid = |reason: &str| Error::InvalidAmount(format!("{:?} {}", s, reason));
+    let malformed = |reason: &str| invalid(&format!("{}, {}", reason, UI_FORMAT));
+    if s.starts_with(['+', '-']) {
+        return Err(malformed("has a sign"));
+    }
+    if s.contains(['e', 'E']) {
+        return Err(malformed("is in exponent notation"));
+    }
+    if let Some(separator) = s
+        .chars()
+        .find(|c| matches!(c, ',' | '\'' | '\u{2019}' | '\u{b7}') || c.is_whitespace())
+    {
+        return Err(malformed(&format!("uses {:?} as a separator", separator)));
+    }
+    if s.matches('.').count() > 1 {
+        return Err(malformed("has more than one decimal point"));
+    }
+    let (whole, fraction) = match s.split_once('.') {
+        Some((whole, fraction)) => (digits(whole), digits(fraction)),
+        None => (digits(s), Some(String::new())),
+    };
+    let (Some(whole), Some(fraction)) = (whole, fraction) else {
+        return Err(malformed("is not a decimal number"));
+    };
+    if fraction.len() > usize::from(decimals.get()) {
+        return Err(invalid(&format!(
+            "has more than {} decimal places",
+            decimals
+        )));
+    }
+    let overflow = || invalid("doesn't fit in a 64 bit amount");
+    let whole: u64 = whole.parse().map_err(|_| overflow())?;
+    let fraction: u64 = match fraction.as_str() {
+        "" => 0,
+        places => {
+            let padding = u32::from(decimals.get()) - places.len() as u32;
+            places.parse::<u64>().map_err(|_| overflow())? * 10u64.pow(padding)
+        }
+    };
+    whole
+        .checked_mul(decimals.scale())
+        .and_then(|units| units.checked_add(fraction))
+        .ok_or_else(overflow)
+}
+
+/// Base units in a UI amount sent as a JSON number. Digits past `decimals` are dropped, as
+/// they always were, but the rest is exact: `0.29` SOL is 290000000 lamports, not the
+/// 289999999 float multiplication gives.
+fn ui_f64(amount: f64, decimals: Decimals) -> Result<u64, Error> {
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(Error::InvalidAmount(format!(
+            "amount must be a positive number, got {}",
+            amount
+        )));
+    }
+    // `Display` prints the shortest digits that read back as the same float, never an exponent
+    let digits = amount.to_string();
+    let (whole, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+    let fraction = &fraction[..fraction.len().min(usize::from(decimals.get()))];
+    if fraction.is_empty() {
+        parse_ui(whole, decimals)
+    } else {
+        parse_ui(&format!("{}.{}", whole, fraction), decimals)
+    }
+}
+
+/// `units` base units as a UI decimal string, without trailing zeros.
+fn format_ui(units: u64, decimals: Decimals) -> String {
+    let (whole, fraction) = (units / decimals.scale(), units % decimals.scale());
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0width$}", fraction, width = usize::from(decimals.get()));
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+fn overflow(what: &str, a: u64, op: &str, b: u64) -> Error {
+    Error::InvalidAmount(format!("{} {} {} {} overflows", a, op, b, what))
+}
+
+/// An amount of lamports, serialized as the plain number.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Lamports(u64);
+
+impl Lamports {
+    pub const ZERO: Self = Self(0);
+
+    pub const fn new(lamports: u64) -> Self {
+        Self(lamports)
+    }
+
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_sol(sol: f64) -> Result<Self, Error> {
+        ui_f64(sol, Decimals::SOL).map(Self)
+    }
+
+    /// `sol` as a decimal string such as `"0.5"`.
+    pub fn parse_sol(sol: &str) -> Result<Self, Error> {
+        parse_ui(sol, Decimals::SOL).map(Self)
+    }
+
+    pub fn to_sol_string(self) -> String {
+        format_ui(self.0, Decimals::SOL)
+    }
+
+    pub fn checked_add(self, other: Self) -> Result<Self, Error> {
+        self.0
+            .checked_add(other.0)
+            .map(Self)
+            .ok_or_else(|| overflow("lamports", self.0, "+", other.0))
+    }
+
+    pub fn checked_sub(self, other: Self) -> Result<Self, Error> {
+        self.0
+            .checked_sub(other.0)
+            .map(Self)
+            .ok_or_else(|| overflow("lamports", self.0, "-", other.0))
+    }
+
+    pub fn checked_mul(self, n: u64) -> Result<Self, Error> {
+        self.0
+            .checked_mul(n)
+            .map(Self)
+            .ok_or_else(|| overflow("lamports", self.0, "*", n))
+    }
+}
+
+impl Display for Lamports {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An amount of token base units, serialized as the plain number. Its decimals live
+/// alongside it, usually in the mint.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct RawTokenAmount(u64);
+
+impl RawTokenAmount {
+    pub const fn new(amount: u64) -> Self {
+        Self(amount)
+    }
+
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_ui(amount: f64, decimals: Decimals) -> Result<Self, Error> {
+        ui_f64(amount, decimals).map(Self)
+    }
+
+    /// `amount` as a decimal string such as `"12.25"`.
+    pub fn parse_ui(amount: &str, decimals: Decimals) -> Result<Self, Error> {
+        parse_ui(amount, decimals).map(Self)
+    }
+
+    pub fn to_ui_string(self, decimals: Decimals) -> String {
+        format_ui(self.0, decimals)
+    }
+
+    pub fn checked_add(self, other: Self) -> Result<Self, Error> {
+        self.0
+            .checked_add(other.0)
+            .map(Self)
+            .ok_or_else(|| overflow("base units", self.0, "+", other.0))
+    }
+
+    pub fn checked_sub(self, other: Self) -> Result<Self, Error> {
+        self.0
+            .checked_sub(other.0)
+            .map(Self)
+            .ok_or_else(|| overflow("base units", self.0, "-", other.0))
+    }
+}
+
+impl Display for RawTokenAmount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A UI amount as a request sends it, a JSON number or a decimal string such as `"1.5"`. The
+/// string is read digit by digit, the number goes through `f64` first.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UiAmount {
+    Number(f64),
+    Decimal(String),
+}
+
+impl UiAmount {
+    pub fn to_lamports(&self) -> Result<Lamports, Error> {
+        match self {
+            Self::Number(sol) => Lamports::from_sol(*sol),
+            Self::Decimal(sol) => Lamports::parse_sol(sol),
+        }
+    }
+
+    pub fn to_raw(&self, decimals: Decimals) -> Result<RawTokenAmount, Error> {
+        match self {
+            Self::Number(amount) => RawTokenAmount::from_ui(*amount, decimals),
+            Self::Decimal(amount) => RawTokenAmount::parse_ui(amount, decimals),
+        }
+    }
+
+    /// Lamports of the amount a request sent in `field`, which has to be at least one.
+    pub fn lamports_in(&self, field: &str) -> Result<Lamports, Error> {
+        let lamports = self.to_lamports().map_err(|e| in_field(field, e))?;
+        at_least_one(field, self, lamports.get(), Decimals::SOL)?;
+        Ok(lamports)
+    }
+
+    /// Base units of the amount a request sent in `field`, which has to be at least one.
+    pub fn raw_in(&self, field: &str, decimals: Decimals) -> Result<RawTokenAmount, Error> {
+        let raw = self.to_raw(decimals).map_err(|e| in_field(field, e))?;
+        at_least_one(field, self, raw.get(), decimals)?;
+        Ok(raw)
+    }
+}
+
+/// `error` of the amount in `field`, naming the field when it is about the amount itself.
+fn in_field(field: &str, error: Error) -> Error {
+    match error {
+        Error::InvalidAmount(reason) => Error::AmountRejected {
+            field: field.to_string(),
+            reason,
+        },
+        error => error,
+    }
+}
+
+/// A transfer of nothing would only cost its fee, and an amount below one base unit rounds
+/// down to nothing.
+fn at_least_one(
+    field: &str,
+    amount: &impl Display,
+    units: u64,
+    decimals: Decimals,
+) -> Result<(), Error> {
+    if units > 0 {
+        return Ok(());
+    }
+    Err(Error::AmountRejected {
+        field: field.to_string(),
+        reason: format!(
+            "must be at least {}, got {}",
+            format_ui(1, decimals),
+            amount
+        ),
+    })
+}
+
+/// Base units a request sent as a whole number in `field`, at least one.
+pub fn base_units_in(field: &str, units: u64) -> Result<u64, Error> {
+    at_least_one(field, &units, units, Decimals(0))?;
+    Ok(units)
+}
+
+impl From<f64> for UiAmount {
+    fn from(amount: f64) -> Self {
+        Self::Number(amount)
+    }
+}
+
+impl From<&str> for UiAmount {
+    fn from(amount: &str) -> Self {
+        Self::Decimal(amount.to_string())
+    }
+}
+
+impl Display for UiAmount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(amount) => write!(f, "{}", amount),
+            Self::Decimal(amount) => f.write_str(amount),
+        }
+    }
+}
+
+impl Serialize for UiAmount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Number(amount) => serializer.serialize_f64(*amount),
+            Self::Decimal(amount) => serializer.serialize_str(amount),
+        }
+    }
+}
+
+/// The field path of the first `amount` in a request body that is a JSON float, such as
+/// `recipients[1].amount`. `1.0` is a float, `1` isn't.
+pub fn float_amount(body: &Value) -> Option<(String, f64)> {
+    let nested = |parent: String, (path, amount): (String, f64)| match path.starts_with('[') {
+        true => (format!("{parent}{path}"), amount),
+        false => (format!("{parent}.{path}"), amount),
+    };
+    match body {
+        Value::Object(fields) => fields.iter().find_map(|(key, value)| match value {
+            Value::Number(n) if key == "amount" && n.is_f64() => {
+                Some((key.clone(), n.as_f64().unwrap_or_default()))
+            }
+            value => float_amount(value).map(|found| nested(key.clone(), found)),
+        }),
+        Value::Array(values) => values.iter().enumerate().find_map(|(i, value)| {
+            float_amount(value).map(|found| nested(format!("[{i}]"), found))
+        }),
+        _ => None,
+    }
+}
+
+impl<'de> Deserialize<'de> for UiAmount {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = UiAmount;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.write_str("an amount as a decimal string or a number")
+            }
+
+            fn visit_f64<E>(self, amount: f64) -> Result<UiAmount, E> {
+                Ok(UiAmount::Number(amount))
+            }
+
+            fn visit_u64<E>(self, amount: u64) -> Result<UiAmount, E> {
+                Ok(UiAmount::Decimal(amount.to_string()))
+            }
+
+            fn visit_i64<E>(self, amount: i64) -> Result<UiAmount, E> {
+                Ok(UiAmount::Decimal(amount.to_string()))
+            }
+
+            fn visit_str<E>(self, amount: &str) -> Result<UiAmount, E> {
+                Ok(UiAmount::Decimal(amount.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl JsonSchema for UiAmount {
+    fn schema_name() -> String {
+        "UiAmount".to_string()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some("A decimal string such as \"1.5\", or a JSON number".to_string()),
+                ..Default::default()
+            })),
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![
+                    generator.subschema_for::<String>(),
+                    generator.subschema_for::<f64>(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Error;
+    use crate::units::{Decimals, Lamports, MAX_DECIMALS, RawTokenAmount, UiAmount, float_amount};
+
+    #[test]
+    fn test_decimals() {
+        assert_eq!(Decimals::new(MAX_DECIMALS).unwrap().scale(), 10u64.pow(19));
+        assert!(Decimals::new(MAX_DECIMALS + 1).is_err());
+        assert_eq!(Decimals::new(0).unwrap().scale(), 1);
+        assert_eq!(serde_json::to_string(&Decimals::SOL).unwrap(), "9");
+        assert_eq!(serde_json::from_str::<Decimals>("6").unwrap().get(), 6);
+        assert!(serde_json::from_str::<Decimals>("20").is_err());
+        assert!(serde_json::from_str::<Decimals>("256").is_err());
+    }
+
+    #[test]
+    fn test_ui_strings() {
+        let six = Decimals::new(6).unwrap();
+        for (ui, units) in [
+            ("0", 0),
+            ("1", 1_000_000),
+            ("1.5", 1_500_000),
+            ("0.000001", 1),
+            ("000.100000", 100_000),
+            ("18446744073709.551615", u64::MAX),
+        ] {
+            assert_eq!(
+                RawTokenAmount::parse_ui(ui, six).unwrap().get(),
+                units,
+                "{}",
+                ui
+            );
+        }
+        for ui in [
+            "",
+            ".",
+            "1.",
+            ".5",
+            "-1",
+            "+1",
+            "1e6",
+            " 1",
+            "1,5",
+            "1.2.3",
+            "0x10",
+            "0.0000001",             // more places than the mint has
+            "18446744073709.551616", // one past u64::MAX
+            "99999999999999999999",
+        ] {
+            assert!(RawTokenAmount::parse_ui(ui, six).is_err(), "{:?}", ui);
+        }
+
+        let zero = Decimals::new(0).unwrap();
+        assert_eq!(RawTokenAmount::parse_ui("7", zero).unwrap().get(), 7);
+        assert!(RawTokenAmount::parse_ui("7.0", zero).is_err());
+        let max = Decimals::new(MAX_DECIMALS).unwrap();
+        assert_eq!(
+            RawTokenAmount::parse_ui("1.8446744073709551615", max)
+                .unwrap()
+                .get(),
+            u64::MAX
+        );
+        assert!(RawTokenAmount::parse_ui("2", max).is_err());
+
+        // Formatting reads back as the same amount
+        for (units, ui) in [
+            (0, "0"),
+            (1, "0.000001"),
+            (1_500_000, "1.5"),
+            (u64::MAX, "18446744073709.551615"),
+        ] {
+            let amount = RawTokenAmount::new(units);
+            assert_eq!(amount.to_ui_string(six), ui);
+            assert_eq!(RawTokenAmount::parse_ui(ui, six).unwrap(), amount);
+        }
+        assert_eq!(Lamports::new(1).to_sol_string(), "0.000000001");
+        assert_eq!(Lamports::parse_sol("2.5").unwrap().get(), 2_500_000_000);
+        assert!(Lamports::parse_sol("0.0000000001").is_err());
+    }
+
+    #[test]
+    fn test_ui_string_forms() {
+        let six = Decimals::new(6).unwrap();
+        for (ui, expected) in [
+            ("1_000", Ok(1_000_000_000)),
+            ("1_000_000", Ok(1_000_000_000_000)),
+            ("1_000.000_5", Ok(1_000_000_500)),
+            ("0_1", Ok(1_000_000)),
+            ("1000.5", Ok(1_000_500_000)),
+            ("_1", Err("is not a decimal number")),
+            ("1_", Err("is not a decimal number")),
+            ("1__000", Err("is not a decimal number")),
+            ("1_.5", Err("is not a decimal number")),
+            ("1._5", Err("is not a decimal number")),
+            ("_", Err("is not a decimal number")),
+            ("1,000.5", Err("uses ',' as a separator")),
+            ("1,5", Err("uses ',' as a separator")),
+            ("1.000,5", Err("uses ',' as a separator")),
+            ("1'000", Err("uses '\\'' as a separator")),
+            ("1\u{2019}000", Err("uses '\u{2019}' as a separator")),
+            ("1 000", Err("uses ' ' as a separator")),
+            ("1\u{a0}000", Err("uses '\\u{a0}' as a separator")),
+            ("1\u{202f}000", Err("uses '\\u{202f}' as a separator")),
+            ("1\u{b7}5", Err("uses '\u{b7}' as a separator")),
+            (" 1", Err("uses ' ' as a separator")),
+            ("+1", Err("has a sign")),
+            ("-1", Err("has a sign")),
+            ("+1.5", Err("has a sign")),
+            ("1.2.3", Err("has more than one decimal point")),
+            ("1..2", Err("has more than one decimal point")),
+            ("1e6", Err("is in exponent notation")),
+            ("1E6", Err("is in exponent notation")),
+            ("1.5e-3", Err("is in exponent notation")),
+            ("0x10", Err("is not a decimal number")),
+            ("1_000.0000001", Err("has more than 6 decimal places")),
+        ] {
+            match (RawTokenAmount::parse_ui(ui, six), expected) {
+                (Ok(amount), Ok(units)) => assert_eq!(amount.get(), units, "{:?}", ui),
+                (Err(e), Err(reason)) => {
+                    let e = e.to_string();
+                    assert!(e.contains(reason), "{:?}: {}", ui, e);
+                    // Syntax errors say how to write the amount
+                    assert_eq!(
+                        e.contains("like \"1_000.5\""),
+                        ui != "1_000.0000001",
+                        "{}",
+                        e
+                    );
+                }
+                (result, expected) => panic!("{:?}: {:?}, expected {:?}", ui, result, expected),
+            }
+        }
+        assert_eq!(
+            Lamports::parse_sol("1_000.5").unwrap().get(),
+            1_000_500_000_000
+        );
+        assert!(Lamports::parse_sol("1,000.5").is_err());
+    }
+
+    #[test]
+    fn test_ui_floats() {
+        for (sol, lamports) in [
+            (0.0, 0),
+            (1.0, 1_000_000_000),
+            (0.29, 290_000_000),
+            (0.1, 100_000_000),
+            (0.000000001, 1),
+            // Digits past the decimals are dropped
+            (0.0000000019, 1),
+            (18446744073.0, 18_446_744_073_000_000_000),
+        ] {
+            assert_eq!(Lamports::from_sol(sol).unwrap().get(), lamports, "{}", sol);
+        }
+        for sol in [-1.0, -0.0000001, f64::NAN, f64::INFINITY, 2e10, 1e300] {
+            assert!(Lamports::from_sol(sol).is_err(), "{}", sol);
+        }
+        let six = Decimals::new(6).unwrap();
+        assert_eq!(RawTokenAmount::from_ui(1.1, six).unwrap().get(), 1_100_000);
+        assert_eq!(
+            RawTokenAmount::from_ui(2.675, six).unwrap().get(),
+            2_675_000
+        );
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = Lamports::new(u64::MAX);
+        assert!(max.checked_add(Lamports::new(1)).is_err());
+        assert_eq!(
+            max.checked_sub(Lamports::new(1)).unwrap().get(),
+            u64::MAX - 1
+        );
+        assert!(Lamports::ZERO.checked_sub(Lamports::new(1)).is_err());
+        assert!(Lamports::new(u64::MAX / 2 + 1).checked_mul(2).is_err());
+        assert_eq!(Lamports::new(5_000).checked_mul(3).unwrap().get(), 15_000);
+        let amount = RawTokenAmount::new(u64::MAX);
+        assert!(amount.checked_add(RawTokenAmount::new(1)).is_err());
+        assert!(
+            RawTokenAmount::new(0)
+                .checked_sub(RawTokenAmount::new(1))
+                .is_err()
+        );
+        assert_eq!(serde_json::to_string(&Lamports::new(42)).unwrap(), "42");
+        assert_eq!(
+            serde_json::from_str::<RawTokenAmount>("42").unwrap(),
+            RawTokenAmount::new(42)
+        );
+    }
+
+    #[test]
+    fn test_ui_amount_forms() {
+        let read = |json: &str| serde_json::from_str::<UiAmount>(json).unwrap();
+        assert_eq!(read("2.5"), UiAmount::Number(2.5));
+        assert_eq!(read("2"), UiAmount::Decimal("2".to_string()));
+        assert_eq!(read("\"2.5\""), UiAmount::Decimal("2.5".to_string()));
+        assert!(serde_json::from_str::<UiAmount>("true").is_err());
+        // A string is exact past the 17 digits a float keeps
+        let amount = read("\"18446744073.709551615\"");
+        assert_eq!(amount.to_lamports().unwrap().get(), u64::MAX);
+        assert!(read("\"-2\"").to_lamports().is_err());
+
+        let body = json!({
+            "amount": "1",
+            "recipients": [{ "amount": 2 }, { "amount": 1.0 }],
+        });
+        assert_eq!(
+            float_amount(&body),
+            Some(("recipients[1].amount".to_string(), 1.0))
+        );
+        assert_eq!(float_amount(&json!({ "amount": 1, "fee": 0.5 })), None);
+    }
+    #[test]
+    fn test_amounts_in_fields() {
+        let read = |json: &str| serde_json::from_str::<UiAmount>(json).unwrap();
+        let rejected = |result: Result<u64, Error>| match result {
+            Err(Error::AmountRejected { field, reason }) => (field, reason),
+            other => panic!("expected a rejected amount, got {:?}", other),
+        };
+        let lamports = |json: &str, field: &str| read(json).lamports_in(field).map(Lamports::get);
+        assert_eq!(
+            lamports("\"18446744073.709551615\"", "amount").unwrap(),
+            u64::MAX
+        );
+        assert_eq!(lamports("0.000000001", "amount").unwrap(), 1);
+
+        let (field, reason) = rejected(lamports("\"18446744073.709551616\"", "amount"));
+        assert_eq!(field, "amount");
+        assert!(reason.contains("64 bit"));
+        let (field, _) = rejected(lamports("1e20", "recipients[2].amount"));
+        assert_eq!(field, "recipients[2].amount");
+        for zero in ["0", "0.0", "\"0\"", "0.0000000001"] {
+            let (_, reason) = rejected(lamports(zero, "amount"));
+            assert!(reason.starts_with("must be at least 0.000000001"));
+        }
+        rejected(lamports("-1", "amount"));
+
+        let raw = |json: &str| read(json).raw_in("amount", Decimals::new(2).unwrap());
+        assert_eq!(raw("0.01").unwrap(), RawTokenAmount::new(1));
+        let (_, reason) = rejected(raw("\"0.001\"").map(RawTokenAmount::get));
+        assert!(reason.contains("decimal places"));
+        let (_, reason) = rejected(raw("0").map(RawTokenAmount::get));
+        assert!(reason.starts_with("must be at least 0.01"));
+    }
+}
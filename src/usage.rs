@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+use serde_json::Value;
+
+use crate::{case::camel_case, models::FieldUsage};
+
+// Request models accumulate alternative fields, `amount` next to `lamports`, `keys` next to
+// `owner`, and every field a camelCase alias. Before one of them is deprecated it helps to know
+// who still sends it. Every JSON request body is looked at for the fields below, and what is
+// counted is the endpoint, the field and the JSON type it came as, never its value.
+
+/// Request fields whose use is counted, `recipients[].amount` for the field of every entry of
+/// an array.
+pub const TRACKED_FIELDS: &[&str] = &[
+    "amount",
+    "lamports",
+    "recipients",
+    "recipients[].amount",
+    "keys",
+    "owner",
+    "token",
+    "token_mint",
+    "decimals",
+    "memo",
+    "signed_memo",
+    "memo_program",
+    "compute_unit_limit",
+    "rent_sponsor",
+    "expected_message_hash",
+    "confirmation",
+    "broadcast_at",
+    "auto_split",
+    "allow_delegate",
+    "allow_non_system_recipient",
+    "include_account_keys",
+    "fresh",
+    "net",
+];
+
+const METRIC: &str = "tss_api_request_fields_total";
+
+/// The representation label of `value`.
+fn representation(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// `field` of `object`, by its snake_case name or its camelCase alias.
+fn lookup<'a>(object: &'a Value, field: &str) -> Option<&'a Value> {
+    object.get(field).or_else(|| object.get(camel_case(field)))
+}
+
+/// The representations `field` is present in within `body`, each once.
+fn present(body: &Value, field: &str) -> Vec<&'static str> {
+    let mut found: Vec<&'static str> = match field.split_once("[].") {
+        Some((array, field)) => lookup(body, array)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| lookup(entry, field))
+            .map(representation)
+            .collect(),
+        None => lookup(body, field)
+            .map(representation)
+            .into_iter()
+            .collect(),
+    };
+    found.sort_unstable();
+    found.dedup();
+    found
+}
+
+/// Requests by endpoint, tracked field and representation.
+#[derive(Debug, Default)]
+pub struct UsageStats {
+    counts: Mutex<BTreeMap<(&'static str, &'static str, &'static str), u64>>,
+}
+
+impl UsageStats {
+    /// Count the tracked fields of a request to `endpoint` with `body`.
+    pub fn record(&self, endpoint: &'static str, body: &Value) {
+        let mut counts = self.counts.lock().unwrap();
+        for field in TRACKED_FIELDS {
+            for representation in present(body, field) {
+                *counts.entry((endpoint, field, representation)).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn fields(&self) -> Vec<FieldUsage> {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&(endpoint, field, representation), &count)| FieldUsage {
+                endpoint: endpoint.to_string(),
+                field: field.to_string(),
+                representation: representation.to_string(),
+                count,
+            })
+            .collect()
+    }
+
+    /// The counts in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "# HELP {METRIC} Requests sending an optional field, by the JSON type it came as\n\
+             # TYPE {METRIC} counter\n"
+        );
+        for ((endpoint, field, representation), count) in self.counts.lock().unwrap().iter() {
+            let _ = writeln!(
+                text,
+                "{METRIC}{{endpoint=\"{endpoint}\",field=\"{field}\",\
+                 representation=\"{representation}\"}} {count}"
+            );
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::usage::UsageStats;
+
+    #[test]
+    fn test_fields_are_counted_by_representation() {
+        let stats = UsageStats::default();
+        stats.record(
+            "/api/send_single",
+            &json!({
+                "keypair": "secret",
+                "amount": "1.5",
+                "memoProgram": "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+                "recipients": [{ "amount": 1 }, { "amount": 2 }, { "amount": 0.5 }],
+            }),
+        );
+        stats.record("/api/send_single", &json!({ "amount": 2.5 }));
+        let counts: Vec<_> = stats
+            .fields()
+            .into_iter()
+            .map(|usage| (usage.field, usage.representation, usage.count))
+            .collect();
+        let expected = [
+            ("amount", "float", 1),
+            ("amount", "string", 1),
+            ("memo_program", "string", 1),
+            ("recipients", "array", 1),
+            ("recipients[].amount", "float", 1),
+            ("recipients[].amount", "integer", 1),
+        ];
+        assert_eq!(
+            counts,
+            expected.map(|(field, representation, count)| (
+                field.to_string(),
+                representation.to_string(),
+                count
+            ))
+        );
+        // Nothing of the values, and untracked fields like `keypair` not at all
+        let text = stats.render();
+        assert!(text.contains(
+            "tss_api_request_fields_total{endpoint=\"/api/send_single\",field=\"amount\",\
+             representation=\"float\"} 1\n"
+        ));
+        assert!(!text.contains("secret") && !text.contains("keypair") && !text.contains("1.5"));
+    }
+}
@@ -0,0 +1,55 @@
+use poem::http::Uri;
+
+use crate::{
+    config::Config,
+    models::{
+        DependencyVersions, Network, NetworkInfo, SerializationCapabilities, VersionResponse,
+    },
+};
+
+/// Commit the binary was built from, `unknown` outside a git checkout.
+pub const GIT_COMMIT: &str = env!("GIT_COMMIT");
+
+/// Cargo features compiled into this build.
+pub fn cargo_features() -> Vec<String> {
+    let mut features = Vec::new();
+    if cfg!(feature = "client") {
+        features.push("client".to_string());
+    }
+    if cfg!(feature = "otel") {
+        features.push("otel".to_string());
+    }
+    features
+}
+
+/// Everything an operator needs to tell two deployments apart, printed at startup and served
+/// by `/api/version`.
+pub fn version_info(config: &Config) -> VersionResponse {
+    VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: GIT_COMMIT.to_string(),
+        cargo_features: cargo_features(),
+        features: config.enabled_features(),
+        dependencies: DependencyVersions {
+            solana_sdk: env!("SOLANA_SDK_VERSION").to_string(),
+            multi_party_eddsa: env!("MULTI_PARTY_EDDSA_VERSION").to_string(),
+        },
+        serialization: SerializationCapabilities::current(),
+        limits: config.limits(),
+        networks: Network::ALL
+            .iter()
+            .map(|&network| NetworkInfo {
+                network,
+                // Only the host, provider URLs tend to carry an API key in the path or query
+                rpc_host: config
+                    .cluster_url(network)
+                    .parse::<Uri>()
+                    .ok()
+                    .and_then(|uri| uri.host().map(str::to_string))
+                    .unwrap_or_default(),
+                custom_rpc: config.rpc_urls.contains_key(&network),
+            })
+            .collect(),
+        environment: config.environment.clone(),
+    }
+}
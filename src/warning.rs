@@ -0,0 +1,120 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Some things go wrong without failing the request: a check the node can't serve is skipped,
+// a balance is reported although the account names another owner. The response still
+// succeeds and says so in its `warnings`, which are left out when there are none so clients
+// that don't know about them see the same responses as before.
+
+/// Codes of the warnings responses can carry, listed by `/api/capabilities`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningCode {
+    CheckSkipped,
+    MintFetchFailed,
+    OwnerMismatch,
+    NativeUnsynced,
+}
+
+impl WarningCode {
+    pub const ALL: [WarningCode; 4] = [
+        Self::CheckSkipped,
+        Self::MintFetchFailed,
+        Self::OwnerMismatch,
+        Self::NativeUnsynced,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::CheckSkipped => "CHECK_SKIPPED",
+            Self::MintFetchFailed => "MINT_FETCH_FAILED",
+            Self::OwnerMismatch => "OWNER_MISMATCH",
+            Self::NativeUnsynced => "NATIVE_UNSYNCED",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::CheckSkipped => {
+                "a precaution was skipped because the node doesn't serve the method it needs"
+            }
+            Self::MintFetchFailed => {
+                "the mint couldn't be read, its decimals come from the token registry"
+            }
+            Self::OwnerMismatch => "the token account's data names another owner",
+            Self::NativeUnsynced => {
+                "lamports were sent to the wrapped SOL account without a SyncNative, they aren't \
+                 part of the balance yet"
+            }
+        }
+    }
+
+    pub fn warning(self, message: impl Into<String>) -> Warning {
+        Warning {
+            code: self.as_str().to_string(),
+            message: message.into(),
+            field: None,
+        }
+    }
+}
+
+/// Something the caller should know about a request that still succeeded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Warning {
+    pub code: String,
+    pub message: String,
+    /// The request field the warning is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl Warning {
+    pub fn field(mut self, field: &str) -> Self {
+        self.field = Some(field.to_string());
+        self
+    }
+}
+
+/// A response and its warnings, which serialize next to the response's own fields.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Warned<T> {
+    #[serde(flatten)]
+    pub response: T,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+}
+
+impl<T> Warned<T> {
+    pub fn new(response: T, warnings: Vec<Warning>) -> Self {
+        Self { response, warnings }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::warning::{Warned, WarningCode};
+
+    #[test]
+    fn test_warnings_only_when_present() {
+        let response = json!({ "balance": 1 });
+        let quiet = serde_json::to_value(Warned::new(response.clone(), vec![])).unwrap();
+        assert_eq!(quiet, response);
+
+        let warning = WarningCode::OwnerMismatch
+            .warning("owned by someone else")
+            .field("owner");
+        let warned = serde_json::to_value(Warned::new(response, vec![warning])).unwrap();
+        assert_eq!(
+            warned,
+            json!({
+                "balance": 1,
+                "warnings": [{
+                    "code": "OWNER_MISMATCH",
+                    "message": "owned by someone else",
+                    "field": "owner",
+                }],
+            })
+        );
+    }
+}
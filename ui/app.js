@@ -0,0 +1,61 @@
+"use strict";
+
+// Every form posts its fields as JSON to its data-endpoint and shows the response below
+// itself. Nothing is written to storage, cookies or the URL.
+
+function body(form) {
+  const json = {};
+  for (const field of form.querySelectorAll("input, textarea, select")) {
+    const value = field.value.trim();
+    if (value === "") continue;
+    switch (field.dataset.type) {
+      case "list":
+        json[field.name] = value.split("\n").map((line) => line.trim()).filter(Boolean);
+        break;
+      case "number":
+        json[field.name] = Number(value);
+        break;
+      default:
+        json[field.name] = value;
+    }
+  }
+  return json;
+}
+
+function show(form, text, failed) {
+  let output = form.querySelector("output");
+  if (!output) {
+    output = document.createElement("output");
+    form.append(output);
+  }
+  output.className = failed ? "error" : "";
+  output.textContent = text;
+}
+
+async function submit(event) {
+  event.preventDefault();
+  const form = event.target;
+  const method = form.dataset.method || "POST";
+  const init = { method, headers: {}, cache: "no-store" };
+  if (method === "POST") {
+    init.headers["Content-Type"] = "application/json";
+    init.body = JSON.stringify(body(form));
+  }
+  try {
+    const resp = await fetch(form.dataset.endpoint, init);
+    const json = await resp.json().catch(() => null);
+    if (!resp.ok) {
+      const code = json && json.error_code ? ` (${json.error_code})` : "";
+      show(form, `${resp.status}${code}: ${json ? json.error : resp.statusText}`, true);
+      return;
+    }
+    show(form, JSON.stringify(json, null, 2), false);
+  } catch (e) {
+    show(form, `request failed: ${e}`, true);
+  }
+}
+
+for (const form of document.querySelectorAll("form")) {
+  form.autocomplete = "off";
+  form.addEventListener("submit", submit);
+}